@@ -59,7 +59,8 @@ const APP: () = {
             &ctx.device.FICR,
             ctx.resources.ble_tx_buf,
             ctx.resources.ble_rx_buf,
-        );
+        )
+        .unwrap();
 
         let beacon = Beacon::new(
             device_address,
@@ -129,7 +129,7 @@ impl DemoAttrs {
     // Lazily produces an attribute to be read/written, representing the LED state.
     fn led_data_attr(&self) -> Attribute<[u8; 1]> {
         Attribute::new(
-            Uuid128::from_bytes(LED_STATE_CHAR_UUID128).into(),
+            Uuid128::from_le_bytes(LED_STATE_CHAR_UUID128).into(),
             Handle::from_raw(0x0003),
             self.led_buf,
         )
@@ -23,8 +23,10 @@ use nrf52840_hal as hal;
 use bbqueue::Consumer;
 use core::sync::atomic::{compiler_fence, Ordering};
 use hal::gpio::Level;
+use rtic::Mutex;
 use rtt_target::{rtt_init, UpChannel};
 use rubble::{
+    aes::SoftAesProvider,
     config::Config,
     l2cap::{BleChannelMap, L2CAPState},
     link::{
@@ -37,6 +39,7 @@ use rubble::{
 };
 use rubble_nrf5x::{
     radio::{BleRadio, PacketBuffer},
+    rng::HwRng,
     timer::BleTimer,
     utils::get_device_address,
 };
@@ -48,6 +51,10 @@ impl Config for AppConfig {
     type Transmitter = BleRadio;
     type ChannelMapper = BleChannelMap<attrs::DemoAttrs, NoSecurity>;
     type PacketQueue = &'static mut SimpleQueue;
+    type Aes = SoftAesProvider;
+    type AdvReportHandler = ();
+    type LinkEventHandler = ();
+    type Rng = HwRng;
 }
 
 #[rtic::app(device = crate::hal::pac, peripherals = true)]
@@ -98,7 +105,8 @@ const APP: () = {
             &ctx.device.FICR,
             ctx.resources.ble_tx_buf,
             ctx.resources.ble_rx_buf,
-        );
+        )
+        .unwrap();
 
         let log_sink = logger::init(ble_timer.create_stamp_source());
 
@@ -107,7 +115,8 @@ const APP: () = {
         let (rx_prod, rx) = ctx.resources.rx_queue.split();
 
         // Create the actual BLE stack objects
-        let mut ble_ll = LinkLayer::<AppConfig>::new(device_address, ble_timer);
+        let mut ble_ll =
+            LinkLayer::<AppConfig>::new(device_address, ble_timer, HwRng::new(ctx.device.RNG));
 
         // Assumes pin 17 corresponds to an LED.
         // On the NRF52DK board, this is LED 1.
@@ -201,12 +210,36 @@ const APP: () = {
         }
     }
 
-    #[task(resources = [ble_r], priority = 2)]
+    #[task(resources = [ble_r, ble_ll, radio], priority = 2)]
     fn ble_worker(ctx: ble_worker::Context) {
+        let ble_worker::Resources {
+            ble_r,
+            mut ble_ll,
+            mut radio,
+        } = ctx.resources;
+
         // Fully drain the packet queue
-        while ctx.resources.ble_r.has_work() {
-            ctx.resources.ble_r.process_one().unwrap();
+        let mut processed = false;
+        while ble_r.has_work() {
+            ble_r.process_one().unwrap();
+            processed = true;
         }
+        if !processed {
+            return;
+        }
+
+        // Any responses should be sent without waiting for connection events skipped due to the
+        // slave latency
+        ble_ll.lock(|ble_ll| {
+            let cmd = match ble_ll.connection_handle() {
+                Some(handle) => ble_ll.wake_for_tx(handle).unwrap(),
+                None => None,
+            };
+            if let Some(cmd) = cmd {
+                radio.lock(|radio| radio.configure_receiver(cmd.radio));
+                ble_ll.timer().configure_interrupt(cmd.next_update);
+            }
+        });
     }
 
     extern "C" {
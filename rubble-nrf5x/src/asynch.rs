@@ -0,0 +1,270 @@
+//! Futures for driving the Link-Layer from an async executor.
+//!
+//! This module is only available when the `async` feature is enabled. Instead of processing BLE
+//! events in the `RADIO` and `TIMERx` interrupt handlers, the handlers only have to call
+//! [`on_radio_interrupt`] and [`on_timer_interrupt`], which wake up the task awaiting the futures
+//! defined here. That task then calls into the Link-Layer just like the interrupt handlers would.
+//!
+//! Received packets have to be answered within 150 µs, so the task must be polled promptly once
+//! it is woken, eg. by running it on an executor driven by a high-priority interrupt.
+//!
+//! Only one task may wait for radio and timer events at a time.
+//!
+//! # Example
+//!
+//! A connection loop that processes all Link-Layer events:
+//!
+//! ```ignore
+//! use rubble_nrf5x::asynch::{self, Event};
+//!
+//! loop {
+//!     let cmd = match asynch::next_event(&mut radio, ble_ll.timer()).await {
+//!         Event::Radio => radio.recv_interrupt(ble_ll.timer().now(), &mut ble_ll),
+//!         Event::Timer => {
+//!             ble_ll.timer().clear_interrupt();
+//!             Some(ble_ll.update_timer(&mut radio))
+//!         }
+//!     };
+//!
+//!     if let Some(cmd) = cmd {
+//!         radio.configure_receiver(cmd.radio);
+//!         ble_ll.timer().configure_interrupt(cmd.next_update);
+//!
+//!         if cmd.queued_work {
+//!             while responder.has_work() {
+//!                 responder.process_one().unwrap();
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! // In the interrupt handlers:
+//! #[interrupt]
+//! fn RADIO() {
+//!     rubble_nrf5x::asynch::on_radio_interrupt();
+//! }
+//!
+//! #[interrupt]
+//! fn TIMER0() {
+//!     rubble_nrf5x::asynch::on_timer_interrupt::<pac::TIMER0>();
+//! }
+//! ```
+
+use crate::pac;
+use crate::radio::BleRadio;
+use crate::timer::{BleTimer, NrfTimerExt};
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Poll, Waker};
+use rubble::link::NextUpdate;
+use rubble::time::{Instant, Timer};
+
+static RADIO_WAKER: WakerSlot = WakerSlot::new();
+static TIMER_WAKER: WakerSlot = WakerSlot::new();
+
+/// Set by `on_radio_interrupt` when the radio has raised an interrupt that wasn't processed yet.
+static RADIO_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Stores the `Waker` of a task waiting for an interrupt.
+///
+/// This only uses atomic loads and stores, so that it also works on the nRF51's Cortex-M0. It
+/// assumes that a single task registers its waker, and that it is only woken from an interrupt
+/// handler that can preempt that task (but not the other way around).
+struct WakerSlot {
+    /// Set while the task replaces the waker, which must not be accessed by the interrupt then.
+    locked: AtomicBool,
+
+    /// Set when the interrupt handler couldn't wake the task because the slot was `locked`.
+    missed_wake: AtomicBool,
+
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// Safety: Accesses to `waker` are synchronized through `locked`, see above.
+unsafe impl Sync for WakerSlot {}
+
+impl WakerSlot {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            missed_wake: AtomicBool::new(false),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Registers `waker` to be woken by the next call to `wake`.
+    fn register(&self, waker: &Waker) {
+        self.locked.store(true, Ordering::SeqCst);
+        let slot = unsafe { &mut *self.waker.get() };
+        match slot {
+            Some(old) if old.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+        self.locked.store(false, Ordering::SeqCst);
+
+        // If the interrupt fires right here, it wakes the task itself, resulting in a harmless
+        // spurious wakeup at worst
+        if self.missed_wake.load(Ordering::SeqCst) {
+            self.missed_wake.store(false, Ordering::SeqCst);
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Wakes the registered task (to be called from an interrupt handler).
+    fn wake(&self) {
+        if self.locked.load(Ordering::SeqCst) {
+            self.missed_wake.store(true, Ordering::SeqCst);
+        } else if let Some(waker) = unsafe { &*self.waker.get() } {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Handles the `RADIO` interrupt when using the futures in this module.
+///
+/// This must be called from the `RADIO` interrupt handler. It masks the radio interrupt (it is
+/// unmasked again by [`BleRadio::configure_receiver`]) and wakes the waiting task.
+pub fn on_radio_interrupt() {
+    let radio = unsafe { &*pac::RADIO::ptr() };
+    if radio.events_disabled.read().bits() != 0 {
+        radio.intenclr.write(|w| w.disabled().clear());
+        RADIO_PENDING.store(true, Ordering::SeqCst);
+        RADIO_WAKER.wake();
+    }
+}
+
+/// Handles the interrupt of timer `T` when using the futures in this module.
+///
+/// This must be called from the interrupt handler of the timer passed to the Link-Layer. It masks
+/// the timer interrupt (it is unmasked again when a new time is configured) and wakes the waiting
+/// task.
+pub fn on_timer_interrupt<T: NrfTimerExt>() {
+    let timer = unsafe { &*T::registers() };
+    if timer.events_compare[1].read().bits() != 0 {
+        timer.intenclr.write(|w| w.compare1().clear());
+        TIMER_WAKER.wake();
+    }
+}
+
+/// Waits until the radio has raised an interrupt.
+///
+/// This happens when the radio has received a packet, or when it was disabled by a timeout. After
+/// this returns, [`BleRadio::recv_interrupt`] should be called to process the packet.
+pub async fn wait_for_radio(_radio: &mut BleRadio) {
+    poll_fn(|cx| {
+        RADIO_WAKER.register(cx.waker());
+        if take_radio_pending() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Waits until `at`, using the interrupt of `timer`.
+///
+/// This uses the same compare register as [`BleTimer::configure_interrupt`], so it must not be
+/// used while the Link-Layer's timer is in use.
+pub async fn wait_until<T: NrfTimerExt>(timer: &mut BleTimer<T>, at: Instant) {
+    timer.configure_interrupt(NextUpdate::At(at));
+
+    // The compare event only fires when the counter passes `at`
+    if timer.now() < at {
+        poll_fn(|cx| {
+            TIMER_WAKER.register(cx.waker());
+            if timer.is_interrupt_pending() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    timer.clear_interrupt();
+}
+
+/// The source of a Link-Layer event, returned by [`next_event`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The radio raised an interrupt, and [`BleRadio::recv_interrupt`] should be called.
+    Radio,
+
+    /// The timer configured by the Link-Layer has expired. The timer interrupt has to be cleared
+    /// and `LinkLayer::update_timer` should be called.
+    Timer,
+}
+
+/// Waits until either the radio or the Link-Layer's timer raises an interrupt.
+///
+/// If both are pending, radio events are reported first, since they are more timing-critical.
+pub async fn next_event<T: NrfTimerExt>(_radio: &mut BleRadio, timer: &mut BleTimer<T>) -> Event {
+    poll_fn(|cx| {
+        RADIO_WAKER.register(cx.waker());
+        TIMER_WAKER.register(cx.waker());
+        if take_radio_pending() {
+            Poll::Ready(Event::Radio)
+        } else if timer.is_interrupt_pending() {
+            Poll::Ready(Event::Timer)
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+fn take_radio_pending() -> bool {
+    let pending = RADIO_PENDING.load(Ordering::SeqCst);
+    if pending {
+        RADIO_PENDING.store(false, Ordering::SeqCst);
+    }
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+    use core::task::{RawWaker, RawWakerVTable};
+
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn wake(_: *const ()) {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        }
+        fn drop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        unsafe { Waker::from_raw(clone(core::ptr::null())) }
+    }
+
+    #[test]
+    fn waker_slot() {
+        let slot = WakerSlot::new();
+        let waker = counting_waker();
+
+        // Waking before a waker was registered does nothing
+        slot.wake();
+        assert_eq!(WAKES.load(Ordering::SeqCst), 0);
+
+        slot.register(&waker);
+        slot.wake();
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+
+        // A wakeup arriving while the waker is being replaced is delivered after registering
+        slot.locked.store(true, Ordering::SeqCst);
+        slot.wake();
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+        slot.locked.store(false, Ordering::SeqCst);
+        slot.register(&waker);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 2);
+
+        slot.register(&waker);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,58 @@
+//! AES-128 provider using the `ECB` peripheral.
+
+use crate::pac::ECB;
+use core::sync::atomic::{compiler_fence, Ordering};
+use rubble::aes::AesProvider;
+
+/// Implements Rubble's `AesProvider` trait using the AES block encryption peripheral.
+pub struct BleEcb {
+    ecb: ECB,
+}
+
+impl BleEcb {
+    /// Takes ownership of the `ECB` peripheral.
+    pub fn new(ecb: ECB) -> Self {
+        ecb.intenclr
+            .write(|w| w.endecb().clear().errorecb().clear());
+        Self { ecb }
+    }
+
+    /// Releases the `ECB` peripheral.
+    pub fn free(self) -> ECB {
+        self.ecb
+    }
+}
+
+impl AesProvider for BleEcb {
+    fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+        // Memory layout expected by the peripheral: Key, cleartext, ciphertext
+        let mut data = [0; 48];
+        data[..16].copy_from_slice(key);
+        data[16..32].copy_from_slice(block);
+
+        loop {
+            self.ecb
+                .ecbdataptr
+                .write(|w| unsafe { w.bits(data.as_mut_ptr() as u32) });
+            self.ecb.events_endecb.reset();
+            self.ecb.events_errorecb.reset();
+
+            // "Release" the buffer to the peripheral before starting the operation.
+            compiler_fence(Ordering::Release);
+            self.ecb.tasks_startecb.write(|w| unsafe { w.bits(1) });
+
+            while self.ecb.events_endecb.read().bits() == 0
+                && self.ecb.events_errorecb.read().bits() == 0
+            {}
+            compiler_fence(Ordering::Acquire);
+
+            // The operation can be aborted when the radio's `CCM` or `AAR` peripherals need the AES
+            // core, in which case we just try again.
+            if self.ecb.events_endecb.read().bits() != 0 {
+                break;
+            }
+        }
+
+        block.copy_from_slice(&data[32..]);
+    }
+}
@@ -24,6 +24,11 @@ use nrf52833_pac as pac;
 #[cfg(feature = "52840")]
 use nrf52840_pac as pac;
 
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod ecb;
 pub mod radio;
+pub mod rng;
+pub mod stack;
 pub mod timer;
 pub mod utils;
@@ -40,45 +40,1035 @@
 //! In our case, this involves "splitting" the header into the `S0` field (everything preceding the
 //! length), the `Length` field, and the `S1` field (which just contains 2 unused bits, but they
 //! must still be sent, of course).
+//!
+//! The LE 2M PHY uses a 2-Byte preamble instead, and the LE Coded PHY inserts a Coding Indicator
+//! and a `TERM1` field between the address and the PDU, and a `TERM2` field after the CRC. The
+//! radio handles these fields when configured for the respective PHY.
 
 use crate::pac;
 use crate::pac::{radio::state::STATE_R, RADIO};
-use core::cmp;
+use crate::timer::{BleTimer, NrfTimerExt};
 use core::sync::atomic::{compiler_fence, Ordering};
+use core::{cmp, fmt, mem};
 use rubble::config::Config;
 use rubble::link::{
-    advertising, data, Cmd, LinkLayer, RadioCmd, Transmitter, CRC_POLY, MIN_PDU_BUF,
+    advertising, data, Cmd, DataPacketInfo, FeatureSet, LinkLayer, RadioCmd, RadioStats,
+    TimeWindow, Transmitter, CRC_POLY, MIN_PDU_BUF,
 };
-use rubble::phy::{AdvertisingChannel, DataChannel};
+#[cfg(any(feature = "52833", feature = "52840"))]
+use rubble::phy::CodingIndicator;
+use rubble::phy::{AdvertisingChannel, DataChannel, Phy};
 use rubble::time::{Duration, Instant, T_IFS};
+use rubble::Error;
 
-/// A packet buffer that can hold header and payload of any advertising or data channel packet.
+/// A packet buffer that can hold header and payload of any legacy advertising or data channel
+/// packet.
+///
+/// Larger buffers can be passed to [`BleRadio::new`] to receive longer data channel PDUs and to
+/// transmit extended advertising PDUs.
 pub type PacketBuffer = [u8; MIN_PDU_BUF];
 
+/// Base address length (in Bytes) used by BLE.
+///
+/// Together with the 1-Byte Address Prefix, this forms the 32-bit Access Address.
+const BLE_BASE_ADDRESS_LEN: u8 = 3;
+
+/// Returns the Link-Layer features that can be used with the radio of the selected chip.
+///
+/// The nRF51 can't use the LE 2M PHY, and only the nRF52833 and nRF52840 support the LE Coded PHY.
+pub fn supported_features() -> FeatureSet {
+    let mut features = FeatureSet::supported();
+    if cfg!(feature = "51") {
+        features -= FeatureSet::LE_2M_PHY;
+    }
+    if !cfg!(any(feature = "52833", feature = "52840")) {
+        features -= FeatureSet::LE_CODED_PHY;
+    }
+    features
+}
+
+/// Configures `MODE` and `PCNF0` for sending and receiving packets on `phy`.
+///
+/// The radio must be disabled.
+///
+/// The S0, Length and S1 fields are laid out according to `layout` for all PHYs. Only the fields
+/// surrounding the PDU differ:
+///
+/// * **LE 1M**: 1-Byte preamble (`PLEN = 8bit`).
+/// * **LE 2M**: 2-Byte preamble (`PLEN = 16bit`).
+/// * **LE Coded**: The 80 µs preamble (`PLEN = LongRange`) is followed by the access address, the
+///   2-bit Coding Indicator (`CILEN = 2`) and the 3-bit TERM1 field (`TERMLEN = 3`), all of which
+///   are coded with S=8. The radio appends TERM2 after the CRC by itself. `MODE` selects the coding
+///   of the transmitted PDU, while received packets are decoded according to their CI field.
+///
+/// CRC and whitening are computed over the uncoded PDU on all PHYs, before the radio applies the
+/// forward error correction of the LE Coded PHY. The CI and TERM fields are neither whitened nor
+/// covered by the CRC, so `CRCCNF` and `PCNF1` don't depend on the PHY.
+fn configure_phy(radio: &pac::radio::RegisterBlock, phy: Phy, layout: HeaderLayout) {
+    // Writing `PCNF0` resets the PHY-specific fields (`PLEN`, `CILEN` and `TERMLEN`)
+    layout.write(radio);
+
+    match phy {
+        Phy::Le1M => {
+            radio.mode.write(|w| w.mode().ble_1mbit());
+            #[cfg(not(feature = "51"))]
+            radio.pcnf0.modify(|_, w| w.plen()._8bit());
+        }
+        #[cfg(not(feature = "51"))]
+        Phy::Le2M => {
+            // The LE 2M PHY uses a 2-Byte preamble
+            radio.mode.write(|w| w.mode().ble_2mbit());
+            radio.pcnf0.modify(|_, w| w.plen()._16bit());
+        }
+        #[cfg(feature = "51")]
+        Phy::Le2M => unreachable!("the nRF51 does not support the LE 2M PHY"),
+        #[cfg(any(feature = "52833", feature = "52840"))]
+        Phy::LeCoded { ci } => {
+            radio.mode.write(|w| match ci {
+                CodingIndicator::S8 => w.mode().ble_lr125kbit(),
+                CodingIndicator::S2 => w.mode().ble_lr500kbit(),
+            });
+            unsafe {
+                radio
+                    .pcnf0
+                    .modify(|_, w| w.plen().long_range().cilen().bits(2).termlen().bits(3));
+            }
+        }
+        #[cfg(not(any(feature = "52833", feature = "52840")))]
+        Phy::LeCoded { .. } => unreachable!("this chip does not support the LE Coded PHY"),
+    }
+}
+
+/// Selects the fast (40 µs) or default (140 µs) ramp-up time of the receiver and transmitter.
+///
+/// The radio must not be ramping up.
+#[cfg(not(feature = "51"))]
+fn configure_ramp_up(radio: &pac::radio::RegisterBlock, fast: bool) {
+    radio.modecnf0.modify(|_, w| {
+        if fast {
+            w.ru().fast()
+        } else {
+            w.ru().default()
+        }
+    });
+}
+
+/// Tunes the radio to `freq` MHz, shifted by `offset` MHz.
+///
+/// The result is clamped to the range the `FREQUENCY` register can express: 2360 to 2500 MHz on
+/// the nRF52 (frequencies below 2400 MHz use the `LOW` map), and 2400 to 2500 MHz on the nRF51.
+fn configure_frequency(radio: &pac::radio::RegisterBlock, freq: u16, offset: i8) {
+    let min = if cfg!(feature = "51") { 2400 } else { 2360 };
+    let freq = (i32::from(freq) + i32::from(offset)).clamp(min, 2500) as u16;
+    if freq >= 2400 {
+        radio
+            .frequency
+            .write(|w| unsafe { w.frequency().bits((freq - 2400) as u8) });
+    } else {
+        #[cfg(not(feature = "51"))]
+        radio
+            .frequency
+            .write(|w| unsafe { w.frequency().bits((freq - 2360) as u8).map().low() });
+    }
+}
+
+/// Lets the transmitter, which is ramped up by the `DISABLED_TXEN` shortcut after a packet was
+/// received, send the response in the TX buffer once it is ready.
+///
+/// If the transmitter is already ready, the response was prepared too late to be sent `T_IFS`
+/// after the received packet, and the peer is no longer listening. The radio is then disabled
+/// instead of transmitting late, the miss is recorded in `stats`, and `false` is returned. The
+/// Link-Layer treats the unsent response like a lost packet and retransmits it in the next
+/// connection event.
+fn start_response(radio: &pac::radio::RegisterBlock, stats: &mut RadioStats) -> bool {
+    radio
+        .shorts
+        .write(|w| w.ready_start().enabled().end_disable().disabled());
+
+    // If `READY` occurs after the shortcut was enabled, `START` is triggered and the radio leaves
+    // `TXIDLE` immediately
+    if radio.state.read().state().is_tx_idle() {
+        radio.shorts.reset();
+        radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+        stats.record_missed_tx();
+        return false;
+    }
+    true
+}
+
+/// How often [`recover_if`] polls the radio state before giving up.
+///
+/// Disabling the radio takes a few µs at most, so this is generous even at the highest clock
+/// frequency.
+const DISABLE_POLLS: u32 = 10_000;
+
+/// How often the end of a transmission is polled before the radio is considered stuck.
+///
+/// The longest PDU (257 Bytes on the LE Coded PHY with S=8) takes about 17 ms to send, which is
+/// less than this many polls take even at the highest clock frequency.
+const TX_POLLS: u32 = 2_000_000;
+
+/// Disables the radio if `unexpected` returns `true` for its current state.
+///
+/// A missed event or a glitch can leave the radio busy where the driver expects it to be idle.
+/// Instead of panicking, the shortcuts are cleared, the `DISABLE` task is triggered and the
+/// recovery is counted in `stats`. Then this waits for the radio to reach the `DISABLED` state,
+/// acknowledges the `DISABLED` event and emits [`RadioEvent::Disabled`] to `event_handler`, so
+/// that the radio can be reconfigured afterwards.
+///
+/// Returns `Error::Timeout` if the radio isn't disabled after polling its state
+/// [`DISABLE_POLLS`] times. The radio is left in an unknown state then.
+fn recover_if(
+    radio: &pac::radio::RegisterBlock,
+    stats: &mut RadioStats,
+    event_handler: Option<fn(RadioEvent)>,
+    unexpected: impl FnOnce(&STATE_R) -> bool,
+) -> Result<bool, Error> {
+    let state = radio.state.read().state();
+    if !unexpected(&state) {
+        return Ok(false);
+    }
+
+    #[cfg(feature = "defmt")]
+    defmt::warn!(
+        "radio in unexpected state {=u8}, disabling it",
+        state.bits()
+    );
+    radio.shorts.reset();
+    radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+    stats.record_recovery();
+
+    if !(0..DISABLE_POLLS).any(|_| radio.state.read().state().is_disabled()) {
+        #[cfg(feature = "defmt")]
+        defmt::error!("radio not disabled after recovery");
+        return Err(Error::Timeout);
+    }
+    finish_disable(radio, event_handler);
+    Ok(true)
+}
+
+/// Waits for the radio to reach the `DISABLED` state after the `DISABLE` task was triggered.
+///
+/// The `DISABLED` event is then acknowledged and [`RadioEvent::Disabled`] is emitted to
+/// `event_handler`. If the radio isn't disabled after polling its state [`DISABLE_POLLS`] times,
+/// it is recovered with [`recover_if`], which returns `Error::Timeout` if that fails as well.
+fn wait_for_disable(
+    radio: &pac::radio::RegisterBlock,
+    stats: &mut RadioStats,
+    event_handler: Option<fn(RadioEvent)>,
+) -> Result<(), Error> {
+    if (0..DISABLE_POLLS).any(|_| radio.state.read().state().is_disabled())
+        || !recover_if(radio, stats, event_handler, |state| !state.is_disabled())?
+    {
+        finish_disable(radio, event_handler);
+    }
+    Ok(())
+}
+
+/// Waits for the `DISABLED` event that ends a transmission started with the END_DISABLE
+/// shortcut.
+///
+/// The event is polled instead of the radio state, since shortcuts like DISABLED_RXEN may already
+/// have moved the radio on. If it isn't raised after polling it [`TX_POLLS`] times, the radio is
+/// disabled with [`recover_if`] and `Error::Timeout` is returned. The PDU is lost then (like on a
+/// noisy channel).
+fn wait_for_tx_end(
+    radio: &pac::radio::RegisterBlock,
+    stats: &mut RadioStats,
+    event_handler: Option<fn(RadioEvent)>,
+) -> Result<(), Error> {
+    if (0..TX_POLLS).any(|_| radio.events_disabled.read().bits() != 0) {
+        return Ok(());
+    }
+    recover_if(radio, stats, event_handler, |_| true)?;
+    Err(Error::Timeout)
+}
+
+/// Acknowledges the `DISABLED` event once the radio is disabled and emits
+/// [`RadioEvent::Disabled`] to `event_handler`.
+fn finish_disable(radio: &pac::radio::RegisterBlock, event_handler: Option<fn(RadioEvent)>) {
+    radio.events_disabled.reset();
+    if let Some(handler) = event_handler {
+        handler(RadioEvent::Disabled);
+    }
+}
+
+/// Acknowledges the `DISABLED` event, which is the only radio event that raises an interrupt.
+///
+/// Returns `false` if the event isn't pending, which means that the interrupt was spurious or
+/// raised by another source sharing the interrupt handler. This is recorded in `stats`, and no
+/// other event is touched.
+fn take_disabled_event(radio: &pac::radio::RegisterBlock, stats: &mut RadioStats) -> bool {
+    if radio.events_disabled.read().bits() == 0 {
+        stats.record_spurious_interrupt();
+        return false;
+    }
+
+    // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+    compiler_fence(Ordering::Acquire);
+
+    radio.events_disabled.reset();
+    true
+}
+
+/// Acknowledges the `END` event of the packet received before the radio was disabled, and counts
+/// the packet in `stats`.
+///
+/// Emits [`RadioEvent::PacketReceived`] to `event_handler` and returns whether the packet's CRC was
+/// correct. If the radio was disabled without receiving a packet, [`RadioEvent::Disabled`] is
+/// emitted and `None` is returned.
+fn take_received_packet(
+    radio: &pac::radio::RegisterBlock,
+    stats: &mut RadioStats,
+    event_handler: Option<fn(RadioEvent)>,
+) -> Option<bool> {
+    let event = if radio.events_end.read().bits() == 0 {
+        RadioEvent::Disabled
+    } else {
+        radio.events_end.reset();
+        let crc_ok = radio.crcstatus.read().crcstatus().is_crcok();
+        stats.record_rx(crc_ok);
+        RadioEvent::PacketReceived { crc_ok }
+    };
+
+    if let Some(handler) = event_handler {
+        handler(event);
+    }
+    match event {
+        RadioEvent::PacketReceived { crc_ok } => Some(crc_ok),
+        _ => None,
+    }
+}
+
+/// Stops the radio so that it can be released, without waiting for it to become disabled.
+///
+/// All interrupts and shortcuts are disabled, and the `DISABLE` task is triggered unless the radio
+/// is already disabled. Returns `true` if the caller has to wait for the `DISABLED` event.
+fn stop_for_release(radio: &pac::radio::RegisterBlock) -> bool {
+    radio.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    radio.shorts.reset();
+    radio.events_disabled.reset();
+
+    if radio.state.read().state().is_disabled() {
+        return false;
+    }
+    radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+    true
+}
+
+/// Stops any ongoing reception or transmission for [`BleRadio::abort`], without waiting for the
+/// radio to become disabled.
+///
+/// The `DISABLED` interrupt and all shortcuts are disabled, and the `DISABLE` task is triggered
+/// unless the radio is already disabled. Returns `true` if a packet was being received (its address
+/// had already been matched).
+fn stop_for_abort(radio: &pac::radio::RegisterBlock) -> bool {
+    // Prevent the aborted operation from raising an interrupt
+    radio.intenclr.write(|w| w.disabled().clear());
+
+    // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+    compiler_fence(Ordering::Acquire);
+
+    let receiving = radio.state.read().state().is_rx() && radio.events_address.read().bits() != 0;
+
+    // Don't start anything automatically after disabling the radio
+    radio.shorts.reset();
+
+    radio.events_disabled.reset();
+    if !radio.state.read().state().is_disabled() {
+        radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+    }
+    receiving
+}
+
+/// Returns whether the radio may still read from `tx_buf`.
+///
+/// Blocking transmissions have finished when they return, so only an ongoing `TX` state is a
+/// problem then. When `armed` is set, a data channel response was started by enabling the
+/// READY_START shortcut while the transmitter ramps up, so the radio will start reading from the
+/// buffer once ramp-up completes, without any further action by the CPU.
+fn tx_in_flight(radio: &pac::radio::RegisterBlock, armed: bool) -> bool {
+    let state = radio.state.read().state();
+    state.is_tx() || (armed && (state.is_tx_ru() || state.is_tx_idle()))
+}
+
+/// Returns whether the radio may still read from `buf` (see [`tx_in_flight`]).
+///
+/// Transmissions read from the buffer `PACKETPTR` was set to when they were started, which is
+/// another buffer than `buf` if the TX buffers were swapped in the meantime.
+fn tx_in_flight_from(radio: &pac::radio::RegisterBlock, armed: bool, buf: &[u8]) -> bool {
+    tx_in_flight(radio, armed) && radio.packetptr.read().bits() == buf.as_ptr() as u32
+}
+
+/// Swaps the spare TX buffer with `tx_buf`, copying the PDU in `tx_buf` to it.
+///
+/// Returns `false` if there is no spare buffer. Otherwise, the buffer previously in `tx_buf`
+/// becomes the spare one, so both buffers are kept.
+fn swap_tx_bufs(tx_buf: &mut &'static mut [u8], spare: &mut Option<&'static mut [u8]>) -> bool {
+    match spare {
+        Some(spare) => {
+            // The radio only reads from `tx_buf`, so it can be copied while being sent
+            spare.copy_from_slice(tx_buf);
+            mem::swap(tx_buf, spare);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Writes `poly`, truncated to 24 bits, to the `CRCPOLY` register.
+fn set_crc_poly(radio: &pac::radio::RegisterBlock, poly: u32) {
+    radio
+        .crcpoly
+        .write(|w| unsafe { w.crcpoly().bits(crc24(poly)) });
+}
+
+/// Writes `init`, truncated to 24 bits, to the `CRCINIT` register.
+fn set_crc_init(radio: &pac::radio::RegisterBlock, init: u32) {
+    radio
+        .crcinit
+        .write(|w| unsafe { w.crcinit().bits(crc24(init)) });
+}
+
+/// Sets the shortcuts for transmitting a repeated advertising PDU.
+///
+/// Unless `last` is set, the DISABLED_TXEN shortcut starts the next repetition `T_IFS` after the
+/// current one ends. After the last one, the receiver is started instead if `listen` is set.
+fn set_adv_repeat_shorts(radio: &pac::radio::RegisterBlock, last: bool, listen: bool) {
+    radio.shorts.write(|w| {
+        let w = w.ready_start().enabled().end_disable().enabled();
+        if !last {
+            w.disabled_txen().enabled()
+        } else if listen {
+            w.disabled_rxen().enabled()
+        } else {
+            w
+        }
+    });
+}
+
+/// Handles the end of a transmission of a repeated advertising PDU, when `left` transmissions
+/// (including this one) hadn't ended yet.
+///
+/// The DISABLED_TXEN shortcut has already started the next repetition at this point. If that is
+/// the last one, the shortcut is replaced by the ones set up for `listen`. This has to happen
+/// before the last repetition ends, which takes at least `T_IFS` and the duration of the PDU.
+///
+/// Returns the number of transmissions that haven't ended yet.
+fn adv_repeat_ended(radio: &pac::radio::RegisterBlock, left: u16, listen: bool) -> u16 {
+    let left = left - 1;
+    if left == 1 {
+        set_adv_repeat_shorts(radio, true, listen);
+    }
+    left
+}
+
+/// Masks `value` to the 24 bits used by the `CRCPOLY` and `CRCINIT` registers (for a 3-Byte CRC).
+///
+/// In `CRCPOLY`, bit `n` corresponds to the `x^n` term. The `x^24` term is implicit.
+fn crc24(value: u32) -> u32 {
+    value & 0x00FF_FFFF
+}
+
+/// Computes the values of the `BASEn` and `PREFIXn` registers for transmitting `address` with a
+/// Base Address of `balen` Bytes.
+///
+/// The address on air consists of the `balen` least significant Bytes of `address` (the Base
+/// Address), followed by the next Byte (the Address Prefix). Any remaining Bytes are ignored.
+///
+/// The `BASEn` registers have, apparently, undocumented semantics: They are proper 32-bit
+/// registers, but the radio only uses the *upper* `balen` Bytes and transmits them as the low
+/// Bytes of the address. The Base Address has to be shifted up to account for this.
+fn base_and_prefix(address: u32, balen: u8) -> (u32, u8) {
+    let base_bits = 8 * u32::from(balen);
+    let base = address.checked_shl(32 - base_bits).unwrap_or(0);
+    let prefix = address.checked_shr(base_bits).unwrap_or(0) as u8;
+    (base, prefix)
+}
+
+/// Extracts the payload of a received packet from `rx_buf` (which starts with the 2-Byte header).
+///
+/// If `payload_length` exceeds the space in `rx_buf` (and thus `MAXLEN`), the radio has truncated
+/// the packet. Such a packet is counted in `stats` and reported as corrupt with an empty payload,
+/// so that the Link-Layer never processes a truncated payload.
+fn checked_payload<'a>(
+    rx_buf: &'a [u8],
+    payload_length: u8,
+    crc_ok: bool,
+    stats: &mut RadioStats,
+) -> (&'a [u8], bool) {
+    match rx_buf.get(2..2 + usize::from(payload_length)) {
+        Some(payload) => (payload, crc_ok),
+        None => {
+            stats.record_oversized();
+            (&[], false)
+        }
+    }
+}
+
+/// Passes a packet that failed the CRC check to the diagnostic `handler`, if there is one.
+///
+/// `rx_buf` holds the packet as received, starting with the 2-Byte header. Since the length field
+/// might be corrupted, the PDU is truncated to the buffer size.
+fn report_crc_error(
+    handler: Option<fn(&CrcErrorPacket<'_>)>,
+    crc_ok: bool,
+    advertising: bool,
+    rx_buf: &[u8],
+    payload_length: u8,
+    rssi: i8,
+    timestamp: Instant,
+) {
+    if let (Some(handler), false) = (handler, crc_ok) {
+        let len = cmp::min(2 + usize::from(payload_length), rx_buf.len());
+        handler(&CrcErrorPacket {
+            timestamp,
+            advertising,
+            pdu: &rx_buf[..len],
+            rssi,
+        });
+    }
+}
+
+/// Copies a complete advertising channel PDU (2-Byte header followed by the payload) to `tx_buf`.
+///
+/// The header's length field must match the payload, and the payload must not exceed
+/// `max_payload` Bytes (the radio's `MAXLEN`). Otherwise, `Error::InvalidLength` is returned and
+/// `tx_buf` is not modified.
+fn load_raw_pdu(tx_buf: &mut [u8], max_payload: u8, pdu: &[u8]) -> Result<(), Error> {
+    let payload_length = match pdu {
+        [_, len, ..] => *len,
+        _ => return Err(Error::InvalidLength),
+    };
+    if pdu.len() != 2 + usize::from(payload_length)
+        || payload_length > max_payload
+        || pdu.len() > tx_buf.len()
+    {
+        return Err(Error::InvalidLength);
+    }
+
+    tx_buf[..pdu.len()].copy_from_slice(pdu);
+    Ok(())
+}
+
+/// Sets the largest payload the radio sends or receives (the `MAXLEN` field of `PCNF1`).
+///
+/// Longer received packets are truncated instead of overflowing the buffer at `PACKETPTR`.
+fn set_max_payload(radio: &pac::radio::RegisterBlock, max_payload: u8) {
+    radio
+        .pcnf1
+        .modify(|_, w| unsafe { w.maxlen().bits(max_payload) });
+}
+
+/// Enables or disables data whitening (the `WHITEEN` field of `PCNF1`).
+fn configure_whitening(radio: &pac::radio::RegisterBlock, enabled: bool) {
+    radio.pcnf1.modify(|_, w| w.whiteen().bit(enabled));
+}
+
+/// Timer compare register used to disable the radio at the end of a `TimeWindow`.
+const DEADLINE_CC: usize = 2;
+
+/// Timer compare register used to disable the radio when a receive timeout elapses.
+const RX_TIMEOUT_CC: usize = 3;
+
+/// Timer and PPI channels connected to the radio by [`BleRadio::enable_timeouts`].
+struct Timeouts {
+    ppi: pac::PPI,
+
+    /// Address of the timer's register block (stored as an integer to keep `BleRadio` `Send`).
+    timer: usize,
+
+    deadline_ch: u8,
+    rx_timeout_ch: u8,
+    capture_ch: u8,
+}
+
+impl Timeouts {
+    fn timer(&self) -> &pac::timer0::RegisterBlock {
+        unsafe { &*(self.timer as *const pac::timer0::RegisterBlock) }
+    }
+
+    fn enable(&self, mask: u32) {
+        self.ppi.chenset.write(|w| unsafe { w.bits(mask) });
+    }
+
+    fn disable(&self, mask: u32) {
+        self.ppi.chenclr.write(|w| unsafe { w.bits(mask) });
+    }
+
+    fn rx_timeout_mask(&self) -> u32 {
+        1 << self.rx_timeout_ch | 1 << self.capture_ch
+    }
+
+    /// Returns when the access address of the last received packet ended, as captured by
+    /// `capture_ch`.
+    fn address_time(&self) -> Instant {
+        let micros = self.timer().cc[RX_TIMEOUT_CC].read().bits();
+        Instant::from_ticks(Duration::micros(micros).ticks())
+    }
+
+    /// Returns whether the receive timeout was armed and has elapsed.
+    fn rx_timed_out(&self) -> bool {
+        let armed = self.ppi.chen.read().bits() & 1 << self.rx_timeout_ch != 0;
+        armed && self.timer().events_compare[RX_TIMEOUT_CC].read().bits() != 0
+    }
+}
+
+/// A radio state transition, reported to the handler set with [`BleRadio::set_event_handler`].
+///
+/// Together with a timestamp taken by the handler, these events can be used to account for the
+/// time the radio is powered, without polling the `STATE` register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RadioEvent {
+    /// The receiver is being ramped up.
+    RxStarted,
+
+    /// A packet was received and the radio has been disabled.
+    PacketReceived {
+        /// Whether the packet's CRC was valid.
+        crc_ok: bool,
+    },
+
+    /// The transmitter is being ramped up to send a packet.
+    TxStarted,
+
+    /// A blocking transmission has completed and the radio has been disabled.
+    ///
+    /// Data channel packets are sent without waiting for completion, so they are not followed by
+    /// this event. The radio disables itself automatically after sending them.
+    TxComplete,
+
+    /// The radio was disabled by the driver, aborting any ongoing operation.
+    Disabled,
+}
+
+/// A packet received with an invalid CRC, reported to the handler set with
+/// [`BleRadio::set_crc_error_handler`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CrcErrorPacket<'a> {
+    /// Time at which the radio interrupt fired for this packet.
+    pub timestamp: Instant,
+
+    /// Whether the packet was received on an advertising channel (or on a data channel).
+    pub advertising: bool,
+
+    /// The received PDU (header and payload), possibly corrupted.
+    pub pdu: &'a [u8],
+
+    /// Received signal strength in dBm.
+    pub rssi: i8,
+}
+
+/// Layout of the packet header preceding the payload, configured in the `PCNF0` register.
+///
+/// The radio splits the header into an optional 1-Byte `S0` field, the `LENGTH` field holding the
+/// payload length, and an `S1` field. Both BLE advertising and data channel PDUs use a 1-Byte `S0`
+/// field, an 8-bit `LENGTH` field and no `S1` field (see [`BLE`](Self::BLE)). Other layouts can be
+/// set with [`BleRadio::set_header_layout`] to follow future header formats or for proprietary
+/// modes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeaderLayout {
+    /// Whether the header starts with a 1-Byte `S0` field.
+    pub s0_byte: bool,
+
+    /// Size of the `LENGTH` field in bits (0-15).
+    pub length_bits: u8,
+
+    /// Size of the `S1` field in bits (0-15).
+    pub s1_bits: u8,
+}
+
+impl HeaderLayout {
+    /// Header layout of BLE advertising and data channel PDUs.
+    pub const BLE: Self = Self {
+        s0_byte: true,
+        length_bits: 8,
+        s1_bits: 0,
+    };
+
+    fn is_valid(&self) -> bool {
+        self.length_bits <= 15 && self.s1_bits <= 15
+    }
+
+    /// Writes the layout to `PCNF0`, clearing all other fields of the register.
+    fn write(&self, radio: &pac::radio::RegisterBlock) {
+        unsafe {
+            radio.pcnf0.write(|w| {
+                w.s0len()
+                    .bit(self.s0_byte)
+                    .lflen()
+                    .bits(self.length_bits)
+                    .s1len()
+                    .bits(self.s1_bits)
+            });
+        }
+    }
+}
+
+/// Time between two antenna switches or IQ samples during a Constant Tone Extension.
+#[cfg(feature = "52833")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CteSpacing {
+    /// 4 µs.
+    Us4,
+    /// 2 µs.
+    Us2,
+    /// 1 µs.
+    Us1,
+    /// 500 ns (IQ samples only).
+    Ns500,
+    /// 250 ns (IQ samples only).
+    Ns250,
+    /// 125 ns (IQ samples only).
+    Ns125,
+}
+
+#[cfg(feature = "52833")]
+impl CteSpacing {
+    /// Returns the encoding of the spacing used by the fields of `DFECTRL1`.
+    fn bits(self) -> u32 {
+        match self {
+            CteSpacing::Us4 => 1,
+            CteSpacing::Us2 => 2,
+            CteSpacing::Us1 => 3,
+            CteSpacing::Ns500 => 4,
+            CteSpacing::Ns250 => 5,
+            CteSpacing::Ns125 => 6,
+        }
+    }
+}
+
+/// Configuration for receiving Constant Tone Extensions (CTEs) for Angle of Arrival (AoA)
+/// direction finding, passed to [`BleRadio::enable_cte_rx`].
+///
+/// Connectionless CTEs are only attached to the `AUX_SYNC_IND` PDUs of a periodic advertising
+/// train, which are received with [`BleRadio::listen_periodic_sync`]. The radio doesn't parse the
+/// `CTEInfo` field of these PDUs, so it samples a CTE of the configured fixed length after the CRC
+/// of every packet received that way.
+///
+/// Direction finding is only available with the `52833` feature. The nRF52840 has no Direction
+/// Finding Extension (DFE). The nRF52811 does, but isn't supported yet, since its DFE registers
+/// haven't been verified against this driver.
+#[cfg(feature = "52833")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CteRxConfig {
+    /// Length of the CTE in units of 8 µs. Must lie between 2 and 20 (16-160 µs).
+    pub length_8us: u8,
+
+    /// Time between two antenna switches. Must be at least 1 µs.
+    ///
+    /// This is twice the slot duration used by the transmitter (4 µs for 2 µs slots).
+    pub switch_spacing: CteSpacing,
+
+    /// Time between two IQ samples during the 8 µs reference period.
+    pub ref_sample_spacing: CteSpacing,
+
+    /// Time between two IQ samples during the switch and sample slots.
+    pub sample_spacing: CteSpacing,
+
+    /// Values written to the GPIOs selected by `antenna_pins` when switching antennas.
+    ///
+    /// Bit `n` of each entry drives `antenna_pins[n]`. The first entry is applied outside of the
+    /// CTE, the second one during the guard and reference periods, and the radio cycles through
+    /// the remaining ones for the switch slots. May hold at most 40 entries, or none if no
+    /// antennas are switched.
+    pub antenna_pattern: &'static [u8],
+
+    /// GPIOs used to switch antennas (the `PSEL.DFEGPIO` registers), counting the pins of port 1
+    /// from 32. `None` leaves the corresponding output disconnected.
+    pub antenna_pins: [Option<u8>; 8],
+}
+
+#[cfg(feature = "52833")]
+impl CteRxConfig {
+    /// Maximum number of entries in [`antenna_pattern`](Self::antenna_pattern).
+    pub const MAX_PATTERN_LEN: usize = 40;
+
+    fn is_valid(&self) -> bool {
+        (2..=20).contains(&self.length_8us)
+            && matches!(
+                self.switch_spacing,
+                CteSpacing::Us4 | CteSpacing::Us2 | CteSpacing::Us1
+            )
+            && self.antenna_pattern.len() <= Self::MAX_PATTERN_LEN
+            && self.antenna_pins.iter().flatten().all(|&pin| pin < 64)
+    }
+
+    /// Returns the value of the `DFECTRL1` register for this configuration.
+    ///
+    /// The CTE is expected after the CRC and sampled as IQ pairs, without repeating antenna
+    /// patterns or backing off the gain.
+    fn dfectrl1(&self) -> u32 {
+        u32::from(self.length_8us)
+            | 1 << 7
+            | self.switch_spacing.bits() << 8
+            | self.ref_sample_spacing.bits() << 12
+            | self.sample_spacing.bits() << 16
+    }
+}
+
+#[cfg(feature = "52833")]
+impl Default for CteRxConfig {
+    /// Returns the configuration matching the reset value of `DFECTRL1`: A 16 µs CTE with 2 µs
+    /// antenna switch and sample spacing, and 1 µs reference sample spacing, without antenna
+    /// switching.
+    fn default() -> Self {
+        Self {
+            length_8us: 2,
+            switch_spacing: CteSpacing::Us2,
+            ref_sample_spacing: CteSpacing::Us1,
+            sample_spacing: CteSpacing::Us2,
+            antenna_pattern: &[],
+            antenna_pins: [None; 8],
+        }
+    }
+}
+
+/// An IQ sample taken during a Constant Tone Extension.
+///
+/// The radio writes each sample as a 32-bit word, with the in-phase component in the lower and the
+/// quadrature component in the upper half, which matches the layout of this struct.
+#[cfg(feature = "52833")]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct IqSample {
+    /// In-phase component.
+    pub i: i16,
+
+    /// Quadrature component.
+    pub q: i16,
+}
+
+/// IQ samples of a Constant Tone Extension, reported to the handler passed to
+/// [`BleRadio::enable_cte_rx`].
+#[cfg(feature = "52833")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IqReport<'a> {
+    /// Time at which the radio interrupt fired for the packet carrying the CTE.
+    pub timestamp: Instant,
+
+    /// Whether the CRC of the packet carrying the CTE was valid.
+    pub crc_ok: bool,
+
+    /// Received signal strength of the packet in dBm.
+    pub rssi: i8,
+
+    /// The samples captured during the CTE, in order of reception.
+    pub samples: &'a [IqSample],
+}
+
+/// CTE reception state set up by [`BleRadio::enable_cte_rx`].
+#[cfg(feature = "52833")]
+struct CteRx {
+    /// EasyDMA buffer the IQ samples are written to.
+    buf: &'static mut [IqSample],
+    handler: fn(&IqReport<'_>),
+}
+
+/// Writes the static Direction Finding configuration to the radio, leaving `DFEMODE` disabled.
+#[cfg(feature = "52833")]
+fn configure_dfe(radio: &pac::radio::RegisterBlock, config: &CteRxConfig) {
+    unsafe {
+        radio.dfemode.write(|w| w.bits(0));
+        // Don't parse the `CTEInfo` of data channel PDUs, use the length from `DFECTRL1` instead
+        radio.cteinlineconf.write(|w| w.bits(0));
+        radio.dfectrl1.write(|w| w.bits(config.dfectrl1()));
+        radio.dfectrl2.write(|w| w.bits(0));
+
+        radio.clearpattern.write(|w| w.bits(1));
+        for &entry in config.antenna_pattern {
+            radio.switchpattern.write(|w| w.bits(entry.into()));
+        }
+        for (psel, pin) in radio.psel.dfegpio.iter().zip(config.antenna_pins) {
+            // Setting the `CONNECT` bit disconnects the pin
+            psel.write(|w| w.bits(pin.map_or(1 << 31, u32::from)));
+        }
+    }
+}
+
+/// The peripheral and buffers handed back by [`BleRadio::free`].
+pub struct FreedRadio {
+    /// The `RADIO` peripheral, in the `DISABLED` state.
+    pub radio: RADIO,
+
+    /// The TX buffer in use when the radio was freed.
+    ///
+    /// If a spare TX buffer was set, this may be either of the two buffers, since they are
+    /// swapped during operation.
+    pub tx_buf: &'static mut [u8],
+
+    /// The RX buffer passed to [`BleRadio::new`].
+    pub rx_buf: &'static mut [u8],
+
+    /// The other TX buffer, if one was passed to [`BleRadio::set_spare_tx_buf`].
+    pub spare_tx_buf: Option<&'static mut [u8]>,
+}
+
+/// Error returned by [`BleRadio::new`], handing back the peripheral and buffers passed to it.
+pub struct RadioInitError {
+    /// The reason the radio couldn't be initialized.
+    pub error: Error,
+
+    /// The `RADIO` peripheral.
+    pub radio: RADIO,
+
+    /// The TX buffer passed to `new`.
+    pub tx_buf: &'static mut [u8],
+
+    /// The RX buffer passed to `new`.
+    pub rx_buf: &'static mut [u8],
+}
+
+impl fmt::Debug for RadioInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RadioInitError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for RadioInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
 /// An interface to the nRF radio in BLE mode.
 pub struct BleRadio {
     /// `true` if the radio is operating on an advertising channel, `false` if it's a data channel.
     advertising: bool,
+
+    /// Advertising channel the receiver was started on automatically after transmitting an
+    /// advertising PDU (using the DISABLED_RXEN shortcut).
+    adv_rx_channel: Option<u8>,
+
+    /// Number of transmissions of a repeated advertising PDU that haven't ended yet.
+    ///
+    /// The repetitions are started by the DISABLED_TXEN shortcut, and `recv_interrupt` counts
+    /// them down (see [`adv_repeat_ended`]).
+    adv_tx_left: u16,
+
+    /// Receive timeout to arm once the receiver is started after the last repetition.
+    adv_rx_timeout: Option<Duration>,
     radio: RADIO,
-    tx_buf: &'static mut PacketBuffer,
+    tx_buf: &'static mut [u8],
+
+    /// Whether a PDU in `tx_buf` is due to be sent by a shortcut (READY_START for data channel
+    /// PDUs, DISABLED_TXEN for repetitions of advertising PDUs).
+    ///
+    /// `tx_payload_buf` must not hand out `tx_buf` until that transmission is over. This is
+    /// cleared once the radio has been disabled.
+    tx_armed: bool,
+
+    /// Second TX buffer that is swapped with `tx_buf` while the radio reads from `tx_buf`.
+    spare_tx_buf: Option<&'static mut [u8]>,
 
     /// Receive buffer.
     ///
     /// This is an `Option` because we need to pass a `&mut BleRadio` to the BLE stack while still
     /// having access to this buffer.
-    rx_buf: Option<&'static mut PacketBuffer>,
+    rx_buf: Option<&'static mut [u8]>,
+
+    /// Number of payload Bytes `rx_buf` can hold (the `MAXLEN` field of `PCNF1`).
+    max_rx_payload: u8,
+
+    /// Whether data whitening is applied to transmitted and received packets.
+    whitening: bool,
+
+    /// Reception counters, updated in `recv_interrupt`.
+    stats: RadioStats,
+
+    /// Length of the Base Address in Bytes (the `BALEN` field of `PCNF1`).
+    base_address_len: u8,
+
+    /// CRC initialization value used on advertising channels.
+    adv_crc_init: u32,
+
+    /// Callback invoked on radio state transitions.
+    event_handler: Option<fn(RadioEvent)>,
+
+    /// Diagnostic callback invoked with every packet that failed the CRC check.
+    crc_error_handler: Option<fn(&CrcErrorPacket<'_>)>,
+
+    /// Hardware used to enforce deadlines and receive timeouts, if enabled.
+    timeouts: Option<Timeouts>,
+
+    /// Whether the radio uses fast ramp-up when it is enabled by software.
+    fast_ramp_up: bool,
+
+    /// Offset in MHz added to the frequency of every channel.
+    frequency_offset: i8,
+
+    /// Header layout used on advertising channels.
+    adv_layout: HeaderLayout,
+
+    /// Header layout used on data channels.
+    data_layout: HeaderLayout,
+
+    /// Buffer and handler for IQ samples, if CTE reception is enabled.
+    #[cfg(feature = "52833")]
+    cte_rx: Option<CteRx>,
+
+    /// Whether the receiver was started by `listen_periodic_sync`, so that the received packet
+    /// isn't passed to the Link-Layer.
+    #[cfg(feature = "52833")]
+    sync_rx: bool,
 }
 
 impl BleRadio {
     /// Initializes the radio in BLE mode and takes ownership of the RX and TX buffers.
+    ///
+    /// `rx_buf` may be larger than a `PacketBuffer` to receive data channel PDUs of up to
+    /// 255 Bytes after the Data Length Update Procedure. The Link-Layer never negotiates PDUs that
+    /// don't fit into it.
+    ///
+    /// `tx_buf` may be larger than a `PacketBuffer` to send extended advertising PDUs with more
+    /// data, independently of the size of `rx_buf`. The radio truncates packets exceeding `MAXLEN`
+    /// in both directions, so it is raised to the size of `tx_buf` for transmissions that aren't
+    /// followed by a reception, and lowered to the size of `rx_buf` whenever the radio may receive.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RadioInitError`] with `Error::InvalidLength` if either buffer can't hold an
+    /// advertising PDU of maximum legacy size (`MIN_PDU_BUF` Bytes), or if it is larger than the
+    /// largest possible PDU (257 Bytes). In that case, the radio is not modified, and `radio`,
+    /// `tx_buf` and `rx_buf` are handed back in the error.
+    ///
+    /// A radio left enabled by a previous user is disabled first. If that doesn't succeed (see
+    /// [`abort`](Self::abort)), the error holds `Error::Timeout` instead.
     // TODO: Use type-safe clock configuration to ensure that chip uses ext. crystal
     pub fn new(
         radio: RADIO,
         ficr: &pac::FICR,
-        tx_buf: &'static mut PacketBuffer,
-        rx_buf: &'static mut PacketBuffer,
-    ) -> Self {
-        assert!(radio.state.read().state().is_disabled());
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+    ) -> Result<Self, RadioInitError> {
+        let buf_sizes = MIN_PDU_BUF..=usize::from(u8::MAX) + 2;
+        if !buf_sizes.contains(&tx_buf.len()) || !buf_sizes.contains(&rx_buf.len()) {
+            return Err(RadioInitError {
+                error: Error::InvalidLength,
+                radio,
+                tx_buf,
+                rx_buf,
+            });
+        }
+        let max_payload = (rx_buf.len() - 2) as u8;
+
+        // Interrupts left enabled by a previous user of the radio would never be acknowledged, and
+        // an ongoing operation is stopped
+        let mut stats = RadioStats::new();
+        if stop_for_release(&radio) && wait_for_disable(&radio, &mut stats, None).is_err() {
+            return Err(RadioInitError {
+                error: Error::Timeout,
+                radio,
+                tx_buf,
+                rx_buf,
+            });
+        }
 
         // The nRF51 requires manually setting the trim values.
         #[cfg(feature = "51")]
@@ -114,17 +1104,14 @@ impl BleRadio {
         radio.mode.write(|w| w.mode().ble_1mbit());
         radio.txpower.write(|w| w.txpower().pos4d_bm());
 
-        let max_payload = rx_buf.len() - 2;
-        assert!(max_payload <= usize::from(u8::max_value()));
-
         unsafe {
             radio.pcnf1.write(|w| {
                 // no packet length limit
                 w.maxlen()
-                    .bits(max_payload as u8)
+                    .bits(max_payload)
                     // 3-Byte Base Address + 1-Byte Address Prefix
                     .balen()
-                    .bits(3)
+                    .bits(BLE_BASE_ADDRESS_LEN)
                     // Enable Data Whitening over PDU+CRC
                     .whiteen()
                     .set_bit()
@@ -136,22 +1123,14 @@ impl BleRadio {
                 w.skipaddr().skip().len().three()
             });
 
-            radio
-                .crcpoly
-                .write(|w| w.crcpoly().bits(CRC_POLY & 0x00FFFFFF));
+            set_crc_poly(&radio, CRC_POLY);
 
             // Configure logical address 0 as the canonical advertising address.
             // Base addresses are up to 32 bits in size. However, an 8 bit Address Prefix is
             // *always* appended, so we must use a 24 bit Base Address and the 8 bit Prefix.
-            // BASE0 has, apparently, undocumented semantics: It is a proper 32-bit register, but
-            // it ignores the *lowest* 8 bit and instead transmits the upper 24 as the low 24 bits
-            // of the Access Address. Shift address up to fix this.
-            radio
-                .base0
-                .write(|w| w.bits(advertising::ACCESS_ADDRESS << 8));
-            radio
-                .prefix0
-                .write(|w| w.ap0().bits((advertising::ACCESS_ADDRESS >> 24) as u8));
+            let (base, prefix) = base_and_prefix(advertising::ACCESS_ADDRESS, BLE_BASE_ADDRESS_LEN);
+            radio.base0.write(|w| w.bits(base));
+            radio.prefix0.modify(|_, w| w.ap0().bits(prefix));
         }
 
         // FIXME: No TIFS hardware support for now. Revisit when precise semantics are clear.
@@ -176,49 +1155,701 @@ impl BleRadio {
         // We can now start the TXEN/RXEN tasks and the radio will do the rest and return to the
         // disabled state.
 
-        Self {
+        Ok(Self {
             advertising: false,
+            adv_rx_channel: None,
+            adv_tx_left: 0,
+            adv_rx_timeout: None,
             radio,
             tx_buf,
+            tx_armed: false,
+            spare_tx_buf: None,
             rx_buf: Some(rx_buf),
-        }
+            max_rx_payload: max_payload,
+            whitening: true,
+            stats,
+            base_address_len: BLE_BASE_ADDRESS_LEN,
+            adv_crc_init: advertising::CRC_PRESET,
+            event_handler: None,
+            crc_error_handler: None,
+            timeouts: None,
+            fast_ramp_up: false,
+            frequency_offset: 0,
+            adv_layout: HeaderLayout::BLE,
+            data_layout: HeaderLayout::BLE,
+            #[cfg(feature = "52833")]
+            cte_rx: None,
+            #[cfg(feature = "52833")]
+            sync_rx: false,
+        })
     }
 
-    /// Returns the current radio state.
-    pub fn state(&self) -> STATE_R {
-        self.radio.state.read().state()
+    /// Connects the radio to `timer`, allowing it to enforce [`TimeWindow`]s and receive timeouts.
+    ///
+    /// Without this, the radio keeps listening until the next `RadioCmd` is applied, and the
+    /// windows passed to [`configure_window`](Self::configure_window) are ignored.
+    ///
+    /// This uses the `CC[2]` and `CC[3]` registers of `timer`, and the following PPI channels,
+    /// which must not be used for anything else:
+    ///
+    /// * `channels[0]` disables the radio when a window ends.
+    /// * `channels[1]` disables the radio when a receive timeout elapses.
+    /// * `channels[2]` cancels the receive timeout when the radio starts receiving a packet. This
+    ///   also timestamps data channel packets, which the Link-Layer uses to synchronize to the
+    ///   master's anchor points more precisely.
+    pub fn enable_timeouts<T: NrfTimerExt>(
+        &mut self,
+        ppi: pac::PPI,
+        channels: [u8; 3],
+        timer: &BleTimer<T>,
+    ) {
+        let [deadline_ch, rx_timeout_ch, capture_ch] = channels;
+        let timeouts = Timeouts {
+            ppi,
+            timer: timer.registers() as usize,
+            deadline_ch,
+            rx_timeout_ch,
+            capture_ch,
+        };
+        timeouts.disable(1 << deadline_ch | timeouts.rx_timeout_mask());
+
+        let timer = timeouts.timer();
+        let connect = |ch: u8, event: u32, task: u32| {
+            let ch = &timeouts.ppi.ch[usize::from(ch)];
+            ch.eep.write(|w| unsafe { w.bits(event) });
+            ch.tep.write(|w| unsafe { w.bits(task) });
+        };
+        let disable_task = &self.radio.tasks_disable as *const _ as u32;
+        connect(
+            deadline_ch,
+            &timer.events_compare[DEADLINE_CC] as *const _ as u32,
+            disable_task,
+        );
+        connect(
+            rx_timeout_ch,
+            &timer.events_compare[RX_TIMEOUT_CC] as *const _ as u32,
+            disable_task,
+        );
+        // Capturing the current time overwrites the compare value, so the timeout will not fire
+        connect(
+            capture_ch,
+            &self.radio.events_address as *const _ as u32,
+            &timer.tasks_capture[RX_TIMEOUT_CC] as *const _ as u32,
+        );
+
+        self.timeouts = Some(timeouts);
     }
 
-    /// Configures the Radio for (not) receiving data according to `cmd`.
-    pub fn configure_receiver(&mut self, cmd: RadioCmd) {
-        // Waits for the end of any ongoing transmissions. Don't wait if we lost the last connection
-        // event, since we shouldn't be transmitting anyway
-        if let RadioCmd::ListenData { timeout, .. } = cmd {
-            if !timeout {
-                while self.state().is_tx() || self.state().is_tx_ru() {}
+    /// Disconnects the radio from the timer connected by [`enable_timeouts`](Self::enable_timeouts)
+    /// and returns the `PPI` peripheral.
+    ///
+    /// The PPI channels used by the radio are disabled. Returns `None` if timeouts weren't enabled.
+    pub fn disable_timeouts(&mut self) -> Option<pac::PPI> {
+        let timeouts = self.timeouts.take()?;
+        timeouts.disable(1 << timeouts.deadline_ch | timeouts.rx_timeout_mask());
+        Some(timeouts.ppi)
+    }
+
+    /// Configures the radio to be disabled at the end of `window` (as found in [`Cmd::window`]).
+    ///
+    /// If `window` is `None`, the radio may stay enabled indefinitely. This does nothing unless
+    /// [`enable_timeouts`](Self::enable_timeouts) was called before.
+    pub fn configure_window(&mut self, window: Option<TimeWindow>) {
+        if let Some(timeouts) = &self.timeouts {
+            let mask = 1 << timeouts.deadline_ch;
+            match window {
+                Some(window) => {
+                    let timer = timeouts.timer();
+                    timer.cc[DEADLINE_CC].write(|w| unsafe { w.bits(window.end().ticks()) });
+                    timer.events_compare[DEADLINE_CC].reset();
+                    timeouts.enable(mask);
+                }
+                None => timeouts.disable(mask),
             }
         }
-        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
-        compiler_fence(Ordering::Acquire);
+    }
 
-        // Disable `DISABLED` interrupt, effectively stopping reception
-        self.radio.intenclr.write(|w| w.disabled().clear());
+    /// Disables the radio `timeout` from now, unless it has started receiving a packet by then.
+    fn arm_rx_timeout(&mut self, timeout: Duration) {
+        if let Some(timeouts) = &self.timeouts {
+            let timer = timeouts.timer();
+            timer.tasks_capture[RX_TIMEOUT_CC].write(|w| unsafe { w.bits(1) });
+            let now = timer.cc[RX_TIMEOUT_CC].read().bits();
+            let at = now.wrapping_add(timeout.to_micros());
+            timer.cc[RX_TIMEOUT_CC].write(|w| unsafe { w.bits(at) });
+            timer.events_compare[RX_TIMEOUT_CC].reset();
+            timeouts.enable(timeouts.rx_timeout_mask());
+        }
+    }
 
-        // Acknowledge left-over disable event
-        self.radio.events_disabled.reset();
-        // Disable radio
-        self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
-        // Then wait until disable event is triggered
-        while self.radio.events_disabled.read().bits() == 0 {}
-        // And acknowledge it
+    fn disarm_rx_timeout(&mut self) {
+        if let Some(timeouts) = &self.timeouts {
+            timeouts.disable(timeouts.rx_timeout_mask());
+        }
+    }
+
+    /// Sets a function to call whenever the radio changes its state.
+    ///
+    /// The handler is called from the context that drives the radio (usually the `RADIO` interrupt
+    /// handler), so it should return quickly. Passing `None` removes the handler.
+    pub fn set_event_handler(&mut self, handler: Option<fn(RadioEvent)>) {
+        self.event_handler = handler;
+    }
+
+    /// Sets a function to call with every packet received with an invalid CRC.
+    ///
+    /// This is meant for sniffers and link analyzers that want to look at corrupted packets. The
+    /// Link-Layer still discards these packets and only uses them for timing, as required by the
+    /// specification, so the handler does not affect the stack.
+    ///
+    /// The handler is called from `recv_interrupt` after the Link-Layer has processed the packet
+    /// (so that it doesn't delay any response). It must return quickly: the radio may receive the
+    /// next packet into the same buffer shortly after. Passing `None` removes the handler.
+    pub fn set_crc_error_handler(&mut self, handler: Option<fn(&CrcErrorPacket<'_>)>) {
+        self.crc_error_handler = handler;
+    }
+
+    #[inline]
+    fn emit(&self, event: RadioEvent) {
+        if let Some(handler) = self.event_handler {
+            handler(event);
+        }
+    }
+
+    /// Sets the length of the Base Address part of the radio address, in Bytes.
+    ///
+    /// The radio address consists of the Base Address and a 1-Byte Address Prefix. BLE requires a
+    /// 3-Byte Base Address (the default) to form the 32-bit Access Address. Other lengths are only
+    /// useful for test setups and proprietary modes, and make the radio unable to communicate with
+    /// BLE devices. With a length of 4, the Address Prefix is always 0.
+    ///
+    /// The advertising address (logical address 0) is reconfigured immediately, data channel
+    /// addresses are applied when the next data channel transmission or reception is prepared.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `len` is not in range 1..=4 or if the radio is not currently disabled.
+    pub fn set_base_address_length(&mut self, len: u8) {
+        assert!(
+            (1..=4).contains(&len),
+            "invalid base address length {}",
+            len
+        );
+        assert!(self.state().is_disabled());
+
+        self.base_address_len = len;
+        let (base, prefix) = base_and_prefix(advertising::ACCESS_ADDRESS, len);
+        unsafe {
+            self.radio.pcnf1.modify(|_, w| w.balen().bits(len));
+            self.radio.base0.write(|w| w.bits(base));
+            self.radio.prefix0.modify(|_, w| w.ap0().bits(prefix));
+        }
+    }
+
+    /// Overrides the CRC polynomial and the CRC initialization value used on advertising channels.
+    ///
+    /// By default, the BLE polynomial ([`CRC_POLY`]) and advertising CRC preset
+    /// ([`advertising::CRC_PRESET`]) are used. Changing them is only useful for test setups and
+    /// for bridging to proprietary protocols: **a radio using a non-standard CRC cannot
+    /// communicate with any real BLE device**, since all packets will be received with a bad CRC.
+    ///
+    /// Both values are truncated to 24 bits. In `poly`, bit `n` corresponds to the `x^n` term, and
+    /// the `x^24` term is implicit. On data channels, the CRC is initialized with the value chosen
+    /// by the connection's master.
+    ///
+    /// The polynomial is reconfigured immediately, the initialization value is applied when the
+    /// next advertising channel transmission or reception is prepared.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the radio is not currently disabled.
+    pub fn set_crc(&mut self, poly: u32, adv_init: u32) {
+        assert!(self.state().is_disabled());
+
+        self.adv_crc_init = crc24(adv_init);
+        set_crc_poly(&self.radio, poly);
+    }
+
+    /// Overrides the header layouts used on advertising and data channels.
+    ///
+    /// By default, [`HeaderLayout::BLE`] is used on both. Changing it makes the radio unable to
+    /// communicate with BLE devices using the current header format. The layouts are applied when
+    /// the next transmission or reception is prepared.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if a `LENGTH` or `S1` field is larger than 15 bits.
+    pub fn set_header_layout(&mut self, advertising: HeaderLayout, data: HeaderLayout) {
+        assert!(
+            advertising.is_valid() && data.is_valid(),
+            "invalid header layout"
+        );
+
+        self.adv_layout = advertising;
+        self.data_layout = data;
+    }
+
+    /// Enables reception of Constant Tone Extensions for Angle of Arrival direction finding.
+    ///
+    /// While enabled, the radio samples a CTE after every packet received with
+    /// [`listen_periodic_sync`](Self::listen_periodic_sync) and writes the IQ samples to `iq_buf`
+    /// using EasyDMA. `handler` is then called from `recv_interrupt` with the samples. Only the
+    /// receive side of connectionless CTEs is supported.
+    ///
+    /// `iq_buf` should hold enough samples for the configured CTE length and spacings. Excess
+    /// samples are discarded by the radio.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `config` is out of range, and `Error::InvalidLength` if
+    /// `iq_buf` is empty or holds more than 65535 samples. In both cases, CTE reception is left
+    /// unchanged.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the radio is not currently disabled.
+    #[cfg(feature = "52833")]
+    pub fn enable_cte_rx(
+        &mut self,
+        config: CteRxConfig,
+        iq_buf: &'static mut [IqSample],
+        handler: fn(&IqReport<'_>),
+    ) -> Result<(), Error> {
+        if !config.is_valid() {
+            return Err(Error::InvalidValue);
+        }
+        if iq_buf.is_empty() || iq_buf.len() > usize::from(u16::MAX) {
+            return Err(Error::InvalidLength);
+        }
+        assert!(self.state().is_disabled());
+
+        configure_dfe(&self.radio, &config);
+        self.cte_rx = Some(CteRx {
+            buf: iq_buf,
+            handler,
+        });
+        Ok(())
+    }
+
+    /// Disables CTE reception, returning the IQ sample buffer passed to
+    /// [`enable_cte_rx`](Self::enable_cte_rx).
+    ///
+    /// Returns `None` if CTE reception wasn't enabled.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the radio is not currently disabled.
+    #[cfg(feature = "52833")]
+    pub fn disable_cte_rx(&mut self) -> Option<&'static mut [IqSample]> {
+        assert!(self.state().is_disabled());
+
+        self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+        self.cte_rx.take().map(|cte| cte.buf)
+    }
+
+    /// Starts sampling the CTE of the next packet received by `listen_periodic_sync`, if enabled.
+    ///
+    /// The radio is disabled at the end of the CTE (`PHYEND`) instead of the end of the packet.
+    #[cfg(feature = "52833")]
+    fn arm_cte_rx(&mut self) {
+        if let Some(cte) = &mut self.cte_rx {
+            let ptr = cte.buf.as_mut_ptr() as u32;
+            let len = cte.buf.len() as u32;
+            unsafe {
+                self.radio.dfepacket.ptr.write(|w| w.bits(ptr));
+                self.radio.dfepacket.maxcnt.write(|w| w.bits(len));
+                // `DFEOPMODE = AoA`
+                self.radio.dfemode.write(|w| w.bits(3));
+            }
+            self.radio
+                .shorts
+                .modify(|_, w| w.end_disable().disabled().phyend_disable().enabled());
+        }
+    }
+
+    /// Starts listening for an `AUX_SYNC_IND` of a periodic advertising train on `channel`, and
+    /// samples the Constant Tone Extension attached to it (if CTE reception is enabled with
+    /// [`enable_cte_rx`](Self::enable_cte_rx)).
+    ///
+    /// `access_address` and `crc_init` are the ones of the train, as sent in its `SyncInfo`. The
+    /// Link-Layer doesn't synchronize to periodic advertising trains, so the caller has to start
+    /// the receiver in time for each `AUX_SYNC_IND`, while the Link-Layer doesn't use the radio
+    /// (eg. in standby). The received packet is not passed to the Link-Layer: `recv_interrupt`
+    /// only reports the IQ samples and returns `None`. Passing the next `RadioCmd` of the
+    /// Link-Layer to [`configure_receiver`](Self::configure_receiver) stops listening.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the radio couldn't be disabled first (see
+    /// [`abort`](Self::abort)).
+    #[cfg(feature = "52833")]
+    pub fn listen_periodic_sync(
+        &mut self,
+        channel: DataChannel,
+        access_address: u32,
+        crc_init: u32,
+    ) -> Result<(), Error> {
+        self.abort()?;
+        self.prepare_txrx_data(channel, access_address, crc_init, Phy::Le1M);
+        self.sync_rx = true;
+
+        let rx_buf = self.rx_buf.as_mut().unwrap().as_mut_ptr() as u32;
+        self.radio.packetptr.write(|w| unsafe { w.bits(rx_buf) });
+        self.radio.rxaddresses.write(|w| w.addr1().enabled());
+
+        // Nothing is sent in response. The RSSI is sampled for the `IqReport`.
+        self.radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .address_rssistart()
+                .enabled()
+                .disabled_rssistop()
+                .enabled()
+        });
+        self.arm_cte_rx();
+
+        self.radio.events_address.reset();
+        self.radio.events_end.reset();
+        self.radio.intenset.write(|w| w.disabled().set());
+
+        self.radio.events_ready.reset();
+        self.prepare_ramp_up();
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+
+        self.radio.tasks_rxen.write(|w| unsafe { w.bits(1) });
+        self.emit(RadioEvent::RxStarted);
+        self.finish_ramp_up();
+        Ok(())
+    }
+
+    /// Handles the end of a reception started by `listen_periodic_sync`.
+    #[cfg(feature = "52833")]
+    fn finish_sync_rx(&mut self, timestamp: Instant) {
+        self.sync_rx = false;
+        self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+        if self.radio.events_end.read().bits() == 0 && self.radio.events_phyend.read().bits() == 0 {
+            self.emit(RadioEvent::Disabled);
+            return;
+        }
+        self.radio.events_end.reset();
+        self.radio.events_phyend.reset();
+
+        let crc_ok = self.radio.crcstatus.read().crcstatus().is_crcok();
+        self.stats.record_rx(crc_ok);
+        self.emit(RadioEvent::PacketReceived { crc_ok });
+        let rssi = -(self.radio.rssisample.read().rssisample().bits() as i8);
+        self.report_iq_samples(timestamp, crc_ok, rssi);
+    }
+
+    /// Passes the IQ samples captured during the last packet to the handler, if CTE reception is
+    /// enabled.
+    #[cfg(feature = "52833")]
+    fn report_iq_samples(&self, timestamp: Instant, crc_ok: bool, rssi: i8) {
+        if let Some(cte) = &self.cte_rx {
+            let amount = self.radio.dfepacket.amount.read().bits() as usize;
+            if amount > 0 {
+                (cte.handler)(&IqReport {
+                    timestamp,
+                    crc_ok,
+                    rssi,
+                    samples: &cte.buf[..cmp::min(amount, cte.buf.len())],
+                });
+            }
+        }
+    }
+
+    /// Returns the CRC polynomial currently configured in the `CRCPOLY` register.
+    pub fn crc_poly(&self) -> u32 {
+        self.radio.crcpoly.read().crcpoly().bits()
+    }
+
+    /// Enables or disables data whitening.
+    ///
+    /// Whitening is enabled by default, as required by the Bluetooth specification. Disabling it
+    /// is only useful for RF conformance testing and debugging: **a radio with whitening disabled
+    /// cannot communicate with any real BLE device**.
+    ///
+    /// The setting persists across all following transmissions and receptions.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the radio is not currently disabled.
+    pub fn set_whitening(&mut self, enabled: bool) {
+        assert!(self.state().is_disabled());
+
+        self.whitening = enabled;
+        self.apply_whitening();
+    }
+
+    /// Writes the configured whitening setting to `PCNF1.WHITEEN`.
+    fn apply_whitening(&mut self) {
+        configure_whitening(&self.radio, self.whitening);
+    }
+
+    /// Returns the reception statistics gathered since creation or the last call to
+    /// [`reset_stats`](Self::reset_stats).
+    pub fn stats(&self) -> &RadioStats {
+        &self.stats
+    }
+
+    /// Resets all reception statistics to 0.
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Enables or disables fast ramp-up (`MODECNF0.RU`).
+    ///
+    /// Fast ramp-up reduces the time needed to enable the receiver or transmitter from 140 µs to
+    /// 40 µs, which saves power at the start of every connection and advertising event. It is
+    /// supported by all nRF52 chips, but not by the nRF51.
+    ///
+    /// The radio only enforces `T_IFS` in hardware when using the default ramp-up, and would reach
+    /// `TXREADY` before `recv_interrupt` can prevent an unwanted transmission. Fast ramp-up is
+    /// therefore only used when the radio is enabled by software. Once the receiver is ready, the
+    /// radio switches back to the default ramp-up, so responses are still sent after exactly
+    /// `T_IFS` via the `DISABLED_TXEN` shortcut.
+    #[cfg(not(feature = "51"))]
+    pub fn set_fast_ramp_up(&mut self, enabled: bool) {
+        self.fast_ramp_up = enabled;
+    }
+
+    /// Adds a second TX buffer, so that the next PDU can be prepared while the last one is sent.
+    ///
+    /// Without it, the Link-Layer has to wait for an ongoing transmission to finish before it can
+    /// write the next PDU into the TX buffer. With a spare buffer, the buffers are swapped instead
+    /// (keeping the contents of the buffer, which are needed for retransmissions). Since the radio
+    /// only sends one PDU at a time, two buffers are always enough.
+    ///
+    /// The buffers may be swapped at any time. [`free`](Self::free) returns both of them, but
+    /// either one may be in [`FreedRadio::tx_buf`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidLength` if `buf` doesn't have the same size as the TX buffer passed to
+    /// [`new`](Self::new). In that case, the spare buffer is not changed.
+    pub fn set_spare_tx_buf(&mut self, buf: &'static mut [u8]) -> Result<(), Error> {
+        if buf.len() != self.tx_buf.len() {
+            return Err(Error::InvalidLength);
+        }
+
+        self.spare_tx_buf = Some(buf);
+        Ok(())
+    }
+
+    /// Shifts the frequency of all channels by `channel_offset` MHz.
+    ///
+    /// This can compensate a known frequency error of the crystal (eg. caused by wrong load
+    /// capacitors), or be used for RF debugging. The resulting frequency is clamped to the range
+    /// supported by the radio (2360 to 2500 MHz on the nRF52, 2400 to 2500 MHz on the nRF51). The
+    /// default offset of 0 uses the standard channel frequencies.
+    ///
+    /// The offset takes effect the next time the radio is tuned to a channel.
+    pub fn set_frequency_offset(&mut self, channel_offset: i8) {
+        self.frequency_offset = channel_offset;
+    }
+
+    /// Returns the time the radio needs to ramp up the receiver or transmitter when it is enabled
+    /// by software.
+    ///
+    /// Pass this to [`Cmd::sleep_duration`] to determine how long the CPU may sleep before the
+    /// radio is needed again.
+    pub fn ramp_up_time(&self) -> Duration {
+        if cfg!(feature = "51") {
+            Duration::micros(130)
+        } else if self.fast_ramp_up {
+            Duration::micros(40)
+        } else {
+            Duration::micros(140)
+        }
+    }
+
+    /// Selects the ramp-up time before the radio is enabled by software.
+    fn prepare_ramp_up(&self) {
+        #[cfg(not(feature = "51"))]
+        configure_ramp_up(&self.radio, self.fast_ramp_up);
+    }
+
+    /// Waits for a receiver or transmitter started with fast ramp-up to become ready, then switches
+    /// back to the default ramp-up, which the `DISABLED_TXEN` shortcut needs for hardware `T_IFS`.
+    ///
+    /// `events_ready` must have been cleared before the radio was enabled. A radio that doesn't
+    /// become ready within [`TX_POLLS`] polls is left to the next [`recover_if`].
+    fn finish_ramp_up(&self) {
+        #[cfg(not(feature = "51"))]
+        if self.fast_ramp_up {
+            let _ = (0..TX_POLLS).any(|_| self.radio.events_ready.read().bits() != 0);
+            configure_ramp_up(&self.radio, false);
+        }
+    }
+
+    /// Returns the current radio state.
+    pub fn state(&self) -> STATE_R {
+        self.radio.state.read().state()
+    }
+
+    /// Disables the radio if `unexpected` returns `true` for its state (see [`recover_if`]).
+    fn recover_if(&mut self, unexpected: impl FnOnce(&STATE_R) -> bool) -> Result<(), Error> {
+        if recover_if(&self.radio, &mut self.stats, self.event_handler, unexpected)? {
+            self.tx_armed = false;
+        }
+        Ok(())
+    }
+
+    /// Disables the radio and returns the `RADIO` peripheral and the TX and RX buffers passed to
+    /// [`new`](Self::new), along with the spare TX buffer, if any.
+    ///
+    /// Any ongoing reception or transmission is aborted. All interrupts and shortcuts are disabled
+    /// and pending events are acknowledged, so the radio is left in the `DISABLED` state and can be
+    /// passed to `new` again, or powered down entirely.
+    ///
+    /// If timeouts are enabled, their PPI channels are disabled and the `PPI` peripheral is
+    /// dropped. Call [`disable_timeouts`](Self::disable_timeouts) first to keep it.
+    ///
+    /// If the radio can't be disabled (see [`abort`](Self::abort)), it is handed back anyway.
+    pub fn free(mut self) -> FreedRadio {
+        let _ = self.disable_timeouts();
+
+        if stop_for_release(&self.radio) {
+            let _ = wait_for_disable(&self.radio, &mut self.stats, self.event_handler);
+        }
         self.radio.events_disabled.reset();
+        self.radio.events_ready.reset();
+        self.radio.events_address.reset();
+        self.radio.events_end.reset();
+        #[cfg(feature = "52833")]
+        self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+
+        FreedRadio {
+            radio: self.radio,
+            tx_buf: self.tx_buf,
+            rx_buf: self.rx_buf.take().unwrap(),
+            spare_tx_buf: self.spare_tx_buf,
+        }
+    }
+
+    /// Immediately stops any ongoing reception or transmission and disables the radio.
+    ///
+    /// This can be used to end a connection event early. The `DISABLED` interrupt is disabled and
+    /// all pending events are acknowledged, so `recv_interrupt` will not process the aborted packet.
+    /// The RX buffer is left untouched. To resume operation, pass the next `RadioCmd` to
+    /// [`configure_receiver`](Self::configure_receiver).
+    ///
+    /// This may be called from the `RADIO` interrupt handler as well as from normal context, as
+    /// long as both are prevented from accessing the `BleRadio` at the same time.
+    ///
+    /// Returns `true` if a packet was being received (its address had already been matched) when
+    /// the radio was stopped. The peer may then expect a response, so a scheduler might want to
+    /// retry the exchange.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the radio doesn't reach the `DISABLED` state, even after
+    /// clearing all shortcuts and disabling it again. This is counted in [`stats`](Self::stats),
+    /// and the radio is left in an unknown state.
+    pub fn abort(&mut self) -> Result<bool, Error> {
+        let receiving = stop_for_abort(&self.radio);
+        self.disarm_rx_timeout();
+        let disabled = wait_for_disable(&self.radio, &mut self.stats, self.event_handler);
+
+        self.radio.events_address.reset();
+        self.radio.events_end.reset();
+        self.adv_rx_channel = None;
+        self.adv_tx_left = 0;
+        self.adv_rx_timeout = None;
+        self.tx_armed = false;
+        #[cfg(feature = "52833")]
+        {
+            self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+            self.sync_rx = false;
+        }
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+
+        disabled.map(|()| receiving)
+    }
+
+    /// Configures the Radio for (not) receiving data according to `cmd`.
+    ///
+    /// If [`enable_timeouts`](Self::enable_timeouts) was called, the receive timeout requested by
+    /// `cmd` is enforced as well.
+    ///
+    /// If the radio can't be disabled before it is reconfigured (see [`abort`](Self::abort)), it
+    /// is left alone and nothing is received. This is counted in [`stats`](Self::stats).
+    pub fn configure_receiver(&mut self, cmd: RadioCmd) {
+        let rx_timeout = cmd.rx_timeout();
+        self.disarm_rx_timeout();
+        if self.start_receiver(cmd).is_err() {
+            return;
+        }
+        if let Some(timeout) = rx_timeout {
+            if self.adv_tx_left > 0 {
+                // The receiver is only started after the last repetition of the advertising PDU
+                self.adv_rx_timeout = Some(timeout);
+            } else {
+                self.arm_rx_timeout(timeout);
+            }
+        }
+    }
+
+    fn start_receiver(&mut self, cmd: RadioCmd) -> Result<(), Error> {
+        if let RadioCmd::ListenAdvertising { channel, .. } = cmd {
+            let state = self.state();
+            if self.adv_rx_channel == Some(channel.channel())
+                && (self.adv_tx_left > 0 || state.is_rx_ru() || state.is_rx_idle() || state.is_rx())
+            {
+                // The receiver was already started T_IFS after the last advertising PDU (or will
+                // be after its last repetition), so we just have to enable the interrupt.
+                compiler_fence(Ordering::Release);
+                self.radio.intenset.write(|w| w.disabled().set());
+                return Ok(());
+            }
+        }
+        self.adv_rx_channel = None;
+        self.adv_tx_left = 0;
+        self.adv_rx_timeout = None;
+
+        // Waits for the end of any ongoing transmissions. Don't wait if we lost the last connection
+        // event, since we shouldn't be transmitting anyway. A transmission that doesn't end in
+        // time is cut off by disabling the radio below.
+        if let RadioCmd::ListenData { timeout, .. } = cmd {
+            if !timeout {
+                let _ = (0..TX_POLLS).any(|_| !(self.state().is_tx() || self.state().is_tx_ru()));
+            }
+        }
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        compiler_fence(Ordering::Acquire);
+
+        // Disable `DISABLED` interrupt, effectively stopping reception
+        self.radio.intenclr.write(|w| w.disabled().clear());
+
+        // Acknowledge left-over disable event
+        self.radio.events_disabled.reset();
+        // Disable radio
+        self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+        // Then wait until it is disabled, and acknowledge the event
+        let disabled = wait_for_disable(&self.radio, &mut self.stats, self.event_handler);
+        self.tx_armed = false;
+        #[cfg(feature = "52833")]
+        {
+            self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+            self.sync_rx = false;
+        }
+        disabled?;
 
         match cmd {
             RadioCmd::Off => {}
-            RadioCmd::ListenAdvertising { channel } => {
-                self.prepare_txrx_advertising(channel);
+            RadioCmd::ListenAdvertising { channel, .. } => {
+                self.prepare_txrx_advertising(channel)?;
 
-                let rx_buf = (*self.rx_buf.as_mut().unwrap()) as *mut _ as u32;
+                let rx_buf = self.rx_buf.as_mut().unwrap().as_mut_ptr() as u32;
                 self.radio.packetptr.write(|w| unsafe { w.bits(rx_buf) });
 
                 // Enable `DISABLED` interrupt (packet fully received)
@@ -227,31 +1858,60 @@ impl BleRadio {
                 // Match on logical address 0 only
                 self.radio.rxaddresses.write(|w| w.addr0().enabled());
 
-                // Enable the correct shortcuts in case it was changed in a previous connection.
+                // Enforce T_IFS in hardware when responding to a received packet (eg. with a
+                // `SCAN_REQ` while actively scanning).
                 self.radio
-                    .shorts
-                    .write(|w| w.ready_start().enabled().end_disable().enabled());
+                    .tifs
+                    .write(|w| unsafe { w.bits(T_IFS.to_micros()) });
+
+                // Enable the correct shortcuts in case it was changed in a previous connection.
+                // Like on data channels, the radio ramps up the transmitter after receiving a
+                // packet, and `recv_interrupt` disables ready->start so that nothing is sent
+                // unless the Link-Layer responds. Also sample the RSSI of every received packet.
+                self.radio.shorts.write(|w| {
+                    w.ready_start()
+                        .enabled()
+                        .end_disable()
+                        .enabled()
+                        .disabled_txen()
+                        .enabled()
+                        .address_rssistart()
+                        .enabled()
+                        .disabled_rssistop()
+                        .enabled()
+                });
+
+                // Forget addresses matched and packets received earlier, so that `abort` and
+                // `recv_interrupt` can tell whether a packet is being or has been received
+                self.radio.events_address.reset();
+                self.radio.events_end.reset();
+
+                self.radio.events_ready.reset();
+                self.prepare_ramp_up();
 
                 // "Preceding reads and writes cannot be moved past subsequent writes."
                 compiler_fence(Ordering::Release);
 
                 // ...and enter RX mode
                 self.radio.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                self.emit(RadioEvent::RxStarted);
+                self.finish_ramp_up();
             }
             RadioCmd::ListenData {
                 channel,
                 access_address,
                 crc_init,
+                phy,
                 ..
             } => {
-                self.prepare_txrx_data(channel, access_address, crc_init);
+                self.prepare_txrx_data(channel, access_address, crc_init, phy);
 
                 // Enforce T_IFS in hardware.
                 self.radio
                     .tifs
                     .write(|w| unsafe { w.bits(T_IFS.to_micros()) });
 
-                let rx_buf = (*self.rx_buf.as_mut().unwrap()) as *mut _ as u32;
+                let rx_buf = self.rx_buf.as_mut().unwrap().as_mut_ptr() as u32;
                 self.radio.packetptr.write(|w| unsafe { w.bits(rx_buf) });
 
                 // Enable `DISABLED` interrupt (packet fully received)
@@ -260,14 +1920,30 @@ impl BleRadio {
                 // Match on logical address 1 only
                 self.radio.rxaddresses.write(|w| w.addr1().enabled());
 
+                // Forget addresses matched and packets received earlier, so that `abort` and
+                // `recv_interrupt` can tell whether a packet is being or has been received
+                self.radio.events_address.reset();
+                self.radio.events_end.reset();
+
+                // Timestamp the received packet, so the Link-Layer can synchronize to it
+                if let Some(timeouts) = &self.timeouts {
+                    timeouts.enable(1 << timeouts.capture_ch);
+                }
+
+                self.radio.events_ready.reset();
+                self.prepare_ramp_up();
+
                 // "Preceding reads and writes cannot be moved past subsequent writes."
                 compiler_fence(Ordering::Release);
 
                 // ...and enter RX mode
                 self.radio.tasks_rxen.write(|w| unsafe { w.bits(1) });
+                self.emit(RadioEvent::RxStarted);
+                self.finish_ramp_up();
 
                 // Enable the required shortcuts for T_IFS. The radio will go into `TXIDLE` state
-                // automatically after receiving a packet.
+                // automatically after receiving a packet. The RSSI is sampled for the Link-Layer's
+                // link quality estimate.
                 self.radio.shorts.write(|w| {
                     w.end_disable()
                         .enabled()
@@ -275,62 +1951,137 @@ impl BleRadio {
                         .enabled()
                         .ready_start()
                         .enabled()
+                        .address_rssistart()
+                        .enabled()
+                        .disabled_rssistop()
+                        .enabled()
                 });
             }
         }
+        Ok(())
     }
 
     /// Call this when the `RADIO` interrupt fires.
     ///
     /// Automatically reconfigures the radio according to the `RadioCmd` returned by the BLE stack.
     ///
-    /// Returns when the `update` method should be called the next time.
+    /// Returns when the `update` method should be called the next time, or `None` if there's
+    /// nothing to do (eg. when the radio was disabled at the end of a window).
+    ///
+    /// The driver only ever enables the `DISABLED` interrupt, and this method only acknowledges
+    /// the events belonging to the packet it processes. Interrupts of other radio events (such as
+    /// `ADDRESS`, `PAYLOAD` or `END`) must not be enabled: They are left pending and would keep the
+    /// interrupt asserted. If the `DISABLED` event isn't pending, the call is counted as a spurious
+    /// interrupt in [`stats`](Self::stats) and does nothing, so this may also be called from an
+    /// interrupt handler shared with other sources.
+    ///
+    /// The interrupt also fires at the end of each repetition of an advertising PDU (see
+    /// [`AdvParams::repeat`]), which this handles by returning `None`. It has to be handled before
+    /// the next repetition ends, or the receiver won't be started afterwards.
+    ///
+    /// [`AdvParams::repeat`]: rubble::link::advertising::AdvParams::repeat
     pub fn recv_interrupt<C: Config<Transmitter = Self>>(
         &mut self,
         timestamp: Instant,
         ll: &mut LinkLayer<C>,
     ) -> Option<Cmd> {
-        if self.radio.events_disabled.read().bits() == 0 {
+        if !take_disabled_event(&self.radio, &mut self.stats) {
             return None;
         }
 
-        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
-        compiler_fence(Ordering::Acquire);
+        if self.adv_tx_left > 0 {
+            self.continue_adv_repeats();
+            return None;
+        }
 
-        // Acknowledge DISABLED event:
-        self.radio.events_disabled.reset();
+        let timed_out = self.timeouts.as_ref().is_some_and(Timeouts::rx_timed_out);
+        self.disarm_rx_timeout();
 
-        let crc_ok = self.radio.crcstatus.read().crcstatus().is_crcok();
+        #[cfg(feature = "52833")]
+        if self.sync_rx {
+            self.finish_sync_rx(timestamp);
+            return None;
+        }
+
+        let crc_ok = match take_received_packet(&self.radio, &mut self.stats, self.event_handler) {
+            Some(crc_ok) => crc_ok,
+            None => {
+                // The radio was disabled before a packet was received, either because the receive
+                // timeout elapsed or because the window configured with `configure_window` ended
+                #[cfg(feature = "defmt")]
+                defmt::trace!("radio disabled without RX (timed out: {=bool})", timed_out);
+                return if timed_out {
+                    Some(ll.process_rx_timeout(self))
+                } else {
+                    None
+                };
+            }
+        };
 
         let cmd = if self.advertising {
-            // When we get here, the radio must have transitioned to DISABLED state.
-            assert!(self.state().is_disabled());
+            // Important! Turn ready->start off before TXREADY is reached (in ~150µs)
+            self.radio.shorts.modify(|_, w| w.ready_start().disabled());
+            if self.recover_if(STATE_R::is_tx).is_err() {
+                // The radio is unusable, drop the packet as if it was never received
+                return None;
+            }
 
-            let header = advertising::Header::parse(*self.rx_buf.as_ref().unwrap());
+            let header = advertising::Header::parse(self.rx_buf.as_ref().unwrap());
 
-            // check that `payload_length` is in bounds
             let rx_buf = self.rx_buf.take().unwrap();
-            let pl_lim = cmp::min(2 + usize::from(header.payload_length()), rx_buf.len());
-            let payload = &rx_buf[2..pl_lim];
-            let cmd = ll.process_adv_packet(timestamp, self, header, payload, crc_ok);
+            let (payload, crc_ok) =
+                checked_payload(rx_buf, header.payload_length(), crc_ok, &mut self.stats);
+            // RSSISAMPLE holds the magnitude of the (negative) signal strength in dBm
+            let rssi = -(self.radio.rssisample.read().rssisample().bits() as i8);
+            let cmd = ll.process_adv_packet(timestamp, self, header, payload, crc_ok, Some(rssi));
+            report_crc_error(
+                self.crc_error_handler,
+                crc_ok,
+                true,
+                rx_buf,
+                header.payload_length(),
+                rssi,
+                timestamp,
+            );
             self.rx_buf = Some(rx_buf);
             cmd
         } else {
             // Important! Turn ready->start off before TXREADY is reached (in ~150µs)
             self.radio.shorts.modify(|_, w| w.ready_start().disabled());
-            assert!(!self.state().is_tx());
+            if self.recover_if(STATE_R::is_tx).is_err() {
+                // The radio is unusable, drop the packet as if it was never received
+                return None;
+            }
 
-            let header = data::Header::parse(*self.rx_buf.as_ref().unwrap());
+            let header = data::Header::parse(self.rx_buf.as_ref().unwrap());
 
-            // check that `payload_length` is in bounds
             let rx_buf = self.rx_buf.take().unwrap();
-            let pl_lim = cmp::min(2 + usize::from(header.payload_length()), rx_buf.len());
-            let payload = &rx_buf[2..pl_lim];
-            let cmd = ll.process_data_packet(timestamp, self, header, payload, crc_ok);
+            let (payload, crc_ok) =
+                checked_payload(rx_buf, header.payload_length(), crc_ok, &mut self.stats);
+            let rssi = -(self.radio.rssisample.read().rssisample().bits() as i8);
+            // The capture was enabled when the receiver was started, so it holds the time at
+            // which the packet's address was matched
+            let mut info = DataPacketInfo::new(timestamp, crc_ok);
+            info.address_time = self.timeouts.as_ref().map(Timeouts::address_time);
+            info.rssi = Some(rssi);
+            let cmd = ll.process_data_packet_with_info(self, header, payload, info);
+            report_crc_error(
+                self.crc_error_handler,
+                crc_ok,
+                false,
+                rx_buf,
+                header.payload_length(),
+                rssi,
+                timestamp,
+            );
             self.rx_buf = Some(rx_buf);
             cmd
         };
 
+        // Any response has already been sent at this point, so logging won't delay it
+        #[cfg(feature = "defmt")]
+        defmt::trace!("RX (CRC ok: {=bool}) -> {}", crc_ok, cmd);
+
         Some(cmd)
     }
 
@@ -346,66 +2097,321 @@ impl BleRadio {
     /// `packetptr` must be pointed to the RX buffer.
     ///
     /// Of course, other tasks may also be performed.
-    fn prepare_txrx_advertising(&mut self, channel: AdvertisingChannel) {
+    ///
+    /// Returns `Error::Timeout` without configuring the radio if the radio can't be disabled (see
+    /// [`wait_for_disable`]).
+    fn prepare_txrx_advertising(&mut self, channel: AdvertisingChannel) -> Result<(), Error> {
         self.advertising = true;
+        self.adv_rx_channel = None;
+        self.adv_tx_left = 0;
+        self.adv_rx_timeout = None;
 
-        unsafe {
-            // Acknowledge left-over disable event
-            self.radio.events_disabled.reset();
+        // Acknowledge left-over disable event
+        self.radio.events_disabled.reset();
 
-            if !self.state().is_disabled() {
-                // In case we're currently receiving, stop that
-                self.radio.tasks_disable.write(|w| w.bits(1));
+        if !self.state().is_disabled() {
+            // In case we're currently receiving, stop that
+            self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
 
-                // Then wait until disable event is triggered
-                while self.radio.events_disabled.read().bits() == 0 {}
-            }
+            // Then wait until the radio is disabled
+            wait_for_disable(&self.radio, &mut self.stats, self.event_handler)?;
         }
 
-        assert!(self.state().is_disabled());
-
         // Now we can freely configure all registers we need
-        unsafe {
-            self.radio
-                .pcnf0
-                .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
+        // Advertising always uses the LE 1M PHY
+        configure_phy(&self.radio, Phy::Le1M, self.adv_layout);
+        set_max_payload(&self.radio, self.max_rx_payload);
+        // CTE sampling is only armed by `listen_periodic_sync`
+        #[cfg(feature = "52833")]
+        {
+            self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+            self.sync_rx = false;
+        }
 
+        unsafe {
             self.radio
                 .datawhiteiv
                 .write(|w| w.datawhiteiv().bits(channel.whitening_iv()));
-            self.radio
-                .crcinit
-                .write(|w| w.crcinit().bits(advertising::CRC_PRESET));
-            self.radio
-                .frequency
-                .write(|w| w.frequency().bits((channel.freq() - 2400) as u8));
+            set_crc_init(&self.radio, self.adv_crc_init);
         }
+        configure_frequency(&self.radio, channel.freq(), self.frequency_offset);
+
+        self.apply_whitening();
+        Ok(())
     }
 
-    fn prepare_txrx_data(&mut self, channel: DataChannel, access_address: u32, crc_init: u32) {
+    fn prepare_txrx_data(
+        &mut self,
+        channel: DataChannel,
+        access_address: u32,
+        crc_init: u32,
+        phy: Phy,
+    ) {
         self.advertising = false;
+        self.adv_rx_channel = None;
+        self.adv_tx_left = 0;
+        self.adv_rx_timeout = None;
+
+        configure_phy(&self.radio, phy, self.data_layout);
+        set_max_payload(&self.radio, self.max_rx_payload);
+        // CTE sampling is only armed by `listen_periodic_sync`
+        #[cfg(feature = "52833")]
+        {
+            self.radio.dfemode.write(|w| unsafe { w.bits(0) });
+            self.sync_rx = false;
+        }
+        configure_frequency(&self.radio, channel.freq(), self.frequency_offset);
 
         unsafe {
-            self.radio
-                .pcnf0
-                .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
-
             self.radio
                 .datawhiteiv
                 .write(|w| w.datawhiteiv().bits(channel.whitening_iv()));
-            self.radio
-                .crcinit
-                .write(|w| w.crcinit().bits(crc_init & 0x00FFFFFF));
-            self.radio
-                .frequency
-                .write(|w| w.frequency().bits((channel.freq() - 2400) as u8));
+            set_crc_init(&self.radio, crc_init);
 
             // Address #1 is our data channel access address
-            self.radio.base1.write(|w| w.bits(access_address << 8));
+            let (base, prefix) = base_and_prefix(access_address, self.base_address_len);
+            self.radio.base1.write(|w| w.bits(base));
+            self.radio.prefix0.modify(|_, w| w.ap1().bits(prefix));
+        }
+
+        self.apply_whitening();
+    }
+
+    /// Transmit a PDU from the internal buffer in response to a received advertising channel PDU.
+    ///
+    /// This will block until the transmission has completed.
+    ///
+    /// The transmitter must already be ramping up (or be ramped up) due to the DISABLED_TXEN
+    /// shortcut enabled while listening on an advertising channel.
+    fn respond_advertising(&mut self) {
+        unsafe {
+            self.radio.txaddress.write(|w| w.txaddress().bits(0));
             self.radio
-                .prefix0
-                .write(|w| w.ap1().bits((access_address >> 24) as u8));
+                .packetptr
+                .write(|w| w.bits(self.tx_buf.as_ptr() as u32));
+        }
+
+        // Acknowledge the disable event of the received packet
+        self.radio.events_disabled.reset();
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+
+        // Start the transmission once ramp-up is done, and don't ramp up again afterwards
+        self.radio
+            .shorts
+            .write(|w| w.ready_start().enabled().end_disable().enabled());
+        if self.state().is_tx_idle() {
+            // Ramp-up has already finished before we enabled the shortcut
+            self.radio.tasks_start.write(|w| unsafe { w.bits(1) });
+        }
+        self.emit(RadioEvent::TxStarted);
+
+        // Then wait until disable event is triggered
+        if wait_for_tx_end(&self.radio, &mut self.stats, self.event_handler).is_err() {
+            return;
+        }
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        compiler_fence(Ordering::Acquire);
+        self.emit(RadioEvent::TxComplete);
+    }
+
+    /// Sends an already encoded advertising channel PDU (header and payload) on `channel`.
+    ///
+    /// Unlike [`Transmitter::transmit_advertising`], which sends the payload previously written
+    /// to [`Transmitter::tx_payload_buf`], this copies all of `pdu` into the TX buffer at once.
+    /// This suits beacons that precompute their PDUs (eg. `PduBuf::beacon`, with the header
+    /// encoded by `Header::to_u16` in little-endian byte order). Like `transmit_advertising`, this
+    /// blocks until the PDU was sent, and then listens for a response on `channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidLength` if `pdu` is shorter than a header, if the header's length
+    /// field doesn't match the length of the payload, or if the PDU doesn't fit in the TX buffer or
+    /// exceeds the size of the RX buffer. Nothing is sent in that case.
+    pub fn transmit_raw_advertising(
+        &mut self,
+        pdu: &[u8],
+        channel: AdvertisingChannel,
+    ) -> Result<(), Error> {
+        self.wait_for_tx_buf();
+        load_raw_pdu(self.tx_buf, self.max_rx_payload, pdu)?;
+        self.send_advertising(channel, 0);
+        Ok(())
+    }
+
+    /// Sends the advertising PDU in `tx_buf` on `channel` (`repeat` additional times), then listens
+    /// for a response.
+    fn send_advertising(&mut self, channel: AdvertisingChannel, repeat: u8) {
+        if self.advertising && (self.state().is_tx_ru() || self.state().is_tx_idle()) {
+            // We're responding to a packet received on `channel`, and the DISABLED_TXEN shortcut
+            // has already started ramping up the transmitter. T_IFS is enforced by the hardware.
+            self.respond_advertising();
+            return;
+        }
+
+        if self.prepare_txrx_advertising(channel).is_err() {
+            // The PDU is lost, like on a noisy channel
+            return;
+        }
+
+        // Set transmission address:
+        // Logical addr. 0 uses BASE0 + PREFIX0, which is the canonical adv. Access Address
+        self.radio
+            .txaddress
+            .write(|w| unsafe { w.txaddress().bits(0) });
+
+        // Send the repetitions without listening in between. `tx_buf` is left untouched, so the
+        // hardware can start each one after the previous transmission disabled the radio, and
+        // `recv_interrupt` only has to count them. This doesn't wait for them to end.
+        if repeat > 0 {
+            self.adv_tx_left = u16::from(repeat) + 1;
+            self.adv_rx_channel = self.rx_buf.is_some().then(|| channel.channel());
+            self.radio
+                .tifs
+                .write(|w| unsafe { w.bits(T_IFS.to_micros()) });
+            set_adv_repeat_shorts(&self.radio, false, self.adv_rx_channel.is_some());
+            self.tx_armed = true;
+            if self.start_transmission() {
+                // Raised when each repetition ends
+                self.radio.intenset.write(|w| w.disabled().set());
+            } else {
+                self.adv_tx_left = 0;
+                self.adv_rx_channel = None;
+                self.tx_armed = false;
+            }
+            return;
+        }
+
+        // The RX buffer is unavailable while `recv_interrupt` is processing a received packet.
+        let rx_buf = match self.rx_buf.as_mut() {
+            Some(rx_buf) => rx_buf.as_mut_ptr() as u32,
+            None => {
+                self.radio
+                    .shorts
+                    .write(|w| w.ready_start().enabled().end_disable().enabled());
+                self.transmit();
+                return;
+            }
+        };
+
+        // Start the receiver T_IFS after the transmission, so that we can receive scan and
+        // connect requests sent in response.
+        self.radio
+            .tifs
+            .write(|w| unsafe { w.bits(T_IFS.to_micros()) });
+        self.radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .disabled_rxen()
+                .enabled()
+        });
+
+        self.transmit();
+        self.listen_after_adv(rx_buf, channel.channel());
+    }
+
+    /// Called from `recv_interrupt` when a transmission of a repeated advertising PDU has ended.
+    fn continue_adv_repeats(&mut self) {
+        self.radio.events_end.reset();
+        self.emit(RadioEvent::TxComplete);
+
+        let listen = self.adv_rx_channel;
+        self.adv_tx_left = adv_repeat_ended(&self.radio, self.adv_tx_left, listen.is_some());
+        if self.adv_tx_left > 0 {
+            self.emit(RadioEvent::TxStarted);
+            return;
+        }
+
+        self.tx_armed = false;
+        if let (Some(channel), Some(rx_buf)) = (listen, self.rx_buf.as_mut()) {
+            let rx_buf = rx_buf.as_mut_ptr() as u32;
+            self.listen_after_adv(rx_buf, channel);
+            if let Some(timeout) = self.adv_rx_timeout.take() {
+                self.arm_rx_timeout(timeout);
+            }
+        }
+    }
+
+    /// Stops sending repetitions of the advertising PDU and waits for the current one to end.
+    ///
+    /// The `DISABLED` event of that transmission is acknowledged, so that `recv_interrupt` doesn't
+    /// mistake it for a received packet. A repetition that doesn't end within [`TX_POLLS`] polls is
+    /// cut off, and a radio that can't be disabled is counted in `stats` (see
+    /// [`wait_for_disable`]).
+    fn stop_adv_repeats(&mut self) {
+        self.radio
+            .shorts
+            .write(|w| w.ready_start().enabled().end_disable().enabled());
+        let _ = (0..TX_POLLS).any(|_| !tx_in_flight(&self.radio, true));
+        if !self.state().is_disabled() {
+            // The last repetition has already ended and started the receiver
+            self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
         }
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        compiler_fence(Ordering::Acquire);
+
+        let _ = wait_for_disable(&self.radio, &mut self.stats, self.event_handler);
+        self.radio.events_end.reset();
+        self.adv_tx_left = 0;
+        self.adv_rx_channel = None;
+        self.adv_rx_timeout = None;
+        self.tx_armed = false;
+    }
+
+    /// Sets up reception on the advertising `channel` while the receiver is ramping up after a
+    /// transmission (started by the DISABLED_RXEN shortcut).
+    fn listen_after_adv(&mut self, rx_buf: u32, channel: u8) {
+        // The receiver reads PACKETPTR when it starts receiving, so we have ~T_IFS to set up
+        // reception like `configure_receiver` does.
+        self.radio.packetptr.write(|w| unsafe { w.bits(rx_buf) });
+        self.radio.rxaddresses.write(|w| w.addr0().enabled());
+        self.radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .disabled_txen()
+                .enabled()
+                .address_rssistart()
+                .enabled()
+                .disabled_rssistop()
+                .enabled()
+        });
+        self.radio.events_disabled.reset();
+        self.radio.events_address.reset();
+        self.radio.events_end.reset();
+        self.adv_rx_channel = Some(channel);
+        self.emit(RadioEvent::RxStarted);
+    }
+
+    /// Waits until the radio no longer reads from `tx_buf`.
+    ///
+    /// If a spare TX buffer is available, it is swapped in instead of waiting. A transmission that
+    /// doesn't end within [`TX_POLLS`] polls is cut off by disabling the radio (see
+    /// [`recover_if`]).
+    fn wait_for_tx_buf(&mut self) {
+        if self.adv_tx_left > 0 {
+            // `recv_interrupt` may not get to end the repetitions before `tx_buf` is needed
+            self.stop_adv_repeats();
+        }
+
+        if tx_in_flight_from(&self.radio, self.tx_armed, self.tx_buf) {
+            swap_tx_bufs(&mut self.tx_buf, &mut self.spare_tx_buf);
+        }
+
+        // Wait for any ongoing transmissions, including one that is still ramping up and will
+        // start on its own
+        if !(0..TX_POLLS).any(|_| !tx_in_flight_from(&self.radio, self.tx_armed, self.tx_buf)) {
+            let _ = self.recover_if(|_| true);
+        }
+        self.tx_armed = false;
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        compiler_fence(Ordering::Acquire);
     }
 
     /// Transmit a PDU from the internal buffer.
@@ -414,62 +2420,90 @@ impl BleRadio {
     ///
     /// Assumes that all registers are correct for this type of transmission.
     fn transmit(&mut self) {
-        assert!(self.state().is_disabled());
+        if !self.start_transmission() {
+            return;
+        }
+
+        // Then wait until disable event is triggered
+        if wait_for_tx_end(&self.radio, &mut self.stats, self.event_handler).is_err() {
+            return;
+        }
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        compiler_fence(Ordering::Acquire);
+
+        // Now our `tx_buf` can be used again.
+        self.emit(RadioEvent::TxComplete);
+    }
+
+    /// Starts transmitting the PDU in `tx_buf`, without waiting for the transmission to end.
+    ///
+    /// Returns `false` if the radio couldn't be disabled first. The PDU is lost then (like on a
+    /// noisy channel).
+    fn start_transmission(&mut self) -> bool {
+        if self.recover_if(|state| !state.is_disabled()).is_err() {
+            return false;
+        }
 
         unsafe {
             // "The CPU should reconfigure this pointer every time before the RADIO is started via
             // the START task."
             self.radio
                 .packetptr
-                .write(|w| w.bits(self.tx_buf as *const _ as u32));
+                .write(|w| w.bits(self.tx_buf.as_ptr() as u32));
 
             // Acknowledge left-over disable event
             self.radio.events_disabled.reset(); // FIXME unnecessary, right?
 
+            self.radio.events_ready.reset();
+            self.prepare_ramp_up();
+
             // "Preceding reads and writes cannot be moved past subsequent writes."
             compiler_fence(Ordering::Release);
 
             // ...and kick off the transmission
             self.radio.tasks_txen.write(|w| w.bits(1));
-
-            // Then wait until disable event is triggered
-            while self.radio.events_disabled.read().bits() == 0 {}
-
-            // "Subsequent reads and writes cannot be moved ahead of preceding reads."
-            compiler_fence(Ordering::Acquire);
-
-            // Now our `tx_buf` can be used again.
         }
+        self.emit(RadioEvent::TxStarted);
+        self.finish_ramp_up();
+        true
     }
 }
 
 impl Transmitter for BleRadio {
     fn tx_payload_buf(&mut self) -> &mut [u8] {
-        // Wait for any ongoing transmissions
-        while self.state().is_tx() {}
-        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
-        compiler_fence(Ordering::Acquire);
+        self.wait_for_tx_buf();
 
-        // Leave 2 Bytes for the data/advertising PDU header.
+        // Leave 2 Bytes for the data/advertising PDU header. Only `transmit_secondary` raises
+        // `MAXLEN` beyond `rx_buf`, the other PDUs are small enough or limited by the Link-Layer.
         &mut self.tx_buf[2..]
     }
 
+    fn rx_payload_capacity(&self) -> usize {
+        usize::from(self.max_rx_payload)
+    }
+
+    fn supported_features(&self) -> FeatureSet {
+        supported_features()
+    }
+
     fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel) {
+        self.transmit_advertising_repeated(header, channel, 0);
+    }
+
+    fn transmit_advertising_repeated(
+        &mut self,
+        header: advertising::Header,
+        channel: AdvertisingChannel,
+        repeat: u8,
+    ) {
         let raw_header = header.to_u16();
         // S0 = 8 bits (LSB)
         self.tx_buf[0] = raw_header as u8;
-        // Length = 6 bits, followed by 2 RFU bits (0)
+        // Length = 8 bits (legacy PDUs never use more than 6)
         self.tx_buf[1] = header.payload_length();
 
-        self.prepare_txrx_advertising(channel);
-
-        // Set transmission address:
-        // Logical addr. 0 uses BASE0 + PREFIX0, which is the canonical adv. Access Address
-        self.radio
-            .txaddress
-            .write(|w| unsafe { w.txaddress().bits(0) });
-
-        self.transmit();
+        self.send_advertising(channel, repeat);
     }
 
     fn transmit_data(
@@ -495,14 +2529,747 @@ impl Transmitter for BleRadio {
         // the START task."
         self.radio
             .packetptr
-            .write(|w| unsafe { w.bits(self.tx_buf as *const _ as u32) });
+            .write(|w| unsafe { w.bits(self.tx_buf.as_ptr() as u32) });
 
         // "Preceding reads and writes cannot be moved past subsequent writes."
         compiler_fence(Ordering::Release);
 
         // ...and kick off the transmission
+        if start_response(&self.radio, &mut self.stats) {
+            // The radio reads `tx_buf` once ramp-up completes, after we've returned
+            self.tx_armed = true;
+            self.emit(RadioEvent::TxStarted);
+        } else {
+            #[cfg(feature = "defmt")]
+            defmt::warn!("missed T_IFS, response not sent");
+            self.emit(RadioEvent::Disabled);
+        }
+    }
+
+    fn transmit_secondary(
+        &mut self,
+        access_address: u32,
+        crc_iv: u32,
+        header: advertising::Header,
+        channel: DataChannel,
+    ) {
+        let raw_header = header.to_u16();
+        // S0 = 8 bits (LSB)
+        self.tx_buf[0] = raw_header as u8;
+        // Length = 8 bits
+        self.tx_buf[1] = header.payload_length();
+
+        // Secondary advertising channels are configured like data channels, using logical
+        // address 1 for the Access Address
+        if self.abort().is_err() {
+            return;
+        }
+        self.prepare_txrx_data(channel, access_address, crc_iv, Phy::Le1M);
+        self.radio
+            .txaddress
+            .write(|w| unsafe { w.txaddress().bits(1) });
+
+        // Nothing is received afterwards, so the whole `tx_buf` can be sent (`new` checks that its
+        // payload fits in `MAXLEN`)
+        set_max_payload(&self.radio, (self.tx_buf.len() - 2) as u8);
         self.radio
             .shorts
-            .write(|w| w.ready_start().enabled().end_disable().disabled());
+            .write(|w| w.ready_start().enabled().end_disable().enabled());
+        self.transmit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::MaybeUninit;
+
+    /// All-zero memory standing in for the `RADIO` register block.
+    fn zeroed_radio() -> pac::radio::RegisterBlock {
+        unsafe { MaybeUninit::zeroed().assume_init() }
+    }
+
+    #[test]
+    fn base_address_shift() {
+        let addr = 0x8E89BED6;
+        assert_eq!(
+            base_and_prefix(addr, BLE_BASE_ADDRESS_LEN),
+            (0x89BED600, 0x8E)
+        );
+        assert_eq!(base_and_prefix(addr, 1), (0xD6000000, 0xBE));
+        assert_eq!(base_and_prefix(addr, 2), (0xBED60000, 0x89));
+        assert_eq!(base_and_prefix(addr, 4), (0x8E89BED6, 0x00));
+    }
+
+    #[test]
+    fn crc_masking() {
+        assert_eq!(crc24(CRC_POLY), 0x00065B);
+        assert_eq!(crc24(advertising::CRC_PRESET), 0x555555);
+
+        // x^24 + x^16 + x^15 + x^2 + 1, with the implicit x^24 term set
+        let poly = (1 << 24) | (1 << 16) | (1 << 15) | (1 << 2) | 1;
+        assert_eq!(crc24(poly), 0x018005);
+        assert_eq!(crc24(0xFFAB_CDEF), 0xABCDEF);
+    }
+
+    #[test]
+    fn crc_registers() {
+        let radio = zeroed_radio();
+
+        // x^24 + x^16 + x^15 + x^2 + 1 reads back without the implicit x^24 term
+        set_crc_poly(&radio, (1 << 24) | (1 << 16) | (1 << 15) | (1 << 2) | 1);
+        assert_eq!(radio.crcpoly.read().crcpoly().bits(), 0x018005);
+        set_crc_init(&radio, 0xFFAB_CDEF);
+        assert_eq!(radio.crcinit.read().crcinit().bits(), 0xABCDEF);
+
+        // The BLE defaults
+        set_crc_poly(&radio, CRC_POLY);
+        assert_eq!(radio.crcpoly.read().bits(), 0x00065B);
+        set_crc_init(&radio, advertising::CRC_PRESET);
+        assert_eq!(radio.crcinit.read().bits(), 0x555555);
+    }
+
+    #[test]
+    fn oversized_payload() {
+        let mut stats = RadioStats::new();
+        let buf = [0xAA; MIN_PDU_BUF];
+        let max = (MIN_PDU_BUF - 2) as u8;
+
+        let (payload, crc_ok) = checked_payload(&buf, max, true, &mut stats);
+        assert_eq!(payload.len(), usize::from(max));
+        assert!(crc_ok);
+
+        let (payload, crc_ok) = checked_payload(&buf, max + 1, true, &mut stats);
+        assert!(payload.is_empty());
+        assert!(!crc_ok);
+        assert_eq!(stats.oversized_packets, 1);
+    }
+
+    #[test]
+    fn raw_advertising_pdu() {
+        use rubble::link::{ad_structure::AdStructure, AddressKind, DeviceAddress};
+
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let beacon =
+            advertising::PduBuf::beacon(addr, &[AdStructure::CompleteLocalName("rubble")]).unwrap();
+        let mut pdu = beacon.header().to_u16().to_le_bytes().to_vec();
+        pdu.extend_from_slice(beacon.payload());
+
+        let mut tx_buf = [0; MIN_PDU_BUF];
+        load_raw_pdu(&mut tx_buf, 37, &pdu).unwrap();
+        assert_eq!(tx_buf[..pdu.len()], pdu[..]);
+
+        // The length field has to match, and the PDU must fit in the buffers
+        let mut other = [0xAA; MIN_PDU_BUF];
+        assert_eq!(
+            load_raw_pdu(&mut other, 37, &pdu[..pdu.len() - 1]),
+            Err(Error::InvalidLength)
+        );
+        assert_eq!(
+            load_raw_pdu(&mut other, 37, &[0x02]),
+            Err(Error::InvalidLength)
+        );
+        assert_eq!(
+            load_raw_pdu(&mut other, pdu[1] - 1, &pdu),
+            Err(Error::InvalidLength)
+        );
+        assert_eq!(
+            load_raw_pdu(&mut other[..pdu.len() - 1], 37, &pdu),
+            Err(Error::InvalidLength)
+        );
+        assert!(other.iter().all(|b| *b == 0xAA));
+    }
+
+    #[test]
+    fn crc_error_handler() {
+        use core::sync::atomic::AtomicUsize;
+
+        static REPORTED: AtomicUsize = AtomicUsize::new(0);
+        fn handler(packet: &CrcErrorPacket<'_>) {
+            assert!(!packet.advertising);
+            assert_eq!(packet.rssi, -70);
+            // The corrupted length field exceeds the buffer, so the PDU is truncated
+            assert_eq!(packet.pdu.len(), MIN_PDU_BUF);
+            REPORTED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut stats = RadioStats::new();
+        let buf = [0xAA; MIN_PDU_BUF];
+        let now = Instant::from_ticks(0);
+
+        // The stack is told to discard the packet, while the handler gets to see it
+        let (_, crc_ok) = checked_payload(&buf, 4, false, &mut stats);
+        assert!(!crc_ok);
+        report_crc_error(Some(handler), crc_ok, false, &buf, 0xAA, -70, now);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+
+        // Valid packets and disabled handlers are not reported
+        report_crc_error(Some(handler), true, false, &buf, 4, -70, now);
+        report_crc_error(None, false, false, &buf, 4, -70, now);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "52840")]
+    fn coded_phy_registers() {
+        use rubble::phy::CodingIndicator;
+
+        let radio = zeroed_radio();
+
+        configure_phy(
+            &radio,
+            Phy::LeCoded {
+                ci: CodingIndicator::S8,
+            },
+            HeaderLayout::BLE,
+        );
+        assert!(radio.mode.read().mode().is_ble_lr125kbit());
+        let pcnf0 = radio.pcnf0.read();
+        assert_eq!(pcnf0.lflen().bits(), 8);
+        assert!(pcnf0.s0len().bit());
+        assert_eq!(pcnf0.s1len().bits(), 0);
+        assert!(pcnf0.plen().is_long_range());
+        assert_eq!(pcnf0.cilen().bits(), 2);
+        assert_eq!(pcnf0.termlen().bits(), 3);
+        assert!(pcnf0.crcinc().is_exclude());
+
+        configure_phy(
+            &radio,
+            Phy::LeCoded {
+                ci: CodingIndicator::S2,
+            },
+            HeaderLayout::BLE,
+        );
+        assert!(radio.mode.read().mode().is_ble_lr500kbit());
+        assert!(radio.pcnf0.read().plen().is_long_range());
+
+        // Switching back to an uncoded PHY removes the CI and TERM fields
+        configure_phy(&radio, Phy::Le2M, HeaderLayout::BLE);
+        assert!(radio.mode.read().mode().is_ble_2mbit());
+        let pcnf0 = radio.pcnf0.read();
+        assert!(pcnf0.plen().is_16bit());
+        assert_eq!(pcnf0.cilen().bits(), 0);
+        assert_eq!(pcnf0.termlen().bits(), 0);
+
+        configure_phy(&radio, Phy::Le1M, HeaderLayout::BLE);
+        assert!(radio.mode.read().mode().is_ble_1mbit());
+        assert!(radio.pcnf0.read().plen().is_8bit());
+        assert_eq!(radio.pcnf0.read().lflen().bits(), 8);
+    }
+
+    #[test]
+    fn header_layout_registers() {
+        let radio = zeroed_radio();
+
+        // The BLE default matches the layout described in the module documentation
+        HeaderLayout::BLE.write(&radio);
+        let pcnf0 = radio.pcnf0.read();
+        assert!(pcnf0.s0len().bit());
+        assert_eq!(pcnf0.lflen().bits(), 8);
+        assert_eq!(pcnf0.s1len().bits(), 0);
+
+        let custom = HeaderLayout {
+            s0_byte: false,
+            length_bits: 6,
+            s1_bits: 3,
+        };
+        assert!(custom.is_valid());
+        configure_phy(&radio, Phy::Le1M, custom);
+        let pcnf0 = radio.pcnf0.read();
+        assert!(!pcnf0.s0len().bit());
+        assert_eq!(pcnf0.lflen().bits(), 6);
+        assert_eq!(pcnf0.s1len().bits(), 3);
+
+        assert!(!HeaderLayout {
+            s1_bits: 16,
+            ..custom
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn max_payload_register() {
+        let radio = zeroed_radio();
+        radio
+            .pcnf1
+            .write(|w| unsafe { w.balen().bits(3).whiteen().set_bit() });
+
+        // Switching between the TX and RX buffer size keeps the rest of `PCNF1`
+        set_max_payload(&radio, 255);
+        assert_eq!(radio.pcnf1.read().maxlen().bits(), 255);
+        set_max_payload(&radio, 37);
+        let pcnf1 = radio.pcnf1.read();
+        assert_eq!(pcnf1.maxlen().bits(), 37);
+        assert_eq!(pcnf1.balen().bits(), 3);
+        assert!(pcnf1.whiteen().bit());
+    }
+
+    #[test]
+    fn whitening_register() {
+        let radio = zeroed_radio();
+        radio
+            .pcnf1
+            .write(|w| unsafe { w.balen().bits(3).maxlen().bits(37).whiteen().set_bit() });
+
+        // Only `WHITEEN` is changed
+        configure_whitening(&radio, false);
+        let pcnf1 = radio.pcnf1.read();
+        assert!(!pcnf1.whiteen().bit());
+        assert_eq!(pcnf1.balen().bits(), 3);
+        assert_eq!(pcnf1.maxlen().bits(), 37);
+
+        // Preparing the next transmission or reception keeps the setting
+        configure_phy(&radio, Phy::Le1M, HeaderLayout::BLE);
+        set_max_payload(&radio, 27);
+        assert!(!radio.pcnf1.read().whiteen().bit());
+
+        configure_whitening(&radio, true);
+        assert!(radio.pcnf1.read().whiteen().bit());
+        assert_eq!(radio.pcnf1.read().maxlen().bits(), 27);
+    }
+
+    // The DFE registers only exist on the nRF52833 (the nRF52840 has no DFE), so this can't run
+    // with the default `52840` feature. See `CteRxConfig` for the nRF52811.
+    #[test]
+    #[cfg(feature = "52833")]
+    fn dfe_registers() {
+        let radio = zeroed_radio();
+        unsafe { radio.dfemode.write(|w| w.bits(3)) };
+
+        let config = CteRxConfig {
+            length_8us: 20,
+            switch_spacing: CteSpacing::Us4,
+            ref_sample_spacing: CteSpacing::Ns125,
+            sample_spacing: CteSpacing::Us1,
+            antenna_pattern: &[0b00, 0b00, 0b01, 0b10],
+            antenna_pins: [Some(2), Some(35), None, None, None, None, None, None],
+        };
+        configure_dfe(&radio, &config);
+
+        // Sampling stays disabled until a periodic advertising packet is expected
+        assert_eq!(radio.dfemode.read().bits(), 0);
+        assert_eq!(radio.cteinlineconf.read().bits(), 0);
+        assert_eq!(radio.dfectrl1.read().bits(), 0x0003_6194);
+        assert_eq!(radio.dfectrl2.read().bits(), 0);
+        assert_eq!(radio.clearpattern.read().bits(), 1);
+        // The last pattern entry written to the `SWITCHPATTERN` FIFO
+        assert_eq!(radio.switchpattern.read().bits(), 0b10);
+        let pins = &radio.psel.dfegpio;
+        assert_eq!(pins[0].read().bits(), 2);
+        assert_eq!(pins[1].read().bits(), 35);
+        assert!(pins[2..].iter().all(|pin| pin.read().bits() == 1 << 31));
+    }
+
+    #[test]
+    #[cfg(feature = "52833")]
+    fn cte_rx_config() {
+        // The defaults match the reset value of `DFECTRL1`
+        let config = CteRxConfig::default();
+        assert!(config.is_valid());
+        assert_eq!(config.dfectrl1(), 0x0002_3282);
+
+        // 160 µs CTE with 2 µs slots, sampled every 1 µs and every 125 ns in the reference period
+        let config = CteRxConfig {
+            length_8us: 20,
+            switch_spacing: CteSpacing::Us4,
+            ref_sample_spacing: CteSpacing::Ns125,
+            sample_spacing: CteSpacing::Us1,
+            antenna_pattern: &[0b00, 0b00, 0b01, 0b10, 0b11],
+            antenna_pins: [Some(2), Some(35), None, None, None, None, None, None],
+        };
+        assert!(config.is_valid());
+        assert_eq!(config.dfectrl1(), 0x0003_6194);
+
+        for invalid in [
+            CteRxConfig {
+                length_8us: 1,
+                ..config
+            },
+            CteRxConfig {
+                length_8us: 21,
+                ..config
+            },
+            CteRxConfig {
+                switch_spacing: CteSpacing::Ns500,
+                ..config
+            },
+            CteRxConfig {
+                antenna_pattern: &[0; CteRxConfig::MAX_PATTERN_LEN + 1],
+                ..config
+            },
+            CteRxConfig {
+                antenna_pins: [Some(64); 8],
+                ..config
+            },
+        ] {
+            assert!(!invalid.is_valid());
+        }
+
+        // The radio writes I to the lower and Q to the upper half of each word
+        let word: u32 = 0xFFFE_0003;
+        let sample: IqSample = unsafe { core::mem::transmute(word.to_le_bytes()) };
+        assert_eq!(sample, IqSample { i: 3, q: -2 });
+    }
+
+    #[test]
+    #[cfg(not(feature = "51"))]
+    fn fast_ramp_up_register() {
+        let radio = zeroed_radio();
+
+        configure_ramp_up(&radio, true);
+        assert!(radio.modecnf0.read().ru().is_fast());
+
+        configure_ramp_up(&radio, false);
+        assert!(radio.modecnf0.read().ru().is_default());
+    }
+
+    #[test]
+    fn late_turnaround() {
+        let radio = zeroed_radio();
+        let mut stats = RadioStats::new();
+
+        // The transmitter is still ramping up (`STATE` is read-only, so set it in memory)
+        unsafe { radio.state.as_ptr().write(9) };
+        assert!(start_response(&radio, &mut stats));
+        assert!(radio.shorts.read().ready_start().is_enabled());
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 0);
+        assert_eq!(stats.missed_tx, 0);
+
+        // `TXIDLE` was already reached, so `T_IFS` has passed and nothing may be sent
+        unsafe { radio.state.as_ptr().write(10) };
+        assert!(!start_response(&radio, &mut stats));
+        assert!(radio.shorts.read().ready_start().is_disabled());
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 1);
+        assert_eq!(stats.missed_tx, 1);
+    }
+
+    #[test]
+    fn unexpected_state_recovery() {
+        use core::sync::atomic::AtomicUsize;
+
+        let radio = zeroed_radio();
+        let mut stats = RadioStats::new();
+
+        static DISABLED_EVENTS: AtomicUsize = AtomicUsize::new(0);
+        fn handler(event: RadioEvent) {
+            assert_eq!(event, RadioEvent::Disabled);
+            DISABLED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Disabled as expected: nothing happens
+        assert_eq!(
+            recover_if(&radio, &mut stats, Some(handler), |s| !s.is_disabled()),
+            Ok(false)
+        );
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 0);
+        assert_eq!(DISABLED_EVENTS.load(Ordering::Relaxed), 0);
+
+        // Still transmitting (`TX` state) with the DISABLED_TXEN shortcut enabled. The radio never
+        // reaches `DISABLED` here, so this gives up.
+        unsafe { radio.state.as_ptr().write(11) };
+        radio.shorts.write(|w| w.disabled_txen().enabled());
+        assert_eq!(
+            recover_if(&radio, &mut stats, Some(handler), STATE_R::is_tx),
+            Err(Error::Timeout)
+        );
+        assert_eq!(radio.shorts.read().bits(), 0);
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 1);
+        assert_eq!(stats.recoveries, 1);
+        assert_eq!(DISABLED_EVENTS.load(Ordering::Relaxed), 0);
+
+        // Once the radio is disabled, the `DISABLED` event is acknowledged and emitted
+        unsafe { radio.state.as_ptr().write(0) };
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+        assert_eq!(
+            recover_if(&radio, &mut stats, Some(handler), |_| true),
+            Ok(true)
+        );
+        assert_eq!(radio.events_disabled.read().bits(), 0);
+        assert_eq!(stats.recoveries, 2);
+        assert_eq!(DISABLED_EVENTS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn bounded_disable_waits() {
+        let radio = zeroed_radio();
+        let mut stats = RadioStats::new();
+
+        // Already disabled: the `DISABLED` event is acknowledged without a recovery
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+        assert_eq!(wait_for_disable(&radio, &mut stats, None), Ok(()));
+        assert_eq!(radio.events_disabled.read().bits(), 0);
+        assert_eq!(stats.recoveries, 0);
+
+        // Stuck in `RX`: the radio is recovered, which gives up as well
+        unsafe { radio.state.as_ptr().write(3) };
+        assert_eq!(
+            wait_for_disable(&radio, &mut stats, None),
+            Err(Error::Timeout)
+        );
+        assert_eq!(stats.recoveries, 1);
+
+        // A transmission that has ended
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+        assert_eq!(wait_for_tx_end(&radio, &mut stats, None), Ok(()));
+        assert_eq!(stats.recoveries, 1);
+
+        // One that never ends is cut off, and its PDU is lost even though the radio is disabled
+        radio.events_disabled.reset();
+        unsafe { radio.state.as_ptr().write(0) };
+        assert_eq!(
+            wait_for_tx_end(&radio, &mut stats, None),
+            Err(Error::Timeout)
+        );
+        assert_eq!(stats.recoveries, 2);
+    }
+
+    #[test]
+    fn adv_repeat_txen() {
+        let radio = zeroed_radio();
+
+        for repeat in 1..=4u16 {
+            set_adv_repeat_shorts(&radio, false, true);
+            // The first transmission is started by the driver
+            let mut txen = 1;
+            let mut left = repeat + 1;
+            while left > 0 {
+                // The transmission ends, and the shortcuts trigger TXEN right away
+                if radio.shorts.read().disabled_txen().is_enabled() {
+                    txen += 1;
+                }
+                left = adv_repeat_ended(&radio, left, true);
+            }
+            assert_eq!(txen, 1 + repeat);
+            assert!(radio.shorts.read().disabled_rxen().is_enabled());
+        }
+
+        // Without listening, the radio stays disabled after the last repetition
+        set_adv_repeat_shorts(&radio, false, false);
+        assert_eq!(adv_repeat_ended(&radio, 2, false), 1);
+        assert!(radio.shorts.read().disabled_txen().is_disabled());
+        assert!(radio.shorts.read().disabled_rxen().is_disabled());
+        assert!(radio.shorts.read().end_disable().is_enabled());
+    }
+
+    #[test]
+    fn event_sequence() {
+        use core::sync::atomic::AtomicUsize;
+
+        let radio = zeroed_radio();
+        let mut stats = RadioStats::new();
+
+        // Records the events as 1 (CRC ok), 2 (bad CRC) and 3 (disabled)
+        static EVENTS: [AtomicUsize; 4] = [
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        ];
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        fn handler(event: RadioEvent) {
+            let code = match event {
+                RadioEvent::PacketReceived { crc_ok: true } => 1,
+                RadioEvent::PacketReceived { crc_ok: false } => 2,
+                RadioEvent::Disabled => 3,
+                _ => panic!("unexpected event {:?}", event),
+            };
+            EVENTS[NEXT.fetch_add(1, Ordering::Relaxed)].store(code, Ordering::Relaxed);
+        }
+        let mut interrupt = |end: bool, crc_ok: bool| {
+            radio.events_disabled.write(|w| unsafe { w.bits(1) });
+            radio.events_end.write(|w| unsafe { w.bits(end.into()) });
+            unsafe { radio.crcstatus.as_ptr().write(crc_ok.into()) };
+            assert!(take_disabled_event(&radio, &mut stats));
+            take_received_packet(&radio, &mut stats, Some(handler))
+        };
+
+        // A good packet, the receive timeout and a corrupted packet
+        assert_eq!(interrupt(true, true), Some(true));
+        assert_eq!(interrupt(false, true), None);
+        assert_eq!(interrupt(true, false), Some(false));
+        assert_eq!(radio.events_end.read().bits(), 0);
+
+        let events = [0, 1, 2, 3].map(|i| EVENTS[i].load(Ordering::Relaxed));
+        assert_eq!(events, [1, 3, 2, 0]);
+        assert_eq!(stats.packets_received, 2);
+        assert_eq!(stats.crc_errors, 1);
+    }
+
+    #[test]
+    fn spurious_interrupt() {
+        let radio = zeroed_radio();
+        let mut stats = RadioStats::new();
+
+        // Events of a packet being received, but the radio isn't disabled yet
+        radio.events_address.write(|w| unsafe { w.bits(1) });
+        radio.events_end.write(|w| unsafe { w.bits(1) });
+        assert!(!take_disabled_event(&radio, &mut stats));
+        assert_eq!(stats.spurious_interrupts, 1);
+        assert_eq!(radio.events_address.read().bits(), 1);
+        assert_eq!(radio.events_end.read().bits(), 1);
+
+        // Once disabled, only `DISABLED` is acknowledged, `END` is left for `recv_interrupt`
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+        assert!(take_disabled_event(&radio, &mut stats));
+        assert_eq!(radio.events_disabled.read().bits(), 0);
+        assert_eq!(radio.events_end.read().bits(), 1);
+        assert_eq!(stats.spurious_interrupts, 1);
+    }
+
+    #[test]
+    fn release_registers() {
+        let radio = zeroed_radio();
+
+        // Transmitting with the DISABLED interrupt and shortcuts enabled
+        unsafe { radio.state.as_ptr().write(11) };
+        radio.intenset.write(|w| w.disabled().set());
+        radio
+            .shorts
+            .write(|w| w.end_disable().enabled().disabled_txen().enabled());
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+
+        // The radio is disabled, and the stale DISABLED event doesn't end the wait early
+        assert!(stop_for_release(&radio));
+        assert_eq!(radio.intenclr.read().bits(), 0xFFFF_FFFF);
+        assert_eq!(radio.shorts.read().bits(), 0);
+        assert_eq!(radio.events_disabled.read().bits(), 0);
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 1);
+
+        // Already disabled: nothing to wait for
+        unsafe {
+            radio.state.as_ptr().write(0);
+            radio.tasks_disable.as_ptr().write(0);
+        }
+        assert!(!stop_for_release(&radio));
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 0);
+
+        // Swapping the TX buffers keeps both of them for `free` to hand back
+        static mut FIRST: PacketBuffer = [0; MIN_PDU_BUF];
+        static mut SECOND: PacketBuffer = [0; MIN_PDU_BUF];
+        let first: &'static mut [u8] = unsafe { &mut *core::ptr::addr_of_mut!(FIRST) };
+        let second: &'static mut [u8] = unsafe { &mut *core::ptr::addr_of_mut!(SECOND) };
+        let (first_ptr, second_ptr) = (first.as_ptr(), second.as_ptr());
+        first[0] = 0x42;
+
+        let mut tx_buf = first;
+        let mut spare = Some(second);
+        assert!(swap_tx_bufs(&mut tx_buf, &mut spare));
+        assert_eq!(tx_buf.as_ptr(), second_ptr);
+        assert_eq!(tx_buf[0], 0x42, "the PDU is copied to the spare buffer");
+        assert_eq!(spare.as_ref().unwrap().as_ptr(), first_ptr);
+
+        assert!(swap_tx_bufs(&mut tx_buf, &mut spare));
+        assert_eq!(tx_buf.as_ptr(), first_ptr);
+        assert_eq!(spare.unwrap().as_ptr(), second_ptr);
+
+        // Without a spare buffer, nothing is swapped
+        let mut spare = None;
+        assert!(!swap_tx_bufs(&mut tx_buf, &mut spare));
+        assert_eq!(tx_buf.as_ptr(), first_ptr);
+    }
+
+    #[test]
+    fn abort_from_rx() {
+        let radio = zeroed_radio();
+
+        // Receiving a packet (`RX` state, address matched) with the usual data channel shortcuts
+        // and a stale DISABLED event
+        unsafe { radio.state.as_ptr().write(3) };
+        radio.events_address.write(|w| unsafe { w.bits(1) });
+        radio.events_disabled.write(|w| unsafe { w.bits(1) });
+        radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .disabled_txen()
+                .enabled()
+        });
+        assert!(stop_for_abort(&radio));
+        assert!(radio.intenclr.read().disabled().bit());
+        assert_eq!(radio.shorts.read().bits(), 0);
+        assert_eq!(radio.events_disabled.read().bits(), 0);
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 1);
+
+        // Listening without having matched an address yet
+        radio.events_address.reset();
+        assert!(!stop_for_abort(&radio));
+
+        // Already disabled: the `DISABLE` task isn't triggered again
+        unsafe {
+            radio.state.as_ptr().write(0);
+            radio.tasks_disable.as_ptr().write(0);
+        }
+        assert!(!stop_for_abort(&radio));
+        assert_eq!(unsafe { radio.tasks_disable.as_ptr().read() }, 0);
+    }
+
+    #[test]
+    fn tx_buf_in_flight() {
+        let radio = zeroed_radio();
+
+        // Disabled: `tx_buf` is free
+        assert!(!tx_in_flight(&radio, false));
+        assert!(!tx_in_flight(&radio, true));
+
+        // Ramping up after a received packet, but the READY_START shortcut is only enabled once
+        // a response is armed
+        for state in [9, 10] {
+            unsafe { radio.state.as_ptr().write(state) };
+            assert!(!tx_in_flight(&radio, false));
+            assert!(tx_in_flight(&radio, true));
+        }
+
+        // Transmitting
+        unsafe { radio.state.as_ptr().write(11) };
+        assert!(tx_in_flight(&radio, false));
+        assert!(tx_in_flight(&radio, true));
+
+        // Only the buffer the transmission was started from is in use
+        let (sent, spare) = ([0; MIN_PDU_BUF], [0; MIN_PDU_BUF]);
+        unsafe { radio.packetptr.as_ptr().write(sent.as_ptr() as u32) };
+        assert!(tx_in_flight_from(&radio, false, &sent));
+        assert!(!tx_in_flight_from(&radio, false, &spare));
+
+        // The transmission has ended and the radio is disabling itself, or receiving again
+        for state in [12, 3] {
+            unsafe { radio.state.as_ptr().write(state) };
+            assert!(!tx_in_flight(&radio, true));
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "51"))]
+    fn frequency_offset() {
+        let radio = zeroed_radio();
+        let freq = |radio: &pac::radio::RegisterBlock| {
+            let reg = radio.frequency.read();
+            (reg.frequency().bits(), reg.map().is_low())
+        };
+
+        configure_frequency(&radio, 2402, 0);
+        assert_eq!(freq(&radio), (2, false));
+        configure_frequency(&radio, 2480, 3);
+        assert_eq!(freq(&radio), (83, false));
+
+        // Frequencies below 2400 MHz use the `LOW` map
+        configure_frequency(&radio, 2402, -5);
+        assert_eq!(freq(&radio), (37, true));
+
+        // Out-of-range frequencies are clamped
+        configure_frequency(&radio, 2402, -100);
+        assert_eq!(freq(&radio), (0, true));
+        configure_frequency(&radio, 2480, 100);
+        assert_eq!(freq(&radio), (100, false));
+    }
+
+    #[test]
+    fn chip_features() {
+        let features = supported_features();
+        assert!(FeatureSet::supported().contains(features));
+        assert_eq!(
+            features.contains(FeatureSet::LE_CODED_PHY),
+            cfg!(any(feature = "52833", feature = "52840"))
+        );
     }
 }
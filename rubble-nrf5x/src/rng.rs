@@ -0,0 +1,62 @@
+//! Random number generator using the `RNG` peripheral.
+
+use crate::pac::RNG;
+use rand_core::{impls, CryptoRng, Error, RngCore};
+
+/// Implements `RngCore` (and thus Rubble's `Rng` trait) using the random number generator
+/// peripheral.
+///
+/// Bias correction is enabled, which makes the generated values uniformly distributed at the cost
+/// of slower generation.
+pub struct HwRng {
+    rng: RNG,
+}
+
+impl HwRng {
+    /// Takes ownership of the `RNG` peripheral and starts generating random values.
+    pub fn new(rng: RNG) -> Self {
+        rng.intenclr.write(|w| w.valrdy().clear());
+        rng.shorts.reset();
+        rng.config.write(|w| w.dercen().enabled());
+        rng.events_valrdy.reset();
+        rng.tasks_start.write(|w| unsafe { w.bits(1) });
+        Self { rng }
+    }
+
+    /// Stops the peripheral and releases it.
+    pub fn free(self) -> RNG {
+        self.rng.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.rng
+    }
+
+    /// Waits for the next random Byte.
+    fn next_byte(&mut self) -> u8 {
+        while self.rng.events_valrdy.read().bits() == 0 {}
+        let byte = self.rng.value.read().value().bits();
+        self.rng.events_valrdy.reset();
+        byte
+    }
+}
+
+impl RngCore for HwRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            *byte = self.next_byte();
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for HwRng {}
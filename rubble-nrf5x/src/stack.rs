@@ -0,0 +1,320 @@
+//! Builder for setting up the BLE stack.
+//!
+//! [`StackBuilder`] collects the parameters needed to create a [`LinkLayer`] and a [`BleRadio`],
+//! checks them for consistency, and starts advertising.
+//!
+//! # Example
+//!
+//! A minimal advertiser, where `pac` is the peripheral access crate of the chip:
+//!
+//! ```no_run
+//! use core::ptr::addr_of_mut;
+//! use rubble::link::{ad_structure::AdStructure, queue::{PacketQueue, SimpleQueue}, MIN_PDU_BUF};
+//! use rubble::time::Duration;
+//! use rubble_nrf5x::{radio::PacketBuffer, rng::HwRng, stack::StackBuilder, timer::BleTimer};
+//! # #[cfg(feature = "51")] use nrf51_pac as pac;
+//! # #[cfg(feature = "52805")] use nrf52805_pac as pac;
+//! # #[cfg(feature = "52810")] use nrf52810_pac as pac;
+//! # #[cfg(feature = "52811")] use nrf52811_pac as pac;
+//! # #[cfg(feature = "52832")] use nrf52832_pac as pac;
+//! # #[cfg(feature = "52833")] use nrf52833_pac as pac;
+//! # #[cfg(feature = "52840")] use nrf52840_pac as pac;
+//! # use rubble::{aes::SoftAesProvider, att::NoAttributes, config::Config};
+//! # use rubble::{l2cap::BleChannelMap, security::NoSecurity};
+//! # use rubble_nrf5x::radio::BleRadio;
+//! #
+//! # enum AppConfig {}
+//! #
+//! # impl Config for AppConfig {
+//! #     type Timer = BleTimer<pac::TIMER0>;
+//! #     type Transmitter = BleRadio;
+//! #     type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+//! #     type PacketQueue = &'static mut SimpleQueue;
+//! #     type Aes = SoftAesProvider;
+//! #     type AdvReportHandler = ();
+//! #     type LinkEventHandler = ();
+//! #     type Rng = HwRng;
+//! # }
+//!
+//! static mut TX_BUF: PacketBuffer = [0; MIN_PDU_BUF];
+//! static mut RX_BUF: PacketBuffer = [0; MIN_PDU_BUF];
+//! static mut TX_QUEUE: SimpleQueue = SimpleQueue::new();
+//! static mut RX_QUEUE: SimpleQueue = SimpleQueue::new();
+//!
+//! let p = pac::Peripherals::take().unwrap();
+//! let (tx, tx_cons) = unsafe { (*addr_of_mut!(TX_QUEUE)).split() };
+//! let (rx_prod, rx) = unsafe { (*addr_of_mut!(RX_QUEUE)).split() };
+//!
+//! let (ble_ll, radio) = StackBuilder::<AppConfig>::new()
+//!     .advertising_data(&[AdStructure::CompleteLocalName("rubble")])
+//!     .advertising_interval(Duration::millis(200))
+//!     .buffers(unsafe { &mut *addr_of_mut!(TX_BUF) }, unsafe { &mut *addr_of_mut!(RX_BUF) })
+//!     .queues(tx_cons, rx_prod)
+//!     .build(p.RADIO, &p.FICR, BleTimer::init(p.TIMER0), HwRng::new(p.RNG))
+//!     .unwrap();
+//!
+//! // `tx` and `rx` are used to create the `Responder`. The `RADIO` and `TIMER0` interrupt
+//! // handlers then drive `ble_ll` and `radio` as usual.
+//! ```
+
+use crate::pac::{self, RADIO};
+use crate::radio::{supported_features, BleRadio};
+use crate::timer::{BleTimer, NrfTimerExt};
+use crate::utils::device_address;
+use core::fmt;
+use rubble::config::{ConfConsumer, ConfProducer, Config};
+use rubble::link::{
+    ad_structure::AdStructure,
+    advertising::{AdvParams, PduBuf},
+    queue::Producer,
+    CompanyId, DeviceAddress, FeatureSet, LinkLayer, MIN_DATA_PAYLOAD_BUF, MIN_PDU_BUF,
+};
+use rubble::time::Duration;
+
+/// Errors returned by [`StackBuilder::build`] when the configuration is inconsistent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StackError {
+    /// No packet buffers were provided (see [`StackBuilder::buffers`]).
+    MissingBuffers,
+
+    /// No packet queues were provided (see [`StackBuilder::queues`]).
+    MissingQueues,
+
+    /// The RX queue can not hold a data channel PDU of maximum size.
+    QueueTooSmall,
+
+    /// The advertising data does not fit in an advertising PDU.
+    AdvDataTooLong,
+
+    /// The scan response data does not fit in a scan response PDU.
+    ScanResponseTooLong,
+
+    /// The advertising interval is outside of the allowed range of 20 ms to 10.24 s.
+    InvalidAdvInterval,
+
+    /// The feature set contains features that Rubble or the chip do not support.
+    UnsupportedFeatures,
+
+    /// The RX buffer is smaller than `MIN_PDU_BUF` or larger than the largest PDU.
+    InvalidRxBuffer,
+
+    /// The TX buffer is smaller than `MIN_PDU_BUF` or larger than the largest PDU.
+    InvalidTxBuffer,
+}
+
+/// Error returned by [`StackBuilder::build`], handing back the peripherals passed to it.
+pub struct BuildError<T: NrfTimerExt, R> {
+    /// The problem with the configuration.
+    pub error: StackError,
+
+    /// The unmodified `RADIO` peripheral.
+    pub radio: RADIO,
+
+    /// The timer, which has not been reconfigured since it was passed to `build`.
+    pub timer: BleTimer<T>,
+
+    /// The random number generator.
+    pub rng: R,
+}
+
+impl<T: NrfTimerExt, R> fmt::Debug for BuildError<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BuildError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: NrfTimerExt, R> fmt::Display for BuildError<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StackError::MissingBuffers => "no packet buffers provided",
+            StackError::MissingQueues => "no packet queues provided",
+            StackError::QueueTooSmall => "RX queue too small for a data channel PDU",
+            StackError::AdvDataTooLong => "advertising data too long",
+            StackError::ScanResponseTooLong => "scan response data too long",
+            StackError::InvalidAdvInterval => "advertising interval out of range",
+            StackError::UnsupportedFeatures => "unsupported Link-Layer features requested",
+            StackError::InvalidRxBuffer => "RX buffer size out of range",
+            StackError::InvalidTxBuffer => "TX buffer size out of range",
+        })
+    }
+}
+
+/// Collects the configuration of the BLE stack and creates the `LinkLayer` and `BleRadio`.
+///
+/// Only the packet buffers and queues are mandatory. By default, the device address is read from
+/// the FICR, the company identifier is `C::COMPANY_ID`, all features supported by Rubble and the
+/// chip are used, and the device advertises every 200 ms without any advertising or scan response
+/// data.
+pub struct StackBuilder<'a, C: Config> {
+    device_address: Option<DeviceAddress>,
+    company_id: CompanyId,
+    features: FeatureSet,
+    adv_interval: Duration,
+    adv_data: &'a [AdStructure<'a>],
+    scan_response: &'a [AdStructure<'a>],
+    buffers: Option<(&'static mut [u8], &'static mut [u8])>,
+    queues: Option<(ConfConsumer<C>, ConfProducer<C>)>,
+}
+
+impl<'a, C: Config> StackBuilder<'a, C> {
+    /// Creates a builder using the default configuration.
+    pub fn new() -> Self {
+        Self {
+            device_address: None,
+            company_id: C::COMPANY_ID,
+            features: supported_features(),
+            adv_interval: Duration::millis(200),
+            adv_data: &[],
+            scan_response: &[],
+            buffers: None,
+            queues: None,
+        }
+    }
+
+    /// Sets the device address to use instead of the one stored in the FICR.
+    pub fn device_address(mut self, address: DeviceAddress) -> Self {
+        self.device_address = Some(address);
+        self
+    }
+
+    /// Sets the company identifier sent to connected devices.
+    pub fn company_id(mut self, company_id: CompanyId) -> Self {
+        self.company_id = company_id;
+        self
+    }
+
+    /// Restricts the Link-Layer features used in connections.
+    ///
+    /// `features` must be a subset of [`supported_features`].
+    pub fn features(mut self, features: FeatureSet) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Sets the interval between advertising events.
+    pub fn advertising_interval(mut self, interval: Duration) -> Self {
+        self.adv_interval = interval;
+        self
+    }
+
+    /// Sets the data to broadcast in advertising PDUs.
+    pub fn advertising_data(mut self, data: &'a [AdStructure<'a>]) -> Self {
+        self.adv_data = data;
+        self
+    }
+
+    /// Sets the data sent in response to scan requests.
+    pub fn scan_response_data(mut self, data: &'a [AdStructure<'a>]) -> Self {
+        self.scan_response = data;
+        self
+    }
+
+    /// Sets the buffers the radio transmits from and receives into.
+    ///
+    /// See [`BleRadio::new`] for the allowed sizes of `tx_buf` and `rx_buf`.
+    pub fn buffers(mut self, tx_buf: &'static mut [u8], rx_buf: &'static mut [u8]) -> Self {
+        self.buffers = Some((tx_buf, rx_buf));
+        self
+    }
+
+    /// Sets the queues exchanging data packets with the `Responder` once connected.
+    ///
+    /// * **`tx`**: Consumer of packets to transmit.
+    /// * **`rx`**: Producer of received packets.
+    pub fn queues(mut self, tx: ConfConsumer<C>, rx: ConfProducer<C>) -> Self {
+        self.queues = Some((tx, rx));
+        self
+    }
+
+    /// Checks the configuration, creates the `LinkLayer` and `BleRadio`, and starts advertising.
+    ///
+    /// The timer interrupt is configured for the next advertising event, so the caller only has to
+    /// hand the returned objects to the `RADIO` and timer interrupt handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BuildError`] describing the problem if the configuration is inconsistent. In
+    /// that case, no peripheral is modified, and `radio`, `timer` and `rng` are handed back.
+    pub fn build<T: NrfTimerExt>(
+        self,
+        radio: RADIO,
+        ficr: &pac::FICR,
+        timer: BleTimer<T>,
+        rng: C::Rng,
+    ) -> Result<(LinkLayer<C>, BleRadio), BuildError<T, C::Rng>>
+    where
+        C: Config<Timer = BleTimer<T>, Transmitter = BleRadio>,
+        C::Aes: Default,
+    {
+        let address = self.device_address.unwrap_or_else(|| device_address(ficr));
+        if let Err(error) = self.check(address) {
+            return Err(BuildError {
+                error,
+                radio,
+                timer,
+                rng,
+            });
+        }
+
+        // Everything that can fail was checked above
+        let (tx_buf, rx_buf) = self.buffers.unwrap();
+        let (tx, rx) = self.queues.unwrap();
+        let mut ll = LinkLayer::<C>::new(address, timer, rng);
+        ll.set_scan_response_data(self.scan_response).unwrap();
+        ll.set_features(self.features).unwrap();
+        ll.set_company_id(self.company_id);
+
+        let mut radio = BleRadio::new(radio, ficr, tx_buf, rx_buf).unwrap();
+        let next_update = ll
+            .start_advertise(self.adv_interval, self.adv_data, &mut radio, tx, rx)
+            .unwrap();
+        ll.timer().configure_interrupt(next_update);
+
+        Ok((ll, radio))
+    }
+
+    /// Checks the configuration for advertising as `address`.
+    fn check(&self, address: DeviceAddress) -> Result<(), StackError> {
+        let (tx_buf, rx_buf) = self.buffers.as_ref().ok_or(StackError::MissingBuffers)?;
+        let (_, rx) = self.queues.as_ref().ok_or(StackError::MissingQueues)?;
+        if usize::from(rx.free_space()) < MIN_DATA_PAYLOAD_BUF {
+            return Err(StackError::QueueTooSmall);
+        }
+        if self.adv_interval < AdvParams::MIN_INTERVAL
+            || self.adv_interval > AdvParams::MAX_INTERVAL
+        {
+            return Err(StackError::InvalidAdvInterval);
+        }
+        if !supported_features().contains(self.features) {
+            return Err(StackError::UnsupportedFeatures);
+        }
+
+        PduBuf::discoverable(address, self.adv_data).map_err(|_| StackError::AdvDataTooLong)?;
+        PduBuf::scan_response(address, self.scan_response)
+            .map_err(|_| StackError::ScanResponseTooLong)?;
+
+        let buf_sizes = MIN_PDU_BUF..=usize::from(u8::MAX) + 2;
+        if !buf_sizes.contains(&tx_buf.len()) {
+            return Err(StackError::InvalidTxBuffer);
+        }
+        if !buf_sizes.contains(&rx_buf.len()) {
+            return Err(StackError::InvalidRxBuffer);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, C: Config> Default for StackBuilder<'a, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
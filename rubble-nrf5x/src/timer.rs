@@ -67,6 +67,11 @@ impl<T: NrfTimerExt> BleTimer<T> {
         self.inner.clear_interrupt();
     }
 
+    /// Returns a pointer to the timer's registers.
+    pub(crate) fn registers(&self) -> *const pac::timer0::RegisterBlock {
+        T::registers()
+    }
+
     /// Provides access to the raw peripheral. Use with caution.
     pub fn inner(&mut self) -> &mut T {
         &mut self.inner
@@ -106,7 +111,10 @@ mod sealed {
 
 /// Extension trait implemented for the nRF timer peripherals.
 ///
-/// We use `CC[0]` to read the counter value, and `CC[1]` to set timer interrupts.
+/// We use `CC[0]` to read the counter value, and `CC[1]` to set timer interrupts. `CC[2]` and
+/// `CC[3]` are used by the radio for timeouts (see [`BleRadio::enable_timeouts`]).
+///
+/// [`BleRadio::enable_timeouts`]: crate::radio::BleRadio::enable_timeouts
 pub trait NrfTimerExt: sealed::Sealed {
     unsafe fn duplicate(&self) -> Self;
 
@@ -119,6 +127,9 @@ pub trait NrfTimerExt: sealed::Sealed {
     /// Disables or acknowledges this timer's interrupt.
     fn clear_interrupt(&mut self);
 
+    /// Returns a pointer to the timer's registers.
+    fn registers() -> *const pac::timer0::RegisterBlock;
+
     /// Returns whether a timer interrupt is currently pending.
     ///
     /// This must be called by the interrupt handler to avoid spurious timer events.
@@ -161,6 +172,10 @@ macro_rules! impl_timer {
                     self.events_compare[1].reset();
                 }
 
+                fn registers() -> *const pac::timer0::RegisterBlock {
+                    <$ty>::ptr()
+                }
+
                 fn is_pending(&self) -> bool {
                     self.events_compare[1].read().bits() == 1u32
                 }
@@ -9,7 +9,16 @@ use rubble::link::{AddressKind, DeviceAddress};
 pub fn get_device_address() -> DeviceAddress {
     // FICR is read-only, so accessing it directly should be safe
     let ficr = unsafe { &*pac::FICR::ptr() };
+    device_address(ficr)
+}
 
+/// Reads the factory-programmed device address from `ficr`.
+///
+/// The address is stored in `DEVICEADDR[0..1]` (least significant Byte first), and
+/// `DEVICEADDRTYPE` tells whether it is a public or a random address. Random addresses are
+/// returned as random static addresses, which requires the 2 most significant bits to be set. Since
+/// the FICR only stores random bits, they are set here.
+pub fn device_address(ficr: &pac::ficr::RegisterBlock) -> DeviceAddress {
     // Address bytes
     let mut devaddr = [0u8; 6];
     let devaddr_lo = ficr.deviceaddr[0].read().bits();
@@ -20,8 +29,49 @@ pub fn get_device_address() -> DeviceAddress {
     // Address type
     let devaddr_type = match ficr.deviceaddrtype.read().deviceaddrtype().variant() {
         DEVICEADDRTYPE_A::PUBLIC => AddressKind::Public,
-        DEVICEADDRTYPE_A::RANDOM => AddressKind::Random,
+        DEVICEADDRTYPE_A::RANDOM => {
+            // Random static addresses have the 2 MSbs set
+            devaddr[5] |= 0b1100_0000;
+            AddressKind::Random
+        }
     };
 
     DeviceAddress::new(devaddr, devaddr_type)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::MaybeUninit;
+
+    /// Returns FICR contents with the given device address registers.
+    fn ficr(deviceaddr: [u32; 2], deviceaddrtype: u32) -> pac::ficr::RegisterBlock {
+        let ficr: pac::ficr::RegisterBlock = unsafe { MaybeUninit::zeroed().assume_init() };
+        unsafe {
+            *ficr.deviceaddr[0].as_ptr() = deviceaddr[0];
+            *ficr.deviceaddr[1].as_ptr() = deviceaddr[1];
+            *ficr.deviceaddrtype.as_ptr() = deviceaddrtype;
+        }
+        ficr
+    }
+
+    #[test]
+    fn public_address() {
+        // The upper 16 bits of `DEVICEADDR[1]` are unused
+        let addr = device_address(&ficr([0x4433_2211, 0xFFFF_6655], 0));
+        assert_eq!(addr.kind(), AddressKind::Public);
+        assert_eq!(*addr.raw(), [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+    }
+
+    #[test]
+    fn random_static_address() {
+        let addr = device_address(&ficr([0x4433_2211, 0x6655], 1));
+        assert_eq!(addr.kind(), AddressKind::Random);
+        assert!(addr.is_random_static());
+        assert_eq!(*addr.raw(), [0x11, 0x22, 0x33, 0x44, 0x55, 0xE6]);
+
+        // Already set bits are left alone
+        let addr = device_address(&ficr([0x4433_2211, 0xC655], 1));
+        assert_eq!(*addr.raw(), [0x11, 0x22, 0x33, 0x44, 0x55, 0xC6]);
+    }
+}
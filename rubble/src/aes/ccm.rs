@@ -0,0 +1,265 @@
+use super::AesProvider;
+use core::fmt;
+
+/// Size of the Message Integrity Check (MIC) appended to encrypted data channel PDUs.
+pub const MIC_SIZE: usize = 4;
+
+/// Direction in which a data channel PDU is sent.
+///
+/// The direction is mixed into the CCM nonce so that packets sent by master and slave never share
+/// a nonce, even though they use the same session key and the same packet counter values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The PDU is sent from the master to the slave (`directionBit = 1`).
+    MasterToSlave,
+    /// The PDU is sent from the slave to the master (`directionBit = 0`).
+    SlaveToMaster,
+}
+
+/// Error returned by [`Ccm::decrypt`] when the MIC of a received packet is incorrect.
+#[derive(Debug)]
+pub struct InvalidMic {}
+
+impl fmt::Display for InvalidMic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid MIC")
+    }
+}
+
+/// The AES-CCM transform used for Link-Layer encryption.
+///
+/// BLE uses CCM with a 4-Byte MIC and a 2-Byte length field. The 13-Byte nonce is made up of the
+/// 39-bit packet counter, the direction bit, and the 8-Byte IV negotiated when starting encryption.
+/// The first Byte of the data channel PDU header, with the `NESN`, `SN` and `MD` bits masked out,
+/// is authenticated as additional data.
+#[derive(Clone)]
+pub struct Ccm {
+    /// The session key (SK), in *FIPS-197* byte order.
+    key: [u8; 16],
+    /// The IV, in the order it is placed in the nonce (`IVm` followed by `IVs`).
+    iv: [u8; 8],
+}
+
+impl Ccm {
+    /// Creates a CCM transform from a session key and IV.
+    ///
+    /// # Parameters
+    ///
+    /// * **`session_key`**: The session key SK, as a 128-bit number.
+    /// * **`iv`**: The 8-Byte IV, consisting of `IVm` (first) and `IVs` (last) as transmitted on
+    ///   the air (least significant Byte first).
+    pub fn new(session_key: u128, iv: [u8; 8]) -> Self {
+        Self {
+            key: session_key.to_be_bytes(),
+            iv,
+        }
+    }
+
+    /// Encrypts `payload` in place and returns the MIC to append to it.
+    ///
+    /// # Parameters
+    ///
+    /// * **`counter`**: The 39-bit packet counter of the sender.
+    /// * **`direction`**: The direction in which the packet is sent.
+    /// * **`header`**: The first Byte of the data channel PDU header.
+    /// * **`payload`**: The payload to encrypt. Must be between 1 and 251 Bytes long.
+    pub fn encrypt(
+        &self,
+        aes: &mut impl AesProvider,
+        counter: u64,
+        direction: Direction,
+        header: u8,
+        payload: &mut [u8],
+    ) -> [u8; MIC_SIZE] {
+        let nonce = self.nonce(counter, direction);
+        let tag = self.cbc_mac(aes, &nonce, header, payload);
+        self.ctr(aes, &nonce, payload);
+        self.mic(aes, &nonce, tag)
+    }
+
+    /// Decrypts `payload` in place and checks its `mic`.
+    ///
+    /// The parameters are the same as for [`Ccm::encrypt`]. If the MIC does not match, an error is
+    /// returned and the contents of `payload` are unspecified.
+    pub fn decrypt(
+        &self,
+        aes: &mut impl AesProvider,
+        counter: u64,
+        direction: Direction,
+        header: u8,
+        payload: &mut [u8],
+        mic: &[u8; MIC_SIZE],
+    ) -> Result<(), InvalidMic> {
+        let nonce = self.nonce(counter, direction);
+        self.ctr(aes, &nonce, payload);
+        let tag = self.cbc_mac(aes, &nonce, header, payload);
+        if self.mic(aes, &nonce, tag) == *mic {
+            Ok(())
+        } else {
+            Err(InvalidMic {})
+        }
+    }
+
+    fn nonce(&self, counter: u64, direction: Direction) -> [u8; 13] {
+        let mut nonce = [0; 13];
+        nonce[..5].copy_from_slice(&counter.to_le_bytes()[..5]);
+        nonce[4] &= 0x7F;
+        if direction == Direction::MasterToSlave {
+            nonce[4] |= 0x80;
+        }
+        nonce[5..].copy_from_slice(&self.iv);
+        nonce
+    }
+
+    /// Computes the (unencrypted) authentication tag over `header` and plaintext `payload`.
+    fn cbc_mac(
+        &self,
+        aes: &mut impl AesProvider,
+        nonce: &[u8; 13],
+        header: u8,
+        payload: &[u8],
+    ) -> [u8; 16] {
+        assert!(!payload.is_empty() && payload.len() <= 251);
+
+        // B0: Flags (Adata = 1, M' = 1, L' = 1), nonce, payload length
+        let mut x = [0; 16];
+        x[0] = 0x49;
+        x[1..14].copy_from_slice(nonce);
+        x[15] = payload.len() as u8;
+        aes.encrypt_block(&self.key, &mut x);
+
+        // B1: Length of additional data, followed by the masked header
+        x[1] ^= 0x01;
+        x[2] ^= header & 0b1110_0011;
+        aes.encrypt_block(&self.key, &mut x);
+
+        for chunk in payload.chunks(16) {
+            for (x, b) in x.iter_mut().zip(chunk) {
+                *x ^= b;
+            }
+            aes.encrypt_block(&self.key, &mut x);
+        }
+
+        x
+    }
+
+    /// Applies the CTR mode keystream (starting at counter 1) to `payload`.
+    fn ctr(&self, aes: &mut impl AesProvider, nonce: &[u8; 13], payload: &mut [u8]) {
+        for (i, chunk) in payload.chunks_mut(16).enumerate() {
+            let s = self.keystream_block(aes, nonce, i as u16 + 1);
+            for (b, s) in chunk.iter_mut().zip(&s) {
+                *b ^= s;
+            }
+        }
+    }
+
+    /// Encrypts the authentication tag into the MIC.
+    fn mic(&self, aes: &mut impl AesProvider, nonce: &[u8; 13], tag: [u8; 16]) -> [u8; MIC_SIZE] {
+        let s0 = self.keystream_block(aes, nonce, 0);
+        let mut mic = [0; MIC_SIZE];
+        for (i, m) in mic.iter_mut().enumerate() {
+            *m = tag[i] ^ s0[i];
+        }
+        mic
+    }
+
+    fn keystream_block(&self, aes: &mut impl AesProvider, nonce: &[u8; 13], i: u16) -> [u8; 16] {
+        let mut a = [0; 16];
+        a[0] = 0x01;
+        a[1..14].copy_from_slice(nonce);
+        a[14..].copy_from_slice(&i.to_be_bytes());
+        aes.encrypt_block(&self.key, &mut a);
+        a
+    }
+}
+
+impl fmt::Debug for Ccm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Ccm { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+
+    /// Session key and IV from the encryption sample data in the spec (Vol 6, Part C, 1).
+    fn sample_ccm() -> Ccm {
+        Ccm::new(
+            0x99AD1B5226A37E3E058E3B8E27C2C666,
+            [0x24, 0xAB, 0xDC, 0xBA, 0xBE, 0xBA, 0xAF, 0xDE],
+        )
+    }
+
+    fn check(counter: u64, direction: Direction, header: u8, plain: &[u8], encrypted: &[u8]) {
+        let ccm = sample_ccm();
+        let aes = &mut SoftAesProvider::new();
+
+        let mut payload = [0; 27];
+        let payload = &mut payload[..plain.len()];
+        payload.copy_from_slice(plain);
+        let mic = ccm.encrypt(aes, counter, direction, header, payload);
+        assert_eq!(payload, &encrypted[..plain.len()]);
+        assert_eq!(&mic, &encrypted[plain.len()..]);
+
+        ccm.decrypt(aes, counter, direction, header, payload, &mic)
+            .unwrap();
+        assert_eq!(payload, plain);
+
+        // Any change in the packet counter, direction, or header must be detected
+        let mut payload2 = [0; 27];
+        let payload2 = &mut payload2[..plain.len()];
+        payload2.copy_from_slice(&encrypted[..plain.len()]);
+        assert!(ccm
+            .decrypt(aes, counter + 1, direction, header, payload2, &mic)
+            .is_err());
+        payload2.copy_from_slice(&encrypted[..plain.len()]);
+        assert!(ccm
+            .decrypt(aes, counter, direction, header ^ 0b01, payload2, &mic)
+            .is_err());
+        // NESN, SN and MD are not authenticated
+        payload2.copy_from_slice(&encrypted[..plain.len()]);
+        ccm.decrypt(aes, counter, direction, header ^ 0b1_1100, payload2, &mic)
+            .unwrap();
+    }
+
+    #[test]
+    fn start_enc_rsp() {
+        // LL_START_ENC_RSP1 (master -> slave)
+        check(
+            0,
+            Direction::MasterToSlave,
+            0x0F,
+            &[0x06],
+            &[0x9F, 0xCD, 0xA7, 0xF4, 0x48],
+        );
+        // LL_START_ENC_RSP2 (slave -> master)
+        check(
+            0,
+            Direction::SlaveToMaster,
+            0x07,
+            &[0x06],
+            &[0xA3, 0x4C, 0x13, 0xA4, 0x15],
+        );
+    }
+
+    #[test]
+    fn data_packet() {
+        // Data packet 1 (master -> slave)
+        check(
+            1,
+            Direction::MasterToSlave,
+            0x0E,
+            &[
+                0x17, 0x00, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x6B, 0x6C, 0x6D, 0x6E,
+                0x6F, 0x70, 0x71, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30,
+            ],
+            &[
+                0x7A, 0x70, 0xD6, 0x64, 0x15, 0x22, 0x6D, 0xF2, 0x6B, 0x17, 0x83, 0x9A, 0x06, 0x04,
+                0x05, 0x59, 0x6B, 0xD6, 0x56, 0x4F, 0x79, 0x6B, 0x5B, 0x9C, 0xE6, 0xFF, 0x32, 0xF7,
+                0x5A, 0x6D, 0x33,
+            ],
+        );
+    }
+}
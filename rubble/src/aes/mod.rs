@@ -0,0 +1,72 @@
+//! AES-128 block encryption.
+//!
+//! BLE uses AES-128 both for the key generation functions used during pairing and for encrypting
+//! Link-Layer connections (via AES-CCM). This module provides an interface for plugging in
+//! different implementations of the block cipher, so that MCUs with an AES peripheral (like the
+//! `ECB` peripheral on nRF chips) can make use of it.
+//!
+//! The primary trait in this module is [`AesProvider`]. Rubble comes with a pure-Rust software
+//! implementation of that trait, [`SoftAesProvider`], which is always available.
+//!
+//! The [`Ccm`] type implements the AES-CCM mode used for encrypting data channel PDUs on top of an
+//! [`AesProvider`].
+
+mod ccm;
+mod soft;
+
+pub use self::ccm::*;
+pub use self::soft::*;
+
+/// Trait for AES-128 providers.
+///
+/// Only the encryption direction of the block cipher is required, since all BLE modes of
+/// operation (the security toolbox functions and CCM) are built on top of it.
+pub trait AesProvider {
+    /// Encrypts a single 16-Byte `block` in place using the 128-bit `key`.
+    ///
+    /// Both `key` and `block` use the byte order of *FIPS-197*: The first Byte of each array is
+    /// the most significant one. Note that this is the opposite of the byte order most BLE PDUs use
+    /// on the air.
+    fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]);
+}
+
+/// Runs Rubble's AES provider testsuite against `provider`.
+///
+/// This checks the known-answer tests given in *FIPS-197*.
+pub fn run_tests(mut provider: impl AesProvider) {
+    // FIPS-197, Appendix B
+    let key = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let mut block = [
+        0x32, 0x43, 0xf6, 0xa8, 0x88, 0x5a, 0x30, 0x8d, 0x31, 0x31, 0x98, 0xa2, 0xe0, 0x37, 0x07,
+        0x34,
+    ];
+    provider.encrypt_block(&key, &mut block);
+    assert_eq!(
+        block,
+        [
+            0x39, 0x25, 0x84, 0x1d, 0x02, 0xdc, 0x09, 0xfb, 0xdc, 0x11, 0x85, 0x97, 0x19, 0x6a,
+            0x0b, 0x32,
+        ]
+    );
+
+    // FIPS-197, Appendix C.1
+    let key = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    let mut block = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+    provider.encrypt_block(&key, &mut block);
+    assert_eq!(
+        block,
+        [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ]
+    );
+}
@@ -32,6 +32,7 @@ impl fmt::Debug for Handle {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for Handle {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "{:#06X}", self.0)
@@ -59,6 +60,11 @@ pub struct RawHandleRange {
 }
 
 impl RawHandleRange {
+    /// Creates a handle range spanning from `start` to `end` (inclusive).
+    pub fn new(start: Handle, end: Handle) -> Self {
+        Self { start, end }
+    }
+
     /// Checks that this handle range is valid according to the Bluetooth spec.
     ///
     /// Returns an `AttError` that should be sent as a response if the range is invalid.
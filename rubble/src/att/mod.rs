@@ -32,11 +32,19 @@ mod pdus;
 mod server;
 mod uuid;
 
-use self::{handle::*, pdus::*};
-use crate::{l2cap::Sender, Error};
+use self::pdus::*;
+use crate::security::LinkSecurity;
+use crate::Error;
+use bitflags::bitflags;
 
+pub(crate) use self::handle::RawHandleRange;
 pub use self::handle::{Handle, HandleRange};
-pub use self::server::{AttributeServer, AttributeServerTx};
+pub use self::pdus::ErrorCode;
+pub(crate) use self::pdus::{AttPdu, Opcode};
+pub use self::server::{
+    AttributeServer, AttributeServerTx, ATT_TRANSACTION_TIMEOUT, DEFAULT_ATT_MTU,
+    MAX_SUBSCRIPTIONS, PREPARE_QUEUE_SIZE,
+};
 pub use self::uuid::AttUuid;
 
 /// An ATT server attribute
@@ -79,6 +87,10 @@ pub enum AttributeAccessPermissions {
     Readable,
     Writeable,
     ReadableAndWriteable,
+
+    /// Neither readable nor writeable, like the value of a characteristic that only supports
+    /// notifications.
+    Inaccessible,
 }
 
 impl AttributeAccessPermissions {
@@ -86,14 +98,18 @@ impl AttributeAccessPermissions {
         match self {
             AttributeAccessPermissions::Readable
             | AttributeAccessPermissions::ReadableAndWriteable => true,
-            AttributeAccessPermissions::Writeable => false,
+            AttributeAccessPermissions::Writeable | AttributeAccessPermissions::Inaccessible => {
+                false
+            }
         }
     }
     fn is_writeable(&self) -> bool {
         match self {
             AttributeAccessPermissions::Writeable
             | AttributeAccessPermissions::ReadableAndWriteable => true,
-            AttributeAccessPermissions::Readable => false,
+            AttributeAccessPermissions::Readable | AttributeAccessPermissions::Inaccessible => {
+                false
+            }
         }
     }
 }
@@ -104,6 +120,71 @@ impl Default for AttributeAccessPermissions {
     }
 }
 
+bitflags! {
+    /// Security requirements an attribute places on the connection accessing it.
+    ///
+    /// These complement [`AttributeAccessPermissions`]: an attribute must be readable (or
+    /// writeable) in the first place, and the connection's [`LinkSecurity`] must also satisfy the
+    /// flags for the operation. Otherwise, the `AttributeServer` responds with *Insufficient
+    /// Encryption* or *Insufficient Authentication*.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+    pub struct AttributeSecurity: u8 {
+        /// Reading requires an encrypted connection.
+        const READ_ENCRYPTED = 1 << 0;
+        /// Reading requires a connection encrypted with an authenticated key.
+        const READ_AUTHENTICATED = 1 << 1;
+        /// Writing requires an encrypted connection.
+        const WRITE_ENCRYPTED = 1 << 2;
+        /// Writing requires a connection encrypted with an authenticated key.
+        const WRITE_AUTHENTICATED = 1 << 3;
+    }
+}
+
+impl AttributeSecurity {
+    /// Checks whether a connection with security `link` may read the attribute.
+    fn check_read(self, link: LinkSecurity) -> Result<(), ErrorCode> {
+        Self::check(
+            link,
+            self.contains(Self::READ_ENCRYPTED),
+            self.contains(Self::READ_AUTHENTICATED),
+        )
+    }
+
+    /// Checks whether a connection with security `link` may write the attribute.
+    fn check_write(self, link: LinkSecurity) -> Result<(), ErrorCode> {
+        Self::check(
+            link,
+            self.contains(Self::WRITE_ENCRYPTED),
+            self.contains(Self::WRITE_AUTHENTICATED),
+        )
+    }
+
+    fn check(link: LinkSecurity, encrypted: bool, authenticated: bool) -> Result<(), ErrorCode> {
+        if authenticated && link < LinkSecurity::Authenticated {
+            Err(ErrorCode::InsufficientAuthentication)
+        } else if encrypted && link < LinkSecurity::Encrypted {
+            Err(ErrorCode::InsufficientEncryption)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A change of the attribute table that clients have to be told about.
+///
+/// See [`AttributeProvider::service_change`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ServiceChange {
+    /// Handle of the *Service Changed* characteristic value, which is indicated to the client.
+    pub characteristic: Handle,
+
+    /// First handle of the affected range.
+    pub start: Handle,
+
+    /// Last handle of the affected range.
+    pub end: Handle,
+}
+
 /// Trait for attribute sets that can be hosted by an `AttributeServer`.
 pub trait AttributeProvider {
     /// Calls a closure `f` with every attribute whose handle is inside `range`, ascending.
@@ -153,6 +234,17 @@ pub trait AttributeProvider {
         AttributeAccessPermissions::Readable
     }
 
+    /// Retrieves the security requirements for accessing the given attribute.
+    ///
+    /// The `AttributeServer` checks these against the security of the current connection, as set
+    /// with [`AttributeServer::set_link_security`], before performing reads and writes permitted by
+    /// `attr_access_permissions`.
+    ///
+    /// Defaults to no requirements.
+    fn attr_security(&self, _handle: Handle) -> AttributeSecurity {
+        AttributeSecurity::empty()
+    }
+
     /// Attempts to write data to the given attribute.
     ///
     /// This will only be called on handles for which
@@ -161,11 +253,38 @@ pub trait AttributeProvider {
     /// or [`AttributeAccessPermissions::ReadableAndWriteable`].
     ///
     /// By default, panics on all writes. This must be overwritten if
-    /// `attribute_access_permissions` is.
+    /// `attribute_access_permissions` is, unless `on_write` is overwritten instead.
     fn write_attr(&mut self, _handle: Handle, _data: &[u8]) -> Result<(), Error> {
         unimplemented!("by default, no attributes should have write access permissions, and this should never be called");
     }
 
+    /// Called when the client writes to the attribute at `handle`.
+    ///
+    /// This is invoked for *Write Requests*, *Write Commands*, and executed *Prepare Write
+    /// Requests* to attributes for which `attr_access_permissions` indicates write access. `offset`
+    /// is the position in the attribute's value at which `value` is written. It is 0 for *Write
+    /// Requests* and *Write Commands*. Queued writes (also called *long writes*) are joined by the
+    /// server, so `value` is the complete data the client has prepared for the attribute, and
+    /// `offset` is the offset of the first prepared chunk.
+    ///
+    /// Returning an error rejects the write. For *Write Requests*, the error code is sent to the
+    /// client in an *Error Response* (instead of a *Write Response*). *Write Commands* are never
+    /// answered, so the error is only logged.
+    ///
+    /// By default, this forwards the write to `write_attr` and maps its `Error::InvalidLength` to
+    /// `ErrorCode::InvalidAttributeValueLength`, and all other errors to
+    /// `ErrorCode::UnlikelyError`. Non-zero offsets are rejected with `ErrorCode::InvalidOffset`.
+    fn on_write(&mut self, handle: Handle, offset: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        if offset != 0 {
+            return Err(ErrorCode::InvalidOffset);
+        }
+
+        self.write_attr(handle, value).map_err(|err| match err {
+            Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
+            _ => ErrorCode::UnlikelyError,
+        })
+    }
+
     /// If this read is from dynamic data fill the buffer and return the length of the data.
     /// If not return None.
     ///
@@ -176,31 +295,47 @@ pub trait AttributeProvider {
         None
     }
 
-    /// In order to write data longer than what would fit one write request the procedure is explained in
-    /// BLUETOOTH CORE SPECIFICATION Version 5.2 | Vol 3, Part F section 3.4.6.
+    /// Formerly called for every *Prepare Write Request* (BLUETOOTH CORE SPECIFICATION Version 5.2
+    /// | Vol 3, Part F section 3.4.6).
+    ///
+    /// The server now queues prepared writes itself and passes the joined value to
+    /// `write_attribute` when they are executed, so this is never called.
+    #[deprecated(note = "queued writes are passed to `write_attribute`")]
     fn prepare_write_attr(
         &mut self,
         _handle: Handle,
         _offset: u16,
         _data: &[u8],
     ) -> Result<(), Error> {
-        unimplemented!("you need to implement prepare_write_attr to make queued writes work")
+        Ok(())
     }
 
-    /// In order to write data longer than what would fit one write request the procedure is explained in
-    /// BLUETOOTH CORE SPECIFICATION Version 5.2 | Vol 3, Part F section 3.4.6.
+    /// Formerly called for every *Execute Write Request* (BLUETOOTH CORE SPECIFICATION Version 5.2
+    /// | Vol 3, Part F section 3.4.6).
+    ///
+    /// The server now executes queued writes itself by calling `write_attribute`, so this is never
+    /// called.
+    #[deprecated(note = "queued writes are passed to `write_attribute`")]
     fn execute_write_attr(&mut self, _flags: u8) -> Result<(), Error> {
-        unimplemented!("you need to implement execute_write_attr to make queued writes work")
+        Ok(())
     }
 
-    /// See BLUETOOTH CORE SPECIFICATION Version 5.2 | Vol 3, Part F section 3.4.3.1 on what to implement here.
-    fn find_information(
-        &mut self,
-        _range: HandleRange,
-        _responder: &mut Sender<'_>,
-    ) -> Result<(), Error> {
-        unimplemented!("you need to implement find_information to make things like Client Characteristic Configuration work")
+    /// Returns the attributes that were added, removed or modified since the client was last told
+    /// about it.
+    ///
+    /// The `AttributeServer` indicates the change via the *Service Changed* characteristic as soon
+    /// as the client has subscribed to it, and calls `service_change_indicated` once the client
+    /// confirmed it.
+    ///
+    /// By default returns `None`, for attribute tables that never change.
+    fn service_change(&self) -> Option<ServiceChange> {
+        None
     }
+
+    /// Called when the client confirmed the indication of `change`.
+    ///
+    /// Changes made after `change` was indicated must still be returned by `service_change`.
+    fn service_change_indicated(&mut self, _change: ServiceChange) {}
 }
 
 /// An empty attribute set.
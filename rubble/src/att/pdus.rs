@@ -2,13 +2,14 @@
 
 use super::{AttUuid, Handle, RawHandleRange};
 use crate::{bytes::*, utils::HexSlice, Error};
-use core::convert::TryInto;
+use core::{cmp, convert::TryInto};
 
 enum_with_unknown! {
     /// Error codes that can be sent from the ATT server to the client in response to a request.
     ///
     /// Used as the payload of `ErrorRsp` PDUs.
-    #[derive(Copy, Clone, Debug, defmt::Format)]
+    #[derive(Copy, Clone, Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum ErrorCode(u8) {
         /// Attempted to use an `Handle` that isn't valid on this server.
         InvalidHandle = 0x01,
@@ -88,8 +89,11 @@ pub struct ByTypeAttData<'a> {
 impl<'a> ByTypeAttData<'a> {
     /// Creates a *Read By Type Response* attribute data structure from the attribute's handle and
     /// value.
-    pub fn new(att_mtu: u8, handle: Handle, mut value: &'a [u8]) -> Self {
-        let max_val_len = usize::from(att_mtu - 2);
+    ///
+    /// The value is truncated to `ATT_MTU - 4` Bytes (leaving space for the opcode, length, and
+    /// handle), or 253 Bytes, whichever is smaller.
+    pub fn new(att_mtu: u16, handle: Handle, mut value: &'a [u8]) -> Self {
+        let max_val_len = usize::from(cmp::min(att_mtu - 4, 253));
         if value.len() > max_val_len {
             value = &value[..max_val_len];
         }
@@ -137,7 +141,12 @@ pub struct ByGroupAttData<'a> {
 }
 
 impl<'a> ByGroupAttData<'a> {
-    pub fn new(att_mtu: u8, handle: Handle, group_end_handle: Handle, mut value: &'a [u8]) -> Self {
+    pub fn new(
+        att_mtu: u16,
+        handle: Handle,
+        group_end_handle: Handle,
+        mut value: &'a [u8],
+    ) -> Self {
         // 2 Bytes for `handle`, 2 Bytes for `group_end_handle`
         let max_val_len = usize::from(att_mtu - 2 - 2);
         if value.len() > max_val_len {
@@ -201,7 +210,7 @@ enum_with_unknown! {
     ///   can ignore unknown commands. Unlike *Requests*, Commands are not followed by a server
     ///   response.
     /// * **`Method`** defines which operation to perform.
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Opcode(u8) {
         ErrorRsp = 0x01,
         ExchangeMtuReq = 0x02,
@@ -2,23 +2,430 @@
 
 use super::{
     pdus::{AttPdu, ByGroupAttData, ByTypeAttData, ErrorCode, Opcode},
-    AttError, AttributeProvider, Handle, HandleRange,
+    AttError, AttUuid, AttributeProvider, Handle, HandleRange, ServiceChange,
 };
-use crate::bytes::{ByteReader, FromBytes, ToBytes};
-use crate::l2cap::{Protocol, ProtocolObj, Sender};
+use crate::bytes::{ByteReader, ByteWriter, FromBytes, ToBytes};
+use crate::gatt::characteristic::{ClientConfig, CLIENT_CONFIG_UUID};
+use crate::l2cap::{self, Protocol, ProtocolObj, Sender};
+use crate::security::LinkSecurity;
+use crate::time::Duration;
+use crate::uuid::Uuid16;
 use crate::{utils::HexSlice, Error};
+use core::cmp;
+use heapless::Vec;
 
 const DYNAMIC_READ_BUFFER_SIZE: usize = 256; // this limits the maximum value size for dynamic reads to 256 bytes
 
+/// The default `ATT_MTU` on LE links, which is also the smallest allowed value.
+pub const DEFAULT_ATT_MTU: u16 = 23;
+
+/// Maximum number of Client Characteristic Configuration descriptors the client can enable at the
+/// same time.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Maximum number of *Prepare Write Requests* that can be queued before they are executed.
+///
+/// Further requests are rejected with a `Prepare Queue Full` error.
+pub const PREPARE_QUEUE_SIZE: usize = 8;
+
+/// Time the client has to confirm an indication before the ATT bearer is considered dead.
+///
+/// The Link-Layer closes the connection when this elapses.
+pub const ATT_TRANSACTION_TIMEOUT: Duration = Duration::secs(30);
+
+/// Total number of value Bytes that can be queued by *Prepare Write Requests*.
+///
+/// This fits `PREPARE_QUEUE_SIZE` requests at the default `ATT_MTU`. With a larger `ATT_MTU`, fewer
+/// requests may fit before they are rejected with a `Prepare Queue Full` error.
+pub const PREPARE_BUF_SIZE: usize = PREPARE_QUEUE_SIZE * (DEFAULT_ATT_MTU as usize - 5);
+
+/// A queued *Prepare Write Request*.
+///
+/// The values of all queued requests are stored back to back in `AttributeServer::prepare_buf`.
+struct PreparedWrite {
+    handle: Handle,
+    offset: u16,
+    len: u8,
+}
+
+/// Returns the writes in `queue` along with their values stored in `buf`.
+fn prepared_writes<'a>(
+    queue: &'a [PreparedWrite],
+    buf: &'a [u8],
+) -> impl Iterator<Item = (&'a PreparedWrite, &'a [u8])> + Clone {
+    let mut start = 0;
+    queue.iter().map(move |write| {
+        let value = &buf[start..start + usize::from(write.len)];
+        start += usize::from(write.len);
+        (write, value)
+    })
+}
+
 /// An Attribute Protocol server providing read and write access to stored attributes.
 pub struct AttributeServer<A: AttributeProvider> {
     attrs: A,
+
+    /// The largest `ATT_MTU` this server is willing to use.
+    max_mtu: u16,
+
+    /// The `ATT_MTU` negotiated with the client on the current connection.
+    mtu: u16,
+
+    /// Non-zero CCCD values written by the client on the current connection.
+    subscriptions: Vec<(Handle, ClientConfig), MAX_SUBSCRIPTIONS>,
+
+    /// Whether an indication was sent that hasn't been confirmed yet.
+    indication_pending: bool,
+
+    /// The change of the attribute table indicated by the pending indication, if any.
+    service_change_sent: Option<ServiceChange>,
+
+    /// Writes queued by *Prepare Write Requests*, in the order they were received.
+    prepare_queue: Vec<PreparedWrite, PREPARE_QUEUE_SIZE>,
+
+    /// The values of the queued writes.
+    prepare_buf: Vec<u8, PREPARE_BUF_SIZE>,
+
+    /// Security of the current connection, checked against the attributes' requirements.
+    security: LinkSecurity,
 }
 
 impl<A: AttributeProvider> AttributeServer<A> {
     /// Creates an `AttributeServer` hosting attributes from an `AttributeProvider`.
     pub fn new(attrs: A) -> Self {
-        Self { attrs }
+        Self {
+            attrs,
+            max_mtu: DEFAULT_ATT_MTU,
+            mtu: DEFAULT_ATT_MTU,
+            subscriptions: Vec::new(),
+            indication_pending: false,
+            service_change_sent: None,
+            prepare_queue: Vec::new(),
+            prepare_buf: Vec::new(),
+            security: LinkSecurity::Unencrypted,
+        }
+    }
+
+    /// Sets the largest `ATT_MTU` the server will offer in an *Exchange MTU* procedure.
+    ///
+    /// `max_mtu` must lie between [`DEFAULT_ATT_MTU`] and [`l2cap::MAX_MTU`], otherwise
+    /// `Error::InvalidValue` is returned. The new value is used starting with the next MTU
+    /// exchange.
+    ///
+    /// ATT PDUs that don't fit in a single Link-Layer data PDU are fragmented, so the TX packet
+    /// queue must be able to hold all fragments of a `max_mtu`-sized PDU at once (see
+    /// [`l2cap::MAX_MTU`]). Otherwise, requests are not processed once a larger `ATT_MTU` was
+    /// negotiated.
+    pub fn set_max_mtu(&mut self, max_mtu: u16) -> Result<(), Error> {
+        if !(DEFAULT_ATT_MTU..=l2cap::MAX_MTU).contains(&max_mtu) {
+            return Err(Error::InvalidValue);
+        }
+
+        self.max_mtu = max_mtu;
+        Ok(())
+    }
+
+    /// Returns the largest `ATT_MTU` the server supports.
+    pub fn max_mtu(&self) -> u16 {
+        self.max_mtu
+    }
+
+    /// Returns the `ATT_MTU` currently in effect.
+    ///
+    /// This is [`DEFAULT_ATT_MTU`] until the client performs an *Exchange MTU* procedure.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Sets the security of the current connection.
+    ///
+    /// This is called by `Responder::encryption_enabled` when the Link-Layer reports
+    /// `LinkEvent::EncryptionEnabled`, with the security reported in `PairingEvent::Complete` for
+    /// the key passed to `LinkLayer::set_encryption_key`. Attributes whose
+    /// [`AttributeSecurity`] requirements exceed `security` are rejected with *Insufficient
+    /// Encryption* or *Insufficient Authentication* errors, prompting the client to pair or to
+    /// enable encryption.
+    ///
+    /// [`AttributeSecurity`]: super::AttributeSecurity
+    pub fn set_link_security(&mut self, security: LinkSecurity) {
+        self.security = security;
+    }
+
+    /// Returns the security of the current connection.
+    pub fn link_security(&self) -> LinkSecurity {
+        self.security
+    }
+
+    /// Resets all per-connection state.
+    ///
+    /// This sets the `ATT_MTU` back to [`DEFAULT_ATT_MTU`], unsubscribes the client from all
+    /// notifications and indications, forgets about unconfirmed indications, discards all prepared
+    /// writes, and marks the connection as unencrypted. It must be called when the connection is
+    /// closed.
+    pub fn reset(&mut self) {
+        self.mtu = DEFAULT_ATT_MTU;
+        self.subscriptions.clear();
+        self.indication_pending = false;
+        self.service_change_sent = None;
+        self.prepare_queue.clear();
+        self.prepare_buf.clear();
+        self.security = LinkSecurity::Unencrypted;
+    }
+
+    /// Returns whether an indication has been sent that wasn't yet confirmed by the client.
+    ///
+    /// No further indications can be sent while this is the case.
+    pub fn indication_pending(&self) -> bool {
+        self.indication_pending
+    }
+
+    /// Returns whether a change of the attribute table has to be indicated to the client.
+    ///
+    /// This is the case if the `AttributeProvider` reports a change (see
+    /// [`AttributeProvider::service_change`]), the client is subscribed to indications of the
+    /// *Service Changed* characteristic, and no other indication is pending. The change can then be
+    /// sent with [`AttributeServerTx::indicate_service_change`].
+    pub fn service_change_pending(&mut self) -> bool {
+        match self.attrs.service_change() {
+            Some(change) => {
+                !self.indication_pending
+                    && self
+                        .client_config(change.characteristic)
+                        .contains(ClientConfig::INDICATE)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the CCCD values written by the client, as pairs of CCCD handle and value.
+    ///
+    /// For bonded clients, the values have to persist across connections. They should be stored
+    /// alongside the bond before the connection state is reset, and be restored with
+    /// [`restore_subscriptions`](Self::restore_subscriptions) when the client reconnects. This
+    /// keeps a bonded client subscribed to *Service Changed*, so changes made while it was
+    /// disconnected are indicated right away.
+    pub fn subscriptions(&self) -> &[(Handle, ClientConfig)] {
+        &self.subscriptions
+    }
+
+    /// Restores CCCD values of a bonded client that were saved from
+    /// [`subscriptions`](Self::subscriptions) during an earlier connection.
+    ///
+    /// Values for handles that are no CCCD are ignored. Returns `Error::Eof` if more than
+    /// [`MAX_SUBSCRIPTIONS`] CCCDs are enabled.
+    pub fn restore_subscriptions(
+        &mut self,
+        subscriptions: &[(Handle, ClientConfig)],
+    ) -> Result<(), Error> {
+        for (handle, config) in subscriptions {
+            if self.is_cccd(*handle) {
+                self.write_cccd(*handle, &config.bits().to_le_bytes())
+                    .map_err(|_| Error::Eof)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the configuration the client has written to the CCCD of the characteristic whose
+    /// value is stored at `value_handle`.
+    ///
+    /// If the characteristic has no CCCD, or the client never wrote it, this returns an empty
+    /// `ClientConfig`.
+    pub fn client_config(&mut self, value_handle: Handle) -> ClientConfig {
+        match self.find_cccd(value_handle) {
+            Some(cccd) => self.cccd_value(cccd),
+            None => ClientConfig::empty(),
+        }
+    }
+
+    /// Returns whether `handle` refers to a Client Characteristic Configuration descriptor.
+    fn is_cccd(&mut self, handle: Handle) -> bool {
+        let mut is_cccd = false;
+        self.attrs
+            .for_attrs_in_range(HandleRange::new(handle, handle), |_, attr| {
+                is_cccd = attr.att_type == CLIENT_CONFIG_UUID;
+                Ok(())
+            })
+            .ok();
+        is_cccd
+    }
+
+    /// Finds the CCCD belonging to the characteristic value at `value_handle`.
+    ///
+    /// Descriptors follow the characteristic value and end at the next declaration.
+    fn find_cccd(&mut self, value_handle: Handle) -> Option<Handle> {
+        let start = Handle::from_raw(value_handle.as_u16().checked_add(1)?);
+        let mut cccd = None;
+        self.attrs
+            .for_attrs_in_range(
+                HandleRange::new(start, Handle::from_raw(0xFFFF)),
+                |_, attr| {
+                    if attr.att_type == CLIENT_CONFIG_UUID {
+                        cccd = Some(attr.handle);
+                    }
+
+                    // Primary/Secondary Service, Include, and Characteristic declarations
+                    let is_declaration =
+                        (0x2800..=0x2803).any(|uuid| attr.att_type == Uuid16(uuid));
+                    if cccd.is_some() || is_declaration {
+                        // Stop iterating
+                        Err(Error::Eof)
+                    } else {
+                        Ok(())
+                    }
+                },
+            )
+            .ok();
+        cccd
+    }
+
+    fn cccd_value(&self, cccd: Handle) -> ClientConfig {
+        self.subscriptions
+            .iter()
+            .find(|(handle, _)| *handle == cccd)
+            .map_or(ClientConfig::empty(), |(_, config)| *config)
+    }
+
+    /// Writes as much of the value of the attribute at `handle` as fits into `writer`.
+    fn write_value(&mut self, handle: Handle, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let mut buffer = [0u8; DYNAMIC_READ_BUFFER_SIZE];
+        if let Some(data_len) = self.attrs.read_attr_dynamic(handle, &mut buffer) {
+            writer.write_slice_truncate(&buffer[..data_len]);
+            Ok(())
+        } else {
+            self.attrs
+                .for_attrs_in_range(HandleRange::new(handle, handle), |_provider, attr| {
+                    writer.write_slice_truncate(attr.value.as_ref());
+                    Ok(())
+                })
+        }
+    }
+
+    /// Checks that the client may read the attribute at `handle` on the current connection.
+    fn check_read(&self, handle: Handle) -> Result<(), AttError> {
+        if !self.attrs.attr_access_permissions(handle).is_readable() {
+            return Err(AttError::new(ErrorCode::ReadNotPermitted, handle));
+        }
+        self.attrs
+            .attr_security(handle)
+            .check_read(self.security)
+            .map_err(|code| AttError::new(code, handle))
+    }
+
+    /// Checks that the client may write the attribute at `handle` on the current connection.
+    fn check_write(&self, handle: Handle) -> Result<(), AttError> {
+        if !self.attrs.attr_access_permissions(handle).is_writeable() {
+            return Err(AttError::new(ErrorCode::WriteNotPermitted, handle));
+        }
+        self.attrs
+            .attr_security(handle)
+            .check_write(self.security)
+            .map_err(|code| AttError::new(code, handle))
+    }
+
+    /// Queues a write to be performed by the next *Execute Write Request*.
+    fn prepare_write(&mut self, handle: Handle, offset: u16, value: &[u8]) -> Result<(), AttError> {
+        self.check_write(handle)?;
+        let full = AttError::new(ErrorCode::PrepareQueueFull, handle);
+        if self.prepare_queue.is_full() {
+            return Err(full);
+        }
+
+        // `value` is shorter than the `ATT_MTU`, which fits in a `u8`
+        self.prepare_buf
+            .extend_from_slice(value)
+            .map_err(|_| full)?;
+        self.prepare_queue
+            .push(PreparedWrite {
+                handle,
+                offset,
+                len: value.len() as u8,
+            })
+            .ok()
+            .unwrap();
+        Ok(())
+    }
+
+    /// Performs all queued writes.
+    ///
+    /// The chunks written to an attribute are joined and passed to `on_write` at once, so the
+    /// attribute is updated with the complete value. Chunks must be contiguous (every chunk must
+    /// start where the previous chunk for the same attribute ended). This is checked for all
+    /// attributes before any of them is written.
+    fn execute_writes(&mut self) -> Result<(), AttError> {
+        let writes = prepared_writes(&self.prepare_queue, &self.prepare_buf);
+        for (i, (write, _)) in writes.clone().enumerate() {
+            let prev = writes
+                .clone()
+                .take(i)
+                .filter(|(w, _)| w.handle == write.handle)
+                .last();
+            if let Some((prev, value)) = prev {
+                if usize::from(prev.offset) + value.len() != usize::from(write.offset) {
+                    return Err(AttError::new(ErrorCode::InvalidOffset, write.handle));
+                }
+            }
+        }
+
+        let mut buf = [0; PREPARE_BUF_SIZE];
+        for (i, (write, _)) in writes.clone().enumerate() {
+            // Each attribute is written when its first chunk is encountered
+            if writes
+                .clone()
+                .take(i)
+                .any(|(w, _)| w.handle == write.handle)
+            {
+                continue;
+            }
+
+            let mut len = 0;
+            for (_, value) in writes
+                .clone()
+                .skip(i)
+                .filter(|(w, _)| w.handle == write.handle)
+            {
+                buf[len..len + value.len()].copy_from_slice(value);
+                len += value.len();
+            }
+
+            self.attrs
+                .on_write(write.handle, write.offset, &buf[..len])
+                .map_err(|code| AttError::new(code, write.handle))?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a client write to the CCCD at `handle`.
+    fn write_cccd(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        let value = match value {
+            [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+            _ => {
+                return Err(AttError::new(
+                    ErrorCode::InvalidAttributeValueLength,
+                    handle,
+                ))
+            }
+        };
+
+        // Reserved bits are ignored
+        let config = ClientConfig::from_bits_truncate(value);
+        let existing = self.subscriptions.iter().position(|(h, _)| *h == handle);
+        match (existing, config.is_empty()) {
+            (Some(i), true) => {
+                self.subscriptions.swap_remove(i);
+            }
+            (Some(i), false) => self.subscriptions[i].1 = config,
+            (None, true) => {}
+            (None, false) => {
+                self.subscriptions
+                    .push((handle, config))
+                    .map_err(|_| AttError::new(ErrorCode::InsufficientResources, handle))?;
+            }
+        }
+
+        debug!("CCCD {:?} = {:?}", handle, config.bits());
+        Ok(())
     }
 
     /// Prepares for performing a server-initiated action (eg. sending a notification/indication).
@@ -27,7 +434,8 @@ impl<A: AttributeProvider> AttributeServer<A> {
     /// available.
     ///
     /// It is usually not necessary to use this function. Instead, call `L2CAPStateTx::att`.
-    pub fn with_sender<'a>(&'a mut self, sender: Sender<'a>) -> AttributeServerTx<'a, A> {
+    pub fn with_sender<'a>(&'a mut self, mut sender: Sender<'a>) -> AttributeServerTx<'a, A> {
+        sender.limit_pdu_size(self.mtu);
         AttributeServerTx {
             server: self,
             sender,
@@ -41,8 +449,24 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
     /// Returns the `ATT_MTU` value, the maximum size of an ATT PDU that can be processed and sent
     /// out by the server.
-    fn att_mtu(&self) -> u8 {
-        Self::RSP_PDU_SIZE
+    fn att_mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Returns whether the attribute provider contains an attribute with the given handle.
+    fn attr_exists(&mut self, handle: Handle) -> bool {
+        if handle == Handle::NULL {
+            return false;
+        }
+
+        let mut exists = false;
+        self.attrs
+            .for_attrs_in_range(HandleRange::new(handle, handle), |_, _| {
+                exists = true;
+                Ok(())
+            })
+            .ok();
+        exists
     }
 
     /// Process an incoming request (or command) PDU and return a response.
@@ -62,7 +486,8 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
         impl From<Error> for RspError {
             fn from(e: Error) -> Self {
-                panic!("unexpected error: {}", e);
+                error!("unexpected error while building ATT response: {}", e);
+                RspError(AttError::new(ErrorCode::UnlikelyError, Handle::NULL))
             }
         }
 
@@ -72,13 +497,36 @@ impl<A: AttributeProvider> AttributeServer<A> {
             }
         }
 
+        // PDUs operating on a single attribute must refer to one that exists
+        let target = match msg {
+            AttPdu::ReadReq { handle }
+            | AttPdu::ReadBlobReq { handle, .. }
+            | AttPdu::WriteReq { handle, .. }
+            | AttPdu::WriteCommand { handle, .. }
+            | AttPdu::PrepareWriteReq { handle, .. } => Some(*handle),
+            _ => None,
+        };
+        if let Some(handle) = target {
+            if !self.attr_exists(handle) {
+                return if msg.opcode().is_command() {
+                    Ok(())
+                } else {
+                    Err(AttError::new(ErrorCode::InvalidHandle, handle))
+                };
+            }
+        }
+
         match msg {
-            AttPdu::ExchangeMtuReq { mtu: _mtu } => {
+            AttPdu::ExchangeMtuReq { mtu: client_mtu } => {
+                // The response is still sent with the old MTU, the new one applies afterwards
                 responder
-                    .send(AttPdu::ExchangeMtuRsp {
-                        mtu: u16::from(Self::RSP_PDU_SIZE),
-                    })
+                    .send(AttPdu::ExchangeMtuRsp { mtu: self.max_mtu })
                     .unwrap();
+
+                // Clients can't go below the default MTU, so treat smaller values as the default
+                let client_mtu = cmp::max(*client_mtu, DEFAULT_ATT_MTU);
+                self.mtu = cmp::min(client_mtu, self.max_mtu);
+                debug!("ATT_MTU is now {}", self.mtu);
                 Ok(())
             }
 
@@ -87,6 +535,7 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 attribute_type,
             } => {
                 let range = handle_range.check()?;
+                let start = range.start();
 
                 let result = responder.send_with(|writer| {
                     // If no attributes match request, return `AttributeNotFound` error, else send
@@ -96,7 +545,9 @@ impl<A: AttributeProvider> AttributeServer<A> {
                     let length = writer.split_next_mut().ok_or(Error::Eof)?;
 
                     let mut size = None;
+                    let mut denied = None;
                     let att_mtu = self.att_mtu();
+                    let security = self.security;
                     self.attrs
                         .for_attrs_in_range(range, |provider, attr| {
                             // "Only attributes that can be read shall be returned in a
@@ -104,13 +555,33 @@ impl<A: AttributeProvider> AttributeServer<A> {
                             if attr.att_type == *attribute_type
                                 && provider.attr_access_permissions(attr.handle).is_readable()
                             {
+                                // If the first matching attribute can't be read on this
+                                // connection, the request fails. Otherwise, the list ends before
+                                // it and the client will request it on its own.
+                                if let Err(code) =
+                                    provider.attr_security(attr.handle).check_read(security)
+                                {
+                                    if size.is_none() {
+                                        denied = Some(AttError::new(code, attr.handle));
+                                    }
+                                    return Err(Error::Eof);
+                                }
+
                                 let data =
                                     ByTypeAttData::new(att_mtu, attr.handle, attr.value.as_ref());
-                                if size == Some(data.encoded_size()) || size.is_none() {
-                                    // Can try to encode `data`. If we run out of space, end the list.
-                                    data.to_bytes(writer)?;
-                                    size = Some(data.encoded_size());
+                                let data_size = data.encoded_size();
+
+                                // All entries must have the same length, and there must be space
+                                // for the whole entry. Otherwise, end the list here, the client
+                                // will request the remaining attributes starting at this one.
+                                if size.is_some_and(|size| size != data_size)
+                                    || writer.space_left() < usize::from(data_size)
+                                {
+                                    return Err(Error::Eof);
                                 }
+
+                                data.to_bytes(writer)?;
+                                size = Some(data_size);
                             }
 
                             Ok(())
@@ -121,8 +592,12 @@ impl<A: AttributeProvider> AttributeServer<A> {
                         // At least one attr
                         *length = size;
                         Ok(())
+                    } else if let Some(error) = denied {
+                        Err(error.into())
                     } else {
-                        Err(AttError::attribute_not_found().into())
+                        // The error refers to the first handle of the requested range
+                        let error = AttError::new(ErrorCode::AttributeNotFound, start);
+                        Err(error.into())
                     }
                 });
 
@@ -197,70 +672,119 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 }
             }
 
-            AttPdu::ReadReq { handle } => {
-                if !self.attrs.attr_access_permissions(*handle).is_readable() {
-                    return Err(AttError::new(ErrorCode::ReadNotPermitted, *handle));
+            AttPdu::FindByTypeValueReq {
+                handle_range,
+                attribute_type,
+                attribute_value,
+            } => {
+                let range = handle_range.check()?;
+                let start = range.start();
+                let attribute_type = AttUuid::from(Uuid16(*attribute_type));
+
+                let result = responder.send_with(|writer| {
+                    // Each entry contains the handle of a matching attribute and the end of its
+                    // group (or the attribute's handle again if it isn't a grouping attribute).
+
+                    writer.write_u8(Opcode::FindByTypeValueRsp.into())?;
+
+                    let mut found = false;
+                    self.attrs
+                        .for_attrs_in_range(range, |provider, attr| {
+                            if attr.att_type == attribute_type
+                                && attr.value.as_ref() == attribute_value.0
+                                && provider.attr_access_permissions(attr.handle).is_readable()
+                            {
+                                if writer.space_left() < 4 {
+                                    // End the list, the client will continue after the last entry
+                                    return Err(Error::Eof);
+                                }
+
+                                let group_end = provider
+                                    .group_end(attr.handle)
+                                    .map_or(attr.handle, |end| end.handle);
+                                attr.handle.to_bytes(writer)?;
+                                group_end.to_bytes(writer)?;
+                                found = true;
+                            }
+
+                            Ok(())
+                        })
+                        .ok();
+
+                    if found {
+                        Ok(())
+                    } else {
+                        Err(AttError::new(ErrorCode::AttributeNotFound, start).into())
+                    }
+                });
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
                 }
+            }
 
+            AttPdu::ReadReq { handle } if self.is_cccd(*handle) => {
+                let value = self.cccd_value(*handle).bits();
                 responder
                     .send_with(|writer| -> Result<(), Error> {
                         writer.write_u8(Opcode::ReadRsp.into())?;
+                        writer.write_u16_le(value)?;
+                        Ok(())
+                    })
+                    .unwrap();
+                Ok(())
+            }
 
-                        let mut buffer = [0u8; DYNAMIC_READ_BUFFER_SIZE];
-                        if let Some(data_len) = self.attrs.read_attr_dynamic(*handle, &mut buffer) {
-                            let value = &buffer[..data_len];
-                            writer.write_slice_truncate(value);
-                        } else {
-                            self.attrs.for_attrs_in_range(
-                                HandleRange::new(*handle, *handle),
-                                |_provider, attr| {
-                                    let value = &attr.value.as_ref();
-                                    writer.write_slice_truncate(value);
-                                    Ok(())
-                                },
-                            )?;
-                        }
+            AttPdu::ReadReq { handle } => {
+                self.check_read(*handle)?;
 
-                        Ok(())
+                responder
+                    .send_with(|writer| -> Result<(), Error> {
+                        writer.write_u8(Opcode::ReadRsp.into())?;
+                        self.write_value(*handle, writer)
                     })
                     .unwrap();
 
                 Ok(())
             }
 
-            AttPdu::ReadBlobReq { handle, offset } => {
-                if !self.attrs.attr_access_permissions(*handle).is_readable() {
-                    return Err(AttError::new(ErrorCode::ReadNotPermitted, *handle));
+            AttPdu::ReadMultipleReq { handles } => {
+                // The request must contain at least 2 handles
+                let handles = handles.as_ref();
+                if handles.len() < 4 || handles.len() % 2 != 0 {
+                    return Err(AttError::new(ErrorCode::InvalidPdu, Handle::NULL));
                 }
+                let handles = || {
+                    handles
+                        .chunks_exact(2)
+                        .map(|raw| Handle::from_raw(u16::from_le_bytes([raw[0], raw[1]])))
+                };
 
+                // If any attribute can't be read, the error refers to the first one and no values
+                // are sent
+                for handle in handles() {
+                    if !self.attr_exists(handle) {
+                        return Err(AttError::new(ErrorCode::InvalidHandle, handle));
+                    }
+                    if !self.is_cccd(handle) {
+                        self.check_read(handle)?;
+                    }
+                }
+
+                // The values are simply concatenated, so the client must know their lengths.
+                // Whatever doesn't fit in the response is cut off.
                 responder
                     .send_with(|writer| -> Result<(), Error> {
-                        writer.write_u8(Opcode::ReadBlobRsp.into())?;
-
-                        let mut buffer = [0u8; DYNAMIC_READ_BUFFER_SIZE];
-                        if let Some(data_len) = self.attrs.read_attr_dynamic(*handle, &mut buffer) {
-                            let offset = *offset as usize;
-                            let slice = &buffer[..data_len];
-                            let slice = &slice[offset..];
-
-                            let value = slice.as_ref();
-
-                            writer.write_slice_truncate(value);
-                        } else {
-                            self.attrs.for_attrs_in_range(
-                                HandleRange::new(*handle, *handle),
-                                |_provider, attr| {
-                                    let value = attr.value.as_ref();
-                                    let offset = *offset as usize;
-                                    let slice = &value[offset..];
-
-                                    writer.write_slice_truncate(slice);
-
-                                    Ok(())
-                                },
-                            )?;
+                        writer.write_u8(Opcode::ReadMultipleRsp.into())?;
+                        for handle in handles() {
+                            if self.is_cccd(handle) {
+                                let value = self.cccd_value(handle).bits().to_le_bytes();
+                                writer.write_slice_truncate(&value);
+                            } else {
+                                self.write_value(handle, writer)?;
+                            }
                         }
-
                         Ok(())
                     })
                     .unwrap();
@@ -268,39 +792,90 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 Ok(())
             }
 
-            AttPdu::WriteReq { value, handle } => {
-                if self.attrs.attr_access_permissions(*handle).is_writeable() {
-                    self.attrs
-                        .write_attr(*handle, value.as_ref())
-                        .map_err(|err| {
-                            // Convert rubble::Error to AttError
-                            AttError::new(
-                                match err {
-                                    Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
-                                    _ => ErrorCode::UnlikelyError,
-                                },
-                                *handle,
-                            )
-                        })?;
-                    responder
-                        .send_with(|writer| -> Result<(), Error> {
-                            writer.write_u8(Opcode::WriteRsp.into())?;
-                            Ok(())
-                        })
-                        .map_err(|err| error!("error while handling write request: {:?}", err))
-                        .ok();
-                    Ok(())
-                } else {
-                    Err(AttError::new(ErrorCode::WriteNotPermitted, *handle))
+            AttPdu::ReadBlobReq { handle, offset } => {
+                self.check_read(*handle)?;
+
+                let offset = usize::from(*offset);
+                let result = responder.send_with(|writer| -> Result<(), RspError> {
+                    writer.write_u8(Opcode::ReadBlobRsp.into())?;
+
+                    // Write as much of the value after `offset` as fits in the response
+                    let mut in_bounds = true;
+                    let mut buffer = [0u8; DYNAMIC_READ_BUFFER_SIZE];
+                    if let Some(data_len) = self.attrs.read_attr_dynamic(*handle, &mut buffer) {
+                        match buffer[..data_len].get(offset..) {
+                            Some(rest) => writer.write_slice_truncate(rest),
+                            None => {
+                                in_bounds = false;
+                                0
+                            }
+                        };
+                    } else {
+                        self.attrs.for_attrs_in_range(
+                            HandleRange::new(*handle, *handle),
+                            |_provider, attr| {
+                                match attr.value.as_ref().get(offset..) {
+                                    Some(rest) => writer.write_slice_truncate(rest),
+                                    None => {
+                                        in_bounds = false;
+                                        0
+                                    }
+                                };
+                                Ok(())
+                            },
+                        )?;
+                    }
+
+                    if in_bounds {
+                        Ok(())
+                    } else {
+                        Err(AttError::new(ErrorCode::InvalidOffset, *handle).into())
+                    }
+                });
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
                 }
             }
+
+            AttPdu::WriteReq { value, handle } if self.is_cccd(*handle) => {
+                self.write_cccd(*handle, value.as_ref())?;
+                responder
+                    .send_with(|writer| -> Result<(), Error> {
+                        writer.write_u8(Opcode::WriteRsp.into())?;
+                        Ok(())
+                    })
+                    .map_err(|err| error!("error while handling write request: {:?}", err))
+                    .ok();
+                Ok(())
+            }
+
+            AttPdu::WriteReq { value, handle } => {
+                self.check_write(*handle)?;
+                self.attrs
+                    .on_write(*handle, 0, value.as_ref())
+                    .map_err(|code| AttError::new(code, *handle))?;
+                responder
+                    .send_with(|writer| -> Result<(), Error> {
+                        writer.write_u8(Opcode::WriteRsp.into())?;
+                        Ok(())
+                    })
+                    .map_err(|err| error!("error while handling write request: {:?}", err))
+                    .ok();
+                Ok(())
+            }
             AttPdu::WriteCommand { handle, value } => {
                 // WriteCommand shouldn't respond to the client even on failure
-                if self.attrs.attr_access_permissions(*handle).is_writeable() {
-                    self.attrs
-                        .write_attr(*handle, value.as_ref())
+                if self.is_cccd(*handle) {
+                    self.write_cccd(*handle, value.as_ref())
                         .map_err(|err| error!("error while handling write command: {:?}", err))
                         .ok();
+                } else if self.check_write(*handle).is_ok() {
+                    self.attrs
+                        .on_write(*handle, 0, value.as_ref())
+                        .map_err(|code| error!("error while handling write command: {:?}", code))
+                        .ok();
                 }
                 Ok(())
             }
@@ -310,46 +885,33 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 offset,
                 value,
             } => {
-                if self.attrs.attr_access_permissions(*handle).is_writeable() {
-                    self.attrs
-                        .prepare_write_attr(*handle, *offset, value.as_ref())
-                        .map_err(|err| {
-                            // Convert rubble::Error to AttError
-                            AttError::new(
-                                match err {
-                                    Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
-                                    _ => ErrorCode::UnlikelyError,
-                                },
-                                *handle,
-                            )
-                        })?;
-                    responder
-                        .send_with(|writer| -> Result<(), Error> {
-                            writer.write_u8(Opcode::PrepareWriteRsp.into())?;
-                            writer.write_u16_le(handle.as_u16())?;
-                            writer.write_u16_le(*offset)?;
-                            writer.write_slice(value.as_ref())?;
-                            Ok(())
-                        })
-                        .map_err(|err| error!("error while handling write request: {:?}", err))
-                        .ok();
-                    Ok(())
-                } else {
-                    Err(AttError::new(ErrorCode::WriteNotPermitted, *handle))
-                }
+                self.prepare_write(*handle, *offset, value.as_ref())?;
+
+                // The response echoes the request, so the client can verify it was received intact
+                responder
+                    .send_with(|writer| -> Result<(), Error> {
+                        writer.write_u8(Opcode::PrepareWriteRsp.into())?;
+                        writer.write_u16_le(handle.as_u16())?;
+                        writer.write_u16_le(*offset)?;
+                        writer.write_slice(value.as_ref())?;
+                        Ok(())
+                    })
+                    .map_err(|err| error!("error while handling write request: {:?}", err))
+                    .ok();
+                Ok(())
             }
 
             AttPdu::ExecuteWriteReq { flags } => {
-                self.attrs.execute_write_attr(*flags).map_err(|err| {
-                    // Convert rubble::Error to AttError
-                    AttError::new(
-                        match err {
-                            Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
-                            _ => ErrorCode::UnlikelyError,
-                        },
-                        Handle::NULL,
-                    )
-                })?;
+                // 0x00 cancels all prepared writes, 0x01 writes them
+                let result = match flags {
+                    0x00 => Ok(()),
+                    0x01 => self.execute_writes(),
+                    _ => Err(AttError::new(ErrorCode::InvalidPdu, Handle::NULL)),
+                };
+                self.prepare_queue.clear();
+                self.prepare_buf.clear();
+                result?;
+
                 responder
                     .send_with(|writer| -> Result<(), Error> {
                         writer.write_u8(Opcode::ExecuteWriteRsp.into())?;
@@ -362,18 +924,47 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
             AttPdu::FindInformationReq { handle_range } => {
                 let range = handle_range.check()?;
-                self.attrs
-                    .find_information(range, responder)
-                    .map_err(|err| {
-                        // Convert rubble::Error to AttError
-                        AttError::new(
-                            match err {
-                                Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
-                                _ => ErrorCode::UnlikelyError,
-                            },
-                            Handle::NULL,
-                        )
-                    })
+                let start = range.start();
+
+                let result = responder.send_with(|writer| {
+                    // All entries in a response must use the same UUID size, which is determined by
+                    // the first attribute in the range. The client will request the remaining
+                    // attributes in another request.
+
+                    writer.write_u8(Opcode::FindInformationRsp.into())?;
+                    let format = writer.split_next_mut().ok_or(Error::Eof)?;
+
+                    let mut uuid128 = None;
+                    self.attrs
+                        .for_attrs_in_range(range, |_provider, attr| {
+                            let is_128 = matches!(attr.att_type, AttUuid::Uuid128(_));
+                            let size = if is_128 { 2 + 16 } else { 2 + 2 };
+                            if *uuid128.get_or_insert(is_128) != is_128
+                                || writer.space_left() < size
+                            {
+                                // End the list
+                                return Err(Error::Eof);
+                            }
+
+                            attr.handle.to_bytes(writer)?;
+                            attr.att_type.to_bytes(writer)?;
+                            Ok(())
+                        })
+                        .ok();
+
+                    match uuid128 {
+                        Some(is_128) => {
+                            *format = if is_128 { 0x02 } else { 0x01 };
+                            Ok(())
+                        }
+                        None => Err(AttError::new(ErrorCode::AttributeNotFound, start).into()),
+                    }
+                });
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
+                }
             }
 
             // Responses are always invalid here
@@ -394,12 +985,20 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 Err(AttError::new(ErrorCode::InvalidPdu, Handle::NULL))
             }
 
+            AttPdu::HandleValueConfirmation => {
+                // Confirmations are not answered
+                if !self.indication_pending {
+                    warn!("ATT: unexpected Handle Value Confirmation");
+                }
+                self.indication_pending = false;
+                if let Some(change) = self.service_change_sent.take() {
+                    self.attrs.service_change_indicated(change);
+                }
+                Ok(())
+            }
+
             // Unknown (undecoded) or unimplemented requests and commands
-            AttPdu::Unknown { .. }
-            | AttPdu::FindByTypeValueReq { .. }
-            | AttPdu::ReadMultipleReq { .. }
-            | AttPdu::SignedWriteCommand { .. }
-            | AttPdu::HandleValueConfirmation { .. } => {
+            AttPdu::Unknown { .. } | AttPdu::SignedWriteCommand { .. } => {
                 if msg.opcode().is_command() {
                     // According to the spec, unknown Command PDUs should be ignored
                     Ok(())
@@ -414,7 +1013,27 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
 impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
     fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
-        let pdu = &AttPdu::from_bytes(&mut ByteReader::new(message))?;
+        responder.limit_pdu_size(self.mtu);
+        let pdu = &match AttPdu::from_bytes(&mut ByteReader::new(message)) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                // Malformed requests are rejected, malformed commands are ignored
+                let opcode = match message.first() {
+                    Some(&raw) => Opcode::from(raw),
+                    None => return Err(e),
+                };
+                if opcode.is_command() {
+                    return Err(e);
+                }
+
+                debug!("ATT<- malformed {:?} ({:?})", opcode, e);
+                return responder.send(AttPdu::ErrorRsp {
+                    opcode,
+                    handle: Handle::NULL,
+                    error_code: ErrorCode::InvalidPdu,
+                });
+            }
+        };
         let opcode = pdu.opcode();
         debug!("ATT<- {:?}", pdu);
 
@@ -434,8 +1053,12 @@ impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
 }
 
 impl<A: AttributeProvider> Protocol for AttributeServer<A> {
-    // FIXME: Would it be useful to have this as a runtime parameter instead?
-    const RSP_PDU_SIZE: u8 = 23;
+    const RSP_PDU_SIZE: u8 = DEFAULT_ATT_MTU as u8;
+
+    // Responses can be as large as the negotiated `ATT_MTU`
+    fn rsp_pdu_size(&self) -> u8 {
+        self.mtu as u8
+    }
 }
 
 /// An ATT server handle that can send packets and initiate actions.
@@ -443,7 +1066,6 @@ impl<A: AttributeProvider> Protocol for AttributeServer<A> {
 /// This type is needed for any server-initiated procedure, where the server sends out a packet on
 /// its own instead of reacting to a client packet.
 pub struct AttributeServerTx<'a, A: AttributeProvider> {
-    #[allow(unused)]
     server: &'a mut AttributeServer<A>,
 
     sender: Sender<'a>,
@@ -456,7 +1078,8 @@ impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
     ///
     /// If `value` is too large to be transmitted in a single `ATT_MTU`, it will be truncated to
     /// fit. A client may fetch the rest of the truncated value by using a *Read Blob Request*.
-    /// If this is unwanted, only notify with a `value` of 19 Bytes or less.
+    /// If this is unwanted, only notify with a `value` of `ATT_MTU - 3` Bytes or less (20 Bytes
+    /// with the default MTU).
     pub fn notify_raw(mut self, handle: Handle, value: &[u8]) {
         // This cannot fail. The `self` guarantees that there's `RSP_PDU_SIZE` bytes free in
         // `sender`, and is consumed by this method. `AttPdu`s encoder will truncate `value` to fit
@@ -468,4 +1091,951 @@ impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
             })
             .unwrap()
     }
+
+    /// Sends a notification if the client has subscribed to it.
+    ///
+    /// `handle` is the handle of the characteristic value. Notifications are only sent when the
+    /// client has enabled them in the characteristic's Client Characteristic Configuration
+    /// descriptor.
+    ///
+    /// Returns `Ok(true)` if the notification was sent, and `Ok(false)` if the client isn't
+    /// subscribed to notifications. If `value` doesn't fit in a single notification (more than
+    /// `ATT_MTU - 3` Bytes), returns `Error::InvalidLength`.
+    pub fn notify(mut self, handle: Handle, value: &[u8]) -> Result<bool, Error> {
+        self.check_value_len(value)?;
+        if !self
+            .server
+            .client_config(handle)
+            .contains(ClientConfig::NOTIFY)
+        {
+            return Ok(false);
+        }
+
+        self.sender.send(AttPdu::HandleValueNotification {
+            handle,
+            value: HexSlice(value),
+        })?;
+        Ok(true)
+    }
+
+    /// Sends an indication if the client has subscribed to it.
+    ///
+    /// Indications work like notifications, except that they are confirmed by the client. Only a
+    /// single indication can be outstanding, so this returns `Ok(false)` without sending anything
+    /// while the last indication hasn't been confirmed yet (see
+    /// [`AttributeServer::indication_pending`]), or when the client isn't subscribed to
+    /// indications.
+    ///
+    /// If the client doesn't confirm the indication within [`ATT_TRANSACTION_TIMEOUT`], the
+    /// Link-Layer closes the connection.
+    ///
+    /// If `value` doesn't fit in a single indication (more than `ATT_MTU - 3` Bytes), returns
+    /// `Error::InvalidLength`.
+    pub fn indicate(mut self, handle: Handle, value: &[u8]) -> Result<bool, Error> {
+        self.check_value_len(value)?;
+        if self.server.indication_pending
+            || !self
+                .server
+                .client_config(handle)
+                .contains(ClientConfig::INDICATE)
+        {
+            return Ok(false);
+        }
+
+        self.sender.send(AttPdu::HandleValueIndication {
+            handle,
+            value: HexSlice(value),
+        })?;
+        self.server.indication_pending = true;
+        Ok(true)
+    }
+
+    /// Indicates a change of the attribute table to the client, if one is pending.
+    ///
+    /// The handle range reported by the `AttributeProvider` is sent as the value of the *Service
+    /// Changed* characteristic. Returns `Ok(true)` if the indication was sent, and `Ok(false)` if
+    /// [`AttributeServer::service_change_pending`] is `false`. The `Responder` calls this
+    /// automatically.
+    pub fn indicate_service_change(mut self) -> Result<bool, Error> {
+        if !self.server.service_change_pending() {
+            return Ok(false);
+        }
+
+        let change = self.server.attrs.service_change().unwrap();
+        let mut value = [0; 4];
+        value[..2].copy_from_slice(&change.start.as_u16().to_le_bytes());
+        value[2..].copy_from_slice(&change.end.as_u16().to_le_bytes());
+        self.sender.send(AttPdu::HandleValueIndication {
+            handle: change.characteristic,
+            value: HexSlice(&value),
+        })?;
+        self.server.indication_pending = true;
+        self.server.service_change_sent = Some(change);
+        Ok(true)
+    }
+
+    fn check_value_len(&self, value: &[u8]) -> Result<(), Error> {
+        // 1 Byte opcode, 2 Bytes handle
+        if value.len() > usize::from(self.server.mtu - 3) {
+            Err(Error::InvalidLength)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::att::{
+        AttUuid, Attribute, AttributeAccessPermissions, AttributeSecurity, NoAttributes,
+    };
+    use crate::gatt::characteristic::{declaration_value128, declaration_value16, Properties};
+    use crate::gatt::server::GattServerBuilder;
+    use crate::gatt::{BatteryServiceAttrs, MidiServiceAttrs};
+    use crate::l2cap::{BleChannelMap, ChannelMapper, L2CAPState};
+    use crate::link::data::Llid;
+    use crate::link::queue::{
+        ArrayQueue, Consume, Consumer, PacketQueue, SimpleConsumer, SimpleProducer, SimpleQueue,
+    };
+    use crate::uuid::Uuid128;
+
+    /// Sends an ATT PDU from the client to the server.
+    fn send<M: ChannelMapper>(l2cap: &mut L2CAPState<M>, tx: &mut SimpleProducer<'_>, pdu: &[u8]) {
+        let mut message = [0; 27];
+        message[..2].copy_from_slice(&(pdu.len() as u16).to_le_bytes());
+        message[2..4].copy_from_slice(&[0x04, 0x00]);
+        message[4..4 + pdu.len()].copy_from_slice(pdu);
+        l2cap
+            .tx(tx)
+            .process_start(&message[..4 + pdu.len()])
+            .into_result()
+            .unwrap();
+    }
+
+    /// Receives the raw ATT PDU sent by the server.
+    fn recv(rx: &mut SimpleConsumer<'_>) -> std::vec::Vec<u8> {
+        rx.consume_raw_with(|_, raw| Consume::always(Ok(raw[4..].to_vec())))
+            .unwrap()
+    }
+
+    #[test]
+    fn exchange_mtu() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+
+        let server = l2cap.channel_mapper().att().into_protocol();
+        assert_eq!(
+            server.set_max_mtu(DEFAULT_ATT_MTU - 1),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            server.set_max_mtu(l2cap::MAX_MTU + 1),
+            Err(Error::InvalidValue)
+        );
+        server.set_max_mtu(l2cap::MAX_MTU).unwrap();
+        assert_eq!(server.mtu(), DEFAULT_ATT_MTU);
+
+        // L2CAP header (length 3, channel 0x0004) + ATT_EXCHANGE_MTU_REQ with a client MTU of 247
+        l2cap
+            .tx(&mut tx)
+            .process_start(&[3, 0, 0x04, 0x00, 0x02, 247, 0])
+            .into_result()
+            .unwrap();
+
+        rx.consume_raw_with(|_, raw| -> Consume<()> {
+            let rsp = &raw[4..];
+            let pdu = AttPdu::from_bytes(&mut ByteReader::new(rsp)).unwrap();
+            match pdu {
+                AttPdu::ExchangeMtuRsp { mtu } => assert_eq!(mtu, l2cap::MAX_MTU),
+                _ => panic!("unexpected response {:?}", pdu),
+            }
+            Consume::always(Ok(()))
+        })
+        .unwrap();
+
+        // The smaller of both MTUs is used
+        let server: &mut AttributeServer<NoAttributes> =
+            l2cap.channel_mapper().att().into_protocol();
+        assert_eq!(server.mtu(), cmp::min(247, l2cap::MAX_MTU));
+
+        server.reset();
+        assert_eq!(server.mtu(), DEFAULT_ATT_MTU);
+    }
+
+    #[test]
+    fn large_mtu_fragmented() {
+        let mut queue = ArrayQueue::<4>::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(Long::new()));
+        let server = l2cap.channel_mapper().att().into_protocol();
+        server.set_max_mtu(50).unwrap();
+
+        // The client asks for an MTU of 247, the server's smaller MTU is used
+        l2cap
+            .tx(&mut tx)
+            .process_start(&[3, 0, 0x04, 0x00, 0x02, 247, 0])
+            .into_result()
+            .unwrap();
+        let rsp = rx.consume_raw_with(|_, raw| Consume::always(Ok(raw.to_vec())));
+        assert_eq!(rsp.unwrap(), [3, 0, 0x04, 0x00, 0x03, 50, 0]);
+        assert_eq!(l2cap.channel_mapper().att().into_protocol().mtu(), 50);
+
+        // The whole 40-Byte value is read in a response that is split into 2 data PDUs
+        l2cap
+            .tx(&mut tx)
+            .process_start(&[3, 0, 0x04, 0x00, 0x0A, 0x01, 0x00])
+            .into_result()
+            .unwrap();
+        let mut fragments = std::vec::Vec::new();
+        while let Ok(fragment) =
+            rx.consume_raw_with(|header, raw| Consume::always(Ok((header.llid(), raw.to_vec()))))
+        {
+            fragments.push(fragment);
+        }
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0].0, Llid::DataStart);
+        assert_eq!(fragments[0].1[..5], [41, 0, 0x04, 0x00, 0x0B]);
+        assert_eq!(fragments[1].0, Llid::DataCont);
+        let value: std::vec::Vec<u8> = fragments[0].1[5..]
+            .iter()
+            .chain(&fragments[1].1)
+            .copied()
+            .collect();
+        assert_eq!(value, Long::new().attr.value);
+    }
+
+    #[test]
+    fn notify_and_indicate() {
+        // Characteristic value at 0x0003, CCCD at 0x0004
+        let value = Handle::from_raw(0x0003);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+
+        // Not subscribed yet
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().notify(value, &[1]),
+            Ok(false)
+        );
+        assert!(!rx.has_data());
+
+        // Subscribe to notifications
+        send(&mut l2cap, &mut tx, &[0x12, 0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(recv(&mut rx), [0x13]);
+        send(&mut l2cap, &mut tx, &[0x0A, 0x04, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 0x01, 0x00]);
+
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().notify(value, &[1, 2, 3]),
+            Ok(true)
+        );
+        assert_eq!(recv(&mut rx), [0x1B, 0x03, 0x00, 1, 2, 3]);
+
+        // Values must fit in the MTU
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().notify(value, &[0; 21]),
+            Err(Error::InvalidLength)
+        );
+
+        // Switch to indications
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().indicate(value, &[1]),
+            Ok(false)
+        );
+        send(&mut l2cap, &mut tx, &[0x12, 0x04, 0x00, 0x02, 0x00]);
+        assert_eq!(recv(&mut rx), [0x13]);
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().notify(value, &[1]),
+            Ok(false)
+        );
+
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().indicate(value, &[4]),
+            Ok(true)
+        );
+        assert_eq!(recv(&mut rx), [0x1D, 0x03, 0x00, 4]);
+
+        // No new indication until the last one was confirmed
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().indicate(value, &[5]),
+            Ok(false)
+        );
+        send(&mut l2cap, &mut tx, &[0x1E]);
+        assert!(!rx.has_data());
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().indicate(value, &[5]),
+            Ok(true)
+        );
+        assert_eq!(recv(&mut rx), [0x1D, 0x03, 0x00, 5]);
+        send(&mut l2cap, &mut tx, &[0x1E]);
+        let server = l2cap.channel_mapper().att().into_protocol();
+        assert!(!server.indication_pending());
+
+        // Disconnecting unsubscribes
+        let server = l2cap.channel_mapper().att().into_protocol();
+        server.reset();
+        assert!(!server.indication_pending());
+        assert_eq!(server.client_config(value), ClientConfig::empty());
+    }
+
+    /// A single writable attribute that only accepts 1-Byte values.
+    struct Writable {
+        attr: Attribute<[u8; 1]>,
+    }
+
+    impl AttributeProvider for Writable {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            if range.contains(self.attr.handle) {
+                f(self, &self.attr)?;
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+            None
+        }
+
+        fn attr_access_permissions(&self, _handle: Handle) -> AttributeAccessPermissions {
+            AttributeAccessPermissions::ReadableAndWriteable
+        }
+
+        fn on_write(
+            &mut self,
+            _handle: Handle,
+            offset: u16,
+            value: &[u8],
+        ) -> Result<(), ErrorCode> {
+            assert_eq!(offset, 0);
+            match value {
+                [byte] => {
+                    self.attr.set_value([*byte]);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::InvalidAttributeValueLength),
+            }
+        }
+    }
+
+    #[test]
+    fn notify_backpressure() {
+        let value = Handle::from_raw(0x0003);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+
+        send(&mut l2cap, &mut tx, &[0x12, 0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(recv(&mut rx), [0x13]);
+
+        // The queue only has room for a single packet, further notifications are rejected until
+        // it is drained
+        assert_eq!(l2cap.tx(&mut tx).notify(value, &[1]), Ok(true));
+        assert_eq!(
+            l2cap.tx(&mut tx).notify(value, &[2]),
+            Err(Error::WouldBlock)
+        );
+        assert_eq!(recv(&mut rx), [0x1B, 0x03, 0x00, 1]);
+        assert_eq!(l2cap.tx(&mut tx).notify(value, &[2]), Ok(true));
+        assert_eq!(recv(&mut rx), [0x1B, 0x03, 0x00, 2]);
+    }
+
+    #[test]
+    fn write_read_only() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(BatteryServiceAttrs::new()));
+
+        // Write Request to the Battery Level (0x0003)
+        send(&mut l2cap, &mut tx, &[0x12, 0x03, 0x00, 50]);
+        // Error Response: Write Request, handle 0x0003, Write Not Permitted
+        assert_eq!(recv(&mut rx), [0x01, 0x12, 0x03, 0x00, 0x03]);
+
+        // Write Commands are never answered
+        send(&mut l2cap, &mut tx, &[0x52, 0x03, 0x00, 50]);
+        assert!(!rx.has_data());
+    }
+
+    #[test]
+    fn security_requirements() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut builder = GattServerBuilder::<4>::new();
+        builder.primary_service(Uuid16(0x180F).into()).unwrap();
+        let level = builder
+            .characteristic(
+                Uuid16(0x2A19).into(),
+                Properties::READ | Properties::WRITE,
+                &[100],
+            )
+            .unwrap();
+        builder
+            .require_security(
+                level.value,
+                AttributeSecurity::READ_ENCRYPTED | AttributeSecurity::WRITE_AUTHENTICATED,
+            )
+            .unwrap();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(builder.build()));
+
+        // Error Response: Read Request, handle 0x0003, Insufficient Encryption
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0A, 0x03, 0x00, 0x0F]);
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x19, 0x2A],
+        );
+        assert_eq!(recv(&mut rx), [0x01, 0x08, 0x03, 0x00, 0x0F]);
+        // Error Response: Write Request, handle 0x0003, Insufficient Authentication
+        send(&mut l2cap, &mut tx, &[0x12, 0x03, 0x00, 50]);
+        assert_eq!(recv(&mut rx), [0x01, 0x12, 0x03, 0x00, 0x05]);
+
+        // Unauthenticated encryption allows reads, but not writes
+        l2cap.encryption_enabled(LinkSecurity::Encrypted);
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 100]);
+        send(&mut l2cap, &mut tx, &[0x52, 0x03, 0x00, 50]);
+        assert!(!rx.has_data());
+        send(&mut l2cap, &mut tx, &[0x12, 0x03, 0x00, 50]);
+        assert_eq!(recv(&mut rx), [0x01, 0x12, 0x03, 0x00, 0x05]);
+
+        l2cap.encryption_enabled(LinkSecurity::Authenticated);
+        send(&mut l2cap, &mut tx, &[0x12, 0x03, 0x00, 50]);
+        assert_eq!(recv(&mut rx), [0x13]);
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 50]);
+
+        // The connection is unencrypted again after disconnecting
+        l2cap.connection_closed();
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0A, 0x03, 0x00, 0x0F]);
+    }
+
+    #[test]
+    fn write_callback() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let attr = Attribute::new(Uuid16(0x2A19).into(), Handle::from_raw(0x0001), [0]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(Writable { attr }));
+
+        send(&mut l2cap, &mut tx, &[0x12, 0x01, 0x00, 42]);
+        assert_eq!(recv(&mut rx), [0x13]);
+        assert_eq!(
+            l2cap.channel_mapper().attribute_provider().attr.value(),
+            [42]
+        );
+
+        // The callback's error code is sent back
+        send(&mut l2cap, &mut tx, &[0x12, 0x01, 0x00, 1, 2]);
+        assert_eq!(recv(&mut rx), [0x01, 0x12, 0x01, 0x00, 0x0D]);
+
+        send(&mut l2cap, &mut tx, &[0x52, 0x01, 0x00, 7]);
+        assert!(!rx.has_data());
+        assert_eq!(
+            l2cap.channel_mapper().attribute_provider().attr.value(),
+            [7]
+        );
+    }
+
+    /// A 40-Byte attribute that counts how often it was written.
+    struct Long {
+        attr: Attribute<[u8; 40]>,
+        writes: usize,
+    }
+
+    impl Long {
+        fn new() -> Self {
+            let mut value = [0; 40];
+            for (i, b) in value.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+            Self {
+                attr: Attribute::new(Uuid16(0x2A00).into(), Handle::from_raw(0x0001), value),
+                writes: 0,
+            }
+        }
+    }
+
+    impl AttributeProvider for Long {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            if range.contains(self.attr.handle) {
+                f(self, &self.attr)?;
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+            None
+        }
+
+        fn attr_access_permissions(&self, _handle: Handle) -> AttributeAccessPermissions {
+            AttributeAccessPermissions::ReadableAndWriteable
+        }
+
+        fn on_write(
+            &mut self,
+            _handle: Handle,
+            offset: u16,
+            value: &[u8],
+        ) -> Result<(), ErrorCode> {
+            let offset = usize::from(offset);
+            if offset + value.len() > 40 {
+                return Err(ErrorCode::InvalidAttributeValueLength);
+            }
+            self.attr.value[offset..offset + value.len()].copy_from_slice(value);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_blob() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(Long::new()));
+        let value = Long::new().attr.value;
+
+        // The first blob fills the whole response (ATT_MTU - 1 Bytes)
+        send(&mut l2cap, &mut tx, &[0x0C, 0x01, 0x00, 0, 0]);
+        let rsp = recv(&mut rx);
+        assert_eq!(rsp[0], 0x0D);
+        assert_eq!(rsp[1..], value[..22]);
+
+        send(&mut l2cap, &mut tx, &[0x0C, 0x01, 0x00, 22, 0]);
+        let rsp = recv(&mut rx);
+        assert_eq!(rsp[0], 0x0D);
+        assert_eq!(rsp[1..], value[22..]);
+
+        // Reading at the end yields an empty value, reading past it is an error
+        send(&mut l2cap, &mut tx, &[0x0C, 0x01, 0x00, 40, 0]);
+        assert_eq!(recv(&mut rx), [0x0D]);
+        send(&mut l2cap, &mut tx, &[0x0C, 0x01, 0x00, 41, 0]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0C, 0x01, 0x00, 0x07]);
+    }
+
+    #[test]
+    fn read_multiple() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut builder = GattServerBuilder::<9>::new();
+        builder.primary_service(Uuid16(0x1800).into()).unwrap();
+        for (uuid, properties, value) in [
+            (0x2A00, Properties::READ, &b"ABCD"[..]),
+            (0x2A01, Properties::READ, b"EF"),
+            (0x2A02, Properties::READ, b"0123456789abcdefghij"),
+            (0x2A03, Properties::WRITE, b"-"),
+        ] {
+            builder
+                .characteristic(Uuid16(uuid).into(), properties, value)
+                .unwrap();
+        }
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(builder.build()));
+
+        // Values 0x0003, 0x0005 and 0x0007 are concatenated and cut off after ATT_MTU - 1 Bytes
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x0E, 0x03, 0x00, 0x05, 0x00, 0x07, 0x00],
+        );
+        let rsp = recv(&mut rx);
+        assert_eq!(rsp[0], 0x0F);
+        assert_eq!(&rsp[1..7], b"ABCDEF");
+        assert_eq!(&rsp[7..], b"0123456789abcdef");
+
+        send(&mut l2cap, &mut tx, &[0x0E, 0x05, 0x00, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), b"\x0FEFABCD");
+
+        // Errors refer to the first handle that can't be read: Invalid Handle, Read Not Permitted
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x0E, 0x03, 0x00, 0x10, 0x00, 0x09, 0x00],
+        );
+        assert_eq!(recv(&mut rx), [0x01, 0x0E, 0x10, 0x00, 0x01]);
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x0E, 0x03, 0x00, 0x09, 0x00, 0x10, 0x00],
+        );
+        assert_eq!(recv(&mut rx), [0x01, 0x0E, 0x09, 0x00, 0x02]);
+
+        // At least 2 handles are required: Invalid PDU
+        send(&mut l2cap, &mut tx, &[0x0E, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0E, 0x00, 0x00, 0x04]);
+    }
+
+    #[test]
+    fn prepared_write() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(Long::new()));
+
+        // Prepare 2 chunks: 18 Bytes at offset 0, 2 Bytes at offset 18
+        let mut req = [0xAA; 23];
+        req[..5].copy_from_slice(&[0x16, 0x01, 0x00, 0, 0]);
+        send(&mut l2cap, &mut tx, &req);
+        let rsp = recv(&mut rx);
+        assert_eq!(rsp[0], 0x17);
+        assert_eq!(rsp[1..], req[1..]);
+        send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, 18, 0, 0xBB, 0xBB]);
+        assert_eq!(recv(&mut rx), [0x17, 0x01, 0x00, 18, 0, 0xBB, 0xBB]);
+
+        // Nothing is written before the queue is executed
+        let attrs = l2cap.channel_mapper().attribute_provider();
+        assert_eq!(attrs.writes, 0);
+        assert_eq!(attrs.attr.value, Long::new().attr.value);
+
+        send(&mut l2cap, &mut tx, &[0x18, 0x01]);
+        assert_eq!(recv(&mut rx), [0x19]);
+
+        // Both chunks were written at once
+        let attrs = l2cap.channel_mapper().attribute_provider();
+        assert_eq!(attrs.writes, 1);
+        assert_eq!(attrs.attr.value[..18], [0xAA; 18]);
+        assert_eq!(attrs.attr.value[18..20], [0xBB; 2]);
+        assert_eq!(attrs.attr.value[20..], Long::new().attr.value[20..]);
+
+        // Cancelling discards the queue
+        send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, 0, 0, 0xCC]);
+        recv(&mut rx);
+        send(&mut l2cap, &mut tx, &[0x18, 0x00]);
+        assert_eq!(recv(&mut rx), [0x19]);
+        send(&mut l2cap, &mut tx, &[0x18, 0x01]);
+        assert_eq!(recv(&mut rx), [0x19]);
+        assert_eq!(l2cap.channel_mapper().attribute_provider().writes, 1);
+
+        // Gaps between chunks are rejected without writing anything
+        send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, 0, 0, 0xDD]);
+        recv(&mut rx);
+        send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, 5, 0, 0xDD]);
+        recv(&mut rx);
+        send(&mut l2cap, &mut tx, &[0x18, 0x01]);
+        assert_eq!(recv(&mut rx), [0x01, 0x18, 0x01, 0x00, 0x07]);
+        assert_eq!(l2cap.channel_mapper().attribute_provider().writes, 1);
+    }
+
+    #[test]
+    fn prepare_queue_full() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(Long::new()));
+
+        for i in 0..PREPARE_QUEUE_SIZE as u8 {
+            send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, i, 0, i]);
+            assert_eq!(recv(&mut rx)[0], 0x17);
+        }
+        send(&mut l2cap, &mut tx, &[0x16, 0x01, 0x00, 0xFF, 0, 0]);
+        assert_eq!(recv(&mut rx), [0x01, 0x16, 0x01, 0x00, 0x09]);
+
+        // The queued writes can still be executed
+        send(&mut l2cap, &mut tx, &[0x18, 0x01]);
+        assert_eq!(recv(&mut rx), [0x19]);
+        let attrs = l2cap.channel_mapper().attribute_provider();
+        assert_eq!(attrs.writes, 1);
+    }
+
+    /// The 128-bit MIDI I/O characteristic UUID, in the little-endian order used on the air.
+    const MIDI_IO_UUID_LE: [u8; 16] = [
+        0xF3, 0x6B, 0x10, 0x9D, 0x66, 0xF2, 0xA9, 0xA1, 0x12, 0x41, 0x68, 0x38, 0xDB, 0xE5, 0x72,
+        0x77,
+    ];
+
+    #[test]
+    fn find_information() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+
+        // Handles 1 and 2 have 16-bit types, the list ends before the 128-bit one
+        send(&mut l2cap, &mut tx, &[0x04, 0x01, 0x00, 0xFF, 0xFF]);
+        assert_eq!(
+            recv(&mut rx),
+            [0x05, 0x01, 0x01, 0x00, 0x00, 0x28, 0x02, 0x00, 0x03, 0x28]
+        );
+
+        send(&mut l2cap, &mut tx, &[0x04, 0x03, 0x00, 0xFF, 0xFF]);
+        let mut expected = std::vec![0x05, 0x02, 0x03, 0x00];
+        expected.extend_from_slice(&MIDI_IO_UUID_LE);
+        assert_eq!(recv(&mut rx), expected);
+
+        send(&mut l2cap, &mut tx, &[0x04, 0x04, 0x00, 0xFF, 0xFF]);
+        assert_eq!(recv(&mut rx), [0x05, 0x01, 0x04, 0x00, 0x02, 0x29]);
+
+        send(&mut l2cap, &mut tx, &[0x04, 0x05, 0x00, 0xFF, 0xFF]);
+        assert_eq!(recv(&mut rx), [0x01, 0x04, 0x05, 0x00, 0x0A]);
+    }
+
+    #[test]
+    fn read_by_type_uuid128() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+
+        // The characteristic declaration contains the value UUID in little-endian order
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x28],
+        );
+        let mut expected = std::vec![0x09, 21, 0x02, 0x00, 0x1E, 0x03, 0x00];
+        expected.extend_from_slice(&MIDI_IO_UUID_LE);
+        assert_eq!(recv(&mut rx), expected);
+
+        // Requests for a 128-bit type only match attributes with that type
+        let mut req = std::vec![0x08, 0x01, 0x00, 0xFF, 0xFF];
+        req.extend_from_slice(&MIDI_IO_UUID_LE);
+        send(&mut l2cap, &mut tx, &req);
+        assert_eq!(recv(&mut rx), [0x09, 2, 0x03, 0x00]);
+    }
+
+    /// A service with 3 readable characteristics, the second one having a 128-bit UUID.
+    struct ThreeChars {
+        attributes: [Attribute<&'static [u8]>; 7],
+    }
+
+    const CHAR_UUID128: Uuid128 = Uuid128::parse_static("7772e5db-3868-4112-a1a9-f2669d106bf3");
+    static CHAR1_DECL: [u8; 5] = declaration_value16(Properties::READ, 0x0003, Uuid16(0x2A00));
+    static CHAR2_DECL: [u8; 19] = declaration_value128(Properties::READ, 0x0005, CHAR_UUID128);
+    static CHAR3_DECL: [u8; 5] = declaration_value16(Properties::READ, 0x0007, Uuid16(0x2A01));
+
+    impl ThreeChars {
+        fn new() -> Self {
+            let attr = |uuid: AttUuid, handle, value: &'static [u8]| {
+                Attribute::new(uuid, Handle::from_raw(handle), value)
+            };
+            Self {
+                attributes: [
+                    attr(Uuid16(0x2800).into(), 0x0001, &[0x00, 0x18]),
+                    attr(Uuid16(0x2803).into(), 0x0002, &CHAR1_DECL),
+                    attr(Uuid16(0x2A00).into(), 0x0003, b"a rather long device name"),
+                    attr(Uuid16(0x2803).into(), 0x0004, &CHAR2_DECL),
+                    attr(CHAR_UUID128.into(), 0x0005, &[0x01]),
+                    attr(Uuid16(0x2803).into(), 0x0006, &CHAR3_DECL),
+                    attr(Uuid16(0x2A01).into(), 0x0007, &[0x00, 0x00]),
+                ],
+            }
+        }
+    }
+
+    impl AttributeProvider for ThreeChars {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            for attr in &self.attributes {
+                if range.contains(attr.handle) {
+                    f(self, attr)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+            uuid == Uuid16(0x2800)
+        }
+
+        fn group_end(&self, handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+            match handle.as_u16() {
+                0x0001 => Some(&self.attributes[6]),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn read_by_type_characteristics() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(ThreeChars::new()));
+        let discover = |start: u16| {
+            let mut req = std::vec![0x08];
+            req.extend_from_slice(&start.to_le_bytes());
+            req.extend_from_slice(&[0xFF, 0xFF, 0x03, 0x28]);
+            req
+        };
+
+        // The first response ends before the 128-bit characteristic, since all entries must have
+        // the same length
+        send(&mut l2cap, &mut tx, &discover(0x0001));
+        assert_eq!(
+            recv(&mut rx),
+            [0x09, 7, 0x02, 0x00, 0x02, 0x03, 0x00, 0x00, 0x2A]
+        );
+
+        // The client continues after the last handle it received. The 128-bit entry is sent alone.
+        send(&mut l2cap, &mut tx, &discover(0x0003));
+        let mut expected = std::vec![0x09, 21, 0x04, 0x00];
+        expected.extend_from_slice(&CHAR2_DECL);
+        assert_eq!(recv(&mut rx), expected);
+
+        send(&mut l2cap, &mut tx, &discover(0x0005));
+        assert_eq!(
+            recv(&mut rx),
+            [0x09, 7, 0x06, 0x00, 0x02, 0x07, 0x00, 0x01, 0x2A]
+        );
+
+        // Discovery ends with an `Attribute Not Found` error
+        send(&mut l2cap, &mut tx, &discover(0x0007));
+        assert_eq!(recv(&mut rx), [0x01, 0x08, 0x07, 0x00, 0x0A]);
+    }
+
+    #[test]
+    fn read_by_type_mtu() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(ThreeChars::new()));
+
+        // With the default MTU of 23, values are truncated to 19 Bytes
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x2A],
+        );
+        let mut expected = std::vec![0x09, 21, 0x03, 0x00];
+        expected.extend_from_slice(b"a rather long devic");
+        assert_eq!(recv(&mut rx), expected);
+    }
+
+    /// 2 primary services with different UUIDs, each containing a characteristic.
+    struct TwoServices {
+        attributes: [Attribute<&'static [u8]>; 6],
+    }
+
+    static BATTERY_LEVEL_DECL: [u8; 5] =
+        declaration_value16(Properties::READ, 0x0003, Uuid16(0x2A19));
+    static MODEL_NUMBER_DECL: [u8; 5] =
+        declaration_value16(Properties::READ, 0x0006, Uuid16(0x2A24));
+
+    impl TwoServices {
+        fn new() -> Self {
+            let attr = |uuid: Uuid16, handle, value: &'static [u8]| {
+                Attribute::new(uuid.into(), Handle::from_raw(handle), value)
+            };
+            Self {
+                attributes: [
+                    attr(Uuid16(0x2800), 0x0001, &[0x0F, 0x18]), // Battery Service
+                    attr(Uuid16(0x2803), 0x0002, &BATTERY_LEVEL_DECL),
+                    attr(Uuid16(0x2A19), 0x0003, &[100]),
+                    attr(Uuid16(0x2800), 0x0004, &[0x0A, 0x18]), // Device Information
+                    attr(Uuid16(0x2803), 0x0005, &MODEL_NUMBER_DECL),
+                    attr(Uuid16(0x2A24), 0x0006, b"rubble"),
+                ],
+            }
+        }
+    }
+
+    impl AttributeProvider for TwoServices {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            for attr in &self.attributes {
+                if range.contains(attr.handle) {
+                    f(self, attr)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+            uuid == Uuid16(0x2800)
+        }
+
+        fn group_end(&self, handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+            match handle.as_u16() {
+                0x0001 => Some(&self.attributes[2]),
+                0x0004 => Some(&self.attributes[5]),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn find_by_type_value() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(TwoServices::new()));
+
+        // Discover the Device Information service by its UUID. Only the matching service is
+        // returned, along with the end of its group.
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x06, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28, 0x0A, 0x18],
+        );
+        assert_eq!(recv(&mut rx), [0x07, 0x04, 0x00, 0x06, 0x00]);
+
+        // The client continues after the end of the last group, discovery ends with an error
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x06, 0x07, 0x00, 0xFF, 0xFF, 0x00, 0x28, 0x0A, 0x18],
+        );
+        assert_eq!(recv(&mut rx), [0x01, 0x06, 0x07, 0x00, 0x0A]);
+
+        // Unknown services are not found
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x06, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28, 0x0D, 0x18],
+        );
+        assert_eq!(recv(&mut rx), [0x01, 0x06, 0x01, 0x00, 0x0A]);
+
+        // Non-grouping attributes can be found as well, their group ends at their own handle
+        send(
+            &mut l2cap,
+            &mut tx,
+            &[0x06, 0x01, 0x00, 0xFF, 0xFF, 0x19, 0x2A, 100],
+        );
+        assert_eq!(recv(&mut rx), [0x07, 0x03, 0x00, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn error_responses() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+
+        // Unknown request opcode: Request Not Supported, handle 0x0000
+        send(&mut l2cap, &mut tx, &[0x30, 0x01, 0x00]);
+        assert_eq!(recv(&mut rx), [0x01, 0x30, 0x00, 0x00, 0x06]);
+
+        // Unknown commands are ignored
+        send(&mut l2cap, &mut tx, &[0x70, 0x01, 0x00]);
+        assert!(!rx.has_data());
+
+        // Read Request past the end of the database: Invalid Handle
+        send(&mut l2cap, &mut tx, &[0x0A, 0x10, 0x00]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0A, 0x10, 0x00, 0x01]);
+
+        // The NULL handle is never valid
+        send(&mut l2cap, &mut tx, &[0x12, 0x00, 0x00, 1]);
+        assert_eq!(recv(&mut rx), [0x01, 0x12, 0x00, 0x00, 0x01]);
+
+        // Prepared writes to missing attributes are rejected as well
+        send(&mut l2cap, &mut tx, &[0x16, 0x10, 0x00, 0x00, 0x00, 1]);
+        assert_eq!(recv(&mut rx), [0x01, 0x16, 0x10, 0x00, 0x01]);
+
+        // Truncated Read Request: Invalid PDU
+        send(&mut l2cap, &mut tx, &[0x0A, 0x01]);
+        assert_eq!(recv(&mut rx), [0x01, 0x0A, 0x00, 0x00, 0x04]);
+    }
 }
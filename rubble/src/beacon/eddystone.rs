@@ -0,0 +1,548 @@
+//! Eddystone beacon frames.
+//!
+//! Eddystone is an open beacon format that transmits its frames as service data associated with
+//! the 16-bit service UUID `0xFEAA`. The first Byte of the service data determines the frame type.
+
+use super::Beacon;
+use crate::link::ad_structure::{AdStructure, Flags, ServiceUuids};
+use crate::link::DeviceAddress;
+use crate::uuid::Uuid16;
+use crate::{bytes::*, Error};
+use core::fmt;
+
+/// The 16-bit service UUID all Eddystone frames are sent with.
+pub const EDDYSTONE_UUID: u16 = 0xFEAA;
+
+/// Maximum length of an Eddystone frame.
+///
+/// This is what's left of the 31 Bytes of advertising data after the flags, the service UUID list
+/// and the service data header.
+const MAX_FRAME_LEN: usize = 20;
+
+/// Maximum length of the encoded URL in an Eddystone-URL frame (excluding the scheme prefix).
+const MAX_URL_LEN: usize = MAX_FRAME_LEN - 3;
+
+const FRAME_UID: u8 = 0x00;
+const FRAME_URL: u8 = 0x10;
+const FRAME_TLM: u8 = 0x20;
+
+/// Version of unencrypted TLM frames.
+const TLM_VERSION: u8 = 0x00;
+
+/// URL scheme prefixes, indexed by their code.
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Common URL parts that are compressed into a single Byte, indexed by their code.
+///
+/// The suffixes with a trailing slash come first, so that they're preferred when encoding.
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// An Eddystone-UID frame, broadcasting an opaque, unique beacon ID.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneUid {
+    /// The TX power measured at a distance of 0 meters, in dBm.
+    pub tx_power: i8,
+
+    /// Namespace of the beacon ID, grouping the beacons of an organization.
+    pub namespace: [u8; 10],
+
+    /// Identifies an individual beacon within its namespace.
+    pub instance: [u8; 6],
+}
+
+impl EddystoneUid {
+    /// Creates an Eddystone-UID frame.
+    pub fn new(tx_power: i8, namespace: [u8; 10], instance: [u8; 6]) -> Self {
+        Self {
+            tx_power,
+            namespace,
+            instance,
+        }
+    }
+}
+
+/// An Eddystone-URL frame, broadcasting a compressed URL.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneUrl {
+    tx_power: i8,
+    scheme: u8,
+    url: [u8; MAX_URL_LEN],
+    len: u8,
+}
+
+impl EddystoneUrl {
+    /// Creates an Eddystone-URL frame by compressing `url`.
+    ///
+    /// `url` must start with `http://` or `https://` and may only contain printable ASCII
+    /// characters, otherwise `Error::InvalidValue` is returned. If the compressed URL is too long
+    /// to fit into an advertising packet, `Error::InvalidLength` is returned.
+    ///
+    /// # Parameters
+    ///
+    /// * **`url`**: The URL to broadcast.
+    /// * **`tx_power`**: The TX power measured at a distance of 0 meters, in dBm.
+    pub fn new(url: &str, tx_power: i8) -> Result<Self, Error> {
+        // The schemes are ordered so that the one with `www.` is found first
+        let (scheme, prefix) = URL_SCHEMES
+            .iter()
+            .enumerate()
+            .find(|(_, prefix)| url.starts_with(*prefix))
+            .ok_or(Error::InvalidValue)?;
+
+        let mut this = Self {
+            tx_power,
+            scheme: scheme as u8,
+            url: [0; MAX_URL_LEN],
+            len: 0,
+        };
+
+        let mut rest = &url[prefix.len()..];
+        while !rest.is_empty() {
+            let expansion = URL_EXPANSIONS
+                .iter()
+                .position(|expansion| rest.starts_with(expansion));
+            let byte = match expansion {
+                Some(code) => {
+                    rest = &rest[URL_EXPANSIONS[code].len()..];
+                    code as u8
+                }
+                None => {
+                    let byte = rest.as_bytes()[0];
+                    if !is_url_char(byte) {
+                        return Err(Error::InvalidValue);
+                    }
+                    rest = &rest[1..];
+                    byte
+                }
+            };
+
+            if usize::from(this.len) == MAX_URL_LEN {
+                return Err(Error::InvalidLength);
+            }
+            this.url[usize::from(this.len)] = byte;
+            this.len += 1;
+        }
+
+        Ok(this)
+    }
+
+    /// Returns the TX power measured at a distance of 0 meters, in dBm.
+    pub fn tx_power(&self) -> i8 {
+        self.tx_power
+    }
+
+    /// Returns the compressed URL (excluding the scheme prefix).
+    pub fn encoded(&self) -> &[u8] {
+        &self.url[..usize::from(self.len)]
+    }
+}
+
+/// Returns whether `byte` may appear uncompressed in an encoded URL.
+fn is_url_char(byte: u8) -> bool {
+    (0x21..=0x7E).contains(&byte)
+}
+
+/// Writes the decompressed URL.
+impl fmt::Display for EddystoneUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(URL_SCHEMES[usize::from(self.scheme)])?;
+        for &byte in self.encoded() {
+            match URL_EXPANSIONS.get(usize::from(byte)) {
+                Some(expansion) => f.write_str(expansion)?,
+                None => fmt::Write::write_char(f, char::from(byte))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EddystoneUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EddystoneUrl")
+            .field("tx_power", &self.tx_power)
+            .field("url", &format_args!("{}", self))
+            .finish()
+    }
+}
+
+/// An unencrypted Eddystone-TLM frame, broadcasting telemetry data of the beacon.
+///
+/// TLM frames are meant to be interleaved with UID or URL frames, which identify the beacon.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneTlm {
+    /// Battery voltage in mV, or 0 if the beacon isn't battery-powered.
+    pub battery_mv: u16,
+
+    /// Beacon temperature in °C as a signed 8.8 fixed-point number.
+    ///
+    /// Set to [`TEMPERATURE_UNSUPPORTED`] if the beacon has no temperature sensor.
+    ///
+    /// [`TEMPERATURE_UNSUPPORTED`]: Self::TEMPERATURE_UNSUPPORTED
+    pub temperature: i16,
+
+    /// Number of advertising PDUs sent since power-up or reboot.
+    pub adv_count: u32,
+
+    /// Time since power-up or reboot, in units of 0.1 seconds.
+    pub uptime: u32,
+}
+
+impl EddystoneTlm {
+    /// Value of [`temperature`] when the beacon doesn't measure its temperature (-128 °C).
+    ///
+    /// [`temperature`]: Self::temperature
+    pub const TEMPERATURE_UNSUPPORTED: i16 = i16::MIN;
+
+    /// Creates a TLM frame from raw sensor values.
+    ///
+    /// # Parameters
+    ///
+    /// * **`battery_mv`**: Battery voltage in mV, or 0 if the beacon isn't battery-powered.
+    /// * **`millicelsius`**: Temperature in 1/1000 °C, or `None` if the beacon has no temperature
+    ///   sensor. The value is rounded to the nearest 1/256 °C and clamped to the representable
+    ///   range.
+    /// * **`adv_count`**: Number of advertising PDUs sent since power-up.
+    /// * **`uptime_secs`**: Seconds since power-up.
+    pub fn new(
+        battery_mv: u16,
+        millicelsius: Option<i32>,
+        adv_count: u32,
+        uptime_secs: u32,
+    ) -> Self {
+        Self {
+            battery_mv,
+            temperature: millicelsius.map_or(Self::TEMPERATURE_UNSUPPORTED, millicelsius_to_fixed),
+            adv_count,
+            uptime: uptime_secs.saturating_mul(10),
+        }
+    }
+
+    /// Returns the temperature in 1/1000 °C, or `None` if the beacon has no temperature sensor.
+    pub fn millicelsius(&self) -> Option<i32> {
+        if self.temperature == Self::TEMPERATURE_UNSUPPORTED {
+            None
+        } else {
+            Some(div_round(i32::from(self.temperature) * 1000, 256))
+        }
+    }
+
+    /// Returns the number of whole seconds since power-up or reboot.
+    pub fn uptime_secs(&self) -> u32 {
+        self.uptime / 10
+    }
+}
+
+/// Converts a temperature in 1/1000 °C to 8.8 fixed-point.
+fn millicelsius_to_fixed(millicelsius: i32) -> i16 {
+    // The smallest value is reserved for "unsupported"
+    let min = i32::from(EddystoneTlm::TEMPERATURE_UNSUPPORTED) + 1;
+    let fixed = div_round(millicelsius.clamp(-128_000, 128_000) * 256, 1000);
+    fixed.clamp(min, i32::from(i16::MAX)) as i16
+}
+
+/// Divides `n` by the positive `d`, rounding to the nearest integer (away from 0 on ties).
+fn div_round(n: i32, d: i32) -> i32 {
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        (n - d / 2) / d
+    }
+}
+
+/// An Eddystone frame, sent as service data for [`EDDYSTONE_UUID`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EddystoneFrame {
+    /// An Eddystone-UID frame.
+    Uid(EddystoneUid),
+
+    /// An Eddystone-URL frame.
+    Url(EddystoneUrl),
+
+    /// An unencrypted Eddystone-TLM telemetry frame.
+    Tlm(EddystoneTlm),
+}
+
+impl EddystoneFrame {
+    /// Decodes an Eddystone frame from an AD structure.
+    ///
+    /// Returns `None` if `ad` isn't service data for [`EDDYSTONE_UUID`] or contains an unknown or
+    /// malformed frame.
+    pub fn from_ad_structure(ad: &AdStructure<'_>) -> Option<Self> {
+        match ad {
+            AdStructure::ServiceData16 { uuid, data } if *uuid == EDDYSTONE_UUID => {
+                Self::from_bytes(&mut ByteReader::new(data)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Searches a list of AD structures (eg. from a scan report) for an Eddystone frame.
+    pub fn find<'a>(ads: impl IntoIterator<Item = AdStructure<'a>>) -> Option<Self> {
+        ads.into_iter().find_map(|ad| Self::from_ad_structure(&ad))
+    }
+
+    /// Creates a [`Beacon`] broadcasting this frame.
+    ///
+    /// # Parameters
+    ///
+    /// * **`addr`**: Address of the beacon device.
+    pub fn beacon(&self, addr: DeviceAddress) -> Result<Beacon, Error> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let mut writer = ByteWriter::new(&mut buf);
+        self.to_bytes(&mut writer)?;
+        let len = MAX_FRAME_LEN - writer.space_left();
+
+        Beacon::new(
+            addr,
+            &[
+                AdStructure::Flags(Flags::discoverable()),
+                AdStructure::ServiceUuids16(ServiceUuids::from_uuids(
+                    true,
+                    &[Uuid16(EDDYSTONE_UUID)],
+                )),
+                AdStructure::ServiceData16 {
+                    uuid: EDDYSTONE_UUID,
+                    data: &buf[..len],
+                },
+            ],
+        )
+    }
+}
+
+impl From<EddystoneUid> for EddystoneFrame {
+    fn from(uid: EddystoneUid) -> Self {
+        EddystoneFrame::Uid(uid)
+    }
+}
+
+impl From<EddystoneUrl> for EddystoneFrame {
+    fn from(url: EddystoneUrl) -> Self {
+        EddystoneFrame::Url(url)
+    }
+}
+
+impl From<EddystoneTlm> for EddystoneFrame {
+    fn from(tlm: EddystoneTlm) -> Self {
+        EddystoneFrame::Tlm(tlm)
+    }
+}
+
+impl<'a> FromBytes<'a> for EddystoneFrame {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(match bytes.read_u8()? {
+            FRAME_UID => {
+                let tx_power = bytes.read_u8()? as i8;
+                let namespace = bytes.read_array()?;
+                let instance = bytes.read_array()?;
+                // The 2 reserved Bytes are omitted by some beacons
+                EddystoneFrame::Uid(EddystoneUid::new(tx_power, namespace, instance))
+            }
+            FRAME_URL => {
+                let tx_power = bytes.read_u8()? as i8;
+                let scheme = bytes.read_u8()?;
+                let encoded = bytes.read_rest();
+                if usize::from(scheme) >= URL_SCHEMES.len()
+                    || encoded.len() > MAX_URL_LEN
+                    || encoded
+                        .iter()
+                        .any(|&b| usize::from(b) >= URL_EXPANSIONS.len() && !is_url_char(b))
+                {
+                    return Err(Error::InvalidValue);
+                }
+
+                let mut url = [0; MAX_URL_LEN];
+                url[..encoded.len()].copy_from_slice(encoded);
+                EddystoneFrame::Url(EddystoneUrl {
+                    tx_power,
+                    scheme,
+                    url,
+                    len: encoded.len() as u8,
+                })
+            }
+            FRAME_TLM => {
+                if bytes.read_u8()? != TLM_VERSION {
+                    // Encrypted TLM frames can't be decoded without the beacon's key
+                    return Err(Error::InvalidValue);
+                }
+
+                EddystoneFrame::Tlm(EddystoneTlm {
+                    battery_mv: u16::from_be_bytes(bytes.read_array()?),
+                    temperature: i16::from_be_bytes(bytes.read_array()?),
+                    adv_count: u32::from_be_bytes(bytes.read_array()?),
+                    uptime: u32::from_be_bytes(bytes.read_array()?),
+                })
+            }
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+impl ToBytes for EddystoneFrame {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match self {
+            EddystoneFrame::Uid(uid) => {
+                writer.write_u8(FRAME_UID)?;
+                writer.write_u8(uid.tx_power as u8)?;
+                writer.write_slice(&uid.namespace)?;
+                writer.write_slice(&uid.instance)?;
+                writer.write_slice(&[0, 0])
+            }
+            EddystoneFrame::Url(url) => {
+                writer.write_u8(FRAME_URL)?;
+                writer.write_u8(url.tx_power as u8)?;
+                writer.write_u8(url.scheme)?;
+                writer.write_slice(url.encoded())
+            }
+            EddystoneFrame::Tlm(tlm) => {
+                writer.write_u8(FRAME_TLM)?;
+                writer.write_u8(TLM_VERSION)?;
+                writer.write_slice(&tlm.battery_mv.to_be_bytes())?;
+                writer.write_slice(&tlm.temperature.to_be_bytes())?;
+                writer.write_slice(&tlm.adv_count.to_be_bytes())?;
+                writer.write_slice(&tlm.uptime.to_be_bytes())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::ad_structure::AdStructureIter;
+    use crate::link::AddressKind;
+    use std::string::ToString;
+
+    const ADDR: DeviceAddress = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+
+    /// Extracts the Eddystone frame from a beacon's advertising PDU.
+    fn frame(beacon: &Beacon) -> EddystoneFrame {
+        let ads = AdStructureIter::new(&beacon.pdu.payload()[6..]).map(Result::unwrap);
+        EddystoneFrame::find(ads).unwrap()
+    }
+
+    #[test]
+    fn url_compression() {
+        let url = EddystoneUrl::new("https://example.com/", -20).unwrap();
+        assert_eq!(url.scheme, 3);
+        assert_eq!(url.encoded(), b"example\x00");
+        assert_eq!(url.to_string(), "https://example.com/");
+
+        let beacon = EddystoneFrame::from(url).beacon(ADDR).unwrap();
+        assert_eq!(
+            &beacon.pdu.payload()[6..],
+            &[
+                0x02, 0x01, 0x06, // flags
+                0x03, 0x03, 0xAA, 0xFE, // service UUIDs
+                0x0E, 0x16, 0xAA, 0xFE, // service data
+                0x10, 0xEC, 0x03, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00,
+            ]
+        );
+        match frame(&beacon) {
+            EddystoneFrame::Url(decoded) => {
+                assert_eq!(decoded, url);
+                assert_eq!(decoded.to_string(), "https://example.com/");
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+
+        let url = EddystoneUrl::new("http://www.rust-lang.org/", 0).unwrap();
+        assert_eq!(url.scheme, 0);
+        assert_eq!(url.to_string(), "http://www.rust-lang.org/");
+
+        assert_eq!(
+            EddystoneUrl::new("ftp://example.com", 0),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            EddystoneUrl::new("https://example.com/a b", 0),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            EddystoneUrl::new("https://a-very-long-domain.com/", 0),
+            Err(Error::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn uid() {
+        let uid = EddystoneUid::new(-30, [0xAB; 10], [1, 2, 3, 4, 5, 6]);
+        let beacon = EddystoneFrame::from(uid).beacon(ADDR).unwrap();
+        assert_eq!(frame(&beacon), EddystoneFrame::Uid(uid));
+        assert!(beacon.pdu.payload().len() <= 6 + 31);
+    }
+
+    #[test]
+    fn tlm() {
+        // 3.0 V, -12.5 °C, 123456 PDUs, 1 day
+        let tlm = EddystoneTlm::new(3000, Some(-12_500), 123_456, 86_400);
+        let beacon = EddystoneFrame::from(tlm).beacon(ADDR).unwrap();
+        assert_eq!(
+            &beacon.pdu.payload()[6 + 3 + 4..],
+            &[
+                0x11, 0x16, 0xAA, 0xFE, // service data
+                0x20, 0x00, // TLM, unencrypted
+                0x0B, 0xB8, // battery voltage
+                0xF3, 0x80, // temperature
+                0x00, 0x01, 0xE2, 0x40, // advertising PDU count
+                0x00, 0x0D, 0x2F, 0x00, // uptime
+            ]
+        );
+
+        let decoded = match frame(&beacon) {
+            EddystoneFrame::Tlm(decoded) => decoded,
+            other => panic!("unexpected frame {:?}", other),
+        };
+        assert_eq!(decoded, tlm);
+        assert_eq!(decoded.battery_mv, 3000);
+        assert_eq!(decoded.millicelsius(), Some(-12_500));
+        assert_eq!(decoded.adv_count, 123_456);
+        assert_eq!(decoded.uptime_secs(), 86_400);
+    }
+
+    #[test]
+    fn tlm_temperature() {
+        for raw in [i16::MIN + 1, -0x0180, -1, 0, 1, 0x1880, i16::MAX] {
+            let tlm = EddystoneTlm {
+                temperature: raw,
+                ..EddystoneTlm::new(0, None, 0, 0)
+            };
+            let millicelsius = tlm.millicelsius().unwrap();
+            assert_eq!(EddystoneTlm::new(0, Some(millicelsius), 0, 0), tlm);
+        }
+
+        assert_eq!(
+            EddystoneTlm::new(0, Some(-1_500), 0, 0).temperature,
+            -0x0180
+        );
+        assert_eq!(
+            EddystoneTlm::new(0, Some(-200_000), 0, 0).temperature,
+            i16::MIN + 1
+        );
+        assert_eq!(EddystoneTlm::new(0, None, 0, 0).millicelsius(), None);
+    }
+
+    #[test]
+    fn classify() {
+        let ad = AdStructure::ServiceData16 {
+            uuid: 0x180F,
+            data: &[0x10, 0x00, 0x03, b'a'],
+        };
+        assert_eq!(EddystoneFrame::from_ad_structure(&ad), None);
+
+        // Encrypted TLM
+        let ad = AdStructure::ServiceData16 {
+            uuid: EDDYSTONE_UUID,
+            data: &[0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        };
+        assert_eq!(EddystoneFrame::from_ad_structure(&ad), None);
+
+        let ad = AdStructure::ServiceData16 {
+            uuid: EDDYSTONE_UUID,
+            data: &[0x40, 0x00],
+        };
+        assert_eq!(EddystoneFrame::from_ad_structure(&ad), None);
+    }
+}
@@ -0,0 +1,523 @@
+//! BLE beacon support, without dealing with Link-Layer stuff.
+
+mod eddystone;
+
+pub use self::eddystone::{
+    EddystoneFrame, EddystoneTlm, EddystoneUid, EddystoneUrl, EDDYSTONE_UUID,
+};
+
+use crate::link::ad_structure::{AdStructure, Flags};
+use crate::link::advertising::{Header, Pdu, PduBuf};
+use crate::link::filter::{self, AddressFilter, ScanFilter};
+use crate::link::{Cmd, CompanyId, DeviceAddress, NextUpdate, RadioCmd, Transmitter};
+use crate::phy::AdvertisingChannel;
+use crate::time::{Duration, Instant};
+use crate::uuid::Uuid128;
+use crate::{bytes::*, Error};
+use heapless::Vec;
+
+/// A BLE beacon.
+///
+/// FIXME: This has to randomly offset the broadcast interval
+pub struct Beacon {
+    pdu: PduBuf,
+}
+
+impl Beacon {
+    /// Creates a new beacon that will broadcast a packet on all advertisement
+    /// channels.
+    ///
+    /// # Parameters
+    ///
+    /// * **`addr`**: Address of the beacon device.
+    /// * **`data`**: Data to broadcast. This must fit within a single PDU.
+    ///
+    /// # Errors
+    ///
+    /// If `data` doesn't fit in a single PDU, an error will be returned.
+    pub fn new(addr: DeviceAddress, data: &[AdStructure<'_>]) -> Result<Self, Error> {
+        let pdu = PduBuf::beacon(addr, data)?;
+        Ok(Self { pdu })
+    }
+
+    /// Creates a beacon broadcasting manufacturer-specific data.
+    ///
+    /// The advertisement also contains the flags marking the device as discoverable.
+    ///
+    /// # Parameters
+    ///
+    /// * **`addr`**: Address of the beacon device.
+    /// * **`company_identifier`**: Identifier of the company that defined the format of `payload`.
+    /// * **`payload`**: Manufacturer-specific data to broadcast. This must fit within a single PDU.
+    pub fn with_manufacturer_data(
+        addr: DeviceAddress,
+        company_identifier: CompanyId,
+        payload: &[u8],
+    ) -> Result<Self, Error> {
+        Self::new(
+            addr,
+            &[
+                AdStructure::Flags(Flags::discoverable()),
+                AdStructure::ManufacturerSpecificData {
+                    company_identifier,
+                    payload,
+                },
+            ],
+        )
+    }
+
+    /// Broadcasts the beacon data using `tx`.
+    ///
+    /// This will broadcast once on every advertising channel.
+    pub fn broadcast<T: Transmitter>(&self, tx: &mut T) {
+        // The spec says that we have to broadcast on all 3 channels in sequence, so that the total
+        // time of this broadcast ("advertising event") is <10ms.
+
+        // The transmitter retains the old transmit buffer, but it might be used for other purposes
+        // (like sending multiple beacon payloads), so we always copy our payload into the tx
+        // buffer here.
+        let payload = self.pdu.payload();
+        let buf = tx.tx_payload_buf();
+        buf[..payload.len()].copy_from_slice(payload);
+
+        for channel in AdvertisingChannel::iter_all() {
+            tx.transmit_advertising(self.pdu.header(), channel);
+        }
+    }
+}
+
+/// A beacon frame and how often it is sent in a row.
+struct ScheduledBeacon {
+    beacon: Beacon,
+    repeat: u8,
+}
+
+/// Rotates between up to `N` beacon payloads, broadcasting one of them per advertising event.
+///
+/// Every payload is broadcast a configurable number of times in a row before the scheduler moves
+/// on to the next one. For example, adding an Eddystone-UID frame with a repeat count of 10 and a
+/// TLM frame with a repeat count of 1 results in one TLM frame being sent for every 10 UID frames.
+///
+/// Like the [`BeaconScanner`], the scheduler is driven by a timer: [`configure`] returns the first
+/// `Cmd` to apply, and [`timer_update`] has to be called whenever the time specified by the last
+/// `Cmd` has been reached.
+///
+/// [`configure`]: Self::configure
+/// [`timer_update`]: Self::timer_update
+pub struct BeaconScheduler<const N: usize> {
+    beacons: Vec<ScheduledBeacon, N>,
+    interval: Duration,
+    current: usize,
+    sent: u8,
+}
+
+impl<const N: usize> BeaconScheduler<N> {
+    /// Creates a scheduler without any beacon payloads.
+    pub fn new() -> Self {
+        Self {
+            beacons: Vec::new(),
+            interval: Duration::micros(0),
+            current: 0,
+            sent: 0,
+        }
+    }
+
+    /// Adds a beacon to the end of the rotation.
+    ///
+    /// The beacon is broadcast `repeat` times in a row before the scheduler moves on to the next
+    /// one. Returns its index, which can be passed to [`replace`] to update its payload.
+    ///
+    /// Returns `Error::InvalidValue` if `repeat` is 0, and `Error::Eof` if the scheduler already
+    /// holds `N` beacons.
+    ///
+    /// [`replace`]: Self::replace
+    pub fn push(&mut self, beacon: Beacon, repeat: u8) -> Result<usize, Error> {
+        if repeat == 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        self.beacons
+            .push(ScheduledBeacon { beacon, repeat })
+            .map_err(|_| Error::Eof)?;
+        Ok(self.beacons.len() - 1)
+    }
+
+    /// Replaces the payload of the beacon at `index`, keeping its position in the rotation.
+    ///
+    /// This can be used to update frames whose content changes over time (eg. telemetry frames).
+    ///
+    /// Returns `Error::InvalidValue` if there is no beacon at `index`.
+    pub fn replace(&mut self, index: usize, beacon: Beacon) -> Result<(), Error> {
+        let scheduled = self.beacons.get_mut(index).ok_or(Error::InvalidValue)?;
+        scheduled.beacon = beacon;
+        Ok(())
+    }
+
+    /// Advances the rotation and returns the beacon to broadcast in the next advertising event.
+    ///
+    /// Returns `None` if no beacons were added.
+    pub fn next_beacon(&mut self) -> Option<&Beacon> {
+        let scheduled = self.beacons.get(self.current)?;
+        let beacon = &scheduled.beacon;
+
+        self.sent += 1;
+        if self.sent == scheduled.repeat {
+            self.sent = 0;
+            self.current = (self.current + 1) % self.beacons.len();
+        }
+        Some(beacon)
+    }
+
+    /// Starts broadcasting and returns the `Cmd` to apply to the radio.
+    ///
+    /// The first advertising event is due immediately, so `timer_update` should be called right
+    /// away.
+    pub fn configure(&mut self, now: Instant, interval: Duration) -> Cmd {
+        self.interval = interval;
+        self.current = 0;
+        self.sent = 0;
+
+        Cmd {
+            next_update: NextUpdate::At(now),
+            window: None,
+            radio: RadioCmd::Off,
+            queued_work: false,
+        }
+    }
+
+    /// Broadcasts the next beacon in the rotation using `tx`.
+    ///
+    /// This should be called whenever the timer configured by the last returned `Cmd` fires. The
+    /// next call is scheduled one advertising interval later.
+    pub fn timer_update<T: Transmitter>(&mut self, now: Instant, tx: &mut T) -> Cmd {
+        if let Some(beacon) = self.next_beacon() {
+            beacon.broadcast(tx);
+        }
+
+        Cmd {
+            next_update: NextUpdate::At(now + self.interval),
+            window: None,
+            radio: RadioCmd::Off,
+            queued_work: false,
+        }
+    }
+}
+
+impl<const N: usize> Default for BeaconScheduler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An Apple iBeacon advertisement.
+///
+/// iBeacons are transmitted as manufacturer-specific data with Apple's company ID (`0x004C`),
+/// followed by the iBeacon type (`0x02`), the length of the remaining data (`0x15`), the proximity
+/// UUID, the major and minor numbers (all big-endian) and the measured TX power.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IBeacon {
+    /// UUID identifying the beacons of an organization or application.
+    pub proximity_uuid: Uuid128,
+
+    /// Identifies a group of beacons (eg. all beacons in a building).
+    pub major: u16,
+
+    /// Identifies an individual beacon within its group.
+    pub minor: u16,
+
+    /// The received signal strength at a distance of 1 meter, in dBm.
+    ///
+    /// Receivers use this to estimate their distance from the beacon.
+    pub measured_power: i8,
+}
+
+impl IBeacon {
+    /// Apple's company identifier.
+    pub const COMPANY_ID: CompanyId = CompanyId::APPLE;
+
+    /// Length of the manufacturer-specific payload following the company identifier.
+    pub const PAYLOAD_LEN: usize = 23;
+
+    const TYPE: u8 = 0x02;
+    const DATA_LEN: u8 = 0x15;
+
+    /// Creates an iBeacon advertisement.
+    pub fn new(proximity_uuid: Uuid128, major: u16, minor: u16, measured_power: i8) -> Self {
+        Self {
+            proximity_uuid,
+            major,
+            minor,
+            measured_power,
+        }
+    }
+
+    /// Returns the manufacturer-specific payload of the advertisement (excluding the company
+    /// identifier).
+    pub fn payload(&self) -> [u8; Self::PAYLOAD_LEN] {
+        let mut payload = [0; Self::PAYLOAD_LEN];
+        payload[0] = Self::TYPE;
+        payload[1] = Self::DATA_LEN;
+        payload[2..18].copy_from_slice(&self.proximity_uuid.to_be_bytes());
+        payload[18..20].copy_from_slice(&self.major.to_be_bytes());
+        payload[20..22].copy_from_slice(&self.minor.to_be_bytes());
+        payload[22] = self.measured_power as u8;
+        payload
+    }
+
+    /// Creates a [`Beacon`] broadcasting this iBeacon advertisement.
+    ///
+    /// # Parameters
+    ///
+    /// * **`addr`**: Address of the beacon device.
+    pub fn beacon(&self, addr: DeviceAddress) -> Result<Beacon, Error> {
+        Beacon::with_manufacturer_data(addr, Self::COMPANY_ID, &self.payload())
+    }
+
+    /// Decodes an iBeacon advertisement from an AD structure.
+    ///
+    /// Returns `None` if `ad` isn't Apple manufacturer-specific data with an iBeacon payload.
+    pub fn from_ad_structure(ad: &AdStructure<'_>) -> Option<Self> {
+        let payload = match ad {
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                payload,
+            } if *company_identifier == Self::COMPANY_ID => payload,
+            _ => return None,
+        };
+
+        if payload.len() != Self::PAYLOAD_LEN
+            || payload[0] != Self::TYPE
+            || payload[1] != Self::DATA_LEN
+        {
+            return None;
+        }
+
+        let mut uuid = [0; 16];
+        uuid.copy_from_slice(&payload[2..18]);
+        Some(Self {
+            proximity_uuid: Uuid128::from_bytes(uuid),
+            major: u16::from_be_bytes([payload[18], payload[19]]),
+            minor: u16::from_be_bytes([payload[20], payload[21]]),
+            measured_power: payload[22] as i8,
+        })
+    }
+
+    /// Searches a list of AD structures (eg. from a scan report) for an iBeacon advertisement.
+    pub fn find<'a>(ads: impl IntoIterator<Item = AdStructure<'a>>) -> Option<Self> {
+        ads.into_iter().find_map(|ad| Self::from_ad_structure(&ad))
+    }
+}
+
+/// Callback for the [`BeaconScanner`].
+pub trait ScanCallback {
+    /// Called when a beacon is received and has passed the configured device address filter.
+    ///
+    /// # Parameters
+    ///
+    /// * **`adv_addr`**: Address of the device sending the beacon.
+    /// * **`adv_data`**: Advertising data structures attached to the beacon.
+    fn beacon<'a, I>(&mut self, adv_addr: DeviceAddress, adv_data: I)
+    where
+        I: Iterator<Item = AdStructure<'a>>;
+}
+
+/// A passive scanner for non-connectable beacon advertisements.
+pub struct BeaconScanner<C: ScanCallback, F: AddressFilter> {
+    cb: C,
+    filter: ScanFilter<F>,
+    interval: Duration,
+    channel: AdvertisingChannel,
+}
+
+impl<C: ScanCallback> BeaconScanner<C, filter::AllowAll> {
+    /// Creates a `BeaconScanner` that will report beacons from any device.
+    pub fn new(callback: C) -> Self {
+        Self::with_filter(callback, filter::AllowAll)
+    }
+}
+
+impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
+    /// Creates a `BeaconScanner` with a custom device filter.
+    pub fn with_filter(callback: C, scan_filter: F) -> Self {
+        Self {
+            cb: callback,
+            filter: ScanFilter::new(scan_filter),
+            interval: Duration::micros(0),
+            channel: AdvertisingChannel::first(),
+        }
+    }
+
+    /// Configures the `BeaconScanner` and returns a `Cmd` to apply to the radio.
+    ///
+    /// The `next_update` field of the returned `Cmd` specifies when to call `timer_update` the next
+    /// time. The timer used for this does not have to be very accurate, it is only used to switch
+    /// to the next advertising channel after `interval` elapses.
+    pub fn configure(&mut self, now: Instant, interval: Duration) -> Cmd {
+        self.interval = interval;
+        self.channel = AdvertisingChannel::first();
+
+        Cmd {
+            // Switch channels
+            next_update: NextUpdate::At(now + self.interval),
+            window: None,
+
+            radio: RadioCmd::ListenAdvertising {
+                channel: self.channel,
+                rx_timeout: None,
+            },
+
+            queued_work: false,
+        }
+    }
+
+    /// Updates the `BeaconScanner` after the configured timer has fired.
+    ///
+    /// This switches to the next advertising channel and will listen there.
+    pub fn timer_update(&mut self, now: Instant) -> Cmd {
+        self.channel = self.channel.cycle();
+
+        Cmd {
+            // Switch channels
+            next_update: NextUpdate::At(now + self.interval),
+            window: None,
+
+            radio: RadioCmd::ListenAdvertising {
+                channel: self.channel,
+                rx_timeout: None,
+            },
+
+            queued_work: false,
+        }
+    }
+
+    /// Processes a received advertising channel packet.
+    ///
+    /// This should be called whenever the radio receives a packet on the configured advertising
+    /// channel.
+    pub fn process_adv_packet(&mut self, header: Header, payload: &[u8], crc_ok: bool) -> Cmd {
+        if crc_ok && header.type_().is_beacon() {
+            // Partially decode to get the device ID and run it through the filter
+            if let Ok(pdu) = Pdu::from_header_and_payload(header, &mut ByteReader::new(payload)) {
+                if self.filter.should_scan(*pdu.sender()) {
+                    let ad = pdu.advertising_data().unwrap();
+                    self.cb.beacon(*pdu.sender(), ad);
+                }
+            }
+        }
+
+        Cmd {
+            next_update: NextUpdate::Keep,
+            window: None,
+            radio: RadioCmd::ListenAdvertising {
+                channel: self.channel,
+                rx_timeout: None,
+            },
+            queued_work: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::ad_structure::AdStructureIter;
+    use crate::link::{data, AddressKind, MIN_PAYLOAD_BUF};
+    use crate::phy::DataChannel;
+    use std::vec::Vec;
+
+    const ADDR: DeviceAddress = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+
+    /// Records the last payload Byte of every transmitted advertising PDU.
+    struct LastByte {
+        buf: [u8; MIN_PAYLOAD_BUF],
+        sent: Vec<u8>,
+    }
+
+    impl Transmitter for LastByte {
+        fn tx_payload_buf(&mut self) -> &mut [u8] {
+            &mut self.buf
+        }
+
+        fn transmit_advertising(&mut self, header: Header, _channel: AdvertisingChannel) {
+            let len = usize::from(header.payload_length());
+            self.sent.push(self.buf[len - 1]);
+        }
+
+        // Beacons never send data channel PDUs
+        fn transmit_data(&mut self, _: u32, _: u32, _: data::Header, _: DataChannel) {}
+    }
+
+    /// Creates a beacon whose advertising data ends in `id`.
+    fn beacon(id: i8) -> Beacon {
+        Beacon::new(ADDR, &[AdStructure::TxPowerLevel(id)]).unwrap()
+    }
+
+    #[test]
+    fn scheduler_ratio() {
+        let mut scheduler = BeaconScheduler::<2>::new();
+        assert_eq!(scheduler.push(beacon(1), 10), Ok(0));
+        assert_eq!(scheduler.push(beacon(2), 1), Ok(1));
+        assert_eq!(scheduler.push(beacon(3), 1), Err(Error::Eof));
+
+        let mut tx = LastByte {
+            buf: [0; MIN_PAYLOAD_BUF],
+            sent: Vec::new(),
+        };
+        let interval = Duration::millis(100);
+        let mut now = Instant::from_ticks(0);
+        let mut cmd = scheduler.configure(now, interval);
+        for _ in 0..22 {
+            match cmd.next_update {
+                NextUpdate::At(at) => now = at,
+                _ => panic!("scheduler must always schedule the next event"),
+            }
+            cmd = scheduler.timer_update(now, &mut tx);
+        }
+        assert_eq!(now, Instant::from_ticks(0) + interval * 21);
+
+        // Every payload is broadcast on all 3 advertising channels
+        let events = tx.sent.chunks(3).map(|c| c[0]).collect::<Vec<_>>();
+        assert!(tx.sent.chunks(3).all(|c| c[0] == c[1] && c[1] == c[2]));
+        let mut expected = std::vec![1; 10];
+        expected.push(2);
+        expected.extend_from_within(..);
+        assert_eq!(events, expected);
+
+        // Replacing a payload doesn't change the rotation
+        scheduler.replace(1, beacon(4)).unwrap();
+        let sent = (0..11)
+            .map(|_| scheduler.next_beacon().unwrap().pdu.payload()[8] as i8)
+            .collect::<Vec<_>>();
+        assert_eq!(&sent[9..], &[1, 4]);
+    }
+
+    #[test]
+    fn ibeacon() {
+        let uuid = Uuid128::parse_static("e2c56db5-dffb-48d2-b060-d0f5a71096e0");
+        let ibeacon = IBeacon::new(uuid, 0x0102, 0x0304, -59);
+        let beacon = ibeacon.beacon(ADDR).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            1, 2, 3, 4, 5, 6, // advertiser address
+            0x02, 0x01, 0x06, // flags
+            0x1A, 0xFF, 0x4C, 0x00, // manufacturer-specific data, Apple
+            0x02, 0x15, // iBeacon type and length
+            0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2,
+            0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0, // proximity UUID
+            0x01, 0x02, // major
+            0x03, 0x04, // minor
+            0xC5, // measured power
+        ];
+        assert_eq!(beacon.pdu.payload(), &expected[..]);
+
+        let ads = AdStructureIter::new(&expected[6..]).map(Result::unwrap);
+        assert_eq!(IBeacon::find(ads), Some(ibeacon));
+
+        let other = AdStructure::ManufacturerSpecificData {
+            company_identifier: CompanyId::NORDIC_SEMICONDUCTOR,
+            payload: &ibeacon.payload(),
+        };
+        assert_eq!(IBeacon::from_ad_structure(&other), None);
+    }
+}
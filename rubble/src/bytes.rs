@@ -359,6 +359,18 @@ impl<'a> ByteWriter<'a> {
         self.write_slice(&value.to_le_bytes())
     }
 
+    /// Writes the lower 24 bits of a `u32` to `self`, using Little Endian byte order.
+    ///
+    /// If `value` doesn't fit in 24 bits, `Error::InvalidValue` is returned. If `self` does not have
+    /// enough space left, `Error::Eof` is returned. In both cases, no bytes will be written to
+    /// `self`.
+    pub fn write_u24_le(&mut self, value: u32) -> Result<(), Error> {
+        if value > 0xFF_FFFF {
+            return Err(Error::InvalidValue);
+        }
+        self.write_slice(&value.to_le_bytes()[..3])
+    }
+
     /// Writes a `u32` to `self`, using Little Endian byte order.
     ///
     /// If `self` does not have enough space left, an error will be returned and no bytes will be
@@ -510,6 +522,12 @@ impl<'a> ByteReader<'a> {
         Ok(u16::from_le_bytes(arr))
     }
 
+    /// Reads a 24-bit value from `self`, using Little Endian byte order.
+    pub fn read_u24_le(&mut self) -> Result<u32, Error> {
+        let arr = self.read_array::<[u8; 3]>()?;
+        Ok(u32::from_le_bytes([arr[0], arr[1], arr[2], 0]))
+    }
+
     /// Reads a `u32` from `self`, using Little Endian byte order.
     pub fn read_u32_le(&mut self) -> Result<u32, Error> {
         let arr = self.read_array::<[u8; 4]>()?;
@@ -622,6 +640,7 @@ where
     }
 }
 
+#[cfg(feature = "defmt")]
 impl<PRIM, T> defmt::Format for Field<PRIM, T>
 where
     PRIM: zerocopy::FromBytes + Copy,
@@ -640,3 +659,68 @@ pub trait RawRepr<T> {
     fn from_raw(raw: T) -> Self;
     fn as_raw(&self) -> T;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u24_roundtrip() {
+        let mut buf = [0; 3];
+        let mut writer = ByteWriter::new(&mut buf);
+        writer.write_u24_le(0x12_3456).unwrap();
+        assert_eq!(writer.space_left(), 0);
+        assert_eq!(buf, [0x56, 0x34, 0x12]);
+        assert_eq!(ByteReader::new(&buf).read_u24_le(), Ok(0x12_3456));
+
+        let mut writer = ByteWriter::new(&mut buf);
+        assert_eq!(writer.write_u24_le(0x100_0000), Err(Error::InvalidValue));
+        assert_eq!(writer.space_left(), 3);
+    }
+
+    #[test]
+    fn short_reads() {
+        let data = [1, 2, 3, 4, 5, 6, 7];
+        for len in 0..data.len() {
+            let mut bytes = ByteReader::new(&data[..len]);
+            if len < 2 {
+                assert_eq!(bytes.read_u16_le(), Err(Error::Eof));
+            }
+            if len < 3 {
+                assert_eq!(bytes.read_u24_le(), Err(Error::Eof));
+            }
+            if len < 4 {
+                assert_eq!(bytes.read_u32_le(), Err(Error::Eof));
+            }
+            assert_eq!(bytes.read_u64_le(), Err(Error::Eof));
+            assert_eq!(bytes.read_slice(len + 1), Err(Error::Eof));
+
+            // Failed reads must not consume anything
+            assert_eq!(bytes.bytes_left(), len);
+            assert_eq!(bytes.read_slice(len), Ok(&data[..len]));
+        }
+    }
+
+    #[test]
+    fn short_writes() {
+        for len in 0..8 {
+            let mut buf = [0; 8];
+            let mut writer = ByteWriter::new(&mut buf[..len]);
+            if len < 2 {
+                assert_eq!(writer.write_u16_le(0xFFFF), Err(Error::Eof));
+            }
+            if len < 3 {
+                assert_eq!(writer.write_u24_le(0xFF_FFFF), Err(Error::Eof));
+            }
+            if len < 4 {
+                assert_eq!(writer.write_u32_le(0xFFFF_FFFF), Err(Error::Eof));
+            }
+            assert_eq!(writer.write_u64_le(u64::MAX), Err(Error::Eof));
+            assert_eq!(writer.write_slice(&[0xFF; 9][..len + 1]), Err(Error::Eof));
+
+            // Failed writes must not modify the buffer
+            assert_eq!(writer.space_left(), len);
+            assert_eq!(buf, [0; 8]);
+        }
+    }
+}
@@ -1,7 +1,14 @@
 //! Stack configuration trait.
 
-use crate::link::{queue::PacketQueue, Transmitter};
-use crate::{l2cap::ChannelMapper, time::Timer};
+use crate::aes::AesProvider;
+use crate::link::{
+    event::LinkEventHandler, queue::PacketQueue, scan::AdvReportHandler, CompanyId, Transmitter,
+};
+use crate::{
+    l2cap::ChannelMapper,
+    security::rng::Rng,
+    time::{Duration, Timer},
+};
 
 // TODO: Use associated type defaults in the trait once stable
 // https://github.com/rust-lang/rust/issues/29661
@@ -27,11 +34,74 @@ pub trait Config {
     /// The packet queue to use for exchanging data between the real-time Link-Layer and
     /// non-realtime parts of the stack.
     type PacketQueue: PacketQueue;
+
+    /// The AES-128 implementation used for encrypting connections.
+    ///
+    /// [`SoftAesProvider`] can be used on any platform.
+    ///
+    /// [`SoftAesProvider`]: crate::aes::SoftAesProvider
+    type Aes: AesProvider;
+
+    /// Receiver of the advertising reports generated while scanning.
+    ///
+    /// Devices that never scan can use `()`, which ignores all reports.
+    type AdvReportHandler: AdvReportHandler;
+
+    /// Receiver of connection events (see [`LinkLayer::set_event_handler`] and
+    /// [`Responder::set_event_handler`]).
+    ///
+    /// Applications that don't need events can use `()`, which ignores them.
+    ///
+    /// [`LinkLayer::set_event_handler`]: crate::link::LinkLayer::set_event_handler
+    /// [`Responder::set_event_handler`]: crate::link::Responder::set_event_handler
+    type LinkEventHandler: LinkEventHandler;
+
+    /// The random number generator used by the Link-Layer.
+    ///
+    /// It provides the `advDelay` added to advertising intervals and the scan request backoff, and
+    /// can generate access addresses for new connections.
+    type Rng: Rng;
+
+    /// Company identifier of the device manufacturer, sent in `LL_VERSION_IND` PDUs.
+    ///
+    /// Defaults to [`CompanyId::TESTING`], which must not be used in shipping products. This can
+    /// be overridden at runtime with [`LinkLayer::set_company_id`].
+    ///
+    /// [`LinkLayer::set_company_id`]: crate::link::LinkLayer::set_company_id
+    const COMPANY_ID: CompanyId = CompanyId::TESTING;
+
+    /// Worst-case accuracy of the clock used to time connection events, in ppm.
+    ///
+    /// Together with the master's sleep clock accuracy, this determines how much the receive window
+    /// is widened to tolerate clock drift. The default is suitable for crystal oscillators.
+    const SLEEP_CLOCK_ACCURACY_PPM: u32 = 50;
+
+    /// Shortest connection interval the device accepts.
+    ///
+    /// A `CONNECT_IND` with a shorter interval is ignored, and an `LL_CONNECTION_UPDATE_IND`
+    /// switching to one terminates the connection with *Unacceptable Connection Parameters*. The
+    /// default is the shortest interval allowed by the spec (7.5 ms).
+    const MIN_CONN_INTERVAL: Duration = Duration::micros(7_500);
+
+    /// Longest connection interval the device accepts (at most 4 s, the default).
+    const MAX_CONN_INTERVAL: Duration = Duration::millis(4_000);
+
+    /// Largest slave latency the device accepts (at most 499, the default).
+    const MAX_SLAVE_LATENCY: u16 = 499;
+
+    /// Longest supervision timeout the device accepts (at most 32 s, the default).
+    ///
+    /// Regardless of this setting, the timeout must exceed `(1 + latency) * interval * 2`.
+    const MAX_SUPERVISION_TIMEOUT: Duration = Duration::millis(32_000);
 }
 
 // Helper aliases to make accessing producer/consumer more convenient.
-pub(crate) type ConfProducer<C> = <<C as Config>::PacketQueue as PacketQueue>::Producer;
-pub(crate) type ConfConsumer<C> = <<C as Config>::PacketQueue as PacketQueue>::Consumer;
+
+/// The producer half of the packet queue used by config `C`.
+pub type ConfProducer<C> = <<C as Config>::PacketQueue as PacketQueue>::Producer;
+
+/// The consumer half of the packet queue used by config `C`.
+pub type ConfConsumer<C> = <<C as Config>::PacketQueue as PacketQueue>::Consumer;
 
 // (`C::PacketQueue::Producer` should work, but doesn't)
 // (see: https://github.com/rust-lang/rust/issues/22519)
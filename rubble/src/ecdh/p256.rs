@@ -7,6 +7,8 @@ use ::p256::{
     },
     ProjectivePoint, Scalar,
 };
+#[cfg(test)]
+use ::p256::{elliptic_curve::ops::Reduce, U256};
 use rand_core::{CryptoRng, RngCore};
 
 /// An ECDH provider using the pure-Rust `p256` crate.
@@ -55,7 +57,7 @@ impl P256SecretKey {
     #[cfg(test)]
     fn from_test_data(bytes: [u8; 32]) -> Self {
         Self {
-            inner: Scalar::from_bytes_reduced((&bytes).into()),
+            inner: <Scalar as Reduce<U256>>::reduce_bytes((&bytes).into()),
         }
     }
 }
@@ -1,7 +1,8 @@
 use core::fmt;
 
 /// Errors returned by the BLE stack.
-#[derive(Debug, PartialEq, Eq, defmt::Format)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum Error {
     /// Packet specified an invalid length value or was too short.
@@ -22,6 +23,16 @@ pub enum Error {
 
     /// Parsing didn't consume the entire buffer.
     IncompleteParse,
+
+    /// A queue is full, so the data could not be sent right now.
+    ///
+    /// Retrying after the queue was drained (eg. after the next connection event) might succeed.
+    WouldBlock,
+
+    /// Hardware did not respond in time.
+    ///
+    /// This is returned by drivers, eg. when the radio doesn't reach the expected state.
+    Timeout,
 }
 
 impl fmt::Display for Error {
@@ -31,6 +42,8 @@ impl fmt::Display for Error {
             Error::InvalidValue => "invalid value for field",
             Error::Eof => "end of buffer",
             Error::IncompleteParse => "excess data in buffer",
+            Error::WouldBlock => "queue full",
+            Error::Timeout => "hardware timed out",
         })
     }
 }
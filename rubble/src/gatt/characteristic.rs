@@ -1,7 +1,16 @@
-use crate::{att::AttUuid, uuid::Uuid16};
+use crate::att::AttUuid;
+use crate::uuid::{Uuid128, Uuid16};
 use bitflags::bitflags;
 
+/// Attribute type of the *Client Characteristic Configuration* descriptor (CCCD).
+///
+/// Characteristics that support notifications or indications must have a CCCD, which is placed
+/// after the characteristic value attribute. The `AttributeServer` stores the CCCD values written
+/// by the client, so the attribute provider doesn't need to handle writes to it.
+pub const CLIENT_CONFIG_UUID: AttUuid = AttUuid::Uuid16(Uuid16(0x2902));
+
 bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct Properties: u8 {
         const BROADCAST    = 0x01;
         const READ         = 0x02;
@@ -14,6 +23,17 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Value of a *Client Characteristic Configuration* descriptor.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ClientConfig: u16 {
+        /// The client wants to receive notifications.
+        const NOTIFY   = 0x0001;
+        /// The client wants to receive indications.
+        const INDICATE = 0x0002;
+    }
+}
+
 /// Bitwise or operation on `bitflags!` types that works in a `const` context.
 macro_rules! const_or {
     (
@@ -27,6 +47,44 @@ macro_rules! const_or {
     ) => { $first };
 }
 
+/// Creates the value of a characteristic declaration for a characteristic with a 16-bit UUID.
+///
+/// The declaration contains the characteristic's `properties`, the handle of the attribute
+/// holding the characteristic value, and the characteristic UUID. This is a `const fn`, so it can
+/// be used to initialize a `static` referenced by the declaration attribute.
+pub const fn declaration_value16(
+    properties: Properties,
+    value_handle: u16,
+    uuid: Uuid16,
+) -> [u8; 5] {
+    let handle = value_handle.to_le_bytes();
+    let uuid = uuid.0.to_le_bytes();
+    [properties.bits(), handle[0], handle[1], uuid[0], uuid[1]]
+}
+
+/// Creates the value of a characteristic declaration for a characteristic with a 128-bit UUID.
+///
+/// This is the 128-bit counterpart to [`declaration_value16`]. The UUID is stored in little-endian
+/// byte order, as required by ATT.
+pub const fn declaration_value128(
+    properties: Properties,
+    value_handle: u16,
+    uuid: Uuid128,
+) -> [u8; 19] {
+    let handle = value_handle.to_le_bytes();
+    let uuid = uuid.to_le_bytes();
+    let mut value = [0; 19];
+    value[0] = properties.bits();
+    value[1] = handle[0];
+    value[2] = handle[1];
+    let mut i = 0;
+    while i < 16 {
+        value[3 + i] = uuid[i];
+        i += 1;
+    }
+    value
+}
+
 pub trait Characteristic {
     const PROPS: Properties;
 
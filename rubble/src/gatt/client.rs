@@ -0,0 +1,480 @@
+//! GATT client for discovering the attributes hosted by a remote GATT server.
+//!
+//! The [`GattClient`] implements the discovery procedures of the Generic Attribute Profile: It
+//! creates the ATT requests that need to be sent to the server, and decodes the server's responses.
+//! Sending requests and routing responses back to the client is left to the application, so the
+//! client can be used with any transport.
+//!
+//! Every discovery procedure is performed by sending the [`Request`] returned by the method
+//! starting the procedure, and passing the server's response to [`GattClient::process_response`].
+//! As long as the procedure isn't finished, that method returns the next request to send. Results
+//! are reported to a [`DiscoveryHandler`] as they arrive.
+
+use crate::att::{AttPdu, AttUuid, ErrorCode, Handle, Opcode, RawHandleRange};
+use crate::bytes::{ByteReader, ByteWriter, FromBytes, ToBytes};
+use crate::gatt::characteristic::Properties;
+use crate::uuid::Uuid16;
+use crate::Error;
+
+/// Attribute type of primary service declarations.
+const PRIMARY_SERVICE: AttUuid = AttUuid::Uuid16(Uuid16(0x2800));
+
+/// Attribute type of characteristic declarations.
+const CHARACTERISTIC: AttUuid = AttUuid::Uuid16(Uuid16(0x2803));
+
+/// A primary service hosted by the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// Handle of the service declaration, the first attribute of the service.
+    pub start: Handle,
+
+    /// Handle of the last attribute belonging to the service.
+    pub end: Handle,
+
+    /// UUID identifying the service type.
+    pub uuid: AttUuid,
+}
+
+/// A characteristic hosted by the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CharacteristicInfo {
+    /// Handle of the characteristic declaration.
+    pub declaration: Handle,
+
+    /// The operations supported by the characteristic.
+    pub properties: Properties,
+
+    /// Handle of the attribute holding the characteristic value.
+    pub value_handle: Handle,
+
+    /// UUID identifying the characteristic type.
+    pub uuid: AttUuid,
+}
+
+/// An attribute found by characteristic descriptor discovery.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DescriptorInfo {
+    /// Handle of the descriptor.
+    pub handle: Handle,
+
+    /// The descriptor type.
+    pub uuid: AttUuid,
+}
+
+/// Trait for receivers of the results of discovery procedures.
+///
+/// All methods do nothing by default, so only the ones needed for the procedures in use have to be
+/// implemented.
+pub trait DiscoveryHandler {
+    /// Called for every primary service found by [`GattClient::discover_services`].
+    fn service(&mut self, _service: &ServiceInfo) {}
+
+    /// Called for every characteristic found by [`GattClient::discover_characteristics`].
+    fn characteristic(&mut self, _characteristic: &CharacteristicInfo) {}
+
+    /// Called for every descriptor found by [`GattClient::discover_descriptors`].
+    fn descriptor(&mut self, _descriptor: &DescriptorInfo) {}
+}
+
+/// An ATT request created by the [`GattClient`] that has to be sent to the server.
+#[derive(Debug)]
+pub struct Request(AttPdu<'static>);
+
+impl ToBytes for Request {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        self.0.to_bytes(writer)
+    }
+}
+
+/// The discovery procedure in progress.
+#[derive(Debug, Copy, Clone)]
+enum Procedure {
+    Idle,
+    Services { end: Handle },
+    Characteristics { end: Handle },
+    Descriptors { end: Handle },
+}
+
+/// Client performing GATT discovery procedures.
+#[derive(Debug)]
+pub struct GattClient {
+    procedure: Procedure,
+}
+
+impl GattClient {
+    /// Creates a new client that's not performing any procedure.
+    pub fn new() -> Self {
+        Self {
+            procedure: Procedure::Idle,
+        }
+    }
+
+    /// Returns whether a procedure is in progress.
+    ///
+    /// Starting a new procedure aborts the current one.
+    pub fn is_busy(&self) -> bool {
+        !matches!(self.procedure, Procedure::Idle)
+    }
+
+    /// Starts discovering all primary services on the server (*Discover All Primary Services*).
+    ///
+    /// Each discovered service is reported to [`DiscoveryHandler::service`].
+    pub fn discover_services(&mut self) -> Request {
+        let end = Handle::from_raw(0xFFFF);
+        self.procedure = Procedure::Services { end };
+        Self::read_by_group(Handle::from_raw(0x0001), end)
+    }
+
+    /// Starts discovering all characteristics of `service` (*Discover All Characteristics of a
+    /// Service*).
+    ///
+    /// Each discovered characteristic is reported to [`DiscoveryHandler::characteristic`].
+    pub fn discover_characteristics(&mut self, service: &ServiceInfo) -> Request {
+        self.procedure = Procedure::Characteristics { end: service.end };
+        Self::read_by_type(service.start, service.end)
+    }
+
+    /// Starts discovering the descriptors of a characteristic (*Discover All Characteristic
+    /// Descriptors*).
+    ///
+    /// Descriptors are located between the characteristic value and the end of the characteristic,
+    /// which is the handle before the next characteristic declaration (or the end of the service).
+    /// Each attribute in the range `start..=end` is reported to [`DiscoveryHandler::descriptor`].
+    pub fn discover_descriptors(&mut self, start: Handle, end: Handle) -> Request {
+        self.procedure = Procedure::Descriptors { end };
+        Self::find_information(start, end)
+    }
+
+    /// Processes a response sent by the server.
+    ///
+    /// Results contained in `response` are reported to `handler`. If the procedure isn't finished
+    /// yet, the next request to send is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if no procedure is in progress, if `response` isn't a response
+    /// to the request that was sent, or if the server responded with an error other than *Attribute
+    /// Not Found* (which ends the procedure normally). Malformed responses result in
+    /// `Error::InvalidLength` or `Error::Eof`. The procedure is aborted when an error is returned.
+    pub fn process_response(
+        &mut self,
+        response: &[u8],
+        handler: &mut impl DiscoveryHandler,
+    ) -> Result<Option<Request>, Error> {
+        let result = self.process(response, handler);
+        match result {
+            Ok(None) | Err(_) => self.procedure = Procedure::Idle,
+            Ok(Some(_)) => {}
+        }
+        result
+    }
+
+    fn process(
+        &mut self,
+        response: &[u8],
+        handler: &mut impl DiscoveryHandler,
+    ) -> Result<Option<Request>, Error> {
+        let pdu = AttPdu::from_bytes(&mut ByteReader::new(response))?;
+        let (expected, end) = match self.procedure {
+            Procedure::Idle => return Err(Error::InvalidValue),
+            Procedure::Services { end } => (Opcode::ReadByGroupReq, end),
+            Procedure::Characteristics { end } => (Opcode::ReadByTypeReq, end),
+            Procedure::Descriptors { end } => (Opcode::FindInformationReq, end),
+        };
+
+        // The handle of the last attribute included in the response
+        let last = match pdu {
+            AttPdu::ErrorRsp {
+                opcode, error_code, ..
+            } if opcode == expected => {
+                return match error_code {
+                    // No (more) attributes to discover
+                    ErrorCode::AttributeNotFound => Ok(None),
+                    _ => {
+                        debug!("GATT discovery failed: {:?}", error_code);
+                        Err(Error::InvalidValue)
+                    }
+                };
+            }
+            AttPdu::ReadByGroupRsp { length, data_list } if expected == Opcode::ReadByGroupReq => {
+                let mut last = None;
+                for_each_entry(length, data_list.as_ref(), |entry| {
+                    let service = ServiceInfo {
+                        start: Handle::from_bytes(entry)?,
+                        end: Handle::from_bytes(entry)?,
+                        uuid: AttUuid::from_bytes(entry)?,
+                    };
+                    handler.service(&service);
+                    last = Some(service.end);
+                    Ok(())
+                })?;
+                last
+            }
+            AttPdu::ReadByTypeRsp { length, data_list } if expected == Opcode::ReadByTypeReq => {
+                let mut last = None;
+                for_each_entry(length, data_list.as_ref(), |entry| {
+                    let characteristic = CharacteristicInfo {
+                        declaration: Handle::from_bytes(entry)?,
+                        properties: Properties::from_bits_retain(entry.read_u8()?),
+                        value_handle: Handle::from_bytes(entry)?,
+                        uuid: AttUuid::from_bytes(entry)?,
+                    };
+                    handler.characteristic(&characteristic);
+                    last = Some(characteristic.declaration);
+                    Ok(())
+                })?;
+                last
+            }
+            AttPdu::FindInformationRsp { format, data }
+                if expected == Opcode::FindInformationReq =>
+            {
+                let length = match format {
+                    0x01 => 2 + 2,
+                    0x02 => 2 + 16,
+                    _ => return Err(Error::InvalidValue),
+                };
+                let mut last = None;
+                for_each_entry(length, data.as_ref(), |entry| {
+                    let descriptor = DescriptorInfo {
+                        handle: Handle::from_bytes(entry)?,
+                        uuid: AttUuid::from_bytes(entry)?,
+                    };
+                    handler.descriptor(&descriptor);
+                    last = Some(descriptor.handle);
+                    Ok(())
+                })?;
+                last
+            }
+            _ => return Err(Error::InvalidValue),
+        };
+
+        // Continue after the last attribute the server has returned
+        let last = last.ok_or(Error::InvalidLength)?;
+        if last.as_u16() >= end.as_u16() {
+            return Ok(None);
+        }
+        let next = Handle::from_raw(last.as_u16() + 1);
+        Ok(Some(match self.procedure {
+            Procedure::Idle => unreachable!(),
+            Procedure::Services { .. } => Self::read_by_group(next, end),
+            Procedure::Characteristics { .. } => Self::read_by_type(next, end),
+            Procedure::Descriptors { .. } => Self::find_information(next, end),
+        }))
+    }
+
+    fn read_by_group(start: Handle, end: Handle) -> Request {
+        Request(AttPdu::ReadByGroupReq {
+            handle_range: RawHandleRange::new(start, end),
+            group_type: PRIMARY_SERVICE,
+        })
+    }
+
+    fn read_by_type(start: Handle, end: Handle) -> Request {
+        Request(AttPdu::ReadByTypeReq {
+            handle_range: RawHandleRange::new(start, end),
+            attribute_type: CHARACTERISTIC,
+        })
+    }
+
+    fn find_information(start: Handle, end: Handle) -> Request {
+        Request(AttPdu::FindInformationReq {
+            handle_range: RawHandleRange::new(start, end),
+        })
+    }
+}
+
+impl Default for GattClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Calls `f` with a reader for every `length`-Byte entry in a response's data list.
+fn for_each_entry(
+    length: u8,
+    data: &[u8],
+    mut f: impl FnMut(&mut ByteReader<'_>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let length = usize::from(length);
+    if length == 0 {
+        return Err(Error::InvalidLength);
+    }
+
+    let entries = data.chunks_exact(length);
+    if !entries.remainder().is_empty() {
+        return Err(Error::InvalidLength);
+    }
+
+    for entry in entries {
+        f(&mut ByteReader::new(entry))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uuid::Uuid128;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Results {
+        services: Vec<ServiceInfo>,
+        characteristics: Vec<CharacteristicInfo>,
+        descriptors: Vec<DescriptorInfo>,
+    }
+
+    impl DiscoveryHandler for Results {
+        fn service(&mut self, service: &ServiceInfo) {
+            self.services.push(*service);
+        }
+
+        fn characteristic(&mut self, characteristic: &CharacteristicInfo) {
+            self.characteristics.push(*characteristic);
+        }
+
+        fn descriptor(&mut self, descriptor: &DescriptorInfo) {
+            self.descriptors.push(*descriptor);
+        }
+    }
+
+    fn encode(req: &Request) -> Vec<u8> {
+        let mut buf = [0; 23];
+        let mut writer = ByteWriter::new(&mut buf);
+        req.to_bytes(&mut writer).unwrap();
+        let len = 23 - writer.space_left();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn discover_services() {
+        let mut client = GattClient::new();
+        let mut results = Results::default();
+
+        let req = client.discover_services();
+        assert_eq!(encode(&req), [0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]);
+
+        // 2 services with 16-bit UUIDs: 0x0001-0x0005 (0x1800), 0x0006-0x0009 (0x180F)
+        let req = client
+            .process_response(
+                &[
+                    0x11, 6, 0x01, 0x00, 0x05, 0x00, 0x00, 0x18, 0x06, 0x00, 0x09, 0x00, 0x0F, 0x18,
+                ],
+                &mut results,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(encode(&req), [0x10, 0x0A, 0x00, 0xFF, 0xFF, 0x00, 0x28]);
+
+        // 1 service with a 128-bit UUID: 0x000A-0x0010
+        let mut rsp = std::vec![0x11, 20, 0x0A, 0x00, 0x10, 0x00];
+        rsp.extend_from_slice(&[0xAB; 16]);
+        let req = client
+            .process_response(&rsp, &mut results)
+            .unwrap()
+            .unwrap();
+        assert_eq!(encode(&req), [0x10, 0x11, 0x00, 0xFF, 0xFF, 0x00, 0x28]);
+
+        // Error Response: Attribute Not Found
+        assert!(client
+            .process_response(&[0x01, 0x10, 0x11, 0x00, 0x0A], &mut results)
+            .unwrap()
+            .is_none());
+        assert!(!client.is_busy());
+
+        let uuid128 =
+            <Uuid128 as FromBytes>::from_bytes(&mut ByteReader::new(&[0xAB; 16])).unwrap();
+        let ranges = results
+            .services
+            .iter()
+            .map(|s| (s.start.as_u16(), s.end.as_u16(), s.uuid))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            [
+                (0x0001, 0x0005, Uuid16(0x1800).into()),
+                (0x0006, 0x0009, Uuid16(0x180F).into()),
+                (0x000A, 0x0010, uuid128.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn discover_characteristics_and_descriptors() {
+        let mut client = GattClient::new();
+        let mut results = Results::default();
+        let service = ServiceInfo {
+            start: Handle::from_raw(0x0006),
+            end: Handle::from_raw(0x0009),
+            uuid: Uuid16(0x180F).into(),
+        };
+
+        let req = client.discover_characteristics(&service);
+        assert_eq!(encode(&req), [0x08, 0x06, 0x00, 0x09, 0x00, 0x03, 0x28]);
+
+        // Battery Level at 0x0007 (declaration at 0x0006), read + notify
+        let req = client
+            .process_response(
+                &[0x09, 7, 0x06, 0x00, 0x12, 0x08, 0x00, 0x19, 0x2A],
+                &mut results,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(encode(&req), [0x08, 0x07, 0x00, 0x09, 0x00, 0x03, 0x28]);
+        assert!(client
+            .process_response(&[0x01, 0x08, 0x07, 0x00, 0x0A], &mut results)
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            results.characteristics,
+            [CharacteristicInfo {
+                declaration: Handle::from_raw(0x0006),
+                properties: Properties::READ | Properties::NOTIFY,
+                value_handle: Handle::from_raw(0x0008),
+                uuid: Uuid16(0x2A19).into(),
+            }]
+        );
+
+        // The descriptors follow the value and end with the service
+        let req = client.discover_descriptors(Handle::from_raw(0x0009), service.end);
+        assert_eq!(encode(&req), [0x04, 0x09, 0x00, 0x09, 0x00]);
+
+        // The last handle of the range was returned, so the procedure is complete
+        assert!(client
+            .process_response(&[0x05, 0x01, 0x09, 0x00, 0x02, 0x29], &mut results)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            results.descriptors,
+            [DescriptorInfo {
+                handle: Handle::from_raw(0x0009),
+                uuid: Uuid16(0x2902).into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unexpected_response() {
+        let mut client = GattClient::new();
+        let mut results = Results::default();
+
+        // No procedure in progress
+        assert_eq!(
+            client
+                .process_response(&[0x01, 0x10, 0x01, 0x00, 0x0A], &mut results)
+                .map(|req| req.is_some()),
+            Err(Error::InvalidValue)
+        );
+
+        // Errors other than Attribute Not Found abort the procedure
+        client.discover_services();
+        assert_eq!(
+            client
+                .process_response(&[0x01, 0x10, 0x01, 0x00, 0x0E], &mut results)
+                .map(|req| req.is_some()),
+            Err(Error::InvalidValue)
+        );
+        assert!(!client.is_busy());
+    }
+}
@@ -4,7 +4,11 @@
 //! interaction
 
 pub mod characteristic;
+pub mod client;
+pub mod server;
+pub mod services;
 
+use self::characteristic::Properties;
 use crate::att::{AttUuid, Attribute, AttributeProvider, Handle, HandleRange};
 use crate::uuid::{Uuid128, Uuid16};
 use crate::Error;
@@ -87,8 +91,18 @@ pub struct MidiServiceAttrs {
     attributes: [Attribute<&'static [u8]>; 4],
 }
 
-// MIDI Service (UUID: 03B80E5A-EDE8-4B33-A751-6CE34EC4C700)
-// MIDI Data I/O Characteristic (UUID: 7772E5DB-3868-4112-A1A9-F2669D106BF3)
+const MIDI_SERVICE_UUID: Uuid128 = Uuid128::parse_static("03b80e5a-ede8-4b33-a751-6ce34ec4c700");
+const MIDI_IO_UUID: Uuid128 = Uuid128::parse_static("7772e5db-3868-4112-a1a9-f2669d106bf3");
+
+static MIDI_SERVICE_DECL: [u8; 16] = MIDI_SERVICE_UUID.to_le_bytes();
+static MIDI_IO_DECL: [u8; 19] = characteristic::declaration_value128(
+    Properties::READ
+        .union(Properties::WRITE)
+        .union(Properties::WRITE_NO_RSP)
+        .union(Properties::NOTIFY),
+    0x0003,
+    MIDI_IO_UUID,
+);
 
 impl MidiServiceAttrs {
     pub fn new() -> Self {
@@ -97,55 +111,18 @@ impl MidiServiceAttrs {
                 Attribute::new(
                     Uuid16(0x2800).into(), // "Primary Service"
                     Handle::from_raw(0x0001),
-                    &[
-                        0x00, 0xC7, 0xC4, 0x4E, 0xE3, 0x6C, /* - */
-                        0x51, 0xA7, /* - */
-                        0x33, 0x4B, /* - */
-                        0xE8, 0xED, /* - */
-                        0x5A, 0x0E, 0xB8, 0x03,
-                    ], // "Midi Service"
+                    &MIDI_SERVICE_DECL, // "Midi Service"
                 ),
                 Attribute::new(
                     Uuid16(0x2803).into(), // "Characteristic"
                     Handle::from_raw(0x0002),
-                    &[
-                        0x02 | 0x08 | 0x04 | 0x10, // 1 byte properties: READ = 0x02, WRITE_REQ = 0x08, WRITE_CMD = 0x04, NOTIFICATION = 0x10
-                        0x03,
-                        0x00, // 2 bytes handle = 0x0003
-                        // the actual UUID
-                        0xF3,
-                        0x6B,
-                        0x10,
-                        0x9D,
-                        0x66,
-                        0xF2, /*-*/
-                        0xA9,
-                        0xA1, /*-*/
-                        0x12,
-                        0x41, /*-*/
-                        0x68,
-                        0x38, /*-*/
-                        0xDB,
-                        0xE5,
-                        0x72,
-                        0x77,
-                    ],
+                    &MIDI_IO_DECL, // "MIDI Data I/O"
                 ),
                 // Characteristic value (Empty Packet)
-                Attribute::new(
-                    AttUuid::Uuid128(Uuid128::from_bytes([
-                        0xF3, 0x6B, 0x10, 0x9D, 0x66, 0xF2, /*-*/
-                        0xA9, 0xA1, /*-*/
-                        0x12, 0x41, /*-*/
-                        0x68, 0x38, /*-*/
-                        0xDB, 0xE5, 0x72, 0x77,
-                    ])),
-                    Handle::from_raw(0x0003),
-                    &[],
-                ),
+                Attribute::new(MIDI_IO_UUID.into(), Handle::from_raw(0x0003), &[]),
                 // CCCD
                 Attribute::new(
-                    AttUuid::Uuid16(Uuid16(0x2902)),
+                    characteristic::CLIENT_CONFIG_UUID,
                     Handle::from_raw(0x0004),
                     &[0x00, 0x00],
                 ),
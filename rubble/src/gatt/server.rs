@@ -0,0 +1,674 @@
+//! A GATT server whose attribute handles are assigned automatically.
+//!
+//! Implementing [`AttributeProvider`] by hand (like [`BatteryServiceAttrs`] does) requires picking
+//! a handle for every attribute and encoding the service and characteristic declarations, which
+//! reference other handles. [`GattServerBuilder`] does this instead: services and characteristics
+//! are added in order, and each attribute is assigned the next free handle. The resulting
+//! [`GattServer`] can be hosted by the `AttributeServer` like any other `AttributeProvider`.
+//!
+//! ```
+//! use rubble::gatt::{characteristic::Properties, server::GattServerBuilder};
+//! use rubble::uuid::Uuid16;
+//!
+//! let mut builder = GattServerBuilder::<8>::new();
+//! let service = builder.primary_service(Uuid16(0x180F).into()).unwrap();
+//! let level = builder
+//!     .characteristic(Uuid16(0x2A19).into(), Properties::READ | Properties::NOTIFY, &[100])
+//!     .unwrap();
+//! let server = builder.build();
+//!
+//! assert_eq!(service.as_u16(), 0x0001);
+//! assert_eq!(level.declaration.as_u16(), 0x0002);
+//! assert_eq!(level.value.as_u16(), 0x0003);
+//! assert_eq!(level.cccd.unwrap().as_u16(), 0x0004);
+//! assert_eq!(server.value(level.value), Some(&[100][..]));
+//! ```
+//!
+//! [`BatteryServiceAttrs`]: super::BatteryServiceAttrs
+
+use crate::att::{
+    AttUuid, Attribute, AttributeAccessPermissions, AttributeProvider, AttributeSecurity,
+    ErrorCode, Handle, HandleRange, ServiceChange,
+};
+use crate::gatt::characteristic::{self, Properties};
+use crate::uuid::Uuid16;
+use crate::Error;
+use core::{cmp, mem};
+use heapless::Vec;
+
+/// Maximum length of a value that can be written to a [`GattServer`] attribute.
+///
+/// Values passed to [`GattServerBuilder::characteristic`] may be longer, but can then not be
+/// changed.
+pub const MAX_VALUE_LEN: usize = 20;
+
+const PRIMARY_SERVICE_UUID: AttUuid = AttUuid::Uuid16(Uuid16(0x2800));
+const CHARACTERISTIC_UUID: AttUuid = AttUuid::Uuid16(Uuid16(0x2803));
+
+/// The value of an attribute stored in a [`GattServer`].
+enum Value {
+    /// Value provided by the application when building the server.
+    Static(&'static [u8]),
+
+    /// Value computed by the builder or written at runtime.
+    Owned(Vec<u8, MAX_VALUE_LEN>),
+}
+
+impl Value {
+    fn owned(value: &[u8]) -> Result<Self, Error> {
+        Vec::from_slice(value)
+            .map(Value::Owned)
+            .map_err(|_| Error::InvalidLength)
+    }
+}
+
+impl AsRef<[u8]> for Value {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Value::Static(value) => value,
+            Value::Owned(value) => value,
+        }
+    }
+}
+
+struct Entry {
+    attr: Attribute<Value>,
+    readable: bool,
+    writable: bool,
+    security: AttributeSecurity,
+}
+
+/// Handles of the attributes making up a characteristic.
+#[derive(Debug, Copy, Clone)]
+pub struct CharacteristicHandles {
+    /// Handle of the characteristic declaration.
+    pub declaration: Handle,
+
+    /// Handle of the attribute holding the characteristic value.
+    ///
+    /// This is the handle to pass to `AttributeServerTx::notify` and `indicate`.
+    pub value: Handle,
+
+    /// Handle of the *Client Characteristic Configuration Descriptor*, if the characteristic
+    /// supports notifications or indications.
+    pub cccd: Option<Handle>,
+}
+
+/// Builds a [`GattServer`] holding up to `N` attributes.
+///
+/// Every service takes 1 attribute, every characteristic 2, plus 1 for the CCCD of notifiable or
+/// indicatable characteristics.
+pub struct GattServerBuilder<const N: usize> {
+    entries: Vec<Entry, N>,
+    next_handle: u32,
+    service_changed: Option<Handle>,
+}
+
+impl<const N: usize> GattServerBuilder<N> {
+    /// Creates a builder for an empty attribute table.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_handle: 0x0001,
+            service_changed: None,
+        }
+    }
+
+    /// Adds a primary service, and returns the handle of its declaration.
+    ///
+    /// All characteristics added after this belong to the service, until the next service is
+    /// added.
+    pub fn primary_service(&mut self, uuid: AttUuid) -> Result<Handle, Error> {
+        let handle = self.next_handle()?;
+        self.primary_service_at(handle, uuid)
+    }
+
+    /// Adds a primary service whose declaration has the fixed handle `handle`.
+    ///
+    /// This leaves a gap in the handle space, which can be used to keep handles stable when
+    /// services are added or removed in later firmware versions. Subsequent attributes are
+    /// assigned handles following `handle`.
+    ///
+    /// Returns `Error::InvalidValue` if `handle` overlaps the attributes added so far.
+    pub fn primary_service_at(&mut self, handle: Handle, uuid: AttUuid) -> Result<Handle, Error> {
+        if u32::from(handle.as_u16()) < self.next_handle {
+            return Err(Error::InvalidValue);
+        }
+
+        let value = match uuid {
+            AttUuid::Uuid16(uuid) => Value::owned(&uuid.0.to_le_bytes())?,
+            AttUuid::Uuid128(uuid) => Value::owned(&uuid.to_le_bytes())?,
+        };
+        self.next_handle = u32::from(handle.as_u16());
+        self.push(PRIMARY_SERVICE_UUID, value, true, false)
+    }
+
+    /// Adds a characteristic to the last service.
+    ///
+    /// The characteristic declaration and value are assigned consecutive handles. If `properties`
+    /// contains `NOTIFY` or `INDICATE`, a CCCD is added after the value, which is managed by the
+    /// `AttributeServer`. The value can be read by clients only if `properties` contains `READ`,
+    /// written only if it contains `WRITE` or `WRITE_NO_RSP`, and updated by the application via
+    /// [`GattServer::set_value`].
+    ///
+    /// Returns `Error::InvalidValue` if no service has been added yet, or `Error::Eof` if the
+    /// table can't hold all attributes of the characteristic. In both cases, nothing is added.
+    pub fn characteristic(
+        &mut self,
+        uuid: AttUuid,
+        properties: Properties,
+        value: &'static [u8],
+    ) -> Result<CharacteristicHandles, Error> {
+        self.add_characteristic(uuid, properties, Value::Static(value))
+    }
+
+    /// Adds a characteristic whose initial value is copied into the attribute table.
+    ///
+    /// Returns `Error::InvalidLength` if `value` is longer than [`MAX_VALUE_LEN`].
+    pub(crate) fn characteristic_owned(
+        &mut self,
+        uuid: AttUuid,
+        properties: Properties,
+        value: &[u8],
+    ) -> Result<CharacteristicHandles, Error> {
+        self.add_characteristic(uuid, properties, Value::owned(value)?)
+    }
+
+    fn add_characteristic(
+        &mut self,
+        uuid: AttUuid,
+        properties: Properties,
+        value: Value,
+    ) -> Result<CharacteristicHandles, Error> {
+        if self.entries.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+
+        // Check that all attributes fit, so that a failure doesn't leave a declaration behind
+        let has_cccd = properties.intersects(Properties::NOTIFY | Properties::INDICATE);
+        let attrs = if has_cccd { 3 } else { 2 };
+        if u16::try_from(self.next_handle + attrs - 1).is_err() {
+            return Err(Error::InvalidValue);
+        }
+        if self.entries.len() + attrs as usize > N {
+            return Err(Error::Eof);
+        }
+
+        let value_handle = (self.next_handle + 1) as u16;
+        let decl = match uuid {
+            AttUuid::Uuid16(uuid) => Value::owned(&characteristic::declaration_value16(
+                properties,
+                value_handle,
+                uuid,
+            ))?,
+            AttUuid::Uuid128(uuid) => Value::owned(&characteristic::declaration_value128(
+                properties,
+                value_handle,
+                uuid,
+            ))?,
+        };
+
+        let declaration = self.push(CHARACTERISTIC_UUID, decl, true, false)?;
+        let value = self.push(
+            uuid,
+            value,
+            properties.contains(Properties::READ),
+            properties.intersects(Properties::WRITE | Properties::WRITE_NO_RSP),
+        )?;
+        let cccd = if has_cccd {
+            Some(self.push(
+                characteristic::CLIENT_CONFIG_UUID,
+                Value::Static(&[0x00, 0x00]),
+                true,
+                true,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(CharacteristicHandles {
+            declaration,
+            value,
+            cccd,
+        })
+    }
+
+    /// Adds a primary service containing only read-only characteristics with fixed values.
+    ///
+    /// This is a shorthand for informational services like *Device Information*. Returns the
+    /// handle of the service declaration.
+    pub fn read_only_service(
+        &mut self,
+        uuid: AttUuid,
+        characteristics: &[(AttUuid, &'static [u8])],
+    ) -> Result<Handle, Error> {
+        let service = self.primary_service(uuid)?;
+        for (uuid, value) in characteristics {
+            self.characteristic(*uuid, Properties::READ, value)?;
+        }
+        Ok(service)
+    }
+
+    /// Restricts access to the attribute at `handle` to connections meeting `security`.
+    ///
+    /// This is typically applied to the value handle of a characteristic. Returns
+    /// `Error::InvalidValue` if there is no attribute at `handle`.
+    pub fn require_security(
+        &mut self,
+        handle: Handle,
+        security: AttributeSecurity,
+    ) -> Result<(), Error> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.attr.handle == handle)
+            .ok_or(Error::InvalidValue)?;
+        entry.security = security;
+        Ok(())
+    }
+
+    /// Finishes building and returns the attribute table.
+    pub fn build(self) -> GattServer<N> {
+        GattServer {
+            entries: self.entries,
+            next_handle: self.next_handle,
+            service_changed: self.service_changed,
+            changed: None,
+        }
+    }
+
+    /// Makes the server indicate changes of the attribute table via the *Service Changed*
+    /// characteristic whose value has handle `value`.
+    pub(crate) fn set_service_changed(&mut self, value: Handle) {
+        self.service_changed = Some(value);
+    }
+
+    fn next_handle(&self) -> Result<Handle, Error> {
+        u16::try_from(self.next_handle)
+            .map(Handle::from_raw)
+            .map_err(|_| Error::InvalidValue)
+    }
+
+    /// Appends an attribute with the next free handle.
+    ///
+    /// Returns `Error::InvalidValue` if the handle space is exhausted, or `Error::Eof` if the
+    /// table is full.
+    fn push(
+        &mut self,
+        att_type: AttUuid,
+        value: Value,
+        readable: bool,
+        writable: bool,
+    ) -> Result<Handle, Error> {
+        let handle = self.next_handle()?;
+        self.entries
+            .push(Entry {
+                attr: Attribute::new(att_type, handle, value),
+                readable,
+                writable,
+                security: AttributeSecurity::empty(),
+            })
+            .map_err(|_| Error::Eof)?;
+        self.next_handle += 1;
+        Ok(handle)
+    }
+}
+
+impl<const N: usize> Default for GattServerBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An attribute table created by a [`GattServerBuilder`].
+///
+/// Services can be added and removed at runtime. If the table contains the *Service Changed*
+/// characteristic (see [`GenericAttributeService`]), the affected handles are indicated to the
+/// client by the `AttributeServer` once it has subscribed.
+///
+/// [`GenericAttributeService`]: super::services::GenericAttributeService
+pub struct GattServer<const N: usize> {
+    entries: Vec<Entry, N>,
+    next_handle: u32,
+    service_changed: Option<Handle>,
+
+    /// Range of handles changed since the client confirmed the last *Service Changed* indication.
+    changed: Option<(Handle, Handle)>,
+}
+
+impl<const N: usize> GattServer<N> {
+    /// Returns the current value of the attribute at `handle`.
+    pub fn value(&self, handle: Handle) -> Option<&[u8]> {
+        self.entry(handle).map(|e| e.attr.value())
+    }
+
+    /// Changes the value of the attribute at `handle`.
+    ///
+    /// This does not notify subscribed clients. Use `AttributeServerTx::notify` for that.
+    ///
+    /// Returns `Error::InvalidValue` if there is no attribute at `handle`, and
+    /// `Error::InvalidLength` if `value` is longer than [`MAX_VALUE_LEN`].
+    pub fn set_value(&mut self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        let value = Value::owned(value)?;
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.attr.handle == handle)
+            .ok_or(Error::InvalidValue)?;
+        entry.attr.set_value(value);
+        Ok(())
+    }
+
+    /// Adds services to the end of the attribute table.
+    ///
+    /// `f` is called with a builder for adding the services, which are assigned handles following
+    /// the existing attributes. If `f` fails, the table is left unchanged and the error is
+    /// returned. Otherwise, the handles of the new attributes are reported as changed.
+    pub fn add_services<R>(
+        &mut self,
+        f: impl FnOnce(&mut GattServerBuilder<N>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let len = self.entries.len();
+        let start = self.next_handle;
+        let mut builder = GattServerBuilder {
+            entries: mem::take(&mut self.entries),
+            next_handle: self.next_handle,
+            service_changed: self.service_changed,
+        };
+        let result = f(&mut builder);
+        self.entries = builder.entries;
+        match result {
+            Ok(result) => {
+                if self.entries.len() > len {
+                    self.next_handle = builder.next_handle;
+                    let first = Handle::from_raw(start as u16);
+                    self.mark_changed(first, self.entries.last().unwrap().attr.handle);
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                self.entries.truncate(len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes the primary service declared at `service`, including all its characteristics.
+    ///
+    /// The handles of the removed attributes are reported as changed. Returns
+    /// `Error::InvalidValue` if `service` is not the handle of a service declaration.
+    pub fn remove_service(&mut self, service: Handle) -> Result<(), Error> {
+        let end = self.group_end(service).ok_or(Error::InvalidValue)?.handle;
+        self.entries.retain(|e| {
+            let handle = e.attr.handle.as_u16();
+            handle < service.as_u16() || handle > end.as_u16()
+        });
+        self.mark_changed(service, end);
+        Ok(())
+    }
+
+    /// Adds the handles from `start` to `end` to the range reported via *Service Changed*.
+    fn mark_changed(&mut self, start: Handle, end: Handle) {
+        self.changed = Some(match self.changed {
+            Some((s, e)) => (
+                Handle::from_raw(cmp::min(s.as_u16(), start.as_u16())),
+                Handle::from_raw(cmp::max(e.as_u16(), end.as_u16())),
+            ),
+            None => (start, end),
+        });
+    }
+
+    fn entry(&self, handle: Handle) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.attr.handle == handle)
+    }
+}
+
+impl<const N: usize> AttributeProvider for GattServer<N> {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for entry in &self.entries {
+            if entry.attr.handle.as_u16() > range.end().as_u16() {
+                break;
+            }
+            if range.contains(entry.attr.handle) {
+                f(self, &entry.attr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == PRIMARY_SERVICE_UUID
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+        let start = self.entries.iter().position(|e| e.attr.handle == handle)?;
+        if self.entries[start].attr.att_type != PRIMARY_SERVICE_UUID {
+            return None;
+        }
+
+        // The group extends up to the next service declaration
+        let len = self.entries[start + 1..]
+            .iter()
+            .take_while(|e| e.attr.att_type != PRIMARY_SERVICE_UUID)
+            .count();
+        Some(&self.entries[start + len].attr)
+    }
+
+    fn attr_access_permissions(&self, handle: Handle) -> AttributeAccessPermissions {
+        match self.entry(handle).map(|e| (e.readable, e.writable)) {
+            Some((true, true)) => AttributeAccessPermissions::ReadableAndWriteable,
+            Some((true, false)) => AttributeAccessPermissions::Readable,
+            Some((false, true)) => AttributeAccessPermissions::Writeable,
+            Some((false, false)) => AttributeAccessPermissions::Inaccessible,
+            None => AttributeAccessPermissions::Readable,
+        }
+    }
+
+    fn attr_security(&self, handle: Handle) -> AttributeSecurity {
+        self.entry(handle)
+            .map_or(AttributeSecurity::empty(), |e| e.security)
+    }
+
+    fn on_write(&mut self, handle: Handle, offset: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        if offset != 0 {
+            return Err(ErrorCode::InvalidOffset);
+        }
+
+        self.set_value(handle, value).map_err(|err| match err {
+            Error::InvalidLength => ErrorCode::InvalidAttributeValueLength,
+            _ => ErrorCode::InvalidHandle,
+        })
+    }
+
+    fn service_change(&self) -> Option<ServiceChange> {
+        let (start, end) = self.changed?;
+        Some(ServiceChange {
+            characteristic: self.service_changed?,
+            start,
+            end,
+        })
+    }
+
+    fn service_change_indicated(&mut self, change: ServiceChange) {
+        if self.changed == Some((change.start, change.end)) {
+            self.changed = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uuid::Uuid128;
+
+    const CUSTOM_UUID: Uuid128 = Uuid128::parse_static("7772e5db-3868-4112-a1a9-f2669d106bf3");
+
+    fn handles<const N: usize>(server: &mut GattServer<N>) -> std::vec::Vec<(u16, AttUuid)> {
+        let mut handles = std::vec::Vec::new();
+        server
+            .for_attrs_in_range(
+                HandleRange::new(Handle::from_raw(1), Handle::from_raw(0xFFFF)),
+                |_, attr| {
+                    handles.push((attr.handle.as_u16(), attr.att_type));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        handles
+    }
+
+    #[test]
+    fn sequential_layout() {
+        let mut builder = GattServerBuilder::<8>::new();
+        let battery = builder.primary_service(Uuid16(0x180F).into()).unwrap();
+        let level = builder
+            .characteristic(
+                Uuid16(0x2A19).into(),
+                Properties::READ | Properties::NOTIFY,
+                &[100],
+            )
+            .unwrap();
+        let info = builder
+            .read_only_service(Uuid16(0x180A).into(), &[(Uuid16(0x2A29).into(), b"rubble")])
+            .unwrap();
+        let mut server = builder.build();
+
+        assert_eq!(battery.as_u16(), 0x0001);
+        assert_eq!(level.declaration.as_u16(), 0x0002);
+        assert_eq!(level.value.as_u16(), 0x0003);
+        assert_eq!(level.cccd.map(|h| h.as_u16()), Some(0x0004));
+        assert_eq!(info.as_u16(), 0x0005);
+        assert_eq!(
+            handles(&mut server),
+            [
+                (0x0001, Uuid16(0x2800).into()),
+                (0x0002, Uuid16(0x2803).into()),
+                (0x0003, Uuid16(0x2A19).into()),
+                (0x0004, characteristic::CLIENT_CONFIG_UUID),
+                (0x0005, Uuid16(0x2800).into()),
+                (0x0006, Uuid16(0x2803).into()),
+                (0x0007, Uuid16(0x2A29).into()),
+            ]
+        );
+
+        // Declarations reference the right handles
+        assert_eq!(server.value(battery), Some(&[0x0F, 0x18][..]));
+        assert_eq!(
+            server.value(level.declaration),
+            Some(&[0x12, 0x03, 0x00, 0x19, 0x2A][..])
+        );
+        assert_eq!(
+            server.value(Handle::from_raw(0x0006)),
+            Some(&[0x02, 0x07, 0x00, 0x29, 0x2A][..])
+        );
+        assert_eq!(server.value(Handle::from_raw(0x0007)), Some(&b"rubble"[..]));
+
+        // Service groups end at the last attribute before the next service
+        let end = |server: &GattServer<8>, h| {
+            server
+                .group_end(Handle::from_raw(h))
+                .map(|a| a.handle.as_u16())
+        };
+        assert_eq!(end(&server, 0x0001), Some(0x0004));
+        assert_eq!(end(&server, 0x0005), Some(0x0007));
+        assert_eq!(end(&server, 0x0002), None);
+    }
+
+    #[test]
+    fn fixed_service_handles() {
+        let mut builder = GattServerBuilder::<8>::new();
+        builder.primary_service(Uuid16(0x180F).into()).unwrap();
+        let custom = builder
+            .primary_service_at(Handle::from_raw(0x0010), CUSTOM_UUID.into())
+            .unwrap();
+        let io = builder
+            .characteristic(CUSTOM_UUID.into(), Properties::WRITE, &[])
+            .unwrap();
+
+        assert_eq!(custom.as_u16(), 0x0010);
+        assert_eq!(io.value.as_u16(), 0x0012);
+        assert!(io.cccd.is_none());
+
+        // Handles may not go backwards or overlap existing attributes
+        assert_eq!(
+            builder
+                .primary_service_at(Handle::from_raw(0x0012), Uuid16(0x180A).into())
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        let mut server = builder.build();
+        assert_eq!(
+            server.value(io.declaration).unwrap(),
+            &characteristic::declaration_value128(Properties::WRITE, 0x0012, CUSTOM_UUID)[..]
+        );
+        assert!(matches!(
+            server.attr_access_permissions(io.value),
+            AttributeAccessPermissions::Writeable
+        ));
+        server.on_write(io.value, 0, &[1, 2, 3]).unwrap();
+        assert_eq!(server.value(io.value), Some(&[1, 2, 3][..]));
+        assert!(matches!(
+            server.on_write(io.value, 0, &[0; MAX_VALUE_LEN + 1]),
+            Err(ErrorCode::InvalidAttributeValueLength)
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let mut builder = GattServerBuilder::<3>::new();
+        assert_eq!(
+            builder
+                .characteristic(Uuid16(0x2A19).into(), Properties::READ, &[])
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        builder.primary_service(Uuid16(0x180F).into()).unwrap();
+        builder
+            .characteristic(Uuid16(0x2A19).into(), Properties::READ, &[])
+            .unwrap();
+        assert_eq!(
+            builder.primary_service(Uuid16(0x180A).into()).unwrap_err(),
+            Error::Eof
+        );
+    }
+
+    #[test]
+    fn notify_only() {
+        let mut builder = GattServerBuilder::<5>::new();
+        builder.primary_service(Uuid16(0x180F).into()).unwrap();
+        let level = builder
+            .characteristic(Uuid16(0x2A19).into(), Properties::NOTIFY, &[100])
+            .unwrap();
+
+        // A characteristic that doesn't fit adds nothing
+        assert_eq!(
+            builder
+                .characteristic(Uuid16(0x2A1A).into(), Properties::READ, &[])
+                .unwrap_err(),
+            Error::Eof
+        );
+        let service = builder.primary_service(Uuid16(0x180A).into()).unwrap();
+        assert_eq!(service.as_u16(), 0x0005);
+
+        let mut server = builder.build();
+        assert_eq!(handles(&mut server).len(), 5);
+        assert!(matches!(
+            server.attr_access_permissions(level.declaration),
+            AttributeAccessPermissions::Readable
+        ));
+        assert!(matches!(
+            server.attr_access_permissions(level.value),
+            AttributeAccessPermissions::Inaccessible
+        ));
+        assert!(matches!(
+            server.attr_access_permissions(level.cccd.unwrap()),
+            AttributeAccessPermissions::ReadableAndWriteable
+        ));
+    }
+}
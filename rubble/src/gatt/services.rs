@@ -0,0 +1,281 @@
+//! Ready-made implementations of services defined by the Bluetooth SIG.
+//!
+//! The services are added to a [`GattServerBuilder`] alongside any application-specific services,
+//! and keep the handles of their characteristics so that values can be updated later.
+
+use crate::att::Handle;
+use crate::gatt::characteristic::Properties;
+use crate::gatt::server::{CharacteristicHandles, GattServer, GattServerBuilder};
+use crate::l2cap::{ChannelMapper, L2CAPState};
+use crate::link::queue::Producer;
+use crate::uuid::Uuid16;
+use crate::Error;
+
+/// The *Generic Attribute Service* (`0x1801`), announcing changes of the attribute table.
+///
+/// Clients may cache the attributes they discovered. The service contains the *Service Changed*
+/// characteristic (`0x2A05`), which is indicated to tell clients which handles they have to
+/// discover again. It should be the first service added to the builder, so that its handles stay
+/// the same when other services change.
+///
+/// Services added or removed via [`GattServer::add_services`] and [`GattServer::remove_service`]
+/// are indicated automatically once the client has subscribed. The subscription of a bonded client
+/// has to be saved and restored across connections (see `AttributeServer::subscriptions`), so
+/// that it is also told about changes made while it was disconnected.
+pub struct GenericAttributeService {
+    service_changed: CharacteristicHandles,
+}
+
+impl GenericAttributeService {
+    /// Adds the service to `builder`.
+    pub fn new<const N: usize>(builder: &mut GattServerBuilder<N>) -> Result<Self, Error> {
+        builder.primary_service(Uuid16(0x1801).into())?;
+        let service_changed =
+            builder.characteristic_owned(Uuid16(0x2A05).into(), Properties::INDICATE, &[0; 4])?;
+        builder.set_service_changed(service_changed.value);
+        Ok(Self { service_changed })
+    }
+
+    /// Returns the handle of the *Service Changed* characteristic value.
+    pub fn service_changed_handle(&self) -> Handle {
+        self.service_changed.value
+    }
+}
+
+/// The *Battery Service* (`0x180F`), exposing the battery charge of the device.
+///
+/// The service contains a single *Battery Level* characteristic (`0x2A19`), which can be read and
+/// supports notifications.
+pub struct BatteryService {
+    level: CharacteristicHandles,
+}
+
+impl BatteryService {
+    /// Adds the service to `builder`, with an initial battery level of `level` percent.
+    ///
+    /// Returns `Error::InvalidValue` if `level` is greater than 100.
+    pub fn new<const N: usize>(
+        builder: &mut GattServerBuilder<N>,
+        level: u8,
+    ) -> Result<Self, Error> {
+        if level > 100 {
+            return Err(Error::InvalidValue);
+        }
+
+        builder.primary_service(Uuid16(0x180F).into())?;
+        let level = builder.characteristic_owned(
+            Uuid16(0x2A19).into(),
+            Properties::READ | Properties::NOTIFY,
+            &[level],
+        )?;
+        Ok(Self { level })
+    }
+
+    /// Returns the handle of the *Battery Level* characteristic value.
+    pub fn level_handle(&self) -> Handle {
+        self.level.value
+    }
+
+    /// Updates the battery level to `level` percent and notifies subscribed clients.
+    ///
+    /// The notification is put into the TX packet queue `tx` (the producer passed to the
+    /// `Responder`). Returns `Ok(true)` if a notification was queued, and `Ok(false)` if no client
+    /// is subscribed. In both cases, the new value is returned by subsequent reads.
+    ///
+    /// Returns `Error::InvalidValue` if `level` is greater than 100, and `Error::WouldBlock` if the
+    /// TX queue is full (in which case the value is still updated).
+    pub fn set_battery_level<M, P, const N: usize>(
+        &self,
+        l2cap: &mut L2CAPState<M>,
+        tx: &mut P,
+        level: u8,
+    ) -> Result<bool, Error>
+    where
+        M: ChannelMapper<AttributeProvider = GattServer<N>>,
+        P: Producer,
+    {
+        if level > 100 {
+            return Err(Error::InvalidValue);
+        }
+
+        l2cap
+            .channel_mapper()
+            .att()
+            .into_protocol()
+            .provider()
+            .set_value(self.level.value, &[level])?;
+        l2cap.tx(tx).notify(self.level.value, &[level])
+    }
+}
+
+/// The *Device Information Service* (`0x180A`), identifying the device and its firmware.
+///
+/// Contains the *Manufacturer Name String* (`0x2A29`), *Model Number String* (`0x2A24`) and
+/// *Firmware Revision String* (`0x2A26`) characteristics, which are read-only.
+pub struct DeviceInformationService {
+    service: Handle,
+}
+
+impl DeviceInformationService {
+    /// Adds the service to `builder`.
+    pub fn new<const N: usize>(
+        builder: &mut GattServerBuilder<N>,
+        manufacturer: &'static str,
+        model: &'static str,
+        firmware: &'static str,
+    ) -> Result<Self, Error> {
+        let service = builder.read_only_service(
+            Uuid16(0x180A).into(),
+            &[
+                (Uuid16(0x2A29).into(), manufacturer.as_bytes()),
+                (Uuid16(0x2A24).into(), model.as_bytes()),
+                (Uuid16(0x2A26).into(), firmware.as_bytes()),
+            ],
+        )?;
+        Ok(Self { service })
+    }
+
+    /// Returns the handle of the service declaration.
+    pub fn service_handle(&self) -> Handle {
+        self.service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l2cap::BleChannelMap;
+    use crate::link::queue::{
+        Consume, Consumer, PacketQueue, SimpleConsumer, SimpleProducer, SimpleQueue,
+    };
+    use crate::security::NoSecurity;
+
+    /// Sends an ATT PDU from the client to the server.
+    fn send<M: ChannelMapper, P: Producer>(l2cap: &mut L2CAPState<M>, tx: &mut P, pdu: &[u8]) {
+        let mut message = std::vec::Vec::new();
+        message.extend_from_slice(&(pdu.len() as u16).to_le_bytes());
+        message.extend_from_slice(&[0x04, 0x00]);
+        message.extend_from_slice(pdu);
+        l2cap.tx(tx).process_start(&message).into_result().unwrap();
+    }
+
+    /// Receives the raw ATT PDU sent by the server.
+    fn recv(rx: &mut SimpleConsumer<'_>) -> std::vec::Vec<u8> {
+        rx.consume_raw_with(|_, raw| Consume::always(Ok(raw[4..].to_vec())))
+            .unwrap()
+    }
+
+    #[test]
+    fn battery_level() {
+        let mut builder = GattServerBuilder::<16>::new();
+        let battery = BatteryService::new(&mut builder, 80).unwrap();
+        let info = DeviceInformationService::new(&mut builder, "rubble", "demo", "1.0").unwrap();
+        assert_eq!(battery.level_handle().as_u16(), 0x0003);
+        assert_eq!(info.service_handle().as_u16(), 0x0005);
+        assert!(BatteryService::new(&mut builder, 101).is_err());
+
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(builder.build()));
+
+        // Read Request
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 80]);
+        send(&mut l2cap, &mut tx, &[0x0A, 0x09, 0x00]);
+        assert_eq!(recv(&mut rx), b"\x0Bdemo");
+
+        // Without a subscription, only the value changes
+        assert_eq!(
+            battery.set_battery_level(&mut l2cap, &mut tx, 70),
+            Ok(false)
+        );
+        assert!(rx.consume_raw_with(|_, _| Consume::always(Ok(()))).is_err());
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 70]);
+
+        // Enable notifications in the CCCD
+        send(&mut l2cap, &mut tx, &[0x12, 0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(recv(&mut rx), [0x13]);
+
+        assert_eq!(battery.set_battery_level(&mut l2cap, &mut tx, 60), Ok(true));
+        assert_eq!(recv(&mut rx), [0x1B, 0x03, 0x00, 60]);
+        send(&mut l2cap, &mut tx, &[0x0A, 0x03, 0x00]);
+        assert_eq!(recv(&mut rx), [0x0B, 60]);
+
+        assert_eq!(
+            battery.set_battery_level(&mut l2cap, &mut tx, 101),
+            Err(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn service_changed() {
+        let mut builder = GattServerBuilder::<24>::new();
+        let gatt = GenericAttributeService::new(&mut builder).unwrap();
+        let battery = BatteryService::new(&mut builder, 80).unwrap();
+        assert_eq!(gatt.service_changed_handle().as_u16(), 0x0003);
+        assert_eq!(battery.level_handle().as_u16(), 0x0007);
+
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(builder.build()));
+        let add_info = |l2cap: &mut L2CAPState<BleChannelMap<GattServer<24>, NoSecurity>>| {
+            let server = l2cap.channel_mapper().att().into_protocol();
+            server
+                .provider()
+                .add_services(|b| DeviceInformationService::new(b, "rubble", "demo", "1.0"))
+                .unwrap();
+        };
+        let indicate = |l2cap: &mut L2CAPState<BleChannelMap<GattServer<24>, NoSecurity>>,
+                        tx: &mut SimpleProducer<'_>| {
+            l2cap.tx(tx).att().unwrap().indicate_service_change()
+        };
+
+        // The client isn't subscribed, so the change isn't indicated yet
+        add_info(&mut l2cap);
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(false));
+        assert!(!rx.has_data());
+
+        // Once it enables indications in the CCCD, the added service is indicated
+        send(&mut l2cap, &mut tx, &[0x12, 0x04, 0x00, 0x02, 0x00]);
+        assert_eq!(recv(&mut rx), [0x13]);
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(true));
+        assert_eq!(recv(&mut rx), [0x1D, 0x03, 0x00, 0x09, 0x00, 0x0F, 0x00]);
+
+        // Changes made before the confirmation arrives are indicated afterwards, joined with the
+        // unconfirmed range
+        let server = l2cap.channel_mapper().att().into_protocol();
+        server
+            .provider()
+            .remove_service(Handle::from_raw(0x0005))
+            .unwrap();
+        assert!(!server.service_change_pending());
+        send(&mut l2cap, &mut tx, &[0x1E]);
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(true));
+        assert_eq!(recv(&mut rx), [0x1D, 0x03, 0x00, 0x05, 0x00, 0x0F, 0x00]);
+        send(&mut l2cap, &mut tx, &[0x1E]);
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(false));
+
+        // The subscription of a bonded client is restored when it reconnects, and it is told about
+        // changes made in the meantime
+        let server = l2cap.channel_mapper().att().into_protocol();
+        let saved = std::vec::Vec::from(server.subscriptions());
+        l2cap.connection_closed();
+        add_info(&mut l2cap);
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(false));
+        let server = l2cap.channel_mapper().att().into_protocol();
+        server.restore_subscriptions(&saved).unwrap();
+        assert!(server.service_change_pending());
+        assert_eq!(indicate(&mut l2cap, &mut tx), Ok(true));
+        assert_eq!(recv(&mut rx), [0x1D, 0x03, 0x00, 0x10, 0x00, 0x16, 0x00]);
+
+        // Removing anything but a service fails
+        let server = l2cap.channel_mapper().att().into_protocol();
+        assert_eq!(
+            server
+                .provider()
+                .remove_service(gatt.service_changed_handle()),
+            Err(Error::InvalidValue)
+        );
+    }
+}
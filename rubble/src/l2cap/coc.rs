@@ -0,0 +1,615 @@
+//! LE credit-based connection-oriented channels (CoC).
+//!
+//! A connection-oriented channel is a dynamically allocated L2CAP channel that is opened by the
+//! peer via an *LE Credit Based Connection Request* addressed to a *Protocol/Service Multiplexer*
+//! (`LE_PSM`) the local device is listening on.
+//!
+//! Applications exchange *Service Data Units* (SDUs) of up to [`MAX_SDU_SIZE`] Bytes over the
+//! channel. SDUs are segmented into *K-frames* of at most *MPS* (Maximum PDU Payload Size) Bytes,
+//! the first of which starts with the 2-Byte length of the SDU. Every K-frame consumes one credit
+//! of the sending device. Once a device has run out of credits, it has to wait until the receiver
+//! sends an *LE Flow Control Credit* packet on the signaling channel.
+//!
+//! Only a single connection-oriented channel can be open at a time.
+
+use super::signaling::{Command, ConnectionResult, Packet};
+use super::{Channel, ChannelData, Header, Protocol, ProtocolObj, Sender};
+use crate::link::MIN_DATA_PAYLOAD_BUF;
+use crate::{bytes::*, Error};
+use heapless::Vec;
+
+/// The largest SDU that can be sent or received (the MTU of the channel).
+pub const MAX_SDU_SIZE: usize = 128;
+
+/// The largest K-frame payload we can receive (the MPS of the channel).
+///
+/// Every K-frame has to fit into a single Link-Layer data PDU.
+pub const MPS: u16 = (MIN_DATA_PAYLOAD_BUF - Header::SIZE as usize) as u16;
+
+/// Smallest MTU and MPS values allowed by the specification.
+const MIN_MTU: u16 = 23;
+
+/// The CID allocated for the channel (the first dynamically allocated LE CID).
+const LOCAL_CID: Channel = Channel(0x0040);
+
+/// Number of credits we give to the peer.
+///
+/// This is enough to send a single SDU of maximum size, which is all that fits into the reassembly
+/// buffer.
+const RX_CREDITS: u16 = (MAX_SDU_SIZE + 2).div_ceil(MPS as usize) as u16;
+
+/// Trait for receivers of the SDUs sent over a connection-oriented channel.
+pub trait CocHandler {
+    /// Called when the peer has opened the channel.
+    fn connected(&mut self, channel: &CocChannel) {
+        let _ = channel;
+    }
+
+    /// Called when the channel was closed by either device or the connection was closed.
+    fn disconnected(&mut self) {}
+
+    /// Called with every SDU received on the channel, after it was reassembled from its K-frames.
+    fn sdu_received(&mut self, sdu: &[u8]);
+}
+
+/// Discards all received SDUs.
+impl CocHandler for () {
+    fn sdu_received(&mut self, _sdu: &[u8]) {}
+}
+
+/// Parameters of an open connection-oriented channel.
+#[derive(Debug)]
+struct Connection {
+    /// The peer's CID, to which all K-frames are sent.
+    remote: Channel,
+    /// The largest SDU the peer can receive.
+    remote_mtu: u16,
+    /// The largest K-frame payload the peer can receive.
+    remote_mps: u16,
+    /// Number of K-frames we may still send.
+    tx_credits: u16,
+    /// Number of K-frames the peer may still send.
+    rx_credits: u16,
+}
+
+/// An LE credit-based connection-oriented channel.
+///
+/// The channel is opened by the peer once [`listen`] has been called. SDUs can then be sent via
+/// [`L2CAPStateTx::coc`], and received SDUs are passed to the [`CocHandler`] of the channel map.
+///
+/// [`listen`]: CocChannel::listen
+/// [`L2CAPStateTx::coc`]: super::L2CAPStateTx::coc
+#[derive(Debug)]
+pub struct CocChannel {
+    psm: Option<u16>,
+    connection: Option<Connection>,
+
+    /// Reassembly buffer for the SDU that is being received.
+    rx: Vec<u8, MAX_SDU_SIZE>,
+    /// Length of the SDU that is being received, from its first K-frame.
+    rx_sdu_len: Option<u16>,
+    /// Number of K-frames received since the last credits were given to the peer.
+    rx_frames: u16,
+
+    /// The SDU that is being sent.
+    tx: Vec<u8, MAX_SDU_SIZE>,
+    /// Number of Bytes of `tx` already sent, or `None` if no SDU is pending.
+    tx_sent: Option<usize>,
+}
+
+impl CocChannel {
+    pub(super) fn new() -> Self {
+        Self {
+            psm: None,
+            connection: None,
+            rx: Vec::new(),
+            rx_sdu_len: None,
+            rx_frames: 0,
+            tx: Vec::new(),
+            tx_sent: None,
+        }
+    }
+
+    /// Accepts incoming connection requests for `psm`.
+    ///
+    /// `psm` must be a valid `LE_PSM` (`0x0001`-`0x00FF`), otherwise `Error::InvalidValue` is
+    /// returned.
+    pub fn listen(&mut self, psm: u16) -> Result<(), Error> {
+        if !(0x0001..=0x00FF).contains(&psm) {
+            return Err(Error::InvalidValue);
+        }
+
+        self.psm = Some(psm);
+        Ok(())
+    }
+
+    /// Returns the `LE_PSM` the channel is listening on.
+    pub fn psm(&self) -> Option<u16> {
+        self.psm
+    }
+
+    /// Returns whether the channel is currently open.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Returns the number of K-frames that can be sent before more credits are needed.
+    pub fn credits(&self) -> u16 {
+        self.connection.as_ref().map_or(0, |conn| conn.tx_credits)
+    }
+
+    /// Returns the largest SDU the peer can receive, or `None` if the channel isn't open.
+    pub fn remote_mtu(&self) -> Option<u16> {
+        self.connection.as_ref().map(|conn| conn.remote_mtu)
+    }
+
+    /// Returns whether an SDU is still waiting to be sent (eg. because the peer hasn't given us
+    /// enough credits yet).
+    pub fn is_sending(&self) -> bool {
+        self.tx_sent.is_some()
+    }
+
+    /// Closes the channel without notifying the peer.
+    ///
+    /// This must be called when the Link-Layer connection is closed. The channel keeps listening
+    /// on its `LE_PSM`.
+    pub fn reset(&mut self) {
+        self.close();
+    }
+
+    /// Prepares for sending K-frames using `sender`.
+    ///
+    /// It is usually not necessary to use this function. Instead, call `L2CAPStateTx::coc`.
+    pub fn with_sender<'a>(&'a mut self, sender: Sender<'a>) -> CocChannelTx<'a> {
+        CocChannelTx {
+            channel: self,
+            sender,
+        }
+    }
+
+    pub(super) fn mtu(&self) -> u16 {
+        MAX_SDU_SIZE as u16
+    }
+
+    pub(super) fn mps(&self) -> u16 {
+        MPS
+    }
+
+    pub(super) fn rx_credits(&self) -> u16 {
+        self.connection.as_ref().map_or(0, |conn| conn.rx_credits)
+    }
+
+    pub(super) fn local_channel(&self) -> Option<Channel> {
+        self.connection.as_ref().map(|_| LOCAL_CID)
+    }
+
+    pub(super) fn remote_channel(&self) -> Option<Channel> {
+        self.connection.as_ref().map(|conn| conn.remote)
+    }
+
+    /// Returns the `ChannelData` for sending K-frames, or `None` if the channel isn't open.
+    pub(super) fn channel_data(&mut self) -> Option<ChannelData<'_, Self>> {
+        let conn = self.connection.as_ref()?;
+        Some(ChannelData {
+            response_channel: conn.remote,
+            pdu: conn.remote_mps.min(MPS) as u8,
+            protocol: self,
+        })
+    }
+
+    /// Handles an *LE Credit Based Connection Request*.
+    ///
+    /// Returns the local CID of the opened channel, or the reason for refusing the request.
+    pub(super) fn accept(
+        &mut self,
+        psm: u16,
+        source: Channel,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+    ) -> Result<Channel, ConnectionResult> {
+        if self.psm != Some(psm) {
+            return Err(ConnectionResult::PsmNotSupported);
+        }
+        if self.connection.is_some() {
+            return Err(ConnectionResult::NoResources);
+        }
+        if !(0x0040..=0x007F).contains(&source.as_raw()) {
+            return Err(ConnectionResult::InvalidSourceCid);
+        }
+        if mtu < MIN_MTU || !(MIN_MTU..=65533).contains(&mps) {
+            return Err(ConnectionResult::UnacceptableParameters);
+        }
+
+        self.close();
+        self.connection = Some(Connection {
+            remote: source,
+            remote_mtu: mtu,
+            remote_mps: mps,
+            tx_credits: credits,
+            rx_credits: RX_CREDITS,
+        });
+        Ok(LOCAL_CID)
+    }
+
+    /// Handles an *LE Flow Control Credit* packet.
+    ///
+    /// Returns an error if the credit count would exceed 65535.
+    pub(super) fn add_credits(&mut self, credits: u16) -> Result<(), Error> {
+        if let Some(conn) = &mut self.connection {
+            conn.tx_credits = conn
+                .tx_credits
+                .checked_add(credits)
+                .ok_or(Error::InvalidValue)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the channel and discards all buffered data.
+    pub(super) fn close(&mut self) {
+        self.connection = None;
+        self.rx.clear();
+        self.rx_sdu_len = None;
+        self.rx_frames = 0;
+        self.tx.clear();
+        self.tx_sent = None;
+    }
+
+    /// Processes a received K-frame.
+    ///
+    /// Returns the number of credits to give back to the peer once an SDU has been received
+    /// completely (or discarded).
+    fn receive(&mut self, frame: &[u8], handler: &mut impl CocHandler) -> Option<u16> {
+        let conn = self.connection.as_mut()?;
+        if conn.rx_credits == 0 {
+            warn!("peer sent K-frame without credits, ignoring");
+            return None;
+        }
+        conn.rx_credits -= 1;
+        self.rx_frames += 1;
+
+        let mut bytes = ByteReader::new(frame);
+        let sdu_len = match self.rx_sdu_len {
+            Some(len) => len,
+            None => {
+                // First K-frame of an SDU
+                let len = bytes.read_u16_le().unwrap_or(u16::MAX);
+                self.rx.clear();
+                self.rx_sdu_len = Some(len);
+                len
+            }
+        };
+
+        let data = bytes.read_rest();
+        let complete = if usize::from(sdu_len) > MAX_SDU_SIZE
+            || frame.len() > usize::from(MPS)
+            || self.rx.extend_from_slice(data).is_err()
+            || self.rx.len() > usize::from(sdu_len)
+        {
+            warn!("malformed SDU on channel {:?}, discarding", LOCAL_CID);
+            true
+        } else if self.rx.len() == usize::from(sdu_len) {
+            handler.sdu_received(&self.rx);
+            true
+        } else {
+            false
+        };
+
+        if !complete {
+            return None;
+        }
+
+        // The reassembly buffer is free again, give the peer the credits to send the next SDU
+        self.rx.clear();
+        self.rx_sdu_len = None;
+        let credits = core::mem::replace(&mut self.rx_frames, 0);
+        conn.rx_credits += credits;
+        Some(credits)
+    }
+}
+
+/// The protocol receiving the K-frames sent to a connection-oriented channel.
+///
+/// Responses to K-frames (credits given back to the peer) are sent on the LE Signaling Channel.
+pub(super) struct CocEndpoint<H: CocHandler> {
+    pub(super) channel: CocChannel,
+    pub(super) handler: H,
+    next_identifier: u8,
+}
+
+impl<H: CocHandler> CocEndpoint<H> {
+    pub(super) fn new(handler: H) -> Self {
+        Self {
+            channel: CocChannel::new(),
+            handler,
+            next_identifier: 1,
+        }
+    }
+
+    /// Returns a new identifier for a signaling request.
+    pub(super) fn next_identifier(&mut self) -> u8 {
+        let id = self.next_identifier;
+        // 0 is not a valid identifier
+        self.next_identifier = self.next_identifier.checked_add(1).unwrap_or(1);
+        id
+    }
+}
+
+impl<H: CocHandler> ProtocolObj for CocEndpoint<H> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        if let Some(credits) = self.channel.receive(message, &mut self.handler) {
+            let identifier = self.next_identifier();
+            responder.send(Packet {
+                identifier,
+                command: Command::FlowControlCredit {
+                    channel: LOCAL_CID,
+                    credits,
+                },
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<H: CocHandler> Protocol for CocEndpoint<H> {
+    // LE Flow Control Credit packet
+    const RSP_PDU_SIZE: u8 = 4 + 4;
+}
+
+/// A `CocChannel` with the ability to send K-frames.
+pub struct CocChannelTx<'a> {
+    channel: &'a mut CocChannel,
+    sender: Sender<'a>,
+}
+
+impl<'a> CocChannelTx<'a> {
+    /// Starts sending an SDU to the peer.
+    ///
+    /// The SDU is segmented into K-frames, which are sent as long as we have credits left and
+    /// there is space in the TX queue. The rest of the SDU is sent when the peer gives us more
+    /// credits.
+    ///
+    /// Returns `Ok(false)` if the previous SDU hasn't been sent completely yet, in which case the
+    /// SDU is not sent. Returns `Error::InvalidLength` if `sdu` is larger than the peer's MTU or
+    /// [`MAX_SDU_SIZE`].
+    pub fn send(&mut self, sdu: &[u8]) -> Result<bool, Error> {
+        let remote_mtu = self.channel.remote_mtu().unwrap_or(0);
+        if sdu.len() > usize::from(remote_mtu) || sdu.len() > MAX_SDU_SIZE {
+            return Err(Error::InvalidLength);
+        }
+        if self.channel.is_sending() {
+            return Ok(false);
+        }
+
+        self.channel.tx.clear();
+        self.channel.tx.extend_from_slice(sdu).unwrap();
+        self.channel.tx_sent = Some(0);
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Sends as many K-frames of the pending SDU as credits and TX queue space allow.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let channel = &mut *self.channel;
+        while let Some(sent) = channel.tx_sent {
+            let conn = match &mut channel.connection {
+                Some(conn) if conn.tx_credits > 0 => conn,
+                _ => break,
+            };
+
+            let sdu = &channel.tx;
+            let result = self.sender.send_with(|writer| -> Result<usize, Error> {
+                if sent == 0 {
+                    // First K-frame of the SDU
+                    writer.write_u16_le(sdu.len() as u16)?;
+                }
+                Ok(writer.write_slice_truncate(&sdu[sent..]))
+            });
+
+            match result {
+                Ok(len) => {
+                    conn.tx_credits -= 1;
+                    let sent = sent + len;
+                    channel.tx_sent = if sent == sdu.len() { None } else { Some(sent) };
+                }
+                // The TX queue is full, continue later
+                Err(Error::Eof) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::att::NoAttributes;
+    use crate::l2cap::{BleChannelMap, L2CAPState};
+    use crate::link::queue::{Consume, Consumer, PacketQueue, SimpleConsumer, SimpleQueue};
+    use crate::security::NoSecurity;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Sdus(Vec<Vec<u8>>, bool);
+
+    impl CocHandler for Sdus {
+        fn sdu_received(&mut self, sdu: &[u8]) {
+            self.0.push(sdu.to_vec());
+        }
+
+        fn disconnected(&mut self) {
+            self.1 = true;
+        }
+    }
+
+    /// Builds an L2CAP message addressed to `channel`.
+    fn message(channel: u16, payload: &[u8]) -> Vec<u8> {
+        let mut message = (payload.len() as u16).to_le_bytes().to_vec();
+        message.extend_from_slice(&channel.to_le_bytes());
+        message.extend_from_slice(payload);
+        message
+    }
+
+    /// Receives a raw L2CAP message (including header) sent by us.
+    fn recv(rx: &mut SimpleConsumer<'_>) -> Vec<u8> {
+        rx.consume_raw_with(|_, raw| Consume::always(Ok(raw.to_vec())))
+            .unwrap()
+    }
+
+    /// Opens a channel on PSM 0x0080, with the peer using CID 0x0041 and an MTU of 100.
+    fn connect(credits: u16) -> L2CAPState<BleChannelMap<NoAttributes, NoSecurity, Sdus>> {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty().with_coc_handler(Sdus::default()));
+        l2cap.channel_mapper().coc_channel().listen(0x0080).unwrap();
+
+        let mut req = std::vec![0x14, 0x01, 10, 0, 0x80, 0x00, 0x41, 0x00, 100, 0, 23, 0];
+        req.extend_from_slice(&credits.to_le_bytes());
+        l2cap
+            .tx(&mut tx)
+            .process_start(&message(0x0005, &req))
+            .into_result()
+            .unwrap();
+
+        let mut rsp = std::vec![0x15, 0x01, 10, 0, 0x40, 0x00, MAX_SDU_SIZE as u8, 0, 23, 0];
+        rsp.extend_from_slice(&RX_CREDITS.to_le_bytes());
+        rsp.extend_from_slice(&[0, 0]);
+        assert_eq!(recv(&mut rx), message(0x0005, &rsp));
+
+        assert!(l2cap.channel_mapper().coc_channel().is_connected());
+        l2cap
+    }
+
+    #[test]
+    fn refuse_unknown_psm() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+
+        let req = [
+            0x14, 0x01, 10, 0, 0x80, 0x00, 0x41, 0x00, 100, 0, 23, 0, 1, 0,
+        ];
+        l2cap
+            .tx(&mut tx)
+            .process_start(&message(0x0005, &req))
+            .into_result()
+            .unwrap();
+
+        let rsp = [0x15, 0x01, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x00];
+        assert_eq!(recv(&mut rx), message(0x0005, &rsp));
+    }
+
+    #[test]
+    fn sdu_spanning_three_frames() {
+        let mut l2cap = connect(10);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let sdu = (0..50).collect::<Vec<u8>>();
+
+        // Receive an SDU in 3 K-frames (21 + 23 + 6 Bytes)
+        let mut first = 50u16.to_le_bytes().to_vec();
+        first.extend_from_slice(&sdu[..21]);
+        for frame in [&first[..], &sdu[21..44], &sdu[44..]] {
+            l2cap
+                .tx(&mut tx)
+                .process_start(&message(0x0040, frame))
+                .into_result()
+                .unwrap();
+        }
+        assert_eq!(
+            l2cap.channel_mapper().coc_handler().0,
+            std::slice::from_ref(&sdu)
+        );
+
+        // The 3 credits are given back to the peer
+        let credits = [0x16, 0x01, 4, 0, 0x40, 0x00, 3, 0];
+        assert_eq!(recv(&mut rx), message(0x0005, &credits));
+        assert!(!rx.has_data());
+
+        // Send it back (the queue only has space for a single K-frame)
+        assert_eq!(l2cap.tx(&mut tx).coc().unwrap().send(&sdu), Ok(true));
+        assert_eq!(recv(&mut rx), message(0x0041, &first));
+        l2cap.tx(&mut tx).coc().unwrap().flush().unwrap();
+        assert_eq!(recv(&mut rx), message(0x0041, &sdu[21..44]));
+        l2cap.tx(&mut tx).coc().unwrap().flush().unwrap();
+        assert_eq!(recv(&mut rx), message(0x0041, &sdu[44..]));
+        assert!(!l2cap.channel_mapper().coc_channel().is_sending());
+        assert_eq!(l2cap.channel_mapper().coc_channel().credits(), 7);
+    }
+
+    #[test]
+    fn credit_starvation() {
+        let mut l2cap = connect(2);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let sdu = [0xAB; 50];
+
+        assert_eq!(l2cap.tx(&mut tx).coc().unwrap().send(&sdu), Ok(true));
+        assert_eq!(recv(&mut rx).len(), 4 + 23);
+        l2cap.tx(&mut tx).coc().unwrap().flush().unwrap();
+        assert_eq!(recv(&mut rx).len(), 4 + 23);
+        l2cap.tx(&mut tx).coc().unwrap().flush().unwrap();
+        assert!(!rx.has_data(), "sent a K-frame without credits");
+
+        let channel = l2cap.channel_mapper().coc_channel();
+        assert_eq!(channel.credits(), 0);
+        assert!(channel.is_sending());
+        assert_eq!(l2cap.tx(&mut tx).coc().unwrap().send(&sdu), Ok(false));
+        assert!(!rx.has_data());
+
+        // Once the peer gives us a credit, the last K-frame is sent
+        let credits = [0x16, 0x01, 4, 0, 0x41, 0x00, 1, 0];
+        l2cap
+            .tx(&mut tx)
+            .process_start(&message(0x0005, &credits))
+            .into_result()
+            .unwrap();
+        assert_eq!(recv(&mut rx), message(0x0041, &sdu[44..]));
+        assert!(!l2cap.channel_mapper().coc_channel().is_sending());
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut l2cap = connect(1);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+
+        let req = [0x06, 0x02, 4, 0, 0x40, 0x00, 0x41, 0x00];
+        l2cap
+            .tx(&mut tx)
+            .process_start(&message(0x0005, &req))
+            .into_result()
+            .unwrap();
+        let rsp = [0x07, 0x02, 4, 0, 0x40, 0x00, 0x41, 0x00];
+        assert_eq!(recv(&mut rx), message(0x0005, &rsp));
+        assert!(!l2cap.channel_mapper().coc_channel().is_connected());
+        assert!(l2cap.tx(&mut tx).coc().is_none());
+    }
+
+    #[test]
+    fn connection_closed() {
+        let mut l2cap = connect(1);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+
+        l2cap.connection_closed();
+        assert!(!l2cap.channel_mapper().coc_channel().is_connected());
+        assert!(l2cap.channel_mapper().coc_handler().1);
+        assert!(l2cap.tx(&mut tx).coc().is_none());
+        assert!(!rx.has_data(), "reset must not notify the peer");
+
+        // The channel still listens on its PSM, so the next connection can open it again
+        let req = [
+            0x14, 0x01, 10, 0, 0x80, 0x00, 0x41, 0x00, 100, 0, 23, 0, 1, 0,
+        ];
+        l2cap
+            .tx(&mut tx)
+            .process_start(&message(0x0005, &req))
+            .into_result()
+            .unwrap();
+        assert_eq!(recv(&mut rx)[4..6], [0x15, 0x01]);
+        assert!(l2cap.channel_mapper().coc_channel().is_connected());
+    }
+}
@@ -20,16 +20,33 @@
 //!
 //! [l2c]: https://www.bluetooth.com/specifications/assigned-numbers/logical-link-control
 
+mod coc;
 mod signaling;
 
-use self::signaling::SignalingState;
+pub(crate) use self::signaling::write_conn_param_update_req;
+
+pub use self::coc::{CocChannel, CocChannelTx, CocHandler, MAX_SDU_SIZE, MPS};
+pub use self::signaling::SignalingState;
 use crate::att::{self, AttributeProvider, AttributeServer, NoAttributes};
+use crate::link::llcp::ConnectionParamRequest;
 use crate::link::queue::{Consume, Producer};
 use crate::link::{data::Llid, MIN_DATA_PAYLOAD_BUF};
-use crate::security::{NoSecurity, SecurityLevel, SecurityManager};
+use crate::security::{LinkSecurity, NoSecurity, PairingIo, SecurityLevel, SecurityManager};
 use crate::{bytes::*, utils::HexSlice, Error};
-use core::fmt;
 use core::ops::{Deref, DerefMut};
+use core::{cmp, fmt};
+use heapless::Vec;
+
+/// The largest MTU supported on any L2CAP channel.
+///
+/// PDUs that don't fit in a single Link-Layer data PDU of `MIN_DATA_PAYLOAD_BUF` Bytes are
+/// fragmented. A PDU of this size, including the 4-Byte L2CAP header, takes up 10 data PDUs.
+pub const MAX_MTU: u16 = 247;
+
+/// The largest L2CAP PDU payload that can be reassembled from multiple Link-Layer data PDUs.
+///
+/// Larger PDUs are dropped.
+pub const REASSEMBLY_BUF_SIZE: usize = 256;
 
 /// An L2CAP channel identifier (CID).
 ///
@@ -97,6 +114,7 @@ impl fmt::Debug for Channel {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for Channel {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "{:#06X}", self.0)
@@ -120,11 +138,17 @@ pub trait ChannelMapper {
     /// The attribute provider used by the ATT server.
     type AttributeProvider: AttributeProvider;
 
+    /// The handler receiving SDUs sent over the connection-oriented channel.
+    type CocHandler: CocHandler;
+
     /// Look up what's connected to `channel` (eg. the `Protocol` to which to forward).
     fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>>;
 
     /// Returns information about the Attribute Protocol on channel `0x0004`.
     fn att(&mut self) -> ChannelData<'_, AttributeServer<Self::AttributeProvider>>;
+
+    /// Returns information about the LE Signaling Channel `0x0005`.
+    fn signaling(&mut self) -> ChannelData<'_, SignalingState<Self::CocHandler>>;
 }
 
 /// Data associated with a connected L2CAP channel.
@@ -149,14 +173,15 @@ impl<'a> ChannelData<'a, dyn ProtocolObj + 'a> {
     /// Creates a `ChannelData` carrying a dynamically-dispatched `dyn ProtocolObj` from a concrete
     /// `Protocol` implementor `T`.
     fn new_dyn<T: Protocol + 'a>(response_channel: Channel, protocol: &'a mut T) -> Self {
+        let pdu = protocol.rsp_pdu_size();
         assert!(
-            usize::from(T::RSP_PDU_SIZE + Header::SIZE) <= MIN_DATA_PAYLOAD_BUF,
-            "protocol min PDU is smaller than data channel PDU (L2CAP reassembly NYI)"
+            u16::from(pdu) <= MAX_MTU,
+            "protocol PDU is larger than MAX_MTU"
         );
 
         ChannelData {
             response_channel,
-            pdu: T::RSP_PDU_SIZE,
+            pdu,
             protocol,
         }
     }
@@ -164,14 +189,15 @@ impl<'a> ChannelData<'a, dyn ProtocolObj + 'a> {
 
 impl<'a, P: Protocol> ChannelData<'a, P> {
     fn new(response_channel: Channel, protocol: &'a mut P) -> Self {
+        let pdu = protocol.rsp_pdu_size();
         assert!(
-            usize::from(P::RSP_PDU_SIZE + Header::SIZE) <= MIN_DATA_PAYLOAD_BUF,
-            "protocol min PDU is smaller than data channel PDU (L2CAP reassembly NYI)"
+            u16::from(pdu) <= MAX_MTU,
+            "protocol PDU is larger than MAX_MTU"
         );
 
         ChannelData {
             response_channel,
-            pdu: P::RSP_PDU_SIZE,
+            pdu,
             protocol,
         }
     }
@@ -209,18 +235,26 @@ impl<'a, P: ?Sized> ChannelData<'a, P> {
     }
 }
 
-/// A fixed BLE channel map that provides only the required channel endpoints and does not allow
-/// dynamic channels.
+/// A BLE channel map that provides the required channel endpoints and a single dynamic channel.
 ///
 /// The channels are mapped as follows (no other channels are supported):
 ///
 /// * `0x0004`: Attribute protocol (ATT).
 /// * `0x0005`: LE L2CAP signaling channel.
 /// * `0x0006`: LE Security Manager protocol.
-pub struct BleChannelMap<A: AttributeProvider, S: SecurityLevel> {
+/// * `0x0040`: LE credit-based connection-oriented channel, if opened by the peer. SDUs received on
+///   this channel are passed to the `CocHandler` `H`.
+///
+/// The Security Manager interacts with the user during pairing through the `PairingIo` `P`.
+pub struct BleChannelMap<
+    A: AttributeProvider,
+    S: SecurityLevel,
+    H: CocHandler = (),
+    P: PairingIo = (),
+> {
     att: AttributeServer<A>,
-    signaling: SignalingState,
-    sm: SecurityManager<S>,
+    signaling: SignalingState<H>,
+    sm: SecurityManager<S, P>,
 }
 
 impl BleChannelMap<NoAttributes, NoSecurity> {
@@ -232,7 +266,7 @@ impl BleChannelMap<NoAttributes, NoSecurity> {
     pub fn empty() -> Self {
         Self {
             att: AttributeServer::new(NoAttributes),
-            signaling: SignalingState::new(),
+            signaling: SignalingState::new(()),
             sm: SecurityManager::no_security(),
         }
     }
@@ -242,25 +276,78 @@ impl<A: AttributeProvider> BleChannelMap<A, NoSecurity> {
     pub fn with_attributes(att: A) -> Self {
         Self {
             att: AttributeServer::new(att),
-            signaling: SignalingState::new(),
+            signaling: SignalingState::new(()),
             sm: SecurityManager::no_security(),
         }
     }
+}
 
+impl<A: AttributeProvider, H: CocHandler> BleChannelMap<A, NoSecurity, H> {
     /// Provides mutable access to the underlying `AttributeProvider`.
     pub fn attribute_provider(&mut self) -> &mut A {
         self.att.provider()
     }
 }
 
-impl<A: AttributeProvider, S: SecurityLevel> ChannelMapper for BleChannelMap<A, S> {
+impl<A: AttributeProvider, S: SecurityLevel, P: PairingIo> BleChannelMap<A, S, (), P> {
+    /// Creates a channel map hosting the attributes `att` and using the security manager `sm` to
+    /// handle pairing requests.
+    pub fn with_security_manager(att: A, sm: SecurityManager<S, P>) -> Self {
+        Self {
+            att: AttributeServer::new(att),
+            signaling: SignalingState::new(()),
+            sm,
+        }
+    }
+
+    /// Passes the SDUs received on the connection-oriented channel to `handler`.
+    pub fn with_coc_handler<H: CocHandler>(self, handler: H) -> BleChannelMap<A, S, H, P> {
+        BleChannelMap {
+            att: self.att,
+            signaling: SignalingState::new(handler),
+            sm: self.sm,
+        }
+    }
+}
+
+impl<A: AttributeProvider, S: SecurityLevel, H: CocHandler, P: PairingIo>
+    BleChannelMap<A, S, H, P>
+{
+    /// Provides mutable access to the `SecurityManager` on channel `0x0006`.
+    pub fn security_manager(&mut self) -> &mut SecurityManager<S, P> {
+        &mut self.sm
+    }
+
+    /// Provides mutable access to the LE credit-based connection-oriented channel.
+    ///
+    /// Call [`CocChannel::listen`] to allow the peer to open the channel.
+    pub fn coc_channel(&mut self) -> &mut CocChannel {
+        self.signaling.coc_channel()
+    }
+
+    /// Provides mutable access to the `CocHandler` receiving SDUs.
+    pub fn coc_handler(&mut self) -> &mut H {
+        self.signaling.coc_handler()
+    }
+}
+
+impl<A: AttributeProvider, S: SecurityLevel, H: CocHandler, P: PairingIo> ChannelMapper
+    for BleChannelMap<A, S, H, P>
+{
     type AttributeProvider = A;
+    type CocHandler = H;
 
     fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>> {
+        let coc = self.signaling.coc_channel().local_channel();
         match channel {
             Channel::ATT => Some(ChannelData::new_dyn(channel, &mut self.att)),
             Channel::LE_SIGNALING => Some(ChannelData::new_dyn(channel, &mut self.signaling)),
             Channel::LE_SECURITY_MANAGER => Some(ChannelData::new_dyn(channel, &mut self.sm)),
+            // Credits for received K-frames are sent on the signaling channel
+            _ if Some(channel) == coc => Some(ChannelData::new_dyn(
+                Channel::LE_SIGNALING,
+                self.signaling.coc_endpoint(),
+            )),
             _ => None,
         }
     }
@@ -268,6 +355,10 @@ impl<A: AttributeProvider, S: SecurityLevel> ChannelMapper for BleChannelMap<A,
     fn att(&mut self) -> ChannelData<'_, AttributeServer<Self::AttributeProvider>> {
         ChannelData::new(Channel::ATT, &mut self.att)
     }
+
+    fn signaling(&mut self) -> ChannelData<'_, SignalingState<Self::CocHandler>> {
+        ChannelData::new(Channel::LE_SIGNALING, &mut self.signaling)
+    }
 }
 
 /// Trait for protocols that sit on top of L2CAP (object-safe part).
@@ -277,7 +368,7 @@ pub trait ProtocolObj {
     /// Process a message sent to the protocol.
     ///
     /// The message is reassembled by L2CAP already, and the `responder` is guaranteed to fit a
-    /// protocol payload of at least `Protocol::RSP_PDU_SIZE` Bytes, as defined by the protocol.
+    /// protocol payload of at least `Protocol::rsp_pdu_size` Bytes, as defined by the protocol.
     ///
     /// # Errors
     ///
@@ -297,10 +388,19 @@ pub trait Protocol: ProtocolObj {
     /// Incoming PDUs will only be forwarded to the protocol if there is at least this much space in
     /// the TX buffer.
     const RSP_PDU_SIZE: u8;
+
+    /// Returns the size needed by PDUs sent by this protocol in its current state.
+    ///
+    /// This is used instead of `RSP_PDU_SIZE` by protocols that negotiate their PDU size at runtime
+    /// (like the ATT MTU). It must not exceed [`MAX_MTU`]. PDUs that don't fit in a single data
+    /// channel PDU are fragmented.
+    fn rsp_pdu_size(&self) -> u8 {
+        Self::RSP_PDU_SIZE
+    }
 }
 
 /// Header used by *all* L2CAP PDUs.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 struct Header {
     /// Length of the payload following the length and channel fields (after reassembly).
     length: u16,
@@ -329,45 +429,70 @@ impl ToBytes for Header {
     }
 }
 
-struct Message<P> {
-    header: Header,
-    payload: P,
-}
-
-impl<'a, P: FromBytes<'a>> FromBytes<'a> for Message<P> {
-    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
-        let header = Header::from_bytes(bytes)?;
-        assert_eq!(
-            header.length as usize,
-            bytes.bytes_left(),
-            "L2CAP reassembly not yet implemented"
-        );
-
-        Ok(Self {
-            header,
-            payload: P::from_bytes(bytes)?,
-        })
-    }
-}
-
-impl<P: ToBytes> ToBytes for Message<P> {
-    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
-        self.header.to_bytes(writer)?;
-        self.payload.to_bytes(writer)?;
-        Ok(())
-    }
-}
-
 /// L2CAP channel manager and responder.
 #[derive(Debug)]
 pub struct L2CAPState<M: ChannelMapper> {
     mapper: M,
+
+    /// Header of the fragmented PDU that is being reassembled.
+    rx_header: Option<Header>,
+    /// The payload of the PDU received so far.
+    rx_buf: Vec<u8, REASSEMBLY_BUF_SIZE>,
+    /// Number of fragments dropped because they were malformed.
+    dropped_fragments: u32,
 }
 
 impl<M: ChannelMapper> L2CAPState<M> {
     /// Creates a new L2CAP state using the given channel configuration.
     pub fn new(mapper: M) -> Self {
-        Self { mapper }
+        Self {
+            mapper,
+            rx_header: None,
+            rx_buf: Vec::new(),
+            dropped_fragments: 0,
+        }
+    }
+
+    /// Returns the number of received fragments that were dropped because they were malformed.
+    ///
+    /// This includes continuation fragments without a preceding start fragment, fragments that
+    /// exceed the length of their PDU, and PDUs that are too large to be reassembled.
+    pub fn dropped_fragments(&self) -> u32 {
+        self.dropped_fragments
+    }
+
+    /// Discards the partially reassembled PDU (if any) and counts it as dropped.
+    fn drop_fragment(&mut self) {
+        self.rx_header = None;
+        self.rx_buf.clear();
+        self.dropped_fragments = self.dropped_fragments.wrapping_add(1);
+    }
+
+    /// Resets all per-connection state after the Link-Layer connection was closed.
+    ///
+    /// This discards any partially reassembled PDU, and resets the ATT server (see
+    /// [`AttributeServer::reset`]) and the LE Signaling Channel (see [`SignalingState::reset`]).
+    /// It must be called when the connection is closed, ie. when the Link-Layer reports
+    /// [`LinkEvent::Disconnected`].
+    ///
+    /// [`LinkEvent::Disconnected`]: crate::link::event::LinkEvent::Disconnected
+    pub fn connection_closed(&mut self) {
+        self.rx_header = None;
+        self.rx_buf.clear();
+        self.mapper.att().protocol.reset();
+        self.mapper.signaling().protocol.reset();
+    }
+
+    /// Updates the security of the connection after the Link-Layer started encrypting it.
+    ///
+    /// `security` is the security of the key used, as reported in `PairingEvent::Complete`. It
+    /// must be called when the Link-Layer reports [`LinkEvent::EncryptionEnabled`], and is checked
+    /// by the ATT server against the attributes' requirements (see
+    /// [`AttributeServer::set_link_security`]).
+    ///
+    /// [`LinkEvent::EncryptionEnabled`]: crate::link::event::LinkEvent::EncryptionEnabled
+    pub fn encryption_enabled(&mut self, security: LinkSecurity) {
+        self.mapper.att().protocol.set_link_security(security);
     }
 
     /// Gives this instance the ability to transmit packets.
@@ -402,11 +527,20 @@ impl<'a> Sender<'a> {
     ///
     /// If there is not enough space in `tx`, returns `None`.
     fn new<T: ?Sized>(chdata: &ChannelData<'_, T>, tx: &'a mut dyn Producer) -> Option<Self> {
-        let free = tx.free_space();
-        let needed = chdata.pdu_size() + Header::SIZE;
-        if free < needed {
-            debug!("{} free bytes, need {}", free, needed);
-            return None;
+        let needed = usize::from(chdata.pdu_size() + Header::SIZE);
+        if needed <= MIN_DATA_PAYLOAD_BUF {
+            let free = tx.free_space();
+            if usize::from(free) < needed {
+                debug!("{} free bytes, need {}", free, needed);
+                return None;
+            }
+        } else {
+            let free = tx.free_packets();
+            let fragments = needed.div_ceil(MIN_DATA_PAYLOAD_BUF);
+            if free < fragments {
+                debug!("{} free packets, need {}", free, fragments);
+                return None;
+            }
         }
 
         let resp_channel = chdata.response_channel();
@@ -418,6 +552,14 @@ impl<'a> Sender<'a> {
         })
     }
 
+    /// Restricts the size of protocol PDUs sent through this `Sender` to at most `size` Bytes.
+    ///
+    /// This is used by protocols that negotiate their PDU size at runtime (like the ATT MTU). A
+    /// `size` larger than the space reserved for the protocol has no effect.
+    pub fn limit_pdu_size(&mut self, size: u16) {
+        self.pdu = cmp::min(u16::from(self.pdu), size) as u8;
+    }
+
     /// Enqueues an L2CAP message to be sent over the data connection.
     ///
     /// L2CAP header (including the destination endpoint's channel) and the data channel PDU header
@@ -433,8 +575,11 @@ impl<'a> Sender<'a> {
     /// L2CAP header and data channel PDU header will be added automatically. The closure `f` only
     /// has to write the protocol PDU to transmit over L2CAP.
     ///
-    /// The L2CAP implementation will ensure that there are exactly `Protocol::RSP_PDU_SIZE` Bytes
+    /// The L2CAP implementation will ensure that there are exactly `Protocol::rsp_pdu_size` Bytes
     /// available in the `ByteWriter` passed to the closure.
+    ///
+    /// If the protocol's PDU size doesn't fit in a single data channel PDU, the message is encoded
+    /// into a temporary buffer and sent using `send_fragmented`.
     pub fn send_with<T, E>(
         &mut self,
         f: impl FnOnce(&mut ByteWriter<'_>) -> Result<T, E>,
@@ -442,7 +587,15 @@ impl<'a> Sender<'a> {
     where
         E: From<Error>,
     {
-        // FIXME automatic fragmentation is not implemented
+        if usize::from(self.pdu + Header::SIZE) > MIN_DATA_PAYLOAD_BUF {
+            let mut buf = [0; MAX_MTU as usize];
+            let mut writer = ByteWriter::new(&mut buf[..self.pdu.into()]);
+            let left = writer.space_left();
+            let result = f(&mut writer)?;
+            let used = left - writer.space_left();
+            self.send_fragmented(&buf[..used])?;
+            return Ok(result);
+        }
 
         // The payload length goes into the header, so we have to skip that part and write it later
         let mut f = Some(f);
@@ -493,6 +646,47 @@ impl<'a> Sender<'a> {
 
         r.unwrap()
     }
+
+    /// Enqueues an L2CAP message whose payload may not fit into a single data channel PDU.
+    ///
+    /// The message (including the L2CAP header) is split into fragments of up to
+    /// `MIN_DATA_PAYLOAD_BUF` Bytes. The first fragment is sent in an `LL Data Start` PDU, all
+    /// others in `LL Data Continuation` PDUs. Unlike `send_with`, the payload is not limited to the
+    /// protocol's PDU size.
+    ///
+    /// If the TX queue can not fit all fragments, nothing is sent and `Error::Eof` is returned.
+    pub fn send_fragmented(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let header = Header {
+            length: u16::try_from(payload.len()).map_err(|_| Error::InvalidLength)?,
+            channel: self.channel,
+        };
+
+        let fragments = (payload.len() + usize::from(Header::SIZE)).div_ceil(MIN_DATA_PAYLOAD_BUF);
+        if self.tx.free_packets() < fragments {
+            return Err(Error::Eof);
+        }
+
+        let first_len = cmp::min(
+            payload.len(),
+            MIN_DATA_PAYLOAD_BUF - usize::from(Header::SIZE),
+        );
+        let (first, rest) = payload.split_at(first_len);
+        self.tx
+            .produce_dyn(first.len() as u8 + Header::SIZE, &mut |writer| {
+                header.to_bytes(writer)?;
+                writer.write_slice(first)?;
+                Ok(Llid::DataStart)
+            })?;
+
+        for fragment in rest.chunks(MIN_DATA_PAYLOAD_BUF) {
+            self.tx.produce_dyn(fragment.len() as u8, &mut |writer| {
+                writer.write_slice(fragment)?;
+                Ok(Llid::DataCont)
+            })?;
+        }
+
+        Ok(())
+    }
 }
 
 /// An `L2CAPState` with the ability to transmit packets.
@@ -507,47 +701,90 @@ impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
     /// Process the start of a new L2CAP message (or a complete, unfragmented message).
     ///
     /// If the incoming message is unfragmented, it will be forwarded to the protocol listening on
-    /// the addressed channel, and a response may be sent.
+    /// the addressed channel, and a response may be sent. Otherwise, the message is buffered until
+    /// all continuation fragments have been received.
     pub fn process_start(&mut self, message: &[u8]) -> Consume<()> {
-        let msg = match Message::<&[u8]>::from_bytes(&mut ByteReader::new(message)) {
-            Ok(msg) => msg,
+        let mut bytes = ByteReader::new(message);
+        let header = match Header::from_bytes(&mut bytes) {
+            Ok(header) => header,
             Err(e) => return Consume::always(Err(e)),
         };
+        let payload = bytes.read_rest();
+
+        if self.l2cap.rx_header.is_some() {
+            warn!("L2CAP start fragment received before previous PDU was complete");
+            self.l2cap.drop_fragment();
+        }
+
+        let length = usize::from(header.length);
+        if payload.len() == length {
+            return self.dispatch(header.channel, payload);
+        }
 
-        if usize::from(msg.header.length) != msg.payload.len() {
-            // Lengths mismatch => Reassembly needed
-            unimplemented!("L2CAP reassembly");
+        if payload.len() > length || length > REASSEMBLY_BUF_SIZE {
+            warn!(
+                "dropping L2CAP fragment with {} Bytes for PDU of length {}",
+                payload.len(),
+                length
+            );
+            self.l2cap.drop_fragment();
+            return Consume::always(Ok(()));
         }
 
-        self.dispatch(msg.header.channel, msg.payload)
+        // Fits, since `payload` is shorter than `length`
+        self.l2cap.rx_buf.extend_from_slice(payload).unwrap();
+        self.l2cap.rx_header = Some(header);
+        Consume::always(Ok(()))
     }
 
     /// Process continuation of an L2CAP message.
     ///
-    /// This is not yet implemented and will always panic.
-    pub fn process_cont(&mut self, _data: &[u8]) -> Consume<()> {
-        unimplemented!("reassembly")
+    /// Once the message is complete, it will be forwarded to the protocol listening on the
+    /// addressed channel, and a response may be sent.
+    pub fn process_cont(&mut self, data: &[u8]) -> Consume<()> {
+        let header = match self.l2cap.rx_header {
+            Some(header) => header,
+            None => {
+                warn!("dropping L2CAP continuation fragment without start");
+                self.l2cap.drop_fragment();
+                return Consume::always(Ok(()));
+            }
+        };
+
+        let received = self.l2cap.rx_buf.len();
+        let length = usize::from(header.length);
+        if received + data.len() > length {
+            warn!(
+                "dropping L2CAP PDU, fragment exceeds length {} by {} Bytes",
+                length,
+                received + data.len() - length
+            );
+            self.l2cap.drop_fragment();
+            return Consume::always(Ok(()));
+        }
+
+        // Fits, since `length` was checked when the PDU was started
+        self.l2cap.rx_buf.extend_from_slice(data).unwrap();
+        if self.l2cap.rx_buf.len() < length {
+            return Consume::always(Ok(()));
+        }
+
+        let l2cap = &mut *self.l2cap;
+        let consume = dispatch(&mut l2cap.mapper, self.tx, header.channel, &l2cap.rx_buf);
+        if consume.should_consume() {
+            l2cap.rx_header = None;
+            l2cap.rx_buf.clear();
+        } else {
+            // The fragment will be processed again
+            l2cap.rx_buf.truncate(received);
+        }
+        consume
     }
 
     /// Dispatches a fully reassembled L2CAP message to the protocol listening on the addressed
     /// channel.
     fn dispatch(&mut self, channel: Channel, payload: &[u8]) -> Consume<()> {
-        if let Some(mut chdata) = self.l2cap.mapper.lookup(channel) {
-            let sender = if let Some(sender) = Sender::new(&chdata, self.tx) {
-                sender
-            } else {
-                return Consume::never(Ok(()));
-            };
-
-            Consume::always(chdata.protocol().process_message(payload, sender))
-        } else {
-            warn!(
-                "ignoring message sent to unconnected channel {:?}: {:?}",
-                channel,
-                HexSlice(payload)
-            );
-            Consume::always(Ok(()))
-        }
+        dispatch(&mut self.l2cap.mapper, self.tx, channel, payload)
     }
 
     /// Prepares for sending data using the Attribute Protocol.
@@ -563,6 +800,102 @@ impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
         let att = self.l2cap.mapper.att();
         Sender::new(&att, self.tx).map(move |sender| att.into_protocol().with_sender(sender))
     }
+
+    /// Queues an attribute value notification if the client has subscribed to it.
+    ///
+    /// Notifications are put into the TX packet queue, so several of them can be queued at once.
+    /// The Link-Layer then sends as many of them as possible in each connection event.
+    ///
+    /// Returns `Err(Error::WouldBlock)` if the TX packet queue is full. Otherwise, behaves like
+    /// [`AttributeServerTx::notify`].
+    ///
+    /// [`AttributeServerTx::notify`]: att::AttributeServerTx::notify
+    pub fn notify(&mut self, handle: att::Handle, value: &[u8]) -> Result<bool, Error> {
+        self.att().ok_or(Error::WouldBlock)?.notify(handle, value)
+    }
+
+    /// Queues an attribute value indication if the client has subscribed to it.
+    ///
+    /// Returns `Err(Error::WouldBlock)` if the TX packet queue is full. Otherwise, behaves like
+    /// [`AttributeServerTx::indicate`].
+    ///
+    /// [`AttributeServerTx::indicate`]: att::AttributeServerTx::indicate
+    pub fn indicate(&mut self, handle: att::Handle, value: &[u8]) -> Result<bool, Error> {
+        self.att().ok_or(Error::WouldBlock)?.indicate(handle, value)
+    }
+
+    /// Prepares for sending SDUs over the LE credit-based connection-oriented channel.
+    ///
+    /// Returns `None` if the channel isn't open, or if there's not enough space in the TX packet
+    /// queue to send a K-frame.
+    pub fn coc(&mut self) -> Option<CocChannelTx<'_>> {
+        coc_tx(&mut self.l2cap.mapper, self.tx)
+    }
+
+    /// Asks the master to update the connection parameters via the LE Signaling Channel.
+    ///
+    /// This works with masters that don't support the Link-Layer Connection Parameters Request
+    /// Procedure. [`LinkLayer::request_conn_param_update`] picks the procedure based on the
+    /// features of both devices. See [`SignalingState::request_conn_param_update`] for details.
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the TX packet queue to send the request.
+    ///
+    /// [`LinkLayer::request_conn_param_update`]: crate::link::LinkLayer::request_conn_param_update
+    pub fn request_conn_param_update(
+        &mut self,
+        params: &ConnectionParamRequest,
+    ) -> Result<(), Error> {
+        let signaling = self.l2cap.mapper.signaling();
+        let mut sender = Sender::new(&signaling, self.tx).ok_or(Error::Eof)?;
+        signaling
+            .into_protocol()
+            .request_conn_param_update(&mut sender, params)
+    }
+}
+
+fn dispatch<M: ChannelMapper>(
+    mapper: &mut M,
+    tx: &mut dyn Producer,
+    channel: Channel,
+    payload: &[u8],
+) -> Consume<()> {
+    if let Some(mut chdata) = mapper.lookup(channel) {
+        let sender = if let Some(sender) = Sender::new(&chdata, tx) {
+            sender
+        } else {
+            return Consume::never(Ok(()));
+        };
+
+        let result = chdata.protocol().process_message(payload, sender);
+
+        // Incoming credits might allow us to continue sending a pending SDU
+        if let Some(mut coc) = coc_tx(mapper, tx) {
+            if let Err(e) = coc.flush() {
+                warn!("failed to send K-frame: {:?}", e);
+            }
+        }
+
+        Consume::always(result)
+    } else {
+        warn!(
+            "ignoring message sent to unconnected channel {:?}: {}",
+            channel,
+            HexSlice(payload)
+        );
+        Consume::always(Ok(()))
+    }
+}
+
+fn coc_tx<'a, M: ChannelMapper>(
+    mapper: &'a mut M,
+    tx: &'a mut dyn Producer,
+) -> Option<CocChannelTx<'a>> {
+    let coc = mapper
+        .signaling()
+        .into_protocol()
+        .coc_channel()
+        .channel_data()?;
+    Sender::new(&coc, tx).map(move |sender| coc.into_protocol().with_sender(sender))
 }
 
 impl<'a, M: ChannelMapper, P: Producer> Deref for L2CAPStateTx<'a, M, P> {
@@ -578,3 +911,173 @@ impl<'a, M: ChannelMapper, P: Producer> DerefMut for L2CAPStateTx<'a, M, P> {
         &mut self.l2cap
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::queue::{ArrayQueue, Consumer, PacketQueue, SimpleQueue};
+    use std::vec::Vec;
+
+    /// Records all messages sent to it.
+    #[derive(Default)]
+    struct Recorder(Vec<Vec<u8>>);
+
+    impl ProtocolObj for Recorder {
+        fn process_message(&mut self, message: &[u8], _responder: Sender<'_>) -> Result<(), Error> {
+            self.0.push(message.to_vec());
+            Ok(())
+        }
+    }
+
+    impl Protocol for Recorder {
+        const RSP_PDU_SIZE: u8 = 0;
+    }
+
+    /// Maps a `Recorder` to channel `0x0040`.
+    struct RecorderMap {
+        att: AttributeServer<NoAttributes>,
+        signaling: SignalingState<()>,
+        recorder: Recorder,
+    }
+
+    impl ChannelMapper for RecorderMap {
+        type AttributeProvider = NoAttributes;
+        type CocHandler = ();
+
+        fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>> {
+            match channel {
+                Channel(0x0040) => Some(ChannelData::new_dyn(channel, &mut self.recorder)),
+                _ => None,
+            }
+        }
+
+        fn att(&mut self) -> ChannelData<'_, AttributeServer<Self::AttributeProvider>> {
+            ChannelData::new(Channel::ATT, &mut self.att)
+        }
+
+        fn signaling(&mut self) -> ChannelData<'_, SignalingState<Self::CocHandler>> {
+            ChannelData::new(Channel::LE_SIGNALING, &mut self.signaling)
+        }
+    }
+
+    fn recorder() -> L2CAPState<RecorderMap> {
+        L2CAPState::new(RecorderMap {
+            att: AttributeServer::new(NoAttributes),
+            signaling: SignalingState::new(()),
+            recorder: Recorder::default(),
+        })
+    }
+
+    #[test]
+    fn reassembly() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, _rx) = queue.split();
+        let mut l2cap = recorder();
+        let payload = (0..100).collect::<Vec<u8>>();
+
+        // 4-Byte header + 23 Bytes, then 27 + 27 + 23 Bytes
+        let mut start = std::vec![100, 0, 0x40, 0x00];
+        start.extend_from_slice(&payload[..23]);
+        let mut l2cap = l2cap.tx(&mut tx);
+        assert!(l2cap.process_start(&start).into_result().is_ok());
+        assert!(l2cap.process_cont(&payload[23..50]).into_result().is_ok());
+        assert!(l2cap.process_cont(&payload[50..77]).into_result().is_ok());
+        assert!(l2cap.channel_mapper().recorder.0.is_empty());
+        assert!(l2cap.process_cont(&payload[77..]).into_result().is_ok());
+
+        assert_eq!(l2cap.channel_mapper().recorder.0, [payload]);
+        assert_eq!(l2cap.dropped_fragments(), 0);
+    }
+
+    #[test]
+    fn malformed_fragments() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, _rx) = queue.split();
+        let mut l2cap = recorder();
+        let mut l2cap = l2cap.tx(&mut tx);
+
+        // Continuation without start
+        assert!(l2cap.process_cont(&[0; 10]).into_result().is_ok());
+        assert_eq!(l2cap.dropped_fragments(), 1);
+
+        // Continuation exceeding the PDU length
+        assert!(l2cap
+            .process_start(&[30, 0, 0x40, 0x00, 1, 2, 3])
+            .into_result()
+            .is_ok());
+        assert!(l2cap.process_cont(&[0; 28]).into_result().is_ok());
+        assert_eq!(l2cap.dropped_fragments(), 2);
+
+        // Start fragment larger than the PDU length
+        assert!(l2cap
+            .process_start(&[1, 0, 0x40, 0x00, 1, 2])
+            .into_result()
+            .is_ok());
+        assert_eq!(l2cap.dropped_fragments(), 3);
+
+        // PDU too large to be reassembled
+        assert!(l2cap
+            .process_start(&[0xff, 0xff, 0x40, 0x00, 1])
+            .into_result()
+            .is_ok());
+        assert_eq!(l2cap.dropped_fragments(), 4);
+
+        // The next valid PDU is received normally
+        assert!(l2cap
+            .process_start(&[2, 0, 0x40, 0x00, 1, 2])
+            .into_result()
+            .is_ok());
+        assert_eq!(l2cap.channel_mapper().recorder.0, [[1, 2]]);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut queue = ArrayQueue::<8>::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let payload = (0..100).collect::<Vec<u8>>();
+
+        let mut l2cap = recorder();
+        let chdata = l2cap.channel_mapper().lookup(Channel(0x0040)).unwrap();
+        let mut sender = Sender::new(&chdata, &mut tx).unwrap();
+        sender.send_fragmented(&payload).unwrap();
+
+        // Feed the fragments back into L2CAP, which reassembles the original message
+        let mut queue = SimpleQueue::new();
+        let (mut tx, _rx) = queue.split();
+        let mut l2cap = l2cap.tx(&mut tx);
+        let mut llids = Vec::new();
+        while rx.has_data() {
+            rx.consume_raw_with(|header, raw| {
+                llids.push(header.llid());
+                match header.llid() {
+                    Llid::DataStart => l2cap.process_start(raw),
+                    _ => l2cap.process_cont(raw),
+                }
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            llids,
+            [
+                Llid::DataStart,
+                Llid::DataCont,
+                Llid::DataCont,
+                Llid::DataCont
+            ]
+        );
+        assert_eq!(l2cap.channel_mapper().recorder.0, [payload]);
+    }
+
+    #[test]
+    fn fragmentation_needs_space() {
+        let mut queue = ArrayQueue::<3>::new();
+        let (mut tx, rx) = (&mut queue).split();
+
+        let mut l2cap = recorder();
+        let chdata = l2cap.channel_mapper().lookup(Channel(0x0040)).unwrap();
+        let mut sender = Sender::new(&chdata, &mut tx).unwrap();
+        assert_eq!(sender.send_fragmented(&[0; 100]), Err(Error::Eof));
+        assert!(!rx.has_data());
+    }
+}
@@ -1,12 +1,15 @@
 //! L2CAP Signaling channel PDUs and functions (`0x0005`).
 
-use super::{Protocol, ProtocolObj, Sender};
-use crate::Error;
+use super::coc::{CocChannel, CocEndpoint, CocHandler};
+use super::{Channel, Protocol, ProtocolObj, Sender};
+use crate::link::llcp::ConnectionParamRequest;
+use crate::{bytes::*, Error};
 
 enum_with_unknown! {
     /// LE Signaling Channel opcodes.
-    #[derive(Debug, Copy, Clone, defmt::Format)]
-    enum Code(u8) {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub(super) enum Code(u8) {
         CommandReject = 0x01,
         DisconnectionReq = 0x06,
         DisconnectionRsp = 0x07,
@@ -20,30 +23,569 @@ enum_with_unknown! {
 
 enum_with_unknown! {
     /// Reasons for a `CommandReject` response.
-    #[derive(Debug, Copy, Clone, defmt::Format)]
-    enum RejectReason(u16) {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub(super) enum RejectReason(u16) {
         CommandNotUnderstood = 0x0000,
         SignalingMtuExceeded = 0x0001,
         InvalidCid = 0x0002,
     }
 }
 
+enum_with_unknown! {
+    /// Result of an `LE Credit Based Connection Request`.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub(super) enum ConnectionResult(u16) {
+        Success = 0x0000,
+        PsmNotSupported = 0x0002,
+        NoResources = 0x0004,
+        InsufficientAuthentication = 0x0005,
+        InsufficientAuthorization = 0x0006,
+        InsufficientEncryptionKeySize = 0x0007,
+        InsufficientEncryption = 0x0008,
+        InvalidSourceCid = 0x0009,
+        SourceCidAlreadyAllocated = 0x000A,
+        UnacceptableParameters = 0x000B,
+    }
+}
+
+/// A command sent over the LE Signaling Channel.
+#[derive(Debug, Copy, Clone)]
+pub(super) enum Command<'a> {
+    Reject {
+        reason: RejectReason,
+        data: &'a [u8],
+    },
+    DisconnectionReq {
+        destination: Channel,
+        source: Channel,
+    },
+    DisconnectionRsp {
+        destination: Channel,
+        source: Channel,
+    },
+    ConnectionParameterUpdateReq {
+        /// Minimum connection interval in units of 1.25 ms.
+        interval_min: u16,
+        /// Maximum connection interval in units of 1.25 ms.
+        interval_max: u16,
+        /// Slave latency in number of connection events.
+        latency: u16,
+        /// Supervision timeout in units of 10 ms.
+        timeout: u16,
+    },
+    ConnectionParameterUpdateRsp {
+        /// `0x0000` if the parameters were accepted, `0x0001` if they were rejected.
+        result: u16,
+    },
+    CreditBasedConnectionReq {
+        psm: u16,
+        source: Channel,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+    },
+    CreditBasedConnectionRsp {
+        destination: Channel,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+        result: ConnectionResult,
+    },
+    FlowControlCredit {
+        channel: Channel,
+        credits: u16,
+    },
+    Unknown {
+        code: Code,
+        data: &'a [u8],
+    },
+}
+
+impl Command<'_> {
+    fn code(&self) -> Code {
+        match self {
+            Command::Reject { .. } => Code::CommandReject,
+            Command::DisconnectionReq { .. } => Code::DisconnectionReq,
+            Command::DisconnectionRsp { .. } => Code::DisconnectionRsp,
+            Command::ConnectionParameterUpdateReq { .. } => Code::ConnectionParameterUpdateReq,
+            Command::ConnectionParameterUpdateRsp { .. } => Code::ConnectionParameterUpdateRsp,
+            Command::CreditBasedConnectionReq { .. } => Code::CreditBasedConnectionReq,
+            Command::CreditBasedConnectionRsp { .. } => Code::CreditBasedConnectionRsp,
+            Command::FlowControlCredit { .. } => Code::FlowControlCredit,
+            Command::Unknown { code, .. } => *code,
+        }
+    }
+}
+
+/// A signaling packet (C-frame), consisting of a single command and its identifier.
+///
+/// The identifier is used to match responses with requests. `0` is not a valid identifier.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Packet<'a> {
+    pub(super) identifier: u8,
+    pub(super) command: Command<'a>,
+}
+
+impl<'a> FromBytes<'a> for Packet<'a> {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let code = Code::from(bytes.read_u8()?);
+        let identifier = bytes.read_u8()?;
+        let length = bytes.read_u16_le()?;
+        let bytes = &mut bytes.split_off(usize::from(length))?;
+
+        let command = match code {
+            Code::CommandReject => Command::Reject {
+                reason: bytes.read_u16_le()?.into(),
+                data: bytes.read_rest(),
+            },
+            Code::DisconnectionReq => Command::DisconnectionReq {
+                destination: Channel::from_bytes(bytes)?,
+                source: Channel::from_bytes(bytes)?,
+            },
+            Code::DisconnectionRsp => Command::DisconnectionRsp {
+                destination: Channel::from_bytes(bytes)?,
+                source: Channel::from_bytes(bytes)?,
+            },
+            Code::ConnectionParameterUpdateReq => Command::ConnectionParameterUpdateReq {
+                interval_min: bytes.read_u16_le()?,
+                interval_max: bytes.read_u16_le()?,
+                latency: bytes.read_u16_le()?,
+                timeout: bytes.read_u16_le()?,
+            },
+            Code::ConnectionParameterUpdateRsp => Command::ConnectionParameterUpdateRsp {
+                result: bytes.read_u16_le()?,
+            },
+            Code::CreditBasedConnectionReq => Command::CreditBasedConnectionReq {
+                psm: bytes.read_u16_le()?,
+                source: Channel::from_bytes(bytes)?,
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+            },
+            Code::CreditBasedConnectionRsp => Command::CreditBasedConnectionRsp {
+                destination: Channel::from_bytes(bytes)?,
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+                result: bytes.read_u16_le()?.into(),
+            },
+            Code::FlowControlCredit => Command::FlowControlCredit {
+                channel: Channel::from_bytes(bytes)?,
+                credits: bytes.read_u16_le()?,
+            },
+            _ => Command::Unknown {
+                code,
+                data: bytes.read_rest(),
+            },
+        };
+
+        Ok(Self {
+            identifier,
+            command,
+        })
+    }
+}
+
+impl ToBytes for Packet<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.command.code().into())?;
+        writer.write_u8(self.identifier)?;
+
+        // The length of the command data is written once it is known
+        let mut length = writer.split_off(2)?;
+        let left = writer.space_left();
+        match self.command {
+            Command::Reject { reason, data } => {
+                writer.write_u16_le(reason.into())?;
+                writer.write_slice(data)?;
+            }
+            Command::DisconnectionReq {
+                destination,
+                source,
+            }
+            | Command::DisconnectionRsp {
+                destination,
+                source,
+            } => {
+                destination.to_bytes(writer)?;
+                source.to_bytes(writer)?;
+            }
+            Command::ConnectionParameterUpdateReq {
+                interval_min,
+                interval_max,
+                latency,
+                timeout,
+            } => {
+                writer.write_u16_le(interval_min)?;
+                writer.write_u16_le(interval_max)?;
+                writer.write_u16_le(latency)?;
+                writer.write_u16_le(timeout)?;
+            }
+            Command::ConnectionParameterUpdateRsp { result } => writer.write_u16_le(result)?,
+            Command::CreditBasedConnectionReq {
+                psm,
+                source,
+                mtu,
+                mps,
+                credits,
+            } => {
+                writer.write_u16_le(psm)?;
+                source.to_bytes(writer)?;
+                writer.write_u16_le(mtu)?;
+                writer.write_u16_le(mps)?;
+                writer.write_u16_le(credits)?;
+            }
+            Command::CreditBasedConnectionRsp {
+                destination,
+                mtu,
+                mps,
+                credits,
+                result,
+            } => {
+                destination.to_bytes(writer)?;
+                writer.write_u16_le(mtu)?;
+                writer.write_u16_le(mps)?;
+                writer.write_u16_le(credits)?;
+                writer.write_u16_le(result.into())?;
+            }
+            Command::FlowControlCredit { channel, credits } => {
+                channel.to_bytes(writer)?;
+                writer.write_u16_le(credits)?;
+            }
+            Command::Unknown { data, .. } => writer.write_slice(data)?,
+        }
+
+        length.write_u16_le((left - writer.space_left()) as u16)
+    }
+}
+
+/// Returns the Connection Parameter Update Request asking for `params`.
+fn conn_param_update_req(params: &ConnectionParamRequest) -> Command<'static> {
+    Command::ConnectionParameterUpdateReq {
+        interval_min: (params.min_conn_interval().to_micros() / 1_250) as u16,
+        interval_max: (params.max_conn_interval().to_micros() / 1_250) as u16,
+        latency: params.slave_latency(),
+        timeout: (params.supervision_timeout().to_millis() / 10) as u16,
+    }
+}
+
+/// Identifier of the Connection Parameter Update Requests sent by the Link-Layer.
+///
+/// `SignalingState` hands out identifiers counting up from 1, so this one is only reused after
+/// 254 other requests.
+const LL_CONN_PARAM_REQ_IDENTIFIER: u8 = 0xFF;
+
+/// Writes a complete L2CAP PDU containing a Connection Parameter Update Request for `params`.
+///
+/// The Link-Layer sends this in a single data channel PDU when the master doesn't support the
+/// Connection Parameters Request Procedure. The master's response is ignored by
+/// `SignalingState`, since an accepted request results in a connection update anyways.
+pub(crate) fn write_conn_param_update_req(
+    writer: &mut ByteWriter<'_>,
+    params: &ConnectionParamRequest,
+) -> Result<(), Error> {
+    // The length of the signaling packet is written once it is known
+    let mut length = writer.split_off(2)?;
+    Channel::LE_SIGNALING.to_bytes(writer)?;
+    let left = writer.space_left();
+    Packet {
+        identifier: LL_CONN_PARAM_REQ_IDENTIFIER,
+        command: conn_param_update_req(params),
+    }
+    .to_bytes(writer)?;
+    length.write_u16_le((left - writer.space_left()) as u16)
+}
+
 /// The `Protocol` implementor listening on the LE Signaling Channel `0x0005`.
-pub struct SignalingState {}
+///
+/// The signaling channel is used to open and close LE credit-based connection-oriented channels
+/// and to exchange their flow control credits. The channel itself is owned by the `SignalingState`.
+///
+/// It is also used to request new connection parameters from the master.
+pub struct SignalingState<H: CocHandler> {
+    coc: CocEndpoint<H>,
+    /// Identifier of the pending Connection Parameter Update Request.
+    conn_param_req: Option<u8>,
+    conn_param_result: Option<bool>,
+}
+
+impl<H: CocHandler> SignalingState<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            coc: CocEndpoint::new(handler),
+            conn_param_req: None,
+            conn_param_result: None,
+        }
+    }
+
+    /// Resets all per-connection state.
+    ///
+    /// An open connection-oriented channel is reset (see [`CocChannel::reset`]) and its handler is
+    /// informed about the disconnection. Any pending Connection Parameter Update Request is
+    /// forgotten.
+    pub fn reset(&mut self) {
+        if self.coc.channel.is_connected() {
+            self.coc.channel.reset();
+            self.coc.handler.disconnected();
+        }
+        self.conn_param_req = None;
+        self.conn_param_result = None;
+    }
+
+    /// Asks the master to change the connection parameters.
+    ///
+    /// This is the L2CAP alternative to the Link-Layer Connection Parameters Request Procedure,
+    /// for masters that don't support it (see `LinkLayer::request_conn_param_update`). If the
+    /// master accepts the request, it starts the Link-Layer Connection Update Procedure, which is
+    /// handled like any other connection update.
+    ///
+    /// Only the interval, slave latency and supervision timeout in `params` are sent to the
+    /// master. The outcome can be queried with [`conn_param_update_accepted`].
+    ///
+    /// [`conn_param_update_accepted`]: Self::conn_param_update_accepted
+    pub fn request_conn_param_update(
+        &mut self,
+        sender: &mut Sender<'_>,
+        params: &ConnectionParamRequest,
+    ) -> Result<(), Error> {
+        let identifier = self.coc.next_identifier();
+        sender.send(Packet {
+            identifier,
+            command: conn_param_update_req(params),
+        })?;
+
+        self.conn_param_req = Some(identifier);
+        self.conn_param_result = None;
+        Ok(())
+    }
+
+    /// Returns whether the master has accepted the last Connection Parameter Update Request.
+    ///
+    /// Returns `None` if no request was sent or the master hasn't responded yet.
+    pub fn conn_param_update_accepted(&self) -> Option<bool> {
+        self.conn_param_result
+    }
+
+    /// Returns the protocol processing the K-frames received on the connection-oriented channel.
+    pub(super) fn coc_endpoint(&mut self) -> &mut CocEndpoint<H> {
+        &mut self.coc
+    }
+
+    /// Returns the LE credit-based connection-oriented channel.
+    pub fn coc_channel(&mut self) -> &mut CocChannel {
+        &mut self.coc.channel
+    }
+
+    /// Returns the handler receiving the SDUs sent over the connection-oriented channel.
+    pub fn coc_handler(&mut self) -> &mut H {
+        &mut self.coc.handler
+    }
+
+    fn process_command(
+        &mut self,
+        identifier: u8,
+        command: Command<'_>,
+        responder: &mut Sender<'_>,
+    ) -> Result<(), Error> {
+        let coc = &mut self.coc;
+        let mut respond = |command| {
+            responder.send(Packet {
+                identifier,
+                command,
+            })
+        };
 
-impl SignalingState {
-    pub fn new() -> Self {
-        Self {}
+        match command {
+            Command::CreditBasedConnectionReq {
+                psm,
+                source,
+                mtu,
+                mps,
+                credits,
+            } => match coc.channel.accept(psm, source, mtu, mps, credits) {
+                Ok(local) => {
+                    coc.handler.connected(&coc.channel);
+                    respond(Command::CreditBasedConnectionRsp {
+                        destination: local,
+                        mtu: coc.channel.mtu(),
+                        mps: coc.channel.mps(),
+                        credits: coc.channel.rx_credits(),
+                        result: ConnectionResult::Success,
+                    })
+                }
+                Err(result) => {
+                    debug!("refusing channel on PSM {:#06X}: {:?}", psm, result);
+                    respond(Command::CreditBasedConnectionRsp {
+                        destination: Channel::NULL,
+                        mtu: 0,
+                        mps: 0,
+                        credits: 0,
+                        result,
+                    })
+                }
+            },
+            Command::FlowControlCredit { channel, credits } => {
+                if coc.channel.remote_channel() != Some(channel) {
+                    debug!("ignoring credits for unknown channel {:?}", channel);
+                    return Ok(());
+                }
+
+                if coc.channel.add_credits(credits).is_err() {
+                    // The credit count must never exceed 65535, if it does the channel has to be
+                    // disconnected.
+                    warn!("credit overflow on channel {:?}, disconnecting", channel);
+                    let local = coc.channel.local_channel().unwrap();
+                    coc.channel.close();
+                    coc.handler.disconnected();
+                    return responder.send(Packet {
+                        identifier: coc.next_identifier(),
+                        command: Command::DisconnectionReq {
+                            destination: channel,
+                            source: local,
+                        },
+                    });
+                }
+
+                Ok(())
+            }
+            Command::DisconnectionReq {
+                destination,
+                source,
+            } => {
+                if coc.channel.local_channel() != Some(destination)
+                    || coc.channel.remote_channel() != Some(source)
+                {
+                    let mut cids = [0; 4];
+                    cids[..2].copy_from_slice(&destination.as_raw().to_le_bytes());
+                    cids[2..].copy_from_slice(&source.as_raw().to_le_bytes());
+                    return respond(Command::Reject {
+                        reason: RejectReason::InvalidCid,
+                        data: &cids,
+                    });
+                }
+
+                coc.channel.close();
+                coc.handler.disconnected();
+                respond(Command::DisconnectionRsp {
+                    destination,
+                    source,
+                })
+            }
+            Command::ConnectionParameterUpdateRsp { result } => {
+                if self.conn_param_req != Some(identifier) {
+                    debug!("ignoring unexpected connection parameter update response");
+                    return Ok(());
+                }
+
+                self.conn_param_req = None;
+                self.conn_param_result = Some(result == 0);
+                Ok(())
+            }
+            Command::DisconnectionRsp { .. }
+            | Command::CreditBasedConnectionRsp { .. }
+            | Command::Reject { .. } => {
+                // We never send requests expecting these responses (the channel is already closed
+                // when our Disconnection Request is answered), so there's nothing to do.
+                Ok(())
+            }
+            // Only the master may receive this request, and we're always the slave
+            Command::ConnectionParameterUpdateReq { .. } | Command::Unknown { .. } => {
+                respond(Command::Reject {
+                    reason: RejectReason::CommandNotUnderstood,
+                    data: &[],
+                })
+            }
+        }
     }
 }
 
-impl ProtocolObj for SignalingState {
-    fn process_message(&mut self, message: &[u8], responder: Sender<'_>) -> Result<(), Error> {
-        let _ = (message, responder);
-        unimplemented!();
+impl<H: CocHandler> ProtocolObj for SignalingState<H> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        let packet = Packet::from_bytes(&mut ByteReader::new(message))?;
+        debug!("signaling: {:?}", packet);
+
+        if packet.identifier == 0 {
+            warn!("ignoring signaling packet with invalid identifier 0");
+            return Ok(());
+        }
+
+        self.process_command(packet.identifier, packet.command, &mut responder)
     }
 }
 
-impl Protocol for SignalingState {
+impl<H: CocHandler> Protocol for SignalingState<H> {
     const RSP_PDU_SIZE: u8 = 23;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l2cap::{BleChannelMap, ChannelMapper, L2CAPState};
+    use crate::link::queue::{Consume, Consumer, PacketQueue, SimpleQueue};
+    use crate::time::Duration;
+
+    #[test]
+    fn conn_param_update() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = queue.split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+
+        let mut params = ConnectionParamRequest::new();
+        params.set_conn_interval(Duration::millis(15), Duration::millis(30));
+        params.set_slave_latency(4);
+        params.set_supervision_timeout(Duration::secs(2));
+        l2cap
+            .tx(&mut tx)
+            .request_conn_param_update(&params)
+            .unwrap();
+
+        let sent = rx
+            .consume_raw_with(|_, raw| Consume::always(Ok(raw.to_vec())))
+            .unwrap();
+        let identifier = sent[5];
+        assert_ne!(identifier, 0);
+        assert_eq!(
+            sent,
+            [
+                12, 0, 0x05, 0x00, // L2CAP header
+                0x12, identifier, 8, 0, // command header
+                12, 0, // interval min (15 ms)
+                24, 0, // interval max (30 ms)
+                4, 0, // slave latency
+                200, 0, // supervision timeout (2 s)
+            ]
+        );
+
+        let accepted = |l2cap: &mut L2CAPState<BleChannelMap<_, _>>| {
+            l2cap
+                .channel_mapper()
+                .signaling()
+                .into_protocol()
+                .conn_param_update_accepted()
+        };
+        assert_eq!(accepted(&mut l2cap), None);
+
+        // Responses to other requests are ignored
+        let rsp = [
+            6,
+            0,
+            0x05,
+            0x00,
+            0x13,
+            identifier.wrapping_add(1),
+            2,
+            0,
+            0,
+            0,
+        ];
+        l2cap.tx(&mut tx).process_start(&rsp).into_result().unwrap();
+        assert_eq!(accepted(&mut l2cap), None);
+
+        let rsp = [6, 0, 0x05, 0x00, 0x13, identifier, 2, 0, 0, 0];
+        l2cap.tx(&mut tx).process_start(&rsp).into_result().unwrap();
+        assert_eq!(accepted(&mut l2cap), Some(true));
+        assert!(!rx.has_data());
+    }
+}
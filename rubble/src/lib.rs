@@ -26,6 +26,7 @@
 mod log;
 #[macro_use]
 mod utils;
+pub mod aes;
 pub mod att;
 pub mod beacon;
 pub mod bytes;
@@ -37,6 +38,8 @@ pub mod l2cap;
 pub mod link;
 pub mod phy;
 pub mod security;
+#[cfg(any(test, feature = "critical-section"))]
+pub mod sync;
 pub mod time;
 pub mod uuid;
 
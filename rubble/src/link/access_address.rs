@@ -0,0 +1,157 @@
+//! Generation of data channel access addresses.
+//!
+//! Every connection uses a randomly chosen access address, picked by the initiator and sent in the
+//! `CONNECT_IND` PDU. To make packets easy to find in noise, and hard to confuse with the
+//! advertising channel access address, the specification restricts the values that may be used
+//! (Vol 6, Part B, Section 2.1.2).
+
+use crate::link::advertising::ACCESS_ADDRESS;
+use crate::security::rng::Rng;
+
+/// Generates a random access address for a new connection.
+///
+/// Values are drawn from `rng` until one passes [`is_valid_access_address`].
+pub fn random_access_address<R: Rng + ?Sized>(rng: &mut R) -> u32 {
+    loop {
+        let aa = rng.next_u32();
+        if is_valid_access_address(aa) {
+            return aa;
+        }
+    }
+}
+
+/// Returns whether `aa` may be used as the access address of a connection.
+///
+/// The access address:
+///
+/// * must not be the advertising access address, or differ from it in only one bit,
+/// * must not consist of 4 equal octets,
+/// * must not contain more than 6 consecutive zeros or ones,
+/// * must not contain more than 24 bit transitions,
+/// * must contain at least 2 transitions in its 6 most significant bits.
+pub fn is_valid_access_address(aa: u32) -> bool {
+    // Must not be, or differ by only a single bit from, the advertising access address
+    if (aa ^ ACCESS_ADDRESS).count_ones() <= 1 {
+        return false;
+    }
+
+    // Not all 4 octets may be equal
+    let [b0, b1, b2, b3] = aa.to_le_bytes();
+    if b0 == b1 && b1 == b2 && b2 == b3 {
+        return false;
+    }
+
+    // No more than 6 consecutive zeros or ones
+    let mut run = 1;
+    for i in 1..32 {
+        if (aa >> i) & 1 == (aa >> (i - 1)) & 1 {
+            run += 1;
+            if run > 6 {
+                return false;
+            }
+        } else {
+            run = 1;
+        }
+    }
+
+    // Bit `i` of `transitions` is set when bits `i` and `i + 1` of `aa` differ
+    let transitions = (aa ^ (aa >> 1)) & 0x7FFF_FFFF;
+
+    // No more than 24 transitions, at least 2 of them in the most significant 6 bits
+    transitions.count_ones() <= 24 && (transitions >> 26).count_ones() >= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::rng::MockRng;
+
+    /// Returns a fixed sequence of values from `next_u32`.
+    struct ScriptedRng(&'static [u32]);
+
+    impl Rng for ScriptedRng {
+        fn fill_bytes(&mut self, buf: &mut [u8]) {
+            for chunk in buf.chunks_mut(4) {
+                let bytes = self.next_u32().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+        }
+
+        fn next_u32(&mut self) -> u32 {
+            let (first, rest) = self.0.split_first().expect("out of values");
+            self.0 = rest;
+            *first
+        }
+    }
+
+    #[test]
+    fn valid() {
+        assert!(is_valid_access_address(0x5065_17AF));
+        assert!(is_valid_access_address(0x7176_4129));
+        assert!(is_valid_access_address(0x0A65_17AF));
+    }
+
+    #[test]
+    fn invalid() {
+        // Advertising access address, or a single bit flipped
+        assert!(!is_valid_access_address(0x8E89_BED6));
+        assert!(!is_valid_access_address(0x8E89_BED7));
+        assert!(!is_valid_access_address(0x0E89_BED6));
+
+        // All octets equal
+        assert!(!is_valid_access_address(0x5A5A_5A5A));
+        assert!(!is_valid_access_address(0x9E9E_9E9E));
+        assert!(!is_valid_access_address(0x0000_0000));
+        assert!(!is_valid_access_address(0xFFFF_FFFF));
+
+        // More than 6 consecutive zeros or ones
+        assert!(!is_valid_access_address(0x8E80_81D6));
+        assert!(!is_valid_access_address(0x8E89_BE80));
+        assert!(!is_valid_access_address(0x8EFE_81D6));
+        assert!(!is_valid_access_address(0x8E89_FFD6));
+
+        // More than 24 transitions
+        assert!(!is_valid_access_address(0x5555_5556));
+        assert!(!is_valid_access_address(0x5555_AAAA));
+        assert!(!is_valid_access_address(0xAB5A_A5AB));
+
+        // Less than 2 transitions in the 6 MSbs
+        assert!(!is_valid_access_address(0x0789_BED6));
+        assert!(!is_valid_access_address(0xF389_BED6));
+        assert!(!is_valid_access_address(0x7E65_17AF));
+    }
+
+    #[test]
+    fn rejection_sampling() {
+        let mut rng = ScriptedRng(&[0x8E89_BED6, 0x5A5A_5A5A, 0x5555_5556, 0x5065_17AF]);
+        assert_eq!(random_access_address(&mut rng), 0x5065_17AF);
+    }
+
+    #[test]
+    fn generated_addresses_are_valid() {
+        let mut rng = MockRng::default();
+        for _ in 0..1000 {
+            let aa = random_access_address(&mut rng);
+            let bits: Vec<u32> = (0..32).map(|i| (aa >> i) & 1).collect();
+
+            assert!(is_valid_access_address(aa));
+            assert_ne!(aa, ACCESS_ADDRESS);
+            assert!((aa ^ ACCESS_ADDRESS).count_ones() > 1, "{:#010X}", aa);
+            assert!(
+                aa.to_le_bytes().windows(2).any(|w| w[0] != w[1]),
+                "{:#010X}",
+                aa
+            );
+            assert!(
+                bits.windows(7).all(|w| w.iter().any(|&b| b != w[0])),
+                "{:#010X} has more than 6 equal bits in a row",
+                aa
+            );
+
+            let transitions = bits.windows(2).filter(|w| w[0] != w[1]).count();
+            assert!(transitions <= 24, "{:#010X}", aa);
+            let msb_transitions = bits[26..].windows(2).filter(|w| w[0] != w[1]).count();
+            assert!(msb_transitions >= 2, "{:#010X}", aa);
+        }
+    }
+}
@@ -4,12 +4,20 @@
 //!
 //! Also see the [assigned numbers document][gap] hosted by the SIG.
 //!
+//! Advertising data can be assembled from a list of [`AdStructure`]s using [`AdvertisingData`],
+//! which checks that the data fits into a single PDU. Received data can be decoded with
+//! [`AdStructureIter`].
+//!
 //! [gap]: https://www.bluetooth.com/specifications/assigned-numbers/generic-access-profile
 
 use crate::link::CompanyId;
 use crate::uuid::{IsUuid, Uuid128, Uuid16, Uuid32, UuidKind};
 use crate::{bytes::*, Error};
 use bitflags::bitflags;
+use core::{fmt, str};
+
+/// Maximum length of the advertising data in an advertising or scan response PDU (31 Bytes).
+pub const MAX_DATA_LEN: usize = 31;
 
 /// A list of AD structures can be sent along with an advertising packet or scan response.
 ///
@@ -44,6 +52,14 @@ pub enum AdStructure<'a> {
         data: &'a [u8],
     },
 
+    /// Service data with 128-bit service UUID.
+    ServiceData128 {
+        /// The 128-bit service UUID.
+        uuid: Uuid128,
+        /// The associated service data. May be empty.
+        data: &'a [u8],
+    },
+
     /// Sets the full (unabbreviated) device name.
     ///
     /// This will be shown to the user when this device is found.
@@ -52,6 +68,11 @@ pub enum AdStructure<'a> {
     /// Sets the shortened device name.
     ShortenedLocalName(&'a str),
 
+    /// The transmitted power level of the packet in dBm.
+    ///
+    /// This allows scanners to estimate the path loss.
+    TxPowerLevel(i8),
+
     /// Set manufacturer specific data
     ManufacturerSpecificData {
         company_identifier: CompanyId,
@@ -59,6 +80,9 @@ pub enum AdStructure<'a> {
     },
 
     /// An unknown or unimplemented AD structure stored as raw bytes.
+    ///
+    /// Received AD structures whose content is malformed (for example, names that aren't valid
+    /// UTF-8) are also decoded as `Unknown`.
     Unknown {
         /// Type byte.
         ty: u8,
@@ -94,6 +118,15 @@ impl<'a> ToBytes for AdStructure<'a> {
                 buf.write_u8((*uuid >> 8) as u8)?;
                 buf.write_slice(data)?;
             }
+            AdStructure::ServiceData128 { uuid, data } => {
+                buf.write_u8(Type::SERVICE_DATA_128BIT_UUID)?;
+                uuid.to_bytes(buf)?;
+                buf.write_slice(data)?;
+            }
+            AdStructure::TxPowerLevel(level) => {
+                buf.write_u8(Type::TX_POWER_LEVEL)?;
+                buf.write_u8(*level as u8)?;
+            }
             AdStructure::CompleteLocalName(name) => {
                 buf.write_u8(Type::COMPLETE_LOCAL_NAME)?;
                 buf.write_slice(name.as_bytes())?;
@@ -107,7 +140,7 @@ impl<'a> ToBytes for AdStructure<'a> {
                 payload,
             } => {
                 buf.write_u8(Type::MANUFACTURER_SPECIFIC_DATA)?;
-                buf.write_u16_le(company_identifier.as_u16())?;
+                company_identifier.to_bytes(buf)?;
                 buf.write_slice(payload)?;
             }
             AdStructure::Unknown { ty, data } => {
@@ -136,6 +169,16 @@ impl<'a> FromBytes<'a> for AdStructure<'a> {
         let ty = ty_and_data[0];
         let data = &ty_and_data[1..];
 
+        // A malformed structure is still delimited by its length, so it doesn't affect the others
+        Ok(Self::decode(ty_and_data).unwrap_or(AdStructure::Unknown { ty, data }))
+    }
+}
+
+impl<'a> AdStructure<'a> {
+    /// Decodes the type-specific content of an AD structure, following its length Byte.
+    fn decode(ty_and_data: &'a [u8]) -> Result<Self, Error> {
+        let ty = ty_and_data[0];
+        let data = &ty_and_data[1..];
         Ok(match ty {
             Type::FLAGS => {
                 if data.len() != 1 {
@@ -151,11 +194,190 @@ impl<'a> FromBytes<'a> for AdStructure<'a> {
                 let uuids = ServiceUuids::<Uuid16>::from_bytes(&mut ByteReader::new(ty_and_data))?;
                 AdStructure::ServiceUuids16(uuids)
             }
+            Type::COMPLETE_LIST_OF_32BIT_SERVICE_UUIDS
+            | Type::INCOMPLETE_LIST_OF_32BIT_SERVICE_UUIDS => {
+                let uuids = ServiceUuids::<Uuid32>::from_bytes(&mut ByteReader::new(ty_and_data))?;
+                AdStructure::ServiceUuids32(uuids)
+            }
+            Type::COMPLETE_LIST_OF_128BIT_SERVICE_UUIDS
+            | Type::INCOMPLETE_LIST_OF_128BIT_SERVICE_UUIDS => {
+                let uuids = ServiceUuids::<Uuid128>::from_bytes(&mut ByteReader::new(ty_and_data))?;
+                AdStructure::ServiceUuids128(uuids)
+            }
+            Type::SERVICE_DATA_16BIT_UUID => {
+                let mut bytes = ByteReader::new(data);
+                let uuid = bytes.read_u16_le()?;
+                AdStructure::ServiceData16 {
+                    uuid,
+                    data: bytes.read_rest(),
+                }
+            }
+            Type::SERVICE_DATA_128BIT_UUID => {
+                let mut bytes = ByteReader::new(data);
+                let uuid = <Uuid128 as FromBytes>::from_bytes(&mut bytes)?;
+                AdStructure::ServiceData128 {
+                    uuid,
+                    data: bytes.read_rest(),
+                }
+            }
+            Type::COMPLETE_LOCAL_NAME => AdStructure::CompleteLocalName(
+                str::from_utf8(data).map_err(|_| Error::InvalidValue)?,
+            ),
+            Type::SHORTENED_LOCAL_NAME => AdStructure::ShortenedLocalName(
+                str::from_utf8(data).map_err(|_| Error::InvalidValue)?,
+            ),
+            Type::TX_POWER_LEVEL => {
+                if data.len() != 1 {
+                    return Err(Error::InvalidLength);
+                }
+
+                AdStructure::TxPowerLevel(data[0] as i8)
+            }
+            Type::MANUFACTURER_SPECIFIC_DATA => {
+                let mut bytes = ByteReader::new(data);
+                let company_identifier = CompanyId::from_bytes(&mut bytes)?;
+                AdStructure::ManufacturerSpecificData {
+                    company_identifier,
+                    payload: bytes.read_rest(),
+                }
+            }
             _ => AdStructure::Unknown { ty, data },
         })
     }
 }
 
+/// Advertising or scan response data consisting of a list of AD structures.
+///
+/// This is a builder for the data sent along with advertising PDUs. It stores up to
+/// [`MAX_DATA_LEN`] Bytes of encoded AD structures and refuses to add structures that don't fit.
+/// The data can be turned into a PDU using [`PduBuf::with_advertising_data`].
+///
+/// [`PduBuf::with_advertising_data`]: crate::link::advertising::PduBuf::with_advertising_data
+#[derive(Copy, Clone)]
+pub struct AdvertisingData {
+    buf: [u8; MAX_DATA_LEN],
+    len: u8,
+}
+
+impl AdvertisingData {
+    /// Creates an empty advertising data buffer.
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_DATA_LEN],
+            len: 0,
+        }
+    }
+
+    /// Encodes a list of AD structures.
+    ///
+    /// Returns `Error::Eof` if the encoded structures exceed [`MAX_DATA_LEN`] Bytes.
+    pub fn from_structures(structures: &[AdStructure<'_>]) -> Result<Self, Error> {
+        let mut this = Self::new();
+        for ad in structures {
+            this.push(ad)?;
+        }
+        Ok(this)
+    }
+
+    /// Appends an AD structure to the data.
+    ///
+    /// Returns `Error::Eof` if the structure doesn't fit into the remaining space, in which case
+    /// `self` is left unchanged.
+    pub fn push(&mut self, ad: &AdStructure<'_>) -> Result<&mut Self, Error> {
+        let len = usize::from(self.len);
+        let mut writer = ByteWriter::new(&mut self.buf[len..]);
+        let left = writer.space_left();
+        ad.to_bytes(&mut writer)?;
+        let used = left - writer.space_left();
+        self.len += used as u8;
+        Ok(self)
+    }
+
+    /// Appends an AD structure to the data, builder-style.
+    ///
+    /// Returns `Error::Eof` if the structure doesn't fit into the remaining space.
+    pub fn with<'a>(mut self, ad: impl Into<AdStructure<'a>>) -> Result<Self, Error> {
+        self.push(&ad.into())?;
+        Ok(self)
+    }
+
+    /// Returns the encoded AD structures.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..usize::from(self.len)]
+    }
+
+    /// Returns the length of the encoded data in Bytes.
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    /// Returns whether no AD structures have been added.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator decoding the contained AD structures.
+    pub fn iter(&self) -> AdStructureIter<'_> {
+        AdStructureIter::new(self.as_bytes())
+    }
+}
+
+impl Default for AdvertisingData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AdvertisingData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl ToBytes for AdvertisingData {
+    fn to_bytes(&self, buffer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        buffer.write_slice(self.as_bytes())
+    }
+}
+
+/// An iterator decoding the AD structures in received advertising or scan response data.
+///
+/// Every item is either a decoded AD structure or the error encountered while decoding it. Errors
+/// only occur if the length of a structure exceeds the data, in which case the iterator ends since
+/// the remaining data can't be delimited. Structures with malformed content are returned as
+/// [`AdStructure::Unknown`]. A length Byte of 0 also ends the data (the remaining Bytes are
+/// padding).
+#[derive(Debug, Copy, Clone)]
+pub struct AdStructureIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AdStructureIter<'a> {
+    /// Creates an iterator over the AD structures encoded in `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for AdStructureIter<'a> {
+    type Item = Result<AdStructure<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.data.first() {
+            None | Some(0) => None,
+            Some(_) => {
+                let mut bytes = ByteReader::new(self.data);
+                let result = AdStructure::from_bytes(&mut bytes);
+                self.data = match result {
+                    Ok(_) => bytes.into_rest(),
+                    Err(_) => &[],
+                };
+                Some(result)
+            }
+        }
+    }
+}
+
 /// List of service UUIDs offered by the device.
 ///
 /// The list can be marked as complete or incomplete. For an incomplete list,
@@ -370,3 +592,182 @@ impl Type {
     const _3D_INFORMATION_DATA: u8 = 0x3D;
     const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::advertising::{Header, Pdu, PduBuf, PduType};
+    use crate::link::{AddressKind, DeviceAddress};
+
+    #[test]
+    fn discoverable_peripheral() {
+        let uuids = [Uuid16(0x180F), Uuid16(0x180A)];
+        let data = AdvertisingData::new()
+            .with(Flags::discoverable())
+            .unwrap()
+            .with(AdStructure::ServiceUuids16(ServiceUuids::from_uuids(
+                true, &uuids,
+            )))
+            .unwrap()
+            .with(AdStructure::CompleteLocalName("Rubble"))
+            .unwrap();
+
+        assert_eq!(
+            data.as_bytes(),
+            &[
+                0x02, 0x01, 0x06, // Flags
+                0x05, 0x03, 0x0F, 0x18, 0x0A, 0x18, // Service UUIDs
+                0x07, 0x09, b'R', b'u', b'b', b'b', b'l', b'e', // Name
+            ]
+        );
+
+        let mut iter = data.iter();
+        assert!(matches!(iter.next(), Some(Ok(AdStructure::Flags(f))) if f.bits() == 0x06));
+        match iter.next() {
+            Some(Ok(AdStructure::ServiceUuids16(list))) => {
+                assert!(list.is_complete());
+                assert!(list.iter().eq(uuids.iter().copied()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            iter.next(),
+            Some(Ok(AdStructure::CompleteLocalName("Rubble")))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn beacon_payloads() {
+        let uuid = Uuid128::from_bytes([0xAB; 16]);
+        let data = AdvertisingData::from_structures(&[
+            AdStructure::ManufacturerSpecificData {
+                company_identifier: CompanyId::NORDIC_SEMICONDUCTOR,
+                payload: &[1, 2, 3],
+            },
+            AdStructure::TxPowerLevel(-8),
+            AdStructure::ServiceData16 {
+                uuid: 0xFEAA,
+                data: &[0x10],
+            },
+        ])
+        .unwrap();
+        let decoded: Vec<_> = data.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert!(matches!(
+            decoded[0],
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                payload: &[1, 2, 3],
+            } if company_identifier == CompanyId::NORDIC_SEMICONDUCTOR
+        ));
+        assert!(matches!(decoded[1], AdStructure::TxPowerLevel(-8)));
+        assert!(matches!(
+            decoded[2],
+            AdStructure::ServiceData16 {
+                uuid: 0xFEAA,
+                data: &[0x10],
+            }
+        ));
+
+        let data = AdvertisingData::from_structures(&[AdStructure::ServiceData128 {
+            uuid,
+            data: &[0x42],
+        }])
+        .unwrap();
+        assert_eq!(data.len(), 2 + 16 + 1);
+        assert!(matches!(
+            data.iter().next(),
+            Some(Ok(AdStructure::ServiceData128 { uuid: u, data: &[0x42] })) if u == uuid
+        ));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut data = AdvertisingData::new();
+        data.push(&Flags::discoverable().into()).unwrap();
+        let name = "a name that is much too long to fit";
+        assert_eq!(
+            data.push(&AdStructure::CompleteLocalName(name))
+                .unwrap_err(),
+            Error::Eof
+        );
+        assert_eq!(data.len(), 3, "failed push must not modify data");
+
+        data.push(&AdStructure::ShortenedLocalName(&name[..26]))
+            .unwrap();
+        assert_eq!(data.len(), MAX_DATA_LEN);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        // Padding ends the data
+        let mut iter = AdStructureIter::new(&[0x02, 0x0A, 0xF8, 0x00, 0xFF, 0xFF]);
+        assert!(matches!(
+            iter.next(),
+            Some(Ok(AdStructure::TxPowerLevel(-8)))
+        ));
+        assert!(iter.next().is_none());
+
+        // Structures with malformed content don't affect the others
+        let payload = [
+            1, 2, 3, 4, 5, 6, // AdvA
+            0x03, 0x0A, 0xF8, 0x00, // TX Power Level with 2 Bytes
+            0x02, 0x09, 0xFF, // Name that isn't UTF-8
+            0x02, 0x09, b'a',
+        ];
+        let mut header = Header::new(PduType::AdvNonconnInd);
+        header.set_payload_length(payload.len() as u8);
+        let pdu = Pdu::from_header_and_payload(header, &mut ByteReader::new(&payload)).unwrap();
+        let mut ads = pdu.advertising_data().unwrap();
+        assert!(matches!(
+            ads.next(),
+            Some(AdStructure::Unknown {
+                ty: 0x0A,
+                data: [0xF8, 0x00],
+            })
+        ));
+        assert!(matches!(
+            ads.next(),
+            Some(AdStructure::Unknown {
+                ty: 0x09,
+                data: [0xFF],
+            })
+        ));
+        assert!(matches!(
+            ads.next(),
+            Some(AdStructure::CompleteLocalName("a"))
+        ));
+        assert!(ads.next().is_none());
+
+        // Truncated structure
+        let mut iter = AdStructureIter::new(&[0x05, 0x09, b'a']);
+        assert!(matches!(iter.next(), Some(Err(Error::Eof))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn pdu_round_trip() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Random);
+        let data = AdvertisingData::new()
+            .with(AdStructure::CompleteLocalName("Rubble"))
+            .unwrap();
+        let pdu = PduBuf::with_advertising_data(PduType::AdvNonconnInd, addr, &data).unwrap();
+        assert!(pdu.header().tx_add());
+
+        let parsed =
+            Pdu::from_header_and_payload(pdu.header(), &mut ByteReader::new(pdu.payload()))
+                .unwrap();
+        assert_eq!(parsed.sender(), &addr);
+        let mut ads = parsed.advertising_data().unwrap();
+        assert!(matches!(
+            ads.next(),
+            Some(AdStructure::CompleteLocalName("Rubble"))
+        ));
+
+        assert_eq!(
+            PduBuf::with_advertising_data(PduType::ScanReq, addr, &data).unwrap_err(),
+            Error::InvalidValue
+        );
+    }
+}
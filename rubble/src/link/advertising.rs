@@ -7,10 +7,14 @@
 //! Note that while the types in here do not completely eliminate illegal values to be created, they
 //! do employ a range of sanity checks that prevent bogus packets from being sent by the stack.
 
-use crate::link::ad_structure::{AdStructure, Flags};
-use crate::link::{channel_map::ChannelMap, AddressKind, DeviceAddress};
+use crate::link::ad_structure::{AdStructure, AdvertisingData, Flags};
+use crate::link::{
+    channel_map::ChannelMap, connection::window_widening, AddressKind, DeviceAddress,
+};
+use crate::phy::AdvertisingChannel;
 use crate::utils::{Hex, HexSlice};
 use crate::{bytes::*, time::Duration, Error};
+use bitflags::bitflags;
 use core::{convert::TryInto, fmt, iter};
 
 /// CRC initialization value for advertising channel packets.
@@ -230,7 +234,8 @@ impl<'a> Pdu<'a> {
                 },
                 lldata: ConnectRequestData::from_bytes(payload)?,
             },
-            PduType::Unknown(_) => return Err(Error::InvalidValue),
+            // Extended advertising PDUs are only sent, not parsed
+            PduType::AdvExtInd | PduType::Unknown(_) => return Err(Error::InvalidValue),
         })
     }
 
@@ -376,13 +381,30 @@ impl ConnectRequestData {
         self.hop
     }
 
-    /// Returns the end of the transmit window from reception of the `CONNECT_REQ` containing
+    /// Returns the size of the transmit window in which the master sends the first data PDU.
+    pub fn win_size(&self) -> Duration {
+        self.win_size
+    }
+
+    /// Returns the offset of the transmit window, counted from the end of the transmit window
+    /// delay (see [`start_of_tx_window`](Self::start_of_tx_window)).
+    pub fn win_offset(&self) -> Duration {
+        self.win_offset
+    }
+
+    /// Returns the start of the transmit window from reception of the `CONNECT_REQ` containing
     /// `self`.
-    pub fn end_of_tx_window(&self) -> Duration {
+    pub fn start_of_tx_window(&self) -> Duration {
         // We only handle `CONNECT_IND`, so transmitWindowDelay is 1.25 ms
         let transmit_window_delay = Duration::micros(1250);
 
-        self.win_offset + self.win_size + transmit_window_delay
+        self.win_offset + transmit_window_delay
+    }
+
+    /// Returns the end of the transmit window from reception of the `CONNECT_REQ` containing
+    /// `self`.
+    pub fn end_of_tx_window(&self) -> Duration {
+        self.start_of_tx_window() + self.win_size
     }
 
     /// Returns the connection event interval in µs.
@@ -402,18 +424,112 @@ impl ConnectRequestData {
     pub fn supervision_timeout(&self) -> Duration {
         self.timeout
     }
+
+    /// Returns the accuracy of the master's sleep clock.
+    pub fn sleep_clock_accuracy(&self) -> SleepClockAccuracy {
+        self.sca
+    }
+
+    /// Creates the connection parameters to send in a `CONNECT_IND` when connecting as the master.
+    ///
+    /// The connection uses no slave latency and a transmit window of 1.25 ms directly after the
+    /// transmit window delay. Use [`with_anchor_delay`](Self::with_anchor_delay) to place the first
+    /// connection event elsewhere.
+    ///
+    /// # Parameters
+    ///
+    /// * **`access_address`**, **`crc_init`**: Generated according to the spec's requirements.
+    /// * **`interval`**: The connection interval in range 7.5 ms to 4 s, in 1.25 ms steps.
+    /// * **`timeout`**: The supervision timeout, in 10 ms steps.
+    /// * **`chm`**: The data channels to use.
+    /// * **`hop`**: Channel hop distance in range `5..=16`.
+    /// * **`sca`**: Accuracy of our own sleep clock.
+    ///
+    /// Returns `Error::InvalidValue` if the parameters are not allowed by the spec.
+    pub fn new(
+        access_address: u32,
+        crc_init: u32,
+        interval: Duration,
+        timeout: Duration,
+        chm: ChannelMap,
+        hop: u8,
+        sca: SleepClockAccuracy,
+    ) -> Result<Self, Error> {
+        let data = Self {
+            access_address: Hex(access_address),
+            crc_init: Hex(crc_init & 0xFF_FFFF),
+            win_size: TX_WINDOW_UNIT,
+            win_offset: Duration::micros(0),
+            interval,
+            latency: 0,
+            timeout,
+            chm,
+            hop,
+            sca,
+        };
+        if interval.to_micros() % 1_250 != 0 || timeout.to_micros() % 10_000 != 0 {
+            return Err(Error::InvalidValue);
+        }
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// Places the transmit window so that the master can send its first data PDU `delay` after the
+    /// end of the `CONNECT_IND`.
+    ///
+    /// The window is made large enough that the anchor point stays inside of it despite the drift
+    /// of the master's sleep clock, so the slave's listen window always covers it.
+    ///
+    /// Returns `Error::InvalidValue` if `delay` is too short for the transmit window delay, or too
+    /// long for the allowed transmit window offset.
+    pub fn with_anchor_delay(mut self, delay: Duration) -> Result<Self, Error> {
+        let transmit_window_delay = Duration::micros(1250);
+        let margin = window_widening(
+            self.sca.ppm(),
+            Duration::micros(0),
+            delay + TX_WINDOW_UNIT,
+            self.interval,
+        );
+
+        let earliest = delay
+            .checked_sub(transmit_window_delay + margin)
+            .ok_or(Error::InvalidValue)?;
+        self.win_offset = Duration::micros(earliest.to_micros() / 1_250 * 1_250);
+        let needed = delay + margin - self.start_of_tx_window();
+        self.win_size = Duration::micros(needed.to_micros().div_ceil(1_250).max(1) * 1_250);
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Checks the parameters against the limits in the spec.
+    fn validate(&self) -> Result<(), Error> {
+        let interval_range = Duration::micros(7_500)..=Duration::millis(4_000);
+        if !interval_range.contains(&self.interval) {
+            return Err(Error::InvalidValue);
+        }
+
+        let max_win_size = Duration::millis(10).min(self.interval - Duration::micros(1_250));
+        if !(5..=16).contains(&self.hop)
+            || self.win_size == Duration::micros(0)
+            || self.win_size > max_win_size
+            || self.win_offset > self.interval
+        {
+            return Err(Error::InvalidValue);
+        }
+        Ok(())
+    }
 }
 
+/// Unit of the transmit window size and offset.
+const TX_WINDOW_UNIT: Duration = Duration::micros(1_250);
+
 impl FromBytes<'_> for ConnectRequestData {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         let sca;
-        Ok(Self {
+        let data = Self {
             access_address: Hex(bytes.read_u32_le()?),
-            crc_init: {
-                let mut le_bytes = [0u8; 4];
-                le_bytes[..3].copy_from_slice(bytes.read_slice(3)?);
-                Hex(u32::from_le_bytes(le_bytes))
-            },
+            crc_init: Hex(bytes.read_u24_le()?),
             // transmitWindowSize in 1.25 ms steps
             win_size: Duration::micros(u32::from(bytes.read_u8()?) * 1250),
             // transmitWindowOffset in 1.25 ms steps
@@ -444,7 +560,25 @@ impl FromBytes<'_> for ConnectRequestData {
                     _ => unreachable!(), // only 3 bits
                 }
             },
-        })
+        };
+
+        // Reject parameters forbidden by the spec, since we couldn't follow the connection
+        data.validate()?;
+        Ok(data)
+    }
+}
+
+impl ToBytes for ConnectRequestData {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u32_le(self.access_address.0)?;
+        writer.write_u24_le(self.crc_init.0)?;
+        writer.write_u8((self.win_size.to_micros() / 1250) as u8)?;
+        writer.write_u16_le((self.win_offset.to_micros() / 1250) as u16)?;
+        writer.write_u16_le((self.interval.to_micros() / 1250) as u16)?;
+        writer.write_u16_le(self.latency)?;
+        writer.write_u16_le((self.timeout.to_micros() / 10_000) as u16)?;
+        writer.write_slice(&self.chm.to_raw())?;
+        writer.write_u8(self.sca.to_raw() << 5 | self.hop)
     }
 }
 
@@ -452,7 +586,7 @@ impl FromBytes<'_> for ConnectRequestData {
 /// million).
 ///
 /// The lower the PPM, the higher the accuracy.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SleepClockAccuracy {
     Ppm251To500,
     Ppm151To250,
@@ -464,6 +598,55 @@ pub enum SleepClockAccuracy {
     Ppm0To20,
 }
 
+impl SleepClockAccuracy {
+    /// Returns the most accurate range that includes a clock accuracy of `ppm`.
+    ///
+    /// Clocks worse than 500 ppm are reported as `Ppm251To500`.
+    pub fn from_ppm(ppm: u32) -> Self {
+        use self::SleepClockAccuracy::*;
+        match ppm {
+            0..=20 => Ppm0To20,
+            21..=30 => Ppm21To30,
+            31..=50 => Ppm31To50,
+            51..=75 => Ppm51To75,
+            76..=100 => Ppm76To100,
+            101..=150 => Ppm101To150,
+            151..=250 => Ppm151To250,
+            _ => Ppm251To500,
+        }
+    }
+
+    /// Returns the 3-bit value encoding this accuracy in `CONNECT_IND` and `SyncInfo`.
+    pub(crate) fn to_raw(self) -> u8 {
+        use self::SleepClockAccuracy::*;
+        match self {
+            Ppm251To500 => 0,
+            Ppm151To250 => 1,
+            Ppm101To150 => 2,
+            Ppm76To100 => 3,
+            Ppm51To75 => 4,
+            Ppm31To50 => 5,
+            Ppm21To30 => 6,
+            Ppm0To20 => 7,
+        }
+    }
+
+    /// Returns the worst-case clock accuracy in ppm (the upper end of the range).
+    pub fn ppm(&self) -> u32 {
+        use self::SleepClockAccuracy::*;
+        match self {
+            Ppm251To500 => 500,
+            Ppm151To250 => 250,
+            Ppm101To150 => 150,
+            Ppm76To100 => 100,
+            Ppm51To75 => 75,
+            Ppm31To50 => 50,
+            Ppm21To30 => 30,
+            Ppm0To20 => 20,
+        }
+    }
+}
+
 /// Stores an advertising channel PDU.
 ///
 /// This is an owned version of `Pdu` and should be used when *creating* a PDU
@@ -557,8 +740,6 @@ impl PduBuf {
     }
 
     /// Creates a scannable undirected advertising PDU (`ADV_SCAN_IND`).
-    ///
-    /// Note that scanning is not supported at the moment.
     pub fn scannable_undirected(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
@@ -604,9 +785,36 @@ impl PduBuf {
         )
     }
 
-    /// Creates a scan request PDU.
+    /// Creates a connection request PDU (`CONNECT_IND`).
     ///
-    /// Note that scanning is not yet implemented.
+    /// # Parameters
+    ///
+    /// * `initiator_addr`: Device address of the initiating device (the future master).
+    /// * `advertiser_addr`: Device address of the advertiser to connect to.
+    /// * `lldata`: The parameters of the connection.
+    pub fn connect_request(
+        initiator_addr: DeviceAddress,
+        advertiser_addr: DeviceAddress,
+        lldata: &ConnectRequestData,
+    ) -> Self {
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        payload[0..6].copy_from_slice(initiator_addr.raw());
+        payload[6..12].copy_from_slice(advertiser_addr.raw());
+        lldata
+            .to_bytes(&mut ByteWriter::new(&mut payload[12..34]))
+            .unwrap();
+
+        let mut header = Header::new(PduType::ConnectReq);
+        header.set_payload_length(34);
+        header.set_tx_add(initiator_addr.is_random());
+        header.set_rx_add(advertiser_addr.is_random());
+        Self {
+            header,
+            payload_buf: payload,
+        }
+    }
+
+    /// Creates a scan request PDU (`SCAN_REQ`).
     ///
     /// # Parameters
     ///
@@ -614,13 +822,22 @@ impl PduBuf {
     ///   the request).
     /// * `adv`: Device address of the advertising device that this scan request
     ///   is directed towards.
-    pub fn scan_request(_scanner: DeviceAddress, _adv: DeviceAddress) -> Result<Self, Error> {
-        unimplemented!()
+    pub fn scan_request(scanner: DeviceAddress, adv: DeviceAddress) -> Result<Self, Error> {
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        payload[0..6].copy_from_slice(scanner.raw());
+        payload[6..12].copy_from_slice(adv.raw());
+
+        let mut header = Header::new(PduType::ScanReq);
+        header.set_payload_length(6 + 6);
+        header.set_tx_add(scanner.is_random());
+        header.set_rx_add(adv.is_random());
+        Ok(Self {
+            header,
+            payload_buf: payload,
+        })
     }
 
-    /// Creates a scan response PDU.
-    ///
-    /// Note that scanning is not yet implemented.
+    /// Creates a scan response PDU (`SCAN_RSP`).
     pub fn scan_response(
         advertiser_addr: DeviceAddress,
         scan_data: &[AdStructure<'_>],
@@ -644,6 +861,34 @@ impl PduBuf {
         })
     }
 
+    /// Creates a PDU carrying prebuilt advertising data.
+    ///
+    /// `ty` must be a PDU type that carries AD structures (`ADV_IND`, `ADV_NONCONN_IND`,
+    /// `ADV_SCAN_IND`, or `SCAN_RSP`), otherwise `Error::InvalidValue` is returned.
+    pub fn with_advertising_data(
+        ty: PduType,
+        advertiser_addr: DeviceAddress,
+        data: &AdvertisingData,
+    ) -> Result<Self, Error> {
+        match ty {
+            PduType::AdvInd | PduType::AdvNonconnInd | PduType::AdvScanInd | PduType::ScanRsp => {}
+            _ => return Err(Error::InvalidValue),
+        }
+
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        payload[..6].copy_from_slice(advertiser_addr.raw());
+        payload[6..6 + data.len()].copy_from_slice(data.as_bytes());
+
+        let mut header = Header::new(ty);
+        header.set_payload_length((6 + data.len()) as u8);
+        header.set_tx_add(advertiser_addr.is_random());
+        header.set_rx_add(false);
+        Ok(Self {
+            header,
+            payload_buf: payload,
+        })
+    }
+
     pub fn header(&self) -> Header {
         self.header
     }
@@ -652,6 +897,16 @@ impl PduBuf {
         let len = self.header.payload_length() as usize;
         &self.payload_buf[..len]
     }
+
+    /// Replaces the advertiser address (`AdvA`) contained in this PDU.
+    ///
+    /// All PDUs created by `PduBuf` except `SCAN_REQ` start with the advertiser address, so this can
+    /// be used to update the address without rebuilding the PDU (eg. when a new private address is
+    /// generated).
+    pub fn set_advertiser_address(&mut self, addr: DeviceAddress) {
+        self.payload_buf[..6].copy_from_slice(addr.raw());
+        self.header.set_tx_add(addr.is_random());
+    }
 }
 
 impl fmt::Debug for PduBuf {
@@ -666,17 +921,18 @@ impl fmt::Debug for PduBuf {
 ///
 /// ```notrust
 /// LSB                                                                     MSB
-/// +------------+------------+---------+---------+--------------+------------+
-/// |  PDU Type  |     -      |  TxAdd  |  RxAdd  |    Length    |     -      |
-/// |  (4 bits)  |  (2 bits)  | (1 bit) | (1 bit) |   (6 bits)   |  (2 bits)  |
-/// +------------+------------+---------+---------+--------------+------------+
+/// +------------+------------+---------+---------+--------------+
+/// |  PDU Type  |     -      |  TxAdd  |  RxAdd  |    Length    |
+/// |  (4 bits)  |  (2 bits)  | (1 bit) | (1 bit) |   (8 bits)   |
+/// +------------+------------+---------+---------+--------------+
 /// ```
 ///
 /// The `TxAdd` and `RxAdd` field are only used for some payloads, for all others, they should be
 /// set to 0.
 ///
-/// Length may be in range 6 to 37 (inclusive). With the 2-Byte header this is exactly the max.
-/// on-air packet size.
+/// Length may be in range 6 to 37 (inclusive) for legacy PDUs. With the 2-Byte header this is
+/// exactly the max. on-air packet size. Extended advertising PDUs (`PduType::AdvExtInd`) may use
+/// the full 8-bit range.
 #[derive(Copy, Clone)]
 pub struct Header(u16);
 
@@ -689,6 +945,10 @@ impl Header {
         Header(u16::from(u8::from(ty)))
     }
 
+    /// Parses a header from raw bytes.
+    ///
+    /// Panics when `raw` contains less than 2 Bytes. Use the [`FromBytes`] implementation to
+    /// handle truncated input gracefully.
     pub fn parse(raw: &[u8]) -> Self {
         let bytes: [u8; 2] = raw[..2].try_into().expect("raw has fewer than 2 bytes");
         Header(u16::from_le_bytes(bytes))
@@ -746,19 +1006,24 @@ impl Header {
 
     /// Returns the length of the payload in octets as specified in the `Length` field.
     ///
-    /// According to the spec, the length must be in range 6...37, but this isn't checked by this
-    /// function.
+    /// According to the spec, the length of legacy PDUs must be in range 6...37, but this isn't
+    /// checked by this function.
     pub fn payload_length(&self) -> u8 {
-        ((self.0 & 0b00111111_00000000) >> 8) as u8
+        (self.0 >> 8) as u8
     }
 
     /// Sets the payload length of this PDU.
     ///
-    /// The `length` must be in range 6...37, otherwise this function panics.
+    /// The `length` must be in range 6...37, or at least 1 for extended advertising PDUs
+    /// (`PduType::AdvExtInd`), otherwise this function panics.
     pub fn set_payload_length(&mut self, length: u8) {
-        assert!(6 <= length && length <= 37);
+        if self.type_() == PduType::AdvExtInd {
+            assert!(length >= 1);
+        } else {
+            assert!(6 <= length && length <= 37);
+        }
 
-        let header = self.0 & !0b00111111_00000000;
+        let header = self.0 & 0x00FF;
         self.0 = header | (u16::from(length) << 8);
     }
 }
@@ -774,6 +1039,20 @@ impl fmt::Debug for Header {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Header {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(
+            fmt,
+            "Header {{ PDU Type: {}, TxAdd: {}, RxAdd: {}, len: {} }}",
+            self.type_(),
+            self.tx_add(),
+            self.rx_add(),
+            self.payload_length()
+        );
+    }
+}
+
 impl<'a> FromBytes<'a> for Header {
     fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
         let raw = bytes.read_u16_le()?;
@@ -791,7 +1070,8 @@ enum_with_unknown! {
     /// 4-bit PDU type in [`Header`].
     ///
     /// For more details, see [`PduBuf`].
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum PduType(u8) {
         /// Connectable undirected advertising event (`ADV_IND`).
         AdvInd = 0b0000,
@@ -817,6 +1097,13 @@ enum_with_unknown! {
         /// Sent by device in Initiating State, received by device in
         /// Advertising State.
         ConnectReq = 0b0101,
+
+        /// Extended advertising PDU (Bluetooth 5).
+        ///
+        /// Used for `ADV_EXT_IND` on the primary advertising channels, and for `AUX_ADV_IND` and
+        /// `AUX_SYNC_IND` on the secondary advertising channels. The payload uses the format
+        /// defined in [`extended`](super::extended).
+        AdvExtInd = 0b0111,
     }
 }
 
@@ -829,9 +1116,11 @@ impl PduType {
     /// Whether AD structures can follow the fixed data in a PDU of this type.
     pub fn allows_adv_data(&self) -> bool {
         match self {
-            PduType::AdvInd | PduType::AdvNonconnInd | PduType::AdvScanInd | PduType::ScanRsp => {
-                true
-            }
+            PduType::AdvInd
+            | PduType::AdvNonconnInd
+            | PduType::AdvScanInd
+            | PduType::ScanRsp
+            | PduType::AdvExtInd => true,
             PduType::AdvDirectInd
             | PduType::ScanReq
             | PduType::ConnectReq
@@ -839,3 +1128,380 @@ impl PduType {
         }
     }
 }
+
+/// Upper bound of the pseudo-random delay added to every advertising interval (`advDelay`).
+pub const MAX_ADV_DELAY: Duration = Duration::millis(10);
+
+bitflags! {
+    /// A set of primary advertising channels.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct AdvChannels: u8 {
+        /// Channel 37 (2402 MHz).
+        const CH37 = 1 << 0;
+        /// Channel 38 (2426 MHz).
+        const CH38 = 1 << 1;
+        /// Channel 39 (2480 MHz).
+        const CH39 = 1 << 2;
+    }
+}
+
+impl AdvChannels {
+    /// Returns whether `channel` is part of this set.
+    pub fn includes(&self, channel: AdvertisingChannel) -> bool {
+        self.bits() & 1 << (channel.channel() - 37) != 0
+    }
+
+    /// Returns the lowest-numbered channel in this set, or `None` if the set is empty.
+    pub fn first(&self) -> Option<AdvertisingChannel> {
+        AdvertisingChannel::iter_all().find(|ch| self.includes(*ch))
+    }
+
+    /// Returns the channel in this set that follows `channel`, or `None` if `channel` is the last
+    /// one.
+    pub fn after(&self, channel: AdvertisingChannel) -> Option<AdvertisingChannel> {
+        AdvertisingChannel::iter_all()
+            .find(|ch| ch.channel() > channel.channel() && self.includes(*ch))
+    }
+
+    /// Returns the channel in this set that follows `channel`, wrapping around to the first one
+    /// after the last channel.
+    ///
+    /// Returns `None` if the set is empty.
+    pub fn cycle(&self, channel: AdvertisingChannel) -> Option<AdvertisingChannel> {
+        self.after(channel).or_else(|| self.first())
+    }
+}
+
+/// Parameters for advertising, passed to [`LinkLayer::start_advertising`].
+///
+/// [`LinkLayer::start_advertising`]: super::LinkLayer::start_advertising
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AdvParams {
+    /// Time between the start of two advertising events.
+    ///
+    /// Must lie between [`MIN_INTERVAL`](Self::MIN_INTERVAL) and
+    /// [`MAX_INTERVAL`](Self::MAX_INTERVAL). A pseudo-random delay of up to [`MAX_ADV_DELAY`] is
+    /// added to every interval, so that devices advertising at the same interval don't collide
+    /// persistently.
+    pub interval: Duration,
+
+    /// The channels to send the advertising PDU on during each event, in ascending order.
+    ///
+    /// Must not be empty. Restricting advertising to a single channel can be useful for testing.
+    pub channels: AdvChannels,
+
+    /// Type of the advertising PDU.
+    ///
+    /// Must be one of `AdvInd` (connectable and scannable), `AdvScanInd` (scannable), or
+    /// `AdvNonconnInd` (neither connectable nor scannable).
+    pub pdu_type: PduType,
+
+    /// Number of times the PDU is repeated on each channel, in addition to the first transmission.
+    ///
+    /// Repeated PDUs are sent back to back, separated only by the transmitter's ramp-up time,
+    /// which makes it more likely that a scanner receives at least one of them. Scan and connect
+    /// requests are only received after the last PDU on each channel.
+    ///
+    /// Must not exceed [`MAX_REPEAT`](Self::MAX_REPEAT). Defaults to 0.
+    pub repeat: u8,
+}
+
+impl AdvParams {
+    /// Smallest allowed advertising interval (20 ms).
+    pub const MIN_INTERVAL: Duration = Duration::millis(20);
+
+    /// Largest allowed advertising interval (10.24 s).
+    pub const MAX_INTERVAL: Duration = Duration::micros(10_240_000);
+
+    /// Largest allowed value of [`repeat`](Self::repeat).
+    ///
+    /// This limits the airtime per channel to ~3.5 ms, so that an advertising event on all 3
+    /// channels takes at most half of the smallest advertising interval.
+    pub const MAX_REPEAT: u8 = 4;
+
+    /// Creates parameters for connectable advertising on all primary channels every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            channels: AdvChannels::all(),
+            pdu_type: PduType::AdvInd,
+            repeat: 0,
+        }
+    }
+
+    /// Checks that the parameters are within the allowed ranges.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let interval_ok = (Self::MIN_INTERVAL..=Self::MAX_INTERVAL).contains(&self.interval);
+        let type_ok = matches!(
+            self.pdu_type,
+            PduType::AdvInd | PduType::AdvScanInd | PduType::AdvNonconnInd
+        );
+        let repeat_ok = self.repeat <= Self::MAX_REPEAT;
+        if interval_ok && type_ok && repeat_ok && !self.channels.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+
+    /// Builds the advertising PDU sent by `addr` with `data`.
+    ///
+    /// Connectable advertisements are made discoverable (see [`PduBuf::discoverable`]).
+    pub(crate) fn pdu(
+        &self,
+        addr: DeviceAddress,
+        data: &[AdStructure<'_>],
+    ) -> Result<PduBuf, Error> {
+        match self.pdu_type {
+            PduType::AdvScanInd => PduBuf::scannable_undirected(addr, data),
+            PduType::AdvNonconnInd => PduBuf::nonconnectable_undirected(addr, data),
+            _ => PduBuf::discoverable(addr, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC: DeviceAddress =
+        DeviceAddress::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06], AddressKind::Public);
+    const RANDOM: DeviceAddress =
+        DeviceAddress::new([0x11, 0x12, 0x13, 0x14, 0x15, 0xD6], AddressKind::Random);
+
+    #[test]
+    fn address_kind_bits() {
+        for &addr in &[PUBLIC, RANDOM] {
+            let random = addr.is_random();
+            let pdus = [
+                PduBuf::connectable_undirected(addr, &[]).unwrap(),
+                PduBuf::beacon(addr, &[]).unwrap(),
+                PduBuf::scan_response(addr, &[]).unwrap(),
+                PduBuf::with_advertising_data(PduType::AdvScanInd, addr, &AdvertisingData::new())
+                    .unwrap(),
+            ];
+            for pdu in &pdus {
+                assert_eq!(pdu.header().tx_add(), random);
+                assert!(!pdu.header().rx_add());
+
+                let parsed =
+                    Pdu::from_header_and_payload(pdu.header(), &mut ByteReader::new(pdu.payload()))
+                        .unwrap();
+                assert_eq!(*parsed.sender(), addr);
+            }
+        }
+
+        let direct = PduBuf::connectable_directed(RANDOM, PUBLIC);
+        assert!(direct.header().tx_add());
+        assert!(!direct.header().rx_add());
+
+        let req = PduBuf::scan_request(PUBLIC, RANDOM).unwrap();
+        assert!(!req.header().tx_add());
+        assert!(req.header().rx_add());
+        let parsed =
+            Pdu::from_header_and_payload(req.header(), &mut ByteReader::new(req.payload()))
+                .unwrap();
+        assert_eq!(*parsed.sender(), PUBLIC);
+        assert_eq!(parsed.receiver(), Some(&RANDOM));
+    }
+
+    #[test]
+    fn set_advertiser_address_updates_tx_add() {
+        let mut pdu = PduBuf::connectable_undirected(PUBLIC, &[]).unwrap();
+        pdu.set_advertiser_address(RANDOM);
+        assert!(pdu.header().tx_add());
+        assert_eq!(&pdu.payload()[..6], RANDOM.raw());
+    }
+
+    #[test]
+    fn truncated_pdus() {
+        let mut connect_req = std::vec::Vec::new();
+        connect_req.extend_from_slice(PUBLIC.raw());
+        connect_req.extend_from_slice(RANDOM.raw());
+        connect_req.extend_from_slice(&[0xAF, 0x17, 0x65, 0x50, 0x55, 0x55, 0x55, 1, 0, 0]);
+        connect_req.extend_from_slice(&[6, 0, 0, 0, 10, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 7]);
+        let scan_req = PduBuf::scan_request(PUBLIC, RANDOM).unwrap();
+        let pdus = [
+            (PduType::ConnectReq, &connect_req[..]),
+            (PduType::ScanReq, scan_req.payload()),
+        ];
+
+        // `Header::set_payload_length` rejects truncated lengths, so build the header manually
+        let header = |ty, len| Header::parse(&[u8::from(ty), len as u8]);
+
+        for (ty, payload) in pdus {
+            let full = header(ty, payload.len());
+            assert!(Pdu::from_header_and_payload(full, &mut ByteReader::new(payload)).is_ok());
+
+            for len in 0..payload.len() {
+                let truncated = &payload[..len];
+
+                // The header still claims the full length
+                let result = Pdu::from_header_and_payload(full, &mut ByteReader::new(truncated));
+                assert_eq!(result.err(), Some(Error::InvalidLength));
+
+                let result =
+                    Pdu::from_header_and_payload(header(ty, len), &mut ByteReader::new(truncated));
+                assert_eq!(result.err(), Some(Error::Eof));
+            }
+        }
+
+        assert_eq!(
+            Header::from_bytes(&mut ByteReader::new(&[0x40])).err(),
+            Some(Error::Eof)
+        );
+    }
+
+    /// A `CONNECT_IND` as sent by a central, connecting to `RANDOM`.
+    const CONNECT_IND: [u8; 36] = [
+        0x85, 0x22, // header: CONNECT_IND, RxAdd = random, 34 Bytes
+        0x3C, 0x4A, 0x2B, 0x1D, 0x5E, 0x6F, // InitA
+        0x11, 0x12, 0x13, 0x14, 0x15, 0xD6, // AdvA
+        0xAF, 0x17, 0x65, 0x50, // AA
+        0x2B, 0x5C, 0x11, // CRCInit
+        0x03, // WinSize
+        0x02, 0x00, // WinOffset
+        0x18, 0x00, // Interval
+        0x01, 0x00, // Latency
+        0x48, 0x00, // Timeout
+        0xFF, 0xFF, 0xFF, 0xFF, 0x1F, // ChM
+        0xA9, // Hop (9), SCA (5)
+    ];
+
+    #[test]
+    fn connect_ind() {
+        let pdu = Pdu::from_bytes(&mut ByteReader::new(&CONNECT_IND)).unwrap();
+        let lldata = match pdu {
+            Pdu::ConnectRequest {
+                initiator_addr,
+                advertiser_addr,
+                lldata,
+            } => {
+                assert_eq!(initiator_addr.raw(), &[0x3C, 0x4A, 0x2B, 0x1D, 0x5E, 0x6F]);
+                assert_eq!(initiator_addr.kind(), AddressKind::Public);
+                assert_eq!(advertiser_addr, RANDOM);
+                lldata
+            }
+            _ => panic!("unexpected PDU {:?}", pdu),
+        };
+
+        assert_eq!(lldata.access_address(), 0x5065_17AF);
+        assert_eq!(lldata.crc_init(), 0x11_5C2B);
+        assert_eq!(lldata.win_size(), Duration::micros(3_750));
+        assert_eq!(lldata.win_offset(), Duration::micros(2_500));
+        assert_eq!(lldata.interval(), Duration::micros(30_000));
+        assert_eq!(lldata.slave_latency(), 1);
+        assert_eq!(lldata.supervision_timeout(), Duration::millis(720));
+        assert_eq!(lldata.channel_map().num_used_channels(), 37);
+        assert_eq!(lldata.hop(), 9);
+        assert_eq!(lldata.sleep_clock_accuracy(), SleepClockAccuracy::Ppm31To50);
+
+        // The transmit window starts 1.25 ms + WinOffset after the `CONNECT_IND`
+        assert_eq!(lldata.start_of_tx_window(), Duration::micros(3_750));
+        assert_eq!(lldata.end_of_tx_window(), Duration::micros(7_500));
+    }
+
+    #[test]
+    fn connect_ind_invalid_params() {
+        // (offset in `CONNECT_IND`, invalid value)
+        let invalid = [
+            (35, 0xA4), // Hop < 5
+            (35, 0xB1), // Hop > 16
+            (21, 0),    // WinSize = 0
+            (21, 9),    // WinSize > 10 ms
+            (22, 0x19), // WinOffset > Interval
+            (24, 5),    // Interval < 7.5 ms
+        ];
+
+        for (offset, value) in invalid {
+            let mut pdu = CONNECT_IND;
+            pdu[offset] = value;
+            assert_eq!(
+                Pdu::from_bytes(&mut ByteReader::new(&pdu)).err(),
+                Some(Error::InvalidValue),
+                "{:#04X} at {}",
+                value,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn connect_request() {
+        let lldata = ConnectRequestData::new(
+            0x5065_17AF,
+            0x11_5C2B,
+            Duration::micros(30_000),
+            Duration::millis(720),
+            ChannelMap::with_all_channels(),
+            9,
+            SleepClockAccuracy::Ppm31To50,
+        )
+        .unwrap();
+
+        // The window must cover 6 ms ± 1 µs of drift, so it starts at 5 ms
+        let lldata = lldata.with_anchor_delay(Duration::micros(6_000)).unwrap();
+        assert_eq!(lldata.win_offset(), Duration::micros(3_750));
+        assert_eq!(lldata.win_size(), Duration::micros(1_250));
+        assert_eq!(lldata.start_of_tx_window(), Duration::micros(5_000));
+
+        let initiator =
+            DeviceAddress::new([0x3C, 0x4A, 0x2B, 0x1D, 0x5E, 0x6F], AddressKind::Public);
+        let pdu = PduBuf::connect_request(initiator, RANDOM, &lldata);
+        let mut expected = CONNECT_IND;
+        expected[21] = 0x01; // WinSize
+        expected[22] = 0x03; // WinOffset
+        expected[26] = 0x00; // Latency
+        assert_eq!(pdu.header().payload_length(), 34);
+        assert!(pdu.header().rx_add());
+        assert!(!pdu.header().tx_add());
+        assert_eq!(pdu.payload(), &expected[2..]);
+
+        // The anchor point can't be before the transmit window delay or after the max. offset
+        assert_eq!(
+            lldata.with_anchor_delay(Duration::micros(1_250)).err(),
+            Some(Error::InvalidValue)
+        );
+        assert_eq!(
+            lldata.with_anchor_delay(Duration::micros(33_000)).err(),
+            Some(Error::InvalidValue)
+        );
+        assert_eq!(
+            ConnectRequestData::new(
+                0x5065_17AF,
+                0x11_5C2B,
+                Duration::micros(30_000),
+                Duration::millis(720),
+                ChannelMap::with_all_channels(),
+                4,
+                SleepClockAccuracy::Ppm31To50,
+            )
+            .err(),
+            Some(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn connect_request_zero_interval() {
+        assert_eq!(
+            ConnectRequestData::new(
+                0x5065_17AF,
+                0x11_5C2B,
+                Duration::micros(0),
+                Duration::millis(720),
+                ChannelMap::with_all_channels(),
+                9,
+                SleepClockAccuracy::Ppm31To50,
+            )
+            .err(),
+            Some(Error::InvalidValue)
+        );
+
+        let mut payload = CONNECT_IND;
+        payload[24..26].copy_from_slice(&[0, 0]); // Interval
+        assert_eq!(
+            ConnectRequestData::from_bytes(&mut ByteReader::new(&payload[14..])).err(),
+            Some(Error::InvalidValue)
+        );
+    }
+}
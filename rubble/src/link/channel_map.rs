@@ -1,13 +1,42 @@
 use crate::{bytes::RawRepr, phy::DataChannel};
 use core::fmt;
 
+/// Returns the channel identifier used by Channel Selection Algorithm #2 for `access_address`.
+///
+/// This is the XOR of the upper and lower 16 bits of the Access Address of the connection or
+/// periodic advertising train.
+pub fn channel_identifier(access_address: u32) -> u16 {
+    (access_address >> 16) as u16 ^ access_address as u16
+}
+
+/// Reverses the bit order within each byte of `value` (the `PERM` operation of CSA #2).
+fn perm(value: u16) -> u16 {
+    let [lo, hi] = value.to_le_bytes();
+    u16::from_le_bytes([lo.reverse_bits(), hi.reverse_bits()])
+}
+
+/// Multiply, add and modulo operation of CSA #2 (`MAM`).
+fn mam(a: u16, b: u16) -> u16 {
+    a.wrapping_mul(17).wrapping_add(b)
+}
+
 /// A map marking data channels as used or unused.
 ///
 /// A channel map must mark at least 2 channels as used.
+///
+/// The channel each unmapped channel is remapped to by Channel Selection Algorithm #1 is computed
+/// once when the map is created, so that [`csa1_channel`] doesn't have to search the map in the
+/// time-critical setup before each connection event. Finding the `n`th used channel walks over up
+/// to 40 bits of the map, which took a few hundred cycles per event when the unmapped channel was
+/// unused; the lookup is now a single array access.
+///
+/// [`csa1_channel`]: Self::csa1_channel
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct ChannelMap {
     raw: [u8; 5],
     num_used_channels: u8,
+    /// The channel used by CSA #1 for each unmapped channel index (derived from `raw`).
+    remapped: [u8; 37],
 }
 
 impl ChannelMap {
@@ -21,9 +50,29 @@ impl ChannelMap {
     /// by this function.
     pub fn from_raw(mut raw: [u8; 5]) -> Self {
         raw[4] &= 0b11111; // clear RFU bits
-        Self {
+        let mut map = Self {
             raw,
             num_used_channels: raw.iter().map(|b| b.count_ones() as u8).sum(),
+            remapped: [0; 37],
+        };
+        map.build_remapping_table();
+        map
+    }
+
+    /// Fills `remapped` with the channel selected by CSA #1 for each unmapped channel.
+    fn build_remapping_table(&mut self) {
+        let mut used = [0; 37];
+        for (slot, channel) in used.iter_mut().zip(self.iter_used()) {
+            *slot = channel.index();
+        }
+
+        for unmapped in 0..37 {
+            self.remapped[usize::from(unmapped)] =
+                if self.num_used_channels == 0 || self.is_used(DataChannel::new(unmapped)) {
+                    unmapped
+                } else {
+                    used[usize::from(unmapped % self.num_used_channels)]
+                };
         }
     }
 
@@ -34,10 +83,7 @@ impl ChannelMap {
 
     /// Creates a new channel map that marks all data channels as used.
     pub fn with_all_channels() -> Self {
-        Self {
-            raw: [0xff, 0xff, 0xff, 0xff, 0b11111],
-            num_used_channels: 37,
-        }
+        Self::from_raw([0xff, 0xff, 0xff, 0xff, 0b11111])
     }
 
     /// Returns the number of data channels marked as used by this map.
@@ -80,6 +126,35 @@ impl ChannelMap {
             .nth(n.into())
             .expect("by_index: index out of bounds")
     }
+
+    /// Returns the data channel Channel Selection Algorithm #1 uses for `unmapped_channel`.
+    ///
+    /// This is `unmapped_channel` itself if it is used, and the used channel with index
+    /// `unmapped_channel % self.num_used_channels()` otherwise.
+    pub fn csa1_channel(&self, unmapped_channel: DataChannel) -> DataChannel {
+        DataChannel::new(self.remapped[usize::from(unmapped_channel.index())])
+    }
+
+    /// Selects the channel of event `counter` using Channel Selection Algorithm #2.
+    ///
+    /// `channel_id` is derived from the Access Address with [`channel_identifier`]. Unlike
+    /// Algorithm #1, the selected channel doesn't depend on the previous one, so any event's
+    /// channel can be computed directly.
+    pub fn csa2_channel(&self, channel_id: u16, counter: u16) -> DataChannel {
+        let mut prn = counter ^ channel_id;
+        for _ in 0..3 {
+            prn = mam(perm(prn), channel_id);
+        }
+        let prn_e = prn ^ channel_id;
+
+        let unmapped = DataChannel::new((prn_e % 37) as u8);
+        if self.is_used(unmapped) {
+            unmapped
+        } else {
+            let index = (u32::from(self.num_used_channels) * u32::from(prn_e)) >> 16;
+            self.by_index(index as u8)
+        }
+    }
 }
 
 impl fmt::Display for ChannelMap {
@@ -135,6 +210,47 @@ mod tests {
         assert_eq!(map, ChannelMap::with_all_channels());
     }
 
+    #[test]
+    fn csa2_sample_data() {
+        // Sample data from the Core specification (Vol 6, Part C, Section 3)
+        let id = channel_identifier(0x8E89_BED6);
+        assert_eq!(id, 0x305F);
+
+        let map = ChannelMap::with_all_channels();
+        let channels: std::vec::Vec<u8> =
+            (1..=3).map(|c| map.csa2_channel(id, c).index()).collect();
+        assert_eq!(channels, [20, 6, 21]);
+
+        // Channels 9, 10, 21, 22, 23, 33, 34, 35 and 36 used
+        let map = ChannelMap::from_raw([0x00, 0x06, 0xE0, 0x00, 0x1E]);
+        assert_eq!(map.num_used_channels(), 9);
+        let channels: std::vec::Vec<u8> =
+            (6..=8).map(|c| map.csa2_channel(id, c).index()).collect();
+        assert_eq!(channels, [23, 9, 34]);
+    }
+
+    #[test]
+    fn csa1_table_matches_direct_computation() {
+        for raw in [
+            [0xff, 0xff, 0xff, 0xff, 0x1f],
+            [0x00, 0x06, 0xE0, 0x00, 0x1E],
+            [0x03, 0x00, 0x00, 0x00, 0x00],
+            [0x55, 0xAA, 0x0F, 0xF0, 0x11],
+            [0x00, 0x00, 0x00, 0x00, 0x10],
+        ] {
+            let map = ChannelMap::from_raw(raw);
+            for index in 0..37 {
+                let unmapped = DataChannel::new(index);
+                let direct = if map.is_used(unmapped) {
+                    unmapped
+                } else {
+                    map.by_index(index % map.num_used_channels())
+                };
+                assert_eq!(map.csa1_channel(unmapped), direct, "{:?} {}", map, index);
+            }
+        }
+    }
+
     #[test]
     fn all_channels() {
         let map = ChannelMap::with_all_channels();
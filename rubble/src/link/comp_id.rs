@@ -2,10 +2,13 @@
 
 use core::fmt;
 
-use crate::bytes::RawRepr;
+use crate::bytes::{ByteReader, ByteWriter, FromBytes, RawRepr, ToBytes};
+use crate::Error;
 
 /// Company identifier for use in link layer Control PDUs.
-#[derive(Copy, Clone)]
+///
+/// Company identifiers are assigned by the Bluetooth SIG. They are sent as a little-endian `u16`.
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct CompanyId(u16);
 
 impl RawRepr<u16> for CompanyId {
@@ -24,20 +27,45 @@ impl fmt::Debug for CompanyId {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for CompanyId {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "CompanyId(0x{=u16:X})", self.as_u16());
     }
 }
 
+impl<'a> FromBytes<'a> for CompanyId {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self(bytes.read_u16_le()?))
+    }
+}
+
+impl ToBytes for CompanyId {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.0)
+    }
+}
+
 impl CompanyId {
+    /// Identifier reserved by the Bluetooth SIG for testing (`0xFFFF`).
+    ///
+    /// This is used by Rubble by default, since it doesn't have an identifier of its own. It must
+    /// not be used in shipping products.
+    pub const TESTING: Self = Self(0xFFFF);
+
+    /// Identifier of Apple, Inc. (`0x004C`), used by iBeacons.
+    pub const APPLE: Self = Self(0x004C);
+
+    /// Identifier of Nordic Semiconductor ASA (`0x0059`).
+    pub const NORDIC_SEMICONDUCTOR: Self = Self(0x0059);
+
     /// Create a company ID from a raw `u16`.
-    pub fn from_raw(raw: u16) -> Self {
+    pub const fn from_raw(raw: u16) -> Self {
         Self(raw)
     }
 
     /// Get the raw `u16` representing this company identifier.
-    pub fn as_u16(&self) -> u16 {
+    pub const fn as_u16(&self) -> u16 {
         self.0
     }
 
@@ -1,19 +1,182 @@
 //! Link-Layer connection management and LLCP implementation.
 
+use crate::aes::{AesProvider, Ccm, Direction, MIC_SIZE};
+use crate::att::{self, Opcode};
+use crate::l2cap;
 use crate::link::data::{self, Header, Llid, Pdu};
-use crate::link::llcp::{ConnectionUpdateData, ControlPdu};
+use crate::link::event::LinkEvent;
+use crate::link::llcp::{
+    ConnectionParamRequest, ConnectionUpdateData, ControlOpcode, ControlPdu, DisconnectReason,
+    EncryptionRequest, MinUsedChannels,
+};
 use crate::link::queue::{Consume, Consumer, Producer};
 use crate::link::{
-    advertising::ConnectRequestData, channel_map::ChannelMap, Cmd, CompanyId, FeatureSet,
-    NextUpdate, RadioCmd, SeqNum, Transmitter,
+    advertising::ConnectRequestData, channel_map::ChannelMap, Cmd, CompanyId, ConnectionStats,
+    DeviceAddress, FeatureSet, LinkQuality, NextUpdate, RadioCmd, SeqNum, TimeWindow, Transmitter,
+    MIN_DATA_PAYLOAD_BUF,
 };
-use crate::time::{Duration, Instant, Timer};
+use crate::phy::{pdu_air_time, CodingIndicator, DataChannel, Phy, PhySet};
+use crate::security::EncryptionKey;
+use crate::time::{Duration, Instant, InstantExt, Timer, T_IFS};
 use crate::utils::{Hex, HexSlice};
-use crate::{bytes::*, config::*, phy::DataChannel, Error, BLUETOOTH_VERSION};
-use core::{marker::PhantomData, num::Wrapping};
+use crate::{bytes::*, config::*, Error, BLUETOOTH_VERSION};
+use bitflags::bitflags;
+use core::{cmp, fmt, marker::PhantomData, mem, num::Wrapping};
+use rand_core::{CryptoRng, RngCore};
+
+/// Minimum tolerance around the expected anchor point of a connection event.
+///
+/// We listen at least this long before the expected anchor point, and consider the event missed
+/// when no packet was received this long after it. This covers the inaccuracy of our timestamps;
+/// clock drift is accounted for by [`window_widening`].
+const WINDOW_WIDENING: Duration = Duration::micros(500);
+
+/// Minimum tolerance around an anchor point predicted from a hardware timestamp.
+///
+/// When the radio measures when the master's packets start, only the master's scheduling jitter
+/// and the time needed to start the receiver have to be covered, so the window can be narrowed.
+const MEASURED_WINDOW_WIDENING: Duration = Duration::micros(250);
+
+/// Largest `MaxRxOctets` value we can ever announce.
+///
+/// Received PDUs are decrypted into a `MIN_DATA_PAYLOAD_BUF`-sized buffer and stored in the RX
+/// queue, whose slots hold `MIN_DATA_PAYLOAD_BUF` payload Bytes as well. Larger L2CAP messages
+/// arrive fragmented into several PDUs and are reassembled by L2CAP. A larger radio receive buffer
+/// doesn't help until these buffers grow.
+const MAX_RX_OCTETS: u16 = MIN_DATA_PAYLOAD_BUF as u16;
+
+/// Computes the radio window of a connection event whose anchor point is expected between
+/// `earliest` and `latest`, widened by `widening` on both sides.
+fn event_window(
+    earliest: Instant,
+    latest: Instant,
+    widening: Duration,
+    exchange_len: Duration,
+) -> TimeWindow {
+    let spread = latest.saturating_duration_since(earliest);
+    TimeWindow::new(
+        earliest - widening,
+        spread + widening + widening + exchange_len,
+    )
+}
+
+/// Returns the `MaxRxOctets` value to announce when the radio's receive buffer can hold
+/// `rx_capacity` payload Bytes.
+///
+/// Room for the MIC is always left, since the master may enable encryption at any time. The result
+/// is clamped to `MAX_RX_OCTETS`. Receive buffers need to hold at least `MIN_PAYLOAD_BUF` Bytes
+/// anyways, so the lower limit of 27 Bytes always fits.
+fn max_rx_octets(rx_capacity: usize) -> u16 {
+    let octets = rx_capacity.saturating_sub(MIC_SIZE);
+    cmp::min(octets, usize::from(MAX_RX_OCTETS)).max(MIN_DATA_PAYLOAD_BUF) as u16
+}
+
+/// Returns the time (in µs) needed to transmit a data PDU with `octets` payload Bytes (plus MIC)
+/// on the LE 1M PHY.
+fn packet_time(octets: u16) -> u16 {
+    pdu_air_time((usize::from(octets) + MIC_SIZE) as u8, Phy::Le1M).to_micros() as u16
+}
+
+/// Returns whether the connection event `instant` lies in the future of event `counter`.
+///
+/// The 16-bit event counter wraps around every 65536 events, so instants are compared like TCP
+/// sequence numbers: An instant is in the past (or is the current event) if
+/// `(instant - counter) mod 65536 >= 32767` (Vol 6, Part B, Section 5.5.1), and in the future
+/// otherwise.
+fn instant_in_future(instant: u16, counter: u16) -> bool {
+    let events_until = instant.wrapping_sub(counter);
+    events_until != 0 && events_until < 32767
+}
+
+/// Returns whether connection parameters are allowed by the spec and acceptable for config `C`.
+///
+/// Besides checking each parameter's range, this enforces that the supervision timeout is longer
+/// than `(1 + latency) * interval * 2`, so that the master gets a few chances to resynchronize.
+pub(crate) fn acceptable_conn_params<C: Config>(
+    interval: Duration,
+    latency: u16,
+    timeout: Duration,
+) -> bool {
+    let intervals = Duration::micros(7_500).max(C::MIN_CONN_INTERVAL)
+        ..=Duration::millis(4_000).min(C::MAX_CONN_INTERVAL);
+    let timeouts = Duration::millis(100)..=Duration::millis(32_000).min(C::MAX_SUPERVISION_TIMEOUT);
+    let min_timeout = u64::from(interval.to_micros()) * (1 + u64::from(latency)) * 2;
+
+    intervals.contains(&interval)
+        && latency <= cmp::min(499, C::MAX_SLAVE_LATENCY)
+        && timeouts.contains(&timeout)
+        && u64::from(timeout.to_micros()) > min_timeout
+}
+
+/// Computes the window widening for an anchor point `elapsed` after the last one we synchronized
+/// to.
+///
+/// `sca_ppm` is the sum of both devices' sleep clock accuracies in ppm. The worst-case drift is
+/// added to the minimum `tolerance`. The result is limited to half the connection `interval` minus
+/// `T_IFS`, since the windows of consecutive events would overlap otherwise.
+pub(crate) fn window_widening(
+    sca_ppm: u32,
+    tolerance: Duration,
+    elapsed: Duration,
+    interval: Duration,
+) -> Duration {
+    let drift = (u64::from(elapsed.to_micros()) * u64::from(sca_ppm)).div_ceil(1_000_000);
+    let max = interval / 2 - T_IFS;
+    cmp::min(tolerance + Duration::micros(drift as u32), max)
+}
+
+/// Identifies a connection in calls to the `LinkLayer`.
+///
+/// Every connection gets a new handle, so a handle kept after its connection was closed never
+/// refers to a later connection; operations using it fail instead. The raw handle is taken from the
+/// range of HCI connection handles (`0x0000..=0x0EFF`). When that range is exhausted, it starts
+/// over at 0 with a new generation, which is also compared, so a handle is only reused after
+/// 65536 times that many connections.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConnectionHandle {
+    raw: u16,
+    generation: u16,
+}
+
+impl ConnectionHandle {
+    /// The largest raw handle value.
+    pub const MAX: u16 = 0x0EFF;
+
+    /// Returns the handle as a raw HCI connection handle.
+    ///
+    /// Unlike the `ConnectionHandle` itself, raw handles repeat every `MAX + 1` connections.
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    /// Returns the handle to use for the connection after `self`.
+    pub(crate) fn next(self) -> Self {
+        if self.raw >= Self::MAX {
+            Self {
+                raw: 0,
+                generation: self.generation.wrapping_add(1),
+            }
+        } else {
+            Self {
+                raw: self.raw + 1,
+                generation: self.generation,
+            }
+        }
+    }
+}
 
 /// Connection state and parameters.
 pub struct Connection<C: Config> {
+    /// Handle identifying this connection.
+    handle: ConnectionHandle,
+
+    /// Device address of the master that initiated the connection.
+    peer_addr: DeviceAddress,
+
+    /// Index of the resolving list entry matching `peer_addr`, if it could be resolved.
+    resolved_peer: Option<usize>,
+
     access_address: u32,
     crc_init: u32,
     channel_map: ChannelMap,
@@ -24,6 +187,19 @@ pub struct Connection<C: Config> {
     /// Connection event interval (duration between the start of 2 subsequent connection events).
     conn_interval: Duration,
 
+    /// Maximum time between 2 received packets before the connection is considered lost.
+    supervision_timeout: Duration,
+
+    /// Number of consecutive connection events we may skip when there's nothing to exchange.
+    slave_latency: u16,
+
+    /// Number of connection events we're currently skipping.
+    ///
+    /// When this is non-zero, the radio is off and the next update happens when the window of the
+    /// connection event after the skipped ones opens. All other fields still describe the next
+    /// (skipped) connection event.
+    skip: u16,
+
     /// Connection event counter (`connEventCount(er)` in the spec).
     conn_event_count: Wrapping<u16>,
 
@@ -46,6 +222,25 @@ pub struct Connection<C: Config> {
     /// Whether we have ever received a data packet in this connection.
     received_packet: bool,
 
+    /// When the last packet with a correct CRC was received (resets the supervision timer).
+    ///
+    /// This is also the last time we synchronized to the master's clock.
+    last_rx: Instant,
+
+    /// Expected anchor point of the next connection event.
+    anchor: Instant,
+
+    /// Whether `anchor` was predicted from a hardware timestamp of the master's last anchor packet,
+    /// rather than from the time its reception was processed.
+    anchor_measured: bool,
+
+    /// Whether the current connection event continues after the last packet exchange, because
+    /// either side indicated more data.
+    in_event: bool,
+
+    /// Sum of the master's and our sleep clock accuracy in ppm.
+    sca_ppm: u32,
+
     tx: ConfConsumer<C>,
     rx: ConfProducer<C>,
 
@@ -54,6 +249,69 @@ pub struct Connection<C: Config> {
     /// Contains the *instant* at which it should be applied to the Link Layer state.
     update_data: Option<LlcpUpdate>,
 
+    /// Key material provided by the host for the next encryption start procedure.
+    pending_key: Option<PendingKey>,
+
+    /// Progress of the encryption start procedure.
+    encryption: EncryptionState,
+
+    /// Progress of the termination procedure, if we started one.
+    termination: Termination,
+
+    /// `packetCounter` of the next encrypted PDU we send.
+    tx_counter: u64,
+
+    /// `packetCounter` of the next encrypted PDU we expect to receive.
+    rx_counter: u64,
+
+    /// Counters of received data channel PDUs.
+    stats: ConnectionStats,
+
+    /// Smoothed RSSI and CRC error rate of received data channel PDUs.
+    link_quality: LinkQuality,
+
+    /// Company identifier sent in `LL_VERSION_IND` PDUs.
+    company_id: CompanyId,
+
+    /// Link-Layer features we support in this connection.
+    features: FeatureSet,
+
+    /// Features supported by the master, once it has sent them in an `LL_FEATURE_REQ`.
+    peer_features: Option<FeatureSet>,
+
+    /// PHY used for packets in both directions.
+    phy: Phy,
+
+    /// Max. payload size of data PDUs the master may send, as agreed on by the Data Length Update
+    /// Procedure.
+    max_rx_octets: u16,
+
+    /// Progress of the PHY Update Procedure, if we started one.
+    phy_request: PhyRequest,
+
+    /// Progress of our request for new connection parameters, if we made one.
+    param_request: ParamRequest,
+
+    /// Number of Bytes of the L2CAP message from the TX queue that haven't been sent yet.
+    ///
+    /// PDUs carrying other L2CAP messages must not be sent in between its fragments.
+    tx_message_left: u16,
+
+    /// When we sent an ATT *Handle Value Indication* that hasn't been confirmed yet.
+    ///
+    /// The connection is closed if the confirmation doesn't arrive within
+    /// [`ATT_TRANSACTION_TIMEOUT`](att::ATT_TRANSACTION_TIMEOUT).
+    indication_sent: Option<Instant>,
+
+    /// `LL_MIN_USED_CHANNELS_IND` to send in place of the next data PDU.
+    min_channels_request: Option<MinUsedChannels>,
+
+    /// Minimum number of used channels the peer asked for in an `LL_MIN_USED_CHANNELS_IND`.
+    peer_min_used_channels: Option<MinUsedChannels>,
+
+    /// Events to report to the application via [`take_events`](Self::take_events).
+    events: PendingEvents,
+
     _p: PhantomData<C>,
 }
 
@@ -65,22 +323,41 @@ impl<C: Config> Connection<C> {
     ///
     /// # Parameters
     ///
+    /// * **`handle`**: Handle identifying the new connection.
+    /// * **`peer_addr`**: Address of the initiator that sent the `CONNECT_REQ`.
+    /// * **`resolved_peer`**: Index of the IRK in the resolving list that `peer_addr` resolves to.
     /// * **`lldata`**: Data contained in the `CONNECT_REQ` advertising PDU.
     /// * **`rx_end`**: Instant at which the `CONNECT_REQ` PDU was fully received.
     /// * **`tx`**: Channel for packets to transmit.
     /// * **`rx`**: Channel for received packets.
+    /// * **`company_id`**: Company identifier to send in `LL_VERSION_IND` PDUs.
+    /// * **`features`**: Link-Layer features we support in this connection.
+    /// * **`link_quality`**: Initial link quality estimate, configured with the smoothing factor.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn create(
+        handle: ConnectionHandle,
+        peer_addr: DeviceAddress,
+        resolved_peer: Option<usize>,
         lldata: &ConnectRequestData,
         rx_end: Instant,
         tx: ConfConsumer<C>,
         rx: ConfProducer<C>,
+        company_id: CompanyId,
+        features: FeatureSet,
+        link_quality: LinkQuality,
     ) -> (Self, Cmd) {
         let mut this = Self {
+            handle,
+            peer_addr,
+            resolved_peer,
             access_address: lldata.access_address(),
             crc_init: lldata.crc_init(),
             channel_map: *lldata.channel_map(),
             hop: lldata.hop(),
             conn_interval: lldata.interval(),
+            supervision_timeout: lldata.supervision_timeout(),
+            slave_latency: lldata.slave_latency(),
+            skip: 0,
             conn_event_count: Wrapping(0),
 
             unmapped_channel: DataChannel::new(0),
@@ -90,44 +367,67 @@ impl<C: Config> Connection<C> {
             next_expected_seq_num: SeqNum::ZERO,
             last_header: Header::new(Llid::DataCont),
             received_packet: false,
+            last_rx: rx_end,
+            anchor: rx_end + lldata.start_of_tx_window(),
+            anchor_measured: false,
+            in_event: false,
+            sca_ppm: lldata.sleep_clock_accuracy().ppm() + C::SLEEP_CLOCK_ACCURACY_PPM,
 
             tx,
             rx,
             update_data: None,
 
+            pending_key: None,
+            encryption: EncryptionState::Off,
+            termination: Termination::None,
+            tx_counter: 0,
+            rx_counter: 0,
+            stats: ConnectionStats::new(),
+            link_quality,
+            company_id,
+            features,
+            peer_features: None,
+            phy: Phy::Le1M,
+            max_rx_octets: MIN_DATA_PAYLOAD_BUF as u16,
+            phy_request: PhyRequest::None,
+            param_request: ParamRequest::None,
+            tx_message_left: 0,
+            indication_sent: None,
+            min_channels_request: None,
+            peer_min_used_channels: None,
+            events: PendingEvents::empty(),
+
             _p: PhantomData,
         };
 
         // Calculate the first channel to use
         this.hop_channel();
 
-        let cmd = Cmd {
-            next_update: NextUpdate::At(
-                rx_end + lldata.end_of_tx_window() + Duration::micros(500),
-            ),
-            radio: RadioCmd::ListenData {
-                channel: this.channel,
-                access_address: this.access_address,
-                crc_init: this.crc_init,
-                timeout: false,
-            },
-            queued_work: false,
-        };
-
+        let cmd = this.listen(rx_end, rx_end + lldata.end_of_tx_window(), false);
         (this, cmd)
     }
 
     /// Called by the `LinkLayer` when a data channel packet is received.
     ///
-    /// Returns `Err(())` when the connection is ended (not necessarily due to an error condition).
+    /// Returns the reason as an error when the connection is ended (not necessarily due to an error
+    /// condition).
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn process_data_packet(
         &mut self,
         rx_end: Instant,
+        address_time: Option<Instant>,
         tx: &mut C::Transmitter,
-        header: data::Header,
+        aes: &mut C::Aes,
+        mut header: data::Header,
         payload: &[u8],
         crc_ok: bool,
-    ) -> Result<Cmd, ()> {
+    ) -> Result<Cmd, DisconnectReason> {
+        self.check_termination(rx_end)?;
+        self.check_att_timeout(rx_end);
+        if crc_ok {
+            self.last_rx = rx_end;
+        }
+
         // If the sequence number of the packet is the same as our next expected sequence number,
         // the packet contains new data that we should try to process. However, if the CRC is bad,
         // we'll never try to process the data and instead request a retransmission.
@@ -139,11 +439,62 @@ impl<C: Config> Connection<C> {
         // was acknowledged and thus always retransmit.
         let acknowledged = header.nesn() == self.transmit_seq_num + SeqNum::ONE && crc_ok;
 
+        // We may only put a new PDU into the TX buffer if the last one was acknowledged, or if we
+        // haven't sent any PDU yet (the master's first PDU has nothing to acknowledge).
+        let can_send_new = acknowledged || !self.received_packet;
+
         let is_empty = header.llid() == Llid::DataCont && payload.is_empty();
+        let master_md = header.md();
+        self.stats.record_rx(crc_ok, is_new, acknowledged, is_empty);
 
         if acknowledged {
+            if let Termination::Sent { .. } = self.termination {
+                // The master has received our `LL_TERMINATE_IND`
+                info!("termination acknowledged, closing connection");
+                return Err(DisconnectReason::LocalHostTerminated);
+            }
+
             self.received_packet = true;
             self.transmit_seq_num += SeqNum::ONE;
+
+            if let EncryptionState::StartEncReqSent(ccm) = &self.encryption {
+                // The master has received our `LL_START_ENC_REQ`, so everything it sends from now
+                // on is encrypted.
+                self.encryption = EncryptionState::WaitStartEncRsp(ccm.clone());
+            }
+        }
+
+        let rx_encrypted = self.encryption.rx_ccm().is_some();
+        let mut plaintext = [0; MIN_DATA_PAYLOAD_BUF];
+        let payload = if is_new && !is_empty && rx_encrypted {
+            match self.decrypt(aes, header, payload, &mut plaintext) {
+                Some(len) => {
+                    header.set_payload_length(len);
+                    &plaintext[..usize::from(len)]
+                }
+                None => {
+                    info!("MIC failure, closing connection");
+                    return Err(DisconnectReason::MicFailure);
+                }
+            }
+        } else {
+            payload
+        };
+        let last_expected_seq_num = self.next_expected_seq_num;
+
+        if !self.in_event {
+            // First packet of the connection event, the next one is relative to it. If the radio
+            // measured when the packet started, we resynchronize to that, but only if the CRC is
+            // correct (otherwise, the packet may just be noise and we keep the schedule).
+            let anchor = match address_time {
+                Some(time) if crc_ok => {
+                    self.anchor_measured = true;
+                    time - self.phy.access_address_end()
+                }
+                _ if self.anchor_measured => self.anchor,
+                _ => rx_end,
+            };
+            self.anchor = anchor + self.conn_interval;
         }
 
         // Whether we've already sent a response packet.
@@ -159,13 +510,25 @@ impl<C: Config> Connection<C> {
                 // LLCP message, try to process it immediately. Certain LLCPDUs might be put in the
                 // channel instead and answered by the non-real-time part.
 
-                if let Ok(pdu) = ControlPdu::from_bytes(&mut ByteReader::new(payload)) {
+                // PDUs with invalid `CtrData` are handled like unknown ones, so that they're answered
+                // with `LL_UNKNOWN_RSP` instead of being retransmitted forever.
+                let pdu = ControlPdu::from_bytes(&mut ByteReader::new(payload))
+                    .ok()
+                    .or_else(|| {
+                        let (&opcode, ctr_data) = payload.split_first()?;
+                        Some(ControlPdu::Unknown {
+                            opcode: ControlOpcode::from(opcode),
+                            ctr_data,
+                        })
+                    });
+                if let Some(pdu) = pdu {
                     // Some LLCPDUs don't need a response, those can always be processed and
                     // ACKed. For those that do, the other device must have ACKed the last
                     // packet we sent, because we'll directly use the radio's TX buffer to send
                     // back the LLCP response.
 
-                    match self.process_control_pdu(pdu, acknowledged) {
+                    let rx_capacity = tx.rx_payload_capacity();
+                    match self.process_control_pdu(pdu, can_send_new, rx_capacity, aes, rx_end) {
                         Ok(Some(response)) => {
                             self.next_expected_seq_num += SeqNum::ONE;
 
@@ -177,7 +540,7 @@ impl<C: Config> Connection<C> {
                             let mut header = Header::new(Llid::Control);
                             let pl_len = (left - payload_writer.space_left()) as u8;
                             header.set_payload_length(pl_len);
-                            self.send(header, tx);
+                            self.send(header, tx, aes, rx_end);
                             responded = true;
 
                             info!("LLCP<- {:?}", pdu);
@@ -189,16 +552,19 @@ impl<C: Config> Connection<C> {
                             info!("LLCP<- {:?}", pdu);
                             info!("LLCP-> (no response)");
                         }
-                        Err(LlcpError::ConnectionLost) => {
-                            return Err(());
+                        Err(LlcpError::ConnectionLost(reason)) => {
+                            return Err(reason);
                         }
                         Err(LlcpError::NoSpace) => {
                             // Do not acknowledge the PDU
                         }
                     }
                 } else {
-                    // Couldn't parse control PDU. CRC might be invalid. NACK
+                    // Control PDU without opcode. NACK
                 }
+            } else if payload.len() > MIN_DATA_PAYLOAD_BUF {
+                // Larger than the `MaxRxOctets` we announced, and than the RX queue can store
+                trace!("NACK (PDU too large)");
             } else {
                 // Try to buffer the packet. If it fails, we don't acknowledge it, so it will be
                 // resent until we have space.
@@ -210,6 +576,10 @@ impl<C: Config> Connection<C> {
                     });
 
                 if result.is_ok() {
+                    if att_opcode(header.llid(), payload) == Some(Opcode::HandleValueConfirmation) {
+                        self.indication_sent = None;
+                    }
+
                     // Acknowledge the packet
                     self.next_expected_seq_num += SeqNum::ONE;
                     queued_work = true;
@@ -219,76 +589,120 @@ impl<C: Config> Connection<C> {
             }
         }
 
-        if acknowledged {
+        if rx_encrypted && !is_empty && self.next_expected_seq_num != last_expected_seq_num {
+            self.rx_counter += 1;
+        }
+
+        if can_send_new {
+            self.received_packet = true;
+
             if !responded {
                 // Send a new data packet.
 
-                // Try to acquire PDU from the tx queue, fall back to an empty PDU.
                 let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
-                let header = match self.tx.consume_raw_with(|header, pl| {
-                    payload_writer.write_slice(pl).expect("TX buf out of space");
-                    Consume::always(Ok(header))
-                }) {
-                    Ok(h) => h,
-                    Err(_) => Header::new(Llid::DataCont),
+                let header = if let Termination::Pending { reason, deadline } = self.termination {
+                    // No more data is sent after we decided to close the connection
+                    let pdu = ControlPdu::TerminateInd { error_code: reason };
+                    Pdu::from(&pdu).to_bytes(&mut payload_writer).unwrap();
+                    self.termination = Termination::Sent { deadline };
+
+                    let mut header = Header::new(Llid::Control);
+                    header.set_payload_length(pdu.encoded_size());
+                    header
+                } else if let EncryptionState::StartEncReqPending(ccm) = &self.encryption {
+                    // Continue the encryption start procedure
+                    let pdu = ControlPdu::StartEncReq;
+                    Pdu::from(&pdu).to_bytes(&mut payload_writer).unwrap();
+                    self.encryption = EncryptionState::StartEncReqSent(ccm.clone());
+
+                    let mut header = Header::new(Llid::Control);
+                    header.set_payload_length(pdu.encoded_size());
+                    header
+                } else if self.encryption.pauses_data() {
+                    // No data PDUs may be sent while encryption is being started
+                    Header::new(Llid::DataCont)
+                } else if let PhyRequest::Pending(phy) = self.phy_request {
+                    // Ask the master to switch to `phy` in both directions
+                    let pdu = ControlPdu::PhyReq {
+                        tx_phys: phy.into(),
+                        rx_phys: phy.into(),
+                    };
+                    Pdu::from(&pdu).to_bytes(&mut payload_writer).unwrap();
+                    self.phy_request = PhyRequest::Sent(phy);
+
+                    let mut header = Header::new(Llid::Control);
+                    header.set_payload_length(pdu.encoded_size());
+                    header
+                } else if let Some(header) = self.write_param_request(&mut payload_writer) {
+                    header
+                } else if let Some(ind) = self.min_channels_request.take() {
+                    // The procedure completes once the master acknowledges the indication
+                    let pdu = ControlPdu::MinUsedChannelsInd(ind);
+                    Pdu::from(&pdu).to_bytes(&mut payload_writer).unwrap();
+
+                    let mut header = Header::new(Llid::Control);
+                    header.set_payload_length(pdu.encoded_size());
+                    header
+                } else {
+                    // Try to acquire PDU from the tx queue, fall back to an empty PDU.
+                    let message_left = &mut self.tx_message_left;
+                    let indication_sent = &mut self.indication_sent;
+                    match self.tx.consume_raw_with(|header, pl| {
+                        payload_writer.write_slice(pl).expect("TX buf out of space");
+                        *message_left = l2cap_bytes_left(*message_left, header.llid(), pl);
+                        if att_opcode(header.llid(), pl) == Some(Opcode::HandleValueIndication) {
+                            *indication_sent = Some(rx_end);
+                        }
+                        Consume::always(Ok(header))
+                    }) {
+                        Ok(h) => h,
+                        Err(_) => Header::new(Llid::DataCont),
+                    }
                 };
 
-                self.send(header, tx);
+                self.send(header, tx, aes, rx_end);
             }
         } else {
-            // Last packet not acknowledged, resend.
+            // Last packet not acknowledged, resend it (it's still in the TX buffer). The NESN is
+            // updated, since we might have acknowledged the master's PDU in the meantime, and so is
+            // MD, since the event might have to close before the queued PDUs are sent.
             // If CRC is bad, this bit could be flipped, so we always retransmit in that case.
-            if self.received_packet {
-                self.last_header.set_nesn(self.next_expected_seq_num);
-                tx.transmit_data(
-                    self.access_address,
-                    self.crc_init,
-                    self.last_header,
-                    self.channel,
-                );
-                trace!("<<RESENT>>");
-            } else {
-                // We've never received (and thus sent) a data packet before, so we can't
-                // *re*transmit anything. Send empty PDU instead.
-                // (this should not really happen, though!)
-                self.received_packet = true;
-
-                let pdu = Pdu::empty();
-                let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
-                pdu.to_bytes(&mut payload_writer).unwrap();
-                self.send(Header::new(pdu.llid()), tx);
-            }
+            self.last_header.set_nesn(self.next_expected_seq_num);
+            self.last_header.set_md(self.has_more_data(rx_end));
+            tx.transmit_data(
+                self.access_address,
+                self.crc_init,
+                self.last_header,
+                self.channel,
+            );
+            trace!("<<RESENT>>");
         }
 
-        let last_channel = self.channel;
-
-        // FIXME: Don't hop if one of the MD bits is set to true (also don't log then)
-        {
-            // Connection event closes
-            self.conn_event_count += Wrapping(1);
+        // The connection event continues as long as either side has more data, unless the master's
+        // packet was corrupted or there's no time left for another exchange
+        if crc_ok && (master_md || self.last_header.md()) && self.can_continue(rx_end) {
+            trace!(
+                "#{} DATA({})<- {:?}, {} (more data)",
+                self.conn_event_count,
+                self.channel.index(),
+                header,
+                HexSlice(payload)
+            );
 
-            if let Some(update) = self.update_data.take() {
-                if update.instant() == self.conn_event_count.0 {
-                    // Next conn event will the the first one with these parameters.
-                    let result = self.apply_llcp_update(update, rx_end);
-                    info!("LLCP patch applied: {:?} -> {:?}", update, result);
-                    if let Some(mut cmd) = result {
-                        cmd.queued_work = queued_work;
-                        return Ok(cmd);
-                    }
-                } else {
-                    // Put it back
-                    self.update_data = Some(update);
-                }
-            }
+            self.in_event = true;
+            let mut cmd = self.listen_in_event(rx_end);
+            cmd.queued_work = queued_work;
+            return Ok(cmd);
+        }
 
-            // Hop channels after applying LLCP update because it might change the channel map used
-            // by the next event
-            self.hop_channel();
+        let last_channel = self.channel;
+        if let Some(mut cmd) = self.close_event(rx_end) {
+            cmd.queued_work = queued_work;
+            return Ok(cmd);
         }
 
         trace!(
-            "#{} DATA({}->{})<- {}{:?}, {:?}",
+            "#{} DATA({}->{})<- {}{:?}, {}",
             self.conn_event_count,
             last_channel.index(),
             self.channel.index(),
@@ -297,47 +711,160 @@ impl<C: Config> Connection<C> {
             HexSlice(payload)
         );
 
-        Ok(Cmd {
-            next_update: NextUpdate::At(rx_end + self.conn_event_timeout()),
-            radio: RadioCmd::ListenData {
-                channel: self.channel,
-                access_address: self.access_address,
-                crc_init: self.crc_init,
-                timeout: false,
-            },
-            queued_work,
-        })
+        let acked_rx = crc_ok && self.next_expected_seq_num != header.sn();
+        let mut cmd = if can_send_new && acked_rx && !master_md && self.is_idle() {
+            self.sleep(rx_end)
+        } else {
+            self.listen(rx_end, self.anchor, false)
+        };
+        cmd.queued_work = queued_work;
+        Ok(cmd)
+    }
+
+    /// Whether we may skip connection events, provided that the master has nothing more to send
+    /// and our last PDU was acknowledged.
+    ///
+    /// This is the case if the last PDU we sent was empty, nothing is queued for transmission, and
+    /// no LLCP procedure is in progress.
+    fn is_idle(&self) -> bool {
+        self.slave_latency != 0
+            && self.last_header.llid() == Llid::DataCont
+            && self.last_header.payload_length() == 0
+            && !self.tx.has_data()
+            && self.update_data.is_none()
+            && matches!(self.termination, Termination::None)
+            && !matches!(self.phy_request, PhyRequest::Pending(_))
+            && !self.param_request.is_pending()
+            && self.min_channels_request.is_none()
+            && matches!(
+                self.encryption,
+                EncryptionState::Off | EncryptionState::On(_)
+            )
+    }
+
+    /// Skips up to `slave_latency` connection events, turning the radio off until the window of the
+    /// event after them opens.
+    ///
+    /// The skipped events are limited so that we listen again within half the supervision timeout,
+    /// leaving time to resynchronize before the connection is lost.
+    fn sleep(&mut self, rx_end: Instant) -> Cmd {
+        let max_events = self.supervision_timeout.ticks() / 2 / self.conn_interval.ticks();
+        let skip = cmp::min(u32::from(self.slave_latency), max_events.saturating_sub(1));
+        if skip == 0 {
+            return self.listen(rx_end, self.anchor, false);
+        }
+
+        self.skip = skip as u16;
+        let anchor = self.anchor + self.conn_interval * skip;
+        Cmd {
+            next_update: NextUpdate::At(self.window_start(anchor)),
+            radio: RadioCmd::Off,
+            window: None,
+            queued_work: false,
+        }
+    }
+
+    /// Skips the next `events` connection events, advancing the anchor point, channel, and event
+    /// counter accordingly.
+    fn skip_events(&mut self, events: u16) {
+        for _ in 0..events {
+            self.hop_channel();
+            self.conn_event_count += Wrapping(1);
+            self.anchor += self.conn_interval;
+        }
+        self.skip = 0;
+    }
+
+    /// Returns the time at which the (widened) window of the event at `anchor` opens.
+    fn window_start(&self, anchor: Instant) -> Instant {
+        let elapsed = anchor.saturating_duration_since(self.last_rx);
+        anchor - self.window_widening(elapsed)
+    }
+
+    /// Computes the window widening for an anchor point `elapsed` after the last packet we
+    /// synchronized to.
+    fn window_widening(&self, elapsed: Duration) -> Duration {
+        let tolerance = if self.anchor_measured {
+            MEASURED_WINDOW_WIDENING
+        } else {
+            WINDOW_WIDENING
+        };
+        window_widening(self.sca_ppm, tolerance, elapsed, self.conn_interval)
+    }
+
+    /// Called by the `LinkLayer` when the application queued data for transmission.
+    ///
+    /// If we're currently skipping connection events, this stops skipping them at the next event
+    /// whose window hasn't opened yet, and returns a `Cmd` listening for it. Otherwise, returns
+    /// `None`, and the data will be sent in the next connection event anyways.
+    pub(crate) fn wake(&mut self, now: Instant) -> Option<Cmd> {
+        let pending = self.tx.has_data()
+            || matches!(self.termination, Termination::Pending { .. })
+            || matches!(self.phy_request, PhyRequest::Pending(_))
+            || self.param_request.is_pending()
+            || self.min_channels_request.is_some();
+        if self.skip == 0 || !pending {
+            return None;
+        }
+
+        let mut events = 0;
+        let mut anchor = self.anchor;
+        while self.window_start(anchor) <= now {
+            events += 1;
+            anchor += self.conn_interval;
+        }
+        if events >= self.skip {
+            // We're waking up for the following event anyways
+            return None;
+        }
+
+        self.skip_events(events);
+        Some(self.listen(now, self.anchor, false))
     }
 
     /// Called by the `LinkLayer` when the configured timer expires (according to a `Cmd` returned
     /// earlier).
     ///
-    /// Returns `Err(())` when the connection is closed or lost. In that case, the Link-Layer will
-    /// return to standby state.
-    pub(crate) fn timer_update(&mut self, timer: &mut C::Timer) -> Result<Cmd, ()> {
-        if self.received_packet {
+    /// Returns the reason as an error when the connection is closed or lost. In that case, the
+    /// Link-Layer will return to standby state.
+    pub(crate) fn timer_update(&mut self, timer: &mut C::Timer) -> Result<Cmd, DisconnectReason> {
+        self.check_termination(timer.now())?;
+        if self.skip != 0 {
+            // Done skipping events, listen for the next one
+            self.skip_events(self.skip);
+            Ok(self.listen(timer.now(), self.anchor, false))
+        } else if self.in_event {
+            // The master didn't send another packet, so the connection event is over
+            let now = timer.now();
+            trace!("#{} closed without more data", self.conn_event_count);
+            Ok(self
+                .close_event(now)
+                .unwrap_or_else(|| self.listen(now, self.anchor, false)))
+        } else if self.received_packet {
             // No packet from master, skip this connection event and listen on the next channel
 
+            if timer.now().saturating_duration_since(self.last_rx) >= self.supervision_timeout {
+                info!("supervision timeout, connection lost");
+                defmt_debug!("supervision timeout: {}", self);
+                return Err(DisconnectReason::ConnectionTimeout);
+            }
+
+            // Updates still take effect at their instant
+            let now = timer.now();
             let last_channel = self.channel;
-            self.hop_channel();
-            self.conn_event_count += Wrapping(1);
+            self.anchor += self.conn_interval;
+            if let Some(cmd) = self.close_event(now) {
+                return Ok(cmd);
+            }
             trace!(
                 "DATA({}->{}): missed conn event #{}",
                 last_channel.index(),
                 self.channel.index(),
                 self.conn_event_count.0,
             );
+            defmt_trace!("missed connection event: {}", self);
 
-            Ok(Cmd {
-                next_update: NextUpdate::At(timer.now() + self.conn_event_timeout()),
-                radio: RadioCmd::ListenData {
-                    channel: self.channel,
-                    access_address: self.access_address,
-                    crc_init: self.crc_init,
-                    timeout: true,
-                },
-                queued_work: false,
-            })
+            Ok(self.listen(now, self.anchor, true))
         } else {
             // Master did not transmit the first packet during this transmit window.
 
@@ -346,21 +873,354 @@ impl<C: Config> Connection<C> {
 
             self.conn_event_count += Wrapping(1);
             trace!("missed transmit window");
-            Err(())
+            Err(DisconnectReason::ConnectionFailedToBeEstablished)
+        }
+    }
+
+    /// Returns a `Cmd` listening for the master's first packet of a connection event.
+    ///
+    /// The event's anchor point is expected between `self.anchor` and `latest` (these only differ
+    /// for transmit windows). If no packet is received until `latest` plus the window widening,
+    /// the event is considered missed.
+    ///
+    /// `timeout` indicates whether the last connection event was missed.
+    fn listen(&self, now: Instant, latest: Instant, timeout: bool) -> Cmd {
+        let elapsed = latest.saturating_duration_since(self.last_rx);
+        let widening = self.window_widening(elapsed);
+        let end = latest + widening;
+        Cmd {
+            next_update: NextUpdate::At(end),
+            radio: RadioCmd::ListenData {
+                channel: self.channel,
+                access_address: self.access_address,
+                crc_init: self.crc_init,
+                timeout,
+                rx_timeout: Some(end.saturating_duration_since(now)),
+                phy: self.phy,
+            },
+            window: Some(event_window(
+                self.anchor,
+                latest,
+                widening,
+                self.max_exchange_len(),
+            )),
+            queued_work: false,
+        }
+    }
+
+    /// Returns a `Cmd` listening for the master's next packet in the current connection event, after
+    /// our response to the packet received at `rx_end`.
+    ///
+    /// The master sends it `T_IFS` after our response. If it doesn't, the event is closed when the
+    /// timer or receive timeout expires.
+    fn listen_in_event(&self, rx_end: Instant) -> Cmd {
+        let exchange_len = self.max_exchange_len();
+        let window = TimeWindow::new(rx_end, exchange_len + exchange_len);
+        Cmd {
+            next_update: NextUpdate::At(window.end()),
+            radio: RadioCmd::ListenData {
+                channel: self.channel,
+                access_address: self.access_address,
+                crc_init: self.crc_init,
+                timeout: false,
+                rx_timeout: Some(exchange_len),
+                phy: self.phy,
+            },
+            window: Some(window),
+            queued_work: false,
         }
     }
 
-    fn conn_event_timeout(&self) -> Duration {
-        // Time out ~500µs after the anchor point of the next conn event.
-        self.conn_interval + Duration::micros(500)
+    /// Closes the current connection event, advancing the event counter and hopping to the channel
+    /// of the next one.
+    ///
+    /// Returns a `Cmd` to use instead of listening for the next event if a connection update took
+    /// effect.
+    fn close_event(&mut self, now: Instant) -> Option<Cmd> {
+        self.in_event = false;
+        self.conn_event_count += Wrapping(1);
+
+        if let Some(update) = self.update_data.take() {
+            if !instant_in_future(update.instant(), self.conn_event_count.0) {
+                // Next conn event will the the first one with these parameters. Events are never
+                // skipped while an update is pending, so the instant is only passed when the
+                // update arrived late.
+                let result = self.apply_llcp_update(update, now);
+                info!("LLCP patch applied: {:?} -> {:?}", update, result);
+                defmt_debug!("LLCP update applied: {}", self);
+                if result.is_some() {
+                    return result;
+                }
+            } else {
+                // Put it back
+                self.update_data = Some(update);
+            }
+        }
+
+        // Hop channels after applying LLCP update because it might change the channel map used by
+        // the next event
+        self.hop_channel();
+        None
+    }
+
+    /// Whether another packet exchange fits into the current connection event after the packet
+    /// received at `rx_end`.
+    ///
+    /// The event must close before the window of the next one opens.
+    fn can_continue(&self, rx_end: Instant) -> bool {
+        let exchange_len = self.max_exchange_len();
+        rx_end + exchange_len + exchange_len <= self.window_start(self.anchor)
+    }
+
+    /// Returns the max. time needed for a packet exchange in a connection event.
+    ///
+    /// This covers a PDU of the largest size the master may send (plus MIC), followed by our
+    /// equally long response after `T_IFS`, on the current PHY.
+    fn max_exchange_len(&self) -> Duration {
+        let pdu_len = (usize::from(self.max_rx_octets) + MIC_SIZE) as u8;
+        let air_time = pdu_air_time(pdu_len, self.phy);
+        air_time + air_time + T_IFS
     }
 
     /// Whether we want to send more data during this connection event.
     ///
     /// Note that this *has to* change to `false` eventually, even if there's more data to be sent,
     /// because the connection event must close at least `T_IFS` before the next one occurs.
-    fn has_more_data(&self) -> bool {
-        false
+    fn has_more_data(&self, rx_end: Instant) -> bool {
+        self.tx.has_data()
+            && !self.encryption.pauses_data()
+            && matches!(self.termination, Termination::None)
+            && self.can_continue(rx_end)
+    }
+
+    /// Starts the termination procedure: `LL_TERMINATE_IND` is sent with `reason` instead of the
+    /// next data PDU.
+    ///
+    /// If the master doesn't acknowledge it within the supervision timeout, the connection is
+    /// closed anyways. Returns a `Cmd` if we have to stop skipping connection events (see
+    /// [`wake`](Self::wake)).
+    pub(crate) fn terminate(&mut self, reason: DisconnectReason, now: Instant) -> Option<Cmd> {
+        if !matches!(self.termination, Termination::None) {
+            return None;
+        }
+
+        info!("terminating connection: {:?}", reason);
+        self.termination = Termination::Pending {
+            reason,
+            deadline: now + self.supervision_timeout,
+        };
+        self.wake(now)
+    }
+
+    /// Starts the termination procedure if the last ATT indication wasn't confirmed in time.
+    ///
+    /// Once an ATT transaction timed out, no further ATT PDUs may be sent on the connection, so it
+    /// is closed as if the host had requested it.
+    fn check_att_timeout(&mut self, now: Instant) {
+        if let Some(sent) = self.indication_sent {
+            if now.saturating_duration_since(sent) >= att::ATT_TRANSACTION_TIMEOUT {
+                warn!("ATT indication not confirmed in time, terminating connection");
+                self.indication_sent = None;
+                self.terminate(DisconnectReason::RemoteUserTerminated, now);
+            }
+        }
+    }
+
+    /// Closes the connection if the termination procedure timed out at `now`.
+    fn check_termination(&self, now: Instant) -> Result<(), DisconnectReason> {
+        match self.termination {
+            Termination::Pending { deadline, .. } | Termination::Sent { deadline }
+                if now >= deadline =>
+            {
+                info!("termination not acknowledged, closing connection");
+                Err(DisconnectReason::LocalHostTerminated)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the PHYs we can use in this connection.
+    ///
+    /// This takes the master's features into account once it has sent them.
+    fn supported_phys(&self) -> PhySet {
+        let features = match self.peer_features {
+            Some(peer) => self.features & peer,
+            None => self.features,
+        };
+        let mut phys = PhySet::LE_1M;
+        if features.contains(FeatureSet::LE_2M_PHY) {
+            phys |= PhySet::LE_2M;
+        }
+        if features.contains(FeatureSet::LE_CODED_PHY) {
+            phys |= PhySet::LE_CODED;
+        }
+        phys
+    }
+
+    /// Returns whether we support the *PHY Update Procedure* at all.
+    fn supports_phy_update(&self) -> bool {
+        self.features
+            .intersects(FeatureSet::LE_2M_PHY | FeatureSet::LE_CODED_PHY)
+    }
+
+    /// Returns the coding to transmit with when switching to the LE Coded PHY.
+    ///
+    /// The coding requested by the application is used, falling back to the current one and then
+    /// to S=8, which has the highest range.
+    fn coding(&self) -> CodingIndicator {
+        match (self.phy_request, self.phy) {
+            (PhyRequest::Pending(Phy::LeCoded { ci }), _)
+            | (PhyRequest::Sent(Phy::LeCoded { ci }), _)
+            | (_, Phy::LeCoded { ci }) => ci,
+            _ => CodingIndicator::S8,
+        }
+    }
+
+    /// Picks the PHY to answer an `LL_PHY_REQ` with, given the PHYs the master prefers to use in
+    /// both directions.
+    ///
+    /// A PHY requested by the application is preferred, followed by the fastest one we support. If
+    /// we have none in common, the current PHY is returned, which leaves the PHY unchanged.
+    fn preferred_phy(&self, offered: PhySet) -> Phy {
+        let candidates = offered & self.supported_phys();
+        match self.phy_request {
+            PhyRequest::Pending(phy) if candidates.contains(phy.into()) => phy,
+            _ if candidates.contains(PhySet::LE_2M) => Phy::Le2M,
+            _ if candidates.contains(PhySet::LE_1M) => Phy::Le1M,
+            _ if candidates.contains(PhySet::LE_CODED) => Phy::LeCoded { ci: self.coding() },
+            _ => self.phy,
+        }
+    }
+
+    /// Starts the PHY Update Procedure to switch to `phy` in both directions.
+    ///
+    /// `LL_PHY_REQ` is sent instead of the next data PDU. Returns a `Cmd` if we have to stop
+    /// skipping connection events (see [`wake`](Self::wake)).
+    pub(crate) fn request_phy(&mut self, phy: Phy, now: Instant) -> Result<Option<Cmd>, Error> {
+        if !self.supported_phys().contains(phy.into()) {
+            return Err(Error::InvalidValue);
+        }
+        if !matches!(self.phy_request, PhyRequest::None) {
+            return Err(Error::WouldBlock);
+        }
+
+        self.phy_request = PhyRequest::Pending(phy);
+        Ok(self.wake(now))
+    }
+
+    /// Asks the master for the connection parameters `params`.
+    ///
+    /// The request is sent instead of the next data PDU. If both devices support the Connection
+    /// Parameters Request Procedure, it is an `LL_CONNECTION_PARAM_REQ`, which the master answers
+    /// with an `LL_CONNECTION_UPDATE_IND` or a rejection. Otherwise, or if the master turns out not
+    /// to support the procedure, an L2CAP Connection Parameter Update Request is sent instead.
+    pub(crate) fn request_conn_params(&mut self, params: ConnectionParamRequest) {
+        self.param_request = ParamRequest::Pending(params);
+    }
+
+    /// Asks the master for the connection parameters `params` while connected.
+    ///
+    /// Like [`request_conn_params`](Self::request_conn_params), but returns a `Cmd` if we have to
+    /// stop skipping connection events (see [`wake`](Self::wake)). Returns `Error::WouldBlock` if
+    /// a request made earlier is still in progress.
+    pub(crate) fn request_conn_param_update(
+        &mut self,
+        params: ConnectionParamRequest,
+        now: Instant,
+    ) -> Result<Option<Cmd>, Error> {
+        if !matches!(self.param_request, ParamRequest::None) {
+            return Err(Error::WouldBlock);
+        }
+
+        self.request_conn_params(params);
+        Ok(self.wake(now))
+    }
+
+    /// Whether the Connection Parameters Request Procedure may be used.
+    ///
+    /// Like [`supported_phys`](Self::supported_phys), this takes the master's features into
+    /// account once it has sent them.
+    fn supports_conn_param_req(&self) -> bool {
+        let features = match self.peer_features {
+            Some(peer) => self.features & peer,
+            None => self.features,
+        };
+        features.contains(FeatureSet::CONN_PARAM_REQ)
+    }
+
+    /// Records that the master doesn't support the procedures in `features`, so that later
+    /// requests don't use them.
+    fn peer_lacks(&mut self, features: FeatureSet) {
+        let peer = self.peer_features.unwrap_or(FeatureSet::all());
+        self.peer_features = Some(peer - features);
+    }
+
+    /// Writes the PDU requesting new connection parameters to `writer`, if one is due.
+    ///
+    /// Returns the header of the PDU, or `None` if nothing was written. An L2CAP request is held
+    /// back while an L2CAP message from the TX queue is only partially sent.
+    fn write_param_request(&mut self, writer: &mut ByteWriter<'_>) -> Option<Header> {
+        let (mut params, ll_procedure) = match self.param_request {
+            ParamRequest::Pending(params) => (params, self.supports_conn_param_req()),
+            ParamRequest::L2cap(params) => (params, false),
+            ParamRequest::None | ParamRequest::Sent(_) => return None,
+        };
+
+        if ll_procedure {
+            params.set_reference_conn_event_count(self.conn_event_count.0);
+            let pdu = ControlPdu::ConnectionParamReq(params);
+            Pdu::from(&pdu).to_bytes(writer).unwrap();
+            self.param_request = ParamRequest::Sent(params);
+
+            let mut header = Header::new(Llid::Control);
+            header.set_payload_length(pdu.encoded_size());
+            Some(header)
+        } else if self.tx_message_left == 0 {
+            let left = writer.space_left();
+            l2cap::write_conn_param_update_req(writer, &params).unwrap();
+            // An accepted request results in an `LL_CONNECTION_UPDATE_IND`, so there's nothing to
+            // wait for
+            self.param_request = ParamRequest::None;
+
+            let mut header = Header::new(Llid::DataStart);
+            header.set_payload_length((left - writer.space_left()) as u8);
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Starts the Minimum Number of Used Channels Procedure, asking the master to use at least
+    /// `ind.min_used_channels()` data channels on the PHYs in `ind.phys()`.
+    ///
+    /// `LL_MIN_USED_CHANNELS_IND` is sent instead of the next data PDU. Returns a `Cmd` if we have
+    /// to stop skipping connection events (see [`wake`](Self::wake)).
+    pub(crate) fn request_min_used_channels(
+        &mut self,
+        ind: MinUsedChannels,
+        now: Instant,
+    ) -> Result<Option<Cmd>, Error> {
+        if !self.supports_min_used_channels() {
+            return Err(Error::InvalidValue);
+        }
+        if self.min_channels_request.is_some() {
+            return Err(Error::WouldBlock);
+        }
+
+        self.min_channels_request = Some(ind);
+        Ok(self.wake(now))
+    }
+
+    /// Whether the Minimum Number of Used Channels Procedure may be used.
+    ///
+    /// Like [`supported_phys`](Self::supported_phys), this takes the master's features into
+    /// account once it has sent them.
+    fn supports_min_used_channels(&self) -> bool {
+        let features = match self.peer_features {
+            Some(peer) => self.features & peer,
+            None => self.features,
+        };
+        features.contains(FeatureSet::MIN_USED_CHANNELS)
     }
 
     /// Advances the `unmapped_channel` and `channel` fields to the next data channel on which a
@@ -371,31 +1231,50 @@ impl<C: Config> Connection<C> {
         let unmapped_channel = DataChannel::new((self.unmapped_channel.index() + self.hop) % 37);
 
         self.unmapped_channel = unmapped_channel;
-        self.channel = if self.channel_map.is_used(unmapped_channel) {
-            unmapped_channel
-        } else {
-            // This channel isn't used, remap channel according to map
-            let remapping_index = unmapped_channel.index() % self.channel_map.num_used_channels();
-            self.channel_map.by_index(remapping_index)
-        };
+        self.channel = self.channel_map.csa1_channel(unmapped_channel);
     }
 
-    /// Sends a new PDU to the connected device (ie. a non-retransmitted PDU).
-    fn send(&mut self, mut header: Header, tx: &mut C::Transmitter) {
-        header.set_md(self.has_more_data());
+    /// Sends a new PDU to the connected device (ie. a non-retransmitted PDU), in response to the
+    /// packet received at `rx_end`.
+    ///
+    /// If the connection is encrypted, the payload in the TX buffer is encrypted in place and the
+    /// MIC is appended.
+    fn send(
+        &mut self,
+        mut header: Header,
+        tx: &mut C::Transmitter,
+        aes: &mut C::Aes,
+        rx_end: Instant,
+    ) {
+        let len = usize::from(header.payload_length());
+        if let (Some(ccm), true) = (self.encryption.tx_ccm(), len != 0) {
+            let (payload, rest) = tx.tx_payload_buf().split_at_mut(len);
+            let mic = ccm.encrypt(
+                aes,
+                self.tx_counter,
+                Direction::SlaveToMaster,
+                header.to_u16() as u8,
+                payload,
+            );
+            rest[..MIC_SIZE].copy_from_slice(&mic);
+            header.set_payload_length((len + MIC_SIZE) as u8);
+            self.tx_counter += 1;
+        }
+
+        header.set_md(self.has_more_data(rx_end));
         header.set_nesn(self.next_expected_seq_num);
         header.set_sn(self.transmit_seq_num);
         self.last_header = header;
 
         tx.transmit_data(self.access_address, self.crc_init, header, self.channel);
 
-        let pl = &tx.tx_payload_buf()[..usize::from(header.payload_length())];
-        trace!("DATA->{:?}, {:?}", header, HexSlice(pl));
+        // The radio may still be sending the payload, so `tx_payload_buf` can't be used to log it
+        trace!("DATA->{:?}", header);
     }
 
     /// Tries to process and acknowledge an LL Control PDU.
     ///
-    /// Returns `Err(())` when the connection is closed or lost.
+    /// Returns `Err(LlcpError::ConnectionLost)` when the connection is closed or lost.
     ///
     /// Note this this function is on a time-critical path and thus can not use logging since that's
     /// currently way too slow. Critical errors can still be logged, since they abort the connection
@@ -407,13 +1286,76 @@ impl<C: Config> Connection<C> {
     /// * **`can_respond`**: Whether the radio's TX buffer may be overwritten to send a response. If
     ///   this is `false`, this method may choose not to acknowledge the PDU and wait for a
     ///   retransmission instead.
+    /// * **`rx_capacity`**: Number of payload Bytes the radio's receive buffer can hold.
+    /// * **`aes`**: The AES provider, used for deriving the session key.
+    /// * **`now`**: Time at which `pdu` was received.
     fn process_control_pdu(
         &mut self,
         pdu: ControlPdu<'_>,
         can_respond: bool,
+        rx_capacity: usize,
+        aes: &mut C::Aes,
+        now: Instant,
     ) -> Result<Option<ControlPdu<'static>>, LlcpError> {
         let response = match pdu {
+            ControlPdu::EncReq(req) => {
+                // Since this changes our state, only process it if we can respond right away.
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+
+                match (&self.encryption, self.pending_key.take()) {
+                    (EncryptionState::Off, Some(key)) => {
+                        let (ccm, skd_s, iv_s) = key.session(aes, &req);
+                        self.encryption = EncryptionState::StartEncReqPending(ccm);
+                        self.tx_counter = 0;
+                        self.rx_counter = 0;
+                        ControlPdu::EncRsp {
+                            skd_s: Hex(skd_s),
+                            iv_s: Hex(iv_s),
+                        }
+                    }
+                    (EncryptionState::Off, None) => ControlPdu::RejectInd {
+                        error_code: Hex(ERROR_PIN_OR_KEY_MISSING),
+                    },
+                    _ => ControlPdu::RejectInd {
+                        error_code: Hex(ERROR_COMMAND_DISALLOWED),
+                    },
+                }
+            }
+            ControlPdu::StartEncRsp => {
+                if let EncryptionState::WaitStartEncRsp(ccm) = &self.encryption {
+                    if !can_respond {
+                        return Err(LlcpError::NoSpace);
+                    }
+
+                    // Our response is the first encrypted PDU we send.
+                    self.encryption = EncryptionState::On(ccm.clone());
+                    self.events |= PendingEvents::ENCRYPTION_ENABLED;
+                    ControlPdu::StartEncRsp
+                } else {
+                    ControlPdu::UnknownRsp {
+                        unknown_type: pdu.opcode(),
+                    }
+                }
+            }
+            ControlPdu::ConnectionUpdateReq(data)
+                if !acceptable_conn_params::<C>(
+                    data.interval(),
+                    data.latency(),
+                    data.timeout(),
+                ) =>
+            {
+                // The master doesn't expect a response to its indication, so the only way to
+                // refuse the new parameters is to end the connection
+                warn!("unacceptable connection parameters, terminating connection");
+                self.param_request = ParamRequest::None;
+                self.terminate(DisconnectReason::UnacceptableConnectionParameters, now);
+                return Ok(None);
+            }
             ControlPdu::ConnectionUpdateReq(data) => {
+                // This also answers our `LL_CONNECTION_PARAM_REQ`, if we sent one
+                self.param_request = ParamRequest::None;
                 self.prepare_llcp_update(LlcpUpdate::ConnUpdate(*data))?;
                 return Ok(None);
             }
@@ -429,23 +1371,139 @@ impl<C: Config> Connection<C> {
                     "closing connection due to termination request: code {:?}",
                     error_code
                 );
-                return Err(LlcpError::ConnectionLost);
+                return Err(LlcpError::ConnectionLost(error_code));
+            }
+            ControlPdu::FeatureReq { features_master } => {
+                self.peer_features = Some(features_master);
+                ControlPdu::FeatureRsp {
+                    features_used: features_master & self.features,
+                }
+            }
+            ControlPdu::LengthReq { max_tx_octets, .. }
+                if self
+                    .features
+                    .contains(FeatureSet::LE_PACKET_LENGTH_EXTENSION) =>
+            {
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+
+                // Never announce more than the receive buffer can hold. We don't send data PDUs
+                // larger than the minimum size, so the TX limits stay at their defaults.
+                let max_rx_octets = max_rx_octets(rx_capacity);
+                self.max_rx_octets = cmp::max(
+                    cmp::min(max_rx_octets, max_tx_octets),
+                    MIN_DATA_PAYLOAD_BUF as u16,
+                );
+                ControlPdu::LengthRsp {
+                    max_rx_octets,
+                    max_rx_time: packet_time(max_rx_octets),
+                    max_tx_octets: MIN_DATA_PAYLOAD_BUF as u16,
+                    max_tx_time: packet_time(MIN_DATA_PAYLOAD_BUF as u16),
+                }
+            }
+            ControlPdu::PhyReq { tx_phys, rx_phys } if self.supports_phy_update() => {
+                // The radio uses one PHY for both directions, so we offer a single PHY that the
+                // master can use for both
+                let phy = self.preferred_phy(tx_phys & rx_phys);
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+
+                // The master's procedure replaces any of ours
+                self.phy_request = PhyRequest::None;
+                ControlPdu::PhyRsp {
+                    tx_phys: phy.into(),
+                    rx_phys: phy.into(),
+                }
+            }
+            ControlPdu::PhyUpdateInd {
+                m_to_s_phy,
+                s_to_m_phy,
+                instant,
+            } => {
+                let coding = self.coding();
+                self.phy_request = PhyRequest::None;
+                if m_to_s_phy.is_empty() && s_to_m_phy.is_empty() {
+                    // Neither PHY changes, which completes the procedure
+                    return Ok(None);
+                }
+
+                // An empty set keeps the PHY of that direction
+                let current = PhySet::from(self.phy);
+                let rx_phys = if m_to_s_phy.is_empty() {
+                    current
+                } else {
+                    m_to_s_phy
+                };
+                let tx_phys = if s_to_m_phy.is_empty() {
+                    current
+                } else {
+                    s_to_m_phy
+                };
+                match rx_phys.single() {
+                    Some(phy) if rx_phys == tx_phys && self.supported_phys().contains(rx_phys) => {
+                        let phy = match phy {
+                            Phy::LeCoded { .. } => Phy::LeCoded { ci: coding },
+                            phy => phy,
+                        };
+                        self.prepare_llcp_update(LlcpUpdate::Phy { phy, instant })?;
+                        return Ok(None);
+                    }
+                    _ => {
+                        error!(
+                            "unsupported PHY update: M->S {:?}, S->M {:?}",
+                            m_to_s_phy, s_to_m_phy
+                        );
+                        return Err(LlcpError::ConnectionLost(
+                            DisconnectReason::UnsupportedRemoteFeature,
+                        ));
+                    }
+                }
+            }
+            ControlPdu::MinUsedChannelsInd(ind) if self.supports_min_used_channels() => {
+                // Only relevant when selecting the channel map, which is up to the master
+                self.peer_min_used_channels = Some(ind);
+                return Ok(None);
+            }
+            ControlPdu::UnknownRsp { unknown_type } => {
+                if unknown_type == ControlOpcode::PhyReq {
+                    // The master doesn't support the PHY Update Procedure
+                    self.phy_request = PhyRequest::None;
+                } else if unknown_type == ControlOpcode::ConnectionParamReq {
+                    // The master doesn't support the Connection Parameters Request Procedure
+                    self.param_request.fall_back_to_l2cap();
+                    self.peer_lacks(FeatureSet::CONN_PARAM_REQ);
+                }
+                return Ok(None);
+            }
+            ControlPdu::RejectIndExt {
+                reject_opcode,
+                error_code,
+            } => {
+                if reject_opcode == ControlOpcode::PhyReq {
+                    self.phy_request = PhyRequest::None;
+                } else if reject_opcode == ControlOpcode::ConnectionParamReq {
+                    if error_code.0 == ERROR_UNSUPPORTED_REMOTE_FEATURE {
+                        self.param_request.fall_back_to_l2cap();
+                        self.peer_lacks(FeatureSet::CONN_PARAM_REQ);
+                    } else {
+                        self.param_request = ParamRequest::None;
+                    }
+                }
+                return Ok(None);
             }
-            ControlPdu::FeatureReq { features_master } => ControlPdu::FeatureRsp {
-                features_used: features_master & FeatureSet::supported(),
-            },
             ControlPdu::VersionInd { .. } => {
-                // FIXME this should be something real, and defined somewhere else
-                let comp_id = 0xFFFF;
                 // FIXME this should correlate with the Cargo package version
                 let sub_vers_nr = 0x0000;
 
                 ControlPdu::VersionInd {
                     vers_nr: BLUETOOTH_VERSION,
-                    comp_id: CompanyId::from_raw(comp_id),
+                    comp_id: self.company_id,
                     sub_vers_nr: Hex(sub_vers_nr),
                 }
             }
+            // Unknown and unimplemented opcodes, or invalid `CtrData`
             _ => ControlPdu::UnknownRsp {
                 unknown_type: pdu.opcode(),
             },
@@ -459,58 +1517,137 @@ impl<C: Config> Connection<C> {
         }
     }
 
+    /// Decrypts a received `payload` (including the MIC) into `plaintext`.
+    ///
+    /// Returns the length of the decrypted payload, or `None` if the MIC is invalid.
+    fn decrypt(
+        &self,
+        aes: &mut C::Aes,
+        header: Header,
+        payload: &[u8],
+        plaintext: &mut [u8; MIN_DATA_PAYLOAD_BUF],
+    ) -> Option<u8> {
+        let ccm = self.encryption.rx_ccm()?;
+        let len = payload.len().checked_sub(MIC_SIZE)?;
+        if len == 0 || len > plaintext.len() {
+            return None;
+        }
+
+        let (payload, mic) = payload.split_at(len);
+        let plaintext = &mut plaintext[..len];
+        plaintext.copy_from_slice(payload);
+        ccm.decrypt(
+            aes,
+            self.rx_counter,
+            Direction::MasterToSlave,
+            header.to_u16() as u8,
+            plaintext,
+            mic.try_into().unwrap(),
+        )
+        .ok()?;
+        Some(len as u8)
+    }
+
     /// Stores `update` in the link layer state so that it will be applied once its *instant* is
     /// reached.
+    ///
+    /// The master retransmits the PDU carrying `update` until we acknowledge it, so it may arrive
+    /// after its instant has passed. The connection is lost in that case, since we can no longer
+    /// follow the master.
     fn prepare_llcp_update(&mut self, update: LlcpUpdate) -> Result<(), LlcpError> {
-        // TODO: check that instant is <32767 in the future
+        if !instant_in_future(update.instant(), self.conn_event_count.0) {
+            error!(
+                "instant of {:?} passed (event counter {})",
+                update, self.conn_event_count.0
+            );
+            return Err(LlcpError::ConnectionLost(DisconnectReason::InstantPassed));
+        }
+
         if let Some(data) = self.update_data {
             error!(
                 "got update data {:?} while update {:?} is already queued",
                 update, data
             );
-            Err(LlcpError::ConnectionLost)
+            Err(LlcpError::ConnectionLost(
+                DisconnectReason::DifferentTransactionCollision,
+            ))
         } else {
             self.update_data = Some(update);
             Ok(())
         }
     }
 
+    /// Passes the events that occurred since the last call to `f`, and clears them.
+    pub(crate) fn take_events(&mut self, mut f: impl FnMut(LinkEvent)) {
+        let events = mem::replace(&mut self.events, PendingEvents::empty());
+        if events.contains(PendingEvents::CONNECTION_UPDATED) {
+            f(LinkEvent::ConnectionUpdated {
+                interval: self.conn_interval,
+                slave_latency: self.slave_latency,
+                supervision_timeout: self.supervision_timeout,
+            });
+        }
+        if events.contains(PendingEvents::ENCRYPTION_ENABLED) {
+            f(LinkEvent::EncryptionEnabled);
+        }
+        if events.contains(PendingEvents::PHY_UPDATED) {
+            f(LinkEvent::PhyUpdated { phy: self.phy });
+        }
+    }
+
     /// Patches the link layer state to incorporate `update`.
     ///
     /// Returns a `Cmd` when the usual Link Layer `Cmd` should be overridden. In that case, this
     /// method must also perform channel hopping.
-    fn apply_llcp_update(&mut self, update: LlcpUpdate, rx_end: Instant) -> Option<Cmd> {
+    fn apply_llcp_update(&mut self, update: LlcpUpdate, now: Instant) -> Option<Cmd> {
         match update {
             LlcpUpdate::ConnUpdate(data) => {
-                let old_conn_interval = self.conn_interval;
                 self.conn_interval = data.interval();
+                self.supervision_timeout = data.timeout();
+                self.slave_latency = data.latency();
+                self.events |= PendingEvents::CONNECTION_UPDATED;
 
                 self.hop_channel();
 
-                Some(Cmd {
-                    // Next update after the tx window ends (= missed it)
-                    next_update: NextUpdate::At(
-                        rx_end + old_conn_interval + data.win_offset() + data.win_size(),
-                    ),
-                    // Listen for the transmit window
-                    radio: RadioCmd::ListenData {
-                        channel: self.channel,
-                        access_address: self.access_address,
-                        crc_init: self.crc_init,
-                        timeout: false,
-                    },
-                    // This function never queues work, but the caller might change this to `true`
-                    queued_work: false,
-                })
+                // Listen for the transmit window. The next update happens after the window ends
+                // (= missed it). This function never queues work, but the caller might change
+                // `queued_work` to `true`.
+                // `anchor` still uses the old interval.
+                let tx_window_start = self.anchor + data.win_offset();
+                self.anchor = tx_window_start;
+                self.anchor_measured = false;
+                Some(self.listen(now, tx_window_start + data.win_size(), false))
             }
             LlcpUpdate::ChannelMap { map, .. } => {
                 self.channel_map = map;
                 None
             }
+            LlcpUpdate::Phy { phy, .. } => {
+                self.phy = phy;
+                self.events |= PendingEvents::PHY_UPDATED;
+                None
+            }
         }
     }
 }
 
+#[cfg(feature = "defmt")]
+impl<C: Config> defmt::Format for Connection<C> {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(
+            fmt,
+            "Connection {{ peer: {}, interval: {}, event: {=u16}, channel: {}, SN: {}, NESN: {}, encrypted: {=bool} }}",
+            self.peer_addr,
+            self.conn_interval,
+            self.conn_event_count.0,
+            self.channel,
+            self.transmit_seq_num,
+            self.next_expected_seq_num,
+            self.encryption.rx_ccm().is_some(),
+        );
+    }
+}
+
 // Public API
 impl<C: Config> Connection<C> {
     /// Returns the configured interval between connection events.
@@ -525,6 +1662,206 @@ impl<C: Config> Connection<C> {
     pub fn connection_interval(&self) -> Duration {
         self.conn_interval
     }
+
+    /// Returns the number of consecutive connection events we may skip when idle.
+    ///
+    /// Skipping events saves power, but increases the latency of data sent by the Central.
+    pub fn slave_latency(&self) -> u16 {
+        self.slave_latency
+    }
+
+    /// Returns the time without a valid packet after which the connection is considered lost.
+    pub fn supervision_timeout(&self) -> Duration {
+        self.supervision_timeout
+    }
+
+    /// Returns the counter of the next connection event (or of the current one, while it is in
+    /// progress).
+    ///
+    /// Skipped connection events are counted as well, since the master still performs them.
+    pub fn event_counter(&self) -> u16 {
+        self.conn_event_count.0
+    }
+
+    /// Returns the number of unmapped channels to hop between connection events (`hopIncrement`).
+    pub fn hop_increment(&self) -> u8 {
+        self.hop
+    }
+
+    /// Returns the unmapped channel of the connection event returned by
+    /// [`event_counter`](Self::event_counter) (`lastUnmappedChannel` once the event has started).
+    ///
+    /// Channel selection algorithm #1 advances this by [`hop_increment`](Self::hop_increment) for
+    /// every connection event, including skipped ones.
+    pub fn unmapped_channel(&self) -> DataChannel {
+        self.unmapped_channel
+    }
+
+    /// Returns the data channel of the connection event returned by
+    /// [`event_counter`](Self::event_counter), after remapping unused channels.
+    pub fn channel(&self) -> DataChannel {
+        self.channel
+    }
+
+    /// Returns the PHY currently used in both directions.
+    pub fn phy(&self) -> Phy {
+        self.phy
+    }
+
+    /// Returns the minimum number of used channels the peer asked for, if it sent an
+    /// `LL_MIN_USED_CHANNELS_IND`.
+    ///
+    /// Channel maps selected for this connection should satisfy it (see
+    /// [`MinUsedChannels::is_satisfied_by`]).
+    pub fn peer_min_used_channels(&self) -> Option<MinUsedChannels> {
+        self.peer_min_used_channels
+    }
+
+    /// Returns the handle identifying this connection.
+    pub fn handle(&self) -> ConnectionHandle {
+        self.handle
+    }
+
+    /// Returns the device address of the connected master.
+    pub fn peer_address(&self) -> DeviceAddress {
+        self.peer_addr
+    }
+
+    /// Returns the resolving list index of the IRK that the master's address was resolved with.
+    ///
+    /// This is `None` if the master doesn't use a Resolvable Private Address, or if none of the keys
+    /// passed to [`LinkLayer::add_peer_irk`] resolve it.
+    ///
+    /// [`LinkLayer::add_peer_irk`]: super::LinkLayer::add_peer_irk
+    pub fn resolved_peer(&self) -> Option<usize> {
+        self.resolved_peer
+    }
+
+    /// Returns the counters of received data channel PDUs.
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    pub(crate) fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Returns the smoothed RSSI and CRC error rate of received data channel PDUs.
+    pub fn link_quality(&self) -> LinkQuality {
+        self.link_quality
+    }
+
+    /// Updates the link quality estimate with a received data channel PDU.
+    pub(crate) fn record_link_quality(&mut self, crc_ok: bool, rssi: Option<i8>) {
+        self.link_quality.record_rx(crc_ok, rssi);
+    }
+
+    /// Returns whether the connection is encrypted in both directions.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.tx_ccm().is_some()
+    }
+
+    /// Provides the key to use when the master starts encrypting the connection.
+    ///
+    /// The key is used for the next `LL_ENC_REQ` sent by the master. If no key was provided when
+    /// that request arrives, it is rejected. `rng` is used to generate this device's contribution
+    /// to the session key diversifier and IV.
+    pub(crate) fn set_encryption_key<R: RngCore + CryptoRng>(
+        &mut self,
+        ltk: EncryptionKey,
+        rng: &mut R,
+    ) {
+        self.pending_key = Some(PendingKey {
+            ltk,
+            skd_s: rng.next_u64(),
+            iv_s: rng.next_u32(),
+        });
+    }
+}
+
+/// `PIN or Key Missing` error code sent when no key is available to start encryption.
+const ERROR_PIN_OR_KEY_MISSING: u8 = 0x06;
+
+/// `Command Disallowed` error code.
+const ERROR_COMMAND_DISALLOWED: u8 = 0x0C;
+
+/// `Unsupported Remote Feature` error code.
+const ERROR_UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1A;
+
+/// Key material for starting encryption, provided by the host.
+struct PendingKey {
+    ltk: EncryptionKey,
+    skd_s: u64,
+    iv_s: u32,
+}
+
+impl PendingKey {
+    /// Derives the session key from the master's `LL_ENC_REQ`.
+    ///
+    /// Returns the CCM transform to use for the connection, along with the `SKDs` and `IVs` values
+    /// to send back to the master.
+    fn session(self, aes: &mut impl AesProvider, req: &EncryptionRequest) -> (Ccm, u64, u32) {
+        // SKD = SKDs || SKDm
+        let skd = u128::from(self.skd_s) << 64 | u128::from(req.skd_m.0);
+        let mut sk = skd.to_be_bytes();
+        aes.encrypt_block(&self.ltk.0.to_be_bytes(), &mut sk);
+
+        // IV = IVs || IVm
+        let mut iv = [0; 8];
+        iv[..4].copy_from_slice(&req.iv_m.0.to_le_bytes());
+        iv[4..].copy_from_slice(&self.iv_s.to_le_bytes());
+
+        let ccm = Ccm::new(u128::from_be_bytes(sk), iv);
+        (ccm, self.skd_s, self.iv_s)
+    }
+}
+
+impl fmt::Debug for PendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PendingKey { .. }")
+    }
+}
+
+/// Progress of the encryption start procedure.
+#[derive(Debug)]
+enum EncryptionState {
+    /// The connection is not encrypted.
+    Off,
+
+    /// `LL_ENC_RSP` was sent, `LL_START_ENC_REQ` has to be sent next.
+    StartEncReqPending(Ccm),
+
+    /// `LL_START_ENC_REQ` was sent (unencrypted), waiting for the master to acknowledge it.
+    StartEncReqSent(Ccm),
+
+    /// Incoming PDUs are encrypted, waiting for the master's `LL_START_ENC_RSP`.
+    WaitStartEncRsp(Ccm),
+
+    /// The connection is encrypted in both directions.
+    On(Ccm),
+}
+
+impl EncryptionState {
+    /// Returns the CCM transform to apply to received PDUs.
+    fn rx_ccm(&self) -> Option<&Ccm> {
+        match self {
+            EncryptionState::WaitStartEncRsp(ccm) | EncryptionState::On(ccm) => Some(ccm),
+            _ => None,
+        }
+    }
+
+    /// Returns the CCM transform to apply to sent PDUs.
+    fn tx_ccm(&self) -> Option<&Ccm> {
+        match self {
+            EncryptionState::On(ccm) => Some(ccm),
+            _ => None,
+        }
+    }
+
+    /// Returns whether sending data PDUs is paused due to an ongoing encryption start procedure.
+    fn pauses_data(&self) -> bool {
+        !matches!(self, EncryptionState::Off | EncryptionState::On(_))
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -532,8 +1869,110 @@ enum LlcpError {
     /// No space in TX buffer, NACK the incoming PDU and retry later.
     NoSpace,
 
-    /// Consider the connection lost due to a critical error or timeout.
-    ConnectionLost,
+    /// Consider the connection lost due to a critical error or timeout, or because the master
+    /// closed it.
+    ConnectionLost(DisconnectReason),
+}
+
+/// Progress of a termination procedure started by us.
+#[derive(Debug, Copy, Clone)]
+enum Termination {
+    /// The connection stays open.
+    None,
+
+    /// `LL_TERMINATE_IND` will be sent in place of the next data PDU.
+    Pending {
+        reason: DisconnectReason,
+        deadline: Instant,
+    },
+
+    /// `LL_TERMINATE_IND` was sent and awaits acknowledgement. The connection is closed
+    /// at `deadline` at the latest.
+    Sent { deadline: Instant },
+}
+
+bitflags! {
+    /// Connection events that have not yet been reported to the application.
+    ///
+    /// The event data is taken from the connection state when reporting.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct PendingEvents: u8 {
+        const CONNECTION_UPDATED = 1 << 0;
+        const ENCRYPTION_ENABLED = 1 << 1;
+        const PHY_UPDATED = 1 << 2;
+    }
+}
+
+/// Progress of a PHY Update Procedure started by us.
+#[derive(Debug, Copy, Clone)]
+enum PhyRequest {
+    /// No procedure is in progress.
+    None,
+
+    /// `LL_PHY_REQ` for the given PHY will be sent in place of the next data PDU.
+    Pending(Phy),
+
+    /// `LL_PHY_REQ` for the given PHY was sent, waiting for the master's `LL_PHY_UPDATE_IND`.
+    Sent(Phy),
+}
+
+/// Progress of a request for new connection parameters made by us.
+#[derive(Debug, Copy, Clone)]
+enum ParamRequest {
+    /// No request is in progress.
+    None,
+
+    /// A request for the given parameters will be sent in place of the next data PDU, using the
+    /// Connection Parameters Request Procedure if both devices support it.
+    Pending(ConnectionParamRequest),
+
+    /// An L2CAP Connection Parameter Update Request will be sent in place of the next data PDU.
+    L2cap(ConnectionParamRequest),
+
+    /// `LL_CONNECTION_PARAM_REQ` was sent, waiting for the master's `LL_CONNECTION_UPDATE_IND`.
+    Sent(ConnectionParamRequest),
+}
+
+impl ParamRequest {
+    /// Whether a request is waiting to be sent.
+    fn is_pending(&self) -> bool {
+        matches!(self, ParamRequest::Pending(_) | ParamRequest::L2cap(_))
+    }
+
+    /// Resends the `LL_CONNECTION_PARAM_REQ` as an L2CAP request, after the master refused it
+    /// because it doesn't support the procedure.
+    fn fall_back_to_l2cap(&mut self) {
+        if let ParamRequest::Sent(params) = *self {
+            *self = ParamRequest::L2cap(params);
+        }
+    }
+}
+
+/// Returns the number of Bytes of an L2CAP message that are left to send after sending `payload`.
+///
+/// `left` is the number before sending `payload`, which is the payload of a data channel PDU with
+/// the given `llid`.
+fn l2cap_bytes_left(left: u16, llid: Llid, payload: &[u8]) -> u16 {
+    match (llid, payload) {
+        // The Basic L2CAP header starts with the length of the information payload following it
+        (Llid::DataStart, [lo, hi, ..]) => u16::from_le_bytes([*lo, *hi])
+            .saturating_add(4)
+            .saturating_sub(payload.len() as u16),
+        (Llid::DataCont, _) => left.saturating_sub(payload.len() as u16),
+        _ => left,
+    }
+}
+
+/// Returns the ATT opcode of the message started by `payload`, if it is sent on the ATT channel.
+fn att_opcode(llid: Llid, payload: &[u8]) -> Option<Opcode> {
+    match (llid, payload) {
+        (Llid::DataStart, [_, _, lo, hi, opcode, ..])
+            if u16::from_le_bytes([*lo, *hi]) == l2cap::Channel::ATT.as_raw() =>
+        {
+            Some(Opcode::from(*opcode))
+        }
+        _ => None,
+    }
 }
 
 /// A Link-Layer state update that may be applied with a delay.
@@ -553,6 +1992,15 @@ enum LlcpUpdate {
         /// The connection event at which to switch.
         instant: u16,
     },
+
+    /// Start using a different PHY in both directions.
+    Phy {
+        /// The new PHY.
+        phy: Phy,
+
+        /// The connection event at which to switch.
+        instant: u16,
+    },
 }
 
 impl LlcpUpdate {
@@ -560,7 +2008,99 @@ impl LlcpUpdate {
     fn instant(&self) -> u16 {
         match self {
             LlcpUpdate::ConnUpdate(data) => data.instant(),
-            LlcpUpdate::ChannelMap { instant, .. } => *instant,
+            LlcpUpdate::ChannelMap { instant, .. } | LlcpUpdate::Phy { instant, .. } => *instant,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+    use crate::link::llcp::ControlOpcode;
+
+    /// Derives the session from the encryption sample data in the spec (Vol 6, Part C, 1) and
+    /// checks it against the encrypted `LL_START_ENC_RSP` sent by the master.
+    #[test]
+    fn session_key_derivation() {
+        let aes = &mut SoftAesProvider::new();
+        let key = PendingKey {
+            ltk: EncryptionKey(0x4C68384139F574D836BCF34E9DFB01BF),
+            skd_s: 0x0213243546576879,
+            iv_s: 0xDEAFBABE,
+        };
+        let req = EncryptionRequest {
+            rand: Hex(0xABCDEF1234567890),
+            ediv: Hex(0x2474),
+            skd_m: Hex(0xACBDCEDFE0F10213),
+            iv_m: Hex(0xBADCAB24),
+        };
+
+        let (ccm, skd_s, iv_s) = key.session(aes, &req);
+        assert_eq!(skd_s, 0x0213243546576879);
+        assert_eq!(iv_s, 0xDEAFBABE);
+
+        let mut payload = [u8::from(ControlOpcode::StartEncRsp)];
+        let mic = ccm.encrypt(aes, 0, Direction::MasterToSlave, 0x0F, &mut payload);
+        assert_eq!(payload, [0x9F]);
+        assert_eq!(mic, [0xCD, 0xA7, 0xF4, 0x48]);
+    }
+
+    #[test]
+    fn instant_wrap() {
+        assert!(instant_in_future(1, 0));
+        assert!(!instant_in_future(0, 0));
+        assert!(!instant_in_future(0xFFFF, 0xFFFF));
+        assert!(instant_in_future(32766, 0));
+        assert!(!instant_in_future(32767, 0));
+        assert!(!instant_in_future(0xFFFF, 0));
+
+        // Straddling the wrap
+        assert!(instant_in_future(0x0000, 0xFFFF));
+        assert!(instant_in_future(0x0002, 0xFFFE));
+        assert!(!instant_in_future(0xFFFE, 0x0002));
+        assert!(instant_in_future(0x7FFC, 0xFFFE));
+        assert!(!instant_in_future(0x7FFD, 0xFFFE));
+        assert!(!instant_in_future(0xFFF0, 0x0010));
+    }
+
+    #[test]
+    fn window_widening_latency() {
+        // Master at 500 ppm, us at 50 ppm, 50 ms connection interval.
+        let interval = Duration::millis(50);
+        let widening = |latency: u32| {
+            window_widening(550, WINDOW_WIDENING, interval * (latency + 1), interval)
+        };
+
+        assert_eq!(widening(0), Duration::micros(500 + 28)); // 27.5 µs of drift
+        assert_eq!(widening(1), Duration::micros(500 + 55));
+        assert_eq!(widening(4), Duration::micros(500 + 138)); // 137.5 µs
+        assert_eq!(widening(99), Duration::micros(500 + 2750));
+
+        // Clamped to half the connection interval minus `T_IFS`.
+        assert_eq!(widening(999), Duration::micros(25_000 - 150));
+
+        // No drift without elapsed time.
+        assert_eq!(
+            window_widening(550, WINDOW_WIDENING, Duration::micros(0), interval),
+            WINDOW_WIDENING
+        );
+    }
+
+    #[test]
+    fn l2cap_message_fragments() {
+        // A 40-Byte ATT PDU split into 27 + 17 Bytes (including the 4-Byte header)
+        let start = [40, 0, 0x04, 0x00, 0x1B];
+        let left = l2cap_bytes_left(0, Llid::DataStart, &[&start[..], &[0; 22]].concat());
+        assert_eq!(left, 17);
+        assert_eq!(l2cap_bytes_left(left, Llid::Control, &[0x07, 0x0F]), 17);
+        assert_eq!(l2cap_bytes_left(left, Llid::DataCont, &[0; 17]), 0);
+
+        // Complete messages and empty PDUs leave nothing to send
+        assert_eq!(
+            l2cap_bytes_left(0, Llid::DataStart, &[1, 0, 0x04, 0x00, 0x1E]),
+            0
+        );
+        assert_eq!(l2cap_bytes_left(0, Llid::DataCont, &[]), 0);
+    }
+}
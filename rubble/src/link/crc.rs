@@ -0,0 +1,90 @@
+//! Software implementation of the Link-Layer CRC.
+//!
+//! Radios usually compute the CRC in hardware, but simulated radios (and hardware without CRC
+//! support) have to compute it in software. The CRC is specified in Vol 6, Part B, Section 3.1.1.
+
+use crate::link::CRC_POLY;
+
+/// Computes the 24-bit CRC of the Link-Layer PDU `data` (header and payload).
+///
+/// `init` is the CRC initialization value (`0x555555` for advertising channel PDUs, or the
+/// `CRCInit` of the connection or advertising train), of which only the least significant 24 bits
+/// are used. Like the `CRCInit`, the returned value has the bit in position 0 of the LFSR in its
+/// least significant bit.
+///
+/// The bits of `data` are fed into the LFSR in the order they are transmitted, ie. starting with
+/// the least significant bit of `data[0]`. The CRC itself, unlike every other field, is transmitted
+/// MSb first: bit 23 of the returned value is the first bit sent after the PDU. Note that this
+/// makes the CRC appear bit-reversed when it is stored in the same way as the PDU Bytes.
+///
+/// Since the LFSR state is returned unmodified, a CRC can be computed in several steps by passing
+/// the result of one call as the `init` value of the next.
+pub fn ble_crc24(init: u32, data: &[u8]) -> u32 {
+    let mut crc = init & 0xFF_FFFF;
+    for &byte in data {
+        for bit in 0..8 {
+            let feedback = ((crc >> 23) as u8 ^ (byte >> bit)) & 1 != 0;
+            crc = (crc << 1) & 0xFF_FFFF;
+            if feedback {
+                // Bit 24 has just been shifted out, so only the lower terms are applied
+                crc ^= CRC_POLY & 0xFF_FFFF;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        // The initialization value is returned unchanged when there's no data
+        assert_eq!(ble_crc24(0x55_5555, &[]), 0x55_5555);
+        assert_eq!(ble_crc24(0xFF55_5555, &[]), 0x55_5555);
+
+        // `ADV_IND` with AdvA 06:05:04:03:02:01 and no AdvData
+        assert_eq!(
+            ble_crc24(0x55_5555, &[0x00, 0x06, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+            0x42_AF4F
+        );
+        // `ADV_IND` with random AdvA EE:FF:C0:EE:FF:C0
+        assert_eq!(
+            ble_crc24(0x55_5555, &[0x40, 0x06, 0xC0, 0xFF, 0xEE, 0xC0, 0xFF, 0xEE]),
+            0x9E_1D3C
+        );
+        // Empty data channel PDU
+        assert_eq!(ble_crc24(0x12_3456, &[0x01, 0x00]), 0x12_3B51);
+
+        let data = (0..27).collect::<Vec<u8>>();
+        assert_eq!(ble_crc24(0xAB_CDEF, &data), 0x3F_630B);
+    }
+
+    #[test]
+    fn incremental() {
+        let pdu = [0x02, 0x05, 0x01, 0x00, 0x04, 0x00, 0x0A];
+        let crc = ble_crc24(0x12_3456, &pdu);
+        for split in 0..pdu.len() {
+            let (header, payload) = pdu.split_at(split);
+            assert_eq!(ble_crc24(ble_crc24(0x12_3456, header), payload), crc);
+        }
+    }
+
+    #[test]
+    fn residue() {
+        // Feeding the CRC into the LFSR after the PDU, in transmission order, clears it
+        let pdu = [0x00, 0x06, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        let crc = ble_crc24(0x55_5555, &pdu);
+        let air = (crc.reverse_bits() >> 8).to_le_bytes();
+        assert_eq!(air[..3], [0x42, 0xF5, 0xF2]);
+        assert_eq!(ble_crc24(crc, &air[..3]), 0);
+
+        // Any single bit error is detected
+        for i in 0..pdu.len() * 8 {
+            let mut corrupted = pdu;
+            corrupted[i / 8] ^= 1 << (i % 8);
+            assert_ne!(ble_crc24(0x55_5555, &corrupted), crc);
+        }
+    }
+}
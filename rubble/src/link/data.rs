@@ -71,7 +71,8 @@ impl Header {
 
     /// Parses a header from raw bytes.
     ///
-    /// Panics when `raw` contains less than 2 Bytes.
+    /// Panics when `raw` contains less than 2 Bytes. Use the [`FromBytes`] implementation to
+    /// handle truncated input gracefully.
     pub fn parse(raw: &[u8]) -> Self {
         let bytes: [u8; 2] = raw[..2].try_into().expect("raw has fewer than 2 bytes");
         Header(u16::from_le_bytes(bytes))
@@ -174,6 +175,21 @@ impl fmt::Debug for Header {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Header {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(
+            fmt,
+            "Header {{ LLID: {}, NESN: {}, SN: {}, MD: {}, Length: {} }}",
+            self.llid(),
+            self.nesn(),
+            self.sn(),
+            self.md(),
+            self.payload_length()
+        );
+    }
+}
+
 impl<'a> FromBytes<'a> for Header {
     fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
         let raw = bytes.read_u16_le()?;
@@ -189,6 +205,7 @@ impl ToBytes for Header {
 
 /// Values of the LLID field in `Header`.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Llid {
     /// Reserved for future use.
     Reserved = 0b00,
@@ -1,7 +1,9 @@
+use crate::Error;
 use core::fmt;
 
 /// Specifies whether a device address is randomly generated or a LAN MAC address.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum AddressKind {
     /// Publicly registered IEEE 802-2001 LAN MAC address.
     Public,
@@ -20,10 +22,31 @@ impl DeviceAddress {
     /// Create a new device address from 6 raw Bytes and an address kind specifier.
     ///
     /// The `bytes` array contains the address Bytes as they are sent over the air (LSB first).
-    pub fn new(bytes: [u8; 6], kind: AddressKind) -> Self {
+    pub const fn new(bytes: [u8; 6], kind: AddressKind) -> Self {
         DeviceAddress { bytes, kind }
     }
 
+    /// Creates a random static device address.
+    ///
+    /// Static addresses are random addresses that stay the same at least until the device is power
+    /// cycled. The 2 most significant bits of `bytes` (the top bits of the last Byte) are set to
+    /// mark the address as static. Returns `Error::InvalidValue` if the remaining 46 bits are all
+    /// 0 or all 1, which the spec disallows.
+    ///
+    /// The `bytes` array contains the address Bytes as they are sent over the air (LSB first).
+    pub fn random_static(mut bytes: [u8; 6]) -> Result<Self, Error> {
+        bytes[5] |= 0b1100_0000;
+
+        let random_bits = u64::from_le_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], 0, 0,
+        ]) & 0x3FFF_FFFF_FFFF;
+        if random_bits == 0 || random_bits == 0x3FFF_FFFF_FFFF {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(DeviceAddress::new(bytes, AddressKind::Random))
+    }
+
     /// Returns the address kind.
     pub fn kind(&self) -> AddressKind {
         self.kind
@@ -34,6 +57,13 @@ impl DeviceAddress {
         self.kind == AddressKind::Random
     }
 
+    /// Returns whether this is a random static address.
+    ///
+    /// Random addresses whose 2 most significant bits are not both set are private addresses.
+    pub fn is_random_static(&self) -> bool {
+        self.is_random() && self.bytes[5] & 0b1100_0000 == 0b1100_0000
+    }
+
     /// Returns the raw bytes making up this address (LSB first).
     pub fn raw(&self) -> &[u8; 6] {
         &self.bytes
@@ -47,6 +77,24 @@ impl fmt::Debug for DeviceAddress {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for DeviceAddress {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        let b = &self.bytes;
+        defmt::write!(
+            fmt,
+            "DeviceAddress({=u8:02x}:{=u8:02x}:{=u8:02x}:{=u8:02x}:{=u8:02x}:{=u8:02x}, {})",
+            b[5],
+            b[4],
+            b[3],
+            b[2],
+            b[1],
+            b[0],
+            self.kind
+        );
+    }
+}
+
 impl fmt::Display for DeviceAddress {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Note: Bluetooth device addresses are usually displayed with MSB
@@ -84,4 +132,26 @@ mod tests {
         let addr = DeviceAddress::new([0x5A, 0x92, 0x04, 0x26, 0xC6, 0x88], AddressKind::Public);
         assert_eq!(format!("{}", addr), "88:c6:26:04:92:5a");
     }
+
+    #[test]
+    fn random_static() {
+        let addr = DeviceAddress::random_static([1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(addr.raw(), &[1, 2, 3, 4, 5, 0xC6]);
+        assert!(addr.is_random());
+        assert!(addr.is_random_static());
+
+        assert_eq!(
+            DeviceAddress::random_static([0; 6]),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            DeviceAddress::random_static([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x3F]),
+            Err(Error::InvalidValue)
+        );
+
+        let public = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Public);
+        assert!(!public.is_random_static());
+        let private = DeviceAddress::new([1, 2, 3, 4, 5, 0x46], AddressKind::Random);
+        assert!(!private.is_random_static());
+    }
 }
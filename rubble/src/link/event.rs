@@ -0,0 +1,101 @@
+//! Connection lifecycle events reported to the application.
+//!
+//! The Link-Layer passes a [`LinkEvent`] to the [`LinkEventHandler`] configured via
+//! [`Config::LinkEventHandler`] whenever the state of a connection changes, so that applications
+//! don't have to poll the [`Connection`] for changes.
+//!
+//! [`Config::LinkEventHandler`]: crate::config::Config::LinkEventHandler
+//! [`Connection`]: super::Connection
+
+use crate::link::{llcp::DisconnectReason, ConnectionHandle, DeviceAddress};
+use crate::phy::Phy;
+use crate::time::Duration;
+
+/// A change in the state of a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkEvent {
+    /// A `CONNECT_IND` was accepted and a connection is being established.
+    Connected {
+        /// Handle identifying the connection in calls to the `LinkLayer`.
+        handle: ConnectionHandle,
+
+        /// Address of the master that initiated the connection.
+        peer: DeviceAddress,
+
+        /// Interval between connection events.
+        interval: Duration,
+
+        /// Number of consecutive connection events we may skip.
+        slave_latency: u16,
+
+        /// Time without a valid packet after which the connection is considered lost.
+        supervision_timeout: Duration,
+    },
+
+    /// The connection was closed, either by one of the devices or because it was lost.
+    ///
+    /// The per-connection state of the upper layers must be reset by calling
+    /// [`Responder::connection_closed`](crate::link::Responder::connection_closed).
+    Disconnected {
+        /// Why the connection ended.
+        reason: DisconnectReason,
+    },
+
+    /// New connection parameters, requested by the master, have taken effect.
+    ConnectionUpdated {
+        /// Interval between connection events.
+        interval: Duration,
+
+        /// Number of consecutive connection events we may skip.
+        slave_latency: u16,
+
+        /// Time without a valid packet after which the connection is considered lost.
+        supervision_timeout: Duration,
+    },
+
+    /// The connection is now encrypted.
+    ///
+    /// This is also reported when encryption is restarted with a new key. The security of the key
+    /// must be passed to
+    /// [`Responder::encryption_enabled`](crate::link::Responder::encryption_enabled), so that
+    /// attributes requiring encryption become accessible.
+    EncryptionEnabled,
+
+    /// A PHY update has taken effect, and packets are now sent and received on `phy`.
+    PhyUpdated {
+        /// The new PHY used in both directions.
+        phy: Phy,
+    },
+
+    /// The `ATT_MTU` was changed by an MTU exchange with the client.
+    ///
+    /// ATT runs on top of L2CAP, so this is reported by the [`Responder`] (see
+    /// [`Responder::set_event_handler`]) instead of the Link-Layer.
+    ///
+    /// [`Responder`]: crate::link::Responder
+    /// [`Responder::set_event_handler`]: crate::link::Responder::set_event_handler
+    MtuChanged {
+        /// The new `ATT_MTU` in Bytes.
+        mtu: u16,
+    },
+}
+
+/// Trait for receivers of [`LinkEvent`]s.
+pub trait LinkEventHandler {
+    /// Called for every event reported by the Link-Layer.
+    ///
+    /// This is called from `LinkLayer` methods, which usually run in the radio and timer interrupt
+    /// handlers (and from `Responder::process_one`), so it must return quickly and must not block
+    /// (eg. it can set a flag or put the event into a queue processed by the application).
+    fn handle_event(&mut self, event: LinkEvent);
+}
+
+/// Ignores all events.
+///
+/// Applications not interested in events can use this as their [`Config::LinkEventHandler`].
+///
+/// [`Config::LinkEventHandler`]: crate::config::Config::LinkEventHandler
+impl LinkEventHandler for () {
+    fn handle_event(&mut self, _event: LinkEvent) {}
+}
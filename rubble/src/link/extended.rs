@@ -0,0 +1,1056 @@
+//! Extended advertising (Bluetooth 5).
+//!
+//! Bluetooth 5 adds advertising PDUs with larger payloads. A short `ADV_EXT_IND` PDU sent on the
+//! primary advertising channels points to an `AUX_ADV_IND` PDU that is sent on one of the 37
+//! secondary advertising channels (which share their channel indices with the data channels). An
+//! `AUX_ADV_IND` can in turn describe a periodic advertising train of `AUX_SYNC_IND` PDUs. All of
+//! these use `PduType::AdvExtInd` and share the *Common Extended Advertising Payload Format*:
+//!
+//! ```notrust
+//! +-----------------+--------------+---------+
+//! | ExtHeaderLength |  Extended    | AdvData |
+//! |    + AdvMode    |   Header     |         |
+//! |     (1 B)       | (0-63 B)     |         |
+//! +-----------------+--------------+---------+
+//! ```
+//!
+//! The extended header starts with a flags byte that determines which of the optional fields
+//! follow. The fields that are present are always sent in this order:
+//!
+//! ```notrust
+//! +-------+-------+---------+---------+-------+--------+----------+---------+------+
+//! | Flags | AdvA  | TargetA | CTEInfo |  ADI  | AuxPtr | SyncInfo | TxPower | ACAD |
+//! | (1 B) | (6 B) |  (6 B)  |  (1 B)  | (2 B) | (3 B)  |  (18 B)  |  (1 B)  |      |
+//! +-------+-------+---------+---------+-------+--------+----------+---------+------+
+//! ```
+//!
+//! Rubble only sends non-connectable and non-scannable extended advertising PDUs, so `AdvMode` is
+//! always 0.
+//!
+//! [`LinkLayer::start_extended_advertising`] sends advertising data set with
+//! [`LinkLayer::set_extended_adv_data`] in `AUX_ADV_IND` PDUs, which allows much more data than the
+//! 31 Bytes of legacy advertising PDUs.
+//!
+//! [`LinkLayer::start_extended_advertising`]: super::LinkLayer::start_extended_advertising
+//! [`LinkLayer::set_extended_adv_data`]: super::LinkLayer::set_extended_adv_data
+
+use crate::link::advertising::{AdvChannels, AdvParams, Header, PduType, SleepClockAccuracy};
+use crate::link::advertising::{ACCESS_ADDRESS, CRC_PRESET};
+use crate::link::{ad_structure::AdStructure, channel_map::ChannelMap, DeviceAddress};
+use crate::link::{Cmd, NextUpdate, RadioCmd, TimeWindow, Transmitter};
+use crate::phy::{pdu_air_time, AdvertisingChannel, DataChannel, Phy};
+use crate::security::rng::Rng;
+use crate::time::{Duration, Instant};
+use crate::utils::HexSlice;
+use crate::{bytes::*, Error};
+use bitflags::bitflags;
+use core::fmt;
+
+/// Max. payload size of an extended advertising PDU in Bytes.
+pub const MAX_EXT_PAYLOAD_SIZE: usize = 255;
+
+/// Max. size of the data passed to [`LinkLayer::set_extended_adv_data`] in Bytes.
+///
+/// This is the PDU payload minus the `ExtHeaderLength` Byte. The extended header of the
+/// `AUX_ADV_IND` takes up part of the payload as well, so at most 245 Bytes of data can actually be
+/// sent along with `AdvA` and `ADI` (244 if `TxPower` is included).
+///
+/// [`LinkLayer::set_extended_adv_data`]: super::LinkLayer::set_extended_adv_data
+pub const MAX_EXT_ADV_DATA: usize = MAX_EXT_PAYLOAD_SIZE - 1;
+
+/// Max. size of the extended header in Bytes (limited by the 6-bit `ExtHeaderLength` field).
+const MAX_EXT_HEADER_SIZE: usize = 63;
+
+/// Largest offset that can be expressed in 30 µs units by `AuxPtr` and `SyncInfo`.
+const MAX_OFFSET_30US: u32 = 8191 * 30;
+
+/// Value added to the `SyncInfo` offset if its `Offset Adjust` bit is set.
+const OFFSET_ADJUST: u32 = 2_457_600;
+
+/// Time between the start of two PDUs of an extended advertising event.
+///
+/// This leaves more than the required 300 µs between the end of the last `ADV_EXT_IND` and the
+/// start of the `AUX_ADV_IND` it points to, and is a multiple of the 30 µs `AuxPtr` unit.
+pub(crate) const PDU_SPACING: Duration = Duration::micros(600);
+
+/// Returns the airtime of an advertising PDU with a `payload_len`-Byte payload on the LE 1M PHY.
+pub(crate) fn airtime(payload_len: usize) -> Duration {
+    pdu_air_time(payload_len as u8, Phy::Le1M)
+}
+
+bitflags! {
+    /// Flags in the extended header, indicating which optional fields are present.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ExtHeaderFlags: u8 {
+        /// Advertiser address.
+        const ADV_A = 1 << 0;
+        /// Address of the device the PDU is directed at.
+        const TARGET_A = 1 << 1;
+        /// Constant Tone Extension info.
+        const CTE_INFO = 1 << 2;
+        /// Advertising Data Info.
+        const ADI = 1 << 3;
+        /// Pointer to an auxiliary PDU.
+        const AUX_PTR = 1 << 4;
+        /// Synchronization info of a periodic advertising train.
+        const SYNC_INFO = 1 << 5;
+        /// Transmit power.
+        const TX_POWER = 1 << 6;
+    }
+}
+
+/// Encodes `offset` in 30 µs or 300 µs units, as used by `AuxPtr` and `SyncInfo`.
+///
+/// Returns the 13-bit offset value and whether 300 µs units are used. The offset is rounded down,
+/// since the receiver must start listening before the PDU is sent.
+fn encode_offset(offset: Duration) -> (u16, bool) {
+    let us = offset.to_micros();
+    if us <= MAX_OFFSET_30US {
+        ((us / 30) as u16, false)
+    } else {
+        ((us / 300) as u16, true)
+    }
+}
+
+/// Advertising Data Info (`ADI`).
+///
+/// Identifies the advertising set a PDU belongs to, and the version of the advertising data sent
+/// in that set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Adi {
+    did: u16,
+    sid: u8,
+}
+
+impl Adi {
+    /// Creates an ADI for advertising set `sid` with data ID `did`.
+    ///
+    /// Returns `Error::InvalidValue` if `sid` doesn't fit in 4 bits or `did` doesn't fit in 12
+    /// bits.
+    pub fn new(sid: u8, did: u16) -> Result<Self, Error> {
+        if sid > 0xF || did > 0xFFF {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self { did, sid })
+    }
+
+    /// Returns the Advertising Set ID (`SID`).
+    pub fn sid(&self) -> u8 {
+        self.sid
+    }
+
+    /// Returns the Advertising Data ID (`DID`).
+    ///
+    /// This changes whenever the advertising data of the set changes, so that scanners can
+    /// filter out duplicates.
+    pub fn did(&self) -> u16 {
+        self.did
+    }
+
+    /// Returns the ADI of the same set with the next `DID`.
+    fn next(self) -> Self {
+        Self {
+            did: (self.did + 1) & 0xFFF,
+            ..self
+        }
+    }
+}
+
+impl ToBytes for Adi {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.did | u16::from(self.sid) << 12)
+    }
+}
+
+/// Pointer to an auxiliary PDU on a secondary advertising channel (`AuxPtr`).
+///
+/// The auxiliary PDU is always sent on the LE 1M PHY.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AuxPtr {
+    channel: DataChannel,
+    offset: Duration,
+    accurate_clock: bool,
+}
+
+impl AuxPtr {
+    /// Largest offset an `AuxPtr` can point to.
+    pub const MAX_OFFSET: Duration = Duration::micros(8191 * 300);
+
+    /// Creates a pointer to an auxiliary PDU sent on `channel`, starting `offset` after the start
+    /// of the PDU containing the pointer.
+    ///
+    /// `sca_ppm` is the accuracy of the clock used to time the auxiliary PDU.
+    ///
+    /// Returns `Error::InvalidValue` if `offset` exceeds [`MAX_OFFSET`](Self::MAX_OFFSET).
+    pub fn new(channel: DataChannel, offset: Duration, sca_ppm: u32) -> Result<Self, Error> {
+        if offset > Self::MAX_OFFSET {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            channel,
+            offset,
+            accurate_clock: sca_ppm <= 50,
+        })
+    }
+
+    /// Returns the channel the auxiliary PDU is sent on.
+    pub fn channel(&self) -> DataChannel {
+        self.channel
+    }
+
+    /// Returns the time between the start of the PDU containing the pointer and the start of the
+    /// auxiliary PDU.
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+}
+
+impl ToBytes for AuxPtr {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let (offset, units_300us) = encode_offset(self.offset);
+        writer.write_u8(
+            self.channel.index() | u8::from(self.accurate_clock) << 6 | u8::from(units_300us) << 7,
+        )?;
+        // The upper 3 bits select the PHY, which is always LE 1M (0)
+        writer.write_u16_le(offset)
+    }
+}
+
+/// Synchronization info describing a periodic advertising train (`SyncInfo`).
+///
+/// Sent in `AUX_ADV_IND` PDUs, this allows scanners to find the `AUX_SYNC_IND` PDUs of the train.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyncInfo {
+    offset: Duration,
+    interval: Duration,
+    channel_map: ChannelMap,
+    sca: SleepClockAccuracy,
+    access_address: u32,
+    crc_init: u32,
+    event_counter: u16,
+}
+
+impl SyncInfo {
+    /// Largest offset to the next `AUX_SYNC_IND` that can be expressed (~4.9 s).
+    pub const MAX_OFFSET: Duration = Duration::micros(8191 * 300 + OFFSET_ADJUST);
+
+    /// Creates the synchronization info of a periodic advertising train.
+    ///
+    /// # Parameters
+    ///
+    /// * **`offset`**: Time from the start of the PDU containing the `SyncInfo` to the start of
+    ///   the next `AUX_SYNC_IND`.
+    /// * **`interval`**: Time between two `AUX_SYNC_IND`s. Must be a multiple of 1.25 ms.
+    /// * **`channel_map`**: Secondary advertising channels used by the train.
+    /// * **`sca`**: Accuracy of the advertiser's sleep clock.
+    /// * **`access_address`**: Access Address of the `AUX_SYNC_IND` PDUs.
+    /// * **`crc_init`**: CRC initialization value of the `AUX_SYNC_IND` PDUs (24 bits).
+    /// * **`event_counter`**: Event counter of the `AUX_SYNC_IND` that `offset` points to.
+    ///
+    /// Returns `Error::InvalidValue` if `offset` exceeds [`MAX_OFFSET`](Self::MAX_OFFSET), or
+    /// `interval` can't be represented.
+    pub fn new(
+        offset: Duration,
+        interval: Duration,
+        channel_map: ChannelMap,
+        sca: SleepClockAccuracy,
+        access_address: u32,
+        crc_init: u32,
+        event_counter: u16,
+    ) -> Result<Self, Error> {
+        let interval_units = interval.to_micros() / 1250;
+        if offset > Self::MAX_OFFSET
+            || interval.to_micros() % 1250 != 0
+            || interval_units == 0
+            || interval_units > u32::from(u16::MAX)
+        {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            offset,
+            interval,
+            channel_map,
+            sca,
+            access_address,
+            crc_init: crc_init & 0xFF_FFFF,
+            event_counter,
+        })
+    }
+
+    /// Returns the time from the start of the PDU containing this `SyncInfo` to the next
+    /// `AUX_SYNC_IND`.
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// Returns the interval of the periodic advertising train.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the Access Address of the periodic advertising train.
+    pub fn access_address(&self) -> u32 {
+        self.access_address
+    }
+
+    /// Returns the event counter of the next `AUX_SYNC_IND`.
+    pub fn event_counter(&self) -> u16 {
+        self.event_counter
+    }
+}
+
+impl ToBytes for SyncInfo {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        // Offsets beyond the range of 300 µs units need the `Offset Adjust` bit, which adds
+        // 2.4576 s (and is only valid with 300 µs units)
+        let us = self.offset.to_micros();
+        let (offset, units_300us, adjust) = if us > 8191 * 300 {
+            (((us - OFFSET_ADJUST) / 300) as u16, true, true)
+        } else {
+            let (offset, units_300us) = encode_offset(self.offset);
+            (offset, units_300us, false)
+        };
+        writer.write_u16_le(offset | u16::from(units_300us) << 13 | u16::from(adjust) << 14)?;
+        writer.write_u16_le((self.interval.to_micros() / 1250) as u16)?;
+        let mut chm = self.channel_map.to_raw();
+        chm[4] |= self.sca.to_raw() << 5;
+        writer.write_slice(&chm)?;
+        writer.write_u32_le(self.access_address)?;
+        writer.write_u24_le(self.crc_init)?;
+        writer.write_u16_le(self.event_counter)
+    }
+}
+
+/// The extended header of an extended advertising PDU.
+///
+/// Only fields that are set are included in the PDU.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ExtHeader {
+    adv_a: Option<DeviceAddress>,
+    adi: Option<Adi>,
+    aux_ptr: Option<AuxPtr>,
+    sync_info: Option<SyncInfo>,
+    tx_power: Option<i8>,
+}
+
+impl ExtHeader {
+    /// Creates an empty extended header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Includes the advertiser address `AdvA`.
+    pub fn adv_a(mut self, addr: DeviceAddress) -> Self {
+        self.adv_a = Some(addr);
+        self
+    }
+
+    /// Includes the Advertising Data Info `ADI`.
+    pub fn adi(mut self, adi: Adi) -> Self {
+        self.adi = Some(adi);
+        self
+    }
+
+    /// Includes a pointer to an auxiliary PDU.
+    pub fn aux_ptr(mut self, aux_ptr: AuxPtr) -> Self {
+        self.aux_ptr = Some(aux_ptr);
+        self
+    }
+
+    /// Includes the synchronization info of a periodic advertising train.
+    pub fn sync_info(mut self, sync_info: SyncInfo) -> Self {
+        self.sync_info = Some(sync_info);
+        self
+    }
+
+    /// Includes the transmit power `TxPower` in dBm.
+    pub fn tx_power(mut self, dbm: i8) -> Self {
+        self.tx_power = Some(dbm);
+        self
+    }
+
+    /// Returns the flags indicating which fields are present.
+    pub fn flags(&self) -> ExtHeaderFlags {
+        let mut flags = ExtHeaderFlags::empty();
+        flags.set(ExtHeaderFlags::ADV_A, self.adv_a.is_some());
+        flags.set(ExtHeaderFlags::ADI, self.adi.is_some());
+        flags.set(ExtHeaderFlags::AUX_PTR, self.aux_ptr.is_some());
+        flags.set(ExtHeaderFlags::SYNC_INFO, self.sync_info.is_some());
+        flags.set(ExtHeaderFlags::TX_POWER, self.tx_power.is_some());
+        flags
+    }
+
+    /// Returns the size of the extended header (the value of `ExtHeaderLength`).
+    ///
+    /// A header without any fields is omitted entirely, including the flags byte.
+    pub fn len(&self) -> usize {
+        let flags = self.flags();
+        if flags.is_empty() {
+            return 0;
+        }
+
+        let fields = [
+            (ExtHeaderFlags::ADV_A, 6),
+            (ExtHeaderFlags::ADI, 2),
+            (ExtHeaderFlags::AUX_PTR, 3),
+            (ExtHeaderFlags::SYNC_INFO, 18),
+            (ExtHeaderFlags::TX_POWER, 1),
+        ];
+        1 + fields
+            .iter()
+            .filter(|(flag, _)| flags.contains(*flag))
+            .map(|(_, len)| len)
+            .sum::<usize>()
+    }
+
+    /// Returns whether the extended header is empty (and thus omitted).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ToBytes for ExtHeader {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        // `AdvMode` (upper 2 bits) is 0: neither connectable nor scannable
+        writer.write_u8(self.len() as u8)?;
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        writer.write_u8(self.flags().bits())?;
+        if let Some(addr) = &self.adv_a {
+            writer.write_slice(addr.raw())?;
+        }
+        if let Some(adi) = &self.adi {
+            adi.to_bytes(writer)?;
+        }
+        if let Some(aux_ptr) = &self.aux_ptr {
+            aux_ptr.to_bytes(writer)?;
+        }
+        if let Some(sync_info) = &self.sync_info {
+            sync_info.to_bytes(writer)?;
+        }
+        if let Some(dbm) = self.tx_power {
+            writer.write_u8(dbm as u8)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stores an extended advertising PDU.
+///
+/// This is the extended counterpart of [`PduBuf`](super::advertising::PduBuf).
+pub struct ExtPduBuf {
+    /// 2-Byte header.
+    header: Header,
+    /// Fixed-size buffer that can store the largest PDU. Actual length is stored in the header.
+    payload_buf: [u8; MAX_EXT_PAYLOAD_SIZE],
+}
+
+impl ExtPduBuf {
+    /// Builds an extended advertising PDU consisting of `ext_header` followed by `adv_data`.
+    ///
+    /// If the header contains `AdvA`, the `TxAdd` bit is set according to its address kind.
+    pub fn new(ext_header: &ExtHeader, adv_data: &[AdStructure<'_>]) -> Result<Self, Error> {
+        Self::build(ext_header, |buf| {
+            for ad in adv_data {
+                ad.to_bytes(buf)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Builds an extended advertising PDU consisting of `ext_header` followed by the already
+    /// encoded advertising data `adv_data`.
+    pub fn with_raw_data(ext_header: &ExtHeader, adv_data: &[u8]) -> Result<Self, Error> {
+        Self::build(ext_header, |buf| buf.write_slice(adv_data))
+    }
+
+    fn build(
+        ext_header: &ExtHeader,
+        write_data: impl FnOnce(&mut ByteWriter<'_>) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        debug_assert!(ext_header.len() <= MAX_EXT_HEADER_SIZE);
+
+        let mut payload = [0; MAX_EXT_PAYLOAD_SIZE];
+        let mut buf = ByteWriter::new(&mut payload[..]);
+        ext_header.to_bytes(&mut buf)?;
+        write_data(&mut buf)?;
+
+        let used = MAX_EXT_PAYLOAD_SIZE - buf.space_left();
+        let mut header = Header::new(PduType::AdvExtInd);
+        header.set_payload_length(used as u8);
+        header.set_tx_add(matches!(ext_header.adv_a, Some(addr) if addr.is_random()));
+        Ok(Self {
+            header,
+            payload_buf: payload,
+        })
+    }
+
+    /// Returns the PDU header.
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Returns the payload, consisting of the extended header and the advertising data.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload_buf[..usize::from(self.header.payload_length())]
+    }
+}
+
+impl fmt::Debug for ExtPduBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtPduBuf")
+            .field("header", &self.header)
+            .field("payload", &HexSlice(self.payload()))
+            .finish()
+    }
+}
+
+/// Parameters for extended advertising, passed to [`LinkLayer::start_extended_advertising`].
+///
+/// [`LinkLayer::start_extended_advertising`]: super::LinkLayer::start_extended_advertising
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExtAdvParams {
+    /// Time between the start of two extended advertising events.
+    ///
+    /// Must lie between [`AdvParams::MIN_INTERVAL`] and [`AdvParams::MAX_INTERVAL`]. Like for
+    /// legacy advertising, a pseudo-random delay of up to
+    /// [`MAX_ADV_DELAY`](super::advertising::MAX_ADV_DELAY) is added to every interval.
+    pub interval: Duration,
+
+    /// The primary channels to send the `ADV_EXT_IND` PDU on during each event. Must not be empty.
+    pub channels: AdvChannels,
+
+    /// Advertising Set ID, which identifies the advertising set to scanners. Must not exceed 15.
+    pub sid: u8,
+
+    /// Transmit power in dBm to include in the `AUX_ADV_IND` PDU, if any.
+    ///
+    /// Defaults to `None`.
+    pub tx_power: Option<i8>,
+}
+
+impl ExtAdvParams {
+    /// Creates parameters for extended advertising on all primary channels every `interval`, using
+    /// Advertising Set ID 0.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            channels: AdvChannels::all(),
+            sid: 0,
+            tx_power: None,
+        }
+    }
+
+    /// Checks that the parameters are within the allowed ranges.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let interval_ok =
+            (AdvParams::MIN_INTERVAL..=AdvParams::MAX_INTERVAL).contains(&self.interval);
+        if interval_ok && !self.channels.is_empty() && self.sid <= 0xF {
+            Ok(())
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+}
+
+/// Schedules the extended advertising events of an advertising set.
+///
+/// Every event consists of an `ADV_EXT_IND` on each selected primary channel, each pointing to the
+/// `AUX_ADV_IND` that follows on a random secondary channel.
+pub(crate) struct ExtAdvEvents {
+    interval: Duration,
+    channels: AdvChannels,
+    sca_ppm: u32,
+    adi: Adi,
+
+    /// Start of the current (or next) event.
+    event_start: Instant,
+
+    /// Number of PDUs already sent in the current event.
+    sent: u8,
+
+    /// Secondary channel of the `AUX_ADV_IND` in the current event.
+    aux_channel: DataChannel,
+}
+
+impl ExtAdvEvents {
+    /// Schedules events every `interval` on `channels`, starting at `start`.
+    ///
+    /// `sca_ppm` is the accuracy of the sleep clock used to time the `AUX_ADV_IND`s.
+    pub fn new<R: Rng + ?Sized>(
+        interval: Duration,
+        channels: AdvChannels,
+        sid: u8,
+        sca_ppm: u32,
+        start: Instant,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            interval,
+            channels,
+            sca_ppm,
+            adi: Adi::new(sid, (rng.next_u32() & 0xFFF) as u16)?,
+            event_start: start,
+            sent: 0,
+            aux_channel: random_channel(rng),
+        })
+    }
+
+    /// Returns the ADI to include in the `AUX_ADV_IND`.
+    pub fn adi(&self) -> Adi {
+        self.adi
+    }
+
+    /// Changes the `DID`, telling scanners that the advertising data has changed.
+    pub fn renew_did(&mut self) {
+        self.adi = self.adi.next();
+    }
+
+    /// Returns when the next PDU is due.
+    pub fn next_pdu_time(&self) -> Instant {
+        self.event_start + PDU_SPACING * u32::from(self.sent)
+    }
+
+    /// Returns the start of the next event, or `None` if the current event isn't over yet.
+    pub fn next_event_start(&self) -> Option<Instant> {
+        if self.sent == 0 {
+            Some(self.event_start)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the next event to `start`.
+    ///
+    /// Must only be called between events (see `next_event_start`).
+    pub fn postpone(&mut self, start: Instant) {
+        debug_assert_eq!(self.sent, 0);
+        self.event_start = start;
+    }
+
+    /// Returns the duration of an event whose `AUX_ADV_IND` has an `aux_len`-Byte payload.
+    pub fn event_len(&self, aux_len: usize) -> Duration {
+        PDU_SPACING * self.channels.bits().count_ones() + airtime(aux_len)
+    }
+
+    /// Sends the next PDU of the current event.
+    ///
+    /// If that is the `AUX_ADV_IND`, it is built by calling `aux_pdu` with the time it is sent at,
+    /// and the next event is scheduled. Returns the window occupied by the transmission.
+    pub fn send_next<T: Transmitter, R: Rng + ?Sized>(
+        &mut self,
+        tx: &mut T,
+        rng: &mut R,
+        aux_pdu: impl FnOnce(Instant) -> ExtPduBuf,
+    ) -> TimeWindow {
+        let pdu_time = self.next_pdu_time();
+        let primary = AdvertisingChannel::iter_all()
+            .filter(|ch| self.channels.includes(*ch))
+            .nth(usize::from(self.sent));
+
+        let len = if let Some(channel) = primary {
+            let remaining = self.channels.bits().count_ones() - u32::from(self.sent);
+            let aux_ptr = AuxPtr::new(self.aux_channel, PDU_SPACING * remaining, self.sca_ppm);
+            let header = ExtHeader::new().adi(self.adi).aux_ptr(aux_ptr.unwrap());
+            let pdu = ExtPduBuf::new(&header, &[]).unwrap();
+
+            let payload = pdu.payload();
+            tx.tx_payload_buf()[..payload.len()].copy_from_slice(payload);
+            tx.transmit_advertising(pdu.header(), channel);
+            self.sent += 1;
+            payload.len()
+        } else {
+            let pdu = aux_pdu(pdu_time);
+
+            let payload = pdu.payload();
+            tx.tx_payload_buf()[..payload.len()].copy_from_slice(payload);
+            tx.transmit_secondary(ACCESS_ADDRESS, CRC_PRESET, pdu.header(), self.aux_channel);
+            self.sent = 0;
+            self.event_start += self.interval + super::adv_delay(rng);
+            self.aux_channel = random_channel(rng);
+            payload.len()
+        };
+
+        TimeWindow::new(pdu_time, airtime(len))
+    }
+}
+
+/// Picks a random secondary advertising channel.
+pub(crate) fn random_channel<R: Rng + ?Sized>(rng: &mut R) -> DataChannel {
+    DataChannel::new((rng.next_u32() % 37) as u8)
+}
+
+/// State of a device sending extended advertising events.
+pub(crate) struct ExtAdvertiser {
+    events: ExtAdvEvents,
+    adv_addr: DeviceAddress,
+    tx_power: Option<i8>,
+
+    /// Size of the transmitter's payload buffer.
+    tx_capacity: usize,
+
+    /// Advertising data set during an event, which is sent starting with the next event.
+    pending_data: Option<heapless::Vec<u8, MAX_EXT_ADV_DATA>>,
+}
+
+impl ExtAdvertiser {
+    /// Starts extended advertising at `now`.
+    ///
+    /// `tx_capacity` is the size of the transmitter's payload buffer, which limits the amount of
+    /// advertising data.
+    pub fn new<R: Rng + ?Sized>(
+        params: ExtAdvParams,
+        adv_addr: DeviceAddress,
+        sca_ppm: u32,
+        tx_capacity: usize,
+        now: Instant,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        params.validate()?;
+        let events = ExtAdvEvents::new(
+            params.interval,
+            params.channels,
+            params.sid,
+            sca_ppm,
+            now,
+            rng,
+        )?;
+        Ok(Self {
+            events,
+            adv_addr,
+            tx_power: params.tx_power,
+            tx_capacity,
+            pending_data: None,
+        })
+    }
+
+    /// Builds the `AUX_ADV_IND` PDU carrying `data`.
+    ///
+    /// Returns `Error::Eof` if `data` doesn't fit in the PDU or the transmitter's buffer.
+    pub fn aux_pdu(&self, data: &[u8]) -> Result<ExtPduBuf, Error> {
+        let pdu = aux_adv_ind(self.adv_addr, self.events.adi(), self.tx_power, data)?;
+        if pdu.payload().len() > self.tx_capacity {
+            return Err(Error::Eof);
+        }
+        Ok(pdu)
+    }
+
+    /// Replaces the advertising data, starting with the next advertising event.
+    ///
+    /// The `ADV_EXT_IND`s of an event already announce the `DID` of its `AUX_ADV_IND`, so both the
+    /// data and the `DID` only change once the current event is over. `data` must have been checked
+    /// with `aux_pdu`.
+    pub fn data_changed(&mut self, data: heapless::Vec<u8, MAX_EXT_ADV_DATA>) {
+        self.pending_data = Some(data);
+    }
+
+    /// Returns the data passed to `data_changed` that hasn't been sent yet.
+    pub fn take_pending_data(&mut self) -> Option<heapless::Vec<u8, MAX_EXT_ADV_DATA>> {
+        self.pending_data.take()
+    }
+
+    /// Sends the next due PDU and returns the `Cmd` to apply afterwards.
+    ///
+    /// `data` must have been checked with `aux_pdu`. It is replaced by the data passed to
+    /// `data_changed` when a new event starts.
+    pub fn update<T: Transmitter, R: Rng + ?Sized>(
+        &mut self,
+        tx: &mut T,
+        rng: &mut R,
+        data: &mut heapless::Vec<u8, MAX_EXT_ADV_DATA>,
+    ) -> Cmd {
+        if self.events.next_event_start().is_some() {
+            if let Some(new_data) = self.pending_data.take() {
+                *data = new_data;
+                self.events.renew_did();
+            }
+        }
+
+        let (adv_addr, adi, tx_power) = (self.adv_addr, self.events.adi(), self.tx_power);
+        let window = self.events.send_next(tx, rng, |_| {
+            aux_adv_ind(adv_addr, adi, tx_power, data).unwrap()
+        });
+
+        Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::At(self.events.next_pdu_time()),
+            window: Some(window),
+            queued_work: false,
+        }
+    }
+}
+
+/// Builds an `AUX_ADV_IND` PDU sent by `adv_addr`, carrying `data`.
+fn aux_adv_ind(
+    adv_addr: DeviceAddress,
+    adi: Adi,
+    tx_power: Option<i8>,
+    data: &[u8],
+) -> Result<ExtPduBuf, Error> {
+    let mut header = ExtHeader::new().adv_a(adv_addr).adi(adi);
+    if let Some(dbm) = tx_power {
+        header = header.tx_power(dbm);
+    }
+    ExtPduBuf::with_raw_data(&header, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::mock::{Device, MockChannel, MockPacket};
+    use crate::link::AddressKind;
+    use crate::security::rng::MockRng;
+
+    fn to_vec(value: &impl ToBytes) -> std::vec::Vec<u8> {
+        let mut buf = [0; MAX_EXT_PAYLOAD_SIZE];
+        let mut writer = ByteWriter::new(&mut buf);
+        value.to_bytes(&mut writer).unwrap();
+        let len = MAX_EXT_PAYLOAD_SIZE - writer.space_left();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn sync_info_packing() {
+        let map = ChannelMap::from_raw([0xFF, 0xFF, 0xFF, 0xFF, 0x1F]);
+        let info = SyncInfo::new(
+            Duration::micros(3_000),
+            Duration::millis(100),
+            map,
+            SleepClockAccuracy::Ppm31To50,
+            0x1234_5678,
+            0xABCDEF,
+            0x0102,
+        )
+        .unwrap();
+        assert_eq!(
+            to_vec(&info),
+            [
+                100, 0x00, // Offset 3 ms in 30 µs units
+                80, 0x00, // Interval 100 ms in 1.25 ms units
+                0xFF, 0xFF, 0xFF, 0xFF, 0xBF, // ChM, SCA 31-50 ppm (5)
+                0x78, 0x56, 0x34, 0x12, // AA
+                0xEF, 0xCD, 0xAB, // CRCInit
+                0x02, 0x01, // Event counter
+            ]
+        );
+
+        // 300 µs units are used above 245.73 ms
+        let info = SyncInfo::new(
+            Duration::millis(300),
+            Duration::millis(500),
+            map,
+            SleepClockAccuracy::Ppm251To500,
+            0x1234_5678,
+            0xABCDEF,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            &to_vec(&info)[..8],
+            [0xE8, 0x23, 0x90, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(to_vec(&info)[8], 0x1F); // SCA 251-500 ppm (0)
+
+        // The Offset Adjust bit adds 2.4576 s
+        let info = SyncInfo::new(
+            Duration::micros(2_457_600 + 3_000),
+            Duration::secs(4),
+            map,
+            SleepClockAccuracy::Ppm0To20,
+            0x1234_5678,
+            0xABCDEF,
+            0,
+        )
+        .unwrap();
+        assert_eq!(&to_vec(&info)[..4], [0x0A, 0x60, 0x80, 0x0C]);
+
+        assert_eq!(
+            SyncInfo::new(
+                SyncInfo::MAX_OFFSET + Duration::micros(1),
+                Duration::millis(100),
+                map,
+                SleepClockAccuracy::Ppm0To20,
+                0,
+                0,
+                0,
+            )
+            .err(),
+            Some(Error::InvalidValue)
+        );
+        assert_eq!(
+            SyncInfo::new(
+                Duration::millis(1),
+                Duration::micros(7_600),
+                map,
+                SleepClockAccuracy::Ppm0To20,
+                0,
+                0,
+                0,
+            )
+            .err(),
+            Some(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn ext_header_field_order() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let adi = Adi::new(3, 0x123).unwrap();
+        let aux_ptr = AuxPtr::new(DataChannel::new(9), Duration::micros(600), 50).unwrap();
+        let sync_info = SyncInfo::new(
+            Duration::micros(3_000),
+            Duration::millis(100),
+            ChannelMap::with_all_channels(),
+            SleepClockAccuracy::Ppm31To50,
+            0x1234_5678,
+            0xABCDEF,
+            0x0102,
+        )
+        .unwrap();
+
+        // Fields are sent in spec order, regardless of the order they were added in
+        let header = ExtHeader::new()
+            .tx_power(-10)
+            .sync_info(sync_info)
+            .aux_ptr(aux_ptr)
+            .adi(adi)
+            .adv_a(addr);
+        assert_eq!(header.len(), 1 + 6 + 2 + 3 + 18 + 1);
+        let bytes = to_vec(&header);
+        assert_eq!(bytes.len(), 1 + header.len());
+        assert_eq!(&bytes[..2], [31, 0b0111_1001]);
+        assert_eq!(&bytes[2..8], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(&bytes[8..10], [0x23, 0x31]);
+        // Channel 9, 0-50 ppm, 600 µs in 30 µs units
+        assert_eq!(&bytes[10..13], [9 | 1 << 6, 20, 0]);
+        assert_eq!(&bytes[13..31], &to_vec(&sync_info)[..]);
+        assert_eq!(bytes[31], -10i8 as u8);
+    }
+
+    #[test]
+    fn ext_header_field_presence() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let adi = Adi::new(3, 0x123).unwrap();
+        let aux_ptr = AuxPtr::new(DataChannel::new(9), Duration::micros(600), 50).unwrap();
+
+        // `ADV_EXT_IND`
+        let header = ExtHeader::new().adi(adi).aux_ptr(aux_ptr);
+        assert_eq!(
+            header.flags(),
+            ExtHeaderFlags::ADI | ExtHeaderFlags::AUX_PTR
+        );
+        assert_eq!(
+            to_vec(&header),
+            [6, 0b0001_1000, 0x23, 0x31, 9 | 1 << 6, 20, 0]
+        );
+
+        // `AUX_ADV_IND`
+        let header = ExtHeader::new().adv_a(addr).adi(adi).tx_power(4);
+        assert_eq!(
+            header.flags(),
+            ExtHeaderFlags::ADV_A | ExtHeaderFlags::ADI | ExtHeaderFlags::TX_POWER
+        );
+        let pdu = ExtPduBuf::with_raw_data(&header, &[2, 0x09, b'a']).unwrap();
+        assert_eq!(pdu.header().type_(), PduType::AdvExtInd);
+        assert!(pdu.header().tx_add());
+        assert_eq!(&pdu.payload()[..2], [10, 0b0100_1001]);
+        assert_eq!(&pdu.payload()[2..8], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(&pdu.payload()[8..], [0x23, 0x31, 4, 2, 0x09, b'a']);
+
+        // An empty extended header is omitted, including the flags
+        assert!(ExtHeader::new().is_empty());
+        let pdu = ExtPduBuf::new(&ExtHeader::new(), &[AdStructure::CompleteLocalName("a")]);
+        assert_eq!(pdu.unwrap().payload(), [0, 2, 0x09, b'a']);
+    }
+
+    #[test]
+    fn extended_advertising() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        let mut params = ExtAdvParams::new(Duration::millis(100));
+        params.channels = AdvChannels::CH37 | AdvChannels::CH39;
+        params.tx_power = Some(0);
+        let mut rng = MockRng::default();
+        let now = Instant::from_ticks(0);
+        let mut adv = ExtAdvertiser::new(params, addr, 50, 255, now, &mut rng).unwrap();
+        let mut channel = MockChannel::new();
+
+        // Data fills the rest of the PDU after `AdvA`, `ADI` and `TxPower`
+        assert!(adv.aux_pdu(&[0; 244]).is_ok());
+        assert_eq!(adv.aux_pdu(&[0; 245]).err(), Some(Error::Eof));
+
+        let mut data = heapless::Vec::from_slice(&[2, 0x09, b'a']).unwrap();
+        let mut times = std::vec::Vec::new();
+        for _ in 0..3 {
+            let cmd = adv.update(channel.radio(Device::A), &mut rng, &mut data);
+            times.push(cmd.window.unwrap().start);
+        }
+        assert_eq!(times, [now, now + PDU_SPACING, now + PDU_SPACING * 2]);
+
+        let mut pointers = std::vec::Vec::new();
+        for ch in [37, 39] {
+            match channel.receive(Device::B) {
+                Some(MockPacket::Advertising {
+                    payload, channel, ..
+                }) => {
+                    assert_eq!(channel.channel(), ch);
+                    pointers.push((payload[4] & 0x3F, payload[5]));
+                }
+                packet => panic!("expected ADV_EXT_IND, got {:?}", packet),
+            }
+        }
+        let did = adv.events.adi().did();
+        match channel.receive(Device::B) {
+            Some(MockPacket::Secondary {
+                access_address,
+                payload,
+                channel,
+                ..
+            }) => {
+                assert_eq!(access_address, ACCESS_ADDRESS);
+                // Both `ADV_EXT_IND`s point at this PDU (1200 and 600 µs ahead)
+                assert_eq!(pointers, [(channel.index(), 40), (channel.index(), 20)]);
+                assert_eq!(payload[1], 0b0100_1001);
+                assert_eq!(&payload[8..10], did.to_le_bytes());
+                assert_eq!(&payload[10..], [0, 2, 0x09, b'a']);
+            }
+            packet => panic!("expected AUX_ADV_IND, got {:?}", packet),
+        }
+
+        // Data changed during an event is sent with a new `DID`, starting with the next event
+        let receive_event = |channel: &mut MockChannel| {
+            let mut dids = std::vec::Vec::new();
+            for _ in 0..2 {
+                match channel.receive(Device::B) {
+                    Some(MockPacket::Advertising { payload, .. }) => {
+                        dids.push(u16::from_le_bytes([payload[2], payload[3]]));
+                    }
+                    packet => panic!("expected ADV_EXT_IND, got {:?}", packet),
+                }
+            }
+            match channel.receive(Device::B) {
+                Some(MockPacket::Secondary { payload, .. }) => {
+                    dids.push(u16::from_le_bytes([payload[8], payload[9]]));
+                    (dids, payload[10..].to_vec())
+                }
+                packet => panic!("expected AUX_ADV_IND, got {:?}", packet),
+            }
+        };
+
+        let _ = adv.update(channel.radio(Device::A), &mut rng, &mut data);
+        adv.data_changed(heapless::Vec::from_slice(&[2, 0x09, b'b']).unwrap());
+        let _ = adv.update(channel.radio(Device::A), &mut rng, &mut data);
+        let _ = adv.update(channel.radio(Device::A), &mut rng, &mut data);
+        let (dids, payload) = receive_event(&mut channel);
+        assert_eq!(dids, [did; 3]);
+        assert_eq!(payload, [0, 2, 0x09, b'a']);
+
+        for _ in 0..3 {
+            let _ = adv.update(channel.radio(Device::A), &mut rng, &mut data);
+        }
+        let (dids, payload) = receive_event(&mut channel);
+        assert_eq!(dids, [(did + 1) & 0xFFF; 3]);
+        assert_eq!(payload, [0, 2, 0x09, b'b']);
+    }
+}
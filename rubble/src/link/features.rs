@@ -62,13 +62,45 @@ bitflags! {
 
         /// Extended scan filter policies.
         const EXT_SCANNER_FILTER_POLICIES = 1 << 7;
+
+        /// Support for the LE 2M PHY.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_PHY_REQ`, `LL_PHY_RSP`,
+        ///   `LL_PHY_UPDATE_IND`.
+        /// * The *PHY Update Procedure*
+        const LE_2M_PHY = 1 << 8;
+
+        /// Support for the LE Coded PHY.
+        ///
+        /// Like `LE_2M_PHY`, this requires support for the *PHY Update Procedure*.
+        const LE_CODED_PHY = 1 << 11;
+
+        /// Minimum Number of Used Channels Procedure.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_MIN_USED_CHANNELS_IND`.
+        /// * The *Minimum Number of Used Channels Procedure*
+        const MIN_USED_CHANNELS = 1 << 14;
     }
 }
 
 impl FeatureSet {
     /// Returns the feature set supported by Rubble.
+    ///
+    /// Radios that can't use the LE 2M or LE Coded PHY remove `LE_2M_PHY` or `LE_CODED_PHY` from
+    /// this set (see [`Transmitter::supported_features`]).
+    ///
+    /// `LE_PACKET_LENGTH_EXTENSION` is not included yet: Received PDUs are stored in buffers of
+    /// `MIN_DATA_PAYLOAD_BUF` Bytes, so the Data Length Update procedure could never announce more
+    /// than the default of 27 Bytes.
+    ///
+    /// [`Transmitter::supported_features`]: crate::link::Transmitter::supported_features
     pub fn supported() -> Self {
-        FeatureSet::empty()
+        FeatureSet::LE_ENCRYPTION
+            | FeatureSet::LE_2M_PHY
+            | FeatureSet::LE_CODED_PHY
+            | FeatureSet::MIN_USED_CHANNELS
     }
 }
 
@@ -1,7 +1,7 @@
 //! Defines packet structures used by the Link Layer Control Protocol.
 
 use crate::link::{channel_map::ChannelMap, comp_id::CompanyId, features::FeatureSet};
-use crate::{bytes::*, time::Duration, utils::Hex, Error};
+use crate::{bytes::*, phy::PhySet, time::Duration, utils::Hex, Error};
 use core::{cmp, convert::TryInto};
 
 /// A connection parameter update request or response (`LL_CONNECTION_PARAM_REQ`/
@@ -66,6 +66,22 @@ impl ConnectionParamRequest {
         self.interval_max = max as u16;
     }
 
+    /// Sets the requested slave latency in number of connection events.
+    ///
+    /// Values above 499 will be clamped to 499.
+    pub fn set_slave_latency(&mut self, latency: u16) {
+        self.slave_latency = cmp::min(latency, 499);
+    }
+
+    /// Sets the requested supervision timeout.
+    ///
+    /// `timeout` must be in range 100ms to 32s, or it will be constrained to lie in that range. It
+    /// will be rounded down to units of 10 ms.
+    pub fn set_supervision_timeout(&mut self, timeout: Duration) {
+        let timeout = timeout.to_millis() / 10;
+        self.supervision_timeout = timeout.clamp(10, 3200) as u16;
+    }
+
     /// Returns the minimum requested connection interval.
     pub fn min_conn_interval(&self) -> Duration {
         Duration::micros(u32::from(self.interval_min) * 1_250)
@@ -85,6 +101,21 @@ impl ConnectionParamRequest {
     pub fn supervision_timeout(&self) -> Duration {
         Duration::millis(self.supervision_timeout as u32 * 10)
     }
+
+    /// Returns whether a connection using the given parameters satisfies this request.
+    ///
+    /// This is the case if `interval` lies within the requested range, and `latency` and `timeout`
+    /// match the requested values.
+    pub fn is_satisfied_by(&self, interval: Duration, latency: u16, timeout: Duration) -> bool {
+        (self.min_conn_interval()..=self.max_conn_interval()).contains(&interval)
+            && latency == self.slave_latency
+            && timeout == self.supervision_timeout()
+    }
+
+    /// Sets the connection event counter the (unused) offsets are relative to.
+    pub(crate) fn set_reference_conn_event_count(&mut self, count: u16) {
+        self.reference_conn_event_count = count;
+    }
 }
 
 impl<'a> FromBytes<'a> for ConnectionParamRequest {
@@ -168,6 +199,99 @@ impl ConnectionUpdateData {
     }
 }
 
+/// Data transmitted with an `LL_ENC_REQ` Control PDU.
+#[derive(Debug, Copy, Clone)]
+pub struct EncryptionRequest {
+    /// Random number identifying the Long-Term Key (0 when using an STK).
+    pub rand: Hex<u64>,
+    /// Encrypted diversifier identifying the Long-Term Key (0 when using an STK).
+    pub ediv: Hex<u16>,
+    /// Master's part of the session key diversifier.
+    pub skd_m: Hex<u64>,
+    /// Master's part of the initialization vector.
+    pub iv_m: Hex<u32>,
+}
+
+impl<'a> FromBytes<'a> for EncryptionRequest {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            rand: Hex(bytes.read_u64_le()?),
+            ediv: Hex(bytes.read_u16_le()?),
+            skd_m: Hex(bytes.read_u64_le()?),
+            iv_m: Hex(bytes.read_u32_le()?),
+        })
+    }
+}
+
+impl ToBytes for EncryptionRequest {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u64_le(self.rand.0)?;
+        writer.write_u16_le(self.ediv.0)?;
+        writer.write_u64_le(self.skd_m.0)?;
+        writer.write_u32_le(self.iv_m.0)?;
+        Ok(())
+    }
+}
+
+/// Minimum number of channels the slave asks the master to use on some PHYs
+/// (`LL_MIN_USED_CHANNELS_IND`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MinUsedChannels {
+    phys: PhySet,
+    min_used_channels: u8,
+}
+
+impl MinUsedChannels {
+    /// Creates a request for at least `min_used_channels` data channels to be used on `phys`.
+    ///
+    /// Returns `Error::InvalidValue` if `phys` is empty or `min_used_channels` is not in range
+    /// `2..=37`.
+    pub fn new(phys: PhySet, min_used_channels: u8) -> Result<Self, Error> {
+        if phys.is_empty() || !(2..=37).contains(&min_used_channels) {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            phys,
+            min_used_channels,
+        })
+    }
+
+    /// Returns the PHYs on which the minimum applies.
+    pub fn phys(&self) -> PhySet {
+        self.phys
+    }
+
+    /// Returns the minimum number of data channels to use.
+    pub fn min_used_channels(&self) -> u8 {
+        self.min_used_channels
+    }
+
+    /// Returns whether `map` uses enough channels when the connection is on any of `phys`.
+    ///
+    /// A master that selects a channel map for the connection must only pick maps satisfying this.
+    pub fn is_satisfied_by(&self, map: &ChannelMap, phys: PhySet) -> bool {
+        !self.phys.intersects(phys) || map.num_used_channels() >= self.min_used_channels
+    }
+}
+
+impl<'a> FromBytes<'a> for MinUsedChannels {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            phys: PhySet::from_bits_truncate(bytes.read_u8()?),
+            min_used_channels: bytes.read_u8()?,
+        })
+    }
+}
+
+impl ToBytes for MinUsedChannels {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.phys.bits())?;
+        writer.write_u8(self.min_used_channels)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, zerocopy::FromBytes, zerocopy::Unaligned)]
 #[repr(packed)]
 pub struct ChannelMapReq {
@@ -192,9 +316,30 @@ pub enum ControlPdu<'a> {
     ///
     /// Can be sent by master or slave.
     TerminateInd {
-        error_code: Hex<u8>,
+        error_code: DisconnectReason,
+    },
+
+    /// `0x03`/`LL_ENC_REQ` - Master requests encryption of the connection.
+    ///
+    /// The slave responds with `LL_ENC_RSP` and then starts encryption by sending
+    /// `LL_START_ENC_REQ`, or rejects the request with `LL_REJECT_IND`.
+    EncReq(EncryptionRequest),
+
+    /// `0x04`/`LL_ENC_RSP` - Slave's session key diversifier and IV contributions.
+    EncRsp {
+        /// Slave's part of the session key diversifier.
+        skd_s: Hex<u64>,
+        /// Slave's part of the initialization vector.
+        iv_s: Hex<u32>,
     },
 
+    /// `0x05`/`LL_START_ENC_REQ` - Sent unencrypted by the slave to start encryption.
+    StartEncReq,
+
+    /// `0x06`/`LL_START_ENC_RSP` - Sent encrypted by both master and slave to finish the
+    /// encryption start procedure.
+    StartEncRsp,
+
     /// `0x07`/`LL_UNKNOWN_RSP` - Response to unknown/unsupported LL Control PDUs.
     ///
     /// This is returned as a response to an incoming LL Control PDU when the opcode is
@@ -227,9 +372,85 @@ pub enum ControlPdu<'a> {
         sub_vers_nr: Hex<u16>,
     },
 
+    /// `0x0D`/`LL_REJECT_IND` - Rejects a request made by the other device.
+    RejectInd {
+        /// The reason for the rejection.
+        error_code: Hex<u8>,
+    },
+
     ConnectionParamReq(ConnectionParamRequest),
     ConnectionParamRsp(ConnectionParamRequest),
 
+    /// `0x11`/`LL_REJECT_IND_EXT` - Rejects a request made by the other device, naming the opcode
+    /// of the rejected PDU.
+    RejectIndExt {
+        /// Opcode of the rejected LL Control PDU.
+        reject_opcode: ControlOpcode,
+        /// The reason for the rejection.
+        error_code: Hex<u8>,
+    },
+
+    /// `0x14`/`LL_LENGTH_REQ` - Starts the Data Length Update Procedure by announcing the
+    /// sender's maximum data channel PDU sizes.
+    LengthReq {
+        /// Max. payload size (excluding the MIC) of data channel PDUs the sender can receive.
+        max_rx_octets: u16,
+        /// Max. time (in µs) the sender will spend receiving a data channel PDU.
+        max_rx_time: u16,
+        /// Max. payload size (excluding the MIC) of data channel PDUs the sender will transmit.
+        max_tx_octets: u16,
+        /// Max. time (in µs) the sender will spend transmitting a data channel PDU.
+        max_tx_time: u16,
+    },
+
+    /// `0x15`/`LL_LENGTH_RSP` - Answers `LL_LENGTH_REQ` with the responder's maximum data channel
+    /// PDU sizes.
+    LengthRsp {
+        /// Max. payload size (excluding the MIC) of data channel PDUs the sender can receive.
+        max_rx_octets: u16,
+        /// Max. time (in µs) the sender will spend receiving a data channel PDU.
+        max_rx_time: u16,
+        /// Max. payload size (excluding the MIC) of data channel PDUs the sender will transmit.
+        max_tx_octets: u16,
+        /// Max. time (in µs) the sender will spend transmitting a data channel PDU.
+        max_tx_time: u16,
+    },
+
+    /// `0x16`/`LL_PHY_REQ` - Requests a change of the PHYs used in the connection.
+    ///
+    /// Can be sent by master or slave. The master answers with `LL_PHY_UPDATE_IND`, the slave with
+    /// `LL_PHY_RSP`.
+    PhyReq {
+        /// PHYs the sender prefers to transmit on.
+        tx_phys: PhySet,
+        /// PHYs the sender prefers to receive on.
+        rx_phys: PhySet,
+    },
+
+    /// `0x17`/`LL_PHY_RSP` - Slave's preferred PHYs, sent in response to `LL_PHY_REQ`.
+    PhyRsp {
+        /// PHYs the slave prefers to transmit on.
+        tx_phys: PhySet,
+        /// PHYs the slave prefers to receive on.
+        rx_phys: PhySet,
+    },
+
+    /// `0x18`/`LL_PHY_UPDATE_IND` - Sent by the master to select the PHYs used from `instant` on.
+    PhyUpdateInd {
+        /// PHY used by the master from `instant` on, or an empty set if it doesn't change.
+        m_to_s_phy: PhySet,
+        /// PHY used by the slave from `instant` on, or an empty set if it doesn't change.
+        s_to_m_phy: PhySet,
+        /// Connection event at which the new PHYs take effect.
+        instant: u16,
+    },
+
+    /// `0x19`/`LL_MIN_USED_CHANNELS_IND` - Sent by the slave to ask the master to use at least a
+    /// minimum number of data channels on some PHYs.
+    ///
+    /// The master does not send a response back.
+    MinUsedChannelsInd(MinUsedChannels),
+
     /// Catch-all variant for unsupported opcodes.
     Unknown {
         /// The opcode we don't support. This can also be the `Unknown` variant.
@@ -247,12 +468,24 @@ impl ControlPdu<'_> {
             ControlPdu::ConnectionUpdateReq { .. } => ControlOpcode::ConnectionUpdateReq,
             ControlPdu::ChannelMapReq { .. } => ControlOpcode::ChannelMapReq,
             ControlPdu::TerminateInd { .. } => ControlOpcode::TerminateInd,
+            ControlPdu::EncReq(_) => ControlOpcode::EncReq,
+            ControlPdu::EncRsp { .. } => ControlOpcode::EncRsp,
+            ControlPdu::StartEncReq => ControlOpcode::StartEncReq,
+            ControlPdu::StartEncRsp => ControlOpcode::StartEncRsp,
             ControlPdu::UnknownRsp { .. } => ControlOpcode::UnknownRsp,
             ControlPdu::FeatureReq { .. } => ControlOpcode::FeatureReq,
             ControlPdu::FeatureRsp { .. } => ControlOpcode::FeatureRsp,
             ControlPdu::VersionInd { .. } => ControlOpcode::VersionInd,
+            ControlPdu::RejectInd { .. } => ControlOpcode::RejectInd,
             ControlPdu::ConnectionParamReq(_) => ControlOpcode::ConnectionParamReq,
             ControlPdu::ConnectionParamRsp(_) => ControlOpcode::ConnectionParamRsp,
+            ControlPdu::RejectIndExt { .. } => ControlOpcode::RejectIndExt,
+            ControlPdu::LengthReq { .. } => ControlOpcode::LengthReq,
+            ControlPdu::LengthRsp { .. } => ControlOpcode::LengthRsp,
+            ControlPdu::PhyReq { .. } => ControlOpcode::PhyReq,
+            ControlPdu::PhyRsp { .. } => ControlOpcode::PhyRsp,
+            ControlPdu::PhyUpdateInd { .. } => ControlOpcode::PhyUpdateInd,
+            ControlPdu::MinUsedChannelsInd(_) => ControlOpcode::MinUsedChannelsInd,
             ControlPdu::Unknown { opcode, .. } => *opcode,
         }
     }
@@ -284,6 +517,9 @@ impl ControlPdu<'_> {
             PingReq => 0,
             PingRsp => 0,
             LengthReq | LengthRsp => 2 + 2 + 2 + 2,
+            PhyReq | PhyRsp => 1 + 1,
+            PhyUpdateInd => 1 + 1 + 2,
+            MinUsedChannelsInd => 1 + 1,
             Unknown(_) => {
                 if let ControlPdu::Unknown {
                     ctr_data,
@@ -308,8 +544,15 @@ impl<'a> FromBytes<'a> for ControlPdu<'a> {
             }
             ControlOpcode::ChannelMapReq => ControlPdu::ChannelMapReq(bytes.read_obj()?),
             ControlOpcode::TerminateInd => ControlPdu::TerminateInd {
-                error_code: Hex(bytes.read_u8()?),
+                error_code: DisconnectReason::from(bytes.read_u8()?),
+            },
+            ControlOpcode::EncReq => ControlPdu::EncReq(EncryptionRequest::from_bytes(bytes)?),
+            ControlOpcode::EncRsp => ControlPdu::EncRsp {
+                skd_s: Hex(bytes.read_u64_le()?),
+                iv_s: Hex(bytes.read_u32_le()?),
             },
+            ControlOpcode::StartEncReq => ControlPdu::StartEncReq,
+            ControlOpcode::StartEncRsp => ControlPdu::StartEncRsp,
             ControlOpcode::UnknownRsp => ControlPdu::UnknownRsp {
                 unknown_type: ControlOpcode::from(bytes.read_u8()?),
             },
@@ -321,9 +564,44 @@ impl<'a> FromBytes<'a> for ControlPdu<'a> {
             },
             ControlOpcode::VersionInd => ControlPdu::VersionInd {
                 vers_nr: VersionNumber::from(bytes.read_u8()?),
-                comp_id: CompanyId::from_raw(bytes.read_u16_le()?),
+                comp_id: CompanyId::from_bytes(bytes)?,
                 sub_vers_nr: Hex(bytes.read_u16_le()?),
             },
+            ControlOpcode::RejectInd => ControlPdu::RejectInd {
+                error_code: Hex(bytes.read_u8()?),
+            },
+            ControlOpcode::RejectIndExt => ControlPdu::RejectIndExt {
+                reject_opcode: ControlOpcode::from(bytes.read_u8()?),
+                error_code: Hex(bytes.read_u8()?),
+            },
+            ControlOpcode::LengthReq => ControlPdu::LengthReq {
+                max_rx_octets: bytes.read_u16_le()?,
+                max_rx_time: bytes.read_u16_le()?,
+                max_tx_octets: bytes.read_u16_le()?,
+                max_tx_time: bytes.read_u16_le()?,
+            },
+            ControlOpcode::LengthRsp => ControlPdu::LengthRsp {
+                max_rx_octets: bytes.read_u16_le()?,
+                max_rx_time: bytes.read_u16_le()?,
+                max_tx_octets: bytes.read_u16_le()?,
+                max_tx_time: bytes.read_u16_le()?,
+            },
+            ControlOpcode::PhyReq => ControlPdu::PhyReq {
+                tx_phys: PhySet::from_bits_truncate(bytes.read_u8()?),
+                rx_phys: PhySet::from_bits_truncate(bytes.read_u8()?),
+            },
+            ControlOpcode::PhyRsp => ControlPdu::PhyRsp {
+                tx_phys: PhySet::from_bits_truncate(bytes.read_u8()?),
+                rx_phys: PhySet::from_bits_truncate(bytes.read_u8()?),
+            },
+            ControlOpcode::PhyUpdateInd => ControlPdu::PhyUpdateInd {
+                m_to_s_phy: PhySet::from_bits_truncate(bytes.read_u8()?),
+                s_to_m_phy: PhySet::from_bits_truncate(bytes.read_u8()?),
+                instant: bytes.read_u16_le()?,
+            },
+            ControlOpcode::MinUsedChannelsInd => {
+                ControlPdu::MinUsedChannelsInd(MinUsedChannels::from_bytes(bytes)?)
+            }
             _ => ControlPdu::Unknown {
                 opcode,
                 ctr_data: bytes.read_rest(),
@@ -351,9 +629,16 @@ impl<'a> ToBytes for ControlPdu<'a> {
                 Ok(())
             }
             ControlPdu::TerminateInd { error_code } => {
-                buffer.write_u8(error_code.0)?;
+                buffer.write_u8((*error_code).into())?;
+                Ok(())
+            }
+            ControlPdu::EncReq(req) => req.to_bytes(buffer),
+            ControlPdu::EncRsp { skd_s, iv_s } => {
+                buffer.write_u64_le(skd_s.0)?;
+                buffer.write_u32_le(iv_s.0)?;
                 Ok(())
             }
+            ControlPdu::StartEncReq | ControlPdu::StartEncRsp => Ok(()),
             ControlPdu::UnknownRsp { unknown_type } => {
                 buffer.write_u8(u8::from(*unknown_type))?;
                 Ok(())
@@ -366,13 +651,59 @@ impl<'a> ToBytes for ControlPdu<'a> {
                 sub_vers_nr,
             } => {
                 buffer.write_u8(u8::from(*vers_nr))?;
-                buffer.write_u16_le(comp_id.as_u16())?;
+                comp_id.to_bytes(buffer)?;
                 buffer.write_u16_le(sub_vers_nr.0)?;
                 Ok(())
             }
+            ControlPdu::RejectInd { error_code } => {
+                buffer.write_u8(error_code.0)?;
+                Ok(())
+            }
             ControlPdu::ConnectionParamReq(data) | ControlPdu::ConnectionParamRsp(data) => {
                 data.to_bytes(buffer)
             }
+            ControlPdu::RejectIndExt {
+                reject_opcode,
+                error_code,
+            } => {
+                buffer.write_u8(u8::from(*reject_opcode))?;
+                buffer.write_u8(error_code.0)?;
+                Ok(())
+            }
+            ControlPdu::LengthReq {
+                max_rx_octets,
+                max_rx_time,
+                max_tx_octets,
+                max_tx_time,
+            }
+            | ControlPdu::LengthRsp {
+                max_rx_octets,
+                max_rx_time,
+                max_tx_octets,
+                max_tx_time,
+            } => {
+                buffer.write_u16_le(*max_rx_octets)?;
+                buffer.write_u16_le(*max_rx_time)?;
+                buffer.write_u16_le(*max_tx_octets)?;
+                buffer.write_u16_le(*max_tx_time)?;
+                Ok(())
+            }
+            ControlPdu::PhyReq { tx_phys, rx_phys } | ControlPdu::PhyRsp { tx_phys, rx_phys } => {
+                buffer.write_u8(tx_phys.bits())?;
+                buffer.write_u8(rx_phys.bits())?;
+                Ok(())
+            }
+            ControlPdu::PhyUpdateInd {
+                m_to_s_phy,
+                s_to_m_phy,
+                instant,
+            } => {
+                buffer.write_u8(m_to_s_phy.bits())?;
+                buffer.write_u8(s_to_m_phy.bits())?;
+                buffer.write_u16_le(*instant)?;
+                Ok(())
+            }
+            ControlPdu::MinUsedChannelsInd(ind) => ind.to_bytes(buffer),
             ControlPdu::Unknown { ctr_data, .. } => {
                 buffer.write_slice(ctr_data)?;
                 Ok(())
@@ -383,7 +714,8 @@ impl<'a> ToBytes for ControlPdu<'a> {
 
 enum_with_unknown! {
     /// Enumeration of all known LL Control PDU opcodes (not all of which might be supported).
-    #[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum ControlOpcode(u8) {
         ConnectionUpdateReq = 0x00,
         ChannelMapReq = 0x01,
@@ -407,6 +739,10 @@ enum_with_unknown! {
         PingRsp = 0x13,
         LengthReq = 0x14,
         LengthRsp = 0x15,
+        PhyReq = 0x16,
+        PhyRsp = 0x17,
+        PhyUpdateInd = 0x18,
+        MinUsedChannelsInd = 0x19,
     }
 }
 
@@ -414,7 +750,8 @@ enum_with_unknown! {
     /// Enumeration of all possible `VersNr` for `LL_VERSION_IND` PDUs.
     ///
     /// According to <https://www.bluetooth.com/specifications/assigned-numbers/link-layer>.
-    #[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum VersionNumber(u8) {
         V4_0 = 6,
         V4_1 = 7,
@@ -424,6 +761,44 @@ enum_with_unknown! {
     }
 }
 
+enum_with_unknown! {
+    /// Reason for closing a connection, as sent in `LL_TERMINATE_IND` PDUs.
+    ///
+    /// These are the error codes defined in Part D of the Core specification. Only the ones that
+    /// can end a connection are listed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum DisconnectReason(u8) {
+        /// Pairing or encryption failed because of a missing or wrong key.
+        AuthenticationFailure = 0x05,
+        /// The supervision timeout elapsed without receiving a packet.
+        ConnectionTimeout = 0x08,
+        /// The user on the remote device terminated the connection.
+        RemoteUserTerminated = 0x13,
+        /// The remote device terminated the connection because it ran low on resources.
+        RemoteLowResources = 0x14,
+        /// The remote device terminated the connection because it is about to power off.
+        RemotePowerOff = 0x15,
+        /// The connection was terminated by the local host (reported locally after we terminated
+        /// the connection).
+        LocalHostTerminated = 0x16,
+        /// The remote device doesn't support a feature required for the connection.
+        UnsupportedRemoteFeature = 0x1A,
+        /// An LLCP procedure didn't complete in time.
+        LlResponseTimeout = 0x22,
+        /// The *instant* of a connection or channel map update has already passed.
+        InstantPassed = 0x28,
+        /// An LLCP procedure was started while another one was in progress.
+        DifferentTransactionCollision = 0x2A,
+        /// The connection parameters are not acceptable.
+        UnacceptableConnectionParameters = 0x3B,
+        /// A received packet failed the Message Integrity Check.
+        MicFailure = 0x3D,
+        /// The master's first packet wasn't received in the transmit window.
+        ConnectionFailedToBeEstablished = 0x3E,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -443,28 +818,127 @@ mod tests {
             assert_eq!(max2, max);
         }
 
-        same(Duration::from_secs(1), Duration::from_secs(1));
-        same(Duration::from_micros(7_500), Duration::from_micros(7_500));
-        same(Duration::from_micros(7_500), Duration::from_secs(4));
-        same(Duration::from_secs(4), Duration::from_secs(4));
+        same(Duration::secs(1), Duration::secs(1));
+        same(Duration::micros(7_500), Duration::micros(7_500));
+        same(Duration::micros(7_500), Duration::secs(4));
+        same(Duration::secs(4), Duration::secs(4));
 
-        let (min, max) = set(Duration::from_secs(8), Duration::from_secs(8));
-        assert_eq!(min, Duration::from_secs(4));
-        assert_eq!(max, Duration::from_secs(4));
+        let (min, max) = set(Duration::secs(8), Duration::secs(8));
+        assert_eq!(min, Duration::secs(4));
+        assert_eq!(max, Duration::secs(4));
 
-        let (min, max) = set(Duration::from_secs(0), Duration::from_secs(8));
-        assert_eq!(min, Duration::from_micros(7_500));
-        assert_eq!(max, Duration::from_secs(4));
+        let (min, max) = set(Duration::secs(0), Duration::secs(8));
+        assert_eq!(min, Duration::micros(7_500));
+        assert_eq!(max, Duration::secs(4));
 
-        let (min, max) = set(Duration::from_micros(7_501), Duration::from_micros(7_502));
-        assert_eq!(min, Duration::from_micros(7_500));
-        assert_eq!(max, Duration::from_micros(7_500));
+        let (min, max) = set(Duration::micros(7_501), Duration::micros(7_502));
+        assert_eq!(min, Duration::micros(7_500));
+        assert_eq!(max, Duration::micros(7_500));
     }
 
     #[test]
     #[should_panic(expected = "min <= max")]
     fn update_req_set_conn_interval_minmax() {
         let mut req = ConnectionParamRequest::new();
-        req.set_conn_interval(Duration::from_secs(8), Duration::from_secs(7));
+        req.set_conn_interval(Duration::secs(8), Duration::secs(7));
+    }
+
+    #[test]
+    fn truncated_control_pdus() {
+        let pdus: &[&[u8]] = &[
+            &[0x00, 1, 2, 0, 6, 0, 0, 0, 100, 0, 10, 0],
+            &[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 10, 0],
+            &[0x02, 0x13],
+            &[
+                0x03, 1, 2, 3, 4, 5, 6, 7, 8, 1, 2, 3, 4, 5, 6, 7, 8, 1, 2, 3, 4, 5, 6,
+            ],
+            &[0x04, 1, 2, 3, 4, 5, 6, 7, 8, 1, 2, 3, 4],
+            &[0x07, 0x42],
+            &[0x08, 1, 0, 0, 0, 0, 0, 0, 0],
+            &[0x09, 1, 0, 0, 0, 0, 0, 0, 0],
+            &[0x0C, 9, 0x59, 0, 0, 0],
+            &[0x0D, 0x06],
+            &[0x11, 0x16, 0x2A],
+            &[0x14, 0xFB, 0x00, 0x48, 0x08, 0x1B, 0x00, 0x48, 0x01],
+            &[0x15, 0x21, 0x00, 0x78, 0x01, 0x1B, 0x00, 0x48, 0x01],
+            &[0x16, 0x02, 0x02],
+            &[0x17, 0x03, 0x03],
+            &[0x18, 0x02, 0x02, 0x34, 0x12],
+            &[0x19, 0x03, 0x08],
+        ];
+
+        for pdu in pdus {
+            let mut bytes = ByteReader::new(pdu);
+            assert!(ControlPdu::from_bytes(&mut bytes).is_ok(), "{:02X?}", pdu);
+            assert!(bytes.is_empty());
+            for len in 0..pdu.len() {
+                assert_eq!(
+                    ControlPdu::from_bytes(&mut ByteReader::new(&pdu[..len])).err(),
+                    Some(Error::Eof),
+                    "{:02X?} truncated to {} Bytes",
+                    pdu,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn min_used_channels_ind() {
+        assert_eq!(
+            MinUsedChannels::new(PhySet::empty(), 8),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            MinUsedChannels::new(PhySet::LE_1M, 1),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            MinUsedChannels::new(PhySet::LE_1M, 38),
+            Err(Error::InvalidValue)
+        );
+
+        let ind = MinUsedChannels::new(PhySet::LE_1M | PhySet::LE_2M, 8).unwrap();
+        let pdu = ControlPdu::MinUsedChannelsInd(ind);
+        let mut buf = [0; 3];
+        let mut writer = ByteWriter::new(&mut buf);
+        pdu.to_bytes(&mut writer).unwrap();
+        assert_eq!(writer.space_left(), 0);
+        assert_eq!(usize::from(pdu.encoded_size()), buf.len());
+        assert_eq!(buf, [0x19, 0x03, 0x08]);
+
+        match ControlPdu::from_bytes(&mut ByteReader::new(&buf)).unwrap() {
+            ControlPdu::MinUsedChannelsInd(parsed) => {
+                assert_eq!(parsed, ind);
+                assert_eq!(parsed.phys(), PhySet::LE_1M | PhySet::LE_2M);
+                assert_eq!(parsed.min_used_channels(), 8);
+            }
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        }
+
+        // 5 used channels
+        let map = ChannelMap::from_raw([0x1F, 0, 0, 0, 0]);
+        assert!(!ind.is_satisfied_by(&map, PhySet::LE_2M));
+        assert!(ind.is_satisfied_by(&map, PhySet::LE_CODED));
+        assert!(ind.is_satisfied_by(&ChannelMap::with_all_channels(), PhySet::LE_2M));
+    }
+
+    #[test]
+    fn version_ind_company_id() {
+        let pdu = ControlPdu::VersionInd {
+            vers_nr: VersionNumber::V4_2,
+            comp_id: CompanyId::from_raw(0x1234),
+            sub_vers_nr: Hex(0xABCD),
+        };
+        let mut buf = [0; 6];
+        let mut writer = ByteWriter::new(&mut buf);
+        pdu.to_bytes(&mut writer).unwrap();
+        assert_eq!(writer.space_left(), 0);
+        assert_eq!(buf, [0x0C, 0x08, 0x34, 0x12, 0xCD, 0xAB]);
+
+        match ControlPdu::from_bytes(&mut ByteReader::new(&buf)).unwrap() {
+            ControlPdu::VersionInd { comp_id, .. } => assert_eq!(comp_id.as_u16(), 0x1234),
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        }
     }
 }
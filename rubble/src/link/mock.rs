@@ -0,0 +1,888 @@
+//! A simulated radio for testing the Link-Layer without hardware.
+//!
+//! A [`MockChannel`] connects two [`MockTransmitter`]s, one for each simulated device. Packets sent
+//! by a device are held back until [`MockChannel::deliver`] passes them to the other device's
+//! `LinkLayer`, like a radio interrupt handler would. A packet only arrives if the receiving radio
+//! is listening on the channel (and access address) it was sent on, as configured by the last
+//! [`Cmd`] applied to its transmitter. Data channel packets carry a CRC computed with
+//! [`ble_crc24`], which the receiving radio checks using its own `CRCInit`. Packet loss can be
+//! simulated per direction.
+//!
+//! Devices without a full `LinkLayer` (eg. a connection master, which Rubble does not implement)
+//! can be scripted by sending packets with [`MockTransmitter::send`] and reading the packets sent
+//! by the other side with [`MockChannel::receive`].
+//!
+//! This module is only available when the `mock` Cargo feature is enabled.
+
+use crate::link::{
+    advertising, ble_crc24, data, AdvertisingChannel, Cmd, Config, DataChannel, FeatureSet,
+    LinkLayer, RadioCmd, Transmitter, MIN_PAYLOAD_BUF,
+};
+use crate::time::{Instant, MockTimer};
+use heapless::{Deque, Vec};
+
+/// Number of packets a `MockTransmitter` holds before the oldest one is dropped.
+const MAX_PENDING: usize = 8;
+
+/// A packet sent by a [`MockTransmitter`].
+#[derive(Debug, Clone)]
+pub enum MockPacket {
+    /// An advertising channel PDU.
+    Advertising {
+        header: advertising::Header,
+        payload: Vec<u8, MIN_PAYLOAD_BUF>,
+        channel: AdvertisingChannel,
+    },
+
+    /// A data channel PDU.
+    ///
+    /// `crc` is the CRC transmitted after the PDU (see [`ble_crc24`]). The receiver validates it
+    /// against its own `CRCInit`. Use [`MockPacket::data`] to compute it.
+    Data {
+        access_address: u32,
+        crc_init: u32,
+        header: data::Header,
+        payload: Vec<u8, MIN_PAYLOAD_BUF>,
+        channel: DataChannel,
+        crc: u32,
+    },
+
+    /// An advertising PDU sent on a secondary advertising channel.
+    Secondary {
+        access_address: u32,
+        crc_init: u32,
+        header: advertising::Header,
+        payload: Vec<u8, MIN_PAYLOAD_BUF>,
+        channel: DataChannel,
+    },
+}
+
+impl MockPacket {
+    /// Creates a data channel packet, computing its CRC from `crc_init`.
+    ///
+    /// Panics if `payload` is larger than `MIN_PAYLOAD_BUF`.
+    pub fn data(
+        access_address: u32,
+        crc_init: u32,
+        header: data::Header,
+        payload: &[u8],
+        channel: DataChannel,
+    ) -> Self {
+        MockPacket::Data {
+            access_address,
+            crc_init,
+            header,
+            payload: Vec::from_slice(payload).unwrap(),
+            channel,
+            crc: data_crc(crc_init, header, payload),
+        }
+    }
+}
+
+/// Computes the CRC of a data channel PDU.
+fn data_crc(crc_init: u32, header: data::Header, payload: &[u8]) -> u32 {
+    let crc = ble_crc24(crc_init, &header.to_u16().to_le_bytes());
+    ble_crc24(crc, payload)
+}
+
+/// The radio of one simulated device.
+pub struct MockTransmitter {
+    buf: [u8; MIN_PAYLOAD_BUF],
+    pending: Deque<MockPacket, MAX_PENDING>,
+    listening: RadioCmd,
+    lose: u32,
+    lost: u32,
+    rx_capacity: usize,
+    transmitting: bool,
+}
+
+impl MockTransmitter {
+    /// Creates a transmitter whose receiver is turned off.
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MIN_PAYLOAD_BUF],
+            pending: Deque::new(),
+            listening: RadioCmd::Off,
+            lose: 0,
+            lost: 0,
+            rx_capacity: MIN_PAYLOAD_BUF,
+            transmitting: false,
+        }
+    }
+
+    /// Sets the number of payload Bytes the simulated receive buffer can hold.
+    pub fn set_rx_capacity(&mut self, capacity: usize) {
+        self.rx_capacity = capacity;
+    }
+
+    /// Reconfigures the receiver according to a `Cmd` returned by the Link-Layer.
+    ///
+    /// This also ends a data channel transmission started by the Link-Layer.
+    pub fn apply(&mut self, cmd: &Cmd) {
+        self.listening = cmd.radio.clone();
+        self.transmitting = false;
+    }
+
+    /// Returns how the receiver is currently configured.
+    pub fn listening(&self) -> &RadioCmd {
+        &self.listening
+    }
+
+    /// Drops the next `count` packets sent by this transmitter instead of delivering them.
+    pub fn lose_next(&mut self, count: u32) {
+        self.lose = count;
+    }
+
+    /// Returns the number of packets sent by this transmitter that were lost.
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+
+    /// Sends `packet` to the other device.
+    ///
+    /// This is used by scripted devices, and by the `Transmitter` implementation.
+    pub fn send(&mut self, packet: MockPacket) {
+        if self.lose > 0 {
+            self.lose -= 1;
+            self.lost += 1;
+            return;
+        }
+
+        if let Err(packet) = self.pending.push_back(packet) {
+            // The other device isn't keeping up, so the oldest packet was missed
+            self.pending.pop_front();
+            self.lost += 1;
+            self.pending.push_back(packet).ok();
+        }
+    }
+
+    fn payload(&self, len: u8) -> Vec<u8, MIN_PAYLOAD_BUF> {
+        Vec::from_slice(&self.buf[..usize::from(len)]).unwrap()
+    }
+}
+
+impl Default for MockTransmitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transmitter for MockTransmitter {
+    /// Panics when called while a data channel PDU is being sent.
+    ///
+    /// Real radios send data channel PDUs after `transmit_data` returns, so they'd have to wait
+    /// for the transmission to finish here, which the Link-Layer must not do in the same call.
+    fn tx_payload_buf(&mut self) -> &mut [u8] {
+        assert!(
+            !self.transmitting,
+            "TX buffer accessed while a data channel PDU is being sent"
+        );
+        &mut self.buf
+    }
+
+    fn rx_payload_capacity(&self) -> usize {
+        self.rx_capacity
+    }
+
+    /// The simulated radio can use all features, so connections use the ones configured in the
+    /// `LinkLayer`.
+    fn supported_features(&self) -> FeatureSet {
+        FeatureSet::all()
+    }
+
+    fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel) {
+        let payload = self.payload(header.payload_length());
+        self.send(MockPacket::Advertising {
+            header,
+            payload,
+            channel,
+        });
+
+        // Like real radios, listen for a response on the same channel afterwards
+        self.listening = RadioCmd::ListenAdvertising {
+            channel,
+            rx_timeout: None,
+        };
+    }
+
+    fn transmit_data(
+        &mut self,
+        access_address: u32,
+        crc_init: u32,
+        header: data::Header,
+        channel: DataChannel,
+    ) {
+        let payload = &self.buf[..usize::from(header.payload_length())];
+        let packet = MockPacket::data(access_address, crc_init, header, payload, channel);
+        self.send(packet);
+        self.transmitting = true;
+    }
+
+    fn transmit_secondary(
+        &mut self,
+        access_address: u32,
+        crc_init: u32,
+        header: advertising::Header,
+        channel: DataChannel,
+    ) {
+        let payload = self.payload(header.payload_length());
+        self.send(MockPacket::Secondary {
+            access_address,
+            crc_init,
+            header,
+            payload,
+            channel,
+        });
+    }
+}
+
+/// Identifies one of the two devices connected by a [`MockChannel`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Device {
+    A,
+    B,
+}
+
+/// A simulated radio channel between two devices.
+#[derive(Default)]
+pub struct MockChannel {
+    a: MockTransmitter,
+    b: MockTransmitter,
+}
+
+impl MockChannel {
+    /// Creates a channel between two devices whose receivers are turned off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the radio of `device`.
+    pub fn radio(&mut self, device: Device) -> &mut MockTransmitter {
+        match device {
+            Device::A => &mut self.a,
+            Device::B => &mut self.b,
+        }
+    }
+
+    /// Returns the radio of `device`, and the radio of the other device.
+    fn split(&mut self, device: Device) -> (&mut MockTransmitter, &mut MockTransmitter) {
+        match device {
+            Device::A => (&mut self.a, &mut self.b),
+            Device::B => (&mut self.b, &mut self.a),
+        }
+    }
+
+    /// Takes the oldest packet sent to `to` without processing it.
+    ///
+    /// This is used when `to` is a scripted device, which receives everything regardless of how
+    /// its radio is configured.
+    pub fn receive(&mut self, to: Device) -> Option<MockPacket> {
+        let (_, from) = self.split(to);
+        from.pending.pop_front()
+    }
+
+    /// Passes the oldest packet sent to `to` to its Link-Layer `ll`, as if it was received at
+    /// `rx_end`.
+    ///
+    /// The timer of `ll` is advanced to `rx_end`, and the returned `Cmd` is applied to the radio
+    /// of `to`. Returns `None` if no packet was pending, or if the radio of `to` wasn't listening
+    /// for it (in which case the packet is lost).
+    pub fn deliver<C>(&mut self, to: Device, ll: &mut LinkLayer<C>, rx_end: Instant) -> Option<Cmd>
+    where
+        C: Config<Transmitter = MockTransmitter, Timer = MockTimer>,
+    {
+        let (rx, from) = self.split(to);
+        let packet = from.pending.pop_front()?;
+        ll.timer().set(rx_end);
+
+        let cmd = match (&packet, rx.listening.clone()) {
+            (
+                MockPacket::Advertising {
+                    header,
+                    payload,
+                    channel,
+                },
+                RadioCmd::ListenAdvertising {
+                    channel: listen, ..
+                },
+            ) if channel.channel() == listen.channel() => {
+                ll.process_adv_packet(rx_end, rx, *header, payload, true, None)
+            }
+            (
+                MockPacket::Data {
+                    access_address,
+                    header,
+                    payload,
+                    channel,
+                    crc,
+                    ..
+                },
+                RadioCmd::ListenData {
+                    channel: listen,
+                    access_address: listen_aa,
+                    crc_init: listen_crc,
+                    ..
+                },
+            ) if *channel == listen && *access_address == listen_aa => {
+                let crc_ok = data_crc(listen_crc, *header, payload) == *crc;
+                ll.process_data_packet(rx_end, rx, *header, payload, crc_ok)
+            }
+            _ => {
+                from.lost += 1;
+                return None;
+            }
+        };
+
+        rx.apply(&cmd);
+        Some(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+    use crate::att::NoAttributes;
+    use crate::bytes::ByteReader;
+    use crate::l2cap::BleChannelMap;
+    use crate::link::ad_structure::AdStructure;
+    use crate::link::advertising::{ConnectRequestData, Pdu, PduBuf, PduType, SleepClockAccuracy};
+    use crate::link::channel_map::ChannelMap;
+    use crate::link::queue::{
+        ArrayConsumer, ArrayProducer, ArrayQueue, Consume, Consumer, PacketQueue, Producer,
+    };
+    use crate::link::scan::{AdvReport, AdvReportHandler, ScanParams};
+    use crate::link::seq_num::SeqNum;
+    use crate::link::{AddressKind, DeviceAddress};
+    use crate::security::{rng::MockRng, NoSecurity};
+    use crate::time::Duration;
+    use crate::Error;
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    /// Records the scan responses of all advertising reports.
+    #[derive(Default, Clone)]
+    struct ScanResponses(Rc<RefCell<Vec<Option<Vec<u8>>>>>);
+
+    impl AdvReportHandler for ScanResponses {
+        fn report(&mut self, report: &AdvReport<'_>) {
+            let response = report.scan_response.map(<[u8]>::to_vec);
+            self.0.borrow_mut().push(response);
+        }
+    }
+
+    enum MockConfig {}
+
+    impl Config for MockConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+        type PacketQueue = &'static mut ArrayQueue<8>;
+        type Aes = SoftAesProvider;
+        type AdvReportHandler = ScanResponses;
+        type LinkEventHandler = ();
+        type Rng = MockRng;
+    }
+
+    fn link_layer(addr: [u8; 6]) -> LinkLayer<MockConfig> {
+        let addr = DeviceAddress::new(addr, AddressKind::Public);
+        LinkLayer::new(addr, MockTimer::default(), MockRng::default())
+    }
+
+    fn at(us: u32) -> Instant {
+        Instant::from_ticks(0) + Duration::micros(us)
+    }
+
+    #[test]
+    fn active_scanning() {
+        let mut channel = MockChannel::new();
+        let mut advertiser = link_layer([1, 2, 3, 4, 5, 6]);
+        let mut scanner = link_layer([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6]);
+        let responses = ScanResponses::default();
+
+        let params = ScanParams::continuous(Duration::millis(100))
+            .unwrap()
+            .active(true);
+        let cmd = scanner.start_scanning(params, responses.clone());
+        channel.radio(Device::B).apply(&cmd);
+
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let name = AdStructure::CompleteLocalName("rubble");
+        advertiser.set_scan_response_data(&[name]).unwrap();
+        advertiser
+            .start_advertise(
+                Duration::millis(100),
+                &[],
+                channel.radio(Device::A),
+                ll_tx,
+                ll_rx,
+            )
+            .unwrap();
+
+        // ADV_IND, SCAN_REQ, SCAN_RSP
+        assert!(channel.deliver(Device::B, &mut scanner, at(200)).is_some());
+        assert!(responses.0.borrow().is_empty());
+        assert!(channel
+            .deliver(Device::A, &mut advertiser, at(500))
+            .is_some());
+        assert!(channel.deliver(Device::B, &mut scanner, at(800)).is_some());
+        assert!(channel.receive(Device::A).is_none());
+        assert!(channel.receive(Device::B).is_none());
+
+        let responses = responses.0.borrow();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].as_deref(), Some(&b"\x07\x09rubble"[..]));
+    }
+
+    /// Sends a data channel PDU from the scripted master on `data_channel` in connection event
+    /// `event`.
+    ///
+    /// Returns the channel the slave listens on next, and the header of its response.
+    fn send_pdu(
+        channel: &mut MockChannel,
+        slave: &mut LinkLayer<MockConfig>,
+        event: u32,
+        data_channel: u8,
+        header: data::Header,
+        payload: &[u8],
+    ) -> (u8, Option<data::Header>) {
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5555,
+            header,
+            payload,
+            DataChannel::new(data_channel),
+        ));
+
+        let anchor = 3_000 + event * 7_500;
+        let cmd = channel.deliver(Device::A, slave, at(anchor + 100)).unwrap();
+        let next = match cmd.radio {
+            RadioCmd::ListenData { channel, .. } => channel.index(),
+            _ => panic!("slave not listening: {:?}", cmd.radio),
+        };
+        match channel.receive(Device::B) {
+            Some(MockPacket::Data { header, .. }) => (next, Some(header)),
+            Some(MockPacket::Advertising { .. }) | Some(MockPacket::Secondary { .. }) => {
+                panic!("advertising PDU sent during connection")
+            }
+            None => (next, None),
+        }
+    }
+
+    /// Sends a data channel PDU from the scripted master on the channel of connection event
+    /// `event`, and returns the header of the slave's response.
+    fn exchange(
+        channel: &mut MockChannel,
+        slave: &mut LinkLayer<MockConfig>,
+        event: u32,
+        sn: SeqNum,
+        nesn: SeqNum,
+        payload: &[u8],
+    ) -> Option<data::Header> {
+        let llid = if payload.is_empty() {
+            data::Llid::DataCont
+        } else {
+            data::Llid::DataStart
+        };
+        let mut header = data::Header::new(llid);
+        header.set_sn(sn);
+        header.set_nesn(nesn);
+        header.set_payload_length(payload.len() as u8);
+
+        let data_channel = ((event + 1) * 7 % 37) as u8;
+        let (next, sent) = send_pdu(channel, slave, event, data_channel, header, payload);
+        assert_eq!(u32::from(next), (event + 2) * 7 % 37);
+        sent
+    }
+
+    /// Connects `slave` to the scripted master, using a 7.5 ms interval, a 100 ms supervision
+    /// timeout, all data channels and a hop increment of 7.
+    ///
+    /// The first connection event's anchor point is at 3 ms.
+    fn connect(
+        channel: &mut MockChannel,
+        slave: &mut LinkLayer<MockConfig>,
+        ll_tx: ArrayConsumer<'static, 8>,
+        ll_rx: ArrayProducer<'static, 8>,
+    ) {
+        slave
+            .start_advertise(
+                Duration::millis(100),
+                &[],
+                channel.radio(Device::A),
+                ll_tx,
+                ll_rx,
+            )
+            .unwrap();
+
+        let packet = channel.receive(Device::B).unwrap();
+        let adv = match &packet {
+            MockPacket::Advertising {
+                header, payload, ..
+            } => Pdu::from_header_and_payload(*header, &mut ByteReader::new(payload)).unwrap(),
+            _ => panic!("expected advertising PDU"),
+        };
+        assert_eq!(adv.ty(), PduType::AdvInd);
+
+        let master = [0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6];
+        let mut payload = heapless::Vec::from_slice(&master).unwrap();
+        payload.extend_from_slice(adv.sender().raw()).unwrap();
+        payload
+            .extend_from_slice(&0x5065_17AF_u32.to_le_bytes())
+            .unwrap();
+        payload.extend_from_slice(&[0x55, 0x55, 0x55]).unwrap();
+        payload.extend_from_slice(&[1, 0, 0]).unwrap();
+        payload.extend_from_slice(&[6, 0, 0, 0, 10, 0]).unwrap();
+        payload
+            .extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 7])
+            .unwrap();
+        let mut header = advertising::Header::new(PduType::ConnectReq);
+        header.set_payload_length(payload.len() as u8);
+        channel.radio(Device::B).send(MockPacket::Advertising {
+            header,
+            payload,
+            channel: AdvertisingChannel::first(),
+        });
+        assert!(channel.deliver(Device::A, slave, at(1_000)).is_some());
+        assert!(slave.is_connected());
+    }
+
+    #[test]
+    #[should_panic(expected = "TX buffer accessed")]
+    fn tx_buf_busy_while_sending() {
+        // This catches Link-Layer code that would make a real radio busy-wait for the transmission
+        // to end, which all connection tests go through.
+        let mut radio = MockTransmitter::new();
+        let header = data::Header::new(data::Llid::DataCont);
+        radio.transmit_data(0x5065_17AF, 0x55_5555, header, DataChannel::new(0));
+        radio.tx_payload_buf();
+    }
+
+    #[test]
+    fn connection() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, mut app_rx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        connect(&mut channel, &mut slave, ll_tx, ll_rx);
+
+        app_tx
+            .produce_with(3, |w| -> Result<_, Error> {
+                w.write_slice(&[1, 2, 3])?;
+                Ok(data::Llid::DataStart)
+            })
+            .unwrap();
+
+        // The slave answers the master's first PDU with its data
+        let sent = exchange(&mut channel, &mut slave, 0, SeqNum::ZERO, SeqNum::ZERO, &[]);
+        let sent = sent.unwrap();
+        assert_eq!(sent.sn(), SeqNum::ZERO);
+        assert_eq!(sent.payload_length(), 3);
+
+        // The slave's response to the master's data gets lost, so the master retransmits its PDU.
+        // It is acknowledged again, but must not be delivered twice.
+        channel.radio(Device::A).lose_next(1);
+        let sent = exchange(
+            &mut channel,
+            &mut slave,
+            1,
+            SeqNum::ONE,
+            SeqNum::ONE,
+            &[0xAA],
+        );
+        assert!(sent.is_none());
+        assert_eq!(channel.radio(Device::A).lost(), 1);
+
+        let sent = exchange(
+            &mut channel,
+            &mut slave,
+            2,
+            SeqNum::ONE,
+            SeqNum::ONE,
+            &[0xAA],
+        );
+        let sent = sent.unwrap();
+        assert_eq!(sent.sn(), SeqNum::ONE);
+        assert_eq!(sent.nesn(), SeqNum::ZERO);
+        assert_eq!(sent.payload_length(), 0);
+
+        let received = app_rx.consume_raw_with(|_, pl| Consume::always(Ok(pl.to_vec())));
+        assert_eq!(received.unwrap(), [0xAA]);
+        assert!(!app_rx.has_data());
+    }
+
+    #[test]
+    fn crc_validation() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+        connect(&mut channel, &mut slave, ll_tx, ll_rx);
+        let handle = slave.connection_handle().unwrap();
+
+        // The slave's response carries a CRC computed with the connection's `CRCInit`
+        let header = data::Header::new(data::Llid::DataCont);
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5555,
+            header,
+            &[],
+            DataChannel::new(7),
+        ));
+        assert!(channel.deliver(Device::A, &mut slave, at(3_100)).is_some());
+        match channel.receive(Device::B) {
+            Some(MockPacket::Data {
+                header,
+                payload,
+                crc,
+                ..
+            }) => {
+                let pdu = [&header.to_u16().to_le_bytes()[..], &payload].concat();
+                assert_eq!(crc, ble_crc24(0x55_5555, &pdu));
+            }
+            packet => panic!("expected data PDU, got {:?}", packet),
+        }
+        assert_eq!(slave.connection_stats(handle).unwrap().crc_errors, 0);
+
+        // A CRC computed with a different `CRCInit` is rejected
+        let mut header = data::Header::new(data::Llid::DataCont);
+        header.set_sn(SeqNum::ONE);
+        header.set_nesn(SeqNum::ONE);
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5554,
+            header,
+            &[],
+            DataChannel::new(14),
+        ));
+        assert!(channel.deliver(Device::A, &mut slave, at(10_600)).is_some());
+        assert_eq!(slave.connection_stats(handle).unwrap().crc_errors, 1);
+    }
+
+    #[test]
+    fn master_transmit_window() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+        slave
+            .start_advertise(
+                Duration::millis(100),
+                &[],
+                channel.radio(Device::A),
+                ll_tx,
+                ll_rx,
+            )
+            .unwrap();
+        let advertiser = match channel.receive(Device::B).unwrap() {
+            MockPacket::Advertising {
+                header, payload, ..
+            } => Pdu::from_header_and_payload(header, &mut ByteReader::new(&payload))
+                .unwrap()
+                .sender()
+                .to_owned(),
+            _ => panic!("expected advertising PDU"),
+        };
+
+        // The master's first connection event is 6 ms after the `CONNECT_IND`, and its sleep clock
+        // may be off by up to 500 ppm (4 µs)
+        let lldata = ConnectRequestData::new(
+            0x5065_17AF,
+            0x55_5555,
+            Duration::micros(7_500),
+            Duration::millis(100),
+            ChannelMap::with_all_channels(),
+            7,
+            SleepClockAccuracy::Ppm251To500,
+        )
+        .unwrap()
+        .with_anchor_delay(Duration::millis(6))
+        .unwrap();
+        let master = DeviceAddress::new([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6], AddressKind::Public);
+        let pdu = PduBuf::connect_request(master, advertiser, &lldata);
+        channel.radio(Device::B).send(MockPacket::Advertising {
+            header: pdu.header(),
+            payload: heapless::Vec::from_slice(pdu.payload()).unwrap(),
+            channel: AdvertisingChannel::first(),
+        });
+        let connect_end = at(1_000);
+        let cmd = channel.deliver(Device::A, &mut slave, connect_end).unwrap();
+        assert!(slave.is_connected());
+
+        // The slave listens for the whole exchange, wherever the master's clock puts the anchor
+        let drift = Duration::micros(4);
+        let anchor = connect_end + Duration::millis(6);
+        let window = cmd.window.unwrap();
+        assert!(window.start <= anchor - drift);
+        assert!(anchor + drift + Duration::micros(80) <= window.end());
+
+        let mut header = data::Header::new(data::Llid::DataCont);
+        header.set_sn(SeqNum::ZERO);
+        header.set_nesn(SeqNum::ZERO);
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5555,
+            header,
+            &[],
+            DataChannel::new(7),
+        ));
+        let rx_end = anchor + drift + Duration::micros(80);
+        assert!(channel.deliver(Device::A, &mut slave, rx_end).is_some());
+        assert!(matches!(
+            channel.receive(Device::B),
+            Some(MockPacket::Data { .. })
+        ));
+    }
+
+    #[test]
+    fn channel_map_update_acknowledgement_lost() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+        connect(&mut channel, &mut slave, ll_tx, ll_rx);
+
+        // `LL_CHANNEL_MAP_IND` switching to channels 0-9 at event 3
+        let pdu = [0x01, 0xFF, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00];
+        let mut header = data::Header::new(data::Llid::Control);
+        header.set_payload_length(pdu.len() as u8);
+
+        // The slave's acknowledgement is lost, so the master retransmits the PDU in the next event.
+        // The slave must not process it again.
+        channel.radio(Device::A).lose_next(1);
+        let (next, sent) = send_pdu(&mut channel, &mut slave, 0, 7, header, &pdu);
+        assert_eq!(next, 14);
+        assert!(sent.is_none());
+        let (next, sent) = send_pdu(&mut channel, &mut slave, 1, 14, header, &pdu);
+        assert_eq!(next, 21);
+        assert_eq!(sent.unwrap().nesn(), SeqNum::ONE);
+        assert!(slave.is_connected());
+
+        // The master received the acknowledgement. Event 3 uses the new map: its unmapped channel
+        // 28 is unused, so it's remapped to 28 % 10.
+        let mut header = data::Header::new(data::Llid::DataCont);
+        header.set_sn(SeqNum::ONE);
+        header.set_nesn(SeqNum::ONE);
+        let (next, sent) = send_pdu(&mut channel, &mut slave, 2, 21, header, &[]);
+        assert_eq!(next, 8);
+        assert!(sent.is_some());
+        assert!(slave.is_connected());
+    }
+
+    #[test]
+    fn channel_map_update_instant_passed() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+        connect(&mut channel, &mut slave, ll_tx, ll_rx);
+
+        // The instant is the current event, so the slave can't follow the master anymore
+        let pdu = [0x01, 0xFF, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut header = data::Header::new(data::Llid::Control);
+        header.set_payload_length(pdu.len() as u8);
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5555,
+            header,
+            &pdu,
+            DataChannel::new(7),
+        ));
+        assert!(channel.deliver(Device::A, &mut slave, at(3_100)).is_some());
+        assert!(!slave.is_connected());
+    }
+
+    /// Sends an `LL_LENGTH_REQ` announcing the largest PDUs allowed to a slave connected with an
+    /// RX buffer of `rx_capacity` Bytes and returns its `LL_LENGTH_RSP`.
+    ///
+    /// The Data Length Update procedure is not enabled by default, so it is enabled here.
+    fn length_update(
+        channel: &mut MockChannel,
+        slave: &mut LinkLayer<MockConfig>,
+        rx_capacity: usize,
+        ll_tx: ArrayConsumer<'static, 8>,
+        ll_rx: ArrayProducer<'static, 8>,
+    ) -> [u8; 9] {
+        slave.features |= FeatureSet::LE_PACKET_LENGTH_EXTENSION;
+        channel.radio(Device::A).set_rx_capacity(rx_capacity);
+        connect(channel, slave, ll_tx, ll_rx);
+
+        let pdu = [0x14, 0xFB, 0x00, 0x48, 0x08, 0xFB, 0x00, 0x48, 0x08];
+        let mut header = data::Header::new(data::Llid::Control);
+        header.set_payload_length(pdu.len() as u8);
+        channel.radio(Device::B).send(MockPacket::data(
+            0x5065_17AF,
+            0x55_5555,
+            header,
+            &pdu,
+            DataChannel::new(7),
+        ));
+        assert!(channel.deliver(Device::A, slave, at(3_100)).is_some());
+        match channel.receive(Device::B) {
+            Some(MockPacket::Data {
+                header, payload, ..
+            }) => {
+                assert_eq!(header.llid(), data::Llid::Control);
+                payload[..].try_into().unwrap()
+            }
+            packet => panic!("expected LL_LENGTH_RSP, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn data_length_update_capped_by_rx_buffer() {
+        fn length_rsp(rx_capacity: usize) -> [u8; 9] {
+            let mut channel = MockChannel::new();
+            let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+            let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+            let (ll_rx, _) = Box::leak(Box::new(ArrayQueue::new())).split();
+            length_update(&mut channel, &mut slave, rx_capacity, ll_tx, ll_rx)
+        }
+
+        // Received PDUs are limited by the RX queue, no matter how large the radio's buffer is
+        let rsp = [0x15, 27, 0, 0x48, 0x01, 27, 0, 0x48, 0x01];
+        assert_eq!(length_rsp(MIN_PAYLOAD_BUF), rsp);
+        assert_eq!(length_rsp(104), rsp);
+        assert_eq!(length_rsp(300), rsp);
+    }
+
+    #[test]
+    fn max_length_pdu_after_data_length_update() {
+        let mut channel = MockChannel::new();
+        let mut slave = link_layer([1, 2, 3, 4, 5, 6]);
+        let (_, ll_tx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let (ll_rx, mut app_rx) = Box::leak(Box::new(ArrayQueue::new())).split();
+        let rsp = length_update(&mut channel, &mut slave, 300, ll_tx, ll_rx);
+        let max_rx_octets = u16::from_le_bytes([rsp[1], rsp[2]]);
+
+        // A PDU of the announced size is received and queued
+        let payload = (0..max_rx_octets as u8).collect::<Vec<_>>();
+        let rsp = exchange(
+            &mut channel,
+            &mut slave,
+            1,
+            SeqNum::ONE,
+            SeqNum::ONE,
+            &payload,
+        );
+        assert_eq!(rsp.unwrap().nesn(), SeqNum::ZERO);
+        let received = app_rx
+            .consume_raw_with(|_, raw| Consume::always(Ok(raw.to_vec())))
+            .unwrap();
+        assert_eq!(received, payload);
+
+        // A master exceeding it is NACKed instead of overflowing the RX queue
+        let payload = [0; MIN_PAYLOAD_BUF];
+        let rsp = exchange(
+            &mut channel,
+            &mut slave,
+            2,
+            SeqNum::ZERO,
+            SeqNum::ZERO,
+            &payload,
+        );
+        assert_eq!(rsp.unwrap().nesn(), SeqNum::ZERO);
+        assert!(!app_rx.has_data());
+        assert!(slave.is_connected());
+    }
+}
@@ -117,31 +117,53 @@
 //! its maximum value is 31, resulting in a 27 octet Payload (the maximum) and a 32-bit `MIC`. 4.2
 //! added the possibility of larger packets.
 
+mod access_address;
 pub mod ad_structure;
 pub mod advertising;
 mod channel_map;
 mod comp_id;
 mod connection;
+mod crc;
 pub mod data;
 mod device_address;
+pub mod event;
+pub mod extended;
 mod features;
 pub mod filter;
 pub mod llcp;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod periodic;
 pub mod queue;
 mod responder;
+pub mod rpa;
+pub mod scan;
 mod seq_num;
+mod stats;
 
+pub use self::access_address::{is_valid_access_address, random_access_address};
+pub use self::channel_map::ChannelMap;
 pub use self::comp_id::*;
-pub use self::connection::Connection;
+pub use self::connection::{Connection, ConnectionHandle};
+pub use self::crc::ble_crc24;
 pub use self::device_address::*;
 pub use self::features::*;
 pub use self::responder::*;
+pub use self::stats::*;
 
-use self::advertising::{Pdu, PduBuf};
+use self::advertising::{AdvParams, Pdu, PduBuf, PduType};
+use self::event::{LinkEvent, LinkEventHandler};
+use self::extended::{ExtAdvParams, ExtAdvertiser, MAX_EXT_ADV_DATA};
+use self::llcp::{ConnectionParamRequest, DisconnectReason, MinUsedChannels};
+use self::periodic::{PeriodicAdvParams, PeriodicAdvertiser};
+use self::rpa::{IdentityResolvingKey, RpaGenerator, RESOLVING_LIST_SIZE};
+use self::scan::{AdvReport, AdvReportHandler, Backoff, DuplicateFilter, PendingScan, ScanParams};
 use self::{ad_structure::AdStructure, seq_num::SeqNum};
-use crate::phy::{AdvertisingChannel, DataChannel};
-use crate::time::{Duration, Instant, Timer};
+use crate::phy::{AdvertisingChannel, DataChannel, Phy, PhySet};
+use crate::security::{rng::Rng, EncryptionKey};
+use crate::time::{Duration, Instant, InstantExt, Timer, T_IFS};
 use crate::{bytes::ByteReader, config::*, utils::HexSlice, Error};
+use rand_core::{CryptoRng, RngCore};
 
 /// The CRC polynomial to use for CRC24 generation.
 ///
@@ -174,14 +196,15 @@ pub const MIN_DATA_PDU_BUF: usize = MIN_DATA_PAYLOAD_BUF + 2;
 /// The Advertising PDU header has a length field that is limited to 37 octets, while data channel
 /// PDUs in Bluetooth 4.0 and 4.1 only have a 5-bit length field, limiting the user payload to 27
 /// octets (after subtracting the optional 4-Byte MIC). Bluetooth 4.2 added the optional Packet
-/// Length Extension, which allows data channel PDUs containing up to 251 user payload bytes,
-/// however Rubble doesn't support that yet.
+/// Length Extension, which allows data channel PDUs containing up to 251 user payload bytes.
+/// Rubble only receives such PDUs if the radio's receive buffer is large enough (see
+/// [`Transmitter::rx_payload_capacity`]).
 pub const MIN_PAYLOAD_BUF: usize = 37;
 
 /// Min. size a Link-Layer PDU buffer must have (to cover both advertising and data channels).
 ///
-/// Bluetooth 4.2 also allows exchanging larger PDUs using the Packet Length Extension, but Rubble
-/// does not currently support that.
+/// Bluetooth 4.2 also allows exchanging larger PDUs using the Packet Length Extension, which Rubble
+/// supports for received PDUs if the receive buffer is larger than this.
 pub const MIN_PDU_BUF: usize = MIN_PAYLOAD_BUF + 2 /* 16-bit header */;
 
 /// Min. size a buffer for Link-Layer packets must have to comply with the spec.
@@ -196,6 +219,34 @@ pub const MIN_PACKET_BUF: usize =
     MIN_PDU_BUF +
     3 /* crc */;
 
+/// How long to listen for a scan or connect request after sending an advertising PDU.
+///
+/// Requests are sent `T_IFS` after our PDU ends. The rest covers the time until the returned
+/// `RadioCmd` is applied.
+const ADV_RX_TIMEOUT: Duration = Duration::micros(500);
+
+/// Max. duration of an advertising event on a single channel.
+///
+/// This covers a 37-Byte advertising PDU, a `SCAN_REQ` or `CONNECT_IND` sent in response, and our
+/// `SCAN_RSP`, separated by `T_IFS` (on the LE 1M PHY, at 8 µs per Byte).
+const ADV_EVENT_LEN: Duration = Duration::micros((47 + 44 + 47) * 8 + 2 * T_IFS.to_micros());
+
+/// Airtime added to an advertising event on a single channel by each repetition of the PDU.
+///
+/// This covers a 37-Byte advertising PDU on the LE 1M PHY, preceded by up to `T_IFS` of transmitter
+/// ramp-up.
+const ADV_REPEAT_LEN: Duration = Duration::micros(47 * 8 + T_IFS.to_micros());
+
+/// Returns the duration of an advertising event on a single channel.
+fn adv_channel_len(params: &AdvParams) -> Duration {
+    ADV_EVENT_LEN + ADV_REPEAT_LEN * u32::from(params.repeat)
+}
+
+/// Returns a random `advDelay`, between 0 and `MAX_ADV_DELAY`.
+fn adv_delay<R: Rng + ?Sized>(rng: &mut R) -> Duration {
+    Duration::micros(rng.next_u32() % (advertising::MAX_ADV_DELAY.to_micros() + 1))
+}
+
 /// Link-Layer state machine, according to the Bluetooth spec.
 enum State<C: Config> {
     /// Radio silence: Not listening, not transmitting anything.
@@ -203,21 +254,56 @@ enum State<C: Config> {
 
     /// Device is advertising and wants to establish a connection.
     Advertising {
-        /// Advertising interval.
-        // TODO: check spec for allowed/recommended values and check for them
+        params: AdvParams,
+
+        /// Start of the current (or next) advertising event.
+        event_start: Instant,
+
+        /// Time of the next PDU transmission.
         next_adv: Instant,
-        interval: Duration,
+
+        /// Time of the last PDU transmission.
+        last_adv: Instant,
 
         /// Precomputed PDU payload to copy into the transmitter's buffer.
         pdu: advertising::PduBuf,
 
-        /// Next advertising channel to use for a message.
-        // FIXME: spec check; no idea what order or change delay
+        /// Advertising channel of the last transmission.
         channel: AdvertisingChannel,
 
         data_queues: Option<(ConfConsumer<C>, ConfProducer<C>)>,
     },
 
+    /// Device is listening for advertisements (observer role).
+    Scanning {
+        params: ScanParams,
+
+        /// Advertising channel currently (or last) listened on.
+        channel: AdvertisingChannel,
+
+        /// Time of the next scan window start or end.
+        next_update: Instant,
+
+        /// Whether the radio is currently listening (inside of a scan window).
+        listening: bool,
+
+        handler: C::AdvReportHandler,
+
+        /// Advertisement we've sent a `SCAN_REQ` for, waiting for the `SCAN_RSP`.
+        pending: Option<PendingScan>,
+
+        backoff: Backoff,
+
+        /// Suppresses reports of recently reported advertisements, if enabled in `params`.
+        filter: DuplicateFilter,
+    },
+
+    /// Device is sending extended advertising events (non-connectable and non-scannable).
+    ExtendedAdvertising(ExtAdvertiser),
+
+    /// Device is sending a periodic advertising train, announced by extended advertising events.
+    PeriodicAdvertising(PeriodicAdvertiser),
+
     /// Connected with another device.
     Connection(Connection<C>),
 }
@@ -230,6 +316,41 @@ pub struct LinkLayer<C: Config> {
     dev_addr: DeviceAddress,
     state: State<C>,
     timer: C::Timer,
+    aes: C::Aes,
+    rng: C::Rng,
+
+    /// Generator for our Resolvable Private Address, if privacy is enabled.
+    privacy: Option<RpaGenerator>,
+
+    /// IRKs of bonded peers, used to resolve their private addresses.
+    resolving_list: heapless::Vec<IdentityResolvingKey, RESOLVING_LIST_SIZE>,
+
+    /// `SCAN_RSP` PDU sent in response to scan requests while advertising.
+    scan_response: PduBuf,
+
+    /// Data sent in `AUX_ADV_IND` PDUs while extended advertising.
+    ext_adv_data: heapless::Vec<u8, MAX_EXT_ADV_DATA>,
+
+    /// Company identifier sent in `LL_VERSION_IND` PDUs.
+    company_id: CompanyId,
+
+    /// Link-Layer features we offer to use in connections.
+    features: FeatureSet,
+
+    /// Smoothing factor of the link quality estimate of connections, in units of 1/256.
+    link_quality_smoothing: u8,
+
+    /// Handle to assign to the next connection.
+    next_handle: ConnectionHandle,
+
+    /// Connection parameters to request when a new connection doesn't satisfy them.
+    preferred_conn_params: Option<ConnectionParamRequest>,
+
+    /// Why the last connection was closed, until retrieved by the application.
+    disconnect_reason: Option<DisconnectReason>,
+
+    /// Receiver of connection events, once set by the application.
+    event_handler: Option<C::LinkEventHandler>,
 }
 
 impl<C: Config> LinkLayer<C> {
@@ -239,14 +360,168 @@ impl<C: Config> LinkLayer<C> {
     ///
     /// * **`dev_addr`**: The device address to broadcast as.
     /// * **`timer`**: A `Timer` implementation.
-    /// * **`tx`**: Input queue of packets to transmit when connected.
-    /// * **`rx`**: Output queue of received packets when connected.
-    pub fn new(dev_addr: DeviceAddress, timer: C::Timer) -> Self {
+    /// * **`rng`**: The random number generator to use.
+    pub fn new(dev_addr: DeviceAddress, timer: C::Timer, rng: C::Rng) -> Self
+    where
+        C::Aes: Default,
+    {
+        Self::with_aes(dev_addr, timer, C::Aes::default(), rng)
+    }
+
+    /// Creates a new Link-Layer that uses `aes` for encrypting connections.
+    ///
+    /// This allows using an AES provider that needs to be constructed manually, such as one backed
+    /// by a hardware peripheral.
+    pub fn with_aes(dev_addr: DeviceAddress, timer: C::Timer, aes: C::Aes, rng: C::Rng) -> Self {
         trace!("new LinkLayer, dev={:?}", dev_addr);
         Self {
             dev_addr,
             state: State::Standby,
             timer,
+            aes,
+            rng,
+            privacy: None,
+            resolving_list: heapless::Vec::new(),
+            scan_response: PduBuf::scan_response(dev_addr, &[]).unwrap(),
+            ext_adv_data: heapless::Vec::new(),
+            company_id: C::COMPANY_ID,
+            features: FeatureSet::supported(),
+            link_quality_smoothing: DEFAULT_LINK_QUALITY_SMOOTHING,
+            next_handle: ConnectionHandle::default(),
+            preferred_conn_params: None,
+            disconnect_reason: None,
+            event_handler: None,
+        }
+    }
+
+    /// Returns the device address this Link-Layer uses.
+    ///
+    /// This is the identity address passed to [`LinkLayer::new`]. When privacy is enabled, a
+    /// Resolvable Private Address is used over the air instead, which is returned by
+    /// [`LinkLayer::own_address`].
+    pub fn device_address(&self) -> DeviceAddress {
+        self.dev_addr
+    }
+
+    /// Returns the device address currently used over the air.
+    pub fn own_address(&self) -> DeviceAddress {
+        match &self.privacy {
+            Some(rpa) => rpa.address(),
+            None => self.dev_addr,
+        }
+    }
+
+    /// Enables LE Privacy, advertising with a Resolvable Private Address generated from `irk`.
+    ///
+    /// A new address is generated every `interval` (see [`rpa::DEFAULT_ROTATION_INTERVAL`]). `rng`
+    /// must be a cryptographically secure random number generator.
+    ///
+    /// The new address is used starting with the next call to [`LinkLayer::start_advertise`].
+    pub fn enable_privacy<R: RngCore + CryptoRng>(
+        &mut self,
+        irk: IdentityResolvingKey,
+        interval: Duration,
+        rng: &mut R,
+    ) {
+        let now = self.timer.now();
+        self.privacy = Some(RpaGenerator::new(&mut self.aes, irk, interval, now, rng));
+    }
+
+    /// Disables LE Privacy, going back to using the identity address over the air.
+    pub fn disable_privacy(&mut self) {
+        self.privacy = None;
+    }
+
+    /// Adds the IRK of a bonded peer to the resolving list.
+    ///
+    /// Private addresses of connecting masters are resolved against this list, and the index of the
+    /// matching entry can be queried via [`Connection::resolved_peer`]. Returns the index of the
+    /// new entry, or `Error::Eof` if the list is full.
+    pub fn add_peer_irk(&mut self, irk: IdentityResolvingKey) -> Result<usize, Error> {
+        self.resolving_list.push(irk).map_err(|_| Error::Eof)?;
+        Ok(self.resolving_list.len() - 1)
+    }
+
+    /// Removes all entries from the resolving list.
+    pub fn clear_resolving_list(&mut self) {
+        self.resolving_list.clear();
+    }
+
+    /// Sets the company identifier sent to connected devices in `LL_VERSION_IND` PDUs.
+    ///
+    /// This overrides [`Config::COMPANY_ID`] and takes effect with the next connection.
+    pub fn set_company_id(&mut self, company_id: CompanyId) {
+        self.company_id = company_id;
+    }
+
+    /// Sets the connection parameters the application prefers.
+    ///
+    /// When a connection is established with parameters that don't satisfy `params` (see
+    /// [`ConnectionParamRequest::is_satisfied_by`]), the Link-Layer immediately asks the master to
+    /// update them. It uses the Connection Parameters Request Procedure if both devices support it
+    /// ([`FeatureSet::CONN_PARAM_REQ`]), and an L2CAP Connection Parameter Update Request
+    /// otherwise. If the master accepts, it announces the new parameters with an
+    /// `LL_CONNECTION_UPDATE_IND`. Otherwise the connection continues with the current parameters.
+    ///
+    /// By default, no parameters are requested. This takes effect with the next connection.
+    pub fn set_preferred_conn_params(&mut self, params: Option<ConnectionParamRequest>) {
+        self.preferred_conn_params = params;
+    }
+
+    /// Restricts the Link-Layer features used in connections to `features`.
+    ///
+    /// By default, all features in [`FeatureSet::supported`] are used. Features the radio can't use
+    /// (see [`Transmitter::supported_features`]) are left out in any case. This takes effect with
+    /// the next connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `features` contains features that Rubble doesn't support.
+    pub fn set_features(&mut self, features: FeatureSet) -> Result<(), Error> {
+        if FeatureSet::supported().contains(features) {
+            self.features = features;
+            Ok(())
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+
+    /// Sets the smoothing factor of the [`LinkQuality`] estimate to `smoothing / 256`.
+    ///
+    /// The default is [`DEFAULT_LINK_QUALITY_SMOOTHING`]. This takes effect with the next
+    /// connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `smoothing` is 0, which would never update the estimate.
+    pub fn set_link_quality_smoothing(&mut self, smoothing: u8) -> Result<(), Error> {
+        if smoothing == 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        self.link_quality_smoothing = smoothing;
+        Ok(())
+    }
+
+    /// Sets the handler to report connection events to.
+    ///
+    /// Events that occur before a handler is set are discarded.
+    pub fn set_event_handler(&mut self, handler: C::LinkEventHandler) {
+        self.event_handler = Some(handler);
+    }
+
+    /// Passes `event` to the event handler, if one is set.
+    fn report(&mut self, event: LinkEvent) {
+        if let Some(handler) = &mut self.event_handler {
+            handler.handle_event(event);
+        }
+    }
+
+    /// Reports the events that occurred in the current connection since the last call.
+    fn report_connection_events(&mut self) {
+        if let (State::Connection(conn), Some(handler)) = (&mut self.state, &mut self.event_handler)
+        {
+            conn.take_events(|event| handler.handle_event(event));
         }
     }
 
@@ -255,7 +530,15 @@ impl<C: Config> LinkLayer<C> {
         &mut self.timer
     }
 
+    /// Returns a reference to the random number generator used by the Link-Layer.
+    pub fn rng(&mut self) -> &mut C::Rng {
+        &mut self.rng
+    }
+
     /// Starts advertising this device, optionally sending data along with the advertising PDU.
+    ///
+    /// This advertises connectably on all primary advertising channels. Use
+    /// [`LinkLayer::start_advertising`] for more control.
     pub fn start_advertise(
         &mut self,
         interval: Duration,
@@ -263,15 +546,45 @@ impl<C: Config> LinkLayer<C> {
         transmitter: &mut C::Transmitter,
         tx: ConfConsumer<C>,
         rx: ConfProducer<C>,
+    ) -> Result<NextUpdate, Error> {
+        self.start_advertising(AdvParams::new(interval), data, transmitter, tx, rx)
+    }
+
+    /// Starts advertising this device according to `params`, optionally sending data along with
+    /// the advertising PDU.
+    ///
+    /// In every advertising event, the PDU is sent on each channel in `params.channels`, and the
+    /// radio listens for requests in between. Events start every `params.interval` plus a
+    /// pseudo-random delay of up to [`MAX_ADV_DELAY`](advertising::MAX_ADV_DELAY).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `params` are invalid (see [`AdvParams`]), or an error if
+    /// `data` doesn't fit in the PDU.
+    pub fn start_advertising(
+        &mut self,
+        params: AdvParams,
+        data: &[AdStructure<'_>],
+        transmitter: &mut C::Transmitter,
+        tx: ConfConsumer<C>,
+        rx: ConfProducer<C>,
     ) -> Result<NextUpdate, Error> {
         // TODO tear down existing connection?
 
-        let pdu = PduBuf::discoverable(self.dev_addr, data)?;
-        debug!("start_advertise: adv_data = {:?}", data);
+        params.validate()?;
+        let pdu = params.pdu(self.own_address(), data)?;
+        self.scan_response
+            .set_advertiser_address(self.own_address());
+        debug!("start_advertise: {:?}, adv_data = {:?}", params, data);
         debug!("start_advertise: PDU = {:?}", pdu);
+        defmt_debug!("start_advertise: interval = {}", params.interval);
+
+        let now = self.timer().now();
         self.state = State::Advertising {
-            next_adv: self.timer().now(),
-            interval,
+            params,
+            event_start: now,
+            next_adv: now,
+            last_adv: now,
             pdu,
             channel: AdvertisingChannel::first(),
             data_queues: Some((tx, rx)),
@@ -279,6 +592,258 @@ impl<C: Config> LinkLayer<C> {
         Ok(self.update_timer(transmitter).next_update)
     }
 
+    /// Switches the type of advertising PDU sent while advertising, along with its data.
+    ///
+    /// This changes between connectable (`ADV_IND`), scannable (`ADV_SCAN_IND`) and
+    /// non-connectable (`ADV_NONCONN_IND`) advertising without restarting. `data` has to be passed
+    /// again, since connectable PDUs include a Flags AD structure (see [`PduBuf::discoverable`]).
+    /// The new PDU is used starting with the next transmission. `CONNECT_IND`s are only accepted
+    /// while advertising with `ADV_IND`, and scan requests are ignored with `ADV_NONCONN_IND`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if the Link-Layer isn't advertising or `pdu_type` is not one of
+    /// the types above, or an error if `data` doesn't fit in the PDU. The current PDU stays in use
+    /// in that case.
+    ///
+    /// [`PduBuf::discoverable`]: advertising::PduBuf::discoverable
+    pub fn set_adv_pdu_type(
+        &mut self,
+        pdu_type: PduType,
+        data: &[AdStructure<'_>],
+    ) -> Result<(), Error> {
+        let own_addr = self.own_address();
+        match &mut self.state {
+            State::Advertising { params, pdu, .. } => {
+                let new_params = AdvParams {
+                    pdu_type,
+                    ..*params
+                };
+                new_params.validate()?;
+                *pdu = new_params.pdu(own_addr, data)?;
+                *params = new_params;
+                debug!("advertising PDU changed: {:?}", pdu);
+                Ok(())
+            }
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    /// Sets the data sent in response to scan requests while advertising.
+    ///
+    /// Scanning devices request this data when actively scanning, so it can be used for
+    /// information that doesn't fit in the advertising PDU (such as the full device name). By
+    /// default, no scan response data is sent.
+    ///
+    /// # Errors
+    ///
+    /// If `data` doesn't fit in a single PDU, an error will be returned and the previously set
+    /// data remains in use.
+    pub fn set_scan_response_data(&mut self, data: &[AdStructure<'_>]) -> Result<(), Error> {
+        self.scan_response = PduBuf::scan_response(self.own_address(), data)?;
+        debug!("scan response: {:?}", self.scan_response);
+        Ok(())
+    }
+
+    /// Starts extended advertising according to `params`.
+    ///
+    /// Every advertising event consists of an `ADV_EXT_IND` PDU on each channel in
+    /// `params.channels`, pointing to an `AUX_ADV_IND` PDU on a secondary advertising channel. The
+    /// `AUX_ADV_IND` carries the data set with [`LinkLayer::set_extended_adv_data`]. Extended
+    /// advertising is neither connectable nor scannable, and the radio only transmits in this
+    /// state, so the returned `Cmd`s always turn it off. Advertising continues until
+    /// [`LinkLayer::stop_extended_advertising`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `params` are invalid (see [`ExtAdvParams`]), or
+    /// `Error::Eof` if the advertising data doesn't fit in the `AUX_ADV_IND` or the transmitter's
+    /// buffer.
+    pub fn start_extended_advertising(
+        &mut self,
+        params: ExtAdvParams,
+        transmitter: &mut C::Transmitter,
+    ) -> Result<NextUpdate, Error> {
+        let now = self.timer.now();
+        let advertiser = ExtAdvertiser::new(
+            params,
+            self.own_address(),
+            C::SLEEP_CLOCK_ACCURACY_PPM,
+            transmitter.tx_payload_buf().len(),
+            now,
+            &mut self.rng,
+        )?;
+        let pdu = advertiser.aux_pdu(&self.ext_adv_data)?;
+
+        debug!(
+            "start_extended_advertising: {:?}, AUX_ADV_IND = {:?}",
+            params, pdu
+        );
+        defmt_debug!("start_extended_advertising");
+        self.state = State::ExtendedAdvertising(advertiser);
+        Ok(self.update_timer(transmitter).next_update)
+    }
+
+    /// Sets the data sent in `AUX_ADV_IND` PDUs while extended advertising.
+    ///
+    /// `data` must be a sequence of encoded AD structures (see [`AdStructure`]). It can be up to
+    /// [`MAX_EXT_ADV_DATA`] Bytes long, minus the space taken by the extended header. If extended
+    /// advertising is already running, the new data is sent starting with the next advertising
+    /// event, and scanners are informed about the change. By default, no data is sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Eof` if `data` doesn't fit in the PDU or the transmitter's buffer. The
+    /// previously set data remains in use in that case.
+    pub fn set_extended_adv_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let data = heapless::Vec::from_slice(data).map_err(|_| Error::Eof)?;
+        if let State::ExtendedAdvertising(advertiser) = &mut self.state {
+            advertiser.aux_pdu(&data)?;
+            debug!("extended advertising data: {}", HexSlice(&data[..]));
+            advertiser.data_changed(data);
+            return Ok(());
+        }
+
+        debug!("extended advertising data: {}", HexSlice(&data[..]));
+        self.ext_adv_data = data;
+        Ok(())
+    }
+
+    /// Stops extended advertising and returns to standby.
+    ///
+    /// Returns an error if the Link-Layer isn't currently extended advertising.
+    pub fn stop_extended_advertising(&mut self) -> Result<Cmd, Error> {
+        let advertiser = match &mut self.state {
+            State::ExtendedAdvertising(advertiser) => advertiser,
+            _ => return Err(Error::InvalidValue),
+        };
+        if let Some(data) = advertiser.take_pending_data() {
+            self.ext_adv_data = data;
+        }
+
+        debug!("stop_extended_advertising, standby");
+        self.state = State::Standby;
+        Ok(Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::Disable,
+            window: None,
+            queued_work: false,
+        })
+    }
+
+    /// Starts periodic advertising of `data` according to `params`.
+    ///
+    /// `data` is sent in `AUX_SYNC_IND` PDUs every `params.sync_interval`. Scanners find this
+    /// periodic advertising train through extended advertising events sent every
+    /// `params.adv_interval`. The radio only transmits in this state, so the returned `Cmd`s always
+    /// turn it off. Periodic advertising continues until [`LinkLayer::stop_periodic_advertising`]
+    /// is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `params` are invalid (see [`PeriodicAdvParams`]), or
+    /// `Error::Eof` if `data` doesn't fit in a PDU or the transmitter's buffer.
+    pub fn start_periodic_advertising(
+        &mut self,
+        params: PeriodicAdvParams,
+        data: &[AdStructure<'_>],
+        transmitter: &mut C::Transmitter,
+    ) -> Result<NextUpdate, Error> {
+        let now = self.timer.now();
+        let advertiser = PeriodicAdvertiser::new(
+            params,
+            self.own_address(),
+            data,
+            C::SLEEP_CLOCK_ACCURACY_PPM,
+            now,
+            &mut self.rng,
+        )?;
+        if advertiser.max_payload_len() > transmitter.tx_payload_buf().len() {
+            return Err(Error::Eof);
+        }
+
+        debug!(
+            "start_periodic_advertising: {:?}, data = {:?}",
+            params, data
+        );
+        defmt_debug!("start_periodic_advertising");
+        self.state = State::PeriodicAdvertising(advertiser);
+        Ok(self.update_timer(transmitter).next_update)
+    }
+
+    /// Stops periodic advertising and returns to standby.
+    ///
+    /// Returns an error if the Link-Layer isn't currently sending a periodic advertising train.
+    pub fn stop_periodic_advertising(&mut self) -> Result<Cmd, Error> {
+        if !self.is_periodic_advertising() {
+            return Err(Error::InvalidValue);
+        }
+
+        debug!("stop_periodic_advertising, standby");
+        self.state = State::Standby;
+        Ok(Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::Disable,
+            window: None,
+            queued_work: false,
+        })
+    }
+
+    /// Starts scanning for advertisements.
+    ///
+    /// Every advertising PDU received while scanning is reported to `handler`. Scanning continues
+    /// until [`LinkLayer::stop_scanning`] is called. The returned `Cmd` has to be applied to the
+    /// radio.
+    pub fn start_scanning(&mut self, params: ScanParams, handler: C::AdvReportHandler) -> Cmd {
+        // TODO tear down existing connection?
+
+        let channel = params.scan_channels().first().unwrap();
+        let now = self.timer().now();
+        let next_update = now + params.window();
+
+        debug!("start_scanning: {:?}", params);
+        defmt_debug!("start_scanning");
+        self.state = State::Scanning {
+            params,
+            channel,
+            next_update,
+            listening: true,
+            handler,
+            pending: None,
+            backoff: Backoff::new(self.rng.next_u32()),
+            filter: DuplicateFilter::new(&params),
+        };
+
+        Cmd {
+            radio: RadioCmd::ListenAdvertising {
+                channel,
+                rx_timeout: None,
+            },
+            next_update: NextUpdate::At(next_update),
+            window: self.adv_window(),
+            queued_work: false,
+        }
+    }
+
+    /// Stops scanning and returns to standby.
+    ///
+    /// The `AdvReportHandler` passed to [`LinkLayer::start_scanning`] is dropped. Returns an error
+    /// if the Link-Layer isn't currently scanning.
+    pub fn stop_scanning(&mut self) -> Result<Cmd, Error> {
+        if !self.is_scanning() {
+            return Err(Error::InvalidValue);
+        }
+
+        debug!("stop_scanning, standby");
+        self.state = State::Standby;
+        Ok(Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::Disable,
+            window: None,
+            queued_work: false,
+        })
+    }
+
     /// Process an incoming packet from an advertising channel.
     ///
     /// The access address of the packet must be `ADVERTISING_ADDRESS`.
@@ -290,6 +855,7 @@ impl<C: Config> LinkLayer<C> {
     /// * **`header`**: The header of the received packet.
     /// * **`payload`**: The packet payload following the header.
     /// * **`crc_ok`**: Whether the packet's CRC is correct.
+    /// * **`rssi`**: The received signal strength in dBm, or `None` if the radio can't measure it.
     pub fn process_adv_packet(
         &mut self,
         rx_end: Instant,
@@ -297,43 +863,178 @@ impl<C: Config> LinkLayer<C> {
         header: advertising::Header,
         payload: &[u8],
         crc_ok: bool,
+        rssi: Option<i8>,
     ) -> Cmd {
         let pdu = advertising::Pdu::from_header_and_payload(header, &mut ByteReader::new(payload));
+        let own_addr = self.own_address();
 
         if let Ok(pdu) = pdu {
             if let State::Advertising {
+                params,
                 channel,
                 data_queues,
                 ..
             } = &mut self.state
             {
-                if crc_ok && pdu.receiver() == Some(&self.dev_addr) {
+                if crc_ok && pdu.receiver() == Some(&own_addr) {
                     // Got a packet addressed at us, can be a scan or connect request
                     match pdu {
-                        Pdu::ScanRequest { .. } => {
-                            let scan_data = &[]; // TODO make this configurable
-                            let response = PduBuf::scan_response(self.dev_addr, scan_data).unwrap();
+                        Pdu::ScanRequest { .. } if params.pdu_type != PduType::AdvNonconnInd => {
+                            let response = &self.scan_response;
+                            let buf = tx.tx_payload_buf();
+                            buf[..response.payload().len()].copy_from_slice(response.payload());
                             tx.transmit_advertising(response.header(), *channel);
 
                             // Log after responding to meet timing
                             debug!("-> SCAN RESP: {:?}", response);
                         }
-                        Pdu::ConnectRequest { lldata, .. } => {
+                        Pdu::ConnectRequest { lldata, .. }
+                            if params.pdu_type == PduType::AdvInd
+                                && !connection::acceptable_conn_params::<C>(
+                                    lldata.interval(),
+                                    lldata.slave_latency(),
+                                    lldata.supervision_timeout(),
+                                ) =>
+                        {
+                            // Keep advertising, the master will time out the connection attempt
+                            warn!("ignoring CONNECT_IND with unacceptable parameters");
+                        }
+                        Pdu::ConnectRequest {
+                            initiator_addr,
+                            lldata,
+                            ..
+                        } if params.pdu_type == PduType::AdvInd => {
                             trace!("ADV<- CONN! {:?}", pdu);
 
+                            let resolved = rpa::resolve_any(
+                                &mut self.aes,
+                                &self.resolving_list,
+                                &initiator_addr,
+                            );
+                            if let Some(index) = resolved {
+                                debug!("resolved peer {} (IRK #{})", initiator_addr, index);
+                            }
+
+                            let features = self.features & tx.supported_features();
                             let (tx, rx) = data_queues.take().unwrap();
-                            let (conn, cmd) = Connection::create(&lldata, rx_end, tx, rx);
+                            let handle = self.next_handle;
+                            self.next_handle = handle.next();
+                            let (mut conn, cmd) = Connection::create(
+                                handle,
+                                initiator_addr,
+                                resolved,
+                                &lldata,
+                                rx_end,
+                                tx,
+                                rx,
+                                self.company_id,
+                                features,
+                                LinkQuality::new(self.link_quality_smoothing),
+                            );
+                            if let Some(params) = self.preferred_conn_params {
+                                if !params.is_satisfied_by(
+                                    conn.connection_interval(),
+                                    conn.slave_latency(),
+                                    conn.supervision_timeout(),
+                                ) {
+                                    debug!("requesting preferred connection parameters");
+                                    conn.request_conn_params(params);
+                                }
+                            }
+                            defmt_debug!("connected: {}, {}", conn, cmd);
+                            let event = LinkEvent::Connected {
+                                handle,
+                                peer: conn.peer_address(),
+                                interval: conn.connection_interval(),
+                                slave_latency: conn.slave_latency(),
+                                supervision_timeout: conn.supervision_timeout(),
+                            };
                             self.state = State::Connection(conn);
+                            self.report(event);
                             return cmd;
                         }
                         _ => {}
                     }
                 }
             }
+
+            if let State::Scanning {
+                params,
+                channel,
+                handler,
+                pending,
+                backoff,
+                filter,
+                ..
+            } = &mut self.state
+            {
+                if let Some(scan) = pending.take() {
+                    let is_response = match pdu {
+                        Pdu::ScanResponse {
+                            advertiser_addr, ..
+                        } => crc_ok && advertiser_addr == *scan.addr(),
+                        _ => false,
+                    };
+
+                    if is_response {
+                        backoff.success();
+                        let report = scan.report(Some(&payload[6..]));
+                        if filter.should_report(&report, rx_end) {
+                            handler.report(&report);
+                        }
+                        let channel = *channel;
+                        return Cmd {
+                            radio: RadioCmd::ListenAdvertising {
+                                channel,
+                                rx_timeout: self.adv_rx_timeout(),
+                            },
+                            next_update: NextUpdate::Keep,
+                            window: self.adv_window(),
+                            queued_work: false,
+                        };
+                    }
+
+                    backoff.failure();
+                    let report = scan.report(None);
+                    if filter.should_report(&report, rx_end) {
+                        handler.report(&report);
+                    }
+                }
+
+                // Directed advertisements are only reported when they're directed at us
+                let for_us = match pdu {
+                    Pdu::ConnectableDirected { .. } => pdu.receiver() == Some(&own_addr),
+                    _ => true,
+                };
+
+                if crc_ok && for_us {
+                    if let Some(report) = AdvReport::new(&pdu, payload, rssi) {
+                        let scannable = matches!(
+                            report.pdu_type,
+                            advertising::PduType::AdvInd | advertising::PduType::AdvScanInd
+                        );
+
+                        if params.is_active() && scannable && backoff.should_request() {
+                            let request = PduBuf::scan_request(own_addr, report.addr).unwrap();
+                            let buf = tx.tx_payload_buf();
+                            buf[..request.payload().len()].copy_from_slice(request.payload());
+                            tx.transmit_advertising(request.header(), *channel);
+
+                            // The report is sent when the response arrives (or doesn't)
+                            *pending = Some(PendingScan::new(&report));
+
+                            // Log after sending the request to meet timing
+                            debug!("-> SCAN REQ: {:?}", request);
+                        } else if filter.should_report(&report, rx_end) {
+                            handler.report(&report);
+                        }
+                    }
+                }
+            }
         }
 
         trace!(
-            "ADV<- {}{:?}, {:?}\n{:?}\n",
+            "ADV<- {}{:?}, {}\n{:?}\n",
             if crc_ok { "" } else { "BADCRC " },
             header,
             HexSlice(payload),
@@ -342,12 +1043,19 @@ impl<C: Config> LinkLayer<C> {
 
         match self.state {
             State::Standby => unreachable!("standby, can't receive packets"),
+            State::ExtendedAdvertising(_) | State::PeriodicAdvertising(_) => {
+                unreachable!("extended advertising, can't receive packets")
+            }
             State::Connection { .. } => unreachable!("process_adv_packet called while connected"),
-            State::Advertising { channel, .. } => {
+            State::Advertising { channel, .. } | State::Scanning { channel, .. } => {
                 Cmd {
-                    radio: RadioCmd::ListenAdvertising { channel },
+                    radio: RadioCmd::ListenAdvertising {
+                        channel,
+                        rx_timeout: self.adv_rx_timeout(),
+                    },
                     // no change
                     next_update: NextUpdate::Keep,
+                    window: self.adv_window(),
                     queued_work: false,
                 }
             }
@@ -363,15 +1071,72 @@ impl<C: Config> LinkLayer<C> {
         payload: &[u8],
         crc_ok: bool,
     ) -> Cmd {
+        let info = DataPacketInfo::new(rx_end, crc_ok);
+        self.process_data_packet_with_info(tx, header, payload, info)
+    }
+
+    /// Process an incoming data channel packet whose access address was received at
+    /// `address_time`.
+    ///
+    /// This works like [`process_data_packet`](Self::process_data_packet), but should be used when
+    /// the radio captures a hardware timestamp of the end of the access address (eg. the nRF
+    /// `ADDRESS` event). The Link-Layer then resynchronizes to the actual start of the master's
+    /// packets, which allows using narrower receive windows. Packets with an incorrect CRC are
+    /// never used for synchronization.
+    pub fn process_timestamped_data_packet(
+        &mut self,
+        rx_end: Instant,
+        address_time: Instant,
+        tx: &mut C::Transmitter,
+        header: data::Header,
+        payload: &[u8],
+        crc_ok: bool,
+    ) -> Cmd {
+        let mut info = DataPacketInfo::new(rx_end, crc_ok);
+        info.address_time = Some(address_time);
+        self.process_data_packet_with_info(tx, header, payload, info)
+    }
+
+    /// Process an incoming data channel packet, along with everything the radio measured while
+    /// receiving it.
+    ///
+    /// This is the most complete form of [`process_data_packet`] and
+    /// [`process_timestamped_data_packet`]. The RSSI in `info` is used for the connection's
+    /// [`LinkQuality`] estimate.
+    ///
+    /// [`process_data_packet`]: Self::process_data_packet
+    /// [`process_timestamped_data_packet`]: Self::process_timestamped_data_packet
+    pub fn process_data_packet_with_info(
+        &mut self,
+        tx: &mut C::Transmitter,
+        header: data::Header,
+        payload: &[u8],
+        info: DataPacketInfo,
+    ) -> Cmd {
+        let DataPacketInfo {
+            rx_end,
+            address_time,
+            crc_ok,
+            rssi,
+        } = info;
         if let State::Connection(conn) = &mut self.state {
-            match conn.process_data_packet(rx_end, tx, header, payload, crc_ok) {
-                Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended, standby");
+            conn.record_link_quality(crc_ok, rssi);
+            let aes = &mut self.aes;
+            match conn.process_data_packet(rx_end, address_time, tx, aes, header, payload, crc_ok) {
+                Ok(cmd) => {
+                    self.report_connection_events();
+                    cmd
+                }
+                Err(reason) => {
+                    debug!("connection ended ({:?}), standby", reason);
+                    defmt_debug!("connection ended ({}), standby", reason);
                     self.state = State::Standby;
+                    self.disconnect_reason = Some(reason);
+                    self.report(LinkEvent::Disconnected { reason });
                     Cmd {
                         next_update: NextUpdate::Disable,
                         radio: RadioCmd::Off,
+                        window: None,
                         // FIXME(#70) this might need to be changed to `true`
                         queued_work: false,
                     }
@@ -382,83 +1147,453 @@ impl<C: Config> LinkLayer<C> {
         }
     }
 
-    /// Update the Link-Layer state after the timer expires.
+    /// Informs the Link-Layer that data was queued for transmission.
     ///
-    /// This should be called whenever the timer set by the last returned `Cmd` has expired.
+    /// While connected with a non-zero slave latency, the Link-Layer may skip connection events
+    /// when there's nothing to send. Calling this after queueing data on connection `handle` makes
+    /// it listen for the next connection event instead, so the data is sent without waiting for
+    /// the skipped events.
     ///
-    /// # Parameters
+    /// Returns a `Cmd` to apply if the radio or timer configuration has to change, or `None`
+    /// otherwise. Returns `Error::InvalidValue` if connection `handle` was closed.
+    pub fn wake_for_tx(&mut self, handle: ConnectionHandle) -> Result<Option<Cmd>, Error> {
+        let now = self.timer.now();
+        Ok(self.connection_mut(handle)?.wake(now))
+    }
+
+    /// Returns the connection identified by `handle`.
     ///
-    /// * `tx`: A `Transmitter` for sending packets.
-    pub fn update_timer(&mut self, tx: &mut C::Transmitter) -> Cmd {
+    /// Returns `Error::InvalidValue` if that connection was closed.
+    fn connection_mut(&mut self, handle: ConnectionHandle) -> Result<&mut Connection<C>, Error> {
         match &mut self.state {
-            State::Advertising {
-                next_adv,
-                interval,
-                pdu,
-                channel,
-                ..
-            } => {
-                *channel = channel.cycle();
-                let payload = pdu.payload();
-                let buf = tx.tx_payload_buf();
-                buf[..payload.len()].copy_from_slice(payload);
-
-                // FIXME According to the spec, this has to broadcast on all advertising channels
+            State::Connection(conn) if conn.handle() == handle => Ok(conn),
+            _ => Err(Error::InvalidValue),
+        }
+    }
 
-                tx.transmit_advertising(pdu.header(), *channel);
+    /// Starts closing the connection `handle` by sending an `LL_TERMINATE_IND` PDU with `reason`.
+    ///
+    /// The PDU is sent instead of the next data PDU. The connection is closed once the master
+    /// acknowledges it, or when the supervision timeout elapses without an acknowledgement. The
+    /// Link-Layer then returns to standby, and [`take_disconnect_reason`] returns
+    /// `DisconnectReason::LocalHostTerminated`.
+    ///
+    /// Like [`wake_for_tx`], returns a `Cmd` to apply if the radio or timer configuration has to
+    /// change. Returns `Error::InvalidValue` if the connection was already closed.
+    ///
+    /// [`take_disconnect_reason`]: Self::take_disconnect_reason
+    /// [`wake_for_tx`]: Self::wake_for_tx
+    pub fn disconnect(
+        &mut self,
+        handle: ConnectionHandle,
+        reason: DisconnectReason,
+    ) -> Result<Option<Cmd>, Error> {
+        let now = self.timer.now();
+        Ok(self.connection_mut(handle)?.terminate(reason, now))
+    }
 
-                *next_adv += *interval;
+    /// Starts the PHY Update Procedure, asking the master to switch connection `handle` to new
+    /// PHYs.
+    ///
+    /// `LL_PHY_REQ` is sent instead of the next data PDU. If the master agrees, the new PHY is used
+    /// starting with the connection event at the instant it indicates (see
+    /// [`RadioCmd::ListenData`]). The master may also keep the current PHY.
+    ///
+    /// Since the radio interface uses a single PHY for both directions, `tx_phy` and `rx_phy` must
+    /// be equal. Like [`wake_for_tx`], returns a `Cmd` to apply if the radio or timer configuration
+    /// has to change.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if the connection was closed, if `tx_phy` and `rx_phy` differ,
+    /// or if the PHY is not supported by the features of both devices. Returns `Error::WouldBlock`
+    /// if a PHY update requested earlier is still in progress.
+    ///
+    /// [`wake_for_tx`]: Self::wake_for_tx
+    pub fn request_phy(
+        &mut self,
+        handle: ConnectionHandle,
+        tx_phy: Phy,
+        rx_phy: Phy,
+    ) -> Result<Option<Cmd>, Error> {
+        let now = self.timer.now();
+        let conn = self.connection_mut(handle)?;
+        if tx_phy != rx_phy {
+            return Err(Error::InvalidValue);
+        }
+        conn.request_phy(tx_phy, now)
+    }
 
-                Cmd {
-                    radio: RadioCmd::ListenAdvertising { channel: *channel },
-                    next_update: NextUpdate::At(*next_adv),
-                    queued_work: false,
+    /// Starts the Minimum Number of Used Channels Procedure, asking the master to use at least
+    /// `min_used_channels` data channels on connection `handle` while on any of `phys`.
+    ///
+    /// `LL_MIN_USED_CHANNELS_IND` is sent instead of the next data PDU. Like [`wake_for_tx`],
+    /// returns a `Cmd` to apply if the radio or timer configuration has to change.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if the connection was closed, if `phys` is empty,
+    /// `min_used_channels` is not in range `2..=37`, or if the procedure is not supported by the
+    /// features of both devices ([`FeatureSet::MIN_USED_CHANNELS`]). Returns `Error::WouldBlock`
+    /// if an indication requested earlier hasn't been sent yet.
+    ///
+    /// [`wake_for_tx`]: Self::wake_for_tx
+    pub fn request_min_used_channels(
+        &mut self,
+        handle: ConnectionHandle,
+        phys: PhySet,
+        min_used_channels: u8,
+    ) -> Result<Option<Cmd>, Error> {
+        let now = self.timer.now();
+        let conn = self.connection_mut(handle)?;
+        conn.request_min_used_channels(MinUsedChannels::new(phys, min_used_channels)?, now)
+    }
+
+    /// Asks the master to update the parameters of connection `handle` to `params`.
+    ///
+    /// As for [`set_preferred_conn_params`], the Connection Parameters Request Procedure is used if
+    /// both devices support it ([`FeatureSet::CONN_PARAM_REQ`]), and an L2CAP Connection Parameter
+    /// Update Request otherwise. The request is sent instead of the next data PDU. Like
+    /// [`wake_for_tx`], returns a `Cmd` to apply if the radio or timer configuration has to change.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if the connection was closed. Returns `Error::WouldBlock` if a
+    /// request made earlier is still in progress.
+    ///
+    /// [`set_preferred_conn_params`]: Self::set_preferred_conn_params
+    /// [`wake_for_tx`]: Self::wake_for_tx
+    pub fn request_conn_param_update(
+        &mut self,
+        handle: ConnectionHandle,
+        params: ConnectionParamRequest,
+    ) -> Result<Option<Cmd>, Error> {
+        let now = self.timer.now();
+        self.connection_mut(handle)?
+            .request_conn_param_update(params, now)
+    }
+
+    /// Returns why the last connection was closed, if it wasn't retrieved before.
+    ///
+    /// When the master closes the connection, this is the reason it sent in its `LL_TERMINATE_IND`.
+    pub fn take_disconnect_reason(&mut self) -> Option<DisconnectReason> {
+        self.disconnect_reason.take()
+    }
+
+    /// Update the Link-Layer state after the timer expires.
+    ///
+    /// This should be called whenever the timer set by the last returned `Cmd` has expired.
+    ///
+    /// # Parameters
+    ///
+    /// * `tx`: A `Transmitter` for sending packets.
+    pub fn update_timer(&mut self, tx: &mut C::Transmitter) -> Cmd {
+        match &mut self.state {
+            State::Advertising {
+                params,
+                event_start,
+                next_adv,
+                last_adv,
+                pdu,
+                channel,
+                ..
+            } => {
+                *channel = if *next_adv == *event_start {
+                    params.channels.first().unwrap()
+                } else {
+                    params.channels.after(*channel).unwrap()
+                };
+                if let Some(rpa) = &mut self.privacy {
+                    if rpa.rotate_if_due(&mut self.aes, *next_adv) {
+                        pdu.set_advertiser_address(rpa.address());
+                        self.scan_response.set_advertiser_address(rpa.address());
+                    }
+                }
+
+                let payload = pdu.payload();
+                let buf = tx.tx_payload_buf();
+                buf[..payload.len()].copy_from_slice(payload);
+
+                tx.transmit_advertising_repeated(pdu.header(), *channel, params.repeat);
+
+                *last_adv = *next_adv;
+                if params.channels.after(*channel).is_some() {
+                    // Continue on the next channel once a request and our response could be
+                    // exchanged
+                    *next_adv += adv_channel_len(params);
+                } else {
+                    *event_start += params.interval + adv_delay(&mut self.rng);
+                    *next_adv = *event_start;
+                }
+
+                let channel = *channel;
+                Cmd {
+                    radio: RadioCmd::ListenAdvertising {
+                        channel,
+                        rx_timeout: Some(ADV_RX_TIMEOUT),
+                    },
+                    next_update: NextUpdate::At(*next_adv),
+                    window: self.adv_window(),
+                    queued_work: false,
+                }
+            }
+            State::Scanning {
+                params,
+                channel,
+                next_update,
+                listening,
+                handler,
+                pending,
+                backoff,
+                filter,
+            } => {
+                if let Some(scan) = pending.take() {
+                    // The advertiser didn't respond to our scan request
+                    backoff.failure();
+                    let report = scan.report(None);
+                    if filter.should_report(&report, self.timer.now()) {
+                        handler.report(&report);
+                    }
+                }
+
+                let radio = if *listening && !params.is_continuous() {
+                    // End of the scan window, sleep until the next interval starts
+                    *listening = false;
+                    *next_update += params.interval() - params.window();
+                    RadioCmd::Off
+                } else {
+                    // Start of a new scan window on the next channel
+                    *listening = true;
+                    *channel = params.scan_channels().cycle(*channel).unwrap();
+                    *next_update += params.window();
+                    RadioCmd::ListenAdvertising {
+                        channel: *channel,
+                        rx_timeout: None,
+                    }
+                };
+
+                let next_update = *next_update;
+                Cmd {
+                    radio,
+                    next_update: NextUpdate::At(next_update),
+                    window: self.adv_window(),
+                    queued_work: false,
                 }
             }
             State::Connection(conn) => match conn.timer_update(&mut self.timer) {
-                Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended (timer), standby");
+                Ok(cmd) => {
+                    self.report_connection_events();
+                    cmd
+                }
+                Err(reason) => {
+                    debug!("connection ended ({:?}, timer), standby", reason);
+                    defmt_debug!("connection ended ({}, timer), standby", reason);
                     self.state = State::Standby;
+                    self.disconnect_reason = Some(reason);
+                    self.report(LinkEvent::Disconnected { reason });
                     Cmd {
                         next_update: NextUpdate::Disable,
                         radio: RadioCmd::Off,
+                        window: None,
                         // FIXME(#70) this might need to be changed to `true`
                         queued_work: false,
                     }
                 }
             },
+            State::ExtendedAdvertising(advertiser) => {
+                advertiser.update(tx, &mut self.rng, &mut self.ext_adv_data)
+            }
+            State::PeriodicAdvertising(advertiser) => advertiser.update(tx, &mut self.rng),
             State::Standby => unreachable!("LL in standby received timer event"),
         }
     }
 
-    /// Returns a reference to the connection state.
+    /// Returns the window of the current advertising or scanning activity.
+    fn adv_window(&self) -> Option<TimeWindow> {
+        match &self.state {
+            State::Advertising {
+                last_adv, params, ..
+            } => Some(TimeWindow::new(*last_adv, adv_channel_len(params))),
+            State::Scanning {
+                params,
+                next_update,
+                listening: true,
+                ..
+            } => Some(TimeWindow::new(
+                *next_update - params.window(),
+                params.window(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns the receive timeout to use while advertising or scanning.
+    fn adv_rx_timeout(&self) -> Option<Duration> {
+        match self.state {
+            // Requests can only follow our own advertising PDUs immediately
+            State::Advertising { .. } => Some(ADV_RX_TIMEOUT),
+            _ => None,
+        }
+    }
+
+    /// Informs the Link-Layer that the radio stopped listening because the `rx_timeout` of the
+    /// last `RadioCmd` has elapsed.
     ///
-    /// If the Link Layer is not currently in a connection, returns `None`.
-    pub fn connection(&self) -> Option<&Connection<C>> {
-        if let State::Connection(conn) = &self.state {
-            Some(conn)
-        } else {
-            None
+    /// While connected, this is handled like a missed connection event (and may end the connection
+    /// due to the supervision timeout). While advertising, the radio is turned off until the next
+    /// advertising event.
+    ///
+    /// This should only be called if the radio supports receive timeouts.
+    pub fn process_rx_timeout(&mut self, tx: &mut C::Transmitter) -> Cmd {
+        defmt_trace!("RX timeout");
+        match self.state {
+            State::Connection(_) => self.update_timer(tx),
+            State::Advertising { .. } | State::Scanning { .. } => Cmd {
+                radio: RadioCmd::Off,
+                next_update: NextUpdate::Keep,
+                window: None,
+                queued_work: false,
+            },
+            State::ExtendedAdvertising(_) | State::PeriodicAdvertising(_) => {
+                unreachable!("LL received RX timeout while extended advertising")
+            }
+            State::Standby => unreachable!("LL in standby received RX timeout"),
+        }
+    }
+
+    /// Returns a reference to the state of connection `handle`.
+    ///
+    /// If that connection was closed, returns `None`.
+    pub fn connection(&self, handle: ConnectionHandle) -> Option<&Connection<C>> {
+        match &self.state {
+            State::Connection(conn) if conn.handle() == handle => Some(conn),
+            _ => None,
+        }
+    }
+
+    /// Returns the handle of the current connection.
+    ///
+    /// If the Link Layer is not currently in a connection, returns `None`. The handle is also
+    /// reported in `LinkEvent::Connected`.
+    pub fn connection_handle(&self) -> Option<ConnectionHandle> {
+        match &self.state {
+            State::Connection(conn) => Some(conn.handle()),
+            _ => None,
         }
     }
 
+    /// Returns the data channel statistics of connection `handle`.
+    ///
+    /// If that connection was closed, returns `None`.
+    pub fn connection_stats(&self, handle: ConnectionHandle) -> Option<&ConnectionStats> {
+        self.connection(handle).map(Connection::stats)
+    }
+
+    /// Returns the smoothed RSSI and CRC error rate of connection `handle`.
+    ///
+    /// Applications can use this to decide when to switch to a more robust PHY, or to give up on a
+    /// bad connection. If that connection was closed, returns `None`.
+    pub fn link_quality(&self, handle: ConnectionHandle) -> Option<LinkQuality> {
+        self.connection(handle).map(Connection::link_quality)
+    }
+
+    /// Resets the data channel statistics of connection `handle`.
+    ///
+    /// Returns `Error::InvalidValue` if that connection was closed.
+    pub fn reset_connection_stats(&mut self, handle: ConnectionHandle) -> Result<(), Error> {
+        self.connection_mut(handle)?.reset_stats();
+        Ok(())
+    }
+
+    /// Provides the key to use when the master of connection `handle` starts encryption.
+    ///
+    /// This should be called with the Short-Term Key when pairing completes (see
+    /// [`PairingEvent::Complete`]), or with a stored Long-Term Key when reconnecting to a bonded
+    /// device. The key is used for the next encryption request of the master. `rng` must be a
+    /// cryptographically secure random number generator, and is used to generate the session key
+    /// diversifier and IV.
+    ///
+    /// Returns `Error::InvalidValue` if the connection was closed.
+    ///
+    /// [`PairingEvent::Complete`]: crate::security::PairingEvent::Complete
+    pub fn set_encryption_key<R: RngCore + CryptoRng>(
+        &mut self,
+        handle: ConnectionHandle,
+        key: EncryptionKey,
+        rng: &mut R,
+    ) -> Result<(), Error> {
+        self.connection_mut(handle)?.set_encryption_key(key, rng);
+        Ok(())
+    }
+
     /// Returns whether the Link-Layer is currently broadcasting advertisement packets.
     pub fn is_advertising(&self) -> bool {
         matches!(self.state, State::Advertising { .. })
     }
 
+    /// Returns whether the Link-Layer is currently sending extended advertising events.
+    ///
+    /// This doesn't include the extended advertising events announcing a periodic advertising
+    /// train (see [`LinkLayer::is_periodic_advertising`]).
+    pub fn is_extended_advertising(&self) -> bool {
+        matches!(self.state, State::ExtendedAdvertising(_))
+    }
+
+    /// Returns whether the Link-Layer is currently sending a periodic advertising train.
+    pub fn is_periodic_advertising(&self) -> bool {
+        matches!(self.state, State::PeriodicAdvertising(_))
+    }
+
+    /// Returns whether the Link-Layer is currently scanning for advertisements.
+    pub fn is_scanning(&self) -> bool {
+        matches!(self.state, State::Scanning { .. })
+    }
+
     /// Returns whether the Link-Layer is currently connected.
     pub fn is_connected(&self) -> bool {
         matches!(self.state, State::Connection { .. })
     }
 }
 
+/// Information about a received data channel packet, in addition to its contents.
+///
+/// Passed to [`LinkLayer::process_data_packet_with_info`]. Fields the radio can't provide can be
+/// left at their defaults set by [`DataPacketInfo::new`].
+#[derive(Debug, Copy, Clone)]
+pub struct DataPacketInfo {
+    /// The time at which the packet was received completely.
+    pub rx_end: Instant,
+
+    /// The time at which the end of the packet's access address was received, if captured by the
+    /// radio (see [`LinkLayer::process_timestamped_data_packet`]).
+    pub address_time: Option<Instant>,
+
+    /// Whether the packet's CRC was correct.
+    pub crc_ok: bool,
+
+    /// The received signal strength in dBm, or `None` if the radio can't measure it.
+    pub rssi: Option<i8>,
+}
+
+impl DataPacketInfo {
+    /// Creates the information for a packet received at `rx_end`, without an access address
+    /// timestamp or RSSI.
+    pub fn new(rx_end: Instant, crc_ok: bool) -> Self {
+        Self {
+            rx_end,
+            address_time: None,
+            crc_ok,
+            rssi: None,
+        }
+    }
+}
+
 /// Command returned by the Link-Layer to the user.
 ///
 /// Specifies how the radio should be configured and when/if to call `LinkLayer::update` again.
 #[must_use]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Cmd {
     /// Radio configuration request.
     pub radio: RadioCmd,
@@ -469,6 +1604,16 @@ pub struct Cmd {
     /// state.
     pub next_update: NextUpdate,
 
+    /// Time window reserved for the radio activity requested by `radio`.
+    ///
+    /// A scheduler arbitrating between several radio users can use this to detect conflicts. Radio
+    /// drivers should disable the radio when the window ends and it is still in use. If this is
+    /// `None`, the activity is not time-bounded (or the radio is off).
+    ///
+    /// Rubble currently starts listening immediately when applying a `RadioCmd`, so the radio may
+    /// already be enabled before the window starts.
+    pub window: Option<TimeWindow>,
+
     /// Whether the Link-Layer code has enqueued more work into the packet queue.
     ///
     /// If this is `true`, the caller needs to ensure that the queue is drained and processed by
@@ -477,8 +1622,26 @@ pub struct Cmd {
     pub queued_work: bool,
 }
 
+impl Cmd {
+    /// Returns how long the CPU may sleep after applying this command, counted from `now`.
+    ///
+    /// `ramp_up` is the time the radio needs to become ready after being enabled. The CPU has to
+    /// wake up that long before `next_update`, so that the radio activity started by the update is
+    /// not delayed. Window widening is already included in `next_update`.
+    ///
+    /// Returns `None` if no update is scheduled, in which case the CPU may sleep until the next
+    /// interrupt. If the update is due in less than `ramp_up`, returns a duration of 0.
+    pub fn sleep_duration(&self, now: Instant, ramp_up: Duration) -> Option<Duration> {
+        match self.next_update {
+            NextUpdate::At(at) => Some((at - ramp_up).saturating_duration_since(now)),
+            NextUpdate::Disable | NextUpdate::Keep => None,
+        }
+    }
+}
+
 /// Specifies when the Link Layer's `update` method should be called the next time.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum NextUpdate {
     /// Disable timer and do not call `update`.
     Disable,
@@ -492,10 +1655,34 @@ pub enum NextUpdate {
     At(Instant),
 }
 
+/// A span of time reserved for a radio activity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeWindow {
+    /// Earliest time at which the radio is needed.
+    pub start: Instant,
+
+    /// Maximum time the radio is needed for, counted from `start`.
+    pub max_len: Duration,
+}
+
+impl TimeWindow {
+    /// Creates a window starting at `start` and lasting at most `max_len`.
+    pub fn new(start: Instant, max_len: Duration) -> Self {
+        Self { start, max_len }
+    }
+
+    /// Returns the time at which the window ends.
+    pub fn end(&self) -> Instant {
+        self.start + self.max_len
+    }
+}
+
 /// Specifies if and how the radio should listen for transmissions.
 ///
 /// Returned by the Link-Layer update and processing methods to reconfigure the radio as needed.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RadioCmd {
     /// Turn the radio off and don't call `LinkLayer::process_*` methods.
     ///
@@ -507,6 +1694,11 @@ pub enum RadioCmd {
     ListenAdvertising {
         /// The advertising channel to listen on.
         channel: AdvertisingChannel,
+
+        /// Time after which to stop listening if no packet is being received.
+        ///
+        /// See [`RadioCmd::rx_timeout`].
+        rx_timeout: Option<Duration>,
     },
 
     /// Listen on a data channel. If a matching packet is received, pass it to
@@ -528,9 +1720,39 @@ pub enum RadioCmd {
 
         /// Flag to indicate if the last connection event timed out.
         timeout: bool,
+
+        /// Time after which to stop listening if no packet is being received.
+        ///
+        /// See [`RadioCmd::rx_timeout`].
+        rx_timeout: Option<Duration>,
+
+        /// The PHY to receive on. Responses are transmitted on the same PHY.
+        ///
+        /// This changes when a PHY update takes effect, starting with the connection event at its
+        /// instant.
+        phy: Phy,
     },
 }
 
+impl RadioCmd {
+    /// Returns the time after which the radio may stop listening.
+    ///
+    /// The time is counted from when the radio starts listening. If it elapses before the radio
+    /// has started receiving a packet, the radio should be turned off and
+    /// [`LinkLayer::process_rx_timeout`] should be called. This saves power while waiting for
+    /// packets that will not arrive.
+    ///
+    /// If this is `None`, the radio has to listen until the next `RadioCmd` is applied. Radios
+    /// that don't support receive timeouts may always listen until then.
+    pub fn rx_timeout(&self) -> Option<Duration> {
+        match self {
+            RadioCmd::Off => None,
+            RadioCmd::ListenAdvertising { rx_timeout, .. }
+            | RadioCmd::ListenData { rx_timeout, .. } => *rx_timeout,
+        }
+    }
+}
+
 /// Trait for Link Layer packet transmission.
 ///
 /// The specifics of sending a Link-Layer packet depend on the underlying hardware. The `link`
@@ -549,8 +1771,36 @@ pub trait Transmitter {
     ///
     /// This buffer must not be changed. The BLE stack relies on the buffer to retain its old
     /// contents after transmitting a packet. A separate buffer must be used for received packets.
+    ///
+    /// Since `transmit_data` may return before the packet is sent, implementors have to wait for
+    /// any transmission that may still read from the buffer, including one that the radio will
+    /// start on its own (eg. via a hardware shortcut) after its transmitter has ramped up.
     fn tx_payload_buf(&mut self) -> &mut [u8];
 
+    /// Returns the number of payload Bytes the radio's receive buffer can hold.
+    ///
+    /// Like for `tx_payload_buf`, this excludes the 2-Byte header. The Link-Layer never announces
+    /// support for data channel PDUs larger than this during the Data Length Update Procedure, so
+    /// that received packets are not truncated.
+    ///
+    /// The default implementation returns `MIN_PAYLOAD_BUF`, the smallest size a receive buffer may
+    /// have.
+    fn rx_payload_capacity(&self) -> usize {
+        MIN_PAYLOAD_BUF
+    }
+
+    /// Returns the Link-Layer features that can be used with this radio.
+    ///
+    /// Connections only use the features contained in both this set and the one configured with
+    /// [`LinkLayer::set_features`], so the Link-Layer never asks the radio to use a PHY it doesn't
+    /// support.
+    ///
+    /// The default implementation returns [`FeatureSet::supported`]. Radios that can't use the LE
+    /// 2M or LE Coded PHY have to override it.
+    fn supported_features(&self) -> FeatureSet {
+        FeatureSet::supported()
+    }
+
     /// Transmit an Advertising Channel PDU.
     ///
     /// For Advertising Channel PDUs, the CRC initialization value is always `CRC_PRESET`, and the
@@ -560,12 +1810,38 @@ pub trait Transmitter {
     /// of the packet, and must apply data whitening and do the CRC calculation. The inter-frame
     /// spacing also has to be upheld by the implementor (`T_IFS`).
     ///
+    /// When called from `LinkLayer::process_adv_packet`, the PDU is a response (`SCAN_REQ` or
+    /// `SCAN_RSP`) and has to be sent `T_IFS` after the end of the received packet. Advertising
+    /// PDUs sent from `LinkLayer::update_timer` are followed by a `RadioCmd::ListenAdvertising` on
+    /// the same channel, and the receiver has to be listening `T_IFS` after the transmission to
+    /// catch scan and connect requests.
+    ///
     /// # Parameters
     ///
     /// * `header`: Advertising Channel PDU Header to prepend to the Payload in `payload_buf()`.
     /// * `channel`: Advertising Channel Index to transmit on.
     fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel);
 
+    /// Transmit an Advertising Channel PDU `1 + repeat` times in a row.
+    ///
+    /// This is used instead of `transmit_advertising` for advertising PDUs sent from
+    /// `LinkLayer::update_timer` (see [`AdvParams::repeat`]). The copies should be sent with as
+    /// little time between them as possible, and no more than `T_IFS` after the previous one
+    /// ended. The receiver has to be listening `T_IFS` after the *last* transmission.
+    ///
+    /// The default implementation calls `transmit_advertising` `1 + repeat` times. Implementors
+    /// should override it if their radio can send the PDU again without re-encoding it.
+    fn transmit_advertising_repeated(
+        &mut self,
+        header: advertising::Header,
+        channel: AdvertisingChannel,
+        repeat: u8,
+    ) {
+        for _ in 0..=repeat {
+            self.transmit_advertising(header, channel);
+        }
+    }
+
     /// Transmit a Data Channel PDU.
     ///
     /// The implementor is expected to send the preamble and assemble the rest of the packet, and
@@ -584,4 +1860,2140 @@ pub trait Transmitter {
         header: data::Header,
         channel: DataChannel,
     );
+
+    /// Transmit an advertising PDU on a secondary advertising channel.
+    ///
+    /// This is used for the auxiliary PDUs of extended and periodic advertising (see
+    /// [`extended`]). Secondary advertising channels use the same frequencies and whitening as the
+    /// data channels, but the PDU has an advertising channel header. Like advertising PDUs, it is
+    /// always sent on the LE 1M PHY. No response is expected, and the `Cmd` returned afterwards
+    /// turns the radio off, which must not cut the transmission short.
+    ///
+    /// # Parameters
+    ///
+    /// * `access_address`: The Access Address of the Link-Layer packet (`ACCESS_ADDRESS` for
+    ///   `AUX_ADV_IND`s, that of the periodic advertising train for `AUX_SYNC_IND`s).
+    /// * `crc_iv`: CRC calculation initial value.
+    /// * `header`: Advertising Channel PDU Header to prepend to the Payload in `payload_buf()`.
+    /// * `channel`: Secondary Advertising Channel Index (equal to the Data Channel Index) to
+    ///   transmit on.
+    ///
+    /// The default implementation passes the PDU to `transmit_data`, since both headers have the
+    /// same size and the length field in the same place. Radios that listen for a response after
+    /// `transmit_data`, or that might send it on a PHY other than LE 1M, have to override this.
+    ///
+    /// [`extended`]: crate::link::extended
+    fn transmit_secondary(
+        &mut self,
+        access_address: u32,
+        crc_iv: u32,
+        header: advertising::Header,
+        channel: DataChannel,
+    ) {
+        let header = data::Header::parse(&header.to_u16().to_le_bytes());
+        self.transmit_data(access_address, crc_iv, header, channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+    use crate::att::{self, NoAttributes};
+    use crate::gatt::MidiServiceAttrs;
+    use crate::l2cap::{BleChannelMap, L2CAPState};
+    use crate::link::advertising::AdvChannels;
+    use crate::link::queue::{ArrayQueue, Consume, Consumer, PacketQueue, Producer};
+    use crate::phy::CodingIndicator;
+    use crate::security::{rng::MockRng, NoSecurity};
+    use crate::time::MockTimer;
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    /// Records all transmitted advertising PDUs and the headers of data channel PDUs.
+    struct TestTransmitter {
+        buf: [u8; MIN_PAYLOAD_BUF],
+        sent: Vec<(advertising::Header, Vec<u8>, AdvertisingChannel)>,
+        data_sent: Vec<data::Header>,
+        features: FeatureSet,
+    }
+
+    impl TestTransmitter {
+        fn new() -> Self {
+            Self {
+                buf: [0; MIN_PAYLOAD_BUF],
+                sent: Vec::new(),
+                data_sent: Vec::new(),
+                features: FeatureSet::supported(),
+            }
+        }
+    }
+
+    impl Transmitter for TestTransmitter {
+        fn tx_payload_buf(&mut self) -> &mut [u8] {
+            &mut self.buf
+        }
+
+        fn supported_features(&self) -> FeatureSet {
+            self.features
+        }
+
+        fn transmit_advertising(
+            &mut self,
+            header: advertising::Header,
+            channel: AdvertisingChannel,
+        ) {
+            let payload = self.buf[..usize::from(header.payload_length())].to_vec();
+            self.sent.push((header, payload, channel));
+        }
+
+        fn transmit_data(&mut self, _: u32, _: u32, header: data::Header, _: DataChannel) {
+            self.data_sent.push(header);
+        }
+    }
+
+    /// An advertising report with owned data.
+    #[derive(Debug)]
+    struct Report {
+        addr: DeviceAddress,
+        addr_type: AddressKind,
+        pdu_type: advertising::PduType,
+        data: Vec<u8>,
+        scan_response: Option<Vec<u8>>,
+        rssi: Option<i8>,
+    }
+
+    #[derive(Default, Clone)]
+    struct Reports(Rc<RefCell<Vec<Report>>>);
+
+    impl AdvReportHandler for Reports {
+        fn report(&mut self, report: &AdvReport<'_>) {
+            self.0.borrow_mut().push(Report {
+                addr: report.addr,
+                addr_type: report.addr_type,
+                pdu_type: report.pdu_type,
+                data: report.data.to_vec(),
+                scan_response: report.scan_response.map(<[u8]>::to_vec),
+                rssi: report.rssi,
+            });
+        }
+    }
+
+    #[derive(Default, Clone)]
+    struct Events(Rc<RefCell<Vec<LinkEvent>>>);
+
+    impl LinkEventHandler for Events {
+        fn handle_event(&mut self, event: LinkEvent) {
+            self.0.borrow_mut().push(event);
+        }
+    }
+
+    /// Packet queue with room for several PDUs.
+    type TestQueue = ArrayQueue<8>;
+
+    enum TestConfig {}
+
+    impl Config for TestConfig {
+        type Timer = MockTimer;
+        type Transmitter = TestTransmitter;
+        type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+        type PacketQueue = &'static mut TestQueue;
+        type Aes = SoftAesProvider;
+        type AdvReportHandler = Reports;
+        type LinkEventHandler = Events;
+        type Rng = MockRng;
+    }
+
+    fn link_layer() -> LinkLayer<TestConfig> {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        LinkLayer::new(addr, MockTimer::default(), MockRng::default())
+    }
+
+    #[test]
+    fn scan_reports() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let reports = Reports::default();
+        let params = ScanParams::new(Duration::millis(100), Duration::millis(50)).unwrap();
+
+        let cmd = ll.start_scanning(params, reports.clone());
+        assert!(ll.is_scanning());
+        assert!(matches!(
+            cmd.radio,
+            RadioCmd::ListenAdvertising { channel, .. } if channel.channel() == 37
+        ));
+
+        let advertiser =
+            DeviceAddress::new([0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xC6], AddressKind::Random);
+        let adv =
+            PduBuf::discoverable(advertiser, &[AdStructure::CompleteLocalName("rubble")]).unwrap();
+        let now = Instant::from_ticks(1_000);
+        let _ = ll.process_adv_packet(now, &mut tx, adv.header(), adv.payload(), false, None);
+        let _ = ll.process_adv_packet(now, &mut tx, adv.header(), adv.payload(), true, Some(-42));
+
+        let reports = reports.0.borrow();
+        assert_eq!(reports.len(), 1, "PDUs with bad CRC must not be reported");
+        let report = &reports[0];
+        assert_eq!(report.addr, advertiser);
+        assert_eq!(report.addr_type, AddressKind::Random);
+        assert_eq!(report.pdu_type, advertising::PduType::AdvInd);
+        assert_eq!(report.data, &adv.payload()[6..]);
+        assert_eq!(report.rssi, Some(-42));
+        assert!(tx.sent.is_empty(), "passive scanning must not transmit");
+    }
+
+    #[test]
+    fn scan_duty_cycle() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let params = ScanParams::new(Duration::millis(100), Duration::millis(30)).unwrap();
+        let at = |ms| Instant::from_ticks(0) + Duration::millis(ms);
+
+        let cmd = ll.start_scanning(params, Reports::default());
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(30)));
+
+        let cmd = ll.update_timer(&mut tx);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(100)));
+
+        let cmd = ll.update_timer(&mut tx);
+        assert!(matches!(
+            cmd.radio,
+            RadioCmd::ListenAdvertising { channel, .. } if channel.channel() == 38
+        ));
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(130)));
+
+        assert!(ll.stop_scanning().is_ok());
+        assert!(!ll.is_scanning());
+        assert_eq!(ll.stop_scanning().unwrap_err(), Error::InvalidValue);
+    }
+
+    #[test]
+    fn scan_channels() {
+        assert_eq!(
+            ScanParams::continuous(Duration::millis(100))
+                .unwrap()
+                .channels(AdvChannels::empty())
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let params = ScanParams::new(Duration::millis(50), Duration::millis(20))
+            .unwrap()
+            .channels(AdvChannels::CH37 | AdvChannels::CH39)
+            .unwrap();
+        let at = |ms| Instant::from_ticks(0) + Duration::millis(ms);
+        let listen_channel = |cmd: &Cmd| match cmd.radio {
+            RadioCmd::ListenAdvertising { channel, .. } => channel.channel(),
+            ref radio => panic!("expected to listen, got {:?}", radio),
+        };
+
+        let cmd = ll.start_scanning(params, Reports::default());
+        assert_eq!(listen_channel(&cmd), 37);
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(20)));
+
+        // Every window is followed by a 30 ms gap with the radio off, and every interval uses the
+        // next configured channel, skipping channel 38
+        for (interval, channel) in [(1, 39), (2, 37), (3, 39)] {
+            let cmd = ll.update_timer(&mut tx);
+            assert!(matches!(cmd.radio, RadioCmd::Off));
+            assert!(cmd.window.is_none());
+            assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(interval * 50)));
+            ll.timer().set(at(interval * 50));
+
+            let cmd = ll.update_timer(&mut tx);
+            assert_eq!(listen_channel(&cmd), channel);
+            assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == at(interval * 50 + 20)));
+            ll.timer().set(at(interval * 50 + 20));
+        }
+    }
+
+    #[test]
+    fn scan_duplicate_filter() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let reports = Reports::default();
+        let params = ScanParams::continuous(Duration::millis(100))
+            .unwrap()
+            .filter_duplicates(Some(Duration::millis(500)));
+        let _ = ll.start_scanning(params, reports.clone());
+
+        let advertiser =
+            DeviceAddress::new([0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xC6], AddressKind::Random);
+        let other = DeviceAddress::new([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6], AddressKind::Public);
+        let adv = |addr, name| {
+            PduBuf::discoverable(addr, &[AdStructure::CompleteLocalName(name)]).unwrap()
+        };
+        let mut recv = |ms, pdu: &PduBuf| {
+            let at = Instant::from_ticks(0) + Duration::millis(ms);
+            let _ = ll.process_adv_packet(at, &mut tx, pdu.header(), pdu.payload(), true, None);
+        };
+        let reported = || -> Vec<_> {
+            reports
+                .0
+                .borrow()
+                .iter()
+                .map(|r| (r.addr, r.data.clone()))
+                .collect()
+        };
+
+        recv(0, &adv(advertiser, "rubble"));
+        recv(10, &adv(other, "rubble"));
+        assert_eq!(reported().len(), 2, "different advertisers are reported");
+
+        // Duplicate within the window is suppressed
+        recv(100, &adv(advertiser, "rubble"));
+        assert_eq!(reported().len(), 2);
+
+        // Changed data is reported immediately
+        let changed = adv(advertiser, "bubble");
+        recv(200, &changed);
+        assert_eq!(reported().len(), 3);
+        assert_eq!(reported()[2], (advertiser, changed.payload()[6..].to_vec()));
+
+        // After the window has passed, the advertisement is reported again
+        recv(400, &changed);
+        assert_eq!(reported().len(), 3);
+        recv(700, &changed);
+        assert_eq!(reported().len(), 4);
+    }
+
+    #[test]
+    fn active_scan() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let reports = Reports::default();
+        let params = ScanParams::continuous(Duration::millis(100))
+            .unwrap()
+            .active(true);
+        let _ = ll.start_scanning(params, reports.clone());
+
+        let advertiser =
+            DeviceAddress::new([0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xC6], AddressKind::Random);
+        let adv = PduBuf::discoverable(advertiser, &[]).unwrap();
+        let now = Instant::from_ticks(1_000);
+        let _ = ll.process_adv_packet(now, &mut tx, adv.header(), adv.payload(), true, None);
+
+        assert_eq!(tx.sent.len(), 1);
+        let (header, payload, channel) = &tx.sent[0];
+        assert_eq!(header.type_(), advertising::PduType::ScanReq);
+        assert_eq!(channel.channel(), 37);
+        assert!(!header.tx_add());
+        assert!(header.rx_add());
+        let req = Pdu::from_header_and_payload(*header, &mut ByteReader::new(payload)).unwrap();
+        assert_eq!(req.sender(), &ll.own_address());
+        assert_eq!(req.receiver(), Some(&advertiser));
+        assert!(
+            reports.0.borrow().is_empty(),
+            "report must wait for SCAN_RSP"
+        );
+
+        let name = AdStructure::CompleteLocalName("rubble");
+        let rsp = PduBuf::scan_response(advertiser, &[name]).unwrap();
+        let _ = ll.process_adv_packet(now, &mut tx, rsp.header(), rsp.payload(), true, None);
+
+        let reports = reports.0.borrow();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].addr, advertiser);
+        assert_eq!(reports[0].pdu_type, advertising::PduType::AdvInd);
+        assert_eq!(reports[0].data, &adv.payload()[6..]);
+        assert_eq!(
+            reports[0].scan_response.as_deref(),
+            Some(&rsp.payload()[6..])
+        );
+    }
+
+    #[test]
+    fn scan_response() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        let name = AdStructure::CompleteLocalName("a rather long device name");
+        ll.set_scan_response_data(&[name]).unwrap();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, consumer, producer)
+            .unwrap();
+        assert_eq!(tx.sent.len(), 1);
+
+        let scanner = DeviceAddress::new([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6], AddressKind::Public);
+        let other = DeviceAddress::new([0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6], AddressKind::Public);
+        let now = Instant::from_ticks(1_000);
+
+        // Scan requests for other devices are ignored
+        let req = PduBuf::scan_request(scanner, other).unwrap();
+        let _ = ll.process_adv_packet(now, &mut tx, req.header(), req.payload(), true, None);
+        assert_eq!(tx.sent.len(), 1);
+
+        let req = PduBuf::scan_request(scanner, ll.own_address()).unwrap();
+        let cmd = ll.process_adv_packet(now, &mut tx, req.header(), req.payload(), true, None);
+        assert!(matches!(cmd.radio, RadioCmd::ListenAdvertising { .. }));
+        assert_eq!(tx.sent.len(), 2);
+
+        let (header, payload, channel) = &tx.sent[1];
+        assert_eq!(header.type_(), advertising::PduType::ScanRsp);
+        assert_eq!(channel.channel(), tx.sent[0].2.channel());
+        let rsp = Pdu::from_header_and_payload(*header, &mut ByteReader::new(payload)).unwrap();
+        assert_eq!(rsp.sender(), &ll.own_address());
+        let mut data = rsp.advertising_data().unwrap();
+        assert!(matches!(
+            data.next(),
+            Some(AdStructure::CompleteLocalName("a rather long device name"))
+        ));
+        assert!(data.next().is_none());
+    }
+
+    /// Connects `ll` to a master using a 7.5 ms interval and a 100 ms supervision timeout.
+    ///
+    /// Received data is looped back into the TX queue. Returns the time at which the
+    /// `CONNECT_IND` was received.
+    fn connect(ll: &mut LinkLayer<TestConfig>, tx: &mut TestTransmitter) -> Instant {
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        connect_with_queues(ll, tx, consumer, producer)
+    }
+
+    /// Like `connect`, but uses the given queues for transmitted and received data.
+    fn connect_with_queues(
+        ll: &mut LinkLayer<TestConfig>,
+        tx: &mut TestTransmitter,
+        tx_queue: ConfConsumer<TestConfig>,
+        rx_queue: ConfProducer<TestConfig>,
+    ) -> Instant {
+        ll.start_advertise(Duration::millis(100), &[], tx, tx_queue, rx_queue)
+            .unwrap();
+
+        let (header, payload) = connect_ind(ll, 1, 0);
+        ll.timer().set(Instant::from_ticks(1_000));
+        let now = ll.timer().now();
+        let _ = ll.process_adv_packet(now, tx, header, &payload, true, None);
+        assert!(ll.is_connected());
+        now
+    }
+
+    /// Builds a `CONNECT_IND` addressed to `ll`, using the given transmit window parameters (in
+    /// units of 1.25 ms).
+    fn connect_ind(
+        ll: &LinkLayer<TestConfig>,
+        win_size: u8,
+        win_offset: u16,
+    ) -> (advertising::Header, Vec<u8>) {
+        let master = DeviceAddress::new([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6], AddressKind::Public);
+        let mut payload = master.raw().to_vec();
+        payload.extend_from_slice(ll.own_address().raw());
+        payload.extend_from_slice(&0x5065_17AF_u32.to_le_bytes()); // access address
+        payload.extend_from_slice(&[0x55, 0x55, 0x55]); // CRC init
+        payload.push(win_size);
+        payload.extend_from_slice(&win_offset.to_le_bytes());
+        payload.extend_from_slice(&[6, 0, 0, 0, 10, 0]); // interval, latency, timeout
+        payload.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 7]); // channel map, hop
+        let mut header = advertising::Header::new(advertising::PduType::ConnectReq);
+        header.set_payload_length(payload.len() as u8);
+        (header, payload)
+    }
+
+    /// Receives an empty data channel PDU at `at`.
+    fn recv_empty(
+        ll: &mut LinkLayer<TestConfig>,
+        tx: &mut TestTransmitter,
+        at: Instant,
+        sn: SeqNum,
+        nesn: SeqNum,
+        crc_ok: bool,
+    ) {
+        ll.timer().set(at);
+        let mut header = data::Header::new(data::Llid::DataCont);
+        header.set_sn(sn);
+        header.set_nesn(nesn);
+        let _ = ll.process_data_packet(at, tx, header, &[], crc_ok);
+    }
+
+    /// Returns the current connection of `ll`.
+    fn conn(ll: &LinkLayer<TestConfig>) -> &Connection<TestConfig> {
+        ll.connection(ll.connection_handle().unwrap()).unwrap()
+    }
+
+    /// Receives a data channel PDU carrying `payload`, which acknowledges our last PDU.
+    fn recv_pdu(
+        ll: &mut LinkLayer<TestConfig>,
+        tx: &mut TestTransmitter,
+        at: Instant,
+        sn: SeqNum,
+        llid: data::Llid,
+        payload: &[u8],
+    ) -> Cmd {
+        ll.timer().set(at);
+        let mut header = data::Header::new(llid);
+        header.set_sn(sn);
+        header.set_nesn(sn);
+        header.set_payload_length(payload.len() as u8);
+        ll.process_data_packet(at, tx, header, payload, true)
+    }
+
+    /// The master's side of a connection, sending one PDU per connection event.
+    ///
+    /// Every PDU acknowledges our last one. Empty PDUs are sent as `DataCont`, all others as LL
+    /// Control PDUs.
+    struct Master {
+        rx_end: Instant,
+        sn: SeqNum,
+    }
+
+    impl Master {
+        /// Starts sending 2 ms after the connection was established at `now`.
+        fn new(now: Instant) -> Self {
+            Self {
+                rx_end: now + Duration::millis(2),
+                sn: SeqNum::ZERO,
+            }
+        }
+
+        /// Sends `pdu` in the next connection event (7.5 ms after the previous one).
+        fn recv(
+            &mut self,
+            ll: &mut LinkLayer<TestConfig>,
+            tx: &mut TestTransmitter,
+            pdu: &[u8],
+        ) -> Cmd {
+            let llid = if pdu.is_empty() {
+                data::Llid::DataCont
+            } else {
+                data::Llid::Control
+            };
+            let cmd = recv_pdu(ll, tx, self.rx_end, self.sn, llid, pdu);
+            self.sn += SeqNum::ONE;
+            self.rx_end += Duration::micros(7_500);
+            cmd
+        }
+    }
+
+    /// Returns the payload of the last data channel PDU sent by `tx`.
+    fn last_sent(tx: &TestTransmitter) -> Vec<u8> {
+        let sent = tx.data_sent.last().unwrap();
+        tx.buf[..usize::from(sent.payload_length())].to_vec()
+    }
+
+    #[test]
+    fn supervision_timeout() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        // The master sends 2 empty PDUs, so that the second one acknowledges our response
+        let mut last_rx = now;
+        for sn in [SeqNum::ZERO, SeqNum::ONE] {
+            last_rx += Duration::micros(2_000);
+            recv_empty(&mut ll, &mut tx, last_rx, sn, sn, true);
+        }
+        assert_eq!(tx.data_sent.len(), 2);
+        assert!(ll.is_connected());
+
+        // Now the master falls silent
+        let mut missed = 0;
+        while ll.is_connected() {
+            let cmd = ll.update_timer(&mut tx);
+            match cmd.next_update {
+                NextUpdate::At(at) => ll.timer().set(at),
+                _ => break,
+            }
+            missed += 1;
+        }
+
+        let elapsed = ll.timer().now() - last_rx;
+        assert!(!ll.is_connected());
+        assert!(elapsed >= Duration::millis(100), "{}", elapsed);
+        assert!(elapsed < Duration::millis(100) + Duration::micros(8_000));
+        assert_eq!(missed, 13);
+    }
+
+    #[test]
+    fn first_connection_event() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, consumer, producer)
+            .unwrap();
+
+        // WinSize = 3.75 ms, WinOffset = 2.5 ms
+        let (header, payload) = connect_ind(&ll, 3, 2);
+        let connect_end = Instant::from_ticks(1_000);
+        ll.timer().set(connect_end);
+        let cmd = ll.process_adv_packet(connect_end, &mut tx, header, &payload, true, None);
+        assert!(ll.is_connected());
+
+        // The transmit window opens 1.25 ms + WinOffset after the `CONNECT_IND` and is WinSize
+        // long. It is widened by 500 µs plus the drift of 550 ppm over 7.5 ms (rounded up to
+        // 5 µs) on both sides, and the exchange may extend past it.
+        let window = cmd.window.unwrap();
+        assert_eq!(window.start, connect_end + Duration::micros(3_750 - 505));
+        assert_eq!(
+            window.max_len,
+            Duration::micros(3_750 + 1_010 + 2 * 328 + 150)
+        );
+
+        // If nothing is received until the end of the (widened) window, the attempt failed
+        let tx_window_end = Duration::micros(7_500 + 505);
+        assert_eq!(cmd.radio.rx_timeout(), Some(tx_window_end));
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == connect_end + tx_window_end));
+        assert!(matches!(
+            cmd.radio,
+            RadioCmd::ListenData {
+                access_address: 0x5065_17AF,
+                crc_init: 0x55_5555,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn connection_event_window() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        // The next event is expected 7.5 ms after this one. The window is widened by 500 µs plus
+        // 5 µs of drift (550 ppm) on both sides and must fit 2 maximum-size packets separated by
+        // T_IFS.
+        let rx_end = now + Duration::micros(2_000);
+        ll.timer().set(rx_end);
+        let header = data::Header::new(data::Llid::DataCont);
+        let cmd = ll.process_data_packet(rx_end, &mut tx, header, &[], true);
+        let window = cmd.window.unwrap();
+        assert_eq!(window.start, rx_end + Duration::micros(7_500 - 505));
+        assert_eq!(window.max_len, Duration::micros(1_010 + 2 * 328 + 150));
+        assert!(
+            matches!(cmd.next_update, NextUpdate::At(t) if t == rx_end + Duration::micros(8_005))
+        );
+
+        // After missing that event, the window moves by one interval, and widens since the clocks
+        // drift apart for another interval (9 µs over 15 ms)
+        ll.timer().set(rx_end + Duration::micros(8_005));
+        let next = ll.update_timer(&mut tx).window.unwrap();
+        assert_eq!(next.start, rx_end + Duration::micros(15_000 - 509));
+        assert_eq!(next.max_len, Duration::micros(1_018 + 2 * 328 + 150));
+    }
+
+    #[test]
+    fn anchor_point_correction() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        // Packets start 40 µs before their access address has been received. The master's clock
+        // runs slightly slow, so each event starts 30 µs later than scheduled, and the next
+        // window is always centered on the measured anchor point, widened by 250 µs plus 5 µs of
+        // drift.
+        let header = data::Header::new(data::Llid::DataCont);
+        let mut anchor = now + Duration::micros(2_000);
+        for _ in 0..4 {
+            let rx_end = anchor + Duration::micros(80);
+            ll.timer().set(rx_end + Duration::micros(20));
+            let cmd = ll.process_timestamped_data_packet(
+                rx_end,
+                anchor + Duration::micros(40),
+                &mut tx,
+                header,
+                &[],
+                true,
+            );
+            let window = cmd.window.unwrap();
+            assert_eq!(window.start, anchor + Duration::micros(7_500 - 255));
+            assert!(matches!(
+                cmd.next_update,
+                NextUpdate::At(t) if t == anchor + Duration::micros(7_500 + 255)
+            ));
+            anchor += Duration::micros(7_500 + 30);
+        }
+
+        // A packet with a bad CRC may be noise, so its timestamp is ignored and the next event is
+        // scheduled relative to the last good anchor point (with 9 µs of drift over 2 intervals)
+        let scheduled = anchor - Duration::micros(30);
+        ll.timer().set(anchor + Duration::micros(100));
+        let cmd = ll.process_timestamped_data_packet(
+            anchor + Duration::micros(80),
+            anchor + Duration::micros(540),
+            &mut tx,
+            header,
+            &[],
+            false,
+        );
+        let next = scheduled + Duration::micros(7_500);
+        assert_eq!(cmd.window.unwrap().start, next - Duration::micros(250 + 9));
+    }
+
+    #[test]
+    fn connection_stats() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let mut now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(ll.connection_stats(handle), Some(&ConnectionStats::new()));
+
+        let mut recv = |sn, nesn, crc_ok| {
+            now += Duration::micros(7_500);
+            recv_empty(&mut ll, &mut tx, now, sn, nesn, crc_ok);
+        };
+        // New PDU, but doesn't acknowledge our (nonexistent) last PDU
+        recv(SeqNum::ZERO, SeqNum::ZERO, true);
+        // New PDU acknowledging our response
+        recv(SeqNum::ONE, SeqNum::ONE, true);
+        // Corrupted PDU
+        recv(SeqNum::ZERO, SeqNum::ZERO, false);
+        // Master didn't get our ACK and retransmits
+        recv(SeqNum::ONE, SeqNum::ONE, true);
+
+        let stats = *ll.connection_stats(handle).unwrap();
+        assert_eq!(
+            stats,
+            ConnectionStats {
+                crc_errors: 1,
+                new_pdus: 2,
+                retransmitted_pdus: 1,
+                nacks: 2,
+                empty_pdus: 3,
+            }
+        );
+
+        ll.reset_connection_stats(handle).unwrap();
+        assert_eq!(ll.connection_stats(handle), Some(&ConnectionStats::new()));
+    }
+
+    #[test]
+    fn link_quality() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        assert_eq!(ll.set_link_quality_smoothing(0), Err(Error::InvalidValue));
+        ll.set_link_quality_smoothing(128).unwrap();
+        assert_eq!(ll.link_quality(ConnectionHandle::default()), None);
+
+        let mut now = connect(&mut ll, &mut tx);
+        let mut recv = |ll: &mut LinkLayer<TestConfig>, sn, crc_ok, rssi| {
+            now += Duration::micros(7_500);
+            ll.timer().set(now);
+            let mut header = data::Header::new(data::Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(sn);
+            let mut info = DataPacketInfo::new(now, crc_ok);
+            info.rssi = rssi;
+            let _ = ll.process_data_packet_with_info(&mut tx, header, &[], info);
+            ll.link_quality(ll.connection_handle().unwrap()).unwrap()
+        };
+
+        // Averaged with α = 1/2
+        assert_eq!(
+            recv(&mut ll, SeqNum::ZERO, true, Some(-50)).rssi(),
+            Some(-50)
+        );
+        assert_eq!(
+            recv(&mut ll, SeqNum::ONE, true, Some(-70)).rssi(),
+            Some(-60)
+        );
+        let quality = recv(&mut ll, SeqNum::ZERO, false, Some(-80));
+        assert_eq!(quality.rssi(), Some(-70));
+        assert_eq!(quality.crc_error_permille(), 500);
+    }
+
+    #[test]
+    fn flow_control() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, mut app_rx) = Box::leak(Box::new(TestQueue::new())).split();
+        let mut now = connect_with_queues(&mut ll, &mut tx, ll_tx, ll_rx);
+
+        app_tx
+            .produce_with(3, |w| -> Result<_, Error> {
+                w.write_slice(&[1, 2, 3])?;
+                Ok(data::Llid::DataStart)
+            })
+            .unwrap();
+
+        let mut recv = |sn, nesn, payload: &[u8]| {
+            now += Duration::micros(7_500);
+            ll.timer().set(now);
+            let llid = if payload.is_empty() {
+                data::Llid::DataCont
+            } else {
+                data::Llid::DataStart
+            };
+            let mut header = data::Header::new(llid);
+            header.set_sn(sn);
+            header.set_nesn(nesn);
+            header.set_payload_length(payload.len() as u8);
+            let _ = ll.process_data_packet(now, &mut tx, header, payload, true);
+            *tx.data_sent.last().unwrap()
+        };
+
+        // The master's first PDU can't acknowledge anything, but we can already send data
+        let sent = recv(SeqNum::ZERO, SeqNum::ZERO, &[]);
+        assert_eq!(sent.sn(), SeqNum::ZERO);
+        assert_eq!(sent.nesn(), SeqNum::ONE);
+        assert_eq!(sent.payload_length(), 3);
+
+        // The master didn't receive it (NESN unchanged), so it is retransmitted, acknowledging the
+        // master's new PDU
+        let sent = recv(SeqNum::ONE, SeqNum::ZERO, &[0xAA]);
+        assert_eq!(sent.sn(), SeqNum::ZERO);
+        assert_eq!(sent.nesn(), SeqNum::ZERO);
+        assert_eq!(sent.payload_length(), 3);
+
+        // The master missed our acknowledgement and resends its PDU. It is acknowledged again, but
+        // must not be delivered twice. Since there's no more data, an empty PDU is sent.
+        let sent = recv(SeqNum::ONE, SeqNum::ONE, &[0xAA]);
+        assert_eq!(sent.sn(), SeqNum::ONE);
+        assert_eq!(sent.nesn(), SeqNum::ZERO);
+        assert_eq!(sent.payload_length(), 0);
+
+        let received = app_rx.consume_raw_with(|_, pl| Consume::always(Ok(pl.to_vec())));
+        assert_eq!(received.unwrap(), [0xAA]);
+        assert!(!app_rx.has_data());
+    }
+
+    #[test]
+    fn sleep_duration_unscheduled() {
+        let cmd = Cmd {
+            next_update: NextUpdate::Disable,
+            radio: RadioCmd::Off,
+            window: None,
+            queued_work: false,
+        };
+        let now = Instant::from_ticks(1_000);
+        assert_eq!(cmd.sleep_duration(now, Duration::micros(140)), None);
+    }
+
+    #[test]
+    fn set_features() {
+        let mut ll = link_layer();
+        assert_eq!(ll.set_features(FeatureSet::empty()), Ok(()));
+        assert_eq!(ll.set_features(FeatureSet::LE_ENCRYPTION), Ok(()));
+        assert_eq!(
+            ll.set_features(FeatureSet::LE_ENCRYPTION | FeatureSet::LL_PRIVACY),
+            Err(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn slave_latency() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _app_rx) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, ll_tx, ll_rx)
+            .unwrap();
+
+        // 7.5 ms interval, latency 3, 100 ms supervision timeout, hopping 7 channels per event
+        let (header, mut payload) = connect_ind(&ll, 1, 0);
+        payload[24..26].copy_from_slice(&3u16.to_le_bytes());
+        let connect_end = Instant::from_ticks(1_000);
+        ll.timer().set(connect_end);
+        let _ = ll.process_adv_packet(connect_end, &mut tx, header, &payload, true, None);
+        assert_eq!(conn(&ll).slave_latency(), 3);
+        let handle = ll.connection_handle().unwrap();
+
+        let recv = |ll: &mut LinkLayer<TestConfig>, tx: &mut TestTransmitter, at, sn, nesn| {
+            ll.timer().set(at);
+            let mut header = data::Header::new(data::Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(nesn);
+            ll.process_data_packet(at, tx, header, &[], true)
+        };
+
+        // Event #0 on channel 7. Nothing to send, so the next 3 events are skipped and we wake up
+        // for #4 (channel 35), widened by 500 µs + 17 µs (550 ppm over 30 ms).
+        let rx_end = connect_end + Duration::millis(2);
+        let cmd = recv(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+        let wake = rx_end + Duration::micros(4 * 7_500 - 517);
+        assert!(matches!(cmd.next_update, NextUpdate::At(t) if t == wake));
+        assert!(ll.wake_for_tx(handle).unwrap().is_none(), "nothing queued");
+
+        // The CPU can sleep until it has to ramp up the radio for the window of event #4
+        let ramp_up = Duration::micros(140);
+        let sleep = cmd.sleep_duration(rx_end, ramp_up).unwrap();
+        assert_eq!(rx_end + sleep + ramp_up, wake);
+        assert_eq!(
+            cmd.sleep_duration(wake - Duration::micros(100), ramp_up),
+            Some(Duration::micros(0))
+        );
+
+        ll.timer().set(wake);
+        let cmd = ll.update_timer(&mut tx);
+        assert_eq!(cmd.window.unwrap().start, wake);
+        assert!(matches!(
+            cmd.radio,
+            RadioCmd::ListenData { channel, timeout: false, .. } if channel.index() == 35
+        ));
+
+        // Event #4. The master indicates more data, so the event continues on the same channel.
+        let rx_end = rx_end + Duration::micros(4 * 7_500);
+        ll.timer().set(rx_end);
+        let mut header = data::Header::new(data::Llid::DataCont);
+        header.set_sn(SeqNum::ONE);
+        header.set_nesn(SeqNum::ONE);
+        header.set_md(true);
+        let cmd = ll.process_data_packet(rx_end, &mut tx, header, &[], true);
+        assert!(matches!(cmd.radio, RadioCmd::ListenData { channel, .. } if channel.index() == 35));
+
+        // It doesn't send anything after all. We can't skip #5, since the event wasn't idle.
+        ll.timer().set(rx_end + Duration::micros(500));
+        let cmd = ll.process_rx_timeout(&mut tx);
+        assert!(matches!(cmd.radio, RadioCmd::ListenData { channel, .. } if channel.index() == 5));
+
+        // Event #5 skips #6 to #8 again
+        let rx_end = rx_end + Duration::micros(7_500);
+        let cmd = recv(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+
+        // Queueing data wakes us up for the first event whose window didn't open yet (#7 on channel
+        // 19, since #6 already started)
+        app_tx
+            .produce_with(1, |w| -> Result<_, Error> {
+                w.write_u8(0xAA)?;
+                Ok(data::Llid::DataStart)
+            })
+            .unwrap();
+        ll.timer().set(rx_end + Duration::micros(7_500 + 1_000));
+        let cmd = ll.wake_for_tx(handle).unwrap().unwrap();
+        assert!(matches!(cmd.radio, RadioCmd::ListenData { channel, .. } if channel.index() == 19));
+        assert_eq!(
+            cmd.window.unwrap().start,
+            rx_end + Duration::micros(2 * 7_500 - 509)
+        );
+
+        // The data is sent in #7, and we listen for the acknowledgement in #8
+        let rx_end = rx_end + Duration::micros(2 * 7_500);
+        let cmd = recv(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE);
+        assert_eq!(tx.data_sent.last().unwrap().payload_length(), 1);
+        assert!(matches!(cmd.radio, RadioCmd::ListenData { channel, .. } if channel.index() == 26));
+    }
+
+    #[test]
+    fn hopping_with_latency() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (_, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, ll_tx, ll_rx)
+            .unwrap();
+
+        // Latency 4, only channels 0 to 9 used, hopping 7 channels per event
+        let (header, mut payload) = connect_ind(&ll, 1, 0);
+        payload[24..26].copy_from_slice(&4u16.to_le_bytes());
+        payload[28..33].copy_from_slice(&[0xFF, 0x03, 0x00, 0x00, 0x00]);
+        let connect_end = Instant::from_ticks(1_000);
+        ll.timer().set(connect_end);
+        let _ = ll.process_adv_packet(connect_end, &mut tx, header, &payload, true, None);
+        assert_eq!(conn(&ll).hop_increment(), 7);
+
+        // Channel selection algorithm #1 as performed by the master, which doesn't skip events
+        let master_channel = |event: u32| {
+            let unmapped = (event + 1) * 7 % 37;
+            let channel = if unmapped < 10 {
+                unmapped
+            } else {
+                unmapped % 10
+            };
+            (unmapped as u8, channel as u8)
+        };
+
+        // Each event is idle, so the following 4 are skipped
+        let mut anchor = connect_end + Duration::millis(2);
+        let mut sn = SeqNum::ZERO;
+        for event in (0..25).step_by(5) {
+            let conn = conn(&ll);
+            assert_eq!(conn.event_counter(), event as u16);
+            let (unmapped, channel) = master_channel(event);
+            assert_eq!(conn.unmapped_channel().index(), unmapped);
+            assert_eq!(conn.channel().index(), channel);
+
+            ll.timer().set(anchor);
+            let mut header = data::Header::new(data::Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(sn);
+            let cmd = ll.process_data_packet(anchor, &mut tx, header, &[], true);
+            assert!(matches!(cmd.radio, RadioCmd::Off));
+
+            let NextUpdate::At(wakeup) = cmd.next_update else {
+                panic!("no wakeup scheduled");
+            };
+            ll.timer().set(wakeup);
+            let cmd = ll.update_timer(&mut tx);
+            let (_, channel) = master_channel(event + 5);
+            assert!(matches!(
+                cmd.radio,
+                RadioCmd::ListenData { channel: ch, .. } if ch.index() == channel
+            ));
+
+            anchor += Duration::micros(5 * 7_500);
+            sn += SeqNum::ONE;
+        }
+    }
+
+    #[test]
+    fn more_data() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _app_rx) = Box::leak(Box::new(TestQueue::new())).split();
+        let now = connect_with_queues(&mut ll, &mut tx, ll_tx, ll_rx);
+
+        // Queue 5 notifications at once
+        for i in 0..5 {
+            app_tx
+                .produce_with(4, |w| -> Result<_, Error> {
+                    w.write_slice(&[0x1B, 0x03, 0x00, i])?;
+                    Ok(data::Llid::DataStart)
+                })
+                .unwrap();
+        }
+
+        let recv = |ll: &mut LinkLayer<TestConfig>, tx: &mut TestTransmitter, at, sn| {
+            ll.timer().set(at);
+            let mut header = data::Header::new(data::Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(sn);
+            ll.process_data_packet(at, tx, header, &[], true)
+        };
+        let channel = |cmd: &Cmd| match cmd.radio {
+            RadioCmd::ListenData { channel, .. } => channel.index(),
+            _ => panic!("not listening: {:?}", cmd.radio),
+        };
+
+        // Event #0 on channel 7 (7.5 ms interval). The master acknowledges each PDU 2 ms after the
+        // last one, so 4 of them fit before the event has to close.
+        let anchor = now + Duration::millis(2);
+        let mut sn = SeqNum::ZERO;
+        for i in 0..4 {
+            let cmd = recv(&mut ll, &mut tx, anchor + Duration::millis(2 * i), sn);
+            sn += SeqNum::ONE;
+
+            let sent = tx.data_sent.last().unwrap();
+            assert_eq!(sent.payload_length(), 4);
+            if i < 3 {
+                assert!(sent.md(), "more data queued");
+                assert_eq!(channel(&cmd), 7, "event continues");
+            } else {
+                assert!(!sent.md(), "no time left in the event");
+                assert_eq!(channel(&cmd), 14, "event closed");
+            }
+        }
+
+        // The last one is sent in event #1, which ends right away
+        let cmd = recv(&mut ll, &mut tx, anchor + Duration::micros(7_500), sn);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.payload_length(), 4);
+        assert!(!sent.md());
+        assert_eq!(channel(&cmd), 21);
+        assert_eq!(tx.data_sent.len(), 5);
+    }
+
+    #[test]
+    fn queued_notifications_across_events() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _app_rx) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, ll_tx, ll_rx)
+            .unwrap();
+
+        // 7.5 ms interval, latency 3, hopping 7 channels per event
+        let (header, mut payload) = connect_ind(&ll, 1, 0);
+        payload[24..26].copy_from_slice(&3u16.to_le_bytes());
+        let connect_end = Instant::from_ticks(1_000);
+        ll.timer().set(connect_end);
+        let _ = ll.process_adv_packet(connect_end, &mut tx, header, &payload, true, None);
+        let handle = ll.connection_handle().unwrap();
+
+        // The master acknowledges every PDU it receives intact, and retransmits its own PDU after
+        // one we couldn't decode
+        let mut sn = SeqNum::ZERO;
+        let mut recv = |ll: &mut LinkLayer<TestConfig>, tx: &mut TestTransmitter, at, crc_ok| {
+            ll.timer().set(at);
+            let mut header = data::Header::new(data::Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(sn);
+            let cmd = ll.process_data_packet(at, tx, header, &[], crc_ok);
+            if crc_ok {
+                sn += SeqNum::ONE;
+            }
+            let sent = *tx.data_sent.last().unwrap();
+            let payload = tx.buf[..usize::from(sent.payload_length())].to_vec();
+            (cmd, sent, payload)
+        };
+        let channel = |cmd: &Cmd| match cmd.radio {
+            RadioCmd::ListenData { channel, .. } => channel.index(),
+            _ => panic!("not listening: {:?}", cmd.radio),
+        };
+        // L2CAP header + ATT Handle Value Notification carrying `i`
+        let notification = |i: u8| [4, 0, 0x04, 0x00, 0x1B, 0x03, 0x00, i];
+
+        // Event #0 is idle, so #1 to #3 are skipped
+        let anchor = connect_end + Duration::millis(2);
+        let (cmd, _, _) = recv(&mut ll, &mut tx, anchor, true);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+
+        // Queueing 6 notifications wakes us up for #1
+        for i in 0..6 {
+            app_tx
+                .produce_with(8, |w| -> Result<_, Error> {
+                    w.write_slice(&notification(i))?;
+                    Ok(data::Llid::DataStart)
+                })
+                .unwrap();
+        }
+        ll.timer().set(anchor + Duration::millis(1));
+        assert_eq!(channel(&ll.wake_for_tx(handle).unwrap().unwrap()), 14);
+
+        // Event #1: 3 notifications are sent before the master's 4th PDU is corrupted. The event
+        // closes, and the unacknowledged notification is resent without indicating more data.
+        let anchor = anchor + Duration::micros(7_500);
+        for i in 0..3 {
+            let (cmd, sent, payload) =
+                recv(&mut ll, &mut tx, anchor + Duration::millis(2 * i), true);
+            assert_eq!(payload, notification(i as u8));
+            assert!(sent.md());
+            assert_eq!(channel(&cmd), 14);
+        }
+        let (cmd, sent, payload) = recv(&mut ll, &mut tx, anchor + Duration::millis(6), false);
+        assert_eq!(payload, notification(2));
+        assert!(!sent.md());
+        assert_eq!(channel(&cmd), 21);
+
+        // Event #2: the master acknowledges the resent PDU, and the rest follows in order
+        let anchor = anchor + Duration::micros(7_500);
+        for i in 3..6 {
+            let at = anchor + Duration::millis(2 * (u32::from(i) - 3));
+            let (cmd, sent, payload) = recv(&mut ll, &mut tx, at, true);
+            assert_eq!(payload, notification(i));
+            assert_eq!(sent.md(), i < 5);
+            assert_eq!(channel(&cmd), if i < 5 { 21 } else { 28 });
+        }
+
+        // Event #3 acknowledges the last one, and we can skip events again
+        let anchor = anchor + Duration::micros(7_500);
+        let (cmd, sent, _) = recv(&mut ll, &mut tx, anchor, true);
+        assert_eq!(sent.payload_length(), 0);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+    }
+
+    #[test]
+    fn queued_control_pdu_sent_first() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(TestQueue::new())).split();
+        let now = connect_with_queues(&mut ll, &mut tx, ll_tx, ll_rx);
+
+        for llid in [
+            data::Llid::DataStart,
+            data::Llid::DataStart,
+            data::Llid::Control,
+        ] {
+            app_tx
+                .produce_with(2, |w| -> Result<_, Error> {
+                    w.write_slice(&[0x07, 0x03])?;
+                    Ok(llid)
+                })
+                .unwrap();
+        }
+
+        let rx_end = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        let llids =
+            |tx: &TestTransmitter| tx.data_sent.iter().map(|h| h.llid()).collect::<Vec<_>>();
+        assert_eq!(llids(&tx), [data::Llid::Control]);
+
+        let rx_end = rx_end + Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert_eq!(llids(&tx), [data::Llid::Control, data::Llid::DataStart]);
+    }
+
+    #[test]
+    fn indication_timeout_disconnects() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (mut app_tx, ll_tx) = Box::leak(Box::new(TestQueue::new())).split();
+        let (ll_rx, _) = Box::leak(Box::new(TestQueue::new())).split();
+        let now = connect_with_queues(&mut ll, &mut tx, ll_tx, ll_rx);
+
+        // The client subscribes to indications of the MIDI characteristic
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(MidiServiceAttrs::new()));
+        l2cap
+            .tx(&mut app_tx)
+            .process_start(&[5, 0, 0x04, 0x00, 0x12, 0x04, 0x00, 0x02, 0x00])
+            .into_result()
+            .unwrap();
+        let value = att::Handle::from_raw(0x0003);
+        assert_eq!(l2cap.tx(&mut app_tx).indicate(value, &[1]), Ok(true));
+
+        // Send the Write Response and the indication
+        let mut rx_end = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        rx_end += Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert_eq!(tx.buf[4], 0x1D);
+        let sent = rx_end;
+
+        // A confirmation stops the transaction timer
+        rx_end += Duration::micros(7_500);
+        let confirmation = [1, 0, 0x04, 0x00, 0x1E];
+        let llid = data::Llid::DataStart;
+        let _ = recv_pdu(&mut ll, &mut tx, rx_end, SeqNum::ZERO, llid, &confirmation);
+        rx_end = sent + att::ATT_TRANSACTION_TIMEOUT;
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert_eq!(tx.data_sent.last().unwrap().llid(), data::Llid::DataCont);
+
+        // The next indication is never confirmed
+        l2cap
+            .tx(&mut app_tx)
+            .process_start(&confirmation)
+            .into_result()
+            .unwrap();
+        assert_eq!(l2cap.tx(&mut app_tx).indicate(value, &[2]), Ok(true));
+        rx_end += Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        assert_eq!(tx.buf[4], 0x1D);
+        let sent = rx_end;
+
+        rx_end = sent + att::ATT_TRANSACTION_TIMEOUT - Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert_eq!(tx.data_sent.last().unwrap().llid(), data::Llid::DataCont);
+
+        // The Link-Layer terminates the connection by itself
+        rx_end += Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(tx.buf[..usize::from(sent.payload_length())], [0x02, 0x13]);
+
+        rx_end += Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert!(!ll.is_connected());
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        assert_eq!(
+            ll.disconnect(
+                ConnectionHandle::default(),
+                DisconnectReason::RemoteUserTerminated
+            )
+            .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert!(ll
+            .disconnect(handle, DisconnectReason::RemoteUserTerminated)
+            .unwrap()
+            .is_none());
+
+        // `LL_TERMINATE_IND` is sent in the first connection event
+        let rx_end = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(tx.buf[..usize::from(sent.payload_length())], [0x02, 0x13]);
+        assert!(ll.is_connected());
+
+        // Once the master acknowledges it, the connection is closed
+        let rx_end = rx_end + Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert!(!ll.is_connected());
+        assert_eq!(
+            ll.take_disconnect_reason(),
+            Some(DisconnectReason::LocalHostTerminated)
+        );
+        assert_eq!(ll.take_disconnect_reason(), None);
+
+        // If it never does, the connection is closed after the supervision timeout (100 ms)
+        let mut ll = link_layer();
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        let _ = ll.disconnect(handle, DisconnectReason::RemoteUserTerminated);
+        let mut rx_end = now + Duration::millis(2);
+        let mut sn = SeqNum::ZERO;
+        while ll.is_connected() {
+            assert!(rx_end - now <= Duration::millis(110));
+            recv_empty(&mut ll, &mut tx, rx_end, sn, SeqNum::ZERO, true);
+            sn += SeqNum::ONE;
+            rx_end += Duration::micros(7_500);
+        }
+        assert!(rx_end - now >= Duration::millis(100));
+        assert_eq!(
+            ll.take_disconnect_reason(),
+            Some(DisconnectReason::LocalHostTerminated)
+        );
+    }
+
+    #[test]
+    fn stale_connection_handle() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+        let old = ll.connection_handle().unwrap();
+        ll.disconnect(old, DisconnectReason::RemoteUserTerminated)
+            .unwrap();
+        let rx_end = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ZERO, SeqNum::ZERO, true);
+        let rx_end = rx_end + Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, rx_end, SeqNum::ONE, SeqNum::ONE, true);
+        assert!(!ll.is_connected());
+        assert_eq!(ll.connection_handle(), None);
+
+        // The handle of the closed connection doesn't refer to the next one
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, consumer, producer)
+            .unwrap();
+        let (header, payload) = connect_ind(&ll, 1, 0);
+        let _ = ll.process_adv_packet(rx_end, &mut tx, header, &payload, true, None);
+        let new = ll.connection_handle().unwrap();
+        assert_ne!(new, old);
+        assert_eq!(
+            ll.disconnect(old, DisconnectReason::RemoteUserTerminated)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+        assert_eq!(
+            ll.request_phy(old, Phy::Le2M, Phy::Le2M).unwrap_err(),
+            Error::InvalidValue
+        );
+        assert!(ll
+            .disconnect(new, DisconnectReason::RemoteUserTerminated)
+            .is_ok());
+
+        // Raw handles wrap around after the largest HCI connection handle, but don't alias the
+        // handles of the previous connections
+        let first = ConnectionHandle::default();
+        let last = (0..ConnectionHandle::MAX).fold(first, |h, _| h.next());
+        assert_eq!(last.raw(), ConnectionHandle::MAX);
+        assert_eq!(last.next().raw(), 0);
+        assert_ne!(last.next(), first);
+        assert_eq!(new.next().raw(), new.raw() + 1);
+    }
+
+    #[test]
+    fn link_events() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let events = Events::default();
+        ll.set_event_handler(events.clone());
+
+        let now = connect(&mut ll, &mut tx);
+        let master = DeviceAddress::new([0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6], AddressKind::Public);
+        assert_eq!(
+            *events.0.borrow(),
+            [LinkEvent::Connected {
+                handle: ll.connection_handle().unwrap(),
+                peer: master,
+                interval: Duration::micros(7_500),
+                slave_latency: 0,
+                supervision_timeout: Duration::millis(100),
+            }]
+        );
+        events.0.borrow_mut().clear();
+
+        // The PHY update is reported once it is applied for the event at instant 2
+        let mut master = Master::new(now);
+        let _ = master.recv(&mut ll, &mut tx, &[0x18, 0x02, 0x02, 2, 0]);
+        assert!(events.0.borrow().is_empty());
+        let _ = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(
+            *events.0.borrow(),
+            [LinkEvent::PhyUpdated { phy: Phy::Le2M }]
+        );
+        events.0.borrow_mut().clear();
+
+        // `LL_TERMINATE_IND` with "Remote User Terminated Connection"
+        let _ = master.recv(&mut ll, &mut tx, &[0x02, 0x13]);
+        assert!(!ll.is_connected());
+        assert_eq!(
+            *events.0.borrow(),
+            [LinkEvent::Disconnected {
+                reason: DisconnectReason::RemoteUserTerminated
+            }]
+        );
+    }
+
+    #[test]
+    fn mtu_changed_event() {
+        use crate::l2cap::ChannelMapper;
+
+        let (tx, _) = Box::leak(Box::new(TestQueue::new())).split();
+        let (mut peer, rx) = Box::leak(Box::new(TestQueue::new())).split();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NoAttributes));
+        l2cap
+            .channel_mapper()
+            .att()
+            .into_protocol()
+            .set_max_mtu(100)
+            .unwrap();
+        let mut responder = Responder::<TestConfig>::new(tx, rx, l2cap);
+        let events = Events::default();
+        responder.set_event_handler(events.clone());
+
+        // `ATT_EXCHANGE_MTU_REQ` with a client MTU of 80
+        let mut exchange_mtu = || {
+            peer.produce_with(7, |writer| -> Result<_, Error> {
+                writer.write_slice(&[3, 0, 0x04, 0x00, 0x02, 80, 0])?;
+                Ok(data::Llid::DataStart)
+            })
+            .unwrap();
+            responder.process_one().unwrap();
+        };
+        exchange_mtu();
+        assert_eq!(*events.0.borrow(), [LinkEvent::MtuChanged { mtu: 80 }]);
+
+        // Exchanging the same MTU again doesn't change it
+        exchange_mtu();
+        assert_eq!(events.0.borrow().len(), 1);
+    }
+
+    #[test]
+    fn terminated_by_master() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        // `LL_TERMINATE_IND` with "Remote User Terminated Connection"
+        let mut header = data::Header::new(data::Llid::Control);
+        header.set_payload_length(2);
+        let rx_end = now + Duration::millis(2);
+        ll.timer().set(rx_end);
+        let cmd = ll.process_data_packet(rx_end, &mut tx, header, &[0x02, 0x13], true);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+        assert!(matches!(cmd.next_update, NextUpdate::Disable));
+        assert!(!ll.is_connected());
+        assert_eq!(
+            ll.take_disconnect_reason(),
+            Some(DisconnectReason::RemoteUserTerminated)
+        );
+    }
+
+    #[test]
+    fn unknown_control_pdu() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        let mut master = Master::new(now);
+        let mut recv_control = |ll: &mut LinkLayer<TestConfig>, tx: &mut TestTransmitter, pdu| {
+            let _ = master.recv(ll, tx, pdu);
+            assert_eq!(tx.data_sent.last().unwrap().llid(), data::Llid::Control);
+            last_sent(tx)
+        };
+
+        // `LL_PING_REQ` isn't implemented
+        assert_eq!(recv_control(&mut ll, &mut tx, &[0x12]), [0x07, 0x12]);
+
+        // Neither is opcode 0xF0
+        assert_eq!(recv_control(&mut ll, &mut tx, &[0xF0, 1, 2]), [0x07, 0xF0]);
+
+        // Truncated `LL_CONNECTION_UPDATE_IND`
+        assert_eq!(recv_control(&mut ll, &mut tx, &[0x00, 1, 2]), [0x07, 0x00]);
+        assert!(ll.is_connected());
+    }
+
+    #[test]
+    fn unacceptable_conn_params() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+
+        // `CONNECT_IND` with a timeout that's too short for the interval (2 * 50 ms) is ignored
+        ll.start_advertise(
+            Duration::millis(100),
+            &[],
+            &mut tx,
+            Box::leak(Box::new(TestQueue::new())).split().1,
+            Box::leak(Box::new(TestQueue::new())).split().0,
+        )
+        .unwrap();
+        let (header, mut payload) = connect_ind(&ll, 1, 0);
+        payload[22..24].copy_from_slice(&40u16.to_le_bytes()); // 50 ms interval
+        let now = Instant::from_ticks(1_000);
+        let _ = ll.process_adv_packet(now, &mut tx, header, &payload, true, None);
+        assert!(!ll.is_connected());
+
+        // Returns the PDU sent in response to an `LL_CONNECTION_UPDATE_IND` on a new connection
+        let recv_update = |interval: u16, latency: u16, timeout: u16| {
+            let mut ll = link_layer();
+            let mut tx = TestTransmitter::new();
+            let now = connect(&mut ll, &mut tx);
+            let mut pdu = vec![0x00, 1, 0, 0];
+            for field in [interval, latency, timeout, 10] {
+                pdu.extend_from_slice(&field.to_le_bytes());
+            }
+            let mut header = data::Header::new(data::Llid::Control);
+            header.set_payload_length(pdu.len() as u8);
+            let rx_end = now + Duration::millis(2);
+            ll.timer().set(rx_end);
+            let _ = ll.process_data_packet(rx_end, &mut tx, header, &pdu, true);
+            assert_eq!(conn(&ll).connection_interval(), Duration::micros(7_500));
+
+            let sent = tx.data_sent.last().unwrap();
+            tx.buf[..usize::from(sent.payload_length())].to_vec()
+        };
+
+        // The master doesn't expect a response to the indication, so the connection is terminated
+        // with *Unacceptable Connection Parameters* instead.
+        // Timeout not longer than `(1 + latency) * interval * 2`:
+        assert_eq!(recv_update(40, 4, 50), [0x02, 0x3B]);
+        // Outside of the spec's ranges:
+        assert_eq!(recv_update(5, 0, 100), [0x02, 0x3B]);
+        assert_eq!(recv_update(3201, 0, 3200), [0x02, 0x3B]);
+        assert_eq!(recv_update(6, 500, 3200), [0x02, 0x3B]);
+
+        // Acceptable parameters are applied at the instant, without a response
+        assert!(recv_update(40, 4, 51).is_empty());
+    }
+
+    #[test]
+    fn conn_param_bounds() {
+        enum BoundedConfig {}
+
+        impl Config for BoundedConfig {
+            type Timer = MockTimer;
+            type Transmitter = TestTransmitter;
+            type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+            type PacketQueue = &'static mut TestQueue;
+            type Aes = SoftAesProvider;
+            type AdvReportHandler = Reports;
+            type LinkEventHandler = Events;
+            type Rng = MockRng;
+
+            const MIN_CONN_INTERVAL: Duration = Duration::millis(15);
+            const MAX_CONN_INTERVAL: Duration = Duration::millis(1_000);
+            const MAX_SLAVE_LATENCY: u16 = 10;
+            const MAX_SUPERVISION_TIMEOUT: Duration = Duration::millis(6_000);
+        }
+
+        let acceptable = |interval_ms, latency, timeout_ms| {
+            connection::acceptable_conn_params::<BoundedConfig>(
+                Duration::millis(interval_ms),
+                latency,
+                Duration::millis(timeout_ms),
+            )
+        };
+        assert!(acceptable(15, 10, 6_000));
+        assert!(acceptable(1_000, 0, 6_000));
+        assert!(!acceptable(10, 0, 1_000));
+        assert!(!acceptable(1_001, 0, 6_000));
+        assert!(!acceptable(50, 11, 6_000));
+        assert!(!acceptable(50, 0, 6_010));
+
+        // The defaults only enforce the spec's limits
+        assert!(connection::acceptable_conn_params::<TestConfig>(
+            Duration::millis(4_000),
+            0,
+            Duration::millis(32_000),
+        ));
+    }
+
+    /// Returns the PHY a `Cmd` listens on for data channel PDUs.
+    fn listen_phy(cmd: &Cmd) -> Phy {
+        match cmd.radio {
+            RadioCmd::ListenData { phy, .. } => phy,
+            ref radio => panic!("not listening on a data channel: {:?}", radio),
+        }
+    }
+
+    #[test]
+    fn phy_update() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        assert_eq!(
+            ll.request_phy(ConnectionHandle::default(), Phy::Le2M, Phy::Le2M)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(
+            ll.request_phy(handle, Phy::Le2M, Phy::Le1M).unwrap_err(),
+            Error::InvalidValue
+        );
+        assert!(ll
+            .request_phy(handle, Phy::Le2M, Phy::Le2M)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            ll.request_phy(handle, Phy::Le1M, Phy::Le1M).unwrap_err(),
+            Error::WouldBlock
+        );
+
+        let mut master = Master::new(now);
+
+        // Event #0: `LL_PHY_REQ` asking for the LE 2M PHY in both directions
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(
+            tx.buf[..usize::from(sent.payload_length())],
+            [0x16, 0x02, 0x02]
+        );
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+
+        // Event #1: The master switches both directions at instant 4
+        let cmd = master.recv(&mut ll, &mut tx, &[0x18, 0x02, 0x02, 4, 0]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+
+        // Events #2 and #3 still use the old PHY, #4 the new one
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+        assert_eq!(conn(&ll).phy(), Phy::Le1M);
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), Phy::Le2M);
+        assert_eq!(conn(&ll).phy(), Phy::Le2M);
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), Phy::Le2M);
+
+        // The procedure is complete, so another one can be started
+        assert!(ll.request_phy(handle, Phy::Le1M, Phy::Le1M).is_ok());
+    }
+
+    #[test]
+    fn min_used_channels() {
+        // Without the feature bit, the procedure can't be used
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        ll.set_features(FeatureSet::supported() - FeatureSet::MIN_USED_CHANNELS)
+            .unwrap();
+        connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(
+            ll.request_min_used_channels(handle, PhySet::LE_CODED, 8)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+
+        let mut ll = link_layer();
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(
+            ll.request_min_used_channels(handle, PhySet::empty(), 8)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+        assert_eq!(
+            ll.request_min_used_channels(handle, PhySet::LE_CODED, 38)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+        assert!(ll
+            .request_min_used_channels(handle, PhySet::LE_1M | PhySet::LE_CODED, 8)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            ll.request_min_used_channels(handle, PhySet::LE_CODED, 8)
+                .unwrap_err(),
+            Error::WouldBlock
+        );
+
+        let mut master = Master::new(now);
+
+        // Event #0: `LL_MIN_USED_CHANNELS_IND` for the LE 1M and LE Coded PHYs
+        let _ = master.recv(&mut ll, &mut tx, &[]);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(
+            tx.buf[..usize::from(sent.payload_length())],
+            [0x19, 0x05, 0x08]
+        );
+
+        // The indication was sent, so another one can be requested
+        assert!(ll
+            .request_min_used_channels(handle, PhySet::LE_CODED, 10)
+            .is_ok());
+
+        // Indications received from the peer are stored
+        assert_eq!(conn(&ll).peer_min_used_channels(), None);
+        let _ = master.recv(&mut ll, &mut tx, &[0x19, 0x02, 0x0C]);
+        let ind = conn(&ll).peer_min_used_channels().unwrap();
+        assert_eq!(ind.phys(), PhySet::LE_2M);
+        assert_eq!(ind.min_used_channels(), 12);
+
+        // Once the master reports that it doesn't support the procedure, it can't be used anymore
+        let _ = master.recv(&mut ll, &mut tx, &[0x08, 0x01, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            ll.request_min_used_channels(handle, PhySet::LE_CODED, 8)
+                .unwrap_err(),
+            Error::InvalidValue
+        );
+    }
+
+    #[test]
+    fn preferred_conn_params_requested() {
+        // `connect` uses a 7.5 ms interval, no latency and a 100 ms timeout
+        let mut params = ConnectionParamRequest::new();
+        params.set_conn_interval(Duration::millis(30), Duration::millis(50));
+        params.set_slave_latency(4);
+        params.set_supervision_timeout(Duration::millis(2_000));
+
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        ll.features |= FeatureSet::CONN_PARAM_REQ;
+        tx.features |= FeatureSet::CONN_PARAM_REQ;
+        ll.set_preferred_conn_params(Some(params));
+        let now = connect(&mut ll, &mut tx);
+
+        let mut master = Master::new(now);
+
+        // L2CAP Connection Parameter Update Request with the preferred parameters
+        let l2cap_req = [12, 0, 0x05, 0, 0x12, 0xFF, 8, 0, 24, 0, 40, 0, 4, 0, 200, 0];
+
+        // Event #0: `LL_CONNECTION_PARAM_REQ` with the preferred parameters, since both devices
+        // support the procedure
+        let _ = master.recv(&mut ll, &mut tx, &[]);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(sent.payload_length(), 24);
+        assert_eq!(tx.buf[..12], [0x0F, 24, 0, 40, 0, 4, 0, 200, 0, 0, 0, 0]);
+
+        // Event #1: A master that doesn't know the procedure after all gets the request via L2CAP
+        let _ = master.recv(&mut ll, &mut tx, &[0x07, 0x0F]);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataStart);
+        assert_eq!(tx.buf[..16], l2cap_req);
+
+        // Event #2: The master accepts with an `LL_CONNECTION_UPDATE_IND`
+        let _ = master.recv(
+            &mut ll,
+            &mut tx,
+            &[0x00, 1, 0, 0, 32, 0, 4, 0, 200, 0, 6, 0],
+        );
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataCont);
+        assert_eq!(sent.payload_length(), 0);
+        let _ = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(tx.data_sent.last().unwrap().payload_length(), 0);
+
+        // Without support for the procedure, the L2CAP request is sent right away
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        ll.set_preferred_conn_params(Some(params));
+        let now = connect(&mut ll, &mut tx);
+        recv_empty(
+            &mut ll,
+            &mut tx,
+            now + Duration::millis(2),
+            SeqNum::ZERO,
+            SeqNum::ZERO,
+            true,
+        );
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataStart);
+        assert_eq!(sent.payload_length(), 16);
+        assert_eq!(tx.buf[..16], l2cap_req);
+
+        // No request is made if the connection already satisfies the preferred parameters
+        let mut params = ConnectionParamRequest::new();
+        params.set_conn_interval(Duration::micros(7_500), Duration::millis(10));
+        params.set_supervision_timeout(Duration::millis(100));
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        ll.set_preferred_conn_params(Some(params));
+        let now = connect(&mut ll, &mut tx);
+        recv_empty(
+            &mut ll,
+            &mut tx,
+            now + Duration::millis(2),
+            SeqNum::ZERO,
+            SeqNum::ZERO,
+            true,
+        );
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataCont);
+        assert_eq!(sent.payload_length(), 0);
+    }
+
+    #[test]
+    fn conn_param_update_requested() {
+        let mut params = ConnectionParamRequest::new();
+        params.set_conn_interval(Duration::millis(30), Duration::millis(50));
+        params.set_slave_latency(4);
+        params.set_supervision_timeout(Duration::millis(2_000));
+        let l2cap_req = [12, 0, 0x05, 0, 0x12, 0xFF, 8, 0, 24, 0, 40, 0, 4, 0, 200, 0];
+
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        ll.features |= FeatureSet::CONN_PARAM_REQ;
+        tx.features |= FeatureSet::CONN_PARAM_REQ;
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+
+        // Both devices support the procedure, so `LL_CONNECTION_PARAM_REQ` is sent
+        ll.request_conn_param_update(handle, params).unwrap();
+        assert_eq!(
+            ll.request_conn_param_update(handle, params).unwrap_err(),
+            Error::WouldBlock
+        );
+        let mut at = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, at, SeqNum::ZERO, SeqNum::ZERO, true);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::Control);
+        assert_eq!(tx.buf[..12], [0x0F, 24, 0, 40, 0, 4, 0, 200, 0, 0, 0, 0]);
+
+        // The master doesn't know the procedure after all, so the request is sent via L2CAP
+        at += Duration::micros(7_500);
+        let llid = data::Llid::Control;
+        let _ = recv_pdu(&mut ll, &mut tx, at, SeqNum::ONE, llid, &[0x07, 0x0F]);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataStart);
+        assert_eq!(tx.buf[..16], l2cap_req);
+
+        // Further requests go straight to L2CAP
+        ll.request_conn_param_update(handle, params).unwrap();
+        at += Duration::micros(7_500);
+        recv_empty(&mut ll, &mut tx, at, SeqNum::ZERO, SeqNum::ZERO, true);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataStart);
+        assert_eq!(tx.buf[..16], l2cap_req);
+
+        // Without support for the procedure, the L2CAP request is sent right away
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        ll.request_conn_param_update(handle, params).unwrap();
+        let at = now + Duration::millis(2);
+        recv_empty(&mut ll, &mut tx, at, SeqNum::ZERO, SeqNum::ZERO, true);
+        let sent = *tx.data_sent.last().unwrap();
+        assert_eq!(sent.llid(), data::Llid::DataStart);
+        assert_eq!(tx.buf[..16], l2cap_req);
+    }
+
+    #[test]
+    fn instant_across_event_counter_wrap() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        let mut master = Master::new(now);
+
+        while conn(&ll).event_counter() != 0xFFFE {
+            let _ = master.recv(&mut ll, &mut tx, &[]);
+            tx.data_sent.clear();
+            assert!(ll.is_connected());
+        }
+
+        // Event #65534: The master switches to the LE 2M PHY at instant 1, after the wrap
+        let cmd = master.recv(&mut ll, &mut tx, &[0x18, 0x02, 0x02, 0x01, 0x00]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+
+        // Events #65535 and #0 still use the old PHY, #1 the new one
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+        assert_eq!(conn(&ll).event_counter(), 0);
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), Phy::Le2M);
+        assert_eq!(conn(&ll).event_counter(), 1);
+        assert_eq!(conn(&ll).phy(), Phy::Le2M);
+
+        // An instant just before the wrap has passed after it
+        let _ = master.recv(&mut ll, &mut tx, &[0x18, 0x01, 0x01, 0xFE, 0xFF]);
+        assert!(!ll.is_connected());
+    }
+
+    #[test]
+    fn phy_update_by_master() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+
+        let mut master = Master::new(now);
+
+        // The master prefers LE 2M for receiving only, so we offer a PHY usable in both directions
+        let _ = master.recv(&mut ll, &mut tx, &[0x16, 0x03, 0x02]);
+        assert_eq!(last_sent(&tx), [0x17, 0x02, 0x02]);
+
+        // The master decides to keep the current PHYs
+        let cmd = master.recv(&mut ll, &mut tx, &[0x18, 0x00, 0x00, 3, 0]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+        for _ in 0..3 {
+            let cmd = master.recv(&mut ll, &mut tx, &[0x12]);
+            assert_eq!(listen_phy(&cmd), Phy::Le1M);
+        }
+
+        // Without the feature, `LL_PHY_REQ` is unknown
+        let mut ll = link_layer();
+        ll.set_features(FeatureSet::LE_ENCRYPTION).unwrap();
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(
+            ll.request_phy(handle, Phy::Le2M, Phy::Le2M).unwrap_err(),
+            Error::InvalidValue
+        );
+        let mut header = data::Header::new(data::Llid::Control);
+        header.set_payload_length(3);
+        let rx_end = now + Duration::millis(2);
+        ll.timer().set(rx_end);
+        let _ = ll.process_data_packet(rx_end, &mut tx, header, &[0x16, 0x02, 0x02], true);
+        let sent = tx.data_sent.last().unwrap();
+        assert_eq!(tx.buf[..usize::from(sent.payload_length())], [0x07, 0x16]);
+    }
+
+    #[test]
+    fn phy_update_coded() {
+        let coded_s2 = Phy::LeCoded {
+            ci: CodingIndicator::S2,
+        };
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let now = connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert!(ll
+            .request_phy(handle, coded_s2, coded_s2)
+            .unwrap()
+            .is_none());
+
+        let mut master = Master::new(now);
+
+        // The coding is not part of `LL_PHY_REQ`, but is kept for our transmissions
+        let _ = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(last_sent(&tx), [0x16, 0x04, 0x04]);
+        let cmd = master.recv(&mut ll, &mut tx, &[0x18, 0x04, 0x04, 3, 0]);
+        assert_eq!(listen_phy(&cmd), Phy::Le1M);
+        let cmd = master.recv(&mut ll, &mut tx, &[]);
+        assert_eq!(listen_phy(&cmd), coded_s2);
+        assert_eq!(conn(&ll).phy(), coded_s2);
+
+        // A master that only offers the LE Coded PHY gets it
+        let _ = master.recv(&mut ll, &mut tx, &[0x16, 0x04, 0x05]);
+        assert_eq!(last_sent(&tx), [0x17, 0x04, 0x04]);
+
+        // Without `LE_CODED_PHY`, it can't be requested
+        let mut ll = link_layer();
+        ll.set_features(FeatureSet::LE_2M_PHY).unwrap();
+        connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        assert_eq!(
+            ll.request_phy(handle, coded_s2, coded_s2).unwrap_err(),
+            Error::InvalidValue
+        );
+    }
+
+    #[test]
+    fn phys_limited_by_radio() {
+        // PHYs the radio can't use are never requested, even though they're enabled by default
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        tx.features -= FeatureSet::LE_2M_PHY | FeatureSet::LE_CODED_PHY;
+        connect(&mut ll, &mut tx);
+        let handle = ll.connection_handle().unwrap();
+        for phy in [
+            Phy::Le2M,
+            Phy::LeCoded {
+                ci: CodingIndicator::S8,
+            },
+        ] {
+            assert_eq!(
+                ll.request_phy(handle, phy, phy).unwrap_err(),
+                Error::InvalidValue
+            );
+        }
+        assert!(ll.request_phy(handle, Phy::Le1M, Phy::Le1M).is_ok());
+    }
+
+    #[test]
+    fn advertising_params() {
+        assert!(AdvParams::new(Duration::millis(20)).validate().is_ok());
+        assert!(AdvParams::new(Duration::millis(19)).validate().is_err());
+        assert!(AdvParams::new(Duration::millis(10_241)).validate().is_err());
+        let no_channels = AdvParams {
+            channels: AdvChannels::empty(),
+            ..AdvParams::new(Duration::millis(100))
+        };
+        assert_eq!(no_channels.validate(), Err(Error::InvalidValue));
+        let directed = AdvParams {
+            pdu_type: PduType::AdvDirectInd,
+            ..AdvParams::new(Duration::millis(100))
+        };
+        assert_eq!(directed.validate(), Err(Error::InvalidValue));
+
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        let params = AdvParams {
+            channels: AdvChannels::CH38 | AdvChannels::CH39,
+            ..AdvParams::new(Duration::millis(100))
+        };
+        let next_at = |next: &NextUpdate| match *next {
+            NextUpdate::At(at) => at,
+            _ => panic!("no update scheduled"),
+        };
+
+        // Each event sends the PDU on channel 38, then on 39
+        let mut event_start = ll.timer().now();
+        let mut next = ll
+            .start_advertising(params, &[], &mut tx, consumer, producer)
+            .unwrap();
+        let mut delays = Vec::new();
+        for _ in 0..10 {
+            assert_eq!(next_at(&next), event_start + ADV_EVENT_LEN);
+            ll.timer().set(next_at(&next));
+            next = ll.update_timer(&mut tx).next_update;
+
+            // The next event starts after the interval plus `advDelay`
+            let delay = next_at(&next) - event_start - params.interval;
+            assert!(delay <= advertising::MAX_ADV_DELAY, "delay {}", delay);
+            delays.push(delay);
+
+            event_start = next_at(&next);
+            ll.timer().set(event_start);
+            next = ll.update_timer(&mut tx).next_update;
+        }
+        let channels: Vec<_> = tx.sent.iter().map(|(_, _, ch)| ch.channel()).collect();
+        assert_eq!(channels[..4], [38, 39, 38, 39]);
+        assert!(delays.iter().any(|d| *d != delays[0]), "delay is random");
+    }
+
+    #[test]
+    fn advertising_repeat() {
+        let too_many = AdvParams {
+            repeat: AdvParams::MAX_REPEAT + 1,
+            ..AdvParams::new(Duration::millis(100))
+        };
+        assert_eq!(too_many.validate(), Err(Error::InvalidValue));
+
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        let params = AdvParams {
+            channels: AdvChannels::CH37 | AdvChannels::CH38,
+            repeat: 2,
+            ..AdvParams::new(Duration::millis(100))
+        };
+
+        // Each scheduled transmission sends the PDU 3 times on the same channel
+        let event_start = ll.timer().now();
+        let next = ll
+            .start_advertising(params, &[], &mut tx, consumer, producer)
+            .unwrap();
+        let channels: Vec<_> = tx.sent.iter().map(|(_, _, ch)| ch.channel()).collect();
+        assert_eq!(channels, [37, 37, 37]);
+        assert!(tx.sent.iter().all(|(_, pl, _)| *pl == tx.sent[0].1));
+
+        // The next channel is used once the repetitions and a request could be exchanged
+        let channel_len = ADV_EVENT_LEN + ADV_REPEAT_LEN * 2;
+        let next_adv = match next {
+            NextUpdate::At(at) => at,
+            _ => panic!("no update scheduled"),
+        };
+        assert_eq!(next_adv, event_start + channel_len);
+
+        ll.timer().set(next_adv);
+        let cmd = ll.update_timer(&mut tx);
+        let channels: Vec<_> = tx.sent.iter().map(|(_, _, ch)| ch.channel()).collect();
+        assert_eq!(channels, [37, 37, 37, 38, 38, 38]);
+        assert_eq!(cmd.window.unwrap().max_len, channel_len);
+    }
+
+    #[test]
+    fn advertising_nonconnectable() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        let params = AdvParams {
+            channels: AdvChannels::CH37,
+            pdu_type: PduType::AdvNonconnInd,
+            ..AdvParams::new(Duration::millis(100))
+        };
+        let next = ll
+            .start_advertising(params, &[], &mut tx, consumer, producer)
+            .unwrap();
+        assert_eq!(tx.sent[0].0.type_(), PduType::AdvNonconnInd);
+        assert_eq!(tx.sent[0].2.channel(), 37);
+
+        // With a single channel, every update starts a new event
+        let start = Instant::from_ticks(0);
+        assert!(matches!(
+            next,
+            NextUpdate::At(t) if t - start >= Duration::millis(100) && t - start <= Duration::millis(110)
+        ));
+
+        // Connection requests are ignored
+        let (header, payload) = connect_ind(&ll, 1, 0);
+        let _ = ll.process_adv_packet(start, &mut tx, header, &payload, true, None);
+        assert!(ll.is_advertising());
+    }
+
+    #[test]
+    fn advertising_pdu_type_switch() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        assert_eq!(
+            ll.set_adv_pdu_type(PduType::AdvScanInd, &[]),
+            Err(Error::InvalidValue)
+        );
+
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        let params = AdvParams {
+            channels: AdvChannels::CH37,
+            ..AdvParams::new(Duration::millis(100))
+        };
+        let mut next = ll
+            .start_advertising(params, &[], &mut tx, consumer, producer)
+            .unwrap();
+        assert_eq!(
+            ll.set_adv_pdu_type(PduType::AdvDirectInd, &[]),
+            Err(Error::InvalidValue)
+        );
+
+        // PDU type bits of the header, and whether a `CONNECT_IND` is accepted
+        for (pdu_type, bits, connectable) in [
+            (PduType::AdvScanInd, 0b0110, false),
+            (PduType::AdvNonconnInd, 0b0010, false),
+            (PduType::AdvInd, 0b0000, true),
+        ] {
+            ll.set_adv_pdu_type(pdu_type, &[]).unwrap();
+            let at = match next {
+                NextUpdate::At(at) => at,
+                _ => panic!("no update scheduled"),
+            };
+            ll.timer().set(at);
+            next = ll.update_timer(&mut tx).next_update;
+
+            let header = tx.sent.last().unwrap().0;
+            assert_eq!(header.type_(), pdu_type);
+            assert_eq!(header.to_u16() & 0b1111, bits);
+
+            let (header, payload) = connect_ind(&ll, 1, 0);
+            let _ = ll.process_adv_packet(at, &mut tx, header, &payload, true, None);
+            assert_eq!(ll.is_connected(), connectable);
+        }
+    }
+
+    #[test]
+    fn rx_timeout() {
+        let mut ll = link_layer();
+        let mut tx = TestTransmitter::new();
+        let (producer, consumer) = Box::leak(Box::new(TestQueue::new())).split();
+        ll.start_advertise(Duration::millis(100), &[], &mut tx, consumer, producer)
+            .unwrap();
+
+        // While advertising, the radio only listens for a response right after each PDU
+        let cmd = ll.update_timer(&mut tx);
+        assert_eq!(cmd.radio.rx_timeout(), Some(ADV_RX_TIMEOUT));
+        let cmd = ll.process_rx_timeout(&mut tx);
+        assert!(matches!(cmd.radio, RadioCmd::Off));
+        assert!(matches!(cmd.next_update, NextUpdate::Keep));
+
+        // A timeout in a connection counts as a missed connection event
+        let mut ll = link_layer();
+        let now = connect(&mut ll, &mut tx);
+        let last_rx = now + Duration::micros(2_000);
+        recv_empty(&mut ll, &mut tx, last_rx, SeqNum::ZERO, SeqNum::ZERO, true);
+        while ll.is_connected() {
+            let cmd = ll.process_rx_timeout(&mut tx);
+            assert!(cmd.radio.rx_timeout().is_some() || !ll.is_connected());
+            match cmd.next_update {
+                NextUpdate::At(at) => ll.timer().set(at),
+                _ => break,
+            }
+        }
+        assert!(!ll.is_connected());
+        assert!(ll.timer().now() - last_rx >= Duration::millis(100));
+    }
 }
@@ -0,0 +1,393 @@
+//! Periodic advertising (Bluetooth 5).
+//!
+//! A periodic advertising train broadcasts `AUX_SYNC_IND` PDUs at a fixed interval on the secondary
+//! advertising channels, hopping according to Channel Selection Algorithm #2. Scanners find the
+//! train through regular extended advertising events: An `ADV_EXT_IND` on each primary channel
+//! points to an `AUX_ADV_IND`, whose `SyncInfo` field describes the timing, channel map and Access
+//! Address of the train (see [`extended`](super::extended)).
+//!
+//! Both kinds of events are sent by the same [`LinkLayer`] state, which makes sure that they don't
+//! overlap. Only the transmit side is supported, Rubble can't synchronize to trains sent by other
+//! devices.
+//!
+//! [`LinkLayer`]: super::LinkLayer
+
+use crate::link::advertising::{AdvChannels, AdvParams, SleepClockAccuracy};
+use crate::link::channel_map::{channel_identifier, ChannelMap};
+use crate::link::extended::{airtime, ExtAdvEvents, ExtHeader, ExtPduBuf, SyncInfo, PDU_SPACING};
+use crate::link::{ad_structure::AdStructure, random_access_address, DeviceAddress};
+use crate::link::{Cmd, NextUpdate, RadioCmd, TimeWindow, Transmitter};
+use crate::security::rng::Rng;
+use crate::time::{Duration, Instant, InstantExt, T_MAFS};
+use crate::Error;
+
+/// Payload size of the `AUX_ADV_IND` PDU (extended header with `AdvA`, `ADI` and `SyncInfo`).
+const AUX_ADV_IND_LEN: usize = 1 + 1 + 6 + 2 + 18;
+
+/// Parameters for periodic advertising, passed to [`LinkLayer::start_periodic_advertising`].
+///
+/// [`LinkLayer::start_periodic_advertising`]: super::LinkLayer::start_periodic_advertising
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeriodicAdvParams {
+    /// Time between the start of two extended advertising events, which announce the periodic
+    /// advertising train.
+    ///
+    /// Must lie between [`AdvParams::MIN_INTERVAL`] and [`AdvParams::MAX_INTERVAL`]. Like for
+    /// legacy advertising, a pseudo-random delay of up to
+    /// [`MAX_ADV_DELAY`](super::advertising::MAX_ADV_DELAY) is added to every interval.
+    pub adv_interval: Duration,
+
+    /// Time between two `AUX_SYNC_IND` PDUs of the periodic advertising train.
+    ///
+    /// Must be a multiple of 1.25 ms between [`MIN_SYNC_INTERVAL`](Self::MIN_SYNC_INTERVAL) and
+    /// [`MAX_SYNC_INTERVAL`](Self::MAX_SYNC_INTERVAL).
+    pub sync_interval: Duration,
+
+    /// The primary channels to send the `ADV_EXT_IND` PDU on during each extended advertising
+    /// event. Must not be empty.
+    pub channels: AdvChannels,
+
+    /// The secondary channels used by the periodic advertising train.
+    ///
+    /// Must mark at least 2 channels as used.
+    pub channel_map: ChannelMap,
+
+    /// Advertising Set ID, which identifies the train to scanners. Must not exceed 15.
+    pub sid: u8,
+}
+
+impl PeriodicAdvParams {
+    /// Smallest allowed periodic advertising interval (7.5 ms).
+    pub const MIN_SYNC_INTERVAL: Duration = Duration::micros(7_500);
+
+    /// Largest supported periodic advertising interval (~4.91 s).
+    ///
+    /// The spec allows longer intervals, but `SyncInfo` can only point this far ahead.
+    pub const MAX_SYNC_INTERVAL: Duration = Duration::micros(3_931 * 1_250);
+
+    /// Creates parameters for a periodic advertising train with the given intervals, using all
+    /// primary and secondary channels and Advertising Set ID 0.
+    pub fn new(adv_interval: Duration, sync_interval: Duration) -> Self {
+        Self {
+            adv_interval,
+            sync_interval,
+            channels: AdvChannels::all(),
+            channel_map: ChannelMap::with_all_channels(),
+            sid: 0,
+        }
+    }
+
+    /// Checks that the parameters are within the allowed ranges.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let adv_interval_ok =
+            (AdvParams::MIN_INTERVAL..=AdvParams::MAX_INTERVAL).contains(&self.adv_interval);
+        let sync_interval_ok = (Self::MIN_SYNC_INTERVAL..=Self::MAX_SYNC_INTERVAL)
+            .contains(&self.sync_interval)
+            && self.sync_interval.to_micros() % 1_250 == 0;
+        if adv_interval_ok
+            && sync_interval_ok
+            && !self.channels.is_empty()
+            && self.channel_map.num_used_channels() >= 2
+            && self.sid <= 0xF
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+}
+
+/// State of a device sending a periodic advertising train.
+pub(crate) struct PeriodicAdvertiser {
+    params: PeriodicAdvParams,
+    adv_addr: DeviceAddress,
+    sca: SleepClockAccuracy,
+
+    /// The extended advertising events announcing the train.
+    events: ExtAdvEvents,
+
+    /// Access Address of the `AUX_SYNC_IND` PDUs.
+    access_address: u32,
+    crc_init: u32,
+    channel_id: u16,
+
+    /// Precomputed `AUX_SYNC_IND` PDU.
+    sync_pdu: ExtPduBuf,
+
+    /// Time of the next `AUX_SYNC_IND`.
+    next_sync: Instant,
+
+    /// Event counter of the next `AUX_SYNC_IND`.
+    sync_counter: u16,
+}
+
+impl PeriodicAdvertiser {
+    /// Creates a periodic advertising train sending `data`, starting at `now`.
+    ///
+    /// The first extended advertising event starts immediately, followed by the first
+    /// `AUX_SYNC_IND`.
+    pub fn new<R: Rng + ?Sized>(
+        params: PeriodicAdvParams,
+        adv_addr: DeviceAddress,
+        data: &[AdStructure<'_>],
+        sca_ppm: u32,
+        now: Instant,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        params.validate()?;
+        let sync_pdu = ExtPduBuf::new(&ExtHeader::new(), data)?;
+        let access_address = random_access_address(rng);
+        let events = ExtAdvEvents::new(
+            params.adv_interval,
+            params.channels,
+            params.sid,
+            sca_ppm,
+            now,
+            rng,
+        )?;
+
+        Ok(Self {
+            params,
+            adv_addr,
+            sca: SleepClockAccuracy::from_ppm(sca_ppm),
+            // The first `AUX_SYNC_IND` follows the `AUX_ADV_IND` after the minimum spacing
+            next_sync: now + events.event_len(AUX_ADV_IND_LEN) + T_MAFS,
+            events,
+            access_address,
+            crc_init: rng.next_u32() & 0xFF_FFFF,
+            channel_id: channel_identifier(access_address),
+            sync_pdu,
+            sync_counter: 0,
+        })
+    }
+
+    /// Returns the size of the largest PDU payload that will be sent.
+    pub fn max_payload_len(&self) -> usize {
+        self.sync_pdu.payload().len().max(AUX_ADV_IND_LEN)
+    }
+
+    /// Sends the next due PDU and returns the `Cmd` to apply afterwards.
+    pub fn update<T: Transmitter, R: Rng + ?Sized>(&mut self, tx: &mut T, rng: &mut R) -> Cmd {
+        let window = if self.next_sync <= self.events.next_pdu_time() {
+            self.send_sync(tx)
+        } else {
+            let sync_info = |pdu_time: Instant| {
+                SyncInfo::new(
+                    self.next_sync.wrapping_duration_since(pdu_time),
+                    self.params.sync_interval,
+                    self.params.channel_map,
+                    self.sca,
+                    self.access_address,
+                    self.crc_init,
+                    self.sync_counter,
+                )
+                .unwrap()
+            };
+            let (adv_addr, adi) = (self.adv_addr, self.events.adi());
+            self.events.send_next(tx, rng, |pdu_time| {
+                let header = ExtHeader::new()
+                    .adv_a(adv_addr)
+                    .adi(adi)
+                    .sync_info(sync_info(pdu_time));
+                ExtPduBuf::new(&header, &[]).unwrap()
+            })
+        };
+
+        self.avoid_sync_overlap();
+        let ext_time = self.events.next_pdu_time();
+        let next_update = if self.next_sync < ext_time {
+            self.next_sync
+        } else {
+            ext_time
+        };
+        Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::At(next_update),
+            window: Some(window),
+            queued_work: false,
+        }
+    }
+
+    /// Postpones the next extended advertising event if it would overlap the next
+    /// `AUX_SYNC_IND`.
+    fn avoid_sync_overlap(&mut self) {
+        let event_start = match self.events.next_event_start() {
+            Some(start) => start,
+            None => return,
+        };
+
+        // The sync interval is longer than both events together, so moving the advertising event
+        // behind this `AUX_SYNC_IND` can't make it overlap the following one
+        let sync_end = self.next_sync + airtime(self.sync_pdu.payload().len()) + PDU_SPACING;
+        let event_end = event_start + self.events.event_len(AUX_ADV_IND_LEN);
+        if event_start < sync_end && self.next_sync < event_end {
+            self.events.postpone(sync_end);
+        }
+    }
+
+    fn send_sync<T: Transmitter>(&mut self, tx: &mut T) -> TimeWindow {
+        let channel = self
+            .params
+            .channel_map
+            .csa2_channel(self.channel_id, self.sync_counter);
+
+        let payload = self.sync_pdu.payload();
+        tx.tx_payload_buf()[..payload.len()].copy_from_slice(payload);
+        tx.transmit_secondary(
+            self.access_address,
+            self.crc_init,
+            self.sync_pdu.header(),
+            channel,
+        );
+
+        let window = TimeWindow::new(self.next_sync, airtime(payload.len()));
+        self.next_sync += self.params.sync_interval;
+        self.sync_counter = self.sync_counter.wrapping_add(1);
+        window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::advertising::ACCESS_ADDRESS;
+    use crate::link::mock::{Device, MockChannel, MockPacket};
+    use crate::link::AddressKind;
+    use crate::security::rng::MockRng;
+    use std::vec::Vec;
+
+    const ADDR: DeviceAddress = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+
+    fn at(us: u32) -> Instant {
+        Instant::from_ticks(0) + Duration::micros(us)
+    }
+
+    #[test]
+    fn params() {
+        let params = PeriodicAdvParams::new(Duration::millis(100), Duration::millis(50));
+        assert!(params.validate().is_ok());
+
+        let mut invalid = params;
+        invalid.sync_interval = Duration::micros(7_600);
+        assert_eq!(invalid.validate(), Err(Error::InvalidValue));
+
+        let mut invalid = params;
+        invalid.channel_map = ChannelMap::from_raw([1, 0, 0, 0, 0]);
+        assert_eq!(invalid.validate(), Err(Error::InvalidValue));
+
+        let mut invalid = params;
+        invalid.sid = 16;
+        assert_eq!(invalid.validate(), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn train() {
+        let params = PeriodicAdvParams::new(Duration::millis(100), Duration::millis(20));
+        let data = [AdStructure::CompleteLocalName("rubble")];
+        let mut rng = MockRng::default();
+        let mut adv = PeriodicAdvertiser::new(params, ADDR, &data, 50, at(0), &mut rng).unwrap();
+        let mut channel = MockChannel::new();
+
+        // 3 `ADV_EXT_IND`s and the `AUX_ADV_IND`, 600 µs apart
+        let mut times = Vec::new();
+        let mut now = at(0);
+        for _ in 0..4 {
+            times.push(now);
+            match adv.update(channel.radio(Device::A), &mut rng).next_update {
+                NextUpdate::At(next) => now = next,
+                _ => panic!("no update scheduled"),
+            }
+        }
+        assert_eq!(times, [at(0), at(600), at(1_200), at(1_800)]);
+
+        let mut packets = Vec::new();
+        while let Some(packet) = channel.receive(Device::B) {
+            packets.push(packet);
+        }
+        assert_eq!(packets.len(), 4);
+        let aux_channel = match &packets[3] {
+            MockPacket::Secondary {
+                access_address,
+                payload,
+                channel,
+                ..
+            } => {
+                assert_eq!(*access_address, ACCESS_ADDRESS);
+                // AdvA, ADI and SyncInfo
+                assert_eq!(&payload[..2], [27, 0b0010_1001]);
+                assert_eq!(&payload[2..8], &ADDR.raw()[..]);
+                *channel
+            }
+            packet => panic!("expected AUX_ADV_IND, got {:?}", packet),
+        };
+        for (i, packet) in packets[..3].iter().enumerate() {
+            match packet {
+                MockPacket::Advertising { payload, .. } => {
+                    // ADI and AuxPtr pointing at the `AUX_ADV_IND`
+                    assert_eq!(payload[1], 0b0001_1000);
+                    assert_eq!(payload[4] & 0x3F, aux_channel.index());
+                    assert_eq!(payload[5], 20 * (3 - i as u8));
+                }
+                packet => panic!("expected ADV_EXT_IND, got {:?}", packet),
+            }
+        }
+
+        // The train follows the extended advertising event after T_MAFS, hopping with CSA #2
+        assert_eq!(now, at(3 * 600 + 38 * 8 + 300));
+        let channel_id = channel_identifier(adv.access_address);
+        for counter in 0..3 {
+            match adv.update(channel.radio(Device::A), &mut rng).next_update {
+                NextUpdate::At(next) => assert_eq!(next, now + params.sync_interval),
+                _ => panic!("no update scheduled"),
+            }
+            now += params.sync_interval;
+            match channel.receive(Device::B) {
+                Some(MockPacket::Secondary {
+                    access_address,
+                    payload,
+                    channel,
+                    ..
+                }) => {
+                    assert_eq!(access_address, adv.access_address);
+                    assert_eq!(&payload[..], b"\x00\x07\x09rubble");
+                    assert_eq!(
+                        channel,
+                        params.channel_map.csa2_channel(channel_id, counter)
+                    );
+                }
+                packet => panic!("expected AUX_SYNC_IND, got {:?}", packet),
+            }
+        }
+    }
+
+    #[test]
+    fn postpones_overlapping_event() {
+        let params = PeriodicAdvParams::new(Duration::millis(20), Duration::micros(7_500));
+        let mut rng = MockRng::default();
+        let mut adv = PeriodicAdvertiser::new(params, ADDR, &[], 50, at(0), &mut rng).unwrap();
+        let mut channel = MockChannel::new();
+
+        // Extended advertising events must never overlap the periodic train
+        let mut sync_windows = Vec::new();
+        let mut ext_windows = Vec::new();
+        for _ in 0..200 {
+            let cmd = adv.update(channel.radio(Device::A), &mut rng);
+            let window = cmd.window.unwrap();
+            match channel.receive(Device::B).unwrap() {
+                MockPacket::Secondary { access_address, .. }
+                    if access_address == adv.access_address =>
+                {
+                    sync_windows.push(window)
+                }
+                _ => ext_windows.push(window),
+            }
+        }
+
+        assert!(ext_windows.len() > 20);
+        for ext in &ext_windows {
+            for sync in &sync_windows {
+                assert!(ext.end() <= sync.start || sync.end() <= ext.start);
+            }
+        }
+    }
+}
@@ -9,8 +9,11 @@
 //!   implementing this trait is needed to use the Rubble stack.
 //! * The [`Producer`] and [`Consumer`] traits, which define the queue functionality used after
 //!   splitting a [`PacketQueue`].
-//! * The [`SimpleQueue`], [`SimpleProducer`] and [`SimpleConsumer`] types, a minimal implementation
-//!   of the queue interface defined by [`PacketQueue`], [`Producer`] and [`Consumer`].
+//! * The [`ArrayQueue`], [`ArrayProducer`] and [`ArrayConsumer`] types, an implementation of the
+//!   queue interface defined by [`PacketQueue`], [`Producer`] and [`Consumer`] with a fixed number
+//!   of packet slots, and a separate lane for LL Control PDUs.
+//! * The [`SimpleQueue`], [`SimpleProducer`] and [`SimpleConsumer`] types, a minimal instantiation
+//!   of [`ArrayQueue`] that holds a single packet.
 
 use crate::link::data::{self, Llid};
 use crate::link::{MIN_DATA_PAYLOAD_BUF, MIN_DATA_PDU_BUF};
@@ -53,6 +56,16 @@ pub trait Producer {
     /// passed.
     fn free_space(&self) -> u8;
 
+    /// Returns the number of packets with a payload of `MIN_DATA_PAYLOAD_BUF` Bytes that can be
+    /// successfully enqueued in the current state.
+    ///
+    /// This is used to ensure that all fragments of an L2CAP message fit into the queue before the
+    /// first one is enqueued. The default implementation conservatively assumes that at most one
+    /// packet fits.
+    fn free_packets(&self) -> usize {
+        usize::from(usize::from(self.free_space()) >= MIN_DATA_PAYLOAD_BUF)
+    }
+
     /// Enqueues a PDU with known size using a closure.
     ///
     /// *This is an object-safe method complemented by its generic counterpart `produce_with`. Only
@@ -62,6 +75,10 @@ pub trait Producer {
     /// not. If sufficient space is available, a `ByteWriter` with access to that space is
     /// constructed and `f` is called. If `f` returns a successful result, the data is committed to
     /// the queue. If not, the queue is left unchanged.
+    ///
+    /// Queues that store PDUs depending on their LLID may only find out after calling `f` that
+    /// there is no room for the PDU. They also return `Error::Eof` then, leaving the queue
+    /// unchanged.
     fn produce_dyn(
         &mut self,
         payload_bytes: u8,
@@ -89,7 +106,7 @@ pub trait Producer {
 
         let mut f = Some(f);
         let mut r = None;
-        self.produce_dyn(payload_bytes, &mut |bytes| {
+        let result = self.produce_dyn(payload_bytes, &mut |bytes| {
             let f = f.take().unwrap();
             let result = f(bytes);
             if let Ok(llid) = result {
@@ -99,10 +116,16 @@ pub trait Producer {
                 r = Some(result.map(|_| ()));
                 Err(Error::InvalidValue)
             }
-        })
-        .ok();
-
-        r.unwrap()
+        });
+
+        match (r, result) {
+            // `f` succeeded, but the queue rejected the PDU (eg. because its lane is full)
+            (Some(Ok(())), Err(e)) => Err(e.into()),
+            (Some(r), _) => r,
+            // The closure was never invoked, so `produce_dyn` bailed out early (eg. because the
+            // queue is full). Forward its error.
+            (None, result) => Err(result.unwrap_err().into()),
+        }
     }
 }
 
@@ -206,39 +229,121 @@ impl<T> Consume<T> {
 ///
 /// This type is compatible with thumbv6 cores, which lack atomic operations that might be needed
 /// for other queue implementations.
-pub struct SimpleQueue {
-    // FIXME this uses 2 PDUs worth of space, but should only use 1
-    inner: spsc::Queue<[u8; MIN_DATA_PDU_BUF], 2>,
+// FIXME this uses 2 PDUs worth of space, but should only use 1
+pub type SimpleQueue = ArrayQueue<2>;
+
+/// Producer (writer) half returned by `SimpleQueue::split`.
+pub type SimpleProducer<'a> = ArrayProducer<'a, 2>;
+
+/// Consumer (reader) half returned by `SimpleQueue::split`.
+pub type SimpleConsumer<'a> = ArrayConsumer<'a, 2>;
+
+/// A packet queue storing up to `N - 1` packets in a fixed-size array.
+///
+/// Every slot in the queue can hold a data channel PDU of up to [`MIN_DATA_PDU_BUF`] bytes, so the
+/// queue occupies `(N + C) * MIN_DATA_PDU_BUF` bytes of memory (plus 4 indices). `N` must be at
+/// least 2.
+///
+/// # Control PDU lane
+///
+/// LL Control PDUs are stored in a separate lane of `C - 1` slots, which is drained before any
+/// queued data PDU is sent. This way, responses to the peer's LLCP procedures aren't delayed by a
+/// queue full of application data (which may take many connection events to drain). Control PDUs
+/// are still sent in the order they were enqueued, and so are data PDUs.
+///
+/// `C` must be at least 2 and defaults to 2 (a single slot), which is sufficient since the
+/// Link-Layer only runs one LLCP procedure at a time.
+///
+/// Both lanes fill up independently. [`free_space`](Producer::free_space) and
+/// [`free_packets`](Producer::free_packets) report the space in the data lane, so enqueueing an LL
+/// Control PDU fails with `Error::Eof` while the control lane is full, even if they report free
+/// space. A full control lane never blocks data PDUs.
+///
+/// Like [`SimpleQueue`] (which is an `ArrayQueue<2>`), this type is compatible with thumbv6 cores:
+/// The producer and consumer only ever need atomic loads and stores to synchronize with each
+/// other.
+///
+/// # Use across interrupt boundaries
+///
+/// The queue is split into its halves via `&'a mut ArrayQueue<N>`. To hand the halves to code
+/// running in different execution contexts (eg. the radio interrupt and the application's idle
+/// loop), the queue should be placed in a `static` and split via a `&'static mut` reference
+/// (`cortex_m::singleton!` or a resource of your RTIC application can provide one). The resulting
+/// [`ArrayProducer`] and [`ArrayConsumer`] are `Send` and can be moved into their respective
+/// contexts.
+pub struct ArrayQueue<const N: usize, const C: usize = 2> {
+    inner: spsc::Queue<[u8; MIN_DATA_PDU_BUF], N>,
+    control: spsc::Queue<[u8; MIN_DATA_PDU_BUF], C>,
 }
 
-impl SimpleQueue {
+impl<const N: usize, const C: usize> Default for ArrayQueue<N, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const C: usize> ArrayQueue<N, C> {
     /// Creates a new, empty queue.
     pub const fn new() -> Self {
         Self {
             inner: spsc::Queue::new(),
+            control: spsc::Queue::new(),
         }
     }
+
+    /// Returns the maximum number of packets this queue can hold at once.
+    ///
+    /// This does not include the slots reserved for LL Control PDUs.
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Returns the number of slots reserved for LL Control PDUs.
+    pub const fn control_capacity(&self) -> usize {
+        C - 1
+    }
 }
 
-impl<'a> PacketQueue for &'a mut SimpleQueue {
-    type Producer = SimpleProducer<'a>;
+impl<'a, const N: usize, const C: usize> PacketQueue for &'a mut ArrayQueue<N, C> {
+    type Producer = ArrayProducer<'a, N, C>;
 
-    type Consumer = SimpleConsumer<'a>;
+    type Consumer = ArrayConsumer<'a, N, C>;
 
     fn split(self) -> (Self::Producer, Self::Consumer) {
+        assert!(
+            N >= 2,
+            "`ArrayQueue` needs to have room for at least 1 packet"
+        );
+        assert!(
+            C >= 2,
+            "`ArrayQueue` needs to have room for at least 1 control packet"
+        );
+
         let (p, c) = self.inner.split();
-        (SimpleProducer { inner: p }, SimpleConsumer { inner: c })
+        let (control_p, control_c) = self.control.split();
+        (
+            ArrayProducer {
+                inner: p,
+                control: control_p,
+            },
+            ArrayConsumer {
+                inner: c,
+                control: control_c,
+            },
+        )
     }
 }
 
-/// Producer (writer) half returned by `SimpleQueue::split`.
-pub struct SimpleProducer<'a> {
-    inner: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], 2>,
+/// Producer (writer) half returned by `ArrayQueue::split`.
+pub struct ArrayProducer<'a, const N: usize, const C: usize = 2> {
+    inner: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], N>,
+    control: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], C>,
 }
 
-impl<'a> Producer for SimpleProducer<'a> {
+impl<'a, const N: usize, const C: usize> Producer for ArrayProducer<'a, N, C> {
     fn free_space(&self) -> u8 {
-        // We can only have space for either 0 or 1 packets with min. payload size
+        // Every free slot fits a packet with min. payload size, so we can only report either 0 or
+        // `MIN_DATA_PAYLOAD_BUF` bytes of space
         if self.inner.ready() {
             MIN_DATA_PAYLOAD_BUF as u8
         } else {
@@ -246,6 +351,10 @@ impl<'a> Producer for SimpleProducer<'a> {
         }
     }
 
+    fn free_packets(&self) -> usize {
+        self.inner.capacity() - self.inner.len()
+    }
+
     fn produce_dyn(
         &mut self,
         payload_bytes: u8,
@@ -253,7 +362,9 @@ impl<'a> Producer for SimpleProducer<'a> {
     ) -> Result<(), Error> {
         assert!(usize::from(payload_bytes) <= MIN_DATA_PAYLOAD_BUF);
 
-        if !self.inner.ready() {
+        // The lane is picked by the LLID returned by `f`, so the PDU is only enqueued afterwards. If
+        // neither lane has room, `f` doesn't have to be called.
+        if !self.inner.ready() && !self.control.ready() {
             return Err(Error::Eof);
         }
 
@@ -267,35 +378,52 @@ impl<'a> Producer for SimpleProducer<'a> {
         header.set_payload_length(used as u8);
         header.to_bytes(&mut ByteWriter::new(&mut buf[..2]))?;
 
-        self.inner.enqueue(buf).map_err(|_| ()).unwrap();
-        Ok(())
+        let result = if llid == Llid::Control {
+            self.control.enqueue(buf)
+        } else {
+            self.inner.enqueue(buf)
+        };
+        result.map_err(|_| Error::Eof)
     }
 }
 
-/// Consumer (reader) half returned by `SimpleQueue::split`.
-pub struct SimpleConsumer<'a> {
-    inner: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], 2>,
+/// Consumer (reader) half returned by `ArrayQueue::split`.
+pub struct ArrayConsumer<'a, const N: usize, const C: usize = 2> {
+    inner: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], N>,
+    control: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], C>,
 }
 
-impl<'a> Consumer for SimpleConsumer<'a> {
+impl<'a, const N: usize, const C: usize> Consumer for ArrayConsumer<'a, N, C> {
     fn has_data(&self) -> bool {
-        self.inner.ready()
+        self.control.ready() || self.inner.ready()
     }
 
     fn consume_raw_with<R>(
         &mut self,
         f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
     ) -> Result<R, Error> {
-        if let Some(packet) = self.inner.peek() {
+        // Queued control PDUs go first
+        let control = self.control.ready();
+        let packet = if control {
+            self.control.peek()
+        } else {
+            self.inner.peek()
+        };
+
+        if let Some(packet) = packet {
             let mut bytes = ByteReader::new(packet);
-            let raw_header: [u8; 2] = bytes.read_array().unwrap();
-            let header = data::Header::parse(&raw_header);
+            let header = data::Header::from_bytes(&mut bytes)?;
             let pl_len = usize::from(header.payload_length());
             let raw_payload = bytes.read_slice(pl_len)?;
 
             let res = f(header, raw_payload);
             if res.should_consume {
-                self.inner.dequeue().unwrap(); // can't fail
+                // can't fail
+                if control {
+                    self.control.dequeue().unwrap();
+                } else {
+                    self.inner.dequeue().unwrap();
+                }
             }
             res.result
         } else {
@@ -442,10 +570,149 @@ pub fn run_tests(queue: impl PacketQueue) {
     // Queue should be emptied out
     assert_empty(&mut c);
 
-    // FIXME: This test could do a lot more
+    // Fill the queue up completely and drain it again, several times. This makes the queue's
+    // internal indices wrap around at least once.
+    for round in 0..3 {
+        let mut count = 0u8;
+        while p.free_space() > 0 {
+            p.produce_with(1, |writer| -> Result<_, Error> {
+                writer.write_u8(count)?;
+                Ok(Llid::DataStart)
+            })
+            .expect("enqueuing packet failed despite free space");
+
+            count += 1;
+            assert!(count < 255, "queue is not bounded");
+        }
+
+        assert!(
+            count > 0,
+            "queue reported no free space in round {}, but it's empty",
+            round
+        );
+
+        // The callback may still be invoked by queues that pick a lane by LLID
+        let err = p
+            .produce_with(1, |writer| -> Result<_, Error> {
+                writer.write_u8(0xFF)?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::Eof,
+            "full queue's `produce_with` didn't return expected `Error::Eof`"
+        );
+
+        // Packets must come out in the order they were enqueued
+        for expected in 0..count {
+            c.consume_raw_with(|header, data| -> Consume<()> {
+                assert_eq!(header.llid(), Llid::DataStart);
+                assert_eq!(
+                    data,
+                    &[expected][..],
+                    "packets were dequeued in the wrong order"
+                );
+                Consume::always(Ok(()))
+            })
+            .expect("consume_raw_with failed on a non-empty queue");
+        }
+
+        assert_empty(&mut c);
+    }
 }
 
 #[test]
 fn simple_queue() {
     run_tests(&mut SimpleQueue::new());
 }
+
+#[test]
+fn array_queue() {
+    run_tests(&mut ArrayQueue::<2>::new());
+    run_tests(&mut ArrayQueue::<3>::new());
+    run_tests(&mut ArrayQueue::<8>::new());
+}
+
+#[test]
+fn array_queue_capacity() {
+    let mut queue = ArrayQueue::<5>::new();
+    assert_eq!(queue.capacity(), 4);
+
+    let (mut p, mut c) = queue.split();
+    for _ in 0..4 {
+        p.produce_with(0, |_| -> Result<_, Error> { Ok(Llid::DataCont) })
+            .unwrap();
+    }
+    assert_eq!(p.free_space(), 0);
+
+    // Dequeuing a single packet makes room for exactly one more
+    c.consume_raw_with(|_, _| -> Consume<()> { Consume::always(Ok(())) })
+        .unwrap();
+    assert_eq!(p.free_space(), MIN_DATA_PAYLOAD_BUF as u8);
+    p.produce_with(0, |_| -> Result<_, Error> { Ok(Llid::DataCont) })
+        .unwrap();
+    assert_eq!(p.free_space(), 0);
+}
+
+#[test]
+fn control_pdus_preempt_data() {
+    let mut queue = ArrayQueue::<4>::new();
+    assert_eq!(queue.control_capacity(), 1);
+    let (mut p, mut c) = queue.split();
+    let mut produce = |llid, byte| {
+        p.produce_with(1, |w| -> Result<_, Error> {
+            w.write_u8(byte)?;
+            Ok(llid)
+        })
+    };
+    produce(Llid::DataStart, 1).unwrap();
+    produce(Llid::DataCont, 2).unwrap();
+    produce(Llid::Control, 3).unwrap();
+
+    // The control lane is full
+    assert_eq!(produce(Llid::Control, 4), Err(Error::Eof));
+
+    let mut consume = || {
+        c.consume_raw_with(|header, pl| Consume::always(Ok((header.llid(), pl[0]))))
+            .unwrap()
+    };
+    assert_eq!(consume(), (Llid::Control, 3));
+    assert_eq!(consume(), (Llid::DataStart, 1));
+    assert_eq!(consume(), (Llid::DataCont, 2));
+    assert!(!c.has_data());
+}
+
+#[test]
+fn lanes_fill_independently() {
+    let mut queue = ArrayQueue::<3>::new();
+    let (mut p, mut c) = queue.split();
+    let produce = |p: &mut ArrayProducer<'_, 3>, llid| {
+        p.produce_with(0, |_| -> Result<_, Error> { Ok(llid) })
+    };
+
+    // A full control lane doesn't block data
+    produce(&mut p, Llid::Control).unwrap();
+    assert_eq!(produce(&mut p, Llid::Control), Err(Error::Eof));
+    assert_eq!(p.free_packets(), 2);
+    produce(&mut p, Llid::DataStart).unwrap();
+    produce(&mut p, Llid::DataStart).unwrap();
+    assert_eq!(p.free_space(), 0);
+    assert_eq!(produce(&mut p, Llid::DataStart), Err(Error::Eof));
+
+    // A full data lane doesn't block control PDUs
+    c.consume_raw_with(|header, _| {
+        assert_eq!(header.llid(), Llid::Control);
+        Consume::always(Ok(()))
+    })
+    .unwrap();
+    produce(&mut p, Llid::Control).unwrap();
+}
+
+#[test]
+fn array_queue_halves_are_send() {
+    fn assert_send<T: Send>() {}
+
+    assert_send::<ArrayProducer<'static, 4>>();
+    assert_send::<ArrayConsumer<'static, 4>>();
+}
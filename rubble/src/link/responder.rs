@@ -1,7 +1,9 @@
-use crate::l2cap::{L2CAPState, L2CAPStateTx};
+use crate::l2cap::{ChannelMapper, L2CAPState, L2CAPStateTx};
 use crate::link::data::{Llid, Pdu};
-use crate::link::llcp::ControlPdu;
+use crate::link::event::{LinkEvent, LinkEventHandler};
+use crate::link::llcp::{ConnectionParamRequest, ControlPdu};
 use crate::link::queue::{Consume, Consumer, Producer};
+use crate::security::LinkSecurity;
 use crate::{bytes::ToBytes, config::*, utils::HexSlice, Error};
 
 /// Data channel packet processor.
@@ -17,6 +19,7 @@ pub struct Responder<C: Config> {
     tx: ConfProducer<C>,
     rx: Option<ConfConsumer<C>>,
     l2cap: L2CAPState<C::ChannelMapper>,
+    event_handler: Option<C::LinkEventHandler>,
 }
 
 impl<C: Config> Responder<C> {
@@ -30,22 +33,51 @@ impl<C: Config> Responder<C> {
             tx,
             rx: Some(rx),
             l2cap,
+            event_handler: None,
         }
     }
 
+    /// Sets the handler to report [`LinkEvent::MtuChanged`] to.
+    ///
+    /// This is usually a second instance of the handler passed to
+    /// [`LinkLayer::set_event_handler`] (eg. another reference to a [`Shared`] handler), so that
+    /// the application receives all events in one place.
+    ///
+    /// [`LinkLayer::set_event_handler`]: crate::link::LinkLayer::set_event_handler
+    /// [`Shared`]: crate::sync::Shared
+    pub fn set_event_handler(&mut self, handler: C::LinkEventHandler) {
+        self.event_handler = Some(handler);
+    }
+
     /// Returns `true` when this responder has work to do.
     ///
     /// If this returns `true`, `process` may be called to process incoming packets and send
     /// outgoing ones.
     pub fn has_work(&mut self) -> bool {
         self.with_rx(|rx, _| rx.has_data())
+            || self
+                .l2cap
+                .channel_mapper()
+                .att()
+                .into_protocol()
+                .service_change_pending()
     }
 
     /// Processes a single incoming packet in the packet queue.
     ///
+    /// A pending *Service Changed* indication is sent first, if there is space for it in the TX
+    /// queue. If the packet changes the `ATT_MTU`, [`LinkEvent::MtuChanged`] is reported.
+    ///
     /// Returns `Error::Eof` if there are no incoming packets in the RX queue.
     pub fn process_one(&mut self) -> Result<(), Error> {
-        self.with_rx(|rx, this| {
+        if let Some(att) = self.l2cap().att() {
+            if att.indicate_service_change()? {
+                return Ok(());
+            }
+        }
+
+        let mtu = self.att_mtu();
+        let result = self.with_rx(|rx, this| {
             rx.consume_pdu_with(|_, pdu| match pdu {
                 Pdu::Control { data } => {
                     // Also see:
@@ -71,15 +103,67 @@ impl<C: Config> Responder<C> {
                     }))
                 }
                 Pdu::DataStart { message } => {
-                    info!("L2start: {:?}", HexSlice(message));
+                    info!("L2start: {}", HexSlice(message));
                     this.l2cap().process_start(message)
                 }
                 Pdu::DataCont { message } => {
-                    info!("L2cont {:?}", HexSlice(message));
+                    info!("L2cont {}", HexSlice(message));
                     this.l2cap().process_cont(message)
                 }
             })
-        })
+        });
+
+        let new_mtu = self.att_mtu();
+        if new_mtu != mtu {
+            if let Some(handler) = &mut self.event_handler {
+                handler.handle_event(LinkEvent::MtuChanged { mtu: new_mtu });
+            }
+        }
+        result
+    }
+
+    /// Returns the `ATT_MTU` currently used by the attribute server.
+    fn att_mtu(&mut self) -> u16 {
+        self.l2cap.channel_mapper().att().into_protocol().mtu()
+    }
+
+    /// Asks the master to update the connection parameters via L2CAP.
+    ///
+    /// The request is always sent over the L2CAP LE Signaling Channel. If the master accepts it,
+    /// it will initiate a regular connection update. Whether the request was accepted can be
+    /// queried via [`SignalingState::conn_param_update_accepted`]. To use the Connection
+    /// Parameters Request Procedure when both devices support it, call
+    /// [`LinkLayer::request_conn_param_update`] instead.
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the TX queue to send the request.
+    ///
+    /// [`SignalingState::conn_param_update_accepted`]: crate::l2cap::SignalingState::conn_param_update_accepted
+    /// [`LinkLayer::request_conn_param_update`]: crate::link::LinkLayer::request_conn_param_update
+    pub fn request_conn_param_update(
+        &mut self,
+        params: &ConnectionParamRequest,
+    ) -> Result<(), Error> {
+        self.l2cap().request_conn_param_update(params)
+    }
+
+    /// Resets the per-connection state of the L2CAP layer and the protocols above it.
+    ///
+    /// This must be called when the Link-Layer reports [`LinkEvent::Disconnected`] (see
+    /// [`L2CAPState::connection_closed`]).
+    ///
+    /// [`LinkEvent::Disconnected`]: crate::link::event::LinkEvent::Disconnected
+    pub fn connection_closed(&mut self) {
+        self.l2cap.connection_closed();
+    }
+
+    /// Updates the security of the connection after the Link-Layer started encrypting it.
+    ///
+    /// This must be called when the Link-Layer reports [`LinkEvent::EncryptionEnabled`] (see
+    /// [`L2CAPState::encryption_enabled`]).
+    ///
+    /// [`LinkEvent::EncryptionEnabled`]: crate::link::event::LinkEvent::EncryptionEnabled
+    pub fn encryption_enabled(&mut self, security: LinkSecurity) {
+        self.l2cap.encryption_enabled(security);
     }
 
     /// Obtains access to the L2CAP instance.
@@ -0,0 +1,248 @@
+//! Resolvable Private Addresses (RPAs).
+//!
+//! A Resolvable Private Address is a random device address that changes periodically, preventing
+//! observers from tracking a device. Only peers that know the device's *Identity Resolving Key*
+//! (IRK), which is distributed during pairing, can determine which device an RPA belongs to.
+//!
+//! An RPA consists of a 24-bit random part `prand` (whose 2 most significant bits are `0b01`) in
+//! the upper half of the address, and a 24-bit hash of `prand` computed with the IRK in the lower
+//! half.
+
+use crate::aes::AesProvider;
+use crate::link::{AddressKind, DeviceAddress};
+use crate::security::{toolbox, Drbg};
+use crate::time::{Duration, Instant};
+use core::fmt;
+use rand_core::{CryptoRng, RngCore};
+
+/// The rotation interval recommended by the specification (15 minutes).
+pub const DEFAULT_ROTATION_INTERVAL: Duration = Duration::secs(15 * 60);
+
+/// Maximum number of peer IRKs the Link-Layer can resolve addresses against.
+pub const RESOLVING_LIST_SIZE: usize = 4;
+
+/// An Identity Resolving Key (IRK) used to generate and resolve private addresses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct IdentityResolvingKey(pub u128);
+
+impl fmt::Debug for IdentityResolvingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't leak the key in logs.
+        f.write_str("IdentityResolvingKey(..)")
+    }
+}
+
+/// Returns whether `addr` is a Resolvable Private Address.
+pub fn is_resolvable(addr: &DeviceAddress) -> bool {
+    addr.is_random() && addr.raw()[5] & 0b1100_0000 == 0b0100_0000
+}
+
+/// Generates the Resolvable Private Address belonging to `irk` and `prand`.
+///
+/// Only the lower 22 bits of `prand` are used, the 2 most significant bits of the 24-bit random
+/// part are fixed to `0b01`. The random bits must not be all 0 or all 1, which is not checked by
+/// this function.
+pub fn generate(
+    aes: &mut impl AesProvider,
+    irk: &IdentityResolvingKey,
+    prand: u32,
+) -> DeviceAddress {
+    let prand = prand & 0x3F_FFFF | 0x40_0000;
+    let hash = toolbox::ah(aes, irk.0, prand);
+
+    let mut bytes = [0; 6];
+    bytes[..3].copy_from_slice(&hash.to_le_bytes()[..3]);
+    bytes[3..].copy_from_slice(&prand.to_le_bytes()[..3]);
+    DeviceAddress::new(bytes, AddressKind::Random)
+}
+
+/// Checks whether `addr` is a Resolvable Private Address generated from `irk`.
+pub fn resolve(
+    aes: &mut impl AesProvider,
+    irk: &IdentityResolvingKey,
+    addr: &DeviceAddress,
+) -> bool {
+    if !is_resolvable(addr) {
+        return false;
+    }
+
+    let raw = addr.raw();
+    let hash = u32::from_le_bytes([raw[0], raw[1], raw[2], 0]);
+    let prand = u32::from_le_bytes([raw[3], raw[4], raw[5], 0]);
+    toolbox::ah(aes, irk.0, prand) == hash
+}
+
+/// Resolves `addr` against a list of IRKs.
+///
+/// Returns the index of the first key in `irks` that `addr` was generated from, or `None` if no
+/// key matches (or `addr` is not a Resolvable Private Address).
+pub fn resolve_any(
+    aes: &mut impl AesProvider,
+    irks: &[IdentityResolvingKey],
+    addr: &DeviceAddress,
+) -> Option<usize> {
+    if !is_resolvable(addr) {
+        return None;
+    }
+
+    irks.iter().position(|irk| resolve(aes, irk, addr))
+}
+
+/// Generates a new Resolvable Private Address in regular intervals.
+pub struct RpaGenerator {
+    irk: IdentityResolvingKey,
+    interval: Duration,
+    current: DeviceAddress,
+    next_rotation: Instant,
+    rng: Drbg,
+}
+
+impl RpaGenerator {
+    /// Creates a new generator and generates the first address.
+    ///
+    /// # Parameters
+    ///
+    /// * **`aes`**: AES provider used to compute the address hash.
+    /// * **`irk`**: The local device's Identity Resolving Key.
+    /// * **`interval`**: The time after which a new address is generated.
+    /// * **`now`**: The current time.
+    /// * **`rng`**: A cryptographically secure random number generator, used to seed the
+    ///   generator for the random part of the addresses.
+    pub fn new<R: RngCore + CryptoRng>(
+        aes: &mut impl AesProvider,
+        irk: IdentityResolvingKey,
+        interval: Duration,
+        now: Instant,
+        rng: &mut R,
+    ) -> Self {
+        let mut rng = Drbg::new(rng);
+        let current = generate(aes, &irk, Self::prand(&mut rng));
+        Self {
+            irk,
+            interval,
+            current,
+            next_rotation: now + interval,
+            rng,
+        }
+    }
+
+    /// Returns the current Resolvable Private Address.
+    pub fn address(&self) -> DeviceAddress {
+        self.current
+    }
+
+    /// Returns the Identity Resolving Key the addresses are generated from.
+    pub fn irk(&self) -> &IdentityResolvingKey {
+        &self.irk
+    }
+
+    /// Generates a new address if the rotation interval has elapsed.
+    ///
+    /// Returns `true` if the address has changed.
+    pub fn rotate_if_due(&mut self, aes: &mut impl AesProvider, now: Instant) -> bool {
+        if now < self.next_rotation {
+            return false;
+        }
+
+        self.current = generate(aes, &self.irk, Self::prand(&mut self.rng));
+        self.next_rotation = now + self.interval;
+        debug!("new RPA: {}", self.current);
+        true
+    }
+
+    /// Draws 22 random bits that are neither all 0 nor all 1.
+    fn prand(rng: &mut Drbg) -> u32 {
+        loop {
+            let bits = rng.next() as u32 & 0x3F_FFFF;
+            if bits != 0 && bits != 0x3F_FFFF {
+                return bits;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RpaGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpaGenerator")
+            .field("interval", &self.interval)
+            .field("current", &self.current)
+            .field("next_rotation", &self.next_rotation)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+
+    /// RNG yielding a fixed sequence of Bytes. Only for testing.
+    struct CountingRng(u8);
+
+    impl RngCore for CountingRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for b in dest {
+                *b = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for CountingRng {}
+
+    const IRK: IdentityResolvingKey = IdentityResolvingKey(0xec0234a357c8ad05341010a60a397d9b);
+
+    /// Uses the `ah` test vector from the spec (Vol 3, Part H, Appendix D.7).
+    #[test]
+    fn generate_spec_address() {
+        let addr = generate(&mut SoftAesProvider::new(), &IRK, 0x708194);
+        assert_eq!(addr.raw(), &[0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70]);
+        assert!(addr.is_random());
+        assert!(is_resolvable(&addr));
+    }
+
+    #[test]
+    fn resolve_generated() {
+        let aes = &mut SoftAesProvider::new();
+        let other = IdentityResolvingKey(0x0123456789abcdef0123456789abcdef);
+        let addr = generate(aes, &IRK, 0x12345);
+
+        assert!(resolve(aes, &IRK, &addr));
+        assert!(!resolve(aes, &other, &addr));
+        assert_eq!(resolve_any(aes, &[other, IRK], &addr), Some(1));
+        assert_eq!(resolve_any(aes, &[other], &addr), None);
+
+        let public = DeviceAddress::new(*addr.raw(), AddressKind::Public);
+        assert_eq!(resolve_any(aes, &[IRK], &public), None);
+    }
+
+    #[test]
+    fn rotation() {
+        let aes = &mut SoftAesProvider::new();
+        let start = Instant::from_ticks(0);
+        let interval = Duration::secs(60);
+        let mut gen = RpaGenerator::new(aes, IRK, interval, start, &mut CountingRng(0));
+        let first = gen.address();
+        assert!(resolve(aes, &IRK, &first));
+
+        assert!(!gen.rotate_if_due(aes, start + Duration::secs(59)));
+        assert_eq!(gen.address(), first);
+
+        assert!(gen.rotate_if_due(aes, start + interval));
+        assert_ne!(gen.address(), first);
+        assert!(resolve(aes, &IRK, &gen.address()));
+    }
+}
@@ -0,0 +1,476 @@
+//! Scanning (observer role).
+//!
+//! A scanning Link-Layer listens on the primary advertising channels and reports every received
+//! advertising PDU to an [`AdvReportHandler`] configured via [`Config::AdvReportHandler`].
+//!
+//! Scanning is performed with a duty cycle: At the start of every *scan interval*, the Link-Layer
+//! switches to the next advertising channel in [`ScanParams::channels`] and listens for the
+//! duration of the *scan window*. For the rest of the interval, the radio is turned off. If the
+//! window is as long as the interval, the Link-Layer scans continuously.
+//!
+//! Advertisers usually broadcast the same data many times per second. To reduce the number of
+//! reports, [`ScanParams::filter_duplicates`] enables a filter that reports every advertisement
+//! only once per configurable time window, unless its data changes.
+//!
+//! [`Config::AdvReportHandler`]: crate::config::Config::AdvReportHandler
+
+use super::ad_structure::{AdStructure, AdStructureIter};
+use super::advertising::{AdvChannels, Pdu, PduType, MAX_PAYLOAD_SIZE};
+use super::{AddressKind, DeviceAddress};
+use crate::time::{Duration, Instant, InstantExt};
+use crate::Error;
+
+/// Number of advertisers the duplicate filter can remember.
+///
+/// When more advertisers are in range, the oldest entries are overwritten, so their next
+/// advertisement is reported again.
+pub const DUPLICATE_FILTER_SIZE: usize = 8;
+
+/// Parameters for scanning, passed to [`LinkLayer::start_scanning`].
+///
+/// [`LinkLayer::start_scanning`]: super::LinkLayer::start_scanning
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanParams {
+    interval: Duration,
+    window: Duration,
+    channels: AdvChannels,
+    active: bool,
+    duplicate_window: Option<Duration>,
+    filter_scan_responses: bool,
+}
+
+impl ScanParams {
+    /// Smallest allowed scan interval and window (2.5 ms).
+    pub const MIN_INTERVAL: Duration = Duration::micros(2_500);
+
+    /// Largest allowed scan interval and window (10.24 s).
+    pub const MAX_INTERVAL: Duration = Duration::micros(10_240_000);
+
+    /// Creates scan parameters that listen for `window` at the start of every `interval`.
+    ///
+    /// Both values must lie between [`MIN_INTERVAL`] and [`MAX_INTERVAL`], and `window` must not
+    /// be larger than `interval`. Otherwise, `Error::InvalidValue` is returned.
+    ///
+    /// [`MIN_INTERVAL`]: Self::MIN_INTERVAL
+    /// [`MAX_INTERVAL`]: Self::MAX_INTERVAL
+    pub fn new(interval: Duration, window: Duration) -> Result<Self, Error> {
+        let range = Self::MIN_INTERVAL..=Self::MAX_INTERVAL;
+        if !range.contains(&interval) || !range.contains(&window) || window > interval {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            interval,
+            window,
+            channels: AdvChannels::all(),
+            active: false,
+            duplicate_window: None,
+            filter_scan_responses: false,
+        })
+    }
+
+    /// Creates scan parameters that listen continuously, switching channels every `interval`.
+    pub fn continuous(interval: Duration) -> Result<Self, Error> {
+        Self::new(interval, interval)
+    }
+
+    /// Returns the scan interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the scan window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Sets the primary advertising channels to scan on.
+    ///
+    /// Every scan window uses the next channel in `channels`, wrapping around after the last one.
+    /// By default, all 3 channels are used. Returns `Error::InvalidValue` if `channels` is empty.
+    pub fn channels(mut self, channels: AdvChannels) -> Result<Self, Error> {
+        if channels.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+
+        self.channels = channels;
+        Ok(self)
+    }
+
+    /// Returns the primary advertising channels to scan on.
+    pub fn scan_channels(&self) -> AdvChannels {
+        self.channels
+    }
+
+    /// Returns whether the radio is never turned off between scan windows.
+    pub fn is_continuous(&self) -> bool {
+        self.window == self.interval
+    }
+
+    /// Enables or disables active scanning.
+    ///
+    /// By default, scanning is passive and no packets are transmitted.
+    pub fn active(mut self, active: bool) -> Self {
+        self.active = active;
+        self
+    }
+
+    /// Returns whether active scanning (sending `SCAN_REQ`s) is enabled.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Enables or disables the duplicate filter.
+    ///
+    /// With `Some(window)`, an advertisement is not reported again if the same advertiser sent the
+    /// same PDU type and data less than `window` ago. Changed data is always reported. By default,
+    /// the filter is disabled and every received advertisement is reported.
+    ///
+    /// Reports that include scan response data are only filtered if
+    /// [`filter_scan_responses`](Self::filter_scan_responses) is enabled.
+    pub fn filter_duplicates(mut self, window: Option<Duration>) -> Self {
+        self.duplicate_window = window;
+        self
+    }
+
+    /// Sets whether the duplicate filter also suppresses reports carrying identical scan response
+    /// data.
+    ///
+    /// This has no effect unless the duplicate filter is enabled. Disabled by default.
+    pub fn filter_scan_responses(mut self, enabled: bool) -> Self {
+        self.filter_scan_responses = enabled;
+        self
+    }
+
+    /// Returns the time window of the duplicate filter, or `None` if it's disabled.
+    pub fn duplicate_window(&self) -> Option<Duration> {
+        self.duplicate_window
+    }
+}
+
+/// A received advertising PDU, reported to the [`AdvReportHandler`].
+#[derive(Debug, Copy, Clone)]
+pub struct AdvReport<'a> {
+    /// Address of the advertising device.
+    pub addr: DeviceAddress,
+
+    /// Whether `addr` is a public or a random address.
+    pub addr_type: AddressKind,
+
+    /// The type of the received PDU.
+    pub pdu_type: PduType,
+
+    /// The raw AD structures contained in the PDU.
+    ///
+    /// This is empty for PDUs that can't carry advertising data (`ADV_DIRECT_IND`). Use
+    /// [`AdvReport::ad_structures`] to decode the data.
+    pub data: &'a [u8],
+
+    /// The raw AD structures contained in the advertiser's `SCAN_RSP`.
+    ///
+    /// This is only `Some` when active scanning is enabled and the advertiser has answered our
+    /// scan request.
+    pub scan_response: Option<&'a [u8]>,
+
+    /// Received signal strength in dBm, if supported by the radio.
+    pub rssi: Option<i8>,
+}
+
+impl<'a> AdvReport<'a> {
+    /// Creates a report from a received PDU.
+    ///
+    /// Returns `None` if `pdu` isn't sent by an advertiser (scan and connect requests). `payload`
+    /// must be the raw payload `pdu` was parsed from.
+    pub(crate) fn new(pdu: &Pdu<'a>, payload: &'a [u8], rssi: Option<i8>) -> Option<Self> {
+        match pdu {
+            Pdu::ScanRequest { .. } | Pdu::ConnectRequest { .. } => return None,
+            _ => {}
+        }
+
+        let addr = *pdu.sender();
+        let data = if pdu.advertising_data().is_some() {
+            // AD structures follow the 6-Byte advertiser address
+            &payload[6..]
+        } else {
+            &[]
+        };
+
+        Some(Self {
+            addr,
+            addr_type: addr.kind(),
+            pdu_type: pdu.ty(),
+            data,
+            scan_response: None,
+            rssi,
+        })
+    }
+
+    /// Returns an iterator over the AD structures in `data`.
+    pub fn ad_structures(&self) -> impl Iterator<Item = AdStructure<'a>> {
+        parse_ad_structures(self.data)
+    }
+
+    /// Returns an iterator over the AD structures in `scan_response`.
+    ///
+    /// The iterator is empty if no scan response was received.
+    pub fn scan_response_structures(&self) -> impl Iterator<Item = AdStructure<'a>> {
+        parse_ad_structures(self.scan_response.unwrap_or(&[]))
+    }
+}
+
+fn parse_ad_structures(data: &[u8]) -> impl Iterator<Item = AdStructure<'_>> {
+    // The data has already been validated when parsing the PDU
+    AdStructureIter::new(data).filter_map(Result::ok)
+}
+
+/// A recently reported advertisement.
+#[derive(Copy, Clone)]
+struct FilterEntry {
+    addr: DeviceAddress,
+    hash: u32,
+    reported: Instant,
+}
+
+/// Suppresses reports of advertisements that were already reported recently.
+///
+/// Remembers the last [`DUPLICATE_FILTER_SIZE`] advertisers in a ring buffer, along with a hash of
+/// their last reported data.
+pub(crate) struct DuplicateFilter {
+    window: Option<Duration>,
+    scan_responses: bool,
+    entries: [Option<FilterEntry>; DUPLICATE_FILTER_SIZE],
+    /// Index of the entry to overwrite when a new advertiser is seen.
+    next: usize,
+}
+
+impl DuplicateFilter {
+    pub(crate) fn new(params: &ScanParams) -> Self {
+        Self {
+            window: params.duplicate_window,
+            scan_responses: params.filter_scan_responses,
+            entries: [None; DUPLICATE_FILTER_SIZE],
+            next: 0,
+        }
+    }
+
+    /// Returns whether `report`, received at `now`, should be passed to the report handler.
+    pub(crate) fn should_report(&mut self, report: &AdvReport<'_>, now: Instant) -> bool {
+        let window = match self.window {
+            Some(window) => window,
+            None => return true,
+        };
+        if report.scan_response.is_some() && !self.scan_responses {
+            return true;
+        }
+
+        let hash = Self::hash(report);
+        let existing = self
+            .entries
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.addr == report.addr);
+        match existing {
+            Some(entry) => {
+                if entry.hash == hash && now.saturating_duration_since(entry.reported) < window {
+                    return false;
+                }
+                entry.hash = hash;
+                entry.reported = now;
+            }
+            None => {
+                self.entries[self.next] = Some(FilterEntry {
+                    addr: report.addr,
+                    hash,
+                    reported: now,
+                });
+                self.next = (self.next + 1) % DUPLICATE_FILTER_SIZE;
+            }
+        }
+        true
+    }
+
+    /// Computes the 32-bit FNV-1a hash of the PDU type and data of `report`.
+    fn hash(report: &AdvReport<'_>) -> u32 {
+        let pdu_type = [
+            u8::from(report.pdu_type),
+            report.scan_response.is_some() as u8,
+        ];
+        let scan_response = report.scan_response.unwrap_or(&[]);
+        pdu_type
+            .iter()
+            .chain(report.data)
+            .chain(scan_response)
+            .fold(0x811C_9DC5, |hash, &byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            })
+    }
+}
+
+/// An advertisement whose report is deferred until the `SCAN_RSP` is received.
+pub(crate) struct PendingScan {
+    addr: DeviceAddress,
+    pdu_type: PduType,
+    rssi: Option<i8>,
+    data: [u8; MAX_PAYLOAD_SIZE - 6],
+    len: u8,
+}
+
+impl PendingScan {
+    pub(crate) fn new(report: &AdvReport<'_>) -> Self {
+        let mut data = [0; MAX_PAYLOAD_SIZE - 6];
+        data[..report.data.len()].copy_from_slice(report.data);
+        Self {
+            addr: report.addr,
+            pdu_type: report.pdu_type,
+            rssi: report.rssi,
+            data,
+            len: report.data.len() as u8,
+        }
+    }
+
+    /// Returns the address of the advertiser we've sent the scan request to.
+    pub(crate) fn addr(&self) -> &DeviceAddress {
+        &self.addr
+    }
+
+    /// Creates the report for the advertisement, merging in the scan response data (if any).
+    pub(crate) fn report<'a>(&'a self, scan_response: Option<&'a [u8]>) -> AdvReport<'a> {
+        AdvReport {
+            addr: self.addr,
+            addr_type: self.addr.kind(),
+            pdu_type: self.pdu_type,
+            data: &self.data[..usize::from(self.len)],
+            scan_response,
+            rssi: self.rssi,
+        }
+    }
+}
+
+/// State of the scan request backoff procedure (Vol 6, Part B, 4.4.3.2).
+///
+/// Every time a scan request could be sent, the backoff counter is decremented, and the request is
+/// only sent once it reaches 0. Afterwards, the counter is reloaded with a random value between 1
+/// and an upper limit, which is doubled after 2 consecutive failed requests and halved after 2
+/// consecutive successful ones.
+pub(crate) struct Backoff {
+    upper_limit: u16,
+    count: u16,
+    successes: u8,
+    failures: u8,
+    /// State of the pseudo-random number generator (xorshift32). Doesn't need to be secure.
+    seed: u32,
+}
+
+impl Backoff {
+    const MAX_UPPER_LIMIT: u16 = 256;
+
+    pub(crate) fn new(seed: u32) -> Self {
+        Self {
+            upper_limit: 1,
+            count: 1,
+            successes: 0,
+            failures: 0,
+            // xorshift gets stuck at 0
+            seed: seed | 1,
+        }
+    }
+
+    /// Decrements the backoff counter and returns whether a scan request should be sent now.
+    pub(crate) fn should_request(&mut self) -> bool {
+        self.count = self.count.saturating_sub(1);
+        self.count == 0
+    }
+
+    /// Records that a scan response was received for the last scan request.
+    pub(crate) fn success(&mut self) {
+        self.failures = 0;
+        self.successes += 1;
+        if self.successes == 2 {
+            self.successes = 0;
+            self.upper_limit = (self.upper_limit / 2).max(1);
+        }
+        self.reload();
+    }
+
+    /// Records that no scan response was received for the last scan request.
+    pub(crate) fn failure(&mut self) {
+        self.successes = 0;
+        self.failures += 1;
+        if self.failures == 2 {
+            self.failures = 0;
+            self.upper_limit = (self.upper_limit * 2).min(Self::MAX_UPPER_LIMIT);
+        }
+        self.reload();
+    }
+
+    fn reload(&mut self) {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.count = 1 + (self.seed % u32::from(self.upper_limit)) as u16;
+    }
+}
+
+/// Trait for receivers of advertising reports while scanning.
+pub trait AdvReportHandler {
+    /// Called for every advertising PDU received with a correct CRC.
+    ///
+    /// This is called from the Link-Layer's packet processing code, so it should return quickly
+    /// (eg. by copying the relevant data into a queue).
+    fn report(&mut self, report: &AdvReport<'_>);
+}
+
+/// Ignores all advertising reports.
+///
+/// Devices that never scan can use this as their [`Config::AdvReportHandler`].
+///
+/// [`Config::AdvReportHandler`]: crate::config::Config::AdvReportHandler
+impl AdvReportHandler for () {
+    fn report(&mut self, _report: &AdvReport<'_>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params() {
+        let ms = Duration::millis;
+        assert!(ScanParams::new(ms(100), ms(50)).is_ok());
+        assert!(ScanParams::continuous(ms(100)).unwrap().is_continuous());
+        assert_eq!(ScanParams::new(ms(50), ms(100)), Err(Error::InvalidValue));
+        assert_eq!(ScanParams::new(ms(100), ms(1)), Err(Error::InvalidValue));
+        assert_eq!(ScanParams::continuous(ms(20_000)), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(0x1234_5678);
+        assert!(
+            backoff.should_request(),
+            "first request must not be delayed"
+        );
+
+        // 2 failures double the upper limit, so we wait for at most 2 opportunities
+        backoff.failure();
+        backoff.failure();
+        assert_eq!(backoff.upper_limit, 2);
+        assert!((1..=2).contains(&backoff.count));
+
+        for _ in 0..20 {
+            backoff.failure();
+        }
+        assert_eq!(backoff.upper_limit, Backoff::MAX_UPPER_LIMIT);
+
+        backoff.success();
+        assert_eq!(backoff.upper_limit, Backoff::MAX_UPPER_LIMIT);
+        backoff.success();
+        assert_eq!(backoff.upper_limit, Backoff::MAX_UPPER_LIMIT / 2);
+
+        let count = backoff.count;
+        for _ in 1..count {
+            assert!(!backoff.should_request());
+        }
+        assert!(backoff.should_request());
+    }
+}
@@ -28,6 +28,7 @@ impl fmt::Debug for SeqNum {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for SeqNum {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "{=char}", if self.0 { '1' } else { '0' });
@@ -0,0 +1,287 @@
+//! Reception statistics for diagnosing link quality.
+//!
+//! All counters wrap around on overflow and are cheap enough to update from an interrupt handler.
+
+/// Packet counters maintained by a radio driver.
+///
+/// Drivers should call [`RadioStats::record_rx`] once for every packet the radio has received,
+/// regardless of the channel type.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioStats {
+    /// Total number of packets received (including those with a CRC error).
+    pub packets_received: u32,
+
+    /// Number of received packets whose CRC check failed.
+    pub crc_errors: u32,
+
+    /// Number of received packets whose length field exceeded the receive buffer.
+    ///
+    /// These packets are truncated by the radio and treated as corrupt.
+    pub oversized_packets: u32,
+
+    /// Number of responses that were not sent because they weren't ready in time for `T_IFS`.
+    pub missed_tx: u32,
+
+    /// Number of times the radio was found in an unexpected state and had to be disabled.
+    pub recoveries: u32,
+
+    /// Number of times the driver's interrupt handler was invoked without a radio event to process.
+    pub spurious_interrupts: u32,
+}
+
+impl RadioStats {
+    /// Creates a new set of statistics with all counters set to 0.
+    pub const fn new() -> Self {
+        Self {
+            packets_received: 0,
+            crc_errors: 0,
+            oversized_packets: 0,
+            missed_tx: 0,
+            recoveries: 0,
+            spurious_interrupts: 0,
+        }
+    }
+
+    /// Records the reception of a packet.
+    #[inline]
+    pub fn record_rx(&mut self, crc_ok: bool) {
+        self.packets_received = self.packets_received.wrapping_add(1);
+        if !crc_ok {
+            self.crc_errors = self.crc_errors.wrapping_add(1);
+        }
+    }
+
+    /// Records a received packet that did not fit in the receive buffer.
+    ///
+    /// The packet must also have been passed to [`RadioStats::record_rx`].
+    #[inline]
+    pub fn record_oversized(&mut self) {
+        self.oversized_packets = self.oversized_packets.wrapping_add(1);
+    }
+
+    /// Records a response that was dropped because the radio wasn't able to send it `T_IFS` after
+    /// the received packet.
+    #[inline]
+    pub fn record_missed_tx(&mut self) {
+        self.missed_tx = self.missed_tx.wrapping_add(1);
+    }
+
+    /// Records that the radio driver recovered from an unexpected radio state.
+    #[inline]
+    pub fn record_recovery(&mut self) {
+        self.recoveries = self.recoveries.wrapping_add(1);
+    }
+
+    /// Records an interrupt that the radio driver had nothing to do for.
+    #[inline]
+    pub fn record_spurious_interrupt(&mut self) {
+        self.spurious_interrupts = self.spurious_interrupts.wrapping_add(1);
+    }
+
+    /// Resets all counters to 0.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Data channel PDU counters of a connection.
+///
+/// Only PDUs with a valid CRC are classified, since the header of a corrupted PDU can not be
+/// trusted.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConnectionStats {
+    /// Number of received PDUs with a CRC error.
+    pub crc_errors: u32,
+
+    /// Number of PDUs carrying the expected sequence number (`SN`).
+    pub new_pdus: u32,
+
+    /// Number of PDUs that repeated an already acknowledged sequence number.
+    ///
+    /// This happens when the master did not receive our acknowledgement and retransmits.
+    pub retransmitted_pdus: u32,
+
+    /// Number of PDUs whose `NESN` did not acknowledge our last PDU, causing a retransmission.
+    pub nacks: u32,
+
+    /// Number of empty PDUs received.
+    pub empty_pdus: u32,
+}
+
+impl ConnectionStats {
+    /// Creates a new set of statistics with all counters set to 0.
+    pub const fn new() -> Self {
+        Self {
+            crc_errors: 0,
+            new_pdus: 0,
+            retransmitted_pdus: 0,
+            nacks: 0,
+            empty_pdus: 0,
+        }
+    }
+
+    /// Records a received PDU.
+    ///
+    /// `is_new` is whether the PDU's `SN` matched the expected one, `acknowledged` whether its
+    /// `NESN` acknowledged our last PDU.
+    #[inline]
+    pub(crate) fn record_rx(
+        &mut self,
+        crc_ok: bool,
+        is_new: bool,
+        acknowledged: bool,
+        empty: bool,
+    ) {
+        fn inc(counter: &mut u32) {
+            *counter = counter.wrapping_add(1);
+        }
+
+        if !crc_ok {
+            inc(&mut self.crc_errors);
+            return;
+        }
+
+        inc(if is_new {
+            &mut self.new_pdus
+        } else {
+            &mut self.retransmitted_pdus
+        });
+        if !acknowledged {
+            inc(&mut self.nacks);
+        }
+        if empty {
+            inc(&mut self.empty_pdus);
+        }
+    }
+
+    /// Resets all counters to 0.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Smoothing factor [`LinkQuality`] uses by default, in units of 1/256 (1/8).
+pub const DEFAULT_LINK_QUALITY_SMOOTHING: u8 = 32;
+
+/// Smoothed link quality estimate of a connection.
+///
+/// The RSSI and CRC error rate of received data channel PDUs are tracked as exponential moving
+/// averages: every PDU moves the average `avg` towards the new sample `x` by `α * (x - avg)`. The
+/// smoothing factor `α` is configured in units of 1/256 with
+/// [`LinkLayer::set_link_quality_smoothing`]. Larger factors follow changes more quickly, while
+/// smaller ones suppress more noise.
+///
+/// [`LinkLayer::set_link_quality_smoothing`]: super::LinkLayer::set_link_quality_smoothing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkQuality {
+    smoothing: u8,
+
+    /// Average RSSI in 1/256 dBm, or `None` if no RSSI was measured yet.
+    rssi: Option<i32>,
+
+    /// Average fraction of PDUs with a CRC error, in 1/65536.
+    crc_errors: u32,
+}
+
+impl LinkQuality {
+    /// Creates an estimate without any samples that uses a smoothing factor of `smoothing / 256`.
+    pub(crate) const fn new(smoothing: u8) -> Self {
+        Self {
+            smoothing,
+            rssi: None,
+            crc_errors: 0,
+        }
+    }
+
+    /// Records a received PDU.
+    ///
+    /// The first RSSI sample initializes the average, since there's nothing to smooth yet.
+    pub(crate) fn record_rx(&mut self, crc_ok: bool, rssi: Option<i8>) {
+        let alpha = i32::from(self.smoothing);
+        if let Some(sample) = rssi {
+            let sample = i32::from(sample) << 8;
+            self.rssi = Some(match self.rssi {
+                Some(avg) => avg + (sample - avg) * alpha / 256,
+                None => sample,
+            });
+        }
+
+        let alpha = u32::from(self.smoothing);
+        if crc_ok {
+            self.crc_errors -= self.crc_errors * alpha / 256;
+        } else {
+            self.crc_errors += (0x1_0000 - self.crc_errors) * alpha / 256;
+        }
+    }
+
+    /// Returns the average RSSI in dBm, or `None` if the radio didn't report any.
+    pub fn rssi(&self) -> Option<i8> {
+        // Round to the nearest dBm
+        self.rssi.map(|avg| ((avg + 128) >> 8) as i8)
+    }
+
+    /// Returns the average fraction of PDUs received with a CRC error, in permille.
+    pub fn crc_error_permille(&self) -> u16 {
+        ((self.crc_errors * 1000 + 0x8000) >> 16) as u16
+    }
+}
+
+impl Default for LinkQuality {
+    fn default() -> Self {
+        Self::new(DEFAULT_LINK_QUALITY_SMOOTHING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radio_stats() {
+        let mut stats = RadioStats::new();
+        stats.record_rx(true);
+        stats.record_rx(false);
+        stats.record_rx(true);
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.crc_errors, 1);
+
+        stats.crc_errors = u32::MAX;
+        stats.record_rx(false);
+        assert_eq!(stats.crc_errors, 0);
+
+        stats.reset();
+        assert_eq!(stats, RadioStats::default());
+    }
+
+    #[test]
+    fn link_quality_ema() {
+        // α = 1/4
+        let mut quality = LinkQuality::new(64);
+        assert_eq!(quality.rssi(), None);
+        assert_eq!(quality.crc_error_permille(), 0);
+
+        // The first sample is taken as is, later ones move the average by a quarter of the
+        // difference: -60, -55, -52.75, -54.0625, -56.546875
+        let mut averages = [0; 5];
+        for (avg, rssi) in averages.iter_mut().zip([-60, -40, -46, -58, -64]) {
+            quality.record_rx(true, Some(rssi));
+            *avg = quality.rssi().unwrap();
+        }
+        assert_eq!(averages, [-60, -55, -53, -54, -57]);
+
+        // PDUs without an RSSI measurement don't change it
+        quality.record_rx(true, None);
+        assert_eq!(quality.rssi(), Some(-57));
+
+        // CRC error rate: 250‰, 187.5‰, 390.625‰
+        quality.record_rx(false, None);
+        assert_eq!(quality.crc_error_permille(), 250);
+        quality.record_rx(true, None);
+        assert_eq!(quality.crc_error_permille(), 188);
+        quality.record_rx(false, None);
+        assert_eq!(quality.crc_error_permille(), 391);
+    }
+}
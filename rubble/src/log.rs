@@ -47,3 +47,26 @@ macro_rules! debug {
 macro_rules! trace {
     ($($t:tt)*) => {{ format_args!($($t)*); }};
 }
+
+// `defmt` logging is separate from the macros above, since it requires arguments to implement
+// `defmt::Format` instead of `Debug`. These must not be used in timing-critical code paths.
+
+#[cfg(feature = "defmt")]
+macro_rules! defmt_debug {
+    ($($t:tt)*) => {{ defmt::debug!($($t)*); }};
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! defmt_trace {
+    ($($t:tt)*) => {{ defmt::trace!($($t)*); }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! defmt_debug {
+    ($($t:tt)*) => {{}};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! defmt_trace {
+    ($($t:tt)*) => {{}};
+}
@@ -8,6 +8,14 @@
 //! that indices 0..=36 refer to data channels and 37..=39 refer to the advertising channels
 //! (presumably to simplify channel hopping). The Link-Layer is only interested in these channel
 //! indices, so only those are implemented here.
+//!
+//! Bluetooth 5 added the LE 2M PHY, which doubles the symbol rate, and the LE Coded PHY, which
+//! trades speed for range by adding forward error correction. Connections always start out on the
+//! LE 1M PHY and can switch to another one with the *PHY Update Procedure*. Advertising always uses
+//! the LE 1M PHY.
+
+use crate::time::Duration;
+use bitflags::bitflags;
 
 /// Returns the center frequency in MHz corresponding to an RF channel.
 fn rf_channel_freq(rf_channel: u8) -> u16 {
@@ -21,7 +29,8 @@ fn whitening_iv(channel_idx: u8) -> u8 {
 }
 
 /// One of the three advertising channels (channel indices 37, 38 or 39).
-#[derive(Copy, Clone, Debug, defmt::Format)]
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct AdvertisingChannel(u8);
 
 impl AdvertisingChannel {
@@ -90,7 +99,8 @@ impl AdvertisingChannel {
 /// One of 37 data channels on which data channel PDUs are sent between connected devices.
 ///
 /// (channel indices 0..=36)
-#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct DataChannel(u8);
 
 impl DataChannel {
@@ -140,6 +150,126 @@ impl DataChannel {
     }
 }
 
+/// A physical layer configuration (modulation and symbol rate) used for data channel packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Phy {
+    /// The LE 1M PHY, at 1 Msym/s. It is supported by all devices and used for advertising.
+    Le1M,
+
+    /// The LE 2M PHY, at 2 Msym/s. Requires the `LE_2M_PHY` feature on both devices.
+    Le2M,
+
+    /// The LE Coded PHY, at 1 Msym/s with forward error correction (also called "LE Long Range").
+    /// Requires the `LE_CODED_PHY` feature on both devices.
+    ///
+    /// `ci` selects the coding used for transmitted packets. Received packets announce their
+    /// coding in their Coding Indicator field, so either coding can be received.
+    LeCoded { ci: CodingIndicator },
+}
+
+/// The coding scheme of packets sent on the LE Coded PHY.
+///
+/// The access address is always sent with S=8 coding. The Coding Indicator that follows it
+/// determines how the rest of the packet is coded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CodingIndicator {
+    /// Each bit is sent as 8 symbols, resulting in 125 kb/s. This gives the highest range.
+    S8,
+
+    /// Each bit is sent as 2 symbols, resulting in 500 kb/s.
+    S2,
+}
+
+bitflags! {
+    /// A set of PHYs, as exchanged in the LL Control PDUs of the PHY Update Procedure.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct PhySet: u8 {
+        /// The LE 1M PHY.
+        const LE_1M = 1 << 0;
+
+        /// The LE 2M PHY.
+        const LE_2M = 1 << 1;
+
+        /// The LE Coded PHY.
+        const LE_CODED = 1 << 2;
+    }
+}
+
+impl PhySet {
+    /// Returns the PHY in this set, if it contains exactly one PHY.
+    ///
+    /// Since the coding is not part of the set, the LE Coded PHY is returned with S=8 coding.
+    pub fn single(self) -> Option<Phy> {
+        if self == PhySet::LE_1M {
+            Some(Phy::Le1M)
+        } else if self == PhySet::LE_2M {
+            Some(Phy::Le2M)
+        } else if self == PhySet::LE_CODED {
+            Some(Phy::LeCoded {
+                ci: CodingIndicator::S8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Phy {
+    /// Returns the time from the start of a packet until its access address has been received.
+    ///
+    /// Radios can usually timestamp the end of the access address, which allows determining when
+    /// the packet started.
+    pub fn access_address_end(self) -> Duration {
+        match self {
+            // 1 Byte preamble, 4 Byte access address at 8 µs per Byte
+            Phy::Le1M => Duration::micros(40),
+            // 2 Byte preamble, 4 Byte access address at 4 µs per Byte
+            Phy::Le2M => Duration::micros(24),
+            // 80 µs preamble, access address coded with S=8 (32 bits at 8 µs)
+            Phy::LeCoded { .. } => Duration::micros(80 + 256),
+        }
+    }
+}
+
+/// Returns the time needed to transmit a packet whose PDU carries `payload_len` payload Bytes on
+/// `phy`.
+///
+/// `payload_len` is the value of the PDU header's length field, so it must include the MIC of
+/// encrypted PDUs. The result covers the whole packet, including the preamble, access address, the
+/// 2-Byte PDU header and the CRC, and the Coding Indicator and TERM fields on the LE Coded PHY.
+pub fn pdu_air_time(payload_len: u8, phy: Phy) -> Duration {
+    // Header, payload and CRC
+    let pdu_bits = (2 + u32::from(payload_len) + 3) * 8;
+    let micros = match phy {
+        // 1 Byte preamble and 4 Byte access address, at 1 µs per bit
+        Phy::Le1M => 5 * 8 + pdu_bits,
+        // 2 Byte preamble and 4 Byte access address, at 0.5 µs per bit
+        Phy::Le2M => (6 * 8 + pdu_bits) / 2,
+        // 80 µs preamble, then the access address, 2-bit CI and 3-bit TERM1 coded with S=8 (8 µs
+        // per bit). The PDU and the 3-bit TERM2 use the coding announced by the CI.
+        Phy::LeCoded { ci } => {
+            let us_per_bit = match ci {
+                CodingIndicator::S8 => 8,
+                CodingIndicator::S2 => 2,
+            };
+            80 + (32 + 2 + 3) * 8 + (pdu_bits + 3) * us_per_bit
+        }
+    };
+    Duration::micros(micros)
+}
+
+impl From<Phy> for PhySet {
+    fn from(phy: Phy) -> Self {
+        match phy {
+            Phy::Le1M => PhySet::LE_1M,
+            Phy::Le2M => PhySet::LE_2M,
+            Phy::LeCoded { .. } => PhySet::LE_CODED,
+        }
+    }
+}
+
 /// Trait for raw 2.4 GHz non-BLE-specific radios.
 ///
 /// You probably won't need to implement this trait, unless you're working with hardware that has
@@ -151,3 +281,31 @@ pub trait Radio {
     /// TODO: Document all radio requirements
     fn transmit(&mut self, buf: &mut [u8], freq: u16);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn air_time() {
+        // Empty PDUs, and the largest PDUs before and after the Data Length Update Procedure (27
+        // and 251 Bytes, plus a 4-Byte MIC)
+        let air_time = |len, phy| pdu_air_time(len, phy).to_micros();
+        assert_eq!(air_time(0, Phy::Le1M), 80);
+        assert_eq!(air_time(31, Phy::Le1M), 328);
+        assert_eq!(air_time(255, Phy::Le1M), 2120);
+        assert_eq!(air_time(0, Phy::Le2M), 44);
+        assert_eq!(air_time(31, Phy::Le2M), 168);
+        assert_eq!(air_time(255, Phy::Le2M), 1064);
+
+        let s8 = Phy::LeCoded {
+            ci: CodingIndicator::S8,
+        };
+        let s2 = Phy::LeCoded {
+            ci: CodingIndicator::S2,
+        };
+        assert_eq!(air_time(31, s8), 2704);
+        assert_eq!(air_time(255, s8), 17040);
+        assert_eq!(air_time(255, s2), 4542);
+    }
+}
@@ -0,0 +1,1744 @@
+//! The LE Security Manager protocol.
+//!
+//! The Security Manager is a mandatory part of BLE and is connected to L2CAP channel `0x0006` when
+//! the Link-Layer connection is established.
+//!
+//! # BLE Security
+//!
+//! As is tradition, BLE security is a complexity nightmare. This section hopes to clear up a few
+//! things and tries to define terms used throughout the code and specficiation.
+//!
+//! ## Pairing and Bonding
+//!
+//! * **Pairing** is the process of generating and exchanging connection-specific keys in order to
+//!   accomplish an encrypted Link-Layer connection.
+//!
+//!   This is done by having the *Security Managers* of the devices talk to each other to perform
+//!   the key exchange, and then using *LL Control PDUs* to enable the negotiated encryption
+//!   parameters.
+//!
+//! * **Bonding** means permanently storing the shared keys derived by *Pairing* in order to reuse
+//!   them for later connections.
+//!
+//!   The way keys are stored is inherently platform- and application-dependent, we just have to
+//!   provide interfaces to export and import key sets.
+//!
+//! Most times, when talking about *pairing*, the *bonding* part is implied. If it were not, you
+//! would constantly have to re-pair devices when reconnecting them.
+//!
+//! ## LE Legacy Pairing vs. LE Secure Connections
+//!
+//! Bluetooth's security track record is an actual record in that it is so atrociously bad that this
+//! protocol should have never seen the light of the day. Alas, here we are.
+//!
+//! LE security is generally able to utilize *AES-128-CCM* for encryption, which isn't broken by
+//! itself (unlike the "export-grade" encryption used by earlier Bluetooth versions). However, the
+//! way the AES key is exchanged differs between *LE Legacy Pairing* and *LE Secure Connections*
+//! pairing, which hugely impacts actual security.
+//!
+//! ### LE Legacy Pairing
+//!
+//! For BLE 4.0 and 4.1, only the *LE Legacy Pairing* (as it is now known as) was available. Like
+//! every awfully designed protocol, they've rolled their own crypto and use their own key exchange
+//! procedure (with the usual catastrophic consequences). First, a shared 128-bit **T**emporary
+//! **K**ey (TK) is obtained, which is then used to generate the 128-bit **S**hort-**T**erm **K**ey
+//! (STK) that is used to initially encrypt the connection while other keys are exchanged.
+//!
+//! The STK is generated from the TK by mixing in random values from master (`Mrand`) and slave
+//! (`Srand`), which are exchanged in plain text. If a passive eavesdropper manages to obtain TK,
+//! they only need to listen for the `Mrand` and `Srand` value and can then compute the STK and
+//! decrypt the connection.
+//!
+//! There are 3 methods of determining the TK:
+//! * *"Just Works"*: TK=0
+//! * *Passkey Entry*: A 6-digit number is displayed on one device and input on the other device.
+//!   The number is directly used as the TK (after zero-padding it to 128 bits).
+//! * *Out-of-Band* (OOB): The 128-bit TK is provided by an external mechanism (eg. NFC).
+//!
+//! "Just Works" obviously is broken without any effort other than listening for the exchanged
+//! `Mrand` and `Srand` values.
+//!
+//! The Passkey Entry method only allows 1000000 different TKs (equivalent to using 20-bit keys)
+//! and does not do any key derivation. This makes it trivial to brute-force the TK by running the
+//! STK derivation up to a million times.
+//!
+//! **The only way to perform *LE Legacy Pairing* with meaningful protection against passive
+//! eavesdropping is by using a secure Out-of-Band channel for agreeing on the TK.**
+//!
+//! ### LE Secure Connections pairing
+//!
+//! Added with BLE 4.2, this finally uses established cryptography to do everything. It uses ECDH on
+//! the P-256 curve (aka "secp256r1" or "prime256v1").
+//!
+//! Using ECDH immediately protects against passive eavesdropping. MITM-protection works similarly
+//! to what *LE Legacy Pairing* attempted to do, but is actually relevant here since the base key
+//! exchange isn't broken to begin with. There are several user confirmation processes that can
+//! offer MITM-protection:
+//!
+//! * *"Just Works"*: No MITM-protection. Uses the *Numeric Comparison* protocol internally, with
+//!   automatic confirmation.
+//! * *Numeric Comparison*: Both devices display a 6-digit confirmation value and the user is
+//!   required to compare them and confirm on each device if they're equal.
+//! * *Passkey Entry*: Either a generated passkey is displayed on one device and input on the other,
+//!   or the user inputs the same passkey into both devices.
+//! * *Out-of-Band* (OOB): An Out-of-Band mechanism is used to exchange random nonces and confirm
+//!   values. The mechanism has to be secure against MITM.
+//!
+//! ## LE Privacy
+//!
+//! BLE devices are normally extremely easy to track. Since many people use BLE devices, and device
+//! addresses are device-unique, they can be very easily used to identify and track people just by
+//! recording BLE advertisements.
+//!
+//! The LE privacy feature can prevent this by changing the device address over time. Bonded devices
+//! can still *resolve* this address by using a shared **I**dentity **R**esolving **K**ey (IRK).
+//!
+//! This feature is not related to encryption or authentication of connections.
+
+pub mod rng;
+pub(crate) mod toolbox;
+
+use crate::aes::SoftAesProvider;
+use crate::ecdh::{EcdhProvider, P256Provider, PublicKey, SecretKey};
+use crate::l2cap::{Protocol, ProtocolObj, Sender};
+use crate::link::DeviceAddress;
+use crate::{bytes::*, utils::HexSlice, Error};
+use bitflags::bitflags;
+use core::fmt;
+use rand_core::{CryptoRng, RngCore};
+use zerocopy::Unaligned;
+
+/// Supported security levels.
+pub trait SecurityLevel {
+    /// The L2CAP MTU required by this security level.
+    const MTU: u8;
+
+    /// Whether *LE Secure Connections* pairing is supported.
+    const SECURE_CONNECTIONS: bool = false;
+}
+
+/// *LE Secure Connections* are not supported and will not be established.
+#[derive(Debug)]
+pub struct NoSecurity;
+impl SecurityLevel for NoSecurity {
+    /// 23 Bytes when *LE Secure Connections* are unsupported
+    const MTU: u8 = 23;
+}
+
+/// Indicates support for *LE Secure Connections*.
+#[derive(Debug)]
+pub struct SecureConnections;
+impl SecurityLevel for SecureConnections {
+    /// 65 Bytes when *LE Secure Connections* are supported
+    const MTU: u8 = 65;
+    const SECURE_CONNECTIONS: bool = true;
+}
+
+/// Security state of a connection, as established by pairing and Link-Layer encryption.
+///
+/// Levels are ordered from weakest to strongest, so requirements can be checked with `>=`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LinkSecurity {
+    /// The connection is not encrypted.
+    #[default]
+    Unencrypted,
+
+    /// The connection is encrypted with a key from unauthenticated (*"Just Works"*) pairing.
+    Encrypted,
+
+    /// The connection is encrypted with a key from pairing that provided MITM protection.
+    Authenticated,
+}
+
+/// A 128-bit key used to encrypt a Link-Layer connection.
+///
+/// This is either a Short-Term Key (STK) resulting from *LE Legacy Pairing*, or a Long-Term Key
+/// (LTK). The key is stored as a 128-bit number; its `Debug` implementation does not print it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct EncryptionKey(pub u128);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// The security policy enforced by the [`SecurityManager`] during pairing.
+///
+/// Pairing requests that can't meet the policy are rejected with a *Pairing Failed* command
+/// carrying [`PairingFailedReason::AuthenticationRequirements`].
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SecurityConfig {
+    /// Only allow *LE Secure Connections* pairing (*Secure Connections Only Mode*).
+    ///
+    /// This requires a Security Manager created with [`SecurityManager::secure_connections`],
+    /// otherwise all pairing attempts are rejected.
+    pub sc_only: bool,
+
+    /// Require MITM protection, rejecting the *"Just Works"* method.
+    ///
+    /// The authenticated pairing methods need user interaction through a [`PairingIo`]
+    /// implementation.
+    pub mitm: bool,
+
+    /// Agree to bond if the peer requests it.
+    ///
+    /// Key distribution is not yet implemented, so no keys are exchanged after pairing.
+    pub bonding: bool,
+
+    /// The user interaction capabilities of this device, which determine the pairing method.
+    pub io_capabilities: IoCapabilities,
+}
+
+impl SecurityConfig {
+    /// Creates a policy that allows any pairing method supported with `io_capabilities`, and
+    /// doesn't bond.
+    pub fn new(io_capabilities: IoCapabilities) -> Self {
+        Self {
+            sc_only: false,
+            mitm: false,
+            bonding: false,
+            io_capabilities,
+        }
+    }
+}
+
+/// User interaction during authenticated pairing.
+///
+/// Which of these methods is called depends on the pairing method selected from the
+/// [`IoCapabilities`] of both devices. All values exchanged with the user are 6-digit numbers below
+/// 1,000,000, which should be shown with leading zeros (`{:06}`) so that they match what the other
+/// device shows.
+///
+/// The methods are called while the Security Manager processes an incoming command, so blocking in
+/// them blocks the L2CAP layer. The peer gives up if pairing takes longer than 30 seconds.
+pub trait PairingIo {
+    /// Shows `passkey` to the user, who has to enter it on the other device (*Passkey Entry*).
+    fn display_passkey(&mut self, passkey: u32);
+
+    /// Asks the user for the passkey shown on the other device (*Passkey Entry*).
+    ///
+    /// Returning a value of 1,000,000 or more cancels pairing.
+    fn request_passkey(&mut self) -> u32;
+
+    /// Shows `value` to the user and asks whether the other device shows the same number
+    /// (*Numeric Comparison*).
+    ///
+    /// Returning `false` cancels pairing.
+    fn confirm_numeric(&mut self, value: u32) -> bool;
+}
+
+/// Cancels every pairing method that needs user interaction.
+///
+/// This is suitable for devices with [`IoCapabilities::NoInputNoOutput`], which always use
+/// *"Just Works"*.
+impl PairingIo for () {
+    fn display_passkey(&mut self, _: u32) {}
+
+    fn request_passkey(&mut self) -> u32 {
+        u32::MAX
+    }
+
+    fn confirm_numeric(&mut self, _: u32) -> bool {
+        false
+    }
+}
+
+/// Events emitted by the Security Manager.
+///
+/// These can be retrieved by calling [`SecurityManager::take_event`].
+#[derive(Debug, Copy, Clone)]
+pub enum PairingEvent {
+    /// Pairing completed successfully.
+    ///
+    /// The contained key has to be used by the Link-Layer when the master starts encryption of the
+    /// connection.
+    Complete {
+        /// The Short-Term Key generated by *LE Legacy Pairing*, or the Long-Term Key generated by
+        /// *LE Secure Connections* pairing.
+        stk: EncryptionKey,
+
+        /// Security of a connection encrypted with `stk`, depending on whether the pairing method
+        /// provided MITM protection.
+        ///
+        /// This has to be passed to `Responder::encryption_enabled` once the Link-Layer reports
+        /// that encryption was started with the key, and should be stored alongside a bonded key.
+        security: LinkSecurity,
+    },
+
+    /// Pairing was aborted, either by us or by the connected device.
+    Failed(PairingFailedReason),
+}
+
+/// The LE Security Manager.
+///
+/// Manages pairing and key generation and exchange.
+///
+/// *LE Legacy Pairing* is supported with the *"Just Works"* and *Passkey Entry* methods. Security
+/// Managers created with [`secure_connections`] additionally support *LE Secure Connections*
+/// pairing with *"Just Works"*, *Numeric Comparison* and *Passkey Entry*. The user takes part in
+/// pairing through the [`PairingIo`] implementation `P`. Out-of-Band pairing and key distribution
+/// are not supported.
+///
+/// [`secure_connections`]: SecurityManager::secure_connections
+#[derive(Debug)]
+pub struct SecurityManager<S: SecurityLevel, P: PairingIo = ()> {
+    _security: S,
+
+    /// Local security policy, or `None` if pairing is disabled.
+    config: Option<SecurityConfig>,
+
+    pairing_io: P,
+
+    /// Addresses of the initiator (master) and responder (slave) of the current connection.
+    addresses: Option<(DeviceAddress, DeviceAddress)>,
+
+    rng: Option<Drbg>,
+    state: PairingState,
+    event: Option<PairingEvent>,
+}
+
+impl SecurityManager<NoSecurity> {
+    /// Creates a Security Manager that supports *LE Legacy Pairing*.
+    ///
+    /// `io_capabilities` describes the user interaction capabilities of this device. Pairing
+    /// methods that need user interaction are canceled unless a [`PairingIo`] implementation is
+    /// provided using [`with_pairing_io`].
+    ///
+    /// Before pairing can succeed, [`set_random_seed`] and [`set_connection_addresses`] have to be
+    /// called.
+    ///
+    /// [`with_pairing_io`]: SecurityManager::with_pairing_io
+    /// [`set_random_seed`]: SecurityManager::set_random_seed
+    /// [`set_connection_addresses`]: SecurityManager::set_connection_addresses
+    pub fn new(io_capabilities: IoCapabilities) -> Self {
+        Self::with_config(SecurityConfig::new(io_capabilities))
+    }
+
+    /// Creates a Security Manager that supports *LE Legacy Pairing*, restricted by the policy in
+    /// `config`.
+    ///
+    /// Like with [`new`](Self::new), [`set_random_seed`](Self::set_random_seed) and
+    /// [`set_connection_addresses`](Self::set_connection_addresses) have to be called before
+    /// pairing can succeed.
+    pub fn with_config(config: SecurityConfig) -> Self {
+        Self {
+            _security: NoSecurity,
+            config: Some(config),
+            pairing_io: (),
+            addresses: None,
+            rng: None,
+            state: PairingState::Idle,
+            event: None,
+        }
+    }
+
+    /// Creates a Security Manager that rejects all pairing attempts.
+    pub fn no_security() -> Self {
+        Self {
+            _security: NoSecurity,
+            config: None,
+            pairing_io: (),
+            addresses: None,
+            rng: None,
+            state: PairingState::Idle,
+            event: None,
+        }
+    }
+}
+
+impl SecurityManager<SecureConnections> {
+    /// Creates a Security Manager that supports *LE Secure Connections* pairing in addition to
+    /// *LE Legacy Pairing*, restricted by the policy in `config`.
+    ///
+    /// The P-256 operations are performed by the [`P256Provider`]. Like with
+    /// [`new`](SecurityManager::new), [`set_random_seed`](Self::set_random_seed) and
+    /// [`set_connection_addresses`](Self::set_connection_addresses) have to be called before
+    /// pairing can succeed.
+    pub fn secure_connections(config: SecurityConfig) -> Self {
+        Self {
+            _security: SecureConnections,
+            config: Some(config),
+            pairing_io: (),
+            addresses: None,
+            rng: None,
+            state: PairingState::Idle,
+            event: None,
+        }
+    }
+}
+
+impl<S: SecurityLevel> SecurityManager<S> {
+    /// Uses `pairing_io` to interact with the user during pairing.
+    pub fn with_pairing_io<P: PairingIo>(self, pairing_io: P) -> SecurityManager<S, P> {
+        SecurityManager {
+            _security: self._security,
+            config: self.config,
+            pairing_io,
+            addresses: self.addresses,
+            rng: self.rng,
+            state: self.state,
+            event: self.event,
+        }
+    }
+}
+
+impl<S: SecurityLevel, P: PairingIo> SecurityManager<S, P> {
+    /// Seeds the generator used for the random values exchanged during pairing.
+    ///
+    /// `rng` must be a cryptographically secure random number generator.
+    pub fn set_random_seed<R: RngCore + CryptoRng>(&mut self, rng: &mut R) {
+        self.rng = Some(Drbg::new(rng));
+    }
+
+    /// Sets the device addresses of the current connection.
+    ///
+    /// This must be called whenever a new connection is established, since the addresses are part
+    /// of the confirm values exchanged during pairing. Any pairing in progress is aborted.
+    ///
+    /// # Parameters
+    ///
+    /// * **`initiator`**: Address of the device that initiated the connection (the master).
+    /// * **`responder`**: Address of this device (the slave).
+    pub fn set_connection_addresses(&mut self, initiator: DeviceAddress, responder: DeviceAddress) {
+        self.addresses = Some((initiator, responder));
+        self.state = PairingState::Idle;
+    }
+
+    /// Returns the next pending pairing event, if any.
+    pub fn take_event(&mut self) -> Option<PairingEvent> {
+        self.event.take()
+    }
+
+    /// Returns a reference to the [`PairingIo`] implementation.
+    pub fn pairing_io(&mut self) -> &mut P {
+        &mut self.pairing_io
+    }
+
+    /// Handles an incoming *Pairing Request*.
+    ///
+    /// Returns the encoded *Pairing Response*, the selected pairing method, and whether
+    /// *LE Secure Connections* pairing will be used.
+    fn pairing_response(
+        &self,
+        req: &PairingFeatures,
+    ) -> Result<([u8; PairingFeatures::SIZE], Method, bool), PairingFailedReason> {
+        let config = self
+            .config
+            .ok_or(PairingFailedReason::PairingNotSupported)?;
+        let io = config.io_capabilities;
+
+        if self.addresses.is_none() {
+            warn!("pairing requested, but connection addresses are not known");
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+        if self.rng.is_none() {
+            warn!("pairing requested, but no random seed was provided");
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+        if req.max_keysize < 16 {
+            return Err(PairingFailedReason::EncryptionKeySize);
+        }
+
+        let peer_io = req.io.value();
+        if let IoCapabilities::Unknown(_) = peer_io {
+            return Err(PairingFailedReason::InvalidParameters);
+        }
+
+        let peer_auth = req.auth_req.value();
+        let sc = S::SECURE_CONNECTIONS && peer_auth.secure_connection();
+        if config.sc_only && !sc {
+            debug!("rejecting LE Legacy Pairing");
+            return Err(PairingFailedReason::AuthenticationRequirements);
+        }
+
+        let method = if peer_auth.mitm() || config.mitm {
+            pairing_method(peer_io, io, sc)
+        } else {
+            Method::JustWorks
+        };
+        if config.mitm && method == Method::JustWorks {
+            // The I/O capabilities only allow *"Just Works"*, which has no MITM protection
+            debug!("rejecting unauthenticated pairing");
+            return Err(PairingFailedReason::AuthenticationRequirements);
+        }
+
+        // We don't support key distribution (yet), so we ask for nothing of that.
+        let bonding = config.bonding && matches!(peer_auth.bonding_type(), BondingType::Bonding);
+        let mut auth_req = AuthReq(0);
+        auth_req.set_bonding_type(if bonding {
+            BondingType::Bonding
+        } else {
+            BondingType::NoBonding
+        });
+        auth_req.set_mitm(config.mitm);
+        auth_req.set_secure_connection(S::SECURE_CONNECTIONS);
+        let rsp = PairingFeatures {
+            io: Field::new(io.into()),
+            oob: Field::new(Oob::NotPresent.into()),
+            auth_req: Field::new(auth_req.as_raw()),
+            max_keysize: 16,
+            initiator_dist: Field::new(KeyDistribution::empty().bits()),
+            responder_dist: Field::new(KeyDistribution::empty().bits()),
+        };
+
+        let mut pres = [0; PairingFeatures::SIZE];
+        Command::PairingResponse(&rsp)
+            .to_bytes(&mut ByteWriter::new(&mut pres))
+            .unwrap();
+        Ok((pres, method, sc))
+    }
+
+    /// Advances the pairing state machine in response to `cmd`.
+    ///
+    /// Returns the command to send back, or `None` if `cmd` should be ignored.
+    fn process_command(
+        &mut self,
+        cmd: Command<'_>,
+        raw: &[u8],
+    ) -> Result<Option<Reply>, PairingFailedReason> {
+        let state = core::mem::replace(&mut self.state, PairingState::Idle);
+        match (cmd, state) {
+            (Command::PairingRequest(req), PairingState::Idle) => {
+                let (pres, method, sc) = self.pairing_response(req)?;
+                let mut preq = [0; PairingFeatures::SIZE];
+                preq.copy_from_slice(&raw[..PairingFeatures::SIZE]);
+
+                let passkey = if method == Method::PasskeyDisplay {
+                    let passkey =
+                        (self.rng.as_mut().unwrap().next() % u128::from(SIX_DIGITS)) as u32;
+                    self.pairing_io.display_passkey(passkey);
+                    passkey
+                } else {
+                    0
+                };
+
+                self.state = if sc {
+                    PairingState::WaitPublicKey {
+                        preq,
+                        pres,
+                        method,
+                        passkey,
+                    }
+                } else {
+                    PairingState::WaitConfirm {
+                        preq,
+                        pres,
+                        method,
+                        passkey,
+                    }
+                };
+                Ok(Some(Reply::Raw(pres)))
+            }
+            (
+                Command::PairingConfirm(mconfirm),
+                PairingState::WaitConfirm {
+                    preq,
+                    pres,
+                    method,
+                    passkey,
+                },
+            ) => {
+                let tk = match method {
+                    Method::PasskeyDisplay => u128::from(passkey),
+                    Method::PasskeyInput => u128::from(self.request_passkey()?),
+                    Method::JustWorks | Method::NumericComparison => TK_JUST_WORKS,
+                };
+                let srand = self.rng.as_mut().unwrap().next();
+                let sconfirm = self.confirm_value(tk, srand, &preq, &pres);
+
+                self.state = PairingState::WaitRandom {
+                    preq,
+                    pres,
+                    method,
+                    tk,
+                    mconfirm,
+                    srand,
+                };
+                Ok(Some(Reply::Cmd(Command::PairingConfirm(sconfirm))))
+            }
+            (
+                Command::PairingRandom(mrand),
+                PairingState::WaitRandom {
+                    preq,
+                    pres,
+                    method,
+                    tk,
+                    mconfirm,
+                    srand,
+                },
+            ) => {
+                if self.confirm_value(tk, mrand, &preq, &pres) != mconfirm {
+                    return Err(PairingFailedReason::ConfirmValueFailed);
+                }
+
+                let stk = toolbox::s1(&mut SoftAesProvider::new(), tk, srand, mrand);
+                self.event = Some(PairingEvent::Complete {
+                    stk: EncryptionKey(stk),
+                    security: method.security(),
+                });
+                Ok(Some(Reply::Cmd(Command::PairingRandom(srand))))
+            }
+            (
+                Command::PairingPublicKey(pka),
+                PairingState::WaitPublicKey {
+                    preq,
+                    pres,
+                    method,
+                    passkey,
+                },
+            ) => {
+                let pka = PublicKey(swap_coordinates(pka));
+                let (secret, pkb) =
+                    P256Provider::new().generate_keypair(self.rng.as_mut().unwrap());
+                let dhkey = secret
+                    .agree(&pka)
+                    .map_err(|_| PairingFailedReason::DhKeyCheckFailed)?;
+
+                let mut pairing = ScPairing {
+                    preq,
+                    pres,
+                    method,
+                    passkey,
+                    pkax: [0; 32],
+                    pkbx: [0; 32],
+                    dhkey: dhkey.0,
+                };
+                pairing.pkax.copy_from_slice(&pka.0[..32]);
+                pairing.pkbx.copy_from_slice(&pkb.0[..32]);
+
+                let key = swap_coordinates(&pkb.0);
+                match method {
+                    Method::JustWorks | Method::NumericComparison => {
+                        let nb = self.rng.as_mut().unwrap().next();
+                        let cb = toolbox::f4(
+                            &mut SoftAesProvider::new(),
+                            &pairing.pkbx,
+                            &pairing.pkax,
+                            nb,
+                            0,
+                        );
+
+                        self.state = PairingState::WaitNonce { pairing, nb };
+                        Ok(Some(Reply::PublicKey {
+                            key,
+                            confirm: Some(cb),
+                        }))
+                    }
+                    Method::PasskeyDisplay | Method::PasskeyInput => {
+                        self.state = PairingState::WaitPasskeyConfirm { pairing, round: 0 };
+                        Ok(Some(Reply::PublicKey { key, confirm: None }))
+                    }
+                }
+            }
+            (Command::PairingRandom(na), PairingState::WaitNonce { pairing, nb }) => {
+                self.state = PairingState::WaitDhKeyCheck { pairing, na, nb };
+                Ok(Some(Reply::Cmd(Command::PairingRandom(nb))))
+            }
+            (
+                Command::PairingConfirm(cai),
+                PairingState::WaitPasskeyConfirm { mut pairing, round },
+            ) => {
+                if round == 0 && pairing.method == Method::PasskeyInput {
+                    pairing.passkey = self.request_passkey()?;
+                }
+
+                let nbi = self.rng.as_mut().unwrap().next();
+                let cbi = toolbox::f4(
+                    &mut SoftAesProvider::new(),
+                    &pairing.pkbx,
+                    &pairing.pkax,
+                    nbi,
+                    pairing.passkey_bit(round),
+                );
+
+                self.state = PairingState::WaitPasskeyRandom {
+                    pairing,
+                    round,
+                    cai,
+                    nbi,
+                };
+                Ok(Some(Reply::Cmd(Command::PairingConfirm(cbi))))
+            }
+            (
+                Command::PairingRandom(nai),
+                PairingState::WaitPasskeyRandom {
+                    pairing,
+                    round,
+                    cai,
+                    nbi,
+                },
+            ) => {
+                let expected = toolbox::f4(
+                    &mut SoftAesProvider::new(),
+                    &pairing.pkax,
+                    &pairing.pkbx,
+                    nai,
+                    pairing.passkey_bit(round),
+                );
+                if expected != cai {
+                    return Err(PairingFailedReason::ConfirmValueFailed);
+                }
+
+                // The nonces of the last round are used for the DHKey check
+                self.state = if round + 1 == PASSKEY_BITS {
+                    PairingState::WaitDhKeyCheck {
+                        pairing,
+                        na: nai,
+                        nb: nbi,
+                    }
+                } else {
+                    PairingState::WaitPasskeyConfirm {
+                        pairing,
+                        round: round + 1,
+                    }
+                };
+                Ok(Some(Reply::Cmd(Command::PairingRandom(nbi))))
+            }
+            (Command::PairingDhKeyCheck(ea), PairingState::WaitDhKeyCheck { pairing, na, nb }) => {
+                let aes = &mut SoftAesProvider::new();
+                if pairing.method == Method::NumericComparison {
+                    let value = toolbox::g2(aes, &pairing.pkax, &pairing.pkbx, na, nb) % SIX_DIGITS;
+                    if !self.pairing_io.confirm_numeric(value) {
+                        return Err(PairingFailedReason::NumericComparisonFailed);
+                    }
+                }
+
+                let (initiator, responder) = self.addresses.as_ref().unwrap();
+                let r = match pairing.method {
+                    Method::PasskeyDisplay | Method::PasskeyInput => u128::from(pairing.passkey),
+                    Method::JustWorks | Method::NumericComparison => 0,
+                };
+                let (mac_key, ltk) = toolbox::f5(aes, &pairing.dhkey, na, nb, initiator, responder);
+
+                let io_cap_a = [pairing.preq[3], pairing.preq[2], pairing.preq[1]];
+                let expected = toolbox::f6(aes, mac_key, na, nb, r, io_cap_a, initiator, responder);
+                if expected != ea {
+                    return Err(PairingFailedReason::DhKeyCheckFailed);
+                }
+
+                let io_cap_b = [pairing.pres[3], pairing.pres[2], pairing.pres[1]];
+                let eb = toolbox::f6(aes, mac_key, nb, na, r, io_cap_b, responder, initiator);
+                self.event = Some(PairingEvent::Complete {
+                    stk: EncryptionKey(ltk),
+                    security: pairing.method.security(),
+                });
+                Ok(Some(Reply::Cmd(Command::PairingDhKeyCheck(eb))))
+            }
+            (Command::PairingFailed(reason), _) => {
+                debug!("peer aborted pairing: {:?}", reason);
+                self.event = Some(PairingEvent::Failed(reason));
+                Ok(None)
+            }
+            (
+                Command::Unknown {
+                    code: CommandCode::Unknown(code),
+                    data,
+                },
+                state,
+            ) => {
+                warn!(
+                    "unknown security manager cmd: 0x{:02X} {}",
+                    code,
+                    HexSlice(data)
+                );
+                self.state = state;
+                Err(PairingFailedReason::CommandNotSupported)
+            }
+            (Command::Unknown { code, data }, state) => {
+                warn!("[NYI] SMP cmd {:?}: {}", code, HexSlice(data));
+                self.state = state;
+                Ok(None)
+            }
+            (cmd, state) => {
+                debug!("unexpected SMP cmd {:?} in state {:?}", cmd, state);
+                Err(PairingFailedReason::UnspecifiedReason)
+            }
+        }
+    }
+
+    /// Asks the user for the passkey displayed by the peer.
+    fn request_passkey(&mut self) -> Result<u32, PairingFailedReason> {
+        let passkey = self.pairing_io.request_passkey();
+        if passkey < SIX_DIGITS {
+            Ok(passkey)
+        } else {
+            debug!("passkey entry canceled");
+            Err(PairingFailedReason::PasskeyEntryFailed)
+        }
+    }
+
+    /// Computes the *LE Legacy Pairing* confirm value for the random value `rand`.
+    fn confirm_value(&self, tk: u128, rand: u128, preq: &[u8; 7], pres: &[u8; 7]) -> u128 {
+        let (initiator, responder) = self.addresses.as_ref().unwrap();
+        toolbox::c1(
+            &mut SoftAesProvider::new(),
+            tk,
+            rand,
+            preq,
+            pres,
+            initiator,
+            responder,
+        )
+    }
+}
+
+impl<S: SecurityLevel, P: PairingIo> ProtocolObj for SecurityManager<S, P> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        let cmd = Command::from_bytes(&mut ByteReader::new(message))?;
+        trace!("SMP cmd {:?}, {}", cmd, HexSlice(message));
+
+        match self.process_command(cmd, message) {
+            Ok(Some(Reply::Cmd(rsp))) => responder.send(rsp),
+            Ok(Some(Reply::Raw(rsp))) => responder.send_with(|writer| writer.write_slice(&rsp)),
+            Ok(Some(Reply::PublicKey { key, confirm })) => {
+                // The public key doesn't fit in the minimum MTU, so it is fragmented if necessary
+                let mut pdu = [0; 65];
+                pdu[0] = CommandCode::PairingPublicKey.into();
+                pdu[1..].copy_from_slice(&key);
+                responder.send_fragmented(&pdu)?;
+                match confirm {
+                    Some(confirm) => responder.send(Command::PairingConfirm(confirm)),
+                    None => Ok(()),
+                }
+            }
+            Ok(None) => Ok(()),
+            Err(reason) => {
+                debug!("pairing failed: {:?}", reason);
+                self.state = PairingState::Idle;
+                self.event = Some(PairingEvent::Failed(reason));
+                responder.send(Command::PairingFailed(reason))
+            }
+        }
+    }
+}
+
+impl<S: SecurityLevel, P: PairingIo> Protocol for SecurityManager<S, P> {
+    // Commands are sent in PDUs of the minimum size, longer ones are fragmented
+    const RSP_PDU_SIZE: u8 = 23;
+}
+
+/// The Temporary Key used by the *"Just Works"* pairing method.
+const TK_JUST_WORKS: u128 = 0;
+
+/// Passkeys and numeric comparison values are 6-digit numbers below this value.
+const SIX_DIGITS: u32 = 1_000_000;
+
+/// Number of passkey bits committed to in *LE Secure Connections* *Passkey Entry*.
+const PASSKEY_BITS: u8 = 20;
+
+/// Method used to authenticate the pairing devices.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Method {
+    /// No authentication (no MITM protection).
+    JustWorks,
+    /// Both devices display a value that the user confirms (*LE Secure Connections* only).
+    NumericComparison,
+    /// We display a passkey that the user enters on the peer.
+    PasskeyDisplay,
+    /// The user enters the passkey displayed on the peer (or on both devices).
+    PasskeyInput,
+}
+
+impl Method {
+    /// Returns the security of a connection encrypted with a key generated by this method.
+    fn security(self) -> LinkSecurity {
+        match self {
+            Method::JustWorks => LinkSecurity::Encrypted,
+            Method::NumericComparison | Method::PasskeyDisplay | Method::PasskeyInput => {
+                LinkSecurity::Authenticated
+            }
+        }
+    }
+}
+
+/// Selects the pairing method for devices with the given I/O capabilities, if MITM protection is
+/// requested.
+///
+/// This implements the mapping in Vol. 3, Part H, 2.3.5.1 of the spec.
+fn pairing_method(initiator: IoCapabilities, responder: IoCapabilities, sc: bool) -> Method {
+    use self::IoCapabilities::*;
+
+    match (initiator, responder) {
+        (NoInputNoOutput, _) | (_, NoInputNoOutput) => Method::JustWorks,
+        (DisplayOnly | DisplayYesNo, DisplayOnly) | (DisplayOnly, DisplayYesNo) => {
+            Method::JustWorks
+        }
+        (DisplayYesNo, DisplayYesNo) if sc => Method::NumericComparison,
+        (DisplayYesNo, DisplayYesNo) => Method::JustWorks,
+        (DisplayYesNo | KeyboardDisplay, KeyboardDisplay) | (KeyboardDisplay, DisplayYesNo)
+            if sc =>
+        {
+            Method::NumericComparison
+        }
+        (KeyboardOnly | KeyboardDisplay, DisplayOnly | DisplayYesNo)
+        | (KeyboardOnly, KeyboardDisplay) => Method::PasskeyDisplay,
+        _ => Method::PasskeyInput,
+    }
+}
+
+/// Converts a public key between the Rubble encoding and the SMP encoding, which encodes the
+/// coordinates in little-endian.
+fn swap_coordinates(key: &[u8; 64]) -> [u8; 64] {
+    let mut swapped = *key;
+    swapped[..32].reverse();
+    swapped[32..].reverse();
+    swapped
+}
+
+/// State of an ongoing *LE Secure Connections* pairing, after the public keys were exchanged.
+#[derive(Copy, Clone)]
+struct ScPairing {
+    preq: [u8; PairingFeatures::SIZE],
+    pres: [u8; PairingFeatures::SIZE],
+    method: Method,
+    /// The passkey, if a *Passkey Entry* method is used and it is known yet.
+    passkey: u32,
+    /// X coordinate of the initiator's public key.
+    pkax: [u8; 32],
+    /// X coordinate of our public key.
+    pkbx: [u8; 32],
+    dhkey: [u8; 32],
+}
+
+impl ScPairing {
+    /// Returns the `z` parameter of the confirm values in the given *Passkey Entry* round.
+    fn passkey_bit(&self, round: u8) -> u8 {
+        0x80 | ((self.passkey >> round) & 1) as u8
+    }
+}
+
+impl fmt::Debug for ScPairing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScPairing")
+            .field("method", &self.method)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Progress of an ongoing pairing procedure.
+#[derive(Debug, Copy, Clone)]
+enum PairingState {
+    /// No pairing in progress.
+    Idle,
+
+    /// Pairing features were exchanged, waiting for the master's confirm value (`Mconfirm`).
+    WaitConfirm {
+        preq: [u8; PairingFeatures::SIZE],
+        pres: [u8; PairingFeatures::SIZE],
+        method: Method,
+        /// The passkey we displayed, if `method` is `PasskeyDisplay`.
+        passkey: u32,
+    },
+
+    /// Our confirm value was sent, waiting for the master's random value (`Mrand`).
+    WaitRandom {
+        preq: [u8; PairingFeatures::SIZE],
+        pres: [u8; PairingFeatures::SIZE],
+        method: Method,
+        tk: u128,
+        mconfirm: u128,
+        srand: u128,
+    },
+
+    /// *LE Secure Connections* pairing was selected, waiting for the master's public key.
+    WaitPublicKey {
+        preq: [u8; PairingFeatures::SIZE],
+        pres: [u8; PairingFeatures::SIZE],
+        method: Method,
+        /// The passkey we displayed, if `method` is `PasskeyDisplay`.
+        passkey: u32,
+    },
+
+    /// Our public key and confirm value were sent, waiting for the master's nonce (`Na`).
+    WaitNonce { pairing: ScPairing, nb: u128 },
+
+    /// Waiting for the master's confirm value (`Cai`) in a *Passkey Entry* round.
+    WaitPasskeyConfirm { pairing: ScPairing, round: u8 },
+
+    /// Our confirm value was sent, waiting for the master's nonce (`Nai`) in a *Passkey Entry*
+    /// round.
+    WaitPasskeyRandom {
+        pairing: ScPairing,
+        round: u8,
+        cai: u128,
+        nbi: u128,
+    },
+
+    /// Nonces were exchanged, waiting for the master's DHKey check value (`Ea`).
+    WaitDhKeyCheck {
+        pairing: ScPairing,
+        na: u128,
+        nb: u128,
+    },
+}
+
+/// A reply to an SMP command.
+enum Reply {
+    Cmd(Command<'static>),
+    /// A preencoded command.
+    Raw([u8; PairingFeatures::SIZE]),
+    /// Our public key in SMP encoding, followed by our confirm value for *"Just Works"* and
+    /// *Numeric Comparison*.
+    PublicKey {
+        key: [u8; 64],
+        confirm: Option<u128>,
+    },
+}
+
+/// Generator for the random values used during pairing and for private addresses.
+///
+/// This runs AES-128 in counter mode, keyed with a seed obtained from a secure RNG.
+pub(crate) struct Drbg {
+    key: u128,
+    counter: u128,
+}
+
+impl Drbg {
+    /// Creates a generator seeded from `rng`, which must be cryptographically secure.
+    pub(crate) fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut key = [0; 16];
+        rng.fill_bytes(&mut key);
+        Self {
+            key: u128::from_le_bytes(key),
+            counter: 0,
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> u128 {
+        self.counter = self.counter.wrapping_add(1);
+        toolbox::e(&mut SoftAesProvider::new(), self.key, self.counter)
+    }
+}
+
+impl RngCore for Drbg {
+    fn next_u32(&mut self) -> u32 {
+        self.next() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.next() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(16) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for Drbg {}
+
+impl fmt::Debug for Drbg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Drbg { .. }")
+    }
+}
+
+/// Pairing features exchanged using the *Pairing Request* and *Pairing Response* commands.
+#[derive(Debug, Copy, Clone, Unaligned, zerocopy::FromBytes)]
+#[repr(C)]
+struct PairingFeatures {
+    /// The I/O capabilities of the device.
+    io: Field<u8, IoCapabilities>,
+    /// Whether the device has OOB pairing data available.
+    oob: Field<u8, Oob>,
+    /// Authentication requirements of the device.
+    auth_req: Field<u8, AuthReq>,
+    /// Maximum supported encryption key size in range 7..=16 Bytes.
+    ///
+    /// For BLE, this is always 16, since it always uses AES-128-CCM (even with the broken
+    /// *LE Legacy Pairing*). We consider anything smaller than 16 to be as insecure as a plain
+    /// text connection.
+    max_keysize: u8,
+    /// Set of keys the initiator (the device sending the request) wants to distribute to the
+    /// responder (the device receiving the request).
+    initiator_dist: Field<u8, KeyDistribution>,
+    /// Set of keys the initiator requests the responder to generate and distribute.
+    responder_dist: Field<u8, KeyDistribution>,
+}
+
+impl PairingFeatures {
+    /// Size of a *Pairing Request* or *Pairing Response* command, including the opcode.
+    const SIZE: usize = 7;
+}
+
+impl ToBytes for PairingFeatures {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(*self.io.raw())?;
+        writer.write_u8(*self.oob.raw())?;
+        writer.write_u8(*self.auth_req.raw())?;
+        writer.write_u8(self.max_keysize)?;
+        writer.write_u8(*self.initiator_dist.raw())?;
+        writer.write_u8(*self.responder_dist.raw())?;
+        Ok(())
+    }
+}
+
+/// An SMP command.
+#[derive(Debug, Copy, Clone)]
+enum Command<'a> {
+    PairingRequest(&'a PairingFeatures),
+    PairingResponse(&'a PairingFeatures),
+    PairingConfirm(u128),
+    PairingRandom(u128),
+    PairingFailed(PairingFailedReason),
+    /// The public key of the sender, encoded as X and Y coordinates in little-endian.
+    PairingPublicKey(&'a [u8; 64]),
+    PairingDhKeyCheck(u128),
+    Unknown {
+        code: CommandCode,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> FromBytes<'a> for Command<'a> {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let code = CommandCode::from(bytes.read_u8()?);
+        Ok(match code {
+            CommandCode::PairingRequest => Command::PairingRequest(bytes.read_obj()?),
+            CommandCode::PairingResponse => Command::PairingResponse(bytes.read_obj()?),
+            CommandCode::PairingConfirm => {
+                Command::PairingConfirm(u128::from_le_bytes(bytes.read_array()?))
+            }
+            CommandCode::PairingRandom => {
+                Command::PairingRandom(u128::from_le_bytes(bytes.read_array()?))
+            }
+            CommandCode::PairingFailed => Command::PairingFailed(bytes.read_u8()?.into()),
+            CommandCode::PairingPublicKey => Command::PairingPublicKey(bytes.read_obj()?),
+            CommandCode::PairingDhKeyCheck => {
+                Command::PairingDhKeyCheck(u128::from_le_bytes(bytes.read_array()?))
+            }
+            _ => Command::Unknown {
+                code,
+                data: bytes.read_rest(),
+            },
+        })
+    }
+}
+
+impl ToBytes for Command<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match self {
+            Command::PairingRequest(features) => {
+                writer.write_u8(CommandCode::PairingRequest.into())?;
+                features.to_bytes(writer)
+            }
+            Command::PairingResponse(features) => {
+                writer.write_u8(CommandCode::PairingResponse.into())?;
+                features.to_bytes(writer)
+            }
+            Command::PairingConfirm(value) => {
+                writer.write_u8(CommandCode::PairingConfirm.into())?;
+                writer.write_slice(&value.to_le_bytes())
+            }
+            Command::PairingRandom(value) => {
+                writer.write_u8(CommandCode::PairingRandom.into())?;
+                writer.write_slice(&value.to_le_bytes())
+            }
+            Command::PairingFailed(reason) => {
+                writer.write_u8(CommandCode::PairingFailed.into())?;
+                writer.write_u8((*reason).into())
+            }
+            Command::PairingPublicKey(key) => {
+                writer.write_u8(CommandCode::PairingPublicKey.into())?;
+                writer.write_slice(&key[..])
+            }
+            Command::PairingDhKeyCheck(value) => {
+                writer.write_u8(CommandCode::PairingDhKeyCheck.into())?;
+                writer.write_slice(&value.to_le_bytes())
+            }
+            Command::Unknown { code, data } => {
+                writer.write_u8((*code).into())?;
+                writer.write_slice(data)
+            }
+        }
+    }
+}
+
+enum_with_unknown! {
+    #[derive(Debug, Copy, Clone)]
+    enum CommandCode(u8) {
+        PairingRequest = 0x01,
+        PairingResponse = 0x02,
+        PairingConfirm = 0x03,
+        PairingRandom = 0x04,
+        PairingFailed = 0x05,
+        EncryptionInformation = 0x06,
+        MasterIdentification = 0x07,
+        IdentityInformation = 0x08,
+        IdentityAddressInformation = 0x09,
+        SigningInformation = 0x0A,
+        SecurityRequest = 0x0B,
+        PairingPublicKey = 0x0C,
+        PairingDhKeyCheck = 0x0D,
+        PairingKeypressNotification = 0x0E,
+    }
+}
+
+enum_with_unknown! {
+    /// Reason codes sent in the *Pairing Failed* command.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum PairingFailedReason(u8) {
+        /// The user input of the passkey failed, eg. because it was canceled.
+        PasskeyEntryFailed = 0x01,
+        /// The OOB data is not available.
+        OobNotAvailable = 0x02,
+        /// The authentication requirements can not be met due to I/O capabilities.
+        AuthenticationRequirements = 0x03,
+        /// The confirm value does not match the calculated value.
+        ConfirmValueFailed = 0x04,
+        /// Pairing is not supported by the device.
+        PairingNotSupported = 0x05,
+        /// The resulting encryption key size is insufficient.
+        EncryptionKeySize = 0x06,
+        /// The received SMP command is not supported.
+        CommandNotSupported = 0x07,
+        /// Pairing failed due to an unspecified reason.
+        UnspecifiedReason = 0x08,
+        /// Too little time has elapsed since the last pairing attempt.
+        RepeatedAttempts = 0x09,
+        /// The command length is invalid or a parameter is outside of the specified range.
+        InvalidParameters = 0x0A,
+        /// The DHKey Check value does not match the calculated value.
+        DhKeyCheckFailed = 0x0B,
+        /// The confirm values in the numeric comparison protocol do not match.
+        NumericComparisonFailed = 0x0C,
+        /// Pairing over LE failed due to a pairing in progress on the BR/EDR transport.
+        BrEdrPairingInProgress = 0x0D,
+        /// The BR/EDR link key or LE LTK can not be used to derive keys for the other transport.
+        CrossTransportKeyDerivationNotAllowed = 0x0E,
+    }
+}
+
+enum_with_unknown! {
+    /// Describes the I/O capabilities of a device that can be used for the pairing process.
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum IoCapabilities(u8) {
+        /// Device can display a 6-digit number, but has no input capabilities.
+        DisplayOnly = 0x00,
+
+        /// Device can display a 6-digit number and the user can input "Yes" or "No".
+        DisplayYesNo = 0x01,
+
+        /// Device does not have output capability, but the user can input a passcode.
+        KeyboardOnly = 0x02,
+
+        /// Device has no meaningful input and output capabilities.
+        NoInputNoOutput = 0x03,
+
+        /// Device can display a 6-digit passcode and allows passcode entry via a keyboard.
+        KeyboardDisplay = 0x04,
+    }
+}
+
+enum_with_unknown! {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum Oob(u8) {
+        NotPresent = 0x00,
+        Present = 0x01,
+    }
+}
+
+/// Authentication requirements exchanged during pairing requests.
+#[derive(Copy, Clone)]
+pub struct AuthReq(u8);
+
+impl AuthReq {
+    const BITS_BONDING: u8 = 0b0000_0011;
+    const BITS_MITM: u8 = 0b0000_0100;
+    const BITS_SC: u8 = 0b0000_1000;
+    const BITS_KEYPRESS: u8 = 0b0001_0000;
+
+    /// Returns the requested bonding.
+    pub fn bonding_type(&self) -> BondingType {
+        BondingType::from(self.0 & Self::BITS_BONDING)
+    }
+
+    pub fn set_bonding_type(&mut self, ty: BondingType) {
+        self.0 = (self.0 & !Self::BITS_BONDING) | u8::from(ty);
+    }
+
+    /// Returns whether MITM protection is requested.
+    pub fn mitm(&self) -> bool {
+        self.0 & Self::BITS_MITM != 0
+    }
+
+    pub fn set_mitm(&mut self, mitm: bool) {
+        self.0 = (self.0 & !Self::BITS_MITM) | if mitm { Self::BITS_MITM } else { 0 };
+    }
+
+    /// Returns whether *LE Secure Connection* pairing is supported and requested.
+    ///
+    /// If this returns `false`, *LE Legacy Pairing* will be used. Note that *LE Legacy Pairing* has
+    /// serious security problems (refer to the module docs for more info).
+    pub fn secure_connection(&self) -> bool {
+        self.0 & Self::BITS_SC != 0
+    }
+
+    /// Sets whether *LE Secure Connection* pairing is supported and requested.
+    pub fn set_secure_connection(&mut self, sc: bool) {
+        self.0 = (self.0 & !Self::BITS_SC) | if sc { Self::BITS_SC } else { 0 };
+    }
+
+    pub fn keypress(&self) -> bool {
+        self.0 & Self::BITS_KEYPRESS != 0
+    }
+
+    pub fn set_keypress(&mut self, keypress: bool) {
+        self.0 = (self.0 & !Self::BITS_KEYPRESS) | if keypress { Self::BITS_KEYPRESS } else { 0 };
+    }
+}
+
+impl fmt::Debug for AuthReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthReq")
+            .field("bonding_type", &self.bonding_type())
+            .field("mitm", &self.mitm())
+            .field("secure_connection", &self.secure_connection())
+            .field("keypress", &self.keypress())
+            .finish()
+    }
+}
+
+impl RawRepr<u8> for AuthReq {
+    fn from_raw(raw: u8) -> Self {
+        Self(raw)
+    }
+
+    fn as_raw(&self) -> u8 {
+        self.0
+    }
+}
+
+enum_with_unknown! {
+    /// Whether to perform bonding in addition to pairing.
+    ///
+    /// If `Bonding` is selected, the exchanged keys are permanently stored on both devices. This
+    /// is usually what you want.
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum BondingType(u8) {
+        /// No bonding should be performed; the exchanged keys should not be permanently stored.
+        ///
+        /// This is usually not what you want since it requires the user to perform pairing every
+        /// time the devices connect again.
+        NoBonding = 0b00,
+
+        /// Permanently store the exchanged keys to allow resuming encryption on future connections.
+        Bonding = 0b01,
+    }
+}
+
+bitflags! {
+    /// Indicates which types of keys a device requests for distribution.
+    #[derive(Debug)]
+    struct KeyDistribution: u8 {
+        const ENC_KEY = 1 << 0;
+        const ID_KEY = 1 << 1;
+        const SIGN_KEY = 1 << 2;
+        const LINK_KEY = 1 << 3;
+    }
+}
+
+impl RawRepr<u8> for KeyDistribution {
+    fn from_raw(raw: u8) -> Self {
+        Self::from_bits_truncate(raw)
+    }
+
+    fn as_raw(&self) -> u8 {
+        self.bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::AddressKind;
+
+    struct CountingRng(u8);
+
+    impl RngCore for CountingRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for b in dest {
+                self.0 = self.0.wrapping_add(1);
+                *b = self.0;
+            }
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for CountingRng {}
+
+    fn reply_bytes(reply: Reply) -> ([u8; 17], usize) {
+        let mut buf = [0; 17];
+        let len = match reply {
+            Reply::Cmd(cmd) => {
+                let mut writer = ByteWriter::new(&mut buf);
+                cmd.to_bytes(&mut writer).unwrap();
+                17 - writer.space_left()
+            }
+            Reply::Raw(raw) => {
+                buf[..raw.len()].copy_from_slice(&raw);
+                raw.len()
+            }
+            Reply::PublicKey { .. } => panic!("unexpected public key"),
+        };
+        (buf, len)
+    }
+
+    fn process<S: SecurityLevel, P: PairingIo>(
+        sm: &mut SecurityManager<S, P>,
+        raw: &[u8],
+    ) -> Result<Reply, PairingFailedReason> {
+        let cmd = Command::from_bytes(&mut ByteReader::new(raw)).unwrap();
+        sm.process_command(cmd, raw).map(Option::unwrap)
+    }
+
+    fn setup() -> (SecurityManager<NoSecurity>, DeviceAddress, DeviceAddress) {
+        let ia = DeviceAddress::new([0xA6, 0xA5, 0xA4, 0xA3, 0xA2, 0xA1], AddressKind::Random);
+        let ra = DeviceAddress::new([0xB6, 0xB5, 0xB4, 0xB3, 0xB2, 0xB1], AddressKind::Public);
+        let mut sm = SecurityManager::new(IoCapabilities::NoInputNoOutput);
+        sm.set_random_seed(&mut CountingRng(0));
+        sm.set_connection_addresses(ia, ra);
+        (sm, ia, ra)
+    }
+
+    #[test]
+    fn just_works() {
+        let (mut sm, ia, ra) = setup();
+        let aes = &mut SoftAesProvider::new();
+
+        // Bonding, MITM and SC requested, but we can only do unauthenticated legacy pairing
+        let preq = [0x01, 0x04, 0x00, 0x0D, 0x10, 0x07, 0x07];
+        let (pres, len) = reply_bytes(process(&mut sm, &preq).unwrap());
+        assert_eq!(&pres[..len], &[0x02, 0x03, 0x00, 0x00, 0x10, 0x00, 0x00]);
+        let pres: [u8; 7] = pres[..7].try_into().unwrap();
+
+        let mrand = 0x5783D52156AD6F0E6388274EC6702EE0;
+        let mconfirm = toolbox::c1(aes, 0, mrand, &preq, &pres, &ia, &ra);
+        let mut cmd = [0x03; 17];
+        cmd[1..].copy_from_slice(&mconfirm.to_le_bytes());
+        let (sconfirm, len) = reply_bytes(process(&mut sm, &cmd).unwrap());
+        assert_eq!(len, 17);
+        assert_eq!(sconfirm[0], 0x03);
+        let sconfirm = u128::from_le_bytes(sconfirm[1..].try_into().unwrap());
+        assert!(sm.take_event().is_none());
+
+        cmd[0] = 0x04;
+        cmd[1..].copy_from_slice(&mrand.to_le_bytes());
+        let (srand, len) = reply_bytes(process(&mut sm, &cmd).unwrap());
+        assert_eq!(len, 17);
+        assert_eq!(srand[0], 0x04);
+        let srand = u128::from_le_bytes(srand[1..].try_into().unwrap());
+
+        // Check the slave's confirm value like the master would
+        assert_eq!(toolbox::c1(aes, 0, srand, &preq, &pres, &ia, &ra), sconfirm);
+
+        match sm.take_event() {
+            Some(PairingEvent::Complete { stk, security }) => {
+                assert_eq!(stk, EncryptionKey(toolbox::s1(aes, 0, srand, mrand)));
+                assert_eq!(security, LinkSecurity::Encrypted);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn wrong_confirm_value() {
+        let (mut sm, _, _) = setup();
+
+        let preq = [0x01, 0x03, 0x00, 0x00, 0x10, 0x00, 0x00];
+        process(&mut sm, &preq).unwrap();
+
+        let mut cmd = [0x03; 17];
+        process(&mut sm, &cmd).unwrap();
+        cmd[0] = 0x04;
+        assert_eq!(
+            process(&mut sm, &cmd).err(),
+            Some(PairingFailedReason::ConfirmValueFailed)
+        );
+    }
+
+    #[test]
+    fn rejects_short_keys_and_disabled_pairing() {
+        let (mut sm, _, _) = setup();
+        let preq = [0x01, 0x03, 0x00, 0x00, 0x0F, 0x00, 0x00];
+        assert_eq!(
+            process(&mut sm, &preq).err(),
+            Some(PairingFailedReason::EncryptionKeySize)
+        );
+
+        let mut sm = SecurityManager::no_security();
+        let preq = [0x01, 0x03, 0x00, 0x00, 0x10, 0x00, 0x00];
+        assert_eq!(
+            process(&mut sm, &preq).err(),
+            Some(PairingFailedReason::PairingNotSupported)
+        );
+    }
+
+    #[test]
+    fn security_policy() {
+        let with_config = |config| {
+            let (_, ia, ra) = setup();
+            let mut sm = SecurityManager::with_config(config);
+            sm.set_random_seed(&mut CountingRng(0));
+            sm.set_connection_addresses(ia, ra);
+            sm
+        };
+        let mut config = SecurityConfig::new(IoCapabilities::NoInputNoOutput);
+        config.sc_only = true;
+        config.mitm = true;
+
+        // Just Works with LE Secure Connections and MITM requested by the peer
+        let preq = [0x01, 0x03, 0x00, 0x0D, 0x10, 0x00, 0x00];
+        let mut sm = with_config(config);
+        assert_eq!(
+            process(&mut sm, &preq).err(),
+            Some(PairingFailedReason::AuthenticationRequirements)
+        );
+
+        // Legacy pairing without MITM protection
+        config.sc_only = false;
+        let mut sm = with_config(config);
+        assert_eq!(
+            process(&mut sm, &preq).err(),
+            Some(PairingFailedReason::AuthenticationRequirements)
+        );
+
+        // Bonding is only agreed to if both sides want it
+        config.mitm = false;
+        config.bonding = true;
+        let mut sm = with_config(config);
+        let (pres, _) = reply_bytes(process(&mut sm, &preq).unwrap());
+        assert_eq!(pres[3], 0x01);
+        let mut sm = with_config(config);
+        let preq = [0x01, 0x03, 0x00, 0x00, 0x10, 0x00, 0x00];
+        let (pres, _) = reply_bytes(process(&mut sm, &preq).unwrap());
+        assert_eq!(pres[3], 0x00);
+    }
+
+    /// Answers the pairing callbacks and records the last value shown to the user.
+    struct User {
+        passkey: u32,
+        confirm: bool,
+        shown: Option<u32>,
+    }
+
+    impl PairingIo for User {
+        fn display_passkey(&mut self, passkey: u32) {
+            self.shown = Some(passkey);
+        }
+
+        fn request_passkey(&mut self) -> u32 {
+            self.passkey
+        }
+
+        fn confirm_numeric(&mut self, value: u32) -> bool {
+            self.shown = Some(value);
+            self.confirm
+        }
+    }
+
+    fn cmd_bytes(cmd: Command<'_>) -> ([u8; 65], usize) {
+        let mut buf = [0; 65];
+        let mut writer = ByteWriter::new(&mut buf);
+        cmd.to_bytes(&mut writer).unwrap();
+        let len = 65 - writer.space_left();
+        (buf, len)
+    }
+
+    fn reply_value(reply: Reply, code: CommandCode) -> u128 {
+        let (buf, len) = reply_bytes(reply);
+        assert_eq!(len, 17);
+        assert_eq!(buf[0], u8::from(code));
+        u128::from_le_bytes(buf[1..].try_into().unwrap())
+    }
+
+    #[test]
+    fn legacy_passkey_entry() {
+        let (_, ia, ra) = setup();
+        let aes = &mut SoftAesProvider::new();
+        let mut config = SecurityConfig::new(IoCapabilities::KeyboardOnly);
+        config.mitm = true;
+        let mut sm = SecurityManager::with_config(config).with_pairing_io(User {
+            passkey: 123_456,
+            confirm: false,
+            shown: None,
+        });
+        sm.set_random_seed(&mut CountingRng(0));
+        sm.set_connection_addresses(ia, ra);
+
+        // The initiator displays the passkey, we input it when its confirm value arrives
+        let preq = [0x01, 0x00, 0x00, 0x04, 0x10, 0x00, 0x00];
+        let (pres, _) = reply_bytes(process(&mut sm, &preq).unwrap());
+        assert_eq!(&pres[..7], &[0x02, 0x02, 0x00, 0x04, 0x10, 0x00, 0x00]);
+        let pres: [u8; 7] = pres[..7].try_into().unwrap();
+
+        let tk = 123_456;
+        let mrand = 0x1234;
+        let mconfirm = toolbox::c1(aes, tk, mrand, &preq, &pres, &ia, &ra);
+        let (cmd, len) = cmd_bytes(Command::PairingConfirm(mconfirm));
+        let sconfirm = reply_value(
+            process(&mut sm, &cmd[..len]).unwrap(),
+            CommandCode::PairingConfirm,
+        );
+        let (cmd, len) = cmd_bytes(Command::PairingRandom(mrand));
+        let srand = reply_value(
+            process(&mut sm, &cmd[..len]).unwrap(),
+            CommandCode::PairingRandom,
+        );
+        assert_eq!(
+            toolbox::c1(aes, tk, srand, &preq, &pres, &ia, &ra),
+            sconfirm
+        );
+        match sm.take_event() {
+            Some(PairingEvent::Complete { stk, security }) => {
+                assert_eq!(stk, EncryptionKey(toolbox::s1(aes, tk, srand, mrand)));
+                assert_eq!(security, LinkSecurity::Authenticated);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        // Canceled passkey entry
+        sm.pairing_io().passkey = SIX_DIGITS;
+        process(&mut sm, &preq).unwrap();
+        let (cmd, len) = cmd_bytes(Command::PairingConfirm(mconfirm));
+        assert_eq!(
+            process(&mut sm, &cmd[..len]).err(),
+            Some(PairingFailedReason::PasskeyEntryFailed)
+        );
+    }
+
+    /// Runs *LE Secure Connections* pairing with *Numeric Comparison* as the initiator, and
+    /// returns the result and the value the user had to confirm.
+    fn numeric_comparison(confirm: bool) -> (Result<Reply, PairingFailedReason>, u32) {
+        let (_, ia, ra) = setup();
+        let aes = &mut SoftAesProvider::new();
+        let mut sm =
+            SecurityManager::secure_connections(SecurityConfig::new(IoCapabilities::DisplayYesNo))
+                .with_pairing_io(User {
+                    passkey: 0,
+                    confirm,
+                    shown: None,
+                });
+        sm.set_random_seed(&mut CountingRng(0));
+        sm.set_connection_addresses(ia, ra);
+
+        let preq = [0x01, 0x01, 0x00, 0x0D, 0x10, 0x00, 0x00];
+        let (pres, _) = reply_bytes(process(&mut sm, &preq).unwrap());
+        assert_eq!(&pres[..7], &[0x02, 0x01, 0x00, 0x08, 0x10, 0x00, 0x00]);
+
+        let (ska, pka) = P256Provider::new().generate_keypair(&mut CountingRng(100));
+        let pka_smp = swap_coordinates(&pka.0);
+        let (cmd, len) = cmd_bytes(Command::PairingPublicKey(&pka_smp));
+        assert_eq!(len, 65);
+        let (pkb, cb) = match process(&mut sm, &cmd[..len]).unwrap() {
+            Reply::PublicKey {
+                key,
+                confirm: Some(cb),
+            } => (PublicKey(swap_coordinates(&key)), cb),
+            _ => panic!("expected public key and confirm value"),
+        };
+        let dhkey = ska.agree(&pkb).unwrap().0;
+        let pkax: [u8; 32] = pka.0[..32].try_into().unwrap();
+        let pkbx: [u8; 32] = pkb.0[..32].try_into().unwrap();
+
+        let na = 0xABCD;
+        let (cmd, len) = cmd_bytes(Command::PairingRandom(na));
+        let nb = reply_value(
+            process(&mut sm, &cmd[..len]).unwrap(),
+            CommandCode::PairingRandom,
+        );
+        assert_eq!(toolbox::f4(aes, &pkbx, &pkax, nb, 0), cb);
+        assert!(sm.pairing_io().shown.is_none());
+
+        let (mac_key, ltk) = toolbox::f5(aes, &dhkey, na, nb, &ia, &ra);
+        let ea = toolbox::f6(aes, mac_key, na, nb, 0, [0x0D, 0x00, 0x01], &ia, &ra);
+        let (cmd, len) = cmd_bytes(Command::PairingDhKeyCheck(ea));
+        let result = process(&mut sm, &cmd[..len]);
+
+        let va = toolbox::g2(aes, &pkax, &pkbx, na, nb) % SIX_DIGITS;
+        assert_eq!(sm.pairing_io().shown, Some(va));
+        if result.is_ok() {
+            match sm.take_event() {
+                Some(PairingEvent::Complete { stk, security }) => {
+                    assert_eq!(stk, EncryptionKey(ltk));
+                    assert_eq!(security, LinkSecurity::Authenticated);
+                }
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        let result = result.map(|reply| {
+            let eb = reply_value(reply, CommandCode::PairingDhKeyCheck);
+            let expected = toolbox::f6(aes, mac_key, nb, na, 0, [0x08, 0x00, 0x01], &ra, &ia);
+            assert_eq!(eb, expected);
+            Reply::Cmd(Command::PairingDhKeyCheck(eb))
+        });
+        (result, va)
+    }
+
+    #[test]
+    fn numeric_comparison_confirmed() {
+        let (result, value) = numeric_comparison(true);
+        assert!(result.is_ok());
+        assert!(value < SIX_DIGITS);
+    }
+
+    #[test]
+    fn numeric_comparison_rejected() {
+        let (result, _) = numeric_comparison(false);
+        assert_eq!(
+            result.err(),
+            Some(PairingFailedReason::NumericComparisonFailed)
+        );
+    }
+
+    #[test]
+    fn method_selection() {
+        use self::IoCapabilities::*;
+
+        assert_eq!(
+            pairing_method(DisplayYesNo, DisplayYesNo, false),
+            Method::JustWorks
+        );
+        assert_eq!(
+            pairing_method(DisplayYesNo, DisplayYesNo, true),
+            Method::NumericComparison
+        );
+        assert_eq!(
+            pairing_method(KeyboardDisplay, KeyboardDisplay, false),
+            Method::PasskeyInput
+        );
+        assert_eq!(
+            pairing_method(KeyboardOnly, DisplayOnly, true),
+            Method::PasskeyDisplay
+        );
+        assert_eq!(
+            pairing_method(KeyboardOnly, KeyboardOnly, true),
+            Method::PasskeyInput
+        );
+        assert_eq!(
+            pairing_method(KeyboardDisplay, NoInputNoOutput, true),
+            Method::JustWorks
+        );
+    }
+}
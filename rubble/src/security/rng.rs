@@ -0,0 +1,76 @@
+//! Random number generation.
+//!
+//! Several parts of BLE need random numbers: The advertising delay, the access addresses of new
+//! connections, the nonces exchanged during pairing, and Resolvable Private Addresses. The
+//! [`Rng`] trait abstracts over the source of this randomness, so that MCUs with a hardware
+//! random number generator (like the `RNG` peripheral on nRF chips) can make use of it.
+//!
+//! Every type implementing `rand_core`'s `RngCore` and `CryptoRng` traits automatically
+//! implements [`Rng`].
+
+use rand_core::{CryptoRng, RngCore};
+
+/// Trait for random number generators used by the stack.
+///
+/// Since the generated values are also used as cryptographic nonces, implementations must be
+/// cryptographically secure (or be seeded from a secure source).
+pub trait Rng {
+    /// Fills `dest` with random Bytes.
+    fn fill_bytes(&mut self, dest: &mut [u8]);
+
+    /// Returns a random `u32`.
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+}
+
+impl<R: RngCore + CryptoRng> Rng for R {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RngCore::fill_bytes(self, dest);
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        RngCore::next_u32(self)
+    }
+}
+
+/// A deterministic [`Rng`] for testing.
+///
+/// This is a simple xorshift generator. It is **not** cryptographically secure and must only be
+/// used in tests.
+#[derive(Debug, Clone)]
+pub struct MockRng {
+    state: u32,
+}
+
+impl MockRng {
+    /// Creates a generator seeded with `seed`.
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck at 0
+        Self { state: seed | 1 }
+    }
+}
+
+impl Default for MockRng {
+    fn default() -> Self {
+        Self::new(0x1234_5678)
+    }
+}
+
+impl Rng for MockRng {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
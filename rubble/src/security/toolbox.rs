@@ -0,0 +1,336 @@
+//! Cryptographic toolbox functions used by the Security Manager.
+//!
+//! All 128-bit values are passed around as `u128` holding their numeric value. Values received
+//! from or sent to the peer are transmitted least-significant Byte first, so they can be converted
+//! with `u128::from_le_bytes` and `u128::to_le_bytes`.
+//!
+//! 256-bit values (public key coordinates and the DHKey) are passed as big-endian Byte arrays, like
+//! in the [`ecdh`](crate::ecdh) module.
+
+use crate::aes::AesProvider;
+use crate::link::DeviceAddress;
+
+/// The security function `e`: AES-128 encryption of `plaintext` using `key`.
+pub(crate) fn e(aes: &mut impl AesProvider, key: u128, plaintext: u128) -> u128 {
+    let mut block = plaintext.to_be_bytes();
+    aes.encrypt_block(&key.to_be_bytes(), &mut block);
+    u128::from_be_bytes(block)
+}
+
+/// Packs a device address into the lower 48 bits of an integer.
+fn address_bits(addr: &DeviceAddress) -> u128 {
+    let mut bytes = [0; 16];
+    bytes[..6].copy_from_slice(addr.raw());
+    u128::from_le_bytes(bytes)
+}
+
+/// The confirm value generation function `c1` used by *LE Legacy Pairing*.
+///
+/// # Parameters
+///
+/// * **`k`**: The Temporary Key (TK).
+/// * **`r`**: The random value (`Mrand` or `Srand`).
+/// * **`preq`**: The 7-Byte *Pairing Request* command, including its opcode.
+/// * **`pres`**: The 7-Byte *Pairing Response* command, including its opcode.
+/// * **`initiator`**: The address of the initiating device (the master).
+/// * **`responder`**: The address of the responding device (the slave).
+pub(crate) fn c1(
+    aes: &mut impl AesProvider,
+    k: u128,
+    r: u128,
+    preq: &[u8; 7],
+    pres: &[u8; 7],
+    initiator: &DeviceAddress,
+    responder: &DeviceAddress,
+) -> u128 {
+    // p1 = pres || preq || rat' || iat'
+    let mut p1 = [0; 16];
+    p1[0] = initiator.is_random().into();
+    p1[1] = responder.is_random().into();
+    p1[2..9].copy_from_slice(preq);
+    p1[9..16].copy_from_slice(pres);
+    let p1 = u128::from_le_bytes(p1);
+
+    // p2 = padding || ia || ra
+    let p2 = address_bits(initiator) << 48 | address_bits(responder);
+
+    let tmp = e(aes, k, r ^ p1);
+    e(aes, k, tmp ^ p2)
+}
+
+/// The key generation function `s1` used to derive the Short-Term Key (STK) in *LE Legacy
+/// Pairing*.
+///
+/// `r1` is the responder's random value (`Srand`) and `r2` is the initiator's (`Mrand`).
+pub(crate) fn s1(aes: &mut impl AesProvider, k: u128, r1: u128, r2: u128) -> u128 {
+    const LOWER_64: u128 = 0xFFFF_FFFF_FFFF_FFFF;
+    e(aes, k, (r1 & LOWER_64) << 64 | (r2 & LOWER_64))
+}
+
+/// The random address hash function `ah` used to generate and resolve private addresses.
+///
+/// `k` is the Identity Resolving Key (IRK), `r` the 24-bit `prand` value. Returns the 24-bit hash.
+pub(crate) fn ah(aes: &mut impl AesProvider, k: u128, r: u32) -> u32 {
+    e(aes, k, u128::from(r & 0xFF_FFFF)) as u32 & 0xFF_FFFF
+}
+
+/// AES-CMAC (RFC 4493) of `msg` using `key`.
+pub(crate) fn aes_cmac(aes: &mut impl AesProvider, key: u128, msg: &[u8]) -> u128 {
+    const RB: u128 = 0x87;
+    let double = |x: u128| x << 1 ^ if x >> 127 != 0 { RB } else { 0 };
+
+    let k1 = double(e(aes, key, 0));
+    let k2 = double(k1);
+
+    let (blocks, last) = match msg.len() {
+        0 => (&[][..], &[][..]),
+        len if len % 16 == 0 => msg.split_at(len - 16),
+        len => msg.split_at(len - len % 16),
+    };
+
+    let mut x = 0;
+    for block in blocks.chunks(16) {
+        x = e(aes, key, x ^ u128::from_be_bytes(block.try_into().unwrap()));
+    }
+
+    let mut padded = [0; 16];
+    padded[..last.len()].copy_from_slice(last);
+    let last = if last.len() == 16 {
+        u128::from_be_bytes(padded) ^ k1
+    } else {
+        padded[last.len()] = 0x80;
+        u128::from_be_bytes(padded) ^ k2
+    };
+    e(aes, key, x ^ last)
+}
+
+/// Encodes a device address as the 56-bit value used by `f5` and `f6`: the address type (1 for
+/// random addresses) followed by the address.
+fn address_bytes(addr: &DeviceAddress) -> [u8; 7] {
+    let mut bytes = [0; 7];
+    bytes[0] = addr.is_random().into();
+    for (byte, raw) in bytes[1..].iter_mut().zip(addr.raw().iter().rev()) {
+        *byte = *raw;
+    }
+    bytes
+}
+
+/// The confirm value generation function `f4` used by *LE Secure Connections*.
+///
+/// `u` and `v` are the X coordinates of the public keys, `x` is the random nonce, and `z` is 0 or
+/// `0x80 | ri` when using *Passkey Entry*.
+pub(crate) fn f4(aes: &mut impl AesProvider, u: &[u8; 32], v: &[u8; 32], x: u128, z: u8) -> u128 {
+    let mut msg = [0; 65];
+    msg[..32].copy_from_slice(u);
+    msg[32..64].copy_from_slice(v);
+    msg[64] = z;
+    aes_cmac(aes, x, &msg)
+}
+
+/// The key generation function `f5` used by *LE Secure Connections*.
+///
+/// Derives the `MacKey` and the Long-Term Key (in that order) from the DHKey `w`, the nonces of
+/// initiator (`n1`) and responder (`n2`), and their addresses.
+pub(crate) fn f5(
+    aes: &mut impl AesProvider,
+    w: &[u8; 32],
+    n1: u128,
+    n2: u128,
+    a1: &DeviceAddress,
+    a2: &DeviceAddress,
+) -> (u128, u128) {
+    const SALT: u128 = 0x6C88_8391_AAF5_A538_6037_0BDB_5A60_83BE;
+    const KEY_ID: [u8; 4] = *b"btle";
+
+    let t = aes_cmac(aes, SALT, w);
+
+    // Counter || keyID || N1 || N2 || A1 || A2 || Length
+    let mut msg = [0; 53];
+    msg[1..5].copy_from_slice(&KEY_ID);
+    msg[5..21].copy_from_slice(&n1.to_be_bytes());
+    msg[21..37].copy_from_slice(&n2.to_be_bytes());
+    msg[37..44].copy_from_slice(&address_bytes(a1));
+    msg[44..51].copy_from_slice(&address_bytes(a2));
+    msg[51..].copy_from_slice(&256u16.to_be_bytes());
+    let mac_key = aes_cmac(aes, t, &msg);
+    msg[0] = 1;
+    let ltk = aes_cmac(aes, t, &msg);
+    (mac_key, ltk)
+}
+
+/// The check value generation function `f6` used by *LE Secure Connections*.
+///
+/// `io_cap` contains the `AuthReq`, OOB data flag, and I/O capabilities (in that order) of the
+/// device computing the check value.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn f6(
+    aes: &mut impl AesProvider,
+    w: u128,
+    n1: u128,
+    n2: u128,
+    r: u128,
+    io_cap: [u8; 3],
+    a1: &DeviceAddress,
+    a2: &DeviceAddress,
+) -> u128 {
+    let mut msg = [0; 65];
+    msg[..16].copy_from_slice(&n1.to_be_bytes());
+    msg[16..32].copy_from_slice(&n2.to_be_bytes());
+    msg[32..48].copy_from_slice(&r.to_be_bytes());
+    msg[48..51].copy_from_slice(&io_cap);
+    msg[51..58].copy_from_slice(&address_bytes(a1));
+    msg[58..].copy_from_slice(&address_bytes(a2));
+    aes_cmac(aes, w, &msg)
+}
+
+/// The numeric comparison value generation function `g2` used by *LE Secure Connections*.
+///
+/// Returns the full 32-bit value. The 6-digit number shown to the user is this value modulo
+/// 1,000,000.
+pub(crate) fn g2(aes: &mut impl AesProvider, u: &[u8; 32], v: &[u8; 32], x: u128, y: u128) -> u32 {
+    let mut msg = [0; 80];
+    msg[..32].copy_from_slice(u);
+    msg[32..64].copy_from_slice(v);
+    msg[64..].copy_from_slice(&y.to_be_bytes());
+    aes_cmac(aes, x, &msg) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::SoftAesProvider;
+    use crate::link::AddressKind;
+
+    /// See "D.2 Confirm value generation function" (Vol 3, Part H, 2.2.3) in the spec.
+    #[test]
+    fn c1_test_vector() {
+        let preq = 0x07071000000101u64.to_le_bytes();
+        let pres = 0x05000800000302u64.to_le_bytes();
+        let ia = DeviceAddress::new([0xA6, 0xA5, 0xA4, 0xA3, 0xA2, 0xA1], AddressKind::Random);
+        let ra = DeviceAddress::new([0xB6, 0xB5, 0xB4, 0xB3, 0xB2, 0xB1], AddressKind::Public);
+
+        let confirm = c1(
+            &mut SoftAesProvider::new(),
+            0,
+            0x5783D52156AD6F0E6388274EC6702EE0,
+            preq[..7].try_into().unwrap(),
+            pres[..7].try_into().unwrap(),
+            &ia,
+            &ra,
+        );
+        assert_eq!(confirm, 0x1e1e3fef878988ead2a74dc5bef13b86);
+    }
+
+    /// See "Key generation function s1" (Vol 3, Part H, 2.2.4) in the spec.
+    #[test]
+    fn s1_test_vector() {
+        let stk = s1(
+            &mut SoftAesProvider::new(),
+            0,
+            0x000F0E0D0C0B0A091122334455667788,
+            0x010203040506070899AABBCCDDEEFF00,
+        );
+        assert_eq!(stk, 0x9a1fe1f0e8b0f49b5b4216ae796da062);
+    }
+
+    /// See "D.7 ah Random Address Hash Function" (Vol 3, Part H, Appendix D) in the spec.
+    #[test]
+    fn ah_test_vector() {
+        let hash = ah(
+            &mut SoftAesProvider::new(),
+            0xec0234a357c8ad05341010a60a397d9b,
+            0x708194,
+        );
+        assert_eq!(hash, 0x0dfbaa);
+    }
+
+    /// See RFC 4493, section 4.
+    #[test]
+    fn aes_cmac_test_vectors() {
+        let aes = &mut SoftAesProvider::new();
+        let key = 0x2b7e151628aed2a6abf7158809cf4f3c;
+        let msg = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac,
+            0x45, 0xaf, 0x8e, 0x51, 0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11, 0xe5, 0xfb,
+            0xc1, 0x19, 0x1a, 0x0a, 0x52, 0xef, 0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17,
+            0xad, 0x2b, 0x41, 0x7b, 0xe6, 0x6c, 0x37, 0x10,
+        ];
+
+        assert_eq!(aes_cmac(aes, key, &[]), 0xbb1d6929e95937287fa37d129b756746);
+        assert_eq!(
+            aes_cmac(aes, key, &msg[..16]),
+            0x070a16b46b4d4144f79bdd9dd04a287c
+        );
+        assert_eq!(
+            aes_cmac(aes, key, &msg[..40]),
+            0xdfa66747de9ae63030ca32611497c827
+        );
+        assert_eq!(aes_cmac(aes, key, &msg), 0x51f0bebf7e3b9d92fc49741779363cfe);
+    }
+
+    const U: [u8; 32] = [
+        0x20, 0xb0, 0x03, 0xd2, 0xf2, 0x97, 0xbe, 0x2c, 0x5e, 0x2c, 0x83, 0xa7, 0xe9, 0xf9, 0xa5,
+        0xb9, 0xef, 0xf4, 0x91, 0x11, 0xac, 0xf4, 0xfd, 0xdb, 0xcc, 0x03, 0x01, 0x48, 0x0e, 0x35,
+        0x9d, 0xe6,
+    ];
+    const V: [u8; 32] = [
+        0x55, 0x18, 0x8b, 0x3d, 0x32, 0xf6, 0xbb, 0x9a, 0x90, 0x0a, 0xfc, 0xfb, 0xee, 0xd4, 0xe7,
+        0x2a, 0x59, 0xcb, 0x9a, 0xc2, 0xf1, 0x9d, 0x7c, 0xfb, 0x6b, 0x4f, 0xdd, 0x49, 0xf4, 0x7f,
+        0xc5, 0xfd,
+    ];
+    const N1: u128 = 0xd5cb8454d177733effffb2ec712baeab;
+    const N2: u128 = 0xa6e8e7cc25a75f6e216583f7ff3dc4cf;
+
+    fn a1() -> DeviceAddress {
+        DeviceAddress::new([0xce, 0xbf, 0x37, 0x37, 0x12, 0x56], AddressKind::Public)
+    }
+
+    fn a2() -> DeviceAddress {
+        DeviceAddress::new([0xc1, 0xcf, 0x2d, 0x70, 0x13, 0xa7], AddressKind::Public)
+    }
+
+    /// See "D.2 f4 LE SC Confirm Value Generation Function" in the spec.
+    #[test]
+    fn f4_test_vector() {
+        let confirm = f4(&mut SoftAesProvider::new(), &U, &V, N1, 0);
+        assert_eq!(confirm, 0xf2c916f107a9bd1cf1eda1bea974872d);
+    }
+
+    /// See "D.3 f5 LE SC Key Generation Function" in the spec.
+    #[test]
+    fn f5_test_vector() {
+        let w = [
+            0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39,
+            0x7d, 0x9b, 0x99, 0x79, 0x6b, 0x13, 0xb4, 0xf8, 0x66, 0xf1, 0x86, 0x8d, 0x34, 0xf3,
+            0x73, 0xbf, 0xa6, 0x98,
+        ];
+        let (mac_key, ltk) = f5(&mut SoftAesProvider::new(), &w, N1, N2, &a1(), &a2());
+        assert_eq!(mac_key, 0x2965f176a1084a02fd3f6a20ce636e20);
+        assert_eq!(ltk, 0x6986791169d7cd23980522b594750a38);
+    }
+
+    /// See "D.4 f6 LE SC Check Value Generation Function" in the spec.
+    #[test]
+    fn f6_test_vector() {
+        let check = f6(
+            &mut SoftAesProvider::new(),
+            0x2965f176a1084a02fd3f6a20ce636e20,
+            N1,
+            N2,
+            0x12a3343bb453bb5408da42d20c2d0fc8,
+            [0x01, 0x01, 0x02],
+            &a1(),
+            &a2(),
+        );
+        assert_eq!(check, 0xe3c473989cd0e8c5d26c0b09da958f61);
+    }
+
+    /// See "D.5 g2 LE SC Numeric Comparison Generation Function" in the spec.
+    #[test]
+    fn g2_test_vector() {
+        let value = g2(&mut SoftAesProvider::new(), &U, &V, N1, N2);
+        assert_eq!(value, 0x2f9ed5ba);
+        assert_eq!(value % 1_000_000, 938_554);
+    }
+}
@@ -0,0 +1,345 @@
+//! Sharing stack state between interrupt handlers and the application.
+//!
+//! Rubble's hardware-facing half (the [`LinkLayer`] and the [`Responder`]'s queue ends) usually
+//! runs in the radio and timer interrupt handlers, while the application processes packets and
+//! events from its idle loop or a lower-priority task. Historically, Rubble relied on the
+//! application to ensure that these never preempt each other in a harmful way (eg. by running
+//! everything at the same interrupt priority).
+//!
+//! This module, enabled by the `critical-section` Cargo feature, provides [`Shared`], a cell that
+//! can be placed in a `static` and accessed from any context by entering a critical section via
+//! the [`critical-section`] crate. The platform (or the application) has to provide a
+//! critical-section implementation, for example through `cortex-m`'s
+//! `critical-section-single-core` feature.
+//!
+//! `&Shared<P>` implements [`Producer`] when `P` does, `&Shared<C>` implements [`Consumer`] when
+//! `C` does, and `&Shared<H>` implements [`LinkEventHandler`] when `H` does, so a shared queue end
+//! or event handler can be handed to the stack directly.
+//!
+//! # Locking discipline
+//!
+//! * Every access to the contents of a [`Shared`] happens inside a critical section, which is
+//!   entered for the duration of the closure passed to [`Shared::lock`] (or of a single
+//!   [`Producer`], [`Consumer`] or [`LinkEventHandler`] method call).
+//! * Critical sections block the radio interrupt, so they must be kept short: Enqueue or dequeue a
+//!   single packet, copy an event out, or update a counter. Do not parse or process packets while
+//!   holding the lock.
+//! * A [`Shared`] must not be locked again from within its own [`Shared::lock`] closure. Doing so
+//!   is a bug and panics. Locking a *different* [`Shared`] is fine, since critical sections nest.
+//! * Nothing in this module blocks: accessing an uninitialized [`Shared`] returns `None` (or an
+//!   error / empty value for the trait impls) instead of waiting.
+//!
+//! [`LinkLayer`]: crate::link::LinkLayer
+//! [`Responder`]: crate::link::Responder
+//! [`critical-section`]: https://docs.rs/critical-section
+
+use crate::bytes::ByteWriter;
+use crate::link::data::{self, Llid};
+use crate::link::event::{LinkEvent, LinkEventHandler};
+use crate::link::queue::{Consume, Consumer, Producer};
+use crate::Error;
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+/// A value shared between interrupt handlers and the application.
+///
+/// The value is guarded by a [`critical_section::Mutex`]. It starts out uninitialized so that a
+/// `Shared` can be created in a `static` and filled in once the value (eg. a queue half returned by
+/// [`PacketQueue::split`]) is available.
+///
+/// See the [module docs](self) for the locking discipline.
+///
+/// [`PacketQueue::split`]: crate::link::queue::PacketQueue::split
+pub struct Shared<T> {
+    inner: Mutex<RefCell<Option<T>>>,
+}
+
+impl<T> Shared<T> {
+    /// Creates a new, uninitialized `Shared` cell.
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Stores `value` in the cell, returning the previously stored value (if any).
+    pub fn init(&self, value: T) -> Option<T> {
+        critical_section::with(|cs| self.inner.borrow(cs).replace(Some(value)))
+    }
+
+    /// Removes and returns the stored value, leaving the cell uninitialized.
+    pub fn take(&self) -> Option<T> {
+        critical_section::with(|cs| self.inner.borrow(cs).take())
+    }
+
+    /// Returns whether a value is stored in the cell.
+    pub fn is_initialized(&self) -> bool {
+        critical_section::with(|cs| self.inner.borrow(cs).borrow().is_some())
+    }
+
+    /// Enters a critical section and invokes `f` with a mutable reference to the stored value.
+    ///
+    /// Returns `None` without calling `f` if the cell is uninitialized.
+    ///
+    /// # Panics
+    ///
+    /// This will panic when called from within `f` (ie. when the same `Shared` is locked
+    /// recursively).
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        critical_section::with(|cs| self.inner.borrow(cs).borrow_mut().as_mut().map(f))
+    }
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A shared queue producer.
+///
+/// An uninitialized producer reports no free space, and fails to enqueue with `Error::Eof`.
+impl<P: Producer> Producer for &'_ Shared<P> {
+    fn free_space(&self) -> u8 {
+        self.lock(|p| p.free_space()).unwrap_or(0)
+    }
+
+    fn free_packets(&self) -> usize {
+        self.lock(|p| p.free_packets()).unwrap_or(0)
+    }
+
+    fn produce_dyn(
+        &mut self,
+        payload_bytes: u8,
+        f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
+    ) -> Result<(), Error> {
+        self.lock(|p| p.produce_dyn(payload_bytes, f))
+            .unwrap_or(Err(Error::Eof))
+    }
+}
+
+/// A shared queue consumer.
+///
+/// An uninitialized consumer behaves like an empty queue.
+impl<C: Consumer> Consumer for &'_ Shared<C> {
+    fn has_data(&self) -> bool {
+        self.lock(|c| c.has_data()).unwrap_or(false)
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        self.lock(|c| c.consume_raw_with(f))
+            .unwrap_or(Err(Error::Eof))
+    }
+}
+
+/// Dispatches events to a shared handler.
+///
+/// Events arriving while the handler is uninitialized are dropped.
+impl<H: LinkEventHandler> LinkEventHandler for &'_ Shared<H> {
+    fn handle_event(&mut self, event: LinkEvent) {
+        self.lock(|h| h.handle_event(event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::queue::{self, ArrayQueue, PacketQueue};
+    use crate::link::MIN_DATA_PAYLOAD_BUF;
+    use core::cell::Cell;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use heapless::Vec;
+
+    /// Mock critical section for std: a global spin lock that can be re-entered by the thread
+    /// holding it, and that counts acquisitions per thread.
+    struct MockCriticalSection;
+
+    static LOCKED: AtomicBool = AtomicBool::new(false);
+
+    std::thread_local! {
+        static DEPTH: Cell<usize> = const { Cell::new(0) };
+        static ACQUIRED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    critical_section::set_impl!(MockCriticalSection);
+
+    unsafe impl critical_section::Impl for MockCriticalSection {
+        unsafe fn acquire() -> critical_section::RawRestoreState {
+            DEPTH.with(|depth| {
+                if depth.get() == 0 {
+                    while LOCKED
+                        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_err()
+                    {
+                        std::thread::yield_now();
+                    }
+                    ACQUIRED.with(|n| n.set(n.get() + 1));
+                }
+                depth.set(depth.get() + 1);
+            });
+
+            // The restore state type depends on the enabled `critical-section` features.
+            Default::default()
+        }
+
+        unsafe fn release(_: critical_section::RawRestoreState) {
+            DEPTH.with(|depth| {
+                depth.set(depth.get() - 1);
+                if depth.get() == 0 {
+                    LOCKED.store(false, Ordering::Release);
+                }
+            });
+        }
+    }
+
+    fn in_critical_section() -> bool {
+        DEPTH.with(|depth| depth.get() != 0)
+    }
+
+    fn acquisitions() -> usize {
+        ACQUIRED.with(|n| n.get())
+    }
+
+    #[test]
+    fn lock_requires_init() {
+        let shared = Shared::new();
+        assert!(!shared.is_initialized());
+        assert_eq!(shared.lock(|v: &mut u32| *v), None);
+
+        assert_eq!(shared.init(1), None);
+        let before = acquisitions();
+        assert_eq!(
+            shared.lock(|v| {
+                assert!(in_critical_section());
+                *v += 1;
+                *v
+            }),
+            Some(2)
+        );
+        assert_eq!(acquisitions(), before + 1);
+        assert!(!in_critical_section());
+
+        assert_eq!(shared.init(5), Some(2));
+        assert_eq!(shared.take(), Some(5));
+        assert!(!shared.is_initialized());
+    }
+
+    #[test]
+    #[should_panic]
+    fn recursive_lock_panics() {
+        let shared = Shared::new();
+        shared.init(0u8);
+        shared.lock(|_| shared.lock(|_| {}));
+    }
+
+    #[test]
+    fn shared_queue() {
+        static PRODUCER: Shared<queue::ArrayProducer<'static, 2>> = Shared::new();
+        static CONSUMER: Shared<queue::ArrayConsumer<'static, 2>> = Shared::new();
+
+        let queue = Box::leak(Box::new(ArrayQueue::<2>::new()));
+        let (mut tx, mut rx) = (&PRODUCER, &CONSUMER);
+
+        // Uninitialized halves behave like a full and an empty queue, respectively.
+        assert_eq!(tx.free_space(), 0);
+        assert_eq!(tx.free_packets(), 0);
+        assert_eq!(
+            tx.produce_dyn(1, &mut |_| Ok(Llid::DataStart)),
+            Err(Error::Eof)
+        );
+        assert!(!rx.has_data());
+
+        let (p, c) = queue.split();
+        PRODUCER.init(p);
+        CONSUMER.init(c);
+
+        assert_eq!(usize::from(tx.free_space()), MIN_DATA_PAYLOAD_BUF);
+        let before = acquisitions();
+        tx.produce_with(3, |writer| -> Result<_, Error> {
+            assert!(in_critical_section());
+            writer.write_slice(&[1, 2, 3])?;
+            Ok(Llid::DataStart)
+        })
+        .unwrap();
+        assert_eq!(acquisitions(), before + 1);
+
+        assert!(rx.has_data());
+        let payload: Vec<u8, 3> = rx
+            .consume_raw_with(|header, raw| {
+                assert_eq!(header.llid(), Llid::DataStart);
+                Consume::always(Ok(Vec::from_slice(raw).unwrap()))
+            })
+            .unwrap();
+        assert_eq!(&payload[..], &[1, 2, 3]);
+        assert!(!rx.has_data());
+    }
+
+    #[test]
+    fn shared_queue_across_threads() {
+        static PRODUCER: Shared<queue::ArrayProducer<'static, 2>> = Shared::new();
+        static CONSUMER: Shared<queue::ArrayConsumer<'static, 2>> = Shared::new();
+        const PACKETS: u8 = 100;
+
+        let queue = Box::leak(Box::new(ArrayQueue::<2>::new()));
+        let (p, c) = queue.split();
+        PRODUCER.init(p);
+        CONSUMER.init(c);
+
+        let producer = std::thread::spawn(|| {
+            let mut tx = &PRODUCER;
+            for i in 0..PACKETS {
+                while tx
+                    .produce_with(1, |writer| -> Result<_, Error> {
+                        writer.write_u8(i)?;
+                        Ok(Llid::DataStart)
+                    })
+                    .is_err()
+                {
+                    std::thread::yield_now();
+                }
+            }
+        });
+
+        let mut rx = &CONSUMER;
+        let mut expected = 0;
+        while expected < PACKETS {
+            match rx.consume_raw_with(|_, raw| Consume::always(Ok(raw[0]))) {
+                Ok(byte) => {
+                    assert_eq!(byte, expected);
+                    expected += 1;
+                }
+                Err(Error::Eof) => std::thread::yield_now(),
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+
+        producer.join().unwrap();
+        assert!(!rx.has_data());
+    }
+
+    #[test]
+    fn shared_event_handler() {
+        struct Recorder(Vec<LinkEvent, 2>);
+
+        impl LinkEventHandler for Recorder {
+            fn handle_event(&mut self, event: LinkEvent) {
+                assert!(in_critical_section());
+                self.0.push(event).ok();
+            }
+        }
+
+        static HANDLER: Shared<Recorder> = Shared::new();
+
+        // Dropped, since there's no handler yet.
+        (&HANDLER).handle_event(LinkEvent::EncryptionEnabled);
+
+        HANDLER.init(Recorder(Vec::new()));
+        (&HANDLER).handle_event(LinkEvent::EncryptionEnabled);
+
+        let events = HANDLER.lock(|h| h.0.clone()).unwrap();
+        assert_eq!(&events[..], &[LinkEvent::EncryptionEnabled]);
+    }
+}
@@ -5,19 +5,87 @@
 
 // use core::fmt;
 // use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::cmp::Ordering;
 use fugit;
 
 // Export aliases for fugit types
-pub type Instant = fugit::Instant::<u32, 1, 1_000_000>;
+pub type Instant = fugit::Instant<u32, 1, 1_000_000>;
 pub type Duration = fugit::Duration<u32, 1, 1_000_000>;
 pub const T_IFS: Duration = Duration::micros(150);
+/// Minimum time between two PDUs of an advertising event or periodic advertising train.
+pub const T_MAFS: Duration = Duration::micros(300);
+
+/// The longest time span that can separate two [`Instant`]s while still ordering them correctly.
+///
+/// The 32-bit microsecond counter wraps around every 71.6 minutes. When comparing 2 `Instant`s,
+/// the one less than half that range ahead of the other is considered to be the later one, so
+/// their order is only meaningful if they are at most `MAX_SPAN` (35.8 minutes) apart.
+pub const MAX_SPAN: Duration = Duration::from_ticks(u32::MAX / 2 - 1);
+
+/// Wraparound-aware calculations with [`Instant`]s.
+///
+/// The comparison operators of `Instant` already take a single wraparound of the underlying
+/// counter into account, but subtracting a later `Instant` from an earlier one panics, and adding
+/// a `Duration` silently wraps. The methods in this trait make these cases explicit.
+pub trait InstantExt: Sized {
+    /// Returns the time elapsed from `earlier` to `self`, assuming that the counter wrapped at
+    /// most once in between.
+    ///
+    /// Unlike `self - earlier`, this never panics: If `earlier` is actually after `self`, the
+    /// result is the time until the counter wraps around and reaches `self` again.
+    fn wrapping_duration_since(self, earlier: Instant) -> Duration;
+
+    /// Returns the time elapsed from `earlier` to `self`, or 0 if `earlier` is after `self`.
+    fn saturating_duration_since(self, earlier: Instant) -> Duration;
+
+    /// Adds `duration` to `self`, returning `None` if the result can't be ordered after `self`.
+    ///
+    /// The addition itself always wraps around, but if `duration` exceeds [`MAX_SPAN`], the
+    /// resulting `Instant` would compare as being *before* `self`.
+    fn checked_add(self, duration: Duration) -> Option<Instant>;
+
+    /// Compares `self` and `other`, assuming they are at most [`MAX_SPAN`] apart.
+    ///
+    /// This is the same ordering used by the comparison operators.
+    fn wrapping_cmp(self, other: Instant) -> Ordering;
+
+    /// Returns whether `self` lies before `other`, accounting for wraparound.
+    fn precedes(self, other: Instant) -> bool {
+        self.wrapping_cmp(other) == Ordering::Less
+    }
+}
+
+impl InstantExt for Instant {
+    fn wrapping_duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_ticks(self.ticks().wrapping_sub(earlier.ticks()))
+    }
+
+    fn saturating_duration_since(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier)
+            .unwrap_or(Duration::from_ticks(0))
+    }
+
+    fn checked_add(self, duration: Duration) -> Option<Instant> {
+        if duration > MAX_SPAN {
+            None
+        } else {
+            Some(Instant::from_ticks(
+                self.ticks().wrapping_add(duration.ticks()),
+            ))
+        }
+    }
+
+    fn wrapping_cmp(self, other: Instant) -> Ordering {
+        self.const_cmp(other)
+    }
+}
 
 /// Trait for time providers.
 ///
 /// The hardware interface has to provide an implementation of `Timer` to the stack. The
 /// implementation must have microsecond accuracy.
 ///
-/// This trait can also be implemented by a mock timer for testing.
+/// This trait can also be implemented by a mock timer for testing, such as [`MockTimer`].
 pub trait Timer {
     /// Obtain the current time as an [`Instant`].
     ///
@@ -25,3 +93,132 @@ pub trait Timer {
     /// the underlying value wraps around.
     fn now(&self) -> Instant;
 }
+
+/// A [`Timer`] returning a time that is controlled manually.
+///
+/// This allows testing time-dependent code (eg. the Link-Layer's timeouts) without hardware.
+#[derive(Debug, Copy, Clone)]
+pub struct MockTimer {
+    now: Instant,
+}
+
+impl MockTimer {
+    /// Creates a mock timer starting at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self { now }
+    }
+
+    /// Moves the current time forward by `duration`.
+    ///
+    /// The underlying counter wraps around when advanced past its maximum value, like a hardware
+    /// timer would.
+    pub fn advance(&mut self, duration: Duration) {
+        self.now = Instant::from_ticks(self.now.ticks().wrapping_add(duration.ticks()));
+    }
+
+    /// Sets the current time to `now`.
+    ///
+    /// To deliberately wrap the timer around, set it to an `Instant` with a smaller raw value that
+    /// lies less than [`MAX_SPAN`] after the current time.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `now` lies before the current time, since `Timer`s must never move
+    /// backwards.
+    pub fn set(&mut self, now: Instant) {
+        assert!(!now.precedes(self.now), "MockTimer must not move backwards");
+        self.now = now;
+    }
+}
+
+impl Default for MockTimer {
+    /// Creates a mock timer starting at 0.
+    fn default() -> Self {
+        Self::new(Instant::from_ticks(0))
+    }
+}
+
+impl Timer for MockTimer {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE_WRAP: Instant = Instant::from_ticks(u32::MAX - 99);
+    const AFTER_WRAP: Instant = Instant::from_ticks(50);
+
+    #[test]
+    fn duration_across_wrap() {
+        assert_eq!(
+            AFTER_WRAP.wrapping_duration_since(BEFORE_WRAP),
+            Duration::micros(150)
+        );
+        assert_eq!(
+            AFTER_WRAP.saturating_duration_since(BEFORE_WRAP),
+            Duration::micros(150)
+        );
+        assert_eq!(AFTER_WRAP - BEFORE_WRAP, Duration::micros(150));
+
+        assert_eq!(
+            BEFORE_WRAP.saturating_duration_since(AFTER_WRAP),
+            Duration::micros(0)
+        );
+        assert_eq!(
+            BEFORE_WRAP.wrapping_duration_since(AFTER_WRAP),
+            Duration::from_ticks(u32::MAX - 149)
+        );
+    }
+
+    #[test]
+    fn add_across_wrap() {
+        assert_eq!(
+            BEFORE_WRAP.checked_add(Duration::micros(150)),
+            Some(AFTER_WRAP)
+        );
+        assert!(BEFORE_WRAP.checked_add(MAX_SPAN).unwrap() > BEFORE_WRAP);
+        assert_eq!(
+            BEFORE_WRAP.checked_add(MAX_SPAN + Duration::micros(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn ordering_across_wrap() {
+        assert_eq!(BEFORE_WRAP.wrapping_cmp(AFTER_WRAP), Ordering::Less);
+        assert_eq!(AFTER_WRAP.wrapping_cmp(BEFORE_WRAP), Ordering::Greater);
+        assert_eq!(AFTER_WRAP.wrapping_cmp(AFTER_WRAP), Ordering::Equal);
+        assert!(BEFORE_WRAP.precedes(AFTER_WRAP));
+        assert!(!AFTER_WRAP.precedes(BEFORE_WRAP));
+
+        // Without wraparound, the raw values are compared
+        let early = Instant::from_ticks(1_000);
+        let late = Instant::from_ticks(2_000);
+        assert!(early.precedes(late));
+        assert!(!late.precedes(early));
+    }
+
+    #[test]
+    fn mock_timer() {
+        let mut timer = MockTimer::new(BEFORE_WRAP);
+        timer.advance(Duration::micros(50));
+        assert_eq!(timer.now(), Instant::from_ticks(u32::MAX - 49));
+
+        // Wraps around like a hardware counter
+        timer.advance(Duration::micros(100));
+        assert_eq!(timer.now(), AFTER_WRAP);
+
+        timer.set(Instant::from_ticks(1_000));
+        assert_eq!(timer.now(), Instant::from_ticks(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "must not move backwards")]
+    fn mock_timer_backwards() {
+        let mut timer = MockTimer::new(AFTER_WRAP);
+        timer.set(BEFORE_WRAP);
+    }
+}
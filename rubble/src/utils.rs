@@ -57,7 +57,10 @@ macro_rules! enum_with_unknown {
     }
 }
 
-/// `Debug`-formats its contents as a hexadecimal byte slice.
+/// Formats its contents as a hexadecimal byte slice.
+///
+/// `Debug` formats the bytes like a slice (`[01, ab]`), while `Display` produces a more compact
+/// space-separated dump (`01 ab`) meant for logging raw PDUs. Neither allocates.
 #[derive(Copy, Clone)]
 pub struct HexSlice<T>(pub T)
 where
@@ -76,6 +79,19 @@ impl<T: AsRef<[u8]>> fmt::Debug for HexSlice<T> {
     }
 }
 
+impl<T: AsRef<[u8]>> fmt::Display for HexSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.as_ref().iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
 impl<T: AsRef<[u8]>> defmt::Format for HexSlice<T> {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "{=[u8]:x}", self.0.as_ref());
@@ -98,8 +114,24 @@ impl<T: fmt::LowerHex> fmt::Debug for Hex<T> {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl<T: defmt::Format> defmt::Format for Hex<T> {
     fn format(&self, fmt: defmt::Formatter<'_>) {
         defmt::write!(fmt, "{:x}", self.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn hex_slice() {
+        let bytes = [0x01, 0xAB, 0x00, 0xFF];
+        assert_eq!(format!("{}", HexSlice(&bytes)), "01 ab 00 ff");
+        assert_eq!(format!("{:?}", HexSlice(&bytes)), "[01, ab, 00, ff]");
+        assert_eq!(format!("{}", HexSlice(&[])), "");
+        assert_eq!(format!("{}", HexSlice([0x7F])), "7f");
+    }
+}
@@ -41,9 +41,28 @@ impl Uuid128 {
         Self(bytes)
     }
 
+    /// Creates a 128-bit UUID from 16 bytes in little-endian order, as they are sent over the air.
+    pub const fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        Self(reverse(bytes))
+    }
+
+    /// Returns the UUID's raw bytes (in big-endian order).
+    pub const fn to_be_bytes(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Returns the UUID's bytes in little-endian order, as they are sent over the air.
+    ///
+    /// This can be used to create the value of a service declaration attribute.
+    pub const fn to_le_bytes(&self) -> [u8; 16] {
+        reverse(self.0)
+    }
+
     /// Parses a UUID string literal, panicking when the string is malformed.
     ///
     /// This is meant to be used in constant contexts.
+    // The out-of-bounds indexing below is how malformed input is reported at compile time.
+    #[allow(unconditional_panic)]
     pub const fn parse_static(s: &'static str) -> Self {
         const fn parse_nibble(nibble: u8) -> u8 {
             let hex_digit_out_of_range = 1;
@@ -105,13 +124,23 @@ impl Uuid128 {
 
         // String must end here.
         if s.len() > index {
-            [()][unexpected_trailing_data];
+            let () = [()][unexpected_trailing_data];
         }
 
         Uuid128(bytes)
     }
 }
 
+const fn reverse(bytes: [u8; 16]) -> [u8; 16] {
+    let mut reversed = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        reversed[i] = bytes[15 - i];
+        i += 1;
+    }
+    reversed
+}
+
 impl From<Uuid16> for Uuid32 {
     fn from(smol: Uuid16) -> Self {
         Uuid32(smol.0.into())
@@ -146,7 +175,8 @@ impl ToBytes for Uuid32 {
 
 impl ToBytes for Uuid128 {
     fn to_bytes(&self, buffer: &mut ByteWriter<'_>) -> Result<(), Error> {
-        buffer.write_slice(&self.0)
+        // Like all other multi-byte values, UUIDs are transmitted in little-endian
+        buffer.write_slice(&self.to_le_bytes())
     }
 }
 
@@ -167,7 +197,7 @@ impl FromBytes<'_> for Uuid32 {
 impl FromBytes<'_> for Uuid128 {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         let array = bytes.read_array()?;
-        Ok(Uuid128(array))
+        Ok(Uuid128::from_le_bytes(array))
     }
 }
 
@@ -196,18 +226,21 @@ impl fmt::Debug for Uuid128 {
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for Uuid16 {
     fn format(&self, f: defmt::Formatter<'_>) {
         defmt::write!(f, "Uuid16({=u16:04x})", self.0);
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for Uuid32 {
     fn format(&self, f: defmt::Formatter<'_>) {
         defmt::write!(f, "Uuid32({=u32:08x})", self.0);
     }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for Uuid128 {
     #[allow(clippy::many_single_char_names, clippy::just_underscores_and_digits)]
     fn format(&self, f: defmt::Formatter<'_>) {
@@ -230,7 +263,8 @@ impl defmt::Format for Uuid128 {
 }
 
 /// List of the supported UUID types.
-#[derive(Debug, Copy, Clone, defmt::Format)]
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum UuidKind {
     Uuid16,
     Uuid32,
@@ -292,4 +326,24 @@ mod tests {
         let uuid = "0000fd6f-0000-1000-8000-00805f9b34fb";
         assert_eq!(format!("{:?}", Uuid128::parse_static(uuid)), uuid);
     }
+
+    #[test]
+    fn byte_order() {
+        let uuid = Uuid128::parse_static("00112233-4455-6677-8899-aabbccddeeff");
+        let mut buf = [0; 16];
+        uuid.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(
+            buf,
+            [
+                0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22,
+                0x11, 0x00
+            ]
+        );
+        assert_eq!(buf, uuid.to_le_bytes());
+        assert_eq!(Uuid128::from_le_bytes(buf), uuid);
+        assert_eq!(
+            <Uuid128 as FromBytes>::from_bytes(&mut ByteReader::new(&buf)).unwrap(),
+            uuid
+        );
+    }
 }